@@ -238,6 +238,8 @@ def main():
                 "narSize": nar_size,
                 "fileSize": file_size,
             }
+            if entry.get("description"):
+                index["packages"][name]["description"] = entry["description"]
 
             ratio = (file_size / nar_size * 100) if nar_size > 0 else 0
             print(
@@ -0,0 +1,252 @@
+//! Attribute cache.
+//!
+//! `ls -l` and build tools stat the same nodes over and over, and without a
+//! cache each stat is a FUSE GETATTR round trip. FUSE replies carry an
+//! attribute timeout for exactly this: until it runs out, the guest may
+//! serve the attributes it already has. A timeout of zero (virtiofsd's
+//! `cache=none`) means the host wants to be asked every time.
+//!
+//! Entries are keyed by nodeid and filled from LOOKUP, GETATTR, CREATE,
+//! MKDIR and SETATTR replies. Local operations that change a node (write,
+//! truncate, unlink, rename) drop or replace its entry immediately.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::fuse::{FuseAttrOut, FuseEntryOut};
+
+/// Environment variable lowering [MAX_ATTR_TIMEOUT], in milliseconds.
+/// `0` disables caching.
+pub const ATTR_TIMEOUT_ENV: &str = "VIRTIO_FSD_ATTR_TIMEOUT_MS";
+
+/// Longest a host-provided timeout is honoured.
+pub const MAX_ATTR_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Upper bound on cached nodes.
+pub const ATTR_CAPACITY: usize = 4096;
+
+/// Parse the value of [ATTR_TIMEOUT_ENV], falling back to
+/// [MAX_ATTR_TIMEOUT] when it is unset or not a number.
+pub fn max_timeout(env: Option<&str>) -> Duration {
+    match env.map(|v| v.trim().parse::<u64>()) {
+        Some(Ok(ms)) => Duration::from_millis(ms).min(MAX_ATTR_TIMEOUT),
+        Some(Err(_)) => {
+            log::warn!("virtio-fsd: ignoring invalid {}", ATTR_TIMEOUT_ENV);
+            MAX_ATTR_TIMEOUT
+        }
+        None => MAX_ATTR_TIMEOUT,
+    }
+}
+
+/// Bounded map of nodeid to attributes, each valid until its own deadline.
+pub struct AttrCache {
+    entries: HashMap<u64, (FuseAttrOut, Instant)>,
+    max_timeout: Duration,
+    capacity: usize,
+}
+
+impl AttrCache {
+    pub fn new(max_timeout: Duration, capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_timeout,
+            capacity,
+        }
+    }
+
+    /// The cached attributes of `nodeid`, if they haven't expired.
+    pub fn get(&mut self, nodeid: u64, now: Instant) -> Option<FuseAttrOut> {
+        match self.entries.get(&nodeid) {
+            Some(&(attr, expires)) if now < expires => Some(attr),
+            Some(_) => {
+                self.entries.remove(&nodeid);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Serve `nodeid` from the cache, or call `fetch` (a GETATTR) and
+    /// cache its reply.
+    pub fn get_or_fetch<E>(
+        &mut self,
+        nodeid: u64,
+        now: Instant,
+        fetch: impl FnOnce() -> Result<FuseAttrOut, E>,
+    ) -> Result<FuseAttrOut, E> {
+        if let Some(attr) = self.get(nodeid, now) {
+            return Ok(attr);
+        }
+        let attr = fetch()?;
+        self.insert(nodeid, attr, now);
+        Ok(attr)
+    }
+
+    /// Remember attributes from a GETATTR or SETATTR reply, for as long
+    /// as the host allows. A zero timeout replaces nothing: the node's old
+    /// entry is dropped.
+    pub fn insert(&mut self, nodeid: u64, attr: FuseAttrOut, now: Instant) {
+        let host = Duration::from_secs(attr.attr_valid)
+            .saturating_add(Duration::from_nanos(attr.attr_valid_nsec.into()));
+        // Hosts may send "forever" (u64::MAX); a day is as good.
+        let timeout = host.min(self.max_timeout);
+        if timeout.is_zero() || self.capacity == 0 {
+            self.entries.remove(&nodeid);
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&nodeid) {
+            self.entries.retain(|_, (_, expires)| now < *expires);
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&nodeid) {
+            // Still full of live entries: drop the one expiring first.
+            if let Some(soonest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(nodeid, _)| *nodeid)
+            {
+                self.entries.remove(&soonest);
+            }
+        }
+        self.entries.insert(nodeid, (attr, now + timeout));
+    }
+
+    /// Remember the attributes a LOOKUP, CREATE or MKDIR reply carries.
+    pub fn insert_entry(&mut self, entry: &FuseEntryOut, now: Instant) {
+        let attr = FuseAttrOut {
+            attr_valid: entry.attr_valid,
+            attr_valid_nsec: entry.attr_valid_nsec,
+            dummy: 0,
+            attr: entry.attr,
+        };
+        self.insert(entry.nodeid, attr, now);
+    }
+
+    /// Forget `nodeid`'s attributes, e.g. after writing to it.
+    pub fn invalidate(&mut self, nodeid: u64) {
+        self.entries.remove(&nodeid);
+    }
+
+    /// Forget every node's attributes.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuse::FuseAttr;
+
+    fn attr_out(size: u64, valid_secs: u64) -> FuseAttrOut {
+        FuseAttrOut {
+            attr_valid: valid_secs,
+            attr_valid_nsec: 0,
+            dummy: 0,
+            attr: FuseAttr {
+                size,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn second_getattr_within_timeout_skips_host() {
+        let mut cache = AttrCache::new(MAX_ATTR_TIMEOUT, 16);
+        let now = Instant::now();
+        let requests = std::cell::Cell::new(0);
+        let mut getattr = |at: Instant| {
+            cache
+                .get_or_fetch(5, at, || {
+                    requests.set(requests.get() + 1);
+                    Ok::<_, ()>(attr_out(10, 2))
+                })
+                .unwrap()
+        };
+
+        assert_eq!(getattr(now).attr.size, 10);
+        assert_eq!(getattr(now + Duration::from_millis(1900)).attr.size, 10);
+        assert_eq!(requests.get(), 1);
+
+        // The host said 2s; after that the node is asked for again.
+        getattr(now + Duration::from_secs(2));
+        assert_eq!(requests.get(), 2);
+    }
+
+    #[test]
+    fn write_invalidates_cached_size() {
+        let mut cache = AttrCache::new(MAX_ATTR_TIMEOUT, 16);
+        let now = Instant::now();
+        cache.insert(5, attr_out(10, 60), now);
+
+        // What VirtioFsScheme::write does after the host accepted data.
+        cache.invalidate(5);
+
+        let mut requests = 0;
+        let attr = cache
+            .get_or_fetch(5, now, || {
+                requests += 1;
+                Ok::<_, ()>(attr_out(4096, 60))
+            })
+            .unwrap();
+        assert_eq!(requests, 1);
+        assert_eq!(attr.attr.size, 4096);
+    }
+
+    #[test]
+    fn zero_host_timeout_is_not_cached() {
+        let mut cache = AttrCache::new(MAX_ATTR_TIMEOUT, 16);
+        let now = Instant::now();
+        cache.insert(5, attr_out(10, 60), now);
+
+        // The reply replaces nothing; the stale entry goes too.
+        cache.insert(5, attr_out(20, 0), now);
+        assert!(cache.get(5, now).is_none());
+    }
+
+    #[test]
+    fn host_timeout_is_capped() {
+        let now = Instant::now();
+
+        let mut cache = AttrCache::new(Duration::from_millis(500), 16);
+        cache.insert(5, attr_out(10, 60), now);
+        assert!(cache.get(5, now + Duration::from_millis(499)).is_some());
+        assert!(cache.get(5, now + Duration::from_millis(500)).is_none());
+
+        let mut disabled = AttrCache::new(Duration::ZERO, 16);
+        disabled.insert(5, attr_out(10, 60), now);
+        assert!(disabled.get(5, now).is_none());
+    }
+
+    #[test]
+    fn fetch_error_is_not_cached() {
+        let mut cache = AttrCache::new(MAX_ATTR_TIMEOUT, 16);
+        let now = Instant::now();
+
+        assert!(cache.get_or_fetch(5, now, || Err(())).is_err());
+        assert!(cache.get(5, now).is_none());
+    }
+
+    #[test]
+    fn full_cache_evicts_soonest_expiring() {
+        let mut cache = AttrCache::new(MAX_ATTR_TIMEOUT, 2);
+        let now = Instant::now();
+        cache.insert(1, attr_out(1, 10), now);
+        cache.insert(2, attr_out(2, 5), now);
+        cache.insert(3, attr_out(3, 10), now);
+
+        assert!(cache.get(1, now).is_some());
+        assert!(cache.get(2, now).is_none());
+        assert!(cache.get(3, now).is_some());
+    }
+
+    #[test]
+    fn timeout_from_env() {
+        assert_eq!(max_timeout(None), MAX_ATTR_TIMEOUT);
+        assert_eq!(max_timeout(Some("250")), Duration::from_millis(250));
+        assert_eq!(max_timeout(Some("0")), Duration::ZERO);
+        assert_eq!(max_timeout(Some("soon")), MAX_ATTR_TIMEOUT);
+        assert_eq!(max_timeout(Some(&u64::MAX.to_string())), MAX_ATTR_TIMEOUT);
+    }
+}
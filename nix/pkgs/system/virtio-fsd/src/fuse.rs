@@ -20,20 +20,20 @@ pub enum FuseOpcode {
     Forget = 2,
     Getattr = 3,
     Setattr = 4,
-    // Readlink = 5,
-    // Symlink = 6,
+    Readlink = 5,
+    Symlink = 6,
     // Mknod = 8,
     Mkdir = 9,
     Unlink = 10,
     Rmdir = 11,
-    // Rename = 12,
+    Rename = 12,
     // Link = 13,
     Open = 14,
     Read = 15,
     Write = 16,
     Statfs = 17,
     Release = 18,
-    // Fsync = 20,
+    Fsync = 20,
     // Setxattr = 21,
     // Getxattr = 22,
     // Listxattr = 23,
@@ -43,11 +43,12 @@ pub enum FuseOpcode {
     Opendir = 27,
     Readdir = 28,
     Releasedir = 29,
-    // Fsyncdir = 30,
+    Fsyncdir = 30,
     // Access = 34,
     Create = 35,
-    // Batchforget = 42,
+    Batchforget = 42,
     Readdirplus = 44,
+    Rename2 = 45,
     // Setupmapping = 48,
     // Removemapping = 49,
 }
@@ -209,6 +210,32 @@ pub struct FuseReleaseIn {
     pub lock_owner: u64,
 }
 
+/// FUSE_FORGET request body. Sent on the hiprio queue; there is no reply.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FuseForgetIn {
+    pub nlookup: u64,
+}
+
+/// One node in a FUSE_BATCH_FORGET request.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuseForgetOne {
+    pub nodeid: u64,
+    pub nlookup: u64,
+}
+
+/// FUSE_BATCH_FORGET request body, followed by `count` [FuseForgetOne]s.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FuseBatchForgetIn {
+    pub count: u32,
+    pub dummy: u32,
+}
+
+const_assert_eq!(core::mem::size_of::<FuseForgetOne>(), 16);
+const_assert_eq!(core::mem::size_of::<FuseBatchForgetIn>(), 8);
+
 /// FUSE_STATFS response body.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -232,6 +259,19 @@ pub struct FuseKstatfs {
     pub spare: [u32; 6],
 }
 
+impl FuseKstatfs {
+    /// Unit of `blocks`, `bfree` and `bavail`: the fragment size if the
+    /// host reports one, otherwise the block size, as in statvfs(3).
+    /// Zeros from the host are passed through.
+    pub fn block_unit(&self) -> u32 {
+        if self.frsize != 0 {
+            self.frsize
+        } else {
+            self.bsize
+        }
+    }
+}
+
 /// FUSE READDIRPLUS entry (inline in readdir response).
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -290,9 +330,38 @@ pub struct FuseMkdirIn {
     pub umask: u32,
 }
 
-/// FUSE_SETATTR request body.
+/// FUSE_RENAME request body (followed by null-terminated old and new names).
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FuseRenameIn {
+    pub newdir: u64,
+}
+
+/// FUSE_RENAME2 request body (followed by null-terminated old and new names).
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FuseRename2In {
+    pub newdir: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
+/// FUSE_FSYNC / FUSE_FSYNCDIR request body.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
+pub struct FuseFsyncIn {
+    pub fh: u64,
+    pub fsync_flags: u32,
+    pub padding: u32,
+}
+
+/// FUSE_FSYNC flag: only flush file data, not metadata (fdatasync).
+pub const FUSE_FSYNC_FDATASYNC: u32 = 1 << 0;
+
+/// FUSE_SETATTR request body. Only the fields selected by `valid` are
+/// applied by the host.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct FuseSetattrIn {
     pub valid: u32,
     pub padding: u32,
@@ -313,8 +382,13 @@ pub struct FuseSetattrIn {
 }
 
 /// FUSE_SETATTR valid bits.
+pub const FATTR_MODE: u32 = 1 << 0;
 pub const FATTR_SIZE: u32 = 1 << 3;
+pub const FATTR_ATIME: u32 = 1 << 4;
+pub const FATTR_MTIME: u32 = 1 << 5;
 pub const FATTR_FH: u32 = 1 << 6;
+pub const FATTR_ATIME_NOW: u32 = 1 << 7;
+pub const FATTR_MTIME_NOW: u32 = 1 << 8;
 
 // ============================================================================
 // FUSE constants
@@ -325,9 +399,22 @@ pub const FUSE_KERNEL_VERSION: u32 = 7;
 /// FUSE kernel minor version (7.39 is current as of Linux 6.x).
 pub const FUSE_KERNEL_MINOR_VERSION: u32 = 39;
 
+/// First protocol minor version with FUSE_RENAME2 (Linux 3.15).
+pub const FUSE_RENAME2_MINOR_VERSION: u32 = 23;
+
+/// First protocol minor version with FUSE_BATCH_FORGET (Linux 2.6.37).
+pub const FUSE_BATCH_FORGET_MINOR_VERSION: u32 = 16;
+
 /// Maximum size for read/write data transfers.
 pub const FUSE_MAX_PAGES: u32 = 256; // 1 MiB with 4K pages
 
+/// Host errno for an unimplemented FUSE opcode (Linux ENOSYS).
+pub const LINUX_ENOSYS: i32 = 38;
+
+/// Linux ENOTDIR and EINVAL, for errors the guest reports in host terms.
+pub const LINUX_ENOTDIR: i32 = 20;
+pub const LINUX_EINVAL: i32 = 22;
+
 // S_IF* mode constants (POSIX).
 pub const S_IFMT: u32 = 0o170000;
 pub const S_IFDIR: u32 = 0o040000;
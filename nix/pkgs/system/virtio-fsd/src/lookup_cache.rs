@@ -0,0 +1,139 @@
+//! Negative lookup cache.
+//!
+//! Shells searching `$PATH` and configure scripts probe many names that
+//! don't exist. Without a cache every probe is a FUSE LOOKUP round trip
+//! to the host that ends in ENOENT.
+//!
+//! Entries are keyed by (parent nodeid, name) and expire after a short TTL,
+//! so files created on the host side become visible again quickly. Local
+//! operations that can make a name exist (create, mkdir, rename) drop the
+//! parent directory's entries immediately.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a failed lookup is remembered.
+pub const NEGATIVE_TTL: Duration = Duration::from_secs(1);
+
+/// Upper bound on remembered misses.
+pub const NEGATIVE_CAPACITY: usize = 1024;
+
+/// Bounded, TTL-limited set of (parent, name) pairs known not to exist.
+pub struct NegativeLookupCache {
+    entries: HashMap<(u64, String), Instant>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl NegativeLookupCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+            capacity,
+        }
+    }
+
+    /// Whether `name` under `parent` failed to resolve less than a TTL ago.
+    pub fn contains(&mut self, parent: u64, name: &str, now: Instant) -> bool {
+        let key = (parent, name.to_string());
+        match self.entries.get(&key) {
+            Some(&inserted) if now.duration_since(inserted) < self.ttl => true,
+            Some(_) => {
+                self.entries.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Remember that `name` under `parent` does not exist.
+    pub fn insert(&mut self, parent: u64, name: &str, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            let ttl = self.ttl;
+            self.entries
+                .retain(|_, inserted| now.duration_since(*inserted) < ttl);
+        }
+        if self.entries.len() >= self.capacity {
+            // Still full of live entries: drop the oldest one.
+            if let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, inserted)| **inserted)
+                .map(|(key, _)| key.clone())
+            {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert((parent, name.to_string()), now);
+    }
+
+    /// Forget every miss recorded under `parent`.
+    pub fn invalidate_dir(&mut self, parent: u64) {
+        self.entries.retain(|(p, _), _| *p != parent);
+    }
+
+    /// Forget every miss.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn miss_is_remembered_within_ttl() {
+        let mut cache = NegativeLookupCache::new(NEGATIVE_TTL, 16);
+        let now = Instant::now();
+
+        assert!(!cache.contains(1, "missing", now));
+        cache.insert(1, "missing", now);
+
+        assert!(cache.contains(1, "missing", now + Duration::from_millis(500)));
+        assert!(!cache.contains(2, "missing", now));
+        assert!(!cache.contains(1, "other", now));
+    }
+
+    #[test]
+    fn miss_expires_after_ttl() {
+        let mut cache = NegativeLookupCache::new(NEGATIVE_TTL, 16);
+        let now = Instant::now();
+        cache.insert(1, "missing", now);
+
+        assert!(!cache.contains(1, "missing", now + NEGATIVE_TTL));
+    }
+
+    #[test]
+    fn invalidate_dir_drops_only_that_parent() {
+        let mut cache = NegativeLookupCache::new(NEGATIVE_TTL, 16);
+        let now = Instant::now();
+        cache.insert(1, "a", now);
+        cache.insert(1, "b", now);
+        cache.insert(7, "a", now);
+
+        cache.invalidate_dir(1);
+
+        assert!(!cache.contains(1, "a", now));
+        assert!(!cache.contains(1, "b", now));
+        assert!(cache.contains(7, "a", now));
+    }
+
+    #[test]
+    fn full_cache_evicts_oldest() {
+        let mut cache = NegativeLookupCache::new(NEGATIVE_TTL, 2);
+        let now = Instant::now();
+        cache.insert(1, "a", now);
+        cache.insert(1, "b", now + Duration::from_millis(1));
+        cache.insert(1, "c", now + Duration::from_millis(2));
+
+        let later = now + Duration::from_millis(3);
+        assert!(!cache.contains(1, "a", later));
+        assert!(cache.contains(1, "b", later));
+        assert!(cache.contains(1, "c", later));
+    }
+}
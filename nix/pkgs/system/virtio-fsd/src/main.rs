@@ -15,22 +15,43 @@
 //! This enables host↔guest shared directories — the critical channel for
 //! the snix build bridge (guest evaluates config, host builds, shared dir
 //! transfers outputs).
+//!
+//! Options:
+//!   --subdir PATH  serve only PATH of the host's share, so /scheme/<tag>/foo
+//!                  is the host's PATH/foo (e.g. `--subdir /nix/store`).
 
+mod attr_cache;
 mod fuse;
+mod lookup_cache;
 mod scheme;
 mod session;
+#[cfg(test)]
+mod test_host;
 mod transport;
 
 use pcid_interface::PciFunctionHandle;
 use redox_scheme::{RequestKind, SignalBehavior, Socket};
 
 use crate::scheme::VirtioFsScheme;
-use crate::session::FuseSession;
+use crate::session::{FuseSession, ROOT_NODEID};
 
 fn main() {
     pcid_interface::pci_daemon(daemon_runner);
 }
 
+/// The `--subdir PATH` (or `--subdir=PATH`) option from the command line.
+fn subdir_arg(mut args: impl Iterator<Item = String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == "--subdir" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--subdir=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
 fn daemon_runner(daemon: daemon::Daemon, pcid_handle: PciFunctionHandle) -> ! {
     match run_daemon(daemon, pcid_handle) {
         Ok(()) => eprintln!("virtio-fsd: daemon exited normally"),
@@ -46,6 +67,8 @@ fn run_daemon(
     // Use eprintln! for early-boot serial output (before logd is ready)
     eprintln!("virtio-fsd: starting driver initialization");
 
+    let subdir = subdir_arg(std::env::args().skip(1));
+
     common::setup_logging(
         "fs",
         "pci",
@@ -100,7 +123,7 @@ fn run_daemon(
     // Set up virtqueues:
     //   Queue 0: hiprio (high-priority, for FORGET etc.)
     //   Queue 1: request queue (normal FUSE operations)
-    let _hiprio_queue = device
+    let hiprio_queue = device
         .transport
         .setup_queue(virtio_core::MSIX_PRIMARY_VECTOR, &device.irq_handle)?;
 
@@ -114,7 +137,7 @@ fn run_daemon(
 
     // Initialize the FUSE session
     eprintln!("virtio-fsd: sending FUSE_INIT...");
-    let session = FuseSession::init(request_queue)
+    let mut session = FuseSession::init(request_queue, hiprio_queue)
         .map_err(|e| {
             eprintln!("virtio-fsd: FUSE init FAILED: {}", e);
             anyhow::anyhow!("FUSE init failed: {}", e)
@@ -122,12 +145,24 @@ fn run_daemon(
 
     eprintln!("virtio-fsd: FUSE session initialized successfully");
 
+    // Resolve the served subdirectory once; the scheme walks from its node.
+    let root = match &subdir {
+        Some(dir) => {
+            let nodeid = session
+                .lookup_subdir(dir)
+                .map_err(|e| anyhow::anyhow!("--subdir {}: {}", dir, e))?;
+            eprintln!("virtio-fsd: serving host '{}' (node {})", dir, nodeid);
+            nodeid
+        }
+        None => ROOT_NODEID,
+    };
+
     // Register as a Redox scheme using the tag as the scheme name.
     // This makes the filesystem accessible at /scheme/<tag>/
     eprintln!("virtio-fsd: creating scheme socket...");
     let socket = Socket::create()?;
 
-    let mut scheme_handler = VirtioFsScheme::new(session, tag.clone());
+    let mut scheme_handler = VirtioFsScheme::new(session, tag.clone(), root);
 
     // Register the scheme (calls scheme_root internally)
     eprintln!("virtio-fsd: registering scheme '{}'...", tag);
@@ -148,6 +183,9 @@ fn run_daemon(
             Some(req) => req,
         };
 
+        // Handles from before a FUSE re-init must not reach the host.
+        scheme_handler.sync_session();
+
         match req.kind() {
             RequestKind::Call(call_req) => {
                 // handle_sync is on CallRequest, dispatches to SchemeSync trait methods
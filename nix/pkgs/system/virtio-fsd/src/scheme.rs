@@ -6,29 +6,74 @@
 //!
 //! Path resolution:
 //!   Redox open("/scheme/shared/foo/bar") → FUSE LOOKUP(root, "foo") → LOOKUP(foo, "bar")
+//!   With `--subdir DIR`, root is the node of the host's DIR, looked up once at
+//!   startup. `.` and `..` are applied before any LOOKUP, and a path whose `..`
+//!   would climb above root is refused with EACCES.
+//!   A LOOKUP answered with ENOENT is remembered briefly (see lookup_cache),
+//!   so repeated probes for missing names don't reach the host.
+//!
+//! Rename:
+//!   frename(fd, "dst/name") → FUSE RENAME2(parent(src), name(src), parent(dst), name(dst)).
+//!   Nodes are looked up fresh on every open, so later paths resolve to the
+//!   renamed entry; only the destination directory's negative entries are dropped.
+//!
+//! Symlinks:
+//!   open(O_CREAT | O_SYMLINK) + write(target) → FUSE SYMLINK(parent, name, target).
+//!   open(O_SYMLINK) + read → FUSE READLINK(node), returning the host's raw bytes.
+//!   This is the pair relibc's symlink() and readlink() use.
+//!
+//! Attributes:
+//!   Replies to LOOKUP, GETATTR, CREATE, MKDIR and SETATTR are cached per node
+//!   for the timeout the host returned (see attr_cache), so repeated stats
+//!   don't reach the host. Writes, truncation, unlink and rename drop the
+//!   affected nodes' entries.
+//!   fchmod, ftruncate and futimens each map to one FUSE SETATTR whose
+//!   `valid` mask names only the changed attributes.
 //!
 //! Handle tracking:
 //!   Each open file/directory gets a Redox handle ID mapped to:
 //!   - FUSE node ID (for getattr, read, etc.)
 //!   - FUSE file handle (from FUSE_OPEN/OPENDIR)
 //!   - Cached attributes
-//!   - Whether it's a directory
-
-use std::collections::BTreeMap;
+//!   - Whether it's a directory or a symlink
+//!   - Whether it is in O_APPEND mode (from open, or set and cleared with
+//!     fcntl F_SETFL); its writes go to the host's current end of file
+//!     rather than the fd offset
+//!
+//! Forgetting nodes:
+//!   The host keeps every node we looked up until we FORGET it. Closing a
+//!   handle forgets, in one BATCH_FORGET on the hiprio queue, every node no
+//!   open handle still refers to; the next open looks it up again anyway.
+//!
+//! Re-initialization:
+//!   If the device fails and the session sends FUSE_INIT again (see
+//!   session.rs), [`VirtioFsScheme::sync_session`] notices before the next
+//!   request: handles opened before become stale and get EBADF until they
+//!   are closed, both caches are dropped and the root is looked up again.
+//!   A path walk cut short by the re-init starts over from the new root.
+
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
 use redox_scheme::scheme::SchemeSync;
 use redox_scheme::{CallerCtx, OpenResult};
-use syscall::data::{Stat, StatVfs};
+use syscall::data::{Stat, StatVfs, TimeSpec};
 use syscall::dirent::{DirEntry as RedoxDirEntry, DirentBuf, DirentKind};
-use syscall::error::{Error, Result, EBADF, EEXIST, EIO, EISDIR, ENOENT, ENOTDIR};
+use syscall::error::{
+    Error, Result, EACCES, EBADF, EBUSY, EEXIST, EINVAL, EIO, EISDIR, ENOENT, ENOTDIR, ESTALE,
+};
 use syscall::flag::{
-    EventFlags, O_ACCMODE, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_STAT, O_TRUNC, O_WRONLY,
+    EventFlags, F_SETFL, O_ACCMODE, O_APPEND, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_STAT,
+    O_SYMLINK, O_TRUNC, O_WRONLY,
 };
 use syscall::schemev2::NewFdFlags;
 
-use crate::fuse::{S_IFDIR, S_IFMT};
-use crate::session::{DirEntry, FuseSession};
+use crate::attr_cache::{self, AttrCache, ATTR_CAPACITY, ATTR_TIMEOUT_ENV};
+use crate::fuse::{FuseAttr, FuseAttrOut, S_IFDIR, S_IFLNK, S_IFMT};
+use crate::lookup_cache::{NegativeLookupCache, NEGATIVE_CAPACITY, NEGATIVE_TTL};
+use crate::session::{path_components, walk, DirEntry, FuseSession, SetTime};
+use crate::transport::FuseTransportError;
 
 // Linux open flag values (for FUSE translation)
 const LINUX_O_WRONLY: u32 = 1;
@@ -47,6 +92,7 @@ const LINUX_O_APPEND: u32 = 0o2000;
 ///   Redox O_CREAT  = 0x0200_0000   Linux O_CREAT  = 0o100
 ///   Redox O_EXCL   = 0x0800_0000   Linux O_EXCL   = 0o200
 ///   Redox O_TRUNC  = 0x0400_0000   Linux O_TRUNC  = 0o1000
+///   Redox O_APPEND = 0x0008_0000   Linux O_APPEND = 0o2000
 ///
 /// FUSE passes these flags to the host virtiofsd which calls open() with
 /// Linux flags. Passing raw Redox flags causes EINVAL/ENOENT on the host.
@@ -72,10 +118,27 @@ fn redox_to_fuse_flags(redox_flags: usize) -> u32 {
     if redox_flags & O_TRUNC != 0 {
         fuse |= LINUX_O_TRUNC;
     }
+    if redox_flags & O_APPEND != 0 {
+        fuse |= LINUX_O_APPEND;
+    }
 
     fuse
 }
 
+/// Where a write lands: at `offset`, or for an O_APPEND handle at the
+/// current end of the file as returned by `end`.
+fn write_offset<E>(
+    append: bool,
+    offset: u64,
+    end: impl FnOnce() -> Result<u64, E>,
+) -> Result<u64, E> {
+    if append {
+        end()
+    } else {
+        Ok(offset)
+    }
+}
+
 /// An open file or directory handle.
 struct Handle {
     /// FUSE node ID.
@@ -84,8 +147,13 @@ struct Handle {
     fh: u64,
     /// Is this a directory?
     is_dir: bool,
+    /// Is this a symlink opened with O_SYMLINK? Reads return its target.
+    is_symlink: bool,
     /// Whether this handle was opened with write access.
     writable: bool,
+    /// O_APPEND, from open or fcntl(F_SETFL): every write goes to the end
+    /// of the file.
+    append: bool,
     /// Cached path (for fpath).
     path: String,
     /// Cached file size.
@@ -94,60 +162,298 @@ struct Handle {
     mode: u32,
     /// Cached directory listing (lazily populated).
     dir_entries: Option<Vec<DirEntry>>,
+    /// Parent node of a symlink that is still waiting for its target.
+    /// FUSE SYMLINK needs the target up front, so it is sent on first write.
+    pending_symlink: Option<u64>,
+}
+
+impl Handle {
+    /// A read-only handle on `nodeid`, open as `fh` (0 for stat-only
+    /// handles), with the attributes the node had when it was opened.
+    fn new(nodeid: u64, fh: u64, path: String, attr: &FuseAttr) -> Self {
+        Self {
+            nodeid,
+            fh,
+            is_dir: (attr.mode & S_IFMT) == S_IFDIR,
+            is_symlink: false,
+            writable: false,
+            append: false,
+            path,
+            size: attr.size,
+            mode: attr.mode,
+            dir_entries: None,
+            pending_symlink: None,
+        }
+    }
 }
 
 pub struct VirtioFsScheme<'a> {
     session: FuseSession<'a>,
     scheme_name: String,
+    /// Node the scheme's paths are resolved from: the FUSE root, or the
+    /// `--subdir` directory. It is kept off the FORGET list.
+    root: u64,
+    /// The session generation `handles` and the caches belong to.
+    generation: u64,
     next_id: AtomicUsize,
     handles: BTreeMap<usize, Handle>,
+    /// Handles opened before the session was re-initialized, until closed.
+    stale: BTreeSet<usize>,
+    /// Recent LOOKUPs that failed with ENOENT.
+    negative: NegativeLookupCache,
+    /// Node attributes the host said are still valid.
+    attrs: AttrCache,
 }
 
 impl<'a> VirtioFsScheme<'a> {
-    pub fn new(session: FuseSession<'a>, scheme_name: String) -> Self {
+    pub fn new(session: FuseSession<'a>, scheme_name: String, root: u64) -> Self {
         Self {
+            generation: session.generation(),
             session,
             scheme_name,
+            root,
             next_id: AtomicUsize::new(1),
             handles: BTreeMap::new(),
+            stale: BTreeSet::new(),
+            negative: NegativeLookupCache::new(NEGATIVE_TTL, NEGATIVE_CAPACITY),
+            attrs: AttrCache::new(
+                attr_cache::max_timeout(std::env::var(ATTR_TIMEOUT_ENV).ok().as_deref()),
+                ATTR_CAPACITY,
+            ),
+        }
+    }
+
+    /// Catch up with a re-initialized FUSE session. The host knows none of
+    /// the old nodes or file handles, so every open handle goes stale and
+    /// the caches are dropped. Called before each request.
+    pub fn sync_session(&mut self) {
+        let generation = self.session.generation();
+        if generation == self.generation {
+            return;
         }
+        self.generation = generation;
+
+        self.stale.extend(std::mem::take(&mut self.handles).into_keys());
+        self.negative.clear();
+        self.attrs.clear();
+        match self.session.resolve_root() {
+            Ok(root) => self.root = root,
+            Err(e) => log::error!("virtio-fsd: cannot resolve the root again: {}", e),
+        }
+        log::warn!(
+            "virtio-fsd: FUSE session re-initialized, {} open handles are stale",
+            self.stale.len()
+        );
+    }
+
+    /// FUSE_GETATTR, served from the attribute cache while it is fresh.
+    fn cached_getattr(
+        &mut self,
+        nodeid: u64,
+    ) -> core::result::Result<FuseAttrOut, FuseTransportError> {
+        let session = &mut self.session;
+        self.attrs
+            .get_or_fetch(nodeid, Instant::now(), || session.getattr(nodeid))
     }
 
-    /// Resolve a path relative to the FUSE root by walking LOOKUP.
+    /// Resolve a path relative to the scheme root by walking LOOKUP.
     fn resolve_path(&mut self, path: &str) -> Result<(u64, crate::fuse::FuseAttr)> {
-        let path = path.trim_matches('/');
+        match self.walk_path(path) {
+            // The session was re-initialized under the walk.
+            Err(e) if e.errno == ESTALE => {
+                self.sync_session();
+                self.walk_path(path)
+            }
+            result => result,
+        }
+    }
 
-        if path.is_empty() {
-            // Root node
-            let attr_out = self
-                .session
-                .getattr(1) // FUSE root nodeid is always 1
-                .map_err(|_| Error::new(ENOENT))?;
-            return Ok((1, attr_out.attr));
+    /// [`Self::resolve_path`], failing with ESTALE if the session is
+    /// re-initialized on the way.
+    fn walk_path(&mut self, path: &str) -> Result<(u64, crate::fuse::FuseAttr)> {
+        let components = path_components(path).ok_or(Error::new(EACCES))?;
+        let now = Instant::now();
+        let (session, negative, attrs) = (&mut self.session, &mut self.negative, &mut self.attrs);
+
+        let current_nodeid = walk::<Error>(self.root, &components, |parent, name| {
+            let entry =
+                cached_lookup(negative, parent, name, now, || session.lookup(parent, name))?;
+            attrs.insert_entry(&entry, now);
+            Ok(entry.nodeid)
+        })?;
+
+        // Get attributes of the final node
+        let attr_out = self.cached_getattr(current_nodeid).map_err(|e| match e {
+            FuseTransportError::Reconnected => Error::new(ESTALE),
+            _ => Error::new(ENOENT),
+        })?;
+
+        Ok((current_nodeid, attr_out.attr))
+    }
+
+    /// The path of directory handle `dirfd`, which `openat` and `unlinkat`
+    /// paths are relative to. A stale or unknown handle is EBADF, never
+    /// the root.
+    fn dir_path(&self, dirfd: usize) -> Result<String> {
+        if self.stale.contains(&dirfd) {
+            return Err(Error::new(EBADF));
         }
+        let handle = self.handles.get(&dirfd).ok_or(Error::new(EBADF))?;
+        Ok(handle.path.clone())
+    }
 
-        let mut current_nodeid: u64 = 1; // FUSE root
+    /// Split a root-relative path into its parent directory's node ID and
+    /// the final component.
+    fn resolve_parent<'p>(&mut self, path: &'p str) -> Result<(u64, &'p str)> {
+        let (parent_path, filename) = match path.rfind('/') {
+            Some(pos) => (&path[..pos], &path[pos + 1..]),
+            None => ("", path),
+        };
+
+        if filename.is_empty() {
+            return Err(Error::new(ENOENT));
+        }
 
-        for component in path.split('/') {
-            if component.is_empty() {
-                continue;
+        let (parent_nodeid, _) = self.resolve_path(parent_path)?;
+        Ok((parent_nodeid, filename))
+    }
+
+    /// Open a symlink itself (O_SYMLINK).
+    ///
+    /// With O_CREAT the link doesn't exist yet: the handle records its parent
+    /// and the first write supplies the target. Closing it before any write
+    /// creates nothing, since the host can't make a link without a target.
+    /// Without O_CREAT, `path` must name an existing symlink.
+    fn open_symlink(&mut self, path: String, flags: usize) -> Result<OpenResult> {
+        let handle = if flags & O_CREAT != 0 {
+            let (parent, _) = self.resolve_parent(&path)?;
+            if self.resolve_path(&path).is_ok() {
+                return Err(Error::new(EEXIST));
             }
 
-            let entry = self
-                .session
-                .lookup(current_nodeid, component)
-                .map_err(|_| Error::new(ENOENT))?;
+            let attr = FuseAttr {
+                mode: S_IFLNK | 0o777,
+                ..FuseAttr::default()
+            };
+            Handle {
+                is_symlink: true,
+                writable: true,
+                pending_symlink: Some(parent),
+                ..Handle::new(0, 0, path, &attr)
+            }
+        } else {
+            let (nodeid, attr) = self.resolve_path(&path)?;
+            if (attr.mode & S_IFMT) != S_IFLNK {
+                return Err(Error::new(EINVAL));
+            }
 
-            current_nodeid = entry.nodeid;
-        }
+            Handle {
+                is_symlink: true,
+                ..Handle::new(nodeid, 0, path, &attr)
+            }
+        };
 
-        // Get attributes of the final node
-        let attr_out = self
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.handles.insert(id, handle);
+
+        Ok(OpenResult::ThisScheme {
+            number: id,
+            flags: NewFdFlags::POSITIONED,
+        })
+    }
+
+    /// Create the symlink a pending O_CREAT | O_SYMLINK handle stands for.
+    fn create_symlink(&mut self, id: usize, target: &[u8]) -> Result<usize> {
+        let handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+
+        // The target is fixed once the link exists.
+        let parent = handle.pending_symlink.ok_or(Error::new(EBADF))?;
+        let name = handle.path.rsplit('/').next().unwrap_or_default().to_string();
+
+        name_added(&mut self.negative, &mut self.attrs, parent);
+        let entry = self
             .session
-            .getattr(current_nodeid)
-            .map_err(|_| Error::new(ENOENT))?;
+            .symlink(parent, &name, target)
+            .map_err(fuse_errno)?;
+        self.attrs.insert_entry(&entry, Instant::now());
 
-        Ok((current_nodeid, attr_out.attr))
+        if let Some(h) = self.handles.get_mut(&id) {
+            h.nodeid = entry.nodeid;
+            h.size = entry.attr.size;
+            h.mode = entry.attr.mode;
+            h.writable = false;
+            h.pending_symlink = None;
+        }
+
+        Ok(target.len())
+    }
+
+    /// Remember a SETATTR reply: the node's attributes, and the size and
+    /// mode cached on handle `id`.
+    fn setattr_done(&mut self, id: usize, attr_out: FuseAttrOut) {
+        if let Some(h) = self.handles.get_mut(&id) {
+            h.size = attr_out.attr.size;
+            h.mode = attr_out.attr.mode;
+            self.attrs.insert(h.nodeid, attr_out, Instant::now());
+        }
+    }
+}
+
+/// `tv_nsec` values with special meaning in futimens, as in Linux and relibc.
+const UTIME_NOW: i64 = (1 << 30) - 1;
+const UTIME_OMIT: i64 = (1 << 30) - 2;
+
+fn set_time(time: &TimeSpec) -> Result<SetTime> {
+    match i64::from(time.tv_nsec) {
+        UTIME_NOW => Ok(SetTime::Now),
+        UTIME_OMIT => Ok(SetTime::Omit),
+        0..=999_999_999 => Ok(SetTime::At(time.tv_sec as u64, time.tv_nsec as u32)),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
+/// LOOKUP `name` under `parent` unless it failed less than a TTL ago, and
+/// remember it if the host answers ENOENT. A re-initialized session is
+/// ESTALE so the walk can start over; other failures are ENOENT.
+fn cached_lookup<T>(
+    negative: &mut NegativeLookupCache,
+    parent: u64,
+    name: &str,
+    now: Instant,
+    lookup: impl FnOnce() -> core::result::Result<T, FuseTransportError>,
+) -> Result<T> {
+    if negative.contains(parent, name, now) {
+        return Err(Error::new(ENOENT));
+    }
+
+    match lookup() {
+        Ok(entry) => Ok(entry),
+        Err(FuseTransportError::FuseError(errno)) if errno == -ENOENT => {
+            negative.insert(parent, name, now);
+            Err(Error::new(ENOENT))
+        }
+        Err(FuseTransportError::Reconnected) => Err(Error::new(ESTALE)),
+        Err(_) => Err(Error::new(ENOENT)),
+    }
+}
+
+/// A name is about to appear in `parent` (create, mkdir, symlink, rename):
+/// forget its misses, and its attributes since its mtime changes.
+fn name_added(negative: &mut NegativeLookupCache, attrs: &mut AttrCache, parent: u64) {
+    negative.invalidate_dir(parent);
+    attrs.invalidate(parent);
+}
+
+/// Map a FUSE error to a Redox errno.
+///
+/// virtiofsd reports host (Linux) errnos, which Redox numbers identically,
+/// so EXDEV, ENOTEMPTY, EISDIR, etc. pass through unchanged. A node gone
+/// with a re-initialized session is EBADF, other transport failures EIO.
+fn fuse_errno(err: FuseTransportError) -> Error {
+    match err {
+        FuseTransportError::FuseError(errno) if errno < 0 => Error::new(-errno),
+        FuseTransportError::Reconnected => Error::new(EBADF),
+        _ => Error::new(EIO),
     }
 }
 
@@ -155,28 +461,18 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
     fn scheme_root(&mut self) -> Result<usize> {
         // Open the root directory
         let attr_out = self
-            .session
-            .getattr(1)
+            .cached_getattr(self.root)
             .map_err(|_| Error::new(ENOENT))?;
 
         let dir_handle = self
             .session
-            .opendir(1)
+            .opendir(self.root)
             .map_err(|_| Error::new(ENOENT))?;
 
         let id = self.next_id.fetch_add(1, Ordering::Relaxed);
         self.handles.insert(
             id,
-            Handle {
-                nodeid: 1,
-                fh: dir_handle.fh,
-                is_dir: true,
-                writable: false,
-                path: String::new(),
-                size: attr_out.attr.size,
-                mode: attr_out.attr.mode,
-                dir_entries: None,
-            },
+            Handle::new(self.root, dir_handle.fh, String::new(), &attr_out.attr),
         );
 
         Ok(id)
@@ -193,15 +489,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
         let path = path.trim_matches('/');
 
         // Resolve the starting directory
-        let base_path = if let Some(handle) = self.handles.get(&dirfd) {
-            if path.starts_with('/') {
-                String::new() // absolute path, ignore dirfd
-            } else {
-                handle.path.clone()
-            }
-        } else {
-            String::new()
-        };
+        let base_path = self.dir_path(dirfd)?;
 
         let full_path = if base_path.is_empty() {
             path.to_string()
@@ -211,6 +499,11 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
             format!("{}/{}", base_path, path)
         };
 
+        // O_SYMLINK opens the link itself, never what it points to.
+        if flags & O_SYMLINK != 0 {
+            return self.open_symlink(full_path, flags);
+        }
+
         let access_mode = flags & O_ACCMODE;
         let writable = access_mode == O_WRONLY || access_mode == (O_RDONLY | O_WRONLY);
 
@@ -224,10 +517,9 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
 
             let (parent_nodeid, _) = if parent_path.is_empty() {
                 let attr_out = self
-                    .session
-                    .getattr(1)
+                    .cached_getattr(self.root)
                     .map_err(|_| Error::new(ENOENT))?;
-                (1u64, attr_out.attr)
+                (self.root, attr_out.attr)
             } else {
                 self.resolve_path(parent_path)?
             };
@@ -251,16 +543,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
                     let id = self.next_id.fetch_add(1, Ordering::Relaxed);
                     self.handles.insert(
                         id,
-                        Handle {
-                            nodeid,
-                            fh: dir_handle.fh,
-                            is_dir: true,
-                            writable: false,
-                            path: full_path,
-                            size: attr.size,
-                            mode: attr.mode,
-                            dir_entries: None,
-                        },
+                        Handle::new(nodeid, dir_handle.fh, full_path, &attr),
                     );
                     return Ok(OpenResult::ThisScheme {
                         number: id,
@@ -273,19 +556,17 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
                     .session
                     .open(nodeid, fuse_flags)
                     .map_err(|_| Error::new(EIO))?;
+                if flags & O_TRUNC != 0 {
+                    self.attrs.invalidate(nodeid);
+                }
 
                 let id = self.next_id.fetch_add(1, Ordering::Relaxed);
                 self.handles.insert(
                     id,
                     Handle {
-                        nodeid,
-                        fh: file_handle.fh,
-                        is_dir: false,
                         writable,
-                        path: full_path,
-                        size: attr.size,
-                        mode: attr.mode,
-                        dir_entries: None,
+                        append: flags & O_APPEND != 0,
+                        ..Handle::new(nodeid, file_handle.fh, full_path, &attr)
                     },
                 );
                 return Ok(OpenResult::ThisScheme {
@@ -297,10 +578,12 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
             // Target doesn't exist — create it
             if flags & O_DIRECTORY != 0 {
                 // O_CREAT | O_DIRECTORY: create a directory (mkdir)
+                name_added(&mut self.negative, &mut self.attrs, parent_nodeid);
                 let entry = self
                     .session
                     .mkdir(parent_nodeid, filename, 0o755)
                     .map_err(|_| Error::new(EIO))?;
+                self.attrs.insert_entry(&entry, Instant::now());
 
                 let dir_handle = self
                     .session
@@ -310,16 +593,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
                 let id = self.next_id.fetch_add(1, Ordering::Relaxed);
                 self.handles.insert(
                     id,
-                    Handle {
-                        nodeid: entry.nodeid,
-                        fh: dir_handle.fh,
-                        is_dir: true,
-                        writable: false,
-                        path: full_path,
-                        size: entry.attr.size,
-                        mode: entry.attr.mode,
-                        dir_entries: None,
-                    },
+                    Handle::new(entry.nodeid, dir_handle.fh, full_path, &entry.attr),
                 );
 
                 return Ok(OpenResult::ThisScheme {
@@ -330,23 +604,20 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
 
             // Regular file creation: FUSE_CREATE (atomic create + open)
             let fuse_flags = redox_to_fuse_flags(flags);
+            name_added(&mut self.negative, &mut self.attrs, parent_nodeid);
             let (entry, open) = self
                 .session
                 .create(parent_nodeid, filename, fuse_flags, 0o644)
                 .map_err(|_| Error::new(EIO))?;
+            self.attrs.insert_entry(&entry, Instant::now());
 
             let id = self.next_id.fetch_add(1, Ordering::Relaxed);
             self.handles.insert(
                 id,
                 Handle {
-                    nodeid: entry.nodeid,
-                    fh: open.fh,
-                    is_dir: false,
                     writable: true,
-                    path: full_path,
-                    size: entry.attr.size,
-                    mode: entry.attr.mode,
-                    dir_entries: None,
+                    append: flags & O_APPEND != 0,
+                    ..Handle::new(entry.nodeid, open.fh, full_path, &entry.attr)
                 },
             );
 
@@ -365,16 +636,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
             let id = self.next_id.fetch_add(1, Ordering::Relaxed);
             self.handles.insert(
                 id,
-                Handle {
-                    nodeid,
-                    fh: 0,
-                    is_dir,
-                    writable: false,
-                    path: full_path,
-                    size: attr.size,
-                    mode: attr.mode,
-                    dir_entries: None,
-                },
+                Handle::new(nodeid, 0, full_path, &attr),
             );
 
             return Ok(OpenResult::ThisScheme {
@@ -396,16 +658,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
             let id = self.next_id.fetch_add(1, Ordering::Relaxed);
             self.handles.insert(
                 id,
-                Handle {
-                    nodeid,
-                    fh: dir_handle.fh,
-                    is_dir: true,
-                    writable: false,
-                    path: full_path,
-                    size: attr.size,
-                    mode: attr.mode,
-                    dir_entries: None,
-                },
+                Handle::new(nodeid, dir_handle.fh, full_path, &attr),
             );
 
             Ok(OpenResult::ThisScheme {
@@ -422,19 +675,17 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
                 .session
                 .open(nodeid, fuse_flags)
                 .map_err(|_| Error::new(ENOENT))?;
+            if flags & O_TRUNC != 0 {
+                self.attrs.invalidate(nodeid);
+            }
 
             let id = self.next_id.fetch_add(1, Ordering::Relaxed);
             self.handles.insert(
                 id,
                 Handle {
-                    nodeid,
-                    fh: file_handle.fh,
-                    is_dir: false,
                     writable,
-                    path: full_path,
-                    size: attr.size,
-                    mode: attr.mode,
-                    dir_entries: None,
+                    append: flags & O_APPEND != 0,
+                    ..Handle::new(nodeid, file_handle.fh, full_path, &attr)
                 },
             );
 
@@ -459,6 +710,20 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
             return Err(Error::new(EISDIR));
         }
 
+        if handle.is_symlink {
+            if handle.pending_symlink.is_some() {
+                return Err(Error::new(EBADF));
+            }
+
+            let nodeid = handle.nodeid;
+            let target = self.session.readlink(nodeid).map_err(fuse_errno)?;
+
+            let start = (offset as usize).min(target.len());
+            let copy_len = (target.len() - start).min(buf.len());
+            buf[..copy_len].copy_from_slice(&target[start..start + copy_len]);
+            return Ok(copy_len);
+        }
+
         let nodeid = handle.nodeid;
         let fh = handle.fh;
 
@@ -485,17 +750,28 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
         if handle.is_dir {
             return Err(Error::new(EISDIR));
         }
+        if handle.is_symlink {
+            return self.create_symlink(id, buf);
+        }
         if !handle.writable {
             return Err(Error::new(EBADF));
         }
 
         let nodeid = handle.nodeid;
         let fh = handle.fh;
+        // The offset the kernel tracks for us goes stale as soon as anyone
+        // else appends, so append writes ask the host where the end is.
+        let offset = write_offset(handle.append, offset, || {
+            self.session
+                .getattr(nodeid)
+                .map(|attr_out| attr_out.attr.size)
+                .map_err(|_| Error::new(EIO))
+        })?;
 
-        let written = self
-            .session
-            .write(nodeid, fh, offset, buf)
-            .map_err(|_| Error::new(EIO))?;
+        // Even a failed write may have changed size and mtime.
+        let written = self.session.write(nodeid, fh, offset, buf);
+        self.attrs.invalidate(nodeid);
+        let written = written.map_err(|_| Error::new(EIO))?;
 
         // Update cached size if write extends beyond current end
         if let Some(h) = self.handles.get_mut(&id) {
@@ -505,7 +781,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
             }
         }
 
-        Ok(written as usize)
+        Ok(written)
     }
 
     fn ftruncate(&mut self, id: usize, len: u64, _ctx: &CallerCtx) -> Result<()> {
@@ -522,23 +798,78 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
             .session
             .truncate(nodeid, fh, len)
             .map_err(|_| Error::new(EIO))?;
+        self.setattr_done(id, attr_out);
 
-        // Update cached size
-        if let Some(h) = self.handles.get_mut(&id) {
-            h.size = attr_out.attr.size;
+        Ok(())
+    }
+
+    fn fchmod(&mut self, id: usize, new_mode: u16, _ctx: &CallerCtx) -> Result<()> {
+        let handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+
+        // Unlike ftruncate, fchmod needs only ownership, not a writable fd;
+        // the host checks it.
+        if handle.pending_symlink.is_some() {
+            return Err(Error::new(EBADF));
         }
 
+        let (nodeid, fh) = (handle.nodeid, handle.fh);
+        let attr_out = self
+            .session
+            .chmod(nodeid, fh, new_mode.into())
+            .map_err(fuse_errno)?;
+        self.setattr_done(id, attr_out);
+
         Ok(())
     }
 
+    fn futimens(&mut self, id: usize, times: &[TimeSpec], _ctx: &CallerCtx) -> Result<()> {
+        let handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+
+        if handle.pending_symlink.is_some() {
+            return Err(Error::new(EBADF));
+        }
+
+        // No times at all means "both now", as in utimensat(2).
+        let (atime, mtime) = match times {
+            [] => (SetTime::Now, SetTime::Now),
+            [atime] => (set_time(atime)?, SetTime::Omit),
+            [atime, mtime, ..] => (set_time(atime)?, set_time(mtime)?),
+        };
+
+        let (nodeid, fh) = (handle.nodeid, handle.fh);
+        let attr_out = self
+            .session
+            .utimens(nodeid, fh, atime, mtime)
+            .map_err(fuse_errno)?;
+        self.setattr_done(id, attr_out);
+
+        Ok(())
+    }
+
+    fn fsync(&mut self, id: usize, _ctx: &CallerCtx) -> Result<()> {
+        let handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+
+        // Nothing to flush: stat-only handles have no FUSE file handle,
+        // and read-only files have no dirty data of ours on the host.
+        if handle.fh == 0 || (!handle.is_dir && !handle.writable) {
+            return Ok(());
+        }
+
+        // Redox fsync has no fdatasync variant, so always flush metadata
+        // too — the build bridge relies on sizes/mtimes being visible.
+        let (nodeid, fh, is_dir) = (handle.nodeid, handle.fh, handle.is_dir);
+        self.session
+            .fsync(nodeid, fh, is_dir, false)
+            .map_err(fuse_errno)
+    }
+
     fn fsize(&mut self, id: usize, _ctx: &CallerCtx) -> Result<u64> {
         let handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
         let nodeid = handle.nodeid;
 
         // Refresh attributes
         let attr_out = self
-            .session
-            .getattr(nodeid)
+            .cached_getattr(nodeid)
             .map_err(|_| Error::new(EBADF))?;
 
         // Update cached size
@@ -570,8 +901,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
         let nodeid = handle.nodeid;
 
         let attr_out = self
-            .session
-            .getattr(nodeid)
+            .cached_getattr(nodeid)
             .map_err(|_| Error::new(EBADF))?;
 
         let attr = &attr_out.attr;
@@ -598,12 +928,11 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
     fn fstatvfs(&mut self, id: usize, stat: &mut StatVfs, _ctx: &CallerCtx) -> Result<()> {
         let _handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
 
-        let fsstat = self
-            .session
-            .statfs()
-            .map_err(|_| Error::new(EBADF))?;
+        let fsstat = self.session.statfs().map_err(fuse_errno)?;
 
-        stat.f_bsize = fsstat.st.bsize;
+        // Redox's StatVfs has a single block size, used as the unit of the
+        // block counts. It has no inode counts, so files/ffree are dropped.
+        stat.f_bsize = fsstat.st.block_unit();
         stat.f_blocks = fsstat.st.blocks;
         stat.f_bfree = fsstat.st.bfree;
         stat.f_bavail = fsstat.st.bavail;
@@ -674,11 +1003,7 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
         let path = path.trim_matches('/');
 
         // Resolve base directory from fd handle
-        let base_path = if let Some(handle) = self.handles.get(&fd) {
-            handle.path.clone()
-        } else {
-            String::new()
-        };
+        let base_path = self.dir_path(fd)?;
 
         let full_path = if base_path.is_empty() {
             path.to_string()
@@ -696,18 +1021,21 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
 
         let (parent_nodeid, _) = if parent_path.is_empty() {
             let attr_out = self
-                .session
-                .getattr(1)
+                .cached_getattr(self.root)
                 .map_err(|_| Error::new(ENOENT))?;
-            (1u64, attr_out.attr)
+            (self.root, attr_out.attr)
         } else {
             self.resolve_path(parent_path)?
         };
 
         // Check if target is a directory or file
-        let (_, attr) = self.resolve_path(&full_path)?;
+        let (nodeid, attr) = self.resolve_path(&full_path)?;
         let is_dir = (attr.mode & S_IFMT) == S_IFDIR;
 
+        // The node's link count and the parent's mtime change either way.
+        self.attrs.invalidate(nodeid);
+        self.attrs.invalidate(parent_nodeid);
+
         if is_dir {
             self.session
                 .rmdir(parent_nodeid, filename)
@@ -721,6 +1049,54 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
         Ok(())
     }
 
+    fn frename(&mut self, id: usize, path: &str, _ctx: &CallerCtx) -> Result<usize> {
+        let old_path = self.handles.get(&id).ok_or(Error::new(EBADF))?.path.clone();
+        let new_path = path.trim_matches('/').to_string();
+
+        // The shared root can't be moved, and nothing can replace it.
+        if old_path.is_empty() || new_path.is_empty() {
+            return Err(Error::new(EBUSY));
+        }
+
+        let (old_parent, old_name) = self.resolve_parent(&old_path)?;
+        let (new_parent, new_name) = self.resolve_parent(&new_path)?;
+
+        // Cross-directory moves go through the same request; the host
+        // decides whether they are possible (EXDEV is passed through).
+        name_added(&mut self.negative, &mut self.attrs, new_parent);
+        let nodeid = self.handles.get(&id).map_or(0, |h| h.nodeid);
+        for node in [nodeid, old_parent] {
+            self.attrs.invalidate(node);
+        }
+        self.session
+            .rename(old_parent, old_name, new_parent, new_name)
+            .map_err(fuse_errno)?;
+
+        // Keep fpath correct for the renamed handle and anything open
+        // beneath it (directory renames).
+        let old_prefix = format!("{old_path}/");
+        for handle in self.handles.values_mut() {
+            if handle.path == old_path {
+                handle.path = new_path.clone();
+            } else if let Some(rest) = handle.path.strip_prefix(&old_prefix) {
+                handle.path = format!("{new_path}/{rest}");
+            }
+        }
+
+        Ok(0)
+    }
+
+    fn fcntl(&mut self, id: usize, cmd: usize, arg: usize, _ctx: &CallerCtx) -> Result<usize> {
+        let handle = self.handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+
+        // The kernel keeps the file description's flags; O_APPEND is the
+        // one that changes what the scheme does.
+        if cmd == F_SETFL {
+            handle.append = arg & O_APPEND != 0;
+        }
+        Ok(0)
+    }
+
     fn fevent(&mut self, id: usize, _flags: EventFlags, _ctx: &CallerCtx) -> Result<EventFlags> {
         if let Some(handle) = self.handles.get(&id) {
             let mut events = EventFlags::EVENT_READ;
@@ -734,6 +1110,10 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
     }
 
     fn on_close(&mut self, id: usize) {
+        // The host already dropped the file handles of stale handles.
+        if self.stale.remove(&id) {
+            return;
+        }
         if let Some(handle) = self.handles.remove(&id) {
             if handle.fh != 0 {
                 if handle.is_dir {
@@ -742,6 +1122,327 @@ impl<'a> SchemeSync for VirtioFsScheme<'a> {
                     let _ = self.session.release(handle.nodeid, handle.fh);
                 }
             }
+
+            let open: BTreeSet<u64> = self
+                .handles
+                .values()
+                .flat_map(|h| core::iter::once(h.nodeid).chain(h.pending_symlink))
+                .collect();
+            let root = self.root;
+            let in_use = |nodeid| nodeid == root || open.contains(&nodeid);
+            for nodeid in self.session.forget_unused(in_use) {
+                // The host may hand the nodeid out again for another file.
+                self.attrs.invalidate(nodeid);
+                self.negative.invalidate_dir(nodeid);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuse::{FuseForgetOne, FuseOpcode, S_IFREG};
+    use crate::session::ROOT_NODEID;
+    use crate::test_host::TestHost;
+
+    /// The caller of a scheme call; the scheme never looks at it.
+    fn ctx() -> CallerCtx {
+        // SAFETY: CallerCtx is plain integers, for which zero is valid.
+        unsafe { core::mem::zeroed() }
+    }
+
+    /// A scheme serving `host`'s whole share, and the fd of its root.
+    fn scheme(host: &TestHost) -> (VirtioFsScheme<'static>, usize) {
+        let mut scheme = VirtioFsScheme::new(host.session(), "shared".into(), ROOT_NODEID);
+        let root = scheme.scheme_root().unwrap();
+        (scheme, root)
+    }
+
+    fn open(scheme: &mut VirtioFsScheme, dirfd: usize, path: &str, flags: usize) -> Result<usize> {
+        match scheme.openat(dirfd, path, flags, 0, &ctx())? {
+            OpenResult::ThisScheme { number, .. } => Ok(number),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The parents of the LOOKUPs the host has been sent, in order.
+    fn lookups(host: &TestHost) -> Vec<u64> {
+        let lookup = FuseOpcode::Lookup as u32;
+        let fs = host.fs();
+        fs.requests.iter().filter(|&&(op, _)| op == lookup).map(|&(_, nodeid)| nodeid).collect()
+    }
+
+    fn read_all(scheme: &mut VirtioFsScheme, fd: usize) -> Vec<u8> {
+        let mut buf = [0u8; 64];
+        let len = scheme.read(fd, &mut buf, 0, 0, &ctx()).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn repeated_miss_skips_the_host() {
+        let host = TestHost::new();
+        let bin = host.fs().add_dir("bin");
+        let (mut scheme, root) = scheme(&host);
+
+        assert_eq!(open(&mut scheme, root, "bin/sh", O_RDONLY), Err(Error::new(ENOENT)));
+        assert_eq!(lookups(&host), [ROOT_NODEID, bin]);
+
+        // Within the TTL only the existing directory is looked up again.
+        assert_eq!(open(&mut scheme, root, "/bin/./sh", O_RDONLY), Err(Error::new(ENOENT)));
+        assert_eq!(lookups(&host), [ROOT_NODEID, bin, ROOT_NODEID]);
+    }
+
+    #[test]
+    fn create_drops_the_parents_misses() {
+        let host = TestHost::new();
+        let bin = host.fs().add_dir("bin");
+        let etc = host.fs().add_dir("etc");
+        let (mut scheme, root) = scheme(&host);
+        let missing = |scheme: &mut VirtioFsScheme, path| {
+            open(scheme, root, path, O_RDONLY) == Err(Error::new(ENOENT))
+        };
+
+        assert!(missing(&mut scheme, "bin/sh") && missing(&mut scheme, "etc/sh"));
+
+        // Created on the host by someone else: still hidden until the TTL runs out.
+        host.fs().add_file("bin/sh", b"");
+        assert!(missing(&mut scheme, "bin/sh"));
+
+        // Creating in the directory through the scheme shows it at once;
+        // other directories keep their misses.
+        open(&mut scheme, root, "bin/new", O_CREAT | O_WRONLY).unwrap();
+        host.fs().requests.clear();
+        assert!(open(&mut scheme, root, "bin/sh", O_RDONLY).is_ok());
+        assert!(missing(&mut scheme, "etc/sh"));
+        assert_eq!(lookups(&host), [ROOT_NODEID, bin, ROOT_NODEID]);
+        assert!(!lookups(&host).contains(&etc));
+    }
+
+    #[test]
+    fn rename_is_visible_at_the_new_path() {
+        let host = TestHost::new();
+        host.fs().add_file("src/a", b"a");
+        host.fs().add_file("dst/b", b"b");
+        let (mut scheme, root) = scheme(&host);
+
+        // A probe for the target right before the move is cached as a miss.
+        assert_eq!(open(&mut scheme, root, "dst/a", O_RDONLY), Err(Error::new(ENOENT)));
+
+        let fd = open(&mut scheme, root, "src/a", O_RDONLY).unwrap();
+        assert_eq!(scheme.frename(fd, "dst/a", &ctx()), Ok(0));
+        let moved = open(&mut scheme, root, "dst/a", O_RDONLY).unwrap();
+        assert_eq!(read_all(&mut scheme, moved), b"a");
+        assert_eq!(open(&mut scheme, root, "src/a", O_RDONLY), Err(Error::new(ENOENT)));
+
+        // Renaming over an existing file replaces it.
+        assert_eq!(scheme.frename(fd, "dst/b", &ctx()), Ok(0));
+        let replaced = open(&mut scheme, root, "dst/b", O_RDONLY).unwrap();
+        assert_eq!(read_all(&mut scheme, replaced), b"a");
+        assert_eq!(open(&mut scheme, root, "dst/a", O_RDONLY), Err(Error::new(ENOENT)));
+    }
+
+    #[test]
+    fn reinit_during_lookup_is_stale_and_not_cached() {
+        let mut negative = NegativeLookupCache::new(NEGATIVE_TTL, NEGATIVE_CAPACITY);
+        let now = Instant::now();
+
+        let stale = cached_lookup(&mut negative, ROOT_NODEID, "bin", now, || {
+            Err::<u64, _>(FuseTransportError::Reconnected)
+        });
+        assert_eq!(stale.map_err(|e| e.errno), Err(ESTALE));
+        assert!(!negative.contains(ROOT_NODEID, "bin", now));
+    }
+
+    #[test]
+    fn stale_dirfd_is_not_the_root() {
+        let host = TestHost::new();
+        host.fs().add_file("a", b"root");
+        host.fs().add_file("sub/a", b"sub");
+        let (mut scheme, root) = scheme(&host);
+        let sub = open(&mut scheme, root, "sub", O_DIRECTORY | O_RDONLY).unwrap();
+
+        // The device fails under a lookup and the session is re-initialized.
+        host.fs().failures = 1;
+        let missing = open(&mut scheme, root, "missing", O_RDONLY);
+        assert_eq!(missing.map_err(|e| e.errno), Err(ENOENT));
+        // What the event loop does before the next request.
+        scheme.sync_session();
+
+        // `sub` is stale: paths relative to it must not resolve from the root.
+        let reopened = open(&mut scheme, sub, "a", O_RDONLY);
+        assert_eq!(reopened.map_err(|e| e.errno), Err(EBADF));
+        let unlinked = scheme.unlinkat(sub, "a", 0, &ctx());
+        assert_eq!(unlinked.map_err(|e| e.errno), Err(EBADF));
+        assert_eq!(host.fs().data("a"), Some(&b"root"[..]));
+        assert_eq!(host.fs().data("sub/a"), Some(&b"sub"[..]));
+
+        // Neither may a handle that never existed.
+        assert_eq!(open(&mut scheme, 999, "a", O_RDONLY).map_err(|e| e.errno), Err(EBADF));
+        assert_eq!(scheme.unlinkat(999, "a", 0, &ctx()).map_err(|e| e.errno), Err(EBADF));
+        assert_eq!(host.fs().count(FuseOpcode::Unlink), 0);
+    }
+
+    #[test]
+    fn stat_follows_the_host_attr_timeout() {
+        let host = TestHost::new();
+        host.fs().add_file("cached", b"");
+        let uncached = host.fs().add_file("uncached", b"");
+        let (mut scheme, root) = scheme(&host);
+        let mut stat = Stat::default();
+
+        // Within the timeout the LOOKUP reply answers every stat.
+        let fd = open(&mut scheme, root, "cached", O_STAT).unwrap();
+        for _ in 0..3 {
+            scheme.fstat(fd, &mut stat, &ctx()).unwrap();
+        }
+        assert_eq!(host.fs().count(FuseOpcode::Getattr), 1);
+
+        // A host sending no timeout is asked each time.
+        host.fs().attr_valid = 0;
+        let fd = open(&mut scheme, root, "uncached", O_STAT).unwrap();
+        host.fs().requests.clear();
+        for size in [0, 5] {
+            host.fs().nodes.get_mut(&uncached).unwrap().data.resize(size, 0);
+            scheme.fstat(fd, &mut stat, &ctx()).unwrap();
+        }
+        assert_eq!(host.fs().count(FuseOpcode::Getattr), 2);
+        assert_eq!(stat.st_size, 5);
+    }
+
+    #[test]
+    fn close_forgets_nodes_no_handle_uses() {
+        let host = TestHost::new();
+        let bin = host.fs().add_dir("bin");
+        let sh = host.fs().add_file("bin/sh", b"");
+        let (mut scheme, root) = scheme(&host);
+
+        let a = open(&mut scheme, root, "bin/sh", O_RDONLY).unwrap();
+        let b = open(&mut scheme, root, "bin/sh", O_STAT).unwrap();
+        assert_eq!(host.fs().nodes[&sh].nlookup, 2);
+
+        // `b` still uses `sh`; nothing refers to `bin` after the walks.
+        scheme.on_close(a);
+        assert_eq!(host.fs().forgets, [FuseForgetOne { nodeid: bin, nlookup: 2 }]);
+        assert_eq!(host.fs().count(FuseOpcode::Release), 1);
+
+        // The last close gives back both lookups of `sh`.
+        scheme.on_close(b);
+        assert_eq!(host.fs().forgets[1..], [FuseForgetOne { nodeid: sh, nlookup: 2 }]);
+        assert!(host.fs().nodes.values().all(|node| node.nlookup == 0));
+        assert_eq!(host.fs().count(FuseOpcode::Batchforget), 2);
+
+        // Closing the root forgets nothing; it was never looked up.
+        scheme.on_close(root);
+        assert_eq!(host.fs().forgets.len(), 2);
+    }
+
+    #[test]
+    fn append_flag_reaches_the_host() {
+        let fuse = redox_to_fuse_flags(O_WRONLY | O_CREAT | O_APPEND);
+        assert_eq!(fuse, LINUX_O_WRONLY | LINUX_O_CREAT | LINUX_O_APPEND);
+        assert_eq!(redox_to_fuse_flags(O_WRONLY) & LINUX_O_APPEND, 0);
+    }
+
+    #[test]
+    fn append_writes_land_at_end_of_file() {
+        let host = TestHost::new();
+        let log = host.fs().add_file("log", b"existing\n");
+        let (mut scheme, root) = scheme(&host);
+        let append = |scheme: &mut VirtioFsScheme, fd, data: &[u8]| {
+            // The kernel passes the fd offset, which nobody moved from 0.
+            assert_eq!(scheme.write(fd, data, 0, 0, &ctx()), Ok(data.len()));
+        };
+
+        let fd = open(&mut scheme, root, "log", O_WRONLY | O_APPEND).unwrap();
+        append(&mut scheme, fd, b"first\n");
+        // Another writer appends on the host in between.
+        host.fs().nodes.get_mut(&log).unwrap().data.extend_from_slice(b"other\n");
+        append(&mut scheme, fd, b"second\n");
+        assert_eq!(host.fs().data("log"), Some(&b"existing\nfirst\nother\nsecond\n"[..]));
+
+        // fcntl(F_SETFL) without O_APPEND writes at the offset again, and
+        // the host isn't asked where the end is.
+        assert_eq!(scheme.fcntl(fd, F_SETFL, O_WRONLY, &ctx()), Ok(0));
+        host.fs().requests.clear();
+        append(&mut scheme, fd, b"E");
+        assert_eq!(host.fs().count(FuseOpcode::Getattr), 0);
+        assert!(host.fs().data("log").unwrap().starts_with(b"Existing\n"));
+
+        // And a handle opened without it can switch to appending.
+        let plain = open(&mut scheme, root, "log", O_WRONLY).unwrap();
+        assert_eq!(scheme.fcntl(plain, F_SETFL, O_WRONLY | O_APPEND, &ctx()), Ok(0));
+        append(&mut scheme, plain, b"third\n");
+        assert!(host.fs().data("log").unwrap().ends_with(b"second\nthird\n"));
+    }
+
+    #[test]
+    fn symlink_is_created_by_the_first_write() {
+        let host = TestHost::new();
+        let (mut scheme, root) = scheme(&host);
+
+        let fd = open(&mut scheme, root, "link", O_SYMLINK | O_CREAT | O_WRONLY).unwrap();
+        assert_eq!(host.fs().count(FuseOpcode::Symlink), 0);
+        assert_eq!(scheme.write(fd, b"../target", 0, 0, &ctx()), Ok(9));
+        assert_eq!(host.fs().data("link"), Some(&b"../target"[..]));
+
+        let fd = open(&mut scheme, root, "link", O_SYMLINK | O_RDONLY).unwrap();
+        let mut buf = [0u8; 32];
+        assert_eq!(scheme.read(fd, &mut buf, 0, 0, &ctx()), Ok(9));
+        assert_eq!(&buf[..9], b"../target");
+    }
+
+    #[test]
+    fn symlink_open_checks_what_is_there() {
+        let host = TestHost::new();
+        host.fs().add_file("file", b"data");
+        let (mut scheme, root) = scheme(&host);
+
+        let create = O_SYMLINK | O_CREAT | O_WRONLY;
+        assert_eq!(open(&mut scheme, root, "file", create), Err(Error::new(EEXIST)));
+        assert_eq!(open(&mut scheme, root, "file", O_SYMLINK), Err(Error::new(EINVAL)));
+        assert_eq!(host.fs().count(FuseOpcode::Symlink), 0);
+    }
+
+    #[test]
+    fn pending_symlink_closed_unwritten_creates_nothing() {
+        let host = TestHost::new();
+        let (mut scheme, root) = scheme(&host);
+
+        let fd = open(&mut scheme, root, "link", O_SYMLINK | O_CREAT | O_WRONLY).unwrap();
+        scheme.on_close(fd);
+
+        assert_eq!(host.fs().count(FuseOpcode::Symlink), 0);
+        assert_eq!(host.fs().find("link"), None);
+        assert_eq!(open(&mut scheme, root, "link", O_SYMLINK), Err(Error::new(ENOENT)));
+    }
+
+    #[test]
+    fn fchmod_and_futimens_reach_the_host_from_a_read_only_fd() {
+        let host = TestHost::new();
+        let file = host.fs().add_file("f", b"data");
+        let (mut scheme, root) = scheme(&host);
+        let fd = open(&mut scheme, root, "f", O_RDONLY).unwrap();
+
+        // What a read-only fd can't do.
+        assert_eq!(scheme.ftruncate(fd, 0, &ctx()), Err(Error::new(EBADF)));
+
+        assert_eq!(scheme.fchmod(fd, 0o600, &ctx()), Ok(()));
+        assert_eq!(host.fs().nodes[&file].mode, S_IFREG | 0o600);
+        let times = [TimeSpec::default(), TimeSpec { tv_sec: 1_700_000_000, tv_nsec: 0 }];
+        assert_eq!(scheme.futimens(fd, &times, &ctx()), Ok(()));
+        assert_eq!(host.fs().nodes[&file].mtime, 1_700_000_000);
+
+        // fstat shows the host's reply without asking again.
+        host.fs().requests.clear();
+        let mut stat = Stat::default();
+        scheme.fstat(fd, &mut stat, &ctx()).unwrap();
+        assert_eq!((stat.st_mode, stat.st_mtime), ((S_IFREG | 0o600) as u16, 1_700_000_000));
+        assert_eq!(host.fs().count(FuseOpcode::Getattr), 0);
+
+        // A symlink that doesn't exist yet has nothing to change.
+        let link = open(&mut scheme, root, "l", O_SYMLINK | O_CREAT | O_WRONLY).unwrap();
+        assert_eq!(scheme.fchmod(link, 0o600, &ctx()), Err(Error::new(EBADF)));
+        assert_eq!(scheme.futimens(link, &[], &ctx()), Err(Error::new(EBADF)));
+    }
+}
@@ -5,7 +5,9 @@
 //!
 //! ## DMA buffer ownership
 //!
-//! Two DMA buffers are allocated once during [`FuseSession::init`] and reused
+//! The session reaches the host through a [`FuseDevice`]: the virtio-fs
+//! queues ([`Virtqueues`]), or an in-memory host in tests. The queues own
+//! three DMA buffers, allocated once during [`FuseSession::init`] and reused
 //! for every subsequent FUSE operation. Buffer sizes are rounded to
 //! power-of-two page counts by [`alloc_dma_buffer`], which avoids a Redox
 //! kernel buddy allocator bug (see transport.rs module docs). With correct
@@ -15,11 +17,33 @@
 //!   Sized to fit a FUSE_WRITE with `MAX_IO_SIZE` bytes of data.
 //! - `resp_buf`: holds incoming response data (header + read payload).
 //!   Sized to fit a FUSE_READ returning `MAX_IO_SIZE` bytes.
+//! - `forget_buf`: one page for FUSE_FORGET / FUSE_BATCH_FORGET on the
+//!   hiprio queue. Those get no reply, so there is no response buffer.
 //!
 //! Per-operation descriptor sizes are controlled via `Buffer::new_sized`,
 //! so virtiofsd sees exactly the right length for each request — no
 //! over-reading on FUSE_READ, no wasted I/O.
-
+//!
+//! ## Node lookup counts
+//!
+//! Every LOOKUP, CREATE, MKDIR and SYMLINK reply makes the host hold a
+//! reference on the returned node until the guest sends a FORGET for it
+//! with the same count. [`LookupCounts`] tallies them per nodeid, and
+//! [`FuseSession::forget_unused`] gives back the ones the scheme no longer
+//! uses, batched into as few FUSE_BATCH_FORGET requests as fit a page.
+//!
+//! ## Re-initialization
+//!
+//! When the device fails a request (e.g. the host's virtiofsd restarted),
+//! the session sends FUSE_INIT again, once, before giving up. The new host
+//! session knows none of the old nodeids or file handles: the lookup counts
+//! are dropped and [`FuseSession::generation`] moves on, so the scheme can
+//! refuse handles opened before. Only requests that just read the root
+//! node (LOOKUP, GETATTR, STATFS) are sent again; any other fails with
+//! [`FuseTransportError::Reconnected`], as it may have reached the old host
+//! or names one of its nodes or file handles.
+
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
@@ -34,11 +58,37 @@ use crate::transport::*;
 /// All methods take `&mut self` because they write into the shared DMA
 /// buffers. The session is used single-threaded from the scheme event loop.
 pub struct FuseSession<'a> {
-    queue: Arc<Queue<'a>>,
+    device: Box<dyn FuseDevice + 'a>,
     unique_counter: AtomicU64,
     max_readahead: u32,
     max_write: u32,
 
+    /// Whether to try FUSE_RENAME2 first. Set from the negotiated protocol
+    /// version and cleared if the host answers ENOSYS.
+    rename2: bool,
+
+    /// Whether the host understands FUSE_BATCH_FORGET. Older hosts get
+    /// one FUSE_FORGET per node.
+    batch_forget: bool,
+
+    /// Lookups the host counts against each node, to be forgotten.
+    lookups: LookupCounts,
+
+    /// Number of times the session was re-initialized.
+    generation: u64,
+
+    /// The directory [`FuseSession::lookup_subdir`] resolved, to resolve
+    /// again after a re-init.
+    subdir: Option<String>,
+}
+
+/// The virtio-fs request and hiprio queues, and the DMA buffers requests
+/// and replies go through.
+struct Virtqueues<'a> {
+    queue: Arc<Queue<'a>>,
+    /// The hiprio queue, for requests without a reply (FORGET).
+    hiprio: Arc<Queue<'a>>,
+
     /// Pre-allocated request DMA buffer. Sized for the largest possible
     /// request (FUSE_WRITE: header + FuseWriteIn + MAX_IO_SIZE), rounded
     /// up to power-of-two pages for safe kernel deallocation.
@@ -48,18 +98,46 @@ pub struct FuseSession<'a> {
     /// response (FUSE_READ: header + MAX_IO_SIZE), rounded up to
     /// power-of-two pages for safe kernel deallocation.
     resp_buf: Dma<[u8]>,
+
+    /// Pre-allocated DMA buffer for hiprio requests, one page.
+    forget_buf: Dma<[u8]>,
+}
+
+impl FuseDevice for Virtqueues<'_> {
+    fn exchange(&mut self, req: &[u8], resp_len: usize) -> Result<Vec<u8>, FuseTransportError> {
+        if req.len() > self.req_buf.len() {
+            return Err(FuseTransportError::RequestTooLarge(req.len()));
+        }
+        if resp_len > self.resp_buf.len() {
+            return Err(FuseTransportError::RequestTooLarge(resp_len));
+        }
+
+        self.req_buf[..req.len()].copy_from_slice(req);
+        fuse_exchange(
+            &self.queue,
+            &self.req_buf,
+            req.len(),
+            &self.resp_buf,
+            resp_len,
+        )
+    }
+
+    fn send_noreply(&mut self, req: &[u8]) {
+        self.forget_buf[..req.len()].copy_from_slice(req);
+        fuse_send_noreply(&self.hiprio, &self.forget_buf, req.len());
+    }
 }
 
 impl<'a> FuseSession<'a> {
     /// Initialize a FUSE session with the host virtiofsd.
     ///
-    /// Allocates two DMA buffers that are reused for the lifetime of the
+    /// Allocates the DMA buffers that are reused for the lifetime of the
     /// driver. Buffer sizes are rounded to power-of-two page counts by
     /// `alloc_dma_buffer`, avoiding the kernel's phys_contiguous bug.
     /// The FUSE_INIT handshake itself uses these buffers.
-    pub fn init(queue: Arc<Queue<'a>>) -> Result<Self, FuseTransportError> {
-        let unique_counter = AtomicU64::new(1);
-
+    ///
+    /// `queue` carries normal requests; `hiprio` carries FORGETs.
+    pub fn init(queue: Arc<Queue<'a>>, hiprio: Arc<Queue<'a>>) -> Result<Self, FuseTransportError> {
         // Pre-allocate DMA buffers at maximum sizes.
         //
         // Request: header(40) + largest args (FuseWriteIn=40) + MAX_IO_SIZE
@@ -73,63 +151,32 @@ impl<'a> FuseSession<'a> {
             + MAX_IO_SIZE;
         let resp_buf_size = core::mem::size_of::<FuseOutHeader>() + MAX_IO_SIZE;
 
-        let mut req_buf = alloc_dma_buffer(req_buf_size)?;
-        let resp_buf = alloc_dma_buffer(resp_buf_size)?;
-
-        // Send FUSE_INIT
-        let init_in = FuseInitIn {
-            major: FUSE_KERNEL_VERSION,
-            minor: FUSE_KERNEL_MINOR_VERSION,
-            max_readahead: 1024 * 1024, // 1 MiB
-            flags: 0,
-            flags2: 0,
-            unused: [0; 11],
+        let device = Virtqueues {
+            queue,
+            hiprio,
+            req_buf: alloc_dma_buffer(req_buf_size)?,
+            resp_buf: alloc_dma_buffer(resp_buf_size)?,
+            forget_buf: alloc_dma_buffer(FORGET_BUF_SIZE)?,
         };
+        Self::with_device(Box::new(device))
+    }
 
+    /// Initialize a FUSE session over `device`, starting with FUSE_INIT.
+    pub fn with_device(mut device: Box<dyn FuseDevice + 'a>) -> Result<Self, FuseTransportError> {
+        let unique_counter = AtomicU64::new(1);
         let unique = unique_counter.fetch_add(1, Ordering::Relaxed);
-        let req = build_request_with_args(
-            FuseOpcode::Init as u32,
-            0,
-            unique,
-            &init_in,
-            None,
-        );
-
-        req_buf[..req.len()].copy_from_slice(&req);
-        let resp = fuse_exchange(&queue, &req_buf, req.len(), &resp_buf, META_RESPONSE)?;
-
-        let _hdr = parse_response_header(&resp)?;
-        let body = response_body(&resp);
-
-        if body.len() < core::mem::size_of::<FuseInitOut>() {
-            return Err(FuseTransportError::UnexpectedSize);
-        }
-
-        let init_out = unsafe { *(body.as_ptr() as *const FuseInitOut) };
-
-        log::info!(
-            "virtio-fsd: FUSE init: version {}.{}, max_readahead={}, max_write={}",
-            init_out.major,
-            init_out.minor,
-            init_out.max_readahead,
-            init_out.max_write
-        );
-
-        if init_out.max_write as usize > MAX_IO_SIZE {
-            log::warn!(
-                "virtio-fsd: negotiated max_write ({}) exceeds buffer size ({}), large writes will fail",
-                init_out.max_write,
-                MAX_IO_SIZE
-            );
-        }
+        let init_out = fuse_init(device.as_mut(), unique)?;
 
         Ok(Self {
-            queue,
+            device,
             unique_counter,
             max_readahead: init_out.max_readahead,
             max_write: init_out.max_write,
-            req_buf,
-            resp_buf,
+            rename2: init_out.minor >= FUSE_RENAME2_MINOR_VERSION,
+            batch_forget: init_out.minor >= FUSE_BATCH_FORGET_MINOR_VERSION,
+            lookups: LookupCounts::default(),
+            generation: 0,
+            subdir: None,
         })
     }
 
@@ -137,61 +184,95 @@ impl<'a> FuseSession<'a> {
         self.unique_counter.fetch_add(1, Ordering::Relaxed)
     }
 
-    /// Copy a serialized request into the request DMA buffer and send it,
-    /// expecting a metadata-sized response (4 KiB descriptor).
-    fn meta_exchange(&mut self, req: &[u8]) -> Result<Vec<u8>, FuseTransportError> {
-        if req.len() > self.req_buf.len() {
-            return Err(FuseTransportError::RequestTooLarge(req.len()));
+    /// Number of times the session was re-initialized. Nodeids and file
+    /// handles from an earlier generation mean nothing to the host.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Send FUSE_INIT again over the same device, after the transport
+    /// failed. See the module docs on re-initialization.
+    fn reinit(&mut self) -> Result<(), FuseTransportError> {
+        let unique = self.next_unique();
+        let init_out = fuse_init(self.device.as_mut(), unique)?;
+
+        self.max_readahead = init_out.max_readahead;
+        self.max_write = init_out.max_write;
+        self.rename2 = init_out.minor >= FUSE_RENAME2_MINOR_VERSION;
+        self.batch_forget = init_out.minor >= FUSE_BATCH_FORGET_MINOR_VERSION;
+        self.lookups = LookupCounts::default();
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// FUSE_BATCH_FORGET: give back the lookups of every counted node for
+    /// which `in_use` returns false, and return those nodeids.
+    ///
+    /// The host may reuse a forgotten nodeid for a different file, so the
+    /// caller must not keep anything keyed by the returned ones.
+    pub fn forget_unused(&mut self, in_use: impl FnMut(u64) -> bool) -> Vec<u64> {
+        let forgets = self.lookups.take_unused(in_use);
+        if forgets.is_empty() {
+            return Vec::new();
         }
-        self.req_buf[..req.len()].copy_from_slice(req);
-        fuse_exchange(
-            &self.queue,
-            &self.req_buf,
-            req.len(),
-            &self.resp_buf,
-            META_RESPONSE,
+
+        let reqs = forget_requests(&forgets, self.batch_forget, FORGET_BUF_SIZE, || {
+            self.next_unique()
+        });
+        for req in reqs {
+            self.device.send_noreply(&req);
+        }
+
+        forgets.iter().map(|f| f.nodeid).collect()
+    }
+
+    /// Send a request expecting up to `resp_len` bytes back, re-initializing
+    /// the session once if the transport fails (see [`exchange_or_reinit`]).
+    fn exchange(&mut self, req: &[u8], resp_len: usize) -> Result<Vec<u8>, FuseTransportError> {
+        exchange_or_reinit(
+            self,
+            req,
+            |session, req| session.device.exchange(req, resp_len),
+            Self::reinit,
         )
     }
 
-    /// Copy a serialized request into the request DMA buffer and send it,
-    /// expecting a data-sized response. The response descriptor is sized to
-    /// exactly `header + data_size` so virtiofsd reads the right amount.
+    /// Send a serialized request, expecting a metadata-sized response
+    /// (4 KiB descriptor).
+    fn meta_exchange(&mut self, req: &[u8]) -> Result<Vec<u8>, FuseTransportError> {
+        self.exchange(req, META_RESPONSE)
+    }
+
+    /// Send a serialized request, expecting a data-sized response. The
+    /// response descriptor is sized to exactly `header + data_size` so
+    /// virtiofsd reads the right amount.
     fn data_exchange(
         &mut self,
         req: &[u8],
         data_size: usize,
     ) -> Result<Vec<u8>, FuseTransportError> {
-        if req.len() > self.req_buf.len() {
-            return Err(FuseTransportError::RequestTooLarge(req.len()));
-        }
-        let resp_len = core::mem::size_of::<FuseOutHeader>() + data_size;
-        if resp_len > self.resp_buf.len() {
-            return Err(FuseTransportError::RequestTooLarge(resp_len));
-        }
-        self.req_buf[..req.len()].copy_from_slice(req);
-        fuse_exchange(
-            &self.queue,
-            &self.req_buf,
-            req.len(),
-            &self.resp_buf,
-            resp_len,
-        )
+        self.exchange(req, core::mem::size_of::<FuseOutHeader>() + data_size)
     }
 
-    /// Copy a large request (with write data) into the request DMA buffer
-    /// and send it, expecting a metadata-sized response.
+    /// Send a large request (with write data), expecting a metadata-sized
+    /// response.
     fn write_exchange(&mut self, req: &[u8]) -> Result<Vec<u8>, FuseTransportError> {
-        if req.len() > self.req_buf.len() {
-            return Err(FuseTransportError::RequestTooLarge(req.len()));
+        self.exchange(req, META_RESPONSE)
+    }
+
+    /// Parse a reply carrying a FuseEntryOut (LOOKUP, MKDIR, SYMLINK) and
+    /// count the lookup it gives us.
+    fn entry_reply(&mut self, resp: &[u8]) -> Result<FuseEntryOut, FuseTransportError> {
+        let _hdr = parse_response_header(resp)?;
+        let body = response_body(resp);
+
+        if body.len() < core::mem::size_of::<FuseEntryOut>() {
+            return Err(FuseTransportError::UnexpectedSize);
         }
-        self.req_buf[..req.len()].copy_from_slice(req);
-        fuse_exchange(
-            &self.queue,
-            &self.req_buf,
-            req.len(),
-            &self.resp_buf,
-            META_RESPONSE,
-        )
+
+        let entry = unsafe { *(body.as_ptr() as *const FuseEntryOut) };
+        self.lookups.add(entry.nodeid);
+        Ok(entry)
     }
 
     /// FUSE_LOOKUP: resolve a name in a directory to a node + attributes.
@@ -205,14 +286,38 @@ impl<'a> FuseSession<'a> {
         );
 
         let resp = self.meta_exchange(&req)?;
-        let _hdr = parse_response_header(&resp)?;
-        let body = response_body(&resp);
+        self.entry_reply(&resp)
+    }
 
-        if body.len() < core::mem::size_of::<FuseEntryOut>() {
-            return Err(FuseTransportError::UnexpectedSize);
+    /// Look up the directory `subdir` of the host's share, to serve it as
+    /// the scheme root. The reply's lookup is counted like any other, so the
+    /// caller has to keep the node from being forgotten.
+    pub fn lookup_subdir(&mut self, subdir: &str) -> Result<u64, FuseTransportError> {
+        let components =
+            path_components(subdir).ok_or(FuseTransportError::FuseError(-LINUX_EINVAL))?;
+
+        let mut is_dir = true;
+        let nodeid = walk(ROOT_NODEID, &components, |parent, name| {
+            let entry = self.lookup(parent, name)?;
+            is_dir = (entry.attr.mode & S_IFMT) == S_IFDIR;
+            Ok(entry.nodeid)
+        })?;
+
+        if !is_dir {
+            return Err(FuseTransportError::FuseError(-LINUX_ENOTDIR));
         }
+        self.subdir = Some(subdir.to_string());
+        Ok(nodeid)
+    }
 
-        Ok(unsafe { *(body.as_ptr() as *const FuseEntryOut) })
+    /// The node to serve as the scheme root: the FUSE root, or the node of
+    /// the directory [`FuseSession::lookup_subdir`] resolved, looked up
+    /// again. Used after a re-init, when the old node is gone.
+    pub fn resolve_root(&mut self) -> Result<u64, FuseTransportError> {
+        match self.subdir.clone() {
+            Some(subdir) => self.lookup_subdir(&subdir),
+            None => Ok(ROOT_NODEID),
+        }
     }
 
     /// FUSE_GETATTR: get attributes of a node.
@@ -232,14 +337,7 @@ impl<'a> FuseSession<'a> {
         );
 
         let resp = self.meta_exchange(&req)?;
-        let _hdr = parse_response_header(&resp)?;
-        let body = response_body(&resp);
-
-        if body.len() < core::mem::size_of::<FuseAttrOut>() {
-            return Err(FuseTransportError::UnexpectedSize);
-        }
-
-        Ok(unsafe { *(body.as_ptr() as *const FuseAttrOut) })
+        parse_attr_response(&resp)
     }
 
     /// FUSE_OPEN: open a file (returns a file handle).
@@ -405,9 +503,28 @@ impl<'a> FuseSession<'a> {
 
     /// FUSE_WRITE: write data to an open file.
     ///
+    /// Splits `data` into requests of at most the negotiated `max_write`
+    /// (capped at `MAX_IO_SIZE`) and advances the offset between them.
+    /// Returns the total number of bytes the host accepted; see
+    /// [`write_chunked`] for short writes and errors.
+    pub fn write(
+        &mut self,
+        nodeid: u64,
+        fh: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, FuseTransportError> {
+        let chunk_size = write_chunk_size(self.max_write);
+        write_chunked(chunk_size, offset, data, |off, chunk| {
+            self.write_once(nodeid, fh, off, chunk)
+        })
+    }
+
+    /// A single FUSE_WRITE request.
+    ///
     /// Data is packed into the request descriptor (header + FuseWriteIn + data).
     /// Returns the number of bytes actually written by the host.
-    pub fn write(
+    fn write_once(
         &mut self,
         nodeid: u64,
         fh: u64,
@@ -483,6 +600,7 @@ impl<'a> FuseSession<'a> {
 
         let entry = unsafe { *(body.as_ptr() as *const FuseEntryOut) };
         let open = unsafe { *(body[entry_size..].as_ptr() as *const FuseOpenOut) };
+        self.lookups.add(entry.nodeid);
 
         Ok((entry, open))
     }
@@ -508,14 +626,36 @@ impl<'a> FuseSession<'a> {
         );
 
         let resp = self.meta_exchange(&req)?;
-        let _hdr = parse_response_header(&resp)?;
-        let body = response_body(&resp);
+        self.entry_reply(&resp)
+    }
 
-        if body.len() < core::mem::size_of::<FuseEntryOut>() {
-            return Err(FuseTransportError::UnexpectedSize);
-        }
+    /// FUSE_SYMLINK: create a symlink `name` in `parent` pointing at `target`.
+    ///
+    /// The target is stored verbatim; it need not exist or be valid UTF-8.
+    pub fn symlink(
+        &mut self,
+        parent: u64,
+        name: &str,
+        target: &[u8],
+    ) -> Result<FuseEntryOut, FuseTransportError> {
+        let req = symlink_request(parent, self.next_unique(), name, target);
+
+        let resp = self.meta_exchange(&req)?;
+        self.entry_reply(&resp)
+    }
+
+    /// FUSE_READLINK: read a symlink's target as raw bytes.
+    pub fn readlink(&mut self, nodeid: u64) -> Result<Vec<u8>, FuseTransportError> {
+        let req = build_request(
+            FuseOpcode::Readlink as u32,
+            nodeid,
+            self.next_unique(),
+            &[],
+            None,
+        );
 
-        Ok(unsafe { *(body.as_ptr() as *const FuseEntryOut) })
+        let resp = self.meta_exchange(&req)?;
+        parse_readlink_response(&resp)
     }
 
     /// FUSE_UNLINK: remove a file.
@@ -548,6 +688,84 @@ impl<'a> FuseSession<'a> {
         Ok(())
     }
 
+    /// FUSE_RENAME2 (or FUSE_RENAME): move `name` in `parent` to `newname`
+    /// in `newdir`, replacing any existing entry there.
+    ///
+    /// RENAME2 is sent with no flags, so both opcodes behave like POSIX
+    /// rename(2). Hosts that reject RENAME2 with ENOSYS get plain RENAME
+    /// for the rest of the session.
+    pub fn rename(
+        &mut self,
+        parent: u64,
+        name: &str,
+        newdir: u64,
+        newname: &str,
+    ) -> Result<(), FuseTransportError> {
+        if self.rename2 {
+            let args = FuseRename2In {
+                newdir,
+                flags: 0,
+                padding: 0,
+            };
+            match self.send_rename(FuseOpcode::Rename2, parent, &args, name, newname) {
+                Err(FuseTransportError::FuseError(errno)) if errno == -LINUX_ENOSYS => {
+                    log::info!("virtio-fsd: host lacks FUSE_RENAME2, using FUSE_RENAME");
+                    self.rename2 = false;
+                }
+                result => return result,
+            }
+        }
+
+        let args = FuseRenameIn { newdir };
+        self.send_rename(FuseOpcode::Rename, parent, &args, name, newname)
+    }
+
+    /// Send a RENAME/RENAME2 request and check the reply.
+    fn send_rename<T: Sized>(
+        &mut self,
+        opcode: FuseOpcode,
+        parent: u64,
+        args: &T,
+        name: &str,
+        newname: &str,
+    ) -> Result<(), FuseTransportError> {
+        let req = rename_request(opcode, parent, self.next_unique(), args, name, newname);
+
+        let resp = self.meta_exchange(&req)?;
+        let _hdr = parse_response_header(&resp)?;
+        Ok(())
+    }
+
+    /// FUSE_FSYNC / FUSE_FSYNCDIR: flush an open handle to stable storage
+    /// on the host.
+    ///
+    /// With `datasync`, only file data is flushed (fdatasync semantics).
+    pub fn fsync(
+        &mut self,
+        nodeid: u64,
+        fh: u64,
+        is_dir: bool,
+        datasync: bool,
+    ) -> Result<(), FuseTransportError> {
+        let req = fsync_request(nodeid, self.next_unique(), fh, is_dir, datasync);
+
+        let resp = self.meta_exchange(&req)?;
+        let _hdr = parse_response_header(&resp)?;
+        Ok(())
+    }
+
+    /// FUSE_SETATTR: change the attributes selected by `args.valid`. The
+    /// reply carries the node's attributes after the change.
+    pub fn setattr(
+        &mut self,
+        nodeid: u64,
+        args: &FuseSetattrIn,
+    ) -> Result<FuseAttrOut, FuseTransportError> {
+        let req = setattr_request(nodeid, self.next_unique(), args);
+        let resp = self.meta_exchange(&req)?;
+        parse_attr_response(&resp)
+    }
+
     /// FUSE_SETATTR with FATTR_SIZE: truncate a file to a given length.
     pub fn truncate(
         &mut self,
@@ -555,66 +773,432 @@ impl<'a> FuseSession<'a> {
         fh: u64,
         size: u64,
     ) -> Result<FuseAttrOut, FuseTransportError> {
-        let args = FuseSetattrIn {
-            valid: FATTR_SIZE | FATTR_FH,
-            padding: 0,
-            fh,
-            size,
-            lock_owner: 0,
-            atime: 0,
-            mtime: 0,
-            ctime: 0,
-            atimensec: 0,
-            mtimensec: 0,
-            ctimensec: 0,
-            mode: 0,
-            unused4: 0,
-            uid: 0,
-            gid: 0,
-            unused5: 0,
-        };
+        self.setattr(nodeid, &truncate_args(fh, size))
+    }
 
-        let req = build_request_with_args(
-            FuseOpcode::Setattr as u32,
-            nodeid,
-            self.next_unique(),
-            &args,
-            None,
-        );
+    /// FUSE_SETATTR with FATTR_MODE: change permission bits.
+    pub fn chmod(
+        &mut self,
+        nodeid: u64,
+        fh: u64,
+        mode: u32,
+    ) -> Result<FuseAttrOut, FuseTransportError> {
+        self.setattr(nodeid, &chmod_args(fh, mode))
+    }
+
+    /// FUSE_SETATTR with FATTR_ATIME/FATTR_MTIME: set timestamps.
+    pub fn utimens(
+        &mut self,
+        nodeid: u64,
+        fh: u64,
+        atime: SetTime,
+        mtime: SetTime,
+    ) -> Result<FuseAttrOut, FuseTransportError> {
+        self.setattr(nodeid, &utimens_args(fh, atime, mtime))
+    }
 
+    /// FUSE_STATFS: get filesystem statistics for the shared directory.
+    pub fn statfs(&mut self) -> Result<FuseStatfsOut, FuseTransportError> {
+        let req = statfs_request(self.next_unique());
         let resp = self.meta_exchange(&req)?;
-        let _hdr = parse_response_header(&resp)?;
-        let body = response_body(&resp);
+        parse_statfs_response(&resp)
+    }
+}
 
-        if body.len() < core::mem::size_of::<FuseAttrOut>() {
-            return Err(FuseTransportError::UnexpectedSize);
+/// A timestamp to set with [FuseSession::utimens].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetTime {
+    /// Leave the timestamp alone.
+    Omit,
+    /// The host's current time.
+    Now,
+    /// Seconds and nanoseconds since the epoch.
+    At(u64, u32),
+}
+
+/// SETATTR arguments changing only what `valid` selects. `fh` is passed
+/// along when the node is open, so the host can act on the open file.
+fn setattr_args(valid: u32, fh: u64) -> FuseSetattrIn {
+    FuseSetattrIn {
+        valid: if fh != 0 { valid | FATTR_FH } else { valid },
+        fh,
+        ..Default::default()
+    }
+}
+
+fn truncate_args(fh: u64, size: u64) -> FuseSetattrIn {
+    FuseSetattrIn {
+        size,
+        ..setattr_args(FATTR_SIZE, fh)
+    }
+}
+
+/// Only permission bits are sent; the file type can't change.
+fn chmod_args(fh: u64, mode: u32) -> FuseSetattrIn {
+    FuseSetattrIn {
+        mode: mode & 0o7777,
+        ..setattr_args(FATTR_MODE, fh)
+    }
+}
+
+fn utimens_args(fh: u64, atime: SetTime, mtime: SetTime) -> FuseSetattrIn {
+    let mut args = setattr_args(0, fh);
+    match atime {
+        SetTime::Omit => {}
+        SetTime::Now => args.valid |= FATTR_ATIME | FATTR_ATIME_NOW,
+        SetTime::At(sec, nsec) => {
+            args.valid |= FATTR_ATIME;
+            args.atime = sec;
+            args.atimensec = nsec;
         }
+    }
+    match mtime {
+        SetTime::Omit => {}
+        SetTime::Now => args.valid |= FATTR_MTIME | FATTR_MTIME_NOW,
+        SetTime::At(sec, nsec) => {
+            args.valid |= FATTR_MTIME;
+            args.mtime = sec;
+            args.mtimensec = nsec;
+        }
+    }
+    args
+}
 
-        Ok(unsafe { *(body.as_ptr() as *const FuseAttrOut) })
+fn setattr_request(nodeid: u64, unique: u64, args: &FuseSetattrIn) -> Vec<u8> {
+    build_request_with_args(FuseOpcode::Setattr as u32, nodeid, unique, args, None)
+}
+
+/// GETATTR and SETATTR both reply with a [FuseAttrOut].
+fn parse_attr_response(resp: &[u8]) -> Result<FuseAttrOut, FuseTransportError> {
+    let _hdr = parse_response_header(resp)?;
+    let body = response_body(resp);
+
+    if body.len() < core::mem::size_of::<FuseAttrOut>() {
+        return Err(FuseTransportError::UnexpectedSize);
     }
 
-    /// FUSE_STATFS: get filesystem statistics.
-    pub fn statfs(&mut self) -> Result<FuseStatfsOut, FuseTransportError> {
-        let req = build_request(
-            FuseOpcode::Statfs as u32,
-            1,
-            self.next_unique(),
-            &[],
-            None,
+    Ok(unsafe { *(body.as_ptr() as *const FuseAttrOut) })
+}
+
+/// FUSE_SYMLINK carries no args struct, just the link name and the target,
+/// each null-terminated.
+fn symlink_request(parent: u64, unique: u64, name: &str, target: &[u8]) -> Vec<u8> {
+    // build_request terminates the trailing target; the name's terminator
+    // is added here.
+    let mut names = Vec::with_capacity(name.len() + 1 + target.len());
+    names.extend_from_slice(name.as_bytes());
+    names.push(0);
+    names.extend_from_slice(target);
+
+    build_request(FuseOpcode::Symlink as u32, parent, unique, &[], Some(&names))
+}
+
+/// RENAME and RENAME2 carry their args (which start with the new parent),
+/// then the old and the new name, each null-terminated.
+fn rename_request<T: Sized>(
+    opcode: FuseOpcode,
+    parent: u64,
+    unique: u64,
+    args: &T,
+    name: &str,
+    newname: &str,
+) -> Vec<u8> {
+    // build_request terminates the new name; the old name's terminator
+    // is added here.
+    let mut names = Vec::with_capacity(name.len() + 1 + newname.len());
+    names.extend_from_slice(name.as_bytes());
+    names.push(0);
+    names.extend_from_slice(newname.as_bytes());
+
+    build_request_with_args(opcode as u32, parent, unique, args, Some(&names))
+}
+
+/// FUSE_FSYNC (FSYNCDIR for a directory handle) for `fh` on `nodeid`.
+fn fsync_request(nodeid: u64, unique: u64, fh: u64, is_dir: bool, datasync: bool) -> Vec<u8> {
+    let args = FuseFsyncIn {
+        fh,
+        fsync_flags: if datasync { FUSE_FSYNC_FDATASYNC } else { 0 },
+        padding: 0,
+    };
+
+    let opcode = if is_dir {
+        FuseOpcode::Fsyncdir
+    } else {
+        FuseOpcode::Fsync
+    };
+
+    build_request_with_args(opcode as u32, nodeid, unique, &args, None)
+}
+
+/// The READLINK reply body is the target itself, without a terminator.
+fn parse_readlink_response(resp: &[u8]) -> Result<Vec<u8>, FuseTransportError> {
+    let _hdr = parse_response_header(resp)?;
+    Ok(response_body(resp).to_vec())
+}
+
+/// FUSE_STATFS takes no arguments; the node is the root of the share.
+fn statfs_request(unique: u64) -> Vec<u8> {
+    build_request(FuseOpcode::Statfs as u32, 1, unique, &[], None)
+}
+
+fn parse_statfs_response(resp: &[u8]) -> Result<FuseStatfsOut, FuseTransportError> {
+    let _hdr = parse_response_header(resp)?;
+    let body = response_body(resp);
+
+    if body.len() < core::mem::size_of::<FuseStatfsOut>() {
+        return Err(FuseTransportError::UnexpectedSize);
+    }
+
+    Ok(unsafe { *(body.as_ptr() as *const FuseStatfsOut) })
+}
+
+/// Largest payload for one FUSE_WRITE: the negotiated `max_write`, capped
+/// at what the request buffer holds. A host reporting 0 gets the buffer size.
+fn write_chunk_size(max_write: u32) -> usize {
+    match max_write as usize {
+        0 => MAX_IO_SIZE,
+        n => n.min(MAX_IO_SIZE),
+    }
+}
+
+/// Issue `data` as consecutive writes of at most `chunk_size` bytes each,
+/// starting at `offset`, and return the total accepted.
+///
+/// As with POSIX write(2), a short write from the host ends the loop and
+/// its partial count is returned. An error after some bytes got through
+/// also returns the partial count; an error on the first chunk is returned.
+fn write_chunked<E>(
+    chunk_size: usize,
+    offset: u64,
+    data: &[u8],
+    mut write_one: impl FnMut(u64, &[u8]) -> Result<u32, E>,
+) -> Result<usize, E> {
+    let mut written = 0usize;
+
+    for chunk in data.chunks(chunk_size.max(1)) {
+        let accepted = match write_one(offset + written as u64, chunk) {
+            Ok(n) => (n as usize).min(chunk.len()),
+            Err(e) if written == 0 => return Err(e),
+            Err(_) => break,
+        };
+        written += accepted;
+        if accepted < chunk.len() {
+            break;
+        }
+    }
+
+    Ok(written)
+}
+
+/// Size of the hiprio request buffer; one BATCH_FORGET fills at most this.
+const FORGET_BUF_SIZE: usize = 4096;
+
+/// The FUSE root node. The host never drops it, so it is not counted.
+pub const ROOT_NODEID: u64 = 1;
+
+/// FUSE_INIT handshake, over the session's device.
+fn fuse_init(device: &mut dyn FuseDevice, unique: u64) -> Result<FuseInitOut, FuseTransportError> {
+    let init_in = FuseInitIn {
+        major: FUSE_KERNEL_VERSION,
+        minor: FUSE_KERNEL_MINOR_VERSION,
+        max_readahead: 1024 * 1024, // 1 MiB
+        flags: 0,
+        flags2: 0,
+        unused: [0; 11],
+    };
+
+    let req = build_request_with_args(FuseOpcode::Init as u32, 0, unique, &init_in, None);
+
+    let resp = device.exchange(&req, META_RESPONSE)?;
+
+    let _hdr = parse_response_header(&resp)?;
+    let body = response_body(&resp);
+
+    if body.len() < core::mem::size_of::<FuseInitOut>() {
+        return Err(FuseTransportError::UnexpectedSize);
+    }
+
+    let init_out = unsafe { *(body.as_ptr() as *const FuseInitOut) };
+
+    log::info!(
+        "virtio-fsd: FUSE init: version {}.{}, max_readahead={}, max_write={}",
+        init_out.major,
+        init_out.minor,
+        init_out.max_readahead,
+        init_out.max_write
+    );
+
+    if init_out.max_write as usize > MAX_IO_SIZE {
+        log::warn!(
+            "virtio-fsd: negotiated max_write ({}) exceeds buffer size ({}), writes are capped at the buffer size",
+            init_out.max_write,
+            MAX_IO_SIZE
         );
+    }
 
-        let resp = self.meta_exchange(&req)?;
-        let _hdr = parse_response_header(&resp)?;
-        let body = response_body(&resp);
+    Ok(init_out)
+}
 
-        if body.len() < core::mem::size_of::<FuseStatfsOut>() {
-            return Err(FuseTransportError::UnexpectedSize);
+/// The header of a serialized request.
+fn request_header(req: &[u8]) -> Option<FuseInHeader> {
+    if req.len() < core::mem::size_of::<FuseInHeader>() {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_unaligned(req.as_ptr() as *const FuseInHeader) })
+}
+
+/// Whether `req` may be sent again to a re-initialized host: it only reads
+/// the root node, the one node both sessions agree on. Anything else may
+/// already have changed the share, or names a node or file handle of the
+/// old session.
+fn resendable(req: &[u8]) -> bool {
+    const READS: [FuseOpcode; 3] = [FuseOpcode::Lookup, FuseOpcode::Getattr, FuseOpcode::Statfs];
+    request_header(req).is_some_and(|hdr| {
+        hdr.nodeid == ROOT_NODEID && READS.iter().any(|&op| op as u32 == hdr.opcode)
+    })
+}
+
+/// Send `req` with `send`. If the transport fails, re-initialize the
+/// session with `reinit`, once: a request that only reads the root node is
+/// then sent again (see [`resendable`]), any other fails with
+/// [`FuseTransportError::Reconnected`]. If the re-init fails too, the
+/// original error is returned.
+fn exchange_or_reinit<S>(
+    session: &mut S,
+    req: &[u8],
+    mut send: impl FnMut(&mut S, &[u8]) -> Result<Vec<u8>, FuseTransportError>,
+    reinit: impl FnOnce(&mut S) -> Result<(), FuseTransportError>,
+) -> Result<Vec<u8>, FuseTransportError> {
+    let err = match send(session, req) {
+        Err(err) if err.is_transport() => err,
+        result => return result,
+    };
+
+    log::warn!("virtio-fsd: {}, re-initializing the FUSE session", err);
+    if let Err(e) = reinit(session) {
+        log::error!("virtio-fsd: FUSE re-init failed: {}", e);
+        return Err(err);
+    }
+    log::info!("virtio-fsd: FUSE session re-initialized");
+
+    if resendable(req) {
+        send(session, req)
+    } else {
+        Err(FuseTransportError::Reconnected)
+    }
+}
+
+/// The components of a scheme path once `.` and `..` are applied, or `None`
+/// if `..` climbs above the start. Walking them never leaves the node they
+/// are walked from, even when the host would follow `..` out of it.
+pub fn path_components(path: &str) -> Option<Vec<&str>> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            name => components.push(name),
         }
+    }
+    Some(components)
+}
+
+/// Follow `components` down from `start`, one `lookup` per component, to
+/// the nodeid they name.
+pub fn walk<E>(
+    start: u64,
+    components: &[&str],
+    mut lookup: impl FnMut(u64, &str) -> Result<u64, E>,
+) -> Result<u64, E> {
+    components
+        .iter()
+        .try_fold(start, |nodeid, name| lookup(nodeid, name))
+}
+
+/// Per-node count of replies that handed out a nodeid (the host's
+/// `nlookup`), which FORGET has to give back in full.
+#[derive(Debug, Default)]
+pub struct LookupCounts {
+    counts: HashMap<u64, u64>,
+}
+
+impl LookupCounts {
+    /// Count one reply carrying `nodeid`.
+    pub fn add(&mut self, nodeid: u64) {
+        if nodeid != ROOT_NODEID {
+            *self.counts.entry(nodeid).or_default() += 1;
+        }
+    }
 
-        Ok(unsafe { *(body.as_ptr() as *const FuseStatfsOut) })
+    /// Remove every node for which `in_use` returns false and return its
+    /// FORGET, ordered by nodeid.
+    pub fn take_unused(&mut self, mut in_use: impl FnMut(u64) -> bool) -> Vec<FuseForgetOne> {
+        let mut forgets = Vec::new();
+        self.counts.retain(|&nodeid, &mut nlookup| {
+            if in_use(nodeid) {
+                return true;
+            }
+            forgets.push(FuseForgetOne { nodeid, nlookup });
+            false
+        });
+        forgets.sort_by_key(|f| f.nodeid);
+        forgets
     }
 }
 
+/// Serialize `forgets` as BATCH_FORGET requests of at most `max_len` bytes
+/// each or, when the host predates it (`batch` unset), one FORGET per node.
+fn forget_requests(
+    forgets: &[FuseForgetOne],
+    batch: bool,
+    max_len: usize,
+    mut next_unique: impl FnMut() -> u64,
+) -> Vec<Vec<u8>> {
+    if !batch {
+        return forgets
+            .iter()
+            .map(|f| {
+                let args = FuseForgetIn { nlookup: f.nlookup };
+                build_request_with_args(
+                    FuseOpcode::Forget as u32,
+                    f.nodeid,
+                    next_unique(),
+                    &args,
+                    None,
+                )
+            })
+            .collect();
+    }
+
+    let one = core::mem::size_of::<FuseForgetOne>();
+    let per_request = (max_len
+        - core::mem::size_of::<FuseInHeader>()
+        - core::mem::size_of::<FuseBatchForgetIn>())
+        / one;
+
+    forgets
+        .chunks(per_request.max(1))
+        .map(|chunk| {
+            let args = FuseBatchForgetIn {
+                count: chunk.len() as u32,
+                dummy: 0,
+            };
+            let items = unsafe {
+                core::slice::from_raw_parts(chunk.as_ptr() as *const u8, chunk.len() * one)
+            };
+            build_request_with_data(
+                FuseOpcode::Batchforget as u32,
+                0,
+                next_unique(),
+                &args,
+                items,
+            )
+        })
+        .collect()
+}
+
 /// Parsed directory entry.
 #[derive(Debug, Clone)]
 pub struct DirEntry {
@@ -654,3 +1238,668 @@ fn parse_dirents(data: &[u8]) -> Result<Vec<DirEntry>, FuseTransportError> {
 
     Ok(entries)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_host::TestHost;
+
+    #[test]
+    fn write_splits_at_max_write() {
+        let data = vec![0xa5u8; 256 * 1024];
+        let mut requests = Vec::new();
+
+        let written = write_chunked::<()>(write_chunk_size(64 * 1024), 4096, &data, |off, chunk| {
+            requests.push((off, chunk.len()));
+            Ok(chunk.len() as u32)
+        })
+        .unwrap();
+
+        assert_eq!(written, data.len());
+        assert_eq!(
+            requests,
+            vec![
+                (4096, 65536),
+                (4096 + 65536, 65536),
+                (4096 + 2 * 65536, 65536),
+                (4096 + 3 * 65536, 65536),
+            ]
+        );
+    }
+
+    #[test]
+    fn short_write_stops_the_loop() {
+        let data = vec![0u8; 3 * 1024];
+        let mut calls = 0;
+
+        let written = write_chunked::<()>(1024, 0, &data, |_, chunk| {
+            calls += 1;
+            Ok(if calls == 2 { 100 } else { chunk.len() as u32 })
+        })
+        .unwrap();
+
+        assert_eq!(written, 1024 + 100);
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn error_after_progress_returns_partial_count() {
+        let data = vec![0u8; 2048];
+        let mut calls = 0;
+
+        let written = write_chunked(1024, 0, &data, |_, chunk| {
+            calls += 1;
+            if calls == 1 { Ok(chunk.len() as u32) } else { Err("EIO") }
+        });
+        assert_eq!(written, Ok(1024));
+
+        let failed = write_chunked(1024, 0, &data, |_, _| Err::<u32, _>("EIO"));
+        assert_eq!(failed, Err("EIO"));
+    }
+
+    fn response(error: i32, body: &[u8]) -> Vec<u8> {
+        let hdr = FuseOutHeader {
+            len: (core::mem::size_of::<FuseOutHeader>() + body.len()) as u32,
+            error,
+            unique: 7,
+        };
+        let mut resp = unsafe {
+            core::slice::from_raw_parts(
+                &hdr as *const _ as *const u8,
+                core::mem::size_of::<FuseOutHeader>(),
+            )
+        }
+        .to_vec();
+        resp.extend_from_slice(body);
+        resp
+    }
+
+    fn statfs_response(error: i32, st: FuseKstatfs) -> Vec<u8> {
+        let body_len = if error == 0 { core::mem::size_of::<FuseStatfsOut>() } else { 0 };
+        let out = FuseStatfsOut { st };
+        let body = unsafe { core::slice::from_raw_parts(&out as *const _ as *const u8, body_len) };
+        response(error, body)
+    }
+
+    fn kstatfs(bsize: u32, frsize: u32, blocks: u64, bfree: u64, bavail: u64) -> FuseKstatfs {
+        FuseKstatfs {
+            blocks,
+            bfree,
+            bavail,
+            files: 1000,
+            ffree: 900,
+            bsize,
+            namelen: 255,
+            frsize,
+            padding: 0,
+            spare: [0; 6],
+        }
+    }
+
+    #[test]
+    fn statfs_targets_root_node() {
+        let req = statfs_request(7);
+        let hdr = unsafe { *(req.as_ptr() as *const FuseInHeader) };
+
+        assert_eq!(req.len(), core::mem::size_of::<FuseInHeader>());
+        assert_eq!(hdr.len as usize, req.len());
+        assert_eq!(hdr.opcode, FuseOpcode::Statfs as u32);
+        assert_eq!(hdr.nodeid, 1);
+        assert_eq!(hdr.unique, 7);
+    }
+
+    #[test]
+    fn statfs_block_counts_pass_through() {
+        let resp = statfs_response(0, kstatfs(4096, 4096, 1_000_000, 250_000, 200_000));
+        let st = parse_statfs_response(&resp).unwrap().st;
+
+        assert_eq!(st.block_unit(), 4096);
+        assert_eq!((st.blocks, st.bfree, st.bavail), (1_000_000, 250_000, 200_000));
+        assert_eq!((st.files, st.ffree), (1000, 900));
+
+        // Counts are in fragment units when the host distinguishes them.
+        assert_eq!(kstatfs(65536, 4096, 1, 1, 1).block_unit(), 4096);
+    }
+
+    #[test]
+    fn statfs_zeros_are_reported_as_is() {
+        let resp = statfs_response(0, kstatfs(0, 0, 0, 0, 0));
+        let st = parse_statfs_response(&resp).unwrap().st;
+
+        assert_eq!(st.block_unit(), 0);
+        assert_eq!((st.blocks, st.bfree, st.bavail), (0, 0, 0));
+    }
+
+    #[test]
+    fn statfs_host_error_and_short_body() {
+        let resp = statfs_response(-38, kstatfs(0, 0, 0, 0, 0));
+        assert!(matches!(
+            parse_statfs_response(&resp),
+            Err(FuseTransportError::FuseError(-38))
+        ));
+
+        let mut resp = statfs_response(0, kstatfs(4096, 0, 1, 1, 1));
+        resp.truncate(resp.len() - 8);
+        assert!(matches!(
+            parse_statfs_response(&resp),
+            Err(FuseTransportError::UnexpectedSize)
+        ));
+    }
+
+    /// What virtiofsd does with a SYMLINK request: split the body into the
+    /// link name and its target.
+    fn host_symlink(req: &[u8]) -> (u64, String, Vec<u8>) {
+        let hdr = unsafe { *(req.as_ptr() as *const FuseInHeader) };
+        assert_eq!(hdr.opcode, FuseOpcode::Symlink as u32);
+        assert_eq!(hdr.len as usize, req.len());
+
+        let body = &req[core::mem::size_of::<FuseInHeader>()..];
+        let (name, rest) = body.split_at(body.iter().position(|&b| b == 0).unwrap());
+        let target = rest[1..].strip_suffix(&[0]).expect("target is null-terminated");
+        (hdr.nodeid, String::from_utf8(name.to_vec()).unwrap(), target.to_vec())
+    }
+
+    /// What virtiofsd reads from a RENAME or RENAME2 request: the opcode,
+    /// both parents and both names.
+    fn host_rename(req: &[u8], args_len: usize) -> (u32, u64, u64, String, String) {
+        let hdr = unsafe { *(req.as_ptr() as *const FuseInHeader) };
+        assert_eq!(hdr.len as usize, req.len());
+
+        let body = &req[core::mem::size_of::<FuseInHeader>()..];
+        let newdir = u64::from_ne_bytes(body[..8].try_into().unwrap());
+        let names = body[args_len..].strip_suffix(&[0]).expect("new name is null-terminated");
+        let (name, newname) = names.split_at(names.iter().position(|&b| b == 0).unwrap());
+        (
+            hdr.opcode,
+            hdr.nodeid,
+            newdir,
+            String::from_utf8(name.to_vec()).unwrap(),
+            String::from_utf8(newname[1..].to_vec()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn rename2_carries_both_parents_and_names() {
+        let args = FuseRename2In {
+            newdir: 9,
+            flags: 0,
+            padding: 0,
+        };
+        let req = rename_request(FuseOpcode::Rename2, 4, 7, &args, "a.tmp", "a");
+        let args_len = core::mem::size_of::<FuseRename2In>();
+
+        assert_eq!(
+            host_rename(&req, args_len),
+            (FuseOpcode::Rename2 as u32, 4, 9, "a.tmp".into(), "a".into())
+        );
+        let flags = &req[core::mem::size_of::<FuseInHeader>() + 8..][..4];
+        assert_eq!(flags, [0; 4], "RENAME2 is sent without flags");
+    }
+
+    #[test]
+    fn plain_rename_has_the_short_args() {
+        let args = FuseRenameIn { newdir: 4 };
+        let req = rename_request(FuseOpcode::Rename, 4, 7, &args, "old", "new name");
+
+        assert_eq!(
+            host_rename(&req, core::mem::size_of::<FuseRenameIn>()),
+            (FuseOpcode::Rename as u32, 4, 4, "old".into(), "new name".into())
+        );
+    }
+
+    #[test]
+    fn fsync_names_the_handle_and_sync_kind() {
+        let hdr_len = core::mem::size_of::<FuseInHeader>();
+        for (is_dir, datasync, opcode, flags) in [
+            (false, false, FuseOpcode::Fsync, 0),
+            (false, true, FuseOpcode::Fsync, FUSE_FSYNC_FDATASYNC),
+            (true, false, FuseOpcode::Fsyncdir, 0),
+        ] {
+            let req = fsync_request(12, 7, 0x55, is_dir, datasync);
+            let hdr = unsafe { *(req.as_ptr() as *const FuseInHeader) };
+            let args = unsafe { *(req[hdr_len..].as_ptr() as *const FuseFsyncIn) };
+
+            assert_eq!(req.len(), hdr_len + core::mem::size_of::<FuseFsyncIn>());
+            assert_eq!(hdr.len as usize, req.len());
+            assert_eq!((hdr.opcode, hdr.nodeid, hdr.unique), (opcode as u32, 12, 7));
+            assert_eq!((args.fh, args.fsync_flags), (0x55, flags), "datasync={datasync}");
+        }
+    }
+
+    #[test]
+    fn rename_moves_the_entry() {
+        let host = TestHost::new();
+        let file = host.fs().add_file("a.tmp", b"x");
+        let dir = host.fs().add_dir("d");
+        let mut session = host.session();
+
+        session.rename(ROOT_NODEID, "a.tmp", dir, "a").unwrap();
+        assert_eq!(host.fs().find("d/a"), Some(file));
+        assert_eq!(host.fs().find("a.tmp"), None);
+        assert_eq!(host.fs().count(FuseOpcode::Rename2), 1);
+
+        let missing = session.rename(ROOT_NODEID, "a.tmp", dir, "b");
+        assert!(matches!(missing, Err(FuseTransportError::FuseError(-2))));
+    }
+
+    #[test]
+    fn hosts_without_rename2_get_rename() {
+        // A host whose INIT reply is older than RENAME2 is never sent one.
+        let host = TestHost::new();
+        host.fs().minor = FUSE_RENAME2_MINOR_VERSION - 1;
+        host.fs().add_file("a", b"");
+        let mut session = host.session();
+        session.rename(ROOT_NODEID, "a", ROOT_NODEID, "b").unwrap();
+        assert_eq!(host.fs().count(FuseOpcode::Rename2), 0);
+        assert_eq!(host.fs().find("b"), Some(2));
+
+        // One that claims it but answers ENOSYS is asked once, then not again.
+        let host = TestHost::new();
+        host.fs().add_file("a", b"");
+        let mut session = host.session();
+        host.fs().minor = FUSE_RENAME2_MINOR_VERSION - 1;
+        session.rename(ROOT_NODEID, "a", ROOT_NODEID, "b").unwrap();
+        session.rename(ROOT_NODEID, "b", ROOT_NODEID, "c").unwrap();
+        assert_eq!(host.fs().count(FuseOpcode::Rename2), 1);
+        assert_eq!(host.fs().count(FuseOpcode::Rename), 2);
+        assert_eq!(host.fs().find("c"), Some(2));
+    }
+
+    #[test]
+    fn fsync_reaches_the_host() {
+        let host = TestHost::new();
+        let file = host.fs().add_file("f", b"");
+        let dir = host.fs().add_dir("d");
+        let mut session = host.session();
+
+        let f = session.open(file, 0o1).unwrap();
+        let d = session.opendir(dir).unwrap();
+        session.fsync(file, f.fh, false, false).unwrap();
+        session.fsync(file, f.fh, false, true).unwrap();
+        session.fsync(dir, d.fh, true, false).unwrap();
+
+        let (fsync, fsyncdir) = (FuseOpcode::Fsync as u32, FuseOpcode::Fsyncdir as u32);
+        assert_eq!(
+            host.fs().fsyncs,
+            [(fsync, f.fh, 0), (fsync, f.fh, FUSE_FSYNC_FDATASYNC), (fsyncdir, d.fh, 0)]
+        );
+    }
+
+    #[test]
+    fn symlink_then_readlink_round_trip() {
+        for target in [
+            &b"../lib/libfoo.so.1"[..],
+            b"/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello-1.0/bin/hello",
+            b"dangling",
+            b"\xff\xfe not utf-8",
+        ] {
+            let req = symlink_request(42, 7, "link", target);
+            let (parent, name, stored) = host_symlink(&req);
+            assert_eq!((parent, name.as_str()), (42, "link"));
+
+            let resp = response(0, &stored);
+            assert_eq!(parse_readlink_response(&resp).unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn readlink_errors_pass_through() {
+        // EINVAL: the node is not a symlink.
+        assert!(matches!(
+            parse_readlink_response(&response(-22, &[])),
+            Err(FuseTransportError::FuseError(-22))
+        ));
+    }
+
+    /// What virtiofsd decodes from a SETATTR request.
+    fn host_setattr(req: &[u8]) -> (u64, FuseSetattrIn) {
+        let hdr = unsafe { *(req.as_ptr() as *const FuseInHeader) };
+        assert_eq!(hdr.opcode, FuseOpcode::Setattr as u32);
+        assert_eq!(hdr.len as usize, req.len());
+        assert_eq!(
+            req.len(),
+            core::mem::size_of::<FuseInHeader>() + core::mem::size_of::<FuseSetattrIn>()
+        );
+
+        let body = &req[core::mem::size_of::<FuseInHeader>()..];
+        (hdr.nodeid, unsafe { *(body.as_ptr() as *const FuseSetattrIn) })
+    }
+
+    #[test]
+    fn chmod_plus_x_sets_mode_only() {
+        let req = setattr_request(42, 7, &chmod_args(3, 0o100755));
+        let (nodeid, args) = host_setattr(&req);
+
+        assert_eq!(nodeid, 42);
+        assert_eq!(args.valid, FATTR_MODE | FATTR_FH);
+        assert_eq!(args.fh, 3);
+        assert_eq!(args.mode, 0o755);
+
+        // Without an open file the host goes by nodeid alone.
+        assert_eq!(chmod_args(0, 0o644).valid, FATTR_MODE);
+    }
+
+    #[test]
+    fn truncate_to_zero_sets_size_only() {
+        let req = setattr_request(42, 7, &truncate_args(3, 0));
+        let (_, args) = host_setattr(&req);
+
+        assert_eq!(args.valid, FATTR_SIZE | FATTR_FH);
+        assert_eq!((args.fh, args.size), (3, 0));
+        assert_eq!(args.valid & (FATTR_MODE | FATTR_ATIME | FATTR_MTIME), 0);
+    }
+
+    #[test]
+    fn utimens_masks() {
+        // What Nix does to store paths: mtime at the epoch.
+        let args = utimens_args(3, SetTime::Omit, SetTime::At(1, 0));
+        assert_eq!(args.valid, FATTR_MTIME | FATTR_FH);
+        assert_eq!((args.mtime, args.mtimensec), (1, 0));
+
+        let args = utimens_args(0, SetTime::Now, SetTime::At(1_700_000_000, 500));
+        assert_eq!(args.valid, FATTR_ATIME | FATTR_ATIME_NOW | FATTR_MTIME);
+        assert_eq!((args.mtime, args.mtimensec), (1_700_000_000, 500));
+
+        assert_eq!(utimens_args(0, SetTime::Omit, SetTime::Omit).valid, 0);
+    }
+
+    #[test]
+    fn setattr_reply_carries_new_attrs() {
+        let out = FuseAttrOut {
+            attr_valid: 1,
+            attr_valid_nsec: 0,
+            dummy: 0,
+            attr: FuseAttr {
+                size: 0,
+                mode: 0o100755,
+                ..Default::default()
+            },
+        };
+        let body = unsafe {
+            core::slice::from_raw_parts(
+                &out as *const _ as *const u8,
+                core::mem::size_of::<FuseAttrOut>(),
+            )
+        };
+
+        let attr = parse_attr_response(&response(0, body)).unwrap().attr;
+        assert_eq!((attr.mode, attr.size), (0o100755, 0));
+
+        // EPERM: not the owner.
+        assert!(matches!(
+            parse_attr_response(&response(-1, &[])),
+            Err(FuseTransportError::FuseError(-1))
+        ));
+        assert!(matches!(
+            parse_attr_response(&response(0, &body[..8])),
+            Err(FuseTransportError::UnexpectedSize)
+        ));
+    }
+
+    #[test]
+    fn chunk_size_capped_at_buffer() {
+        assert_eq!(write_chunk_size(64 * 1024), 64 * 1024);
+        assert_eq!(write_chunk_size(u32::MAX), MAX_IO_SIZE);
+        assert_eq!(write_chunk_size(0), MAX_IO_SIZE);
+    }
+
+    /// What virtiofsd decodes from a BATCH_FORGET request.
+    fn host_batch_forget(req: &[u8]) -> Vec<FuseForgetOne> {
+        let hdr = unsafe { *(req.as_ptr() as *const FuseInHeader) };
+        assert_eq!(hdr.opcode, FuseOpcode::Batchforget as u32);
+        assert_eq!(hdr.len as usize, req.len());
+
+        let body = &req[core::mem::size_of::<FuseInHeader>()..];
+        let args = unsafe { *(body.as_ptr() as *const FuseBatchForgetIn) };
+        let items = &body[core::mem::size_of::<FuseBatchForgetIn>()..];
+        assert_eq!(items.len(), args.count as usize * core::mem::size_of::<FuseForgetOne>());
+
+        items
+            .chunks(core::mem::size_of::<FuseForgetOne>())
+            .map(|item| unsafe {
+                core::ptr::read_unaligned(item.as_ptr() as *const FuseForgetOne)
+            })
+            .collect()
+    }
+
+    /// A device that fails the first `failures` requests, and a host that
+    /// accepts FUSE_INIT unless `init_fails`.
+    #[derive(Default)]
+    struct FlakyHost {
+        failures: usize,
+        init_fails: bool,
+        inits: u32,
+        sent: Vec<u64>,
+    }
+
+    fn flaky_send(host: &mut FlakyHost, req: &[u8]) -> Result<Vec<u8>, FuseTransportError> {
+        let nodeid = request_header(req).unwrap().nodeid;
+        host.sent.push(nodeid);
+        if host.failures > 0 {
+            host.failures -= 1;
+            return Err(FuseTransportError::ShortResponse(0));
+        }
+        match nodeid {
+            9 => Ok(response(-2, &[])),
+            _ => Ok(response(0, b"ok")),
+        }
+    }
+
+    fn flaky_reinit(host: &mut FlakyHost) -> Result<(), FuseTransportError> {
+        host.inits += 1;
+        if host.init_fails {
+            return Err(FuseTransportError::ShortResponse(0));
+        }
+        Ok(())
+    }
+
+    fn lookup_request(parent: u64) -> Vec<u8> {
+        build_request(FuseOpcode::Lookup as u32, parent, 2, &[], Some(b"nix"))
+    }
+
+    #[test]
+    fn transport_error_reinits_and_retries() {
+        // The first request fails; after FUSE_INIT the retry goes through.
+        let mut host = FlakyHost { failures: 1, ..Default::default() };
+        let resp =
+            exchange_or_reinit(&mut host, &lookup_request(ROOT_NODEID), flaky_send, flaky_reinit);
+        assert_eq!(resp.unwrap(), response(0, b"ok"));
+        assert_eq!(host.inits, 1);
+        assert_eq!(host.sent, vec![ROOT_NODEID, ROOT_NODEID]);
+
+        // Later requests don't re-init again.
+        let resp = exchange_or_reinit(&mut host, &lookup_request(7), flaky_send, flaky_reinit);
+        assert_eq!(resp.unwrap(), response(0, b"ok"));
+        assert_eq!(host.inits, 1);
+    }
+
+    #[test]
+    fn stale_nodes_are_not_retried() {
+        let mut host = FlakyHost { failures: 1, ..Default::default() };
+        let resp = exchange_or_reinit(&mut host, &lookup_request(7), flaky_send, flaky_reinit);
+        assert!(matches!(resp, Err(FuseTransportError::Reconnected)));
+        assert_eq!(host.inits, 1);
+        assert_eq!(host.sent, vec![7]);
+
+        // A failed re-init reports the transport error.
+        let mut host = FlakyHost { failures: 1, init_fails: true, ..Default::default() };
+        let resp =
+            exchange_or_reinit(&mut host, &lookup_request(ROOT_NODEID), flaky_send, flaky_reinit);
+        assert!(matches!(resp, Err(FuseTransportError::ShortResponse(0))));
+        assert_eq!(host.sent.len(), 1);
+
+        // Host errors are answers, not transport failures.
+        let mut host = FlakyHost::default();
+        let resp = exchange_or_reinit(&mut host, &lookup_request(9), flaky_send, flaky_reinit);
+        assert_eq!(resp.unwrap(), response(-2, &[]));
+        assert_eq!(host.inits, 0);
+    }
+
+    #[test]
+    fn reinit_resends_only_reads_of_the_root() {
+        let host = TestHost::new();
+        host.fs().add_dir("etc");
+        host.fs().add_file("hosts", b"");
+        let mut session = host.session();
+
+        // A LOOKUP in the root survives the device failing under it.
+        host.fs().failures = 1;
+        let etc = session.lookup(ROOT_NODEID, "etc").unwrap();
+        assert_eq!((host.fs().inits, session.generation()), (2, 1));
+        host.fs().failures = 1;
+        assert_eq!(session.statfs().unwrap().st.blocks, 1000);
+
+        // Changes are not sent again: the failed request may have reached
+        // the old host already.
+        type Change = fn(&mut FuseSession<'static>) -> Result<(), FuseTransportError>;
+        let changes: [(FuseOpcode, Change); 4] = [
+            (FuseOpcode::Create, |s| s.create(ROOT_NODEID, "new", 0o101, 0o644).map(drop)),
+            (FuseOpcode::Mkdir, |s| s.mkdir(ROOT_NODEID, "new", 0o755).map(drop)),
+            (FuseOpcode::Unlink, |s| s.unlink(ROOT_NODEID, "hosts")),
+            (FuseOpcode::Rename2, |s| s.rename(ROOT_NODEID, "etc", ROOT_NODEID, "etc2")),
+        ];
+        for (opcode, change) in changes {
+            host.fs().failures = 1;
+            let result = change(&mut session);
+            assert!(matches!(result, Err(FuseTransportError::Reconnected)), "{opcode:?}");
+            assert_eq!(host.fs().count(opcode), 0, "{opcode:?} was sent again");
+        }
+        assert_eq!(host.fs().find("hosts"), Some(3));
+
+        // Neither are reads through a handle or a node of the old session.
+        let dir = session.opendir(ROOT_NODEID).unwrap();
+        host.fs().failures = 1;
+        let listing = session.readdir(ROOT_NODEID, dir.fh, 0, 4096);
+        assert!(matches!(listing, Err(FuseTransportError::Reconnected)));
+        host.fs().failures = 1;
+        let attr = session.getattr(etc.nodeid);
+        assert!(matches!(attr, Err(FuseTransportError::Reconnected)));
+        assert_eq!(host.fs().count(FuseOpcode::Readdir), 0);
+    }
+
+    #[test]
+    fn subdir_prefixes_lookups() {
+        // The host's share: / → nix → store → foo, and etc beside nix.
+        let host: HashMap<(u64, String), u64> = [
+            ((ROOT_NODEID, "nix"), 2),
+            ((2, "store"), 3),
+            ((3, "foo"), 4),
+            ((ROOT_NODEID, "etc"), 5),
+        ]
+        .into_iter()
+        .map(|((parent, name), nodeid)| ((parent, name.to_string()), nodeid))
+        .collect();
+        let mut asked = Vec::new();
+        let mut lookup = |parent: u64, name: &str| {
+            asked.push(name.to_string());
+            host.get(&(parent, name.to_string())).copied().ok_or(-2)
+        };
+
+        let root = walk(ROOT_NODEID, &path_components("/nix/store").unwrap(), &mut lookup);
+        assert_eq!(root, Ok(3));
+
+        // Guest /foo is host /nix/store/foo, and the host never sees "..".
+        for guest in ["/foo", "foo/", "./foo", "/bar/../foo"] {
+            let components = path_components(guest).unwrap();
+            assert_eq!(walk(3, &components, &mut lookup), Ok(4), "{guest}");
+        }
+        assert!(!asked.iter().any(|name| name == ".." || name == "etc"), "{asked:?}");
+        assert_eq!(path_components("/").unwrap(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn lookup_subdir_walks_the_host_share() {
+        let host = TestHost::new();
+        let nix = host.fs().add_dir("nix");
+        let store = host.fs().add_dir("nix/store");
+        host.fs().add_file("nix/store/foo", b"");
+        let mut session = host.session();
+
+        // Resolving the root is done in the session, before any guest call.
+        assert_eq!(session.resolve_root().ok(), Some(ROOT_NODEID));
+        assert_eq!(session.lookup_subdir("/nix/./store/").ok(), Some(store));
+        let requests = host.fs().requests.clone();
+        let lookup = FuseOpcode::Lookup as u32;
+        assert_eq!(requests[1..], [(lookup, ROOT_NODEID), (lookup, nix)]);
+        assert_eq!(host.fs().nodes[&store].nlookup, 1);
+
+        // After a re-init the same directory is looked up from the new root.
+        host.fs().requests.clear();
+        host.fs().failures = 1;
+        assert_eq!(session.resolve_root().ok(), Some(store));
+        assert_eq!(host.fs().inits, 2);
+        assert_eq!(host.fs().count(FuseOpcode::Lookup), 2);
+
+        let mut errno = |subdir| match session.lookup_subdir(subdir) {
+            Err(FuseTransportError::FuseError(errno)) => -errno,
+            other => panic!("{subdir}: {other:?}"),
+        };
+        assert_eq!(errno("/nix/store/foo"), LINUX_ENOTDIR);
+        assert_eq!(errno("/nix/missing"), 2);
+        assert_eq!(errno("/../etc"), LINUX_EINVAL);
+        // A failed lookup leaves the directory served before in place.
+        assert_eq!(session.resolve_root().ok(), Some(store));
+    }
+
+    #[test]
+    fn dotdot_cannot_escape_the_root() {
+        for guest in ["..", "/../etc", "/foo/../../etc", "a/./../../b"] {
+            assert_eq!(path_components(guest), None, "{guest}");
+        }
+        assert_eq!(path_components("/a/b/../c").unwrap(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn closing_handles_forgets_accumulated_lookups() {
+        let mut lookups = LookupCounts::default();
+        // Node 7 was looked up three times, node 9 once; the root is free.
+        for nodeid in [7, 9, 7, 1, 7] {
+            lookups.add(nodeid);
+        }
+
+        // A handle on node 9 is still open.
+        let forgets = lookups.take_unused(|nodeid| nodeid == 9);
+        assert_eq!(forgets, vec![FuseForgetOne { nodeid: 7, nlookup: 3 }]);
+
+        let reqs = forget_requests(&forgets, true, FORGET_BUF_SIZE, || 5);
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(host_batch_forget(&reqs[0]), forgets);
+
+        // Closing the last handle releases node 9, and nothing is left.
+        assert_eq!(
+            lookups.take_unused(|_| false),
+            vec![FuseForgetOne { nodeid: 9, nlookup: 1 }]
+        );
+        assert!(lookups.take_unused(|_| false).is_empty());
+    }
+
+    #[test]
+    fn batch_forget_fits_the_buffer() {
+        let forgets: Vec<_> = (2..300)
+            .map(|nodeid| FuseForgetOne { nodeid, nlookup: 1 })
+            .collect();
+
+        let reqs = forget_requests(&forgets, true, FORGET_BUF_SIZE, || 5);
+        // (4096 - 40 - 8) / 16 = 253 nodes per request.
+        assert_eq!(reqs.len(), 2);
+        assert!(reqs.iter().all(|req| req.len() <= FORGET_BUF_SIZE));
+        let sent: Vec<_> = reqs.iter().flat_map(|req| host_batch_forget(req)).collect();
+        assert_eq!(sent, forgets);
+    }
+
+    #[test]
+    fn old_hosts_get_one_forget_per_node() {
+        let forgets = [
+            FuseForgetOne { nodeid: 7, nlookup: 3 },
+            FuseForgetOne { nodeid: 9, nlookup: 1 },
+        ];
+        let reqs = forget_requests(&forgets, false, FORGET_BUF_SIZE, || 5);
+        assert_eq!(reqs.len(), 2);
+
+        let hdr = unsafe { *(reqs[0].as_ptr() as *const FuseInHeader) };
+        assert_eq!((hdr.opcode, hdr.nodeid), (FuseOpcode::Forget as u32, 7));
+        let body = &reqs[0][core::mem::size_of::<FuseInHeader>()..];
+        let args = unsafe { *(body.as_ptr() as *const FuseForgetIn) };
+        assert_eq!(args.nlookup, 3);
+    }
+}
@@ -0,0 +1,585 @@
+//! An in-memory virtio-fs host for tests.
+//!
+//! [`TestHost`] is a [`FuseDevice`] answering FUSE requests from a tree of
+//! nodes, the way virtiofsd answers them from the shared directory, so
+//! session and scheme tests go through the real request encoding. It
+//! records what it was sent, and can fail requests like a broken device.
+
+use std::cell::{RefCell, RefMut};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::fuse::*;
+use crate::session::{FuseSession, ROOT_NODEID};
+use crate::transport::{FuseDevice, FuseTransportError};
+
+// Host (Linux) errnos.
+const ENOENT: i32 = 2;
+const EBADF: i32 = 9;
+const EEXIST: i32 = 17;
+const EISDIR: i32 = 21;
+const ENOTEMPTY: i32 = 39;
+
+// Host (Linux) open flags.
+const O_ACCMODE: u32 = 3;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+
+/// A file, directory or symlink on the host.
+pub struct Node {
+    pub mode: u32,
+    /// File contents, or a symlink's target.
+    pub data: Vec<u8>,
+    /// Directory entries.
+    pub entries: BTreeMap<String, u64>,
+    pub mtime: u64,
+    /// Lookups the guest holds on the node and has yet to FORGET.
+    pub nlookup: u64,
+}
+
+impl Node {
+    fn new(mode: u32, data: &[u8]) -> Self {
+        Self {
+            mode,
+            data: data.to_vec(),
+            entries: BTreeMap::new(),
+            mtime: 0,
+            nlookup: 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+/// What the host shares and what it has been sent.
+pub struct HostFs {
+    pub nodes: BTreeMap<u64, Node>,
+    next_nodeid: u64,
+    /// Open file handles: fh → (nodeid, open flags).
+    pub open: BTreeMap<u64, (u64, u32)>,
+    next_fh: u64,
+    /// Every request the host received, on either queue, as (opcode, nodeid).
+    pub requests: Vec<(u32, u64)>,
+    /// FSYNC and FSYNCDIR requests, as (opcode, fh, fsync_flags).
+    pub fsyncs: Vec<(u32, u64, u32)>,
+    /// FORGET and BATCH_FORGET entries received on the hiprio queue.
+    pub forgets: Vec<FuseForgetOne>,
+    /// Protocol minor version the host answers FUSE_INIT with.
+    pub minor: u32,
+    /// Entry and attribute timeout in replies, in seconds.
+    pub attr_valid: u64,
+    /// Number of upcoming requests the device fails before the host sees them.
+    pub failures: usize,
+    /// FUSE_INIT requests received.
+    pub inits: u32,
+}
+
+/// A handle on a shared [`HostFs`]; the session gets one copy as its
+/// device, the test keeps another.
+#[derive(Clone)]
+pub struct TestHost(Rc<RefCell<HostFs>>);
+
+impl TestHost {
+    /// A host sharing an empty directory.
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(ROOT_NODEID, Node::new(S_IFDIR | 0o755, b""));
+
+        Self(Rc::new(RefCell::new(HostFs {
+            nodes,
+            next_nodeid: ROOT_NODEID + 1,
+            open: BTreeMap::new(),
+            next_fh: 1,
+            requests: Vec::new(),
+            fsyncs: Vec::new(),
+            forgets: Vec::new(),
+            minor: FUSE_KERNEL_MINOR_VERSION,
+            attr_valid: 60,
+            failures: 0,
+            inits: 0,
+        })))
+    }
+
+    /// The host's state, to set up or inspect.
+    pub fn fs(&self) -> RefMut<'_, HostFs> {
+        self.0.borrow_mut()
+    }
+
+    /// A FUSE session with this host.
+    pub fn session(&self) -> FuseSession<'static> {
+        FuseSession::with_device(Box::new(self.clone())).expect("FUSE_INIT")
+    }
+}
+
+impl HostFs {
+    /// Create `path` with `mode` and `data`, and any missing directory on
+    /// the way, returning its nodeid.
+    pub fn add(&mut self, path: &str, mode: u32, data: &[u8]) -> u64 {
+        let mut parent = ROOT_NODEID;
+        let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+        while let Some(name) = names.next() {
+            let last = names.peek().is_none();
+            parent = match self.nodes[&parent].entries.get(name) {
+                Some(&nodeid) if !last => nodeid,
+                _ if last => self.insert(parent, name, Node::new(mode, data)),
+                _ => self.insert(parent, name, Node::new(S_IFDIR | 0o755, b"")),
+            };
+        }
+        parent
+    }
+
+    pub fn add_dir(&mut self, path: &str) -> u64 {
+        self.add(path, S_IFDIR | 0o755, b"")
+    }
+
+    pub fn add_file(&mut self, path: &str, data: &[u8]) -> u64 {
+        self.add(path, S_IFREG | 0o644, data)
+    }
+
+    /// The node `path` names, if it exists.
+    pub fn find(&self, path: &str) -> Option<u64> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(ROOT_NODEID, |parent, name| {
+                self.nodes[&parent].entries.get(name).copied()
+            })
+    }
+
+    /// The contents of the file, or the target of the symlink, `path` names.
+    pub fn data(&self, path: &str) -> Option<&[u8]> {
+        self.find(path)
+            .map(|nodeid| self.nodes[&nodeid].data.as_slice())
+    }
+
+    /// How many requests with `opcode` the host received.
+    pub fn count(&self, opcode: FuseOpcode) -> usize {
+        self.requests
+            .iter()
+            .filter(|&&(op, _)| op == opcode as u32)
+            .count()
+    }
+
+    fn insert(&mut self, parent: u64, name: &str, node: Node) -> u64 {
+        let nodeid = self.next_nodeid;
+        self.next_nodeid += 1;
+        self.nodes.insert(nodeid, node);
+        self.dir(parent)
+            .unwrap()
+            .entries
+            .insert(name.to_string(), nodeid);
+        nodeid
+    }
+
+    fn node(&mut self, nodeid: u64) -> Result<&mut Node, i32> {
+        self.nodes.get_mut(&nodeid).ok_or(ENOENT)
+    }
+
+    fn dir(&mut self, nodeid: u64) -> Result<&mut Node, i32> {
+        match self.node(nodeid)? {
+            node if node.is_dir() => Ok(node),
+            _ => Err(LINUX_ENOTDIR),
+        }
+    }
+
+    fn child(&mut self, parent: u64, name: &str) -> Result<u64, i32> {
+        self.dir(parent)?.entries.get(name).copied().ok_or(ENOENT)
+    }
+
+    fn new_fh(&mut self, nodeid: u64, flags: u32) -> FuseOpenOut {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.open.insert(fh, (nodeid, flags));
+        FuseOpenOut {
+            fh,
+            open_flags: 0,
+            backing_id: 0,
+        }
+    }
+
+    /// The open flags of `fh`, which must be open on `nodeid`.
+    fn fh_flags(&self, nodeid: u64, fh: u64) -> Result<u32, i32> {
+        match self.open.get(&fh) {
+            Some(&(node, flags)) if node == nodeid => Ok(flags),
+            _ => Err(EBADF),
+        }
+    }
+
+    fn attr(&self, nodeid: u64) -> FuseAttr {
+        let node = &self.nodes[&nodeid];
+        FuseAttr {
+            ino: nodeid,
+            size: node.data.len() as u64,
+            blocks: node.data.len().div_ceil(512) as u64,
+            mtime: node.mtime,
+            mode: node.mode,
+            nlink: 1,
+            blksize: 4096,
+            ..Default::default()
+        }
+    }
+
+    fn attr_out(&self, nodeid: u64) -> FuseAttrOut {
+        FuseAttrOut {
+            attr_valid: self.attr_valid,
+            attr_valid_nsec: 0,
+            dummy: 0,
+            attr: self.attr(nodeid),
+        }
+    }
+
+    /// A reply handing out `nodeid`, which the guest now has to FORGET.
+    fn entry_out(&mut self, nodeid: u64) -> FuseEntryOut {
+        if nodeid != ROOT_NODEID {
+            self.nodes.get_mut(&nodeid).unwrap().nlookup += 1;
+        }
+        FuseEntryOut {
+            nodeid,
+            generation: 0,
+            entry_valid: self.attr_valid,
+            attr_valid: self.attr_valid,
+            entry_valid_nsec: 0,
+            attr_valid_nsec: 0,
+            attr: self.attr(nodeid),
+        }
+    }
+
+    /// Create `name` in `parent`, failing if it exists.
+    fn create_entry(&mut self, parent: u64, name: &str, node: Node) -> Result<u64, i32> {
+        if self.dir(parent)?.entries.contains_key(name) {
+            return Err(EEXIST);
+        }
+        Ok(self.insert(parent, name, node))
+    }
+
+    /// Answer one request with a reply body, or a host errno.
+    fn handle(&mut self, hdr: FuseInHeader, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let nodeid = hdr.nodeid;
+        const LOOKUP: u32 = FuseOpcode::Lookup as u32;
+        const GETATTR: u32 = FuseOpcode::Getattr as u32;
+        const SETATTR: u32 = FuseOpcode::Setattr as u32;
+        const READLINK: u32 = FuseOpcode::Readlink as u32;
+        const SYMLINK: u32 = FuseOpcode::Symlink as u32;
+        const MKDIR: u32 = FuseOpcode::Mkdir as u32;
+        const UNLINK: u32 = FuseOpcode::Unlink as u32;
+        const RMDIR: u32 = FuseOpcode::Rmdir as u32;
+        const RENAME: u32 = FuseOpcode::Rename as u32;
+        const RENAME2: u32 = FuseOpcode::Rename2 as u32;
+        const OPEN: u32 = FuseOpcode::Open as u32;
+        const READ: u32 = FuseOpcode::Read as u32;
+        const WRITE: u32 = FuseOpcode::Write as u32;
+        const STATFS: u32 = FuseOpcode::Statfs as u32;
+        const RELEASE: u32 = FuseOpcode::Release as u32;
+        const FSYNC: u32 = FuseOpcode::Fsync as u32;
+        const INIT: u32 = FuseOpcode::Init as u32;
+        const OPENDIR: u32 = FuseOpcode::Opendir as u32;
+        const READDIR: u32 = FuseOpcode::Readdir as u32;
+        const RELEASEDIR: u32 = FuseOpcode::Releasedir as u32;
+        const FSYNCDIR: u32 = FuseOpcode::Fsyncdir as u32;
+        const CREATE: u32 = FuseOpcode::Create as u32;
+
+        match hdr.opcode {
+            INIT => {
+                self.inits += 1;
+                // A restarted virtiofsd remembers no lookups or handles.
+                for node in self.nodes.values_mut() {
+                    node.nlookup = 0;
+                }
+                self.open.clear();
+                Ok(bytes(&FuseInitOut {
+                    major: FUSE_KERNEL_VERSION,
+                    minor: self.minor,
+                    max_readahead: 1024 * 1024,
+                    flags: 0,
+                    max_background: 0,
+                    congestion_threshold: 0,
+                    max_write: 128 * 1024,
+                    time_gran: 1,
+                    max_pages: 0,
+                    map_alignment: 0,
+                    flags2: 0,
+                    max_stack_depth: 0,
+                    unused: [0; 6],
+                }))
+            }
+            LOOKUP => {
+                let child = self.child(nodeid, &names(body)[0])?;
+                Ok(bytes(&self.entry_out(child)))
+            }
+            GETATTR => {
+                self.node(nodeid)?;
+                Ok(bytes(&self.attr_out(nodeid)))
+            }
+            SETATTR => {
+                let args: FuseSetattrIn = read(body);
+                if args.valid & FATTR_SIZE != 0 {
+                    if args.valid & FATTR_FH != 0
+                        && self.fh_flags(nodeid, args.fh)? & O_ACCMODE == 0
+                    {
+                        return Err(EBADF);
+                    }
+                    self.node(nodeid)?.data.resize(args.size as usize, 0);
+                }
+                let node = self.node(nodeid)?;
+                if args.valid & FATTR_MODE != 0 {
+                    node.mode = (node.mode & S_IFMT) | (args.mode & 0o7777);
+                }
+                if args.valid & FATTR_MTIME != 0 && args.valid & FATTR_MTIME_NOW == 0 {
+                    node.mtime = args.mtime;
+                }
+                Ok(bytes(&self.attr_out(nodeid)))
+            }
+            READLINK => match self.node(nodeid)? {
+                node if node.mode & S_IFMT == S_IFLNK => Ok(node.data.clone()),
+                _ => Err(LINUX_EINVAL),
+            },
+            SYMLINK => {
+                // The target is stored as is, even when it isn't UTF-8.
+                let (name, target) = body.split_at(body.iter().position(|&b| b == 0).unwrap());
+                let target = target[1..]
+                    .strip_suffix(&[0])
+                    .expect("target is null-terminated");
+                let node = Node::new(S_IFLNK | 0o777, target);
+                let child = self.create_entry(nodeid, &String::from_utf8_lossy(name), node)?;
+                Ok(bytes(&self.entry_out(child)))
+            }
+            MKDIR => {
+                let args: FuseMkdirIn = read(body);
+                let name = &names(&body[size_of::<FuseMkdirIn>()..])[0];
+                let node = Node::new(S_IFDIR | (args.mode & !args.umask), b"");
+                let child = self.create_entry(nodeid, name, node)?;
+                Ok(bytes(&self.entry_out(child)))
+            }
+            UNLINK | RMDIR => {
+                let name = &names(body)[0];
+                let child = self.child(nodeid, name)?;
+                let node = &self.nodes[&child];
+                match (hdr.opcode, node.is_dir()) {
+                    (UNLINK, true) => return Err(EISDIR),
+                    (RMDIR, false) => return Err(LINUX_ENOTDIR),
+                    (RMDIR, true) if !node.entries.is_empty() => return Err(ENOTEMPTY),
+                    _ => {}
+                }
+                self.dir(nodeid)?.entries.remove(name);
+                Ok(Vec::new())
+            }
+            RENAME2 if self.minor < FUSE_RENAME2_MINOR_VERSION => Err(LINUX_ENOSYS),
+            RENAME | RENAME2 => {
+                let (newdir, args_len) = match hdr.opcode {
+                    RENAME => (read::<FuseRenameIn>(body).newdir, size_of::<FuseRenameIn>()),
+                    _ => (
+                        read::<FuseRename2In>(body).newdir,
+                        size_of::<FuseRename2In>(),
+                    ),
+                };
+                let names = names(&body[args_len..]);
+                let child = self.child(nodeid, &names[0])?;
+                self.dir(newdir)?;
+                self.dir(nodeid)?.entries.remove(&names[0]);
+                self.dir(newdir)?.entries.insert(names[1].clone(), child);
+                Ok(Vec::new())
+            }
+            OPEN => {
+                let args: FuseOpenIn = read(body);
+                let node = self.node(nodeid)?;
+                if node.is_dir() {
+                    return Err(EISDIR);
+                }
+                if args.flags & O_TRUNC != 0 {
+                    node.data.clear();
+                }
+                Ok(bytes(&self.new_fh(nodeid, args.flags)))
+            }
+            OPENDIR => {
+                self.dir(nodeid)?;
+                Ok(bytes(&self.new_fh(nodeid, 0)))
+            }
+            READ => {
+                let args: FuseReadIn = read(body);
+                self.fh_flags(nodeid, args.fh)?;
+                let data = &self.nodes[&nodeid].data;
+                let start = (args.offset as usize).min(data.len());
+                let end = (start + args.size as usize).min(data.len());
+                Ok(data[start..end].to_vec())
+            }
+            WRITE => {
+                let args: FuseWriteIn = read(body);
+                if self.fh_flags(nodeid, args.fh)? & O_ACCMODE == 0 {
+                    return Err(EBADF);
+                }
+                let data = &body[size_of::<FuseWriteIn>()..][..args.size as usize];
+                let file = &mut self.node(nodeid)?.data;
+                let end = args.offset as usize + data.len();
+                if file.len() < end {
+                    file.resize(end, 0);
+                }
+                file[args.offset as usize..end].copy_from_slice(data);
+                Ok(bytes(&FuseWriteOut {
+                    size: data.len() as u32,
+                    padding: 0,
+                }))
+            }
+            STATFS => Ok(bytes(&FuseStatfsOut {
+                st: FuseKstatfs {
+                    blocks: 1000,
+                    bfree: 500,
+                    bavail: 400,
+                    files: 100,
+                    ffree: 50,
+                    bsize: 4096,
+                    namelen: 255,
+                    frsize: 4096,
+                    padding: 0,
+                    spare: [0; 6],
+                },
+            })),
+            RELEASE | RELEASEDIR => {
+                let args: FuseReleaseIn = read(body);
+                self.fh_flags(nodeid, args.fh)?;
+                self.open.remove(&args.fh);
+                Ok(Vec::new())
+            }
+            FSYNC | FSYNCDIR => {
+                let args: FuseFsyncIn = read(body);
+                self.fh_flags(nodeid, args.fh)?;
+                self.fsyncs.push((hdr.opcode, args.fh, args.fsync_flags));
+                Ok(Vec::new())
+            }
+            READDIR => {
+                let args: FuseReadIn = read(body);
+                self.fh_flags(nodeid, args.fh)?;
+                let mut out = Vec::new();
+                let entries = self.dir(nodeid)?.entries.clone();
+                for (i, (name, &child)) in entries.iter().enumerate().skip(args.offset as usize) {
+                    let dirent = FuseDirent {
+                        ino: child,
+                        off: i as u64 + 1,
+                        namelen: name.len() as u32,
+                        typ: (self.nodes[&child].mode & S_IFMT) >> 12,
+                    };
+                    let size = fuse_dirent_size(name.len());
+                    if out.len() + size > args.size as usize {
+                        break;
+                    }
+                    let start = out.len();
+                    out.extend_from_slice(bytes(&dirent).as_slice());
+                    out.extend_from_slice(name.as_bytes());
+                    out.resize(start + size, 0);
+                }
+                Ok(out)
+            }
+            CREATE => {
+                let args: FuseCreateIn = read(body);
+                let name = &names(&body[size_of::<FuseCreateIn>()..])[0];
+                let child = match self.child(nodeid, name) {
+                    Ok(_) if args.flags & O_EXCL != 0 => return Err(EEXIST),
+                    Ok(child) => child,
+                    Err(ENOENT) => {
+                        let mode = S_IFREG | (args.mode & !args.umask);
+                        self.insert(nodeid, name, Node::new(mode, b""))
+                    }
+                    Err(e) => return Err(e),
+                };
+                let mut reply = bytes(&self.entry_out(child));
+                reply.extend_from_slice(&bytes(&self.new_fh(child, args.flags)));
+                Ok(reply)
+            }
+            _ => Err(LINUX_ENOSYS),
+        }
+    }
+
+    /// Drop the lookups a FORGET or BATCH_FORGET gives back.
+    fn forget(&mut self, hdr: FuseInHeader, body: &[u8]) {
+        let forgets = if hdr.opcode == FuseOpcode::Forget as u32 {
+            let args: FuseForgetIn = read(body);
+            vec![FuseForgetOne {
+                nodeid: hdr.nodeid,
+                nlookup: args.nlookup,
+            }]
+        } else {
+            assert_eq!(hdr.opcode, FuseOpcode::Batchforget as u32);
+            let args: FuseBatchForgetIn = read(body);
+            let items = &body[size_of::<FuseBatchForgetIn>()..];
+            (0..args.count as usize)
+                .map(|i| read(&items[i * size_of::<FuseForgetOne>()..]))
+                .collect()
+        };
+
+        for forget in forgets {
+            let node = self
+                .nodes
+                .get_mut(&forget.nodeid)
+                .expect("FORGET of an unknown node");
+            assert!(
+                node.nlookup >= forget.nlookup,
+                "FORGET of more lookups than handed out"
+            );
+            node.nlookup -= forget.nlookup;
+            self.forgets.push(forget);
+        }
+    }
+}
+
+impl FuseDevice for TestHost {
+    fn exchange(&mut self, req: &[u8], resp_len: usize) -> Result<Vec<u8>, FuseTransportError> {
+        let mut fs = self.fs();
+        if fs.failures > 0 {
+            fs.failures -= 1;
+            return Err(FuseTransportError::ShortResponse(0));
+        }
+
+        let hdr: FuseInHeader = read(req);
+        assert_eq!(hdr.len as usize, req.len());
+        fs.requests.push((hdr.opcode, hdr.nodeid));
+
+        let (error, body) = match fs.handle(hdr, &req[size_of::<FuseInHeader>()..]) {
+            Ok(body) => (0, body),
+            Err(errno) => (-errno, Vec::new()),
+        };
+        let out_len = size_of::<FuseOutHeader>() + body.len();
+        assert!(
+            out_len <= resp_len,
+            "reply of {out_len} bytes in a {resp_len} byte buffer"
+        );
+
+        let mut resp = bytes(&FuseOutHeader {
+            len: out_len as u32,
+            error,
+            unique: hdr.unique,
+        });
+        resp.extend_from_slice(&body);
+        Ok(resp)
+    }
+
+    fn send_noreply(&mut self, req: &[u8]) {
+        let hdr: FuseInHeader = read(req);
+        assert_eq!(hdr.len as usize, req.len());
+        let mut fs = self.fs();
+        fs.requests.push((hdr.opcode, hdr.nodeid));
+        fs.forget(hdr, &req[size_of::<FuseInHeader>()..]);
+    }
+}
+
+fn size_of<T>() -> usize {
+    core::mem::size_of::<T>()
+}
+
+/// Decode a wire struct from the start of `data`.
+fn read<T: Copy>(data: &[u8]) -> T {
+    assert!(data.len() >= size_of::<T>(), "request too short");
+    unsafe { core::ptr::read_unaligned(data.as_ptr() as *const T) }
+}
+
+/// Encode a wire struct.
+fn bytes<T: Copy>(value: &T) -> Vec<u8> {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }.to_vec()
+}
+
+/// The null-terminated names at the end of a request.
+fn names(data: &[u8]) -> Vec<String> {
+    let data = data.strip_suffix(&[0]).expect("names are null-terminated");
+    data.split(|&b| b == 0)
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect()
+}
@@ -116,6 +116,31 @@ pub fn fuse_exchange(
     Ok(result)
 }
 
+/// Send a FUSE request that gets no reply (FUSE_FORGET, FUSE_BATCH_FORGET).
+///
+/// The chain is the request descriptor alone; the call returns once the
+/// device has consumed it.
+pub fn fuse_send_noreply(queue: &Queue<'_>, req_buf: &Dma<[u8]>, req_len: usize) {
+    debug_assert!(req_len <= req_buf.len());
+
+    let chain = ChainBuilder::new()
+        .chain(Buffer::new_sized(req_buf, req_len))
+        .build();
+
+    futures::executor::block_on(queue.send(chain));
+}
+
+/// Where a [`FuseSession`](crate::session::FuseSession) sends its requests:
+/// the virtio-fs queues, or an in-memory host in tests.
+pub trait FuseDevice {
+    /// Send a serialized request on the request queue, with room for a
+    /// reply of `resp_len` bytes, and return the reply.
+    fn exchange(&mut self, req: &[u8], resp_len: usize) -> Result<Vec<u8>, FuseTransportError>;
+
+    /// Send a serialized request that gets no reply on the hiprio queue.
+    fn send_noreply(&mut self, req: &[u8]);
+}
+
 /// Build a FUSE request with typed args struct AND trailing data (for FUSE_WRITE).
 pub fn build_request_with_data<T: Sized>(
     opcode: u32,
@@ -245,4 +270,14 @@ pub enum FuseTransportError {
     UnexpectedSize,
     #[error("request too large for pre-allocated buffer ({0} bytes)")]
     RequestTooLarge(usize),
+    #[error("FUSE session was re-initialized, the node is stale")]
+    Reconnected,
+}
+
+impl FuseTransportError {
+    /// Whether the device failed the request, rather than the host
+    /// answering it. The FUSE session can't be trusted afterwards.
+    pub fn is_transport(&self) -> bool {
+        matches!(self, FuseTransportError::ShortResponse(_))
+    }
 }
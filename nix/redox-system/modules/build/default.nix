@@ -365,6 +365,9 @@ adios:
         in
         uu != null && builtins.any (p: p == uu) allPackages;
 
+      # snix on rootFS: init applies generations staged with `switch --boot`
+      snixInstalled = builtins.any (p: (p.pname or "") == "snix-redox") allPackages;
+
       # Collect all directories
       homeDirectories = lib.filter (d: d != null) (
         lib.mapAttrsToList (name: user: if user.createHome or false then user.home else null) (
@@ -650,6 +653,14 @@ adios:
             };
           })
         ))
+        // (lib.optionalAttrs snixInstalled {
+          # Before stored/profiled and services, so a generation staged with
+          # `snix system switch --boot` is in place when they start.
+          "11_snix_boot" = {
+            text = "/bin/snix system activate-boot";
+            directory = "init.d";
+          };
+        })
         // (lib.optionalAttrs userutilsInstalled {
           # Serial console via getty + PTY bridge.
           # getty opens /scheme/debug/no-preserve with non-blocking I/O, creates a
@@ -29,6 +29,10 @@ pub enum DerivationError {
     EmptyInputDerivationOutputNames(String),
     #[error("input derivation {0} output name {1} is invalid")]
     InvalidInputDerivationOutputName(String, String),
+    #[error("input derivation {0} is not in the derivation map")]
+    UnknownInputDerivation(String),
+    #[error("input derivation {0} has no output named {1}")]
+    MissingInputDerivationOutput(String, String),
 
     // input sources
     #[error("unable to parse input sources path {0}: {1}")]
@@ -97,6 +97,12 @@ impl Derivation {
     }
 
     /// return the ATerm serialization.
+    ///
+    /// The output is byte-identical to what Nix writes to a `.drv` file
+    /// (same field order and string escaping), so parsing a `.drv` with
+    /// [Derivation::from_aterm_bytes] and serializing it again round-trips,
+    /// and [Derivation::calculate_derivation_path] of a modified
+    /// [Derivation] matches the path Nix would assign.
     pub fn to_aterm_bytes(&self) -> Vec<u8> {
         self.to_aterm_bytes_with_replacements(&self.input_derivations)
     }
@@ -131,6 +137,9 @@ impl Derivation {
     /// the `name` with a `.drv` suffix as name, all [Derivation::input_sources] and
     /// keys of [Derivation::input_derivations] as references, and the ATerm string of
     /// the [Derivation] as content.
+    ///
+    /// This is the path `writeDerivation` in nixcpp picks. Unlike output
+    /// paths, it hashes the ATerm verbatim, not modulo fixed-output inputs.
     pub fn calculate_derivation_path(
         &self,
         name: &str,
@@ -151,6 +160,17 @@ impl Derivation {
             .map_err(|_e| DerivationError::InvalidOutputName(name))
     }
 
+    /// The structured attributes of a derivation with
+    /// `__structuredAttrs = true`, which Nix passes as JSON in the `__json`
+    /// environment variable (and the builder gets as `.attrs.json`).
+    ///
+    /// Returns None for classic derivations, and if `__json` is not valid
+    /// JSON.
+    #[cfg(feature = "serde")]
+    pub fn structured_attrs(&self) -> Option<serde_json::Value> {
+        serde_json::from_slice(self.environment.get("__json")?).ok()
+    }
+
     /// Returns the FOD digest, if the derivation is fixed-output, or None if
     /// it's not.
     /// TODO: this is kinda the string from [build_ca_path] with a
@@ -644,6 +644,40 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn structured_attrs_from_json_env() {
+        use crate::derivation::Derivation;
+
+        let json = r#"{"builder":"/bin/sh","outputs":{"out":{"outputHashMode":"recursive"}},"nativeBuildInputs":["/nix/store/9dnshk8v7cprb2xxbzq5fh5yqcni7yzk-cmake-3.29.6"]}"#;
+        let mut drv = Derivation {
+            builder: "/bin/sh".to_string(),
+            system: "x86_64-linux".to_string(),
+            ..Default::default()
+        };
+        let out = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-classic";
+        let output = crate::derivation::Output {
+            path: Some(StorePath::from_absolute_path(out.as_bytes()).unwrap()),
+            ca_hash: None,
+        };
+        drv.outputs.insert("out".to_string(), output);
+        drv.environment.insert("__json".to_string(), json.into());
+        drv.environment.insert("out".to_string(), out.into());
+
+        let parsed = Derivation::from_aterm_bytes(&drv.to_aterm_bytes()).expect("must parse");
+        let attrs = parsed.structured_attrs().expect("must be structured");
+        assert_eq!(attrs["outputs"]["out"]["outputHashMode"], "recursive");
+        assert_eq!(
+            attrs["nativeBuildInputs"][0],
+            "/nix/store/9dnshk8v7cprb2xxbzq5fh5yqcni7yzk-cmake-3.29.6"
+        );
+
+        drv.environment.remove("__json");
+        drv.environment.insert("name".to_string(), "classic".into());
+        let parsed = Derivation::from_aterm_bytes(&drv.to_aterm_bytes()).expect("must parse");
+        assert_eq!(parsed.structured_attrs(), None);
+    }
+
     #[test]
     fn from_algo_and_mode_and_digest_failure() {
         assert!(from_algo_and_mode_and_digest("r:sha256", []).is_err());
@@ -1,5 +1,7 @@
+use std::collections::BTreeMap;
+
 use crate::derivation::{Derivation, DerivationError};
-use crate::store_path;
+use crate::store_path::{self, StorePath};
 
 /// Validates an output name using derivation output name rules.
 ///
@@ -103,6 +105,36 @@ impl Derivation {
 
         Ok(())
     }
+
+    /// Checks every entry of `input_derivations` against `derivations`,
+    /// the already-parsed derivations keyed by their path: each input
+    /// derivation must be present, and each output name used from it must
+    /// be one it declares.
+    ///
+    /// This catches broken derivation closures (e.g. a dependent asking for
+    /// `dev` from a derivation that only has `out`) before a build is
+    /// attempted.
+    pub fn validate_against(
+        &self,
+        derivations: &BTreeMap<StorePath<String>, Derivation>,
+    ) -> Result<(), DerivationError> {
+        for (input_derivation_path, output_names) in &self.input_derivations {
+            let input = derivations.get(input_derivation_path).ok_or_else(|| {
+                DerivationError::UnknownInputDerivation(input_derivation_path.to_absolute_path())
+            })?;
+
+            for output_name in output_names {
+                if !input.outputs.contains_key(output_name) {
+                    return Err(DerivationError::MissingInputDerivationOutput(
+                        input_derivation_path.to_absolute_path(),
+                        output_name.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -111,6 +143,7 @@ mod test {
 
     use super::validate_output_name;
     use crate::derivation::{CAHash, Derivation, DerivationError, Output};
+    use crate::store_path::StorePath;
 
     /// Test the validate_output_name function with valid names
     #[test]
@@ -173,4 +206,75 @@ mod test {
 
         drv.validate(false).expect_err("must fail");
     }
+
+    const DEP_DRV: &str = "/nix/store/0hm2f1psjpcwg8fijsmr4wwxrx59s092-dep.drv";
+    const APP_DRV: &str = "/nix/store/4wvvbi4jwn0prsdxb7vs673qa5h9gr7x-app.drv";
+
+    /// A derivation declaring `outputs`, using `inputs` (drv path, output names).
+    fn drv(outputs: &[&str], inputs: &[(&str, &[&str])]) -> Derivation {
+        Derivation {
+            builder: "/bin/sh".to_string(),
+            system: "x86_64-linux".to_string(),
+            outputs: outputs
+                .iter()
+                .map(|name| (name.to_string(), Output::default()))
+                .collect(),
+            input_derivations: inputs
+                .iter()
+                .map(|(path, names)| {
+                    (
+                        StorePath::from_absolute_path(path.as_bytes()).unwrap(),
+                        names.iter().map(|n| n.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_against_valid_graph() {
+        let dep = drv(&["out", "dev"], &[]);
+        let app = drv(&["out"], &[(DEP_DRV, &["out", "dev"])]);
+        let derivations = BTreeMap::from([
+            (
+                StorePath::from_absolute_path(DEP_DRV.as_bytes()).unwrap(),
+                dep,
+            ),
+            (
+                StorePath::from_absolute_path(APP_DRV.as_bytes()).unwrap(),
+                app.clone(),
+            ),
+        ]);
+
+        app.validate_against(&derivations).expect("must validate");
+    }
+
+    #[test]
+    fn validate_against_missing_output() {
+        let dep = drv(&["out"], &[]);
+        let app = drv(&["out"], &[(DEP_DRV, &["dev"])]);
+        let derivations = BTreeMap::from([(
+            StorePath::from_absolute_path(DEP_DRV.as_bytes()).unwrap(),
+            dep,
+        )]);
+
+        assert_eq!(
+            Err(DerivationError::MissingInputDerivationOutput(
+                DEP_DRV.to_string(),
+                "dev".to_string()
+            )),
+            app.validate_against(&derivations)
+        );
+    }
+
+    #[test]
+    fn validate_against_unknown_input() {
+        let app = drv(&["out"], &[(DEP_DRV, &["out"])]);
+
+        assert_eq!(
+            Err(DerivationError::UnknownInputDerivation(DEP_DRV.to_string())),
+            app.validate_against(&BTreeMap::new())
+        );
+    }
 }
@@ -2,10 +2,15 @@
 // It defines the `FlakeRef` enum which represents different types of flake sources
 // (such as Git repositories, GitHub repos, local paths, etc.), along with functionality
 // to parse URLs into `FlakeRef` instances and convert them back to URIs.
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, fmt, path::PathBuf};
 use url::Url;
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+mod registry;
+#[cfg(feature = "serde")]
+pub use registry::{MatchKind, Registry, RegistryError};
+
+#[derive(Clone, Debug)]
 #[non_exhaustive]
 pub enum FlakeRef {
     File {
@@ -17,6 +22,7 @@ pub enum FlakeRef {
     },
     Git {
         all_refs: bool,
+        dir: Option<String>,
         export_ignore: bool,
         keytype: Option<String>,
         public_key: Option<String>,
@@ -31,6 +37,7 @@ pub enum FlakeRef {
     GitHub {
         owner: String,
         repo: String,
+        dir: Option<String>,
         host: Option<String>,
         keytype: Option<String>,
         public_key: Option<String>,
@@ -41,6 +48,7 @@ pub enum FlakeRef {
     GitLab {
         owner: String,
         repo: String,
+        dir: Option<String>,
         host: Option<String>,
         keytype: Option<String>,
         public_key: Option<String>,
@@ -58,6 +66,7 @@ pub enum FlakeRef {
         rev: Option<String>,
     },
     Path {
+        dir: Option<String>,
         last_modified: Option<u64>,
         nar_hash: Option<String>,
         path: PathBuf,
@@ -67,6 +76,7 @@ pub enum FlakeRef {
     SourceHut {
         owner: String,
         repo: String,
+        dir: Option<String>,
         host: Option<String>,
         keytype: Option<String>,
         public_key: Option<String>,
@@ -211,10 +221,15 @@ impl std::str::FromStr for FlakeRef {
             },
             FetchType::Git => {
                 let params = extract_git_params(&query_pairs);
+                // Every query parameter of a git flake reference is a flake
+                // attribute, so keep only the bare repository URL. Otherwise
+                // `to_uri` and `Display` would emit the parameters twice.
+                url.set_query(None);
                 FlakeRef::Git {
                     url,
                     r#ref: params.r#ref,
                     rev: params.rev,
+                    dir: params.dir,
                     keytype: params.keytype,
                     public_key: params.public_key,
                     public_keys: params.public_keys,
@@ -229,6 +244,7 @@ impl std::str::FromStr for FlakeRef {
                 let params = extract_common_file_params(&query_pairs);
                 FlakeRef::Path {
                     path: PathBuf::from(url.path()),
+                    dir: query_pairs.get("dir").cloned(),
                     rev: params.rev,
                     nar_hash: params.nar_hash,
                     rev_count: params.rev_count,
@@ -239,6 +255,7 @@ impl std::str::FromStr for FlakeRef {
                 create_repo_host_args(&url, &query_pairs, |params| FlakeRef::GitHub {
                     owner: params.owner,
                     repo: params.repo,
+                    dir: params.dir,
                     r#ref: params.r#ref,
                     rev: params.rev,
                     host: params.host,
@@ -251,6 +268,7 @@ impl std::str::FromStr for FlakeRef {
                 create_repo_host_args(&url, &query_pairs, |params| FlakeRef::GitLab {
                     owner: params.owner,
                     repo: params.repo,
+                    dir: params.dir,
                     r#ref: params.r#ref,
                     rev: params.rev,
                     host: params.host,
@@ -263,6 +281,7 @@ impl std::str::FromStr for FlakeRef {
                 create_repo_host_args(&url, &query_pairs, |params| FlakeRef::SourceHut {
                     owner: params.owner,
                     repo: params.repo,
+                    dir: params.dir,
                     r#ref: params.r#ref,
                     rev: params.rev,
                     host: params.host,
@@ -288,6 +307,7 @@ struct FileParams {
 struct GitParams {
     r#ref: Option<String>,
     rev: Option<String>,
+    dir: Option<String>,
     keytype: Option<String>,
     public_key: Option<String>,
     public_keys: Option<Vec<String>>,
@@ -302,6 +322,7 @@ struct GitParams {
 struct RepoHostParams {
     owner: String,
     repo: String,
+    dir: Option<String>,
     host: Option<String>,
     r#ref: Option<String>,
     rev: Option<String>,
@@ -354,6 +375,7 @@ fn extract_git_params(query_pairs: &HashMap<String, String>) -> GitParams {
     GitParams {
         r#ref: query_pairs.get("ref").cloned(),
         rev: query_pairs.get("rev").cloned(),
+        dir: query_pairs.get("dir").cloned(),
         keytype: query_pairs.get("keytype").cloned(),
         public_key: query_pairs.get("publicKey").cloned(),
         public_keys: query_pairs
@@ -387,6 +409,7 @@ fn extract_repo_params(
         repo,
         r#ref,
         rev: query_pairs.get("rev").cloned(),
+        dir: query_pairs.get("dir").cloned(),
         host: query_pairs.get("host").cloned(),
         keytype: query_pairs.get("keytype").cloned(),
         public_key: query_pairs.get("publicKey").cloned(),
@@ -478,6 +501,7 @@ fn append_git_params(url: &mut Url, params: &GitParams) {
         &[
             ("ref", params.r#ref.clone()),
             ("rev", params.rev.clone()),
+            ("dir", params.dir.clone()),
             ("keytype", params.keytype.clone()),
             ("publicKey", params.public_key.clone()),
         ],
@@ -496,6 +520,7 @@ fn append_repo_host_params(url: &mut Url, params: &RepoHostParams) {
         &[
             ("ref", params.r#ref.clone()),
             ("rev", params.rev.clone()),
+            ("dir", params.dir.clone()),
             ("keytype", params.keytype.clone()),
             ("publicKey", params.public_key.clone()),
         ],
@@ -528,6 +553,7 @@ impl FlakeRef {
                 url,
                 r#ref,
                 rev,
+                dir,
                 keytype,
                 public_key,
                 public_keys,
@@ -541,6 +567,7 @@ impl FlakeRef {
                 let params = GitParams {
                     r#ref: r#ref.clone(),
                     rev: rev.clone(),
+                    dir: dir.clone(),
                     keytype: keytype.clone(),
                     public_key: public_key.clone(),
                     public_keys: public_keys.clone(),
@@ -556,6 +583,7 @@ impl FlakeRef {
             FlakeRef::GitHub {
                 owner,
                 repo,
+                dir,
                 host,
                 keytype,
                 public_key,
@@ -566,6 +594,7 @@ impl FlakeRef {
             | FlakeRef::GitLab {
                 owner,
                 repo,
+                dir,
                 host,
                 keytype,
                 public_key,
@@ -576,6 +605,7 @@ impl FlakeRef {
             | FlakeRef::SourceHut {
                 owner,
                 repo,
+                dir,
                 host,
                 keytype,
                 public_key,
@@ -598,6 +628,7 @@ impl FlakeRef {
                 let params = RepoHostParams {
                     owner: owner.clone(),
                     repo: repo.clone(),
+                    dir: dir.clone(),
                     host: host.clone(),
                     r#ref: r#ref.clone(),
                     rev: rev.clone(),
@@ -615,6 +646,7 @@ impl FlakeRef {
             }
             FlakeRef::Path {
                 path,
+                dir,
                 rev,
                 nar_hash,
                 rev_count,
@@ -628,6 +660,7 @@ impl FlakeRef {
                     last_modified: *last_modified,
                 };
                 append_common_file_params(&mut url, &params);
+                append_param(&mut url, "dir", dir);
                 url
             }
             FlakeRef::Tarball {
@@ -656,6 +689,160 @@ impl FlakeRef {
     }
 }
 
+impl FlakeRef {
+    /// Parses a flake reference such as `github:owner/repo/ref`,
+    /// `git+https://host/path?ref=main&rev=...` or `path:/local/dir`.
+    pub fn parse(s: &str) -> Result<Self, FlakeRefError> {
+        s.parse()
+    }
+}
+
+// Writes `?key=value&...` for every parameter that is set, percent-encoding values.
+fn write_query(f: &mut fmt::Formatter<'_>, params: &[(&str, Option<String>)]) -> fmt::Result {
+    let mut separator = '?';
+    for (key, value) in params {
+        if let Some(value) = value {
+            let encoded: String = url::form_urlencoded::byte_serialize(value.as_bytes()).collect();
+            write!(f, "{separator}{key}={encoded}")?;
+            separator = '&';
+        }
+    }
+    Ok(())
+}
+
+fn bool_param(value: bool) -> Option<String> {
+    value.then(|| "1".to_string())
+}
+
+// Formats a flake reference in its canonical string form, the inverse of `FlakeRef::parse`.
+// Repository hosts use the `github:owner/repo/ref` shorthand rather than `to_uri`'s URL form.
+impl fmt::Display for FlakeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlakeRef::Git {
+                url,
+                r#ref,
+                rev,
+                dir,
+                keytype,
+                public_key,
+                public_keys,
+                shallow,
+                submodules,
+                export_ignore,
+                all_refs,
+                verify_commit,
+            } => {
+                let mut url = url.clone();
+                url.set_query(None);
+                write!(f, "git+{url}")?;
+                write_query(
+                    f,
+                    &[
+                        ("ref", r#ref.clone()),
+                        ("rev", rev.clone()),
+                        ("dir", dir.clone()),
+                        ("keytype", keytype.clone()),
+                        ("publicKey", public_key.clone()),
+                        (
+                            "publicKeys",
+                            public_keys.as_ref().map(|keys| keys.join(",")),
+                        ),
+                        ("shallow", bool_param(*shallow)),
+                        ("submodules", bool_param(*submodules)),
+                        ("exportIgnore", bool_param(*export_ignore)),
+                        ("allRefs", bool_param(*all_refs)),
+                        ("verifyCommit", bool_param(*verify_commit)),
+                    ],
+                )
+            }
+            FlakeRef::GitHub {
+                owner,
+                repo,
+                dir,
+                host,
+                keytype,
+                public_key,
+                public_keys,
+                r#ref,
+                rev,
+            }
+            | FlakeRef::GitLab {
+                owner,
+                repo,
+                dir,
+                host,
+                keytype,
+                public_key,
+                public_keys,
+                r#ref,
+                rev,
+            }
+            | FlakeRef::SourceHut {
+                owner,
+                repo,
+                dir,
+                host,
+                keytype,
+                public_key,
+                public_keys,
+                r#ref,
+                rev,
+            } => {
+                let scheme = match self {
+                    FlakeRef::GitHub { .. } => "github",
+                    FlakeRef::GitLab { .. } => "gitlab",
+                    FlakeRef::SourceHut { .. } => "sourcehut",
+                    _ => unreachable!(),
+                };
+
+                write!(f, "{scheme}:{owner}/{repo}")?;
+                if let Some(git_ref) = r#ref {
+                    write!(f, "/{git_ref}")?;
+                }
+                write_query(
+                    f,
+                    &[
+                        ("rev", rev.clone()),
+                        ("dir", dir.clone()),
+                        ("host", host.clone()),
+                        ("keytype", keytype.clone()),
+                        ("publicKey", public_key.clone()),
+                        (
+                            "publicKeys",
+                            public_keys.as_ref().map(|keys| keys.join(",")),
+                        ),
+                    ],
+                )
+            }
+            FlakeRef::Path {
+                path,
+                dir,
+                rev,
+                nar_hash,
+                rev_count,
+                last_modified,
+            } => {
+                write!(f, "path:{}", path.display())?;
+                write_query(
+                    f,
+                    &[
+                        ("dir", dir.clone()),
+                        ("narHash", nar_hash.clone()),
+                        ("rev", rev.clone()),
+                        ("revCount", rev_count.map(|c| c.to_string())),
+                        ("lastModified", last_modified.map(|m| m.to_string())),
+                    ],
+                )
+            }
+            FlakeRef::File { .. }
+            | FlakeRef::Tarball { .. }
+            | FlakeRef::Indirect { .. }
+            | FlakeRef::Mercurial { .. } => write!(f, "{}", self.to_uri()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -767,6 +954,7 @@ mod tests {
             input.parse::<FlakeRef>(),
             Ok(FlakeRef::Path {
                 path,
+                dir: None,
                 rev: None,
                 nar_hash: None,
                 rev_count: None,
@@ -781,4 +969,90 @@ mod tests {
             _ => panic!("Expected UrlParseError error"),
         }
     }
+
+    #[test]
+    fn test_github_rev_round_trip() {
+        let input = "github:NixOS/nixpkgs?rev=a3a3dda3bacf61e8a39258a0ed9c924eeca8e293";
+        let flake_ref = FlakeRef::parse(input).unwrap();
+        match &flake_ref {
+            FlakeRef::GitHub {
+                owner,
+                repo,
+                r#ref,
+                rev,
+                ..
+            } => {
+                assert_eq!(owner, "NixOS");
+                assert_eq!(repo, "nixpkgs");
+                assert_eq!(r#ref, &None);
+                assert_eq!(
+                    rev.as_deref(),
+                    Some("a3a3dda3bacf61e8a39258a0ed9c924eeca8e293")
+                );
+            }
+            _ => panic!("Expected GitHub input type"),
+        }
+        assert_eq!(flake_ref.to_string(), input);
+
+        // A ref given as a query parameter is rendered in the path form.
+        let flake_ref = FlakeRef::parse("github:snowfallorg/lib?ref=v2.1.1&dir=lib").unwrap();
+        assert_eq!(
+            flake_ref.to_string(),
+            "github:snowfallorg/lib/v2.1.1?dir=lib"
+        );
+        assert_eq!(
+            FlakeRef::parse(&flake_ref.to_string()).unwrap().to_string(),
+            flake_ref.to_string()
+        );
+    }
+
+    #[test]
+    fn test_git_https_ref_and_rev_round_trip() {
+        let input = "git+https://example.org/redox/base.git?ref=main&rev=0123456789abcdef0123456789abcdef01234567&dir=pkgs";
+        let flake_ref = FlakeRef::parse(input).unwrap();
+        match &flake_ref {
+            FlakeRef::Git {
+                url,
+                r#ref,
+                rev,
+                dir,
+                ..
+            } => {
+                assert_eq!(url.as_str(), "https://example.org/redox/base.git");
+                assert_eq!(r#ref.as_deref(), Some("main"));
+                assert_eq!(
+                    rev.as_deref(),
+                    Some("0123456789abcdef0123456789abcdef01234567")
+                );
+                assert_eq!(dir.as_deref(), Some("pkgs"));
+            }
+            _ => panic!("Expected Git input type"),
+        }
+        assert_eq!(flake_ref.to_string(), input);
+    }
+
+    #[test]
+    fn test_path_dir_round_trip() {
+        let input = "path:/home/user/config?dir=hosts";
+        let flake_ref = FlakeRef::parse(input).unwrap();
+        match &flake_ref {
+            FlakeRef::Path { path, dir, .. } => {
+                assert_eq!(path.to_str().unwrap(), "/home/user/config");
+                assert_eq!(dir.as_deref(), Some("hosts"));
+            }
+            _ => panic!("Expected Path input type"),
+        }
+        assert_eq!(flake_ref.to_string(), input);
+    }
+
+    #[test]
+    fn test_github_missing_repo() {
+        match FlakeRef::parse("github:NixOS") {
+            Ok(_) => panic!("Expected error for missing repo"),
+            Err(FlakeRefError::UnsupportedType(msg)) => {
+                assert!(msg.contains("owner and repo"), "{msg}")
+            }
+            _ => panic!("Expected UnsupportedType error"),
+        }
+    }
 }
@@ -0,0 +1,397 @@
+// Resolves indirect flake references (`nixpkgs`, `flake:nixpkgs/nixos-24.05`) through a
+// flake registry, the JSON file (`registry.json`, version 2) mapping short names to
+// concrete references such as `github:NixOS/nixpkgs`.
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use super::FlakeRef;
+
+/// Indirect references resolving to indirect references again are followed at most this
+/// many times, which also stops a registry that maps names in a cycle.
+const MAX_INDIRECTIONS: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistryError {
+    #[error("failed to read registry: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse registry: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported registry version {0}")]
+    UnsupportedVersion(u64),
+    #[error("invalid registry entry #{0}: {1}")]
+    InvalidEntry(usize, String),
+}
+
+/// How an entry's `from` must match the reference being resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    /// `from` names the id and whatever `ref`/`rev` it sets. The reference's own `ref`
+    /// and `rev` are carried over to the target, so `nixpkgs/nixos-24.05` becomes
+    /// `github:NixOS/nixpkgs/nixos-24.05`.
+    Prefix,
+    /// The reference must equal `from` exactly, and is replaced by the target as is
+    /// (`"exact": true` in the file).
+    Exact,
+}
+
+/// A registry entry: `from` is always an indirect reference.
+#[derive(Debug, Clone)]
+struct Entry {
+    id: String,
+    r#ref: Option<String>,
+    rev: Option<String>,
+    to: FlakeRef,
+    kind: MatchKind,
+}
+
+/// A flake registry, as loaded from one registry file. Entries are tried in file order.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct RegistryFile {
+    version: u64,
+    #[serde(default)]
+    flakes: Vec<EntryFile>,
+}
+
+#[derive(Deserialize)]
+struct EntryFile {
+    from: Map<String, Value>,
+    to: Map<String, Value>,
+    #[serde(default)]
+    exact: bool,
+}
+
+impl Registry {
+    /// Parses a registry file's contents.
+    pub fn from_json(json: &str) -> Result<Self, RegistryError> {
+        let file: RegistryFile = serde_json::from_str(json)?;
+        if file.version != 2 {
+            return Err(RegistryError::UnsupportedVersion(file.version));
+        }
+
+        let entries = file
+            .flakes
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let invalid = |msg: &str| RegistryError::InvalidEntry(i, msg.to_string());
+                let Some(FlakeRef::Indirect { id, r#ref, rev }) = from_attrs(&entry.from) else {
+                    return Err(invalid("'from' is not an indirect flake reference"));
+                };
+                let to = from_attrs(&entry.to)
+                    .ok_or_else(|| invalid("'to' is not a supported flake reference"))?;
+                Ok(Entry {
+                    id,
+                    r#ref,
+                    rev,
+                    to,
+                    kind: if entry.exact {
+                        MatchKind::Exact
+                    } else {
+                        MatchKind::Prefix
+                    },
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { entries })
+    }
+
+    /// Loads the registry file at `path`.
+    pub fn load(path: &Path) -> Result<Self, RegistryError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Resolves a flake reference string to a concrete reference.
+    ///
+    /// Indirect references (`nixpkgs`, `nixpkgs/nixos-24.05`, `flake:nixpkgs`) are
+    /// looked up until they resolve to something else; other references are returned as
+    /// parsed. Returns `None` if `s` doesn't parse or names a flake the registry doesn't
+    /// know.
+    pub fn resolve(&self, s: &str) -> Option<FlakeRef> {
+        let mut flake_ref = parse_indirect(s).or_else(|| FlakeRef::parse(s).ok())?;
+
+        for _ in 0..MAX_INDIRECTIONS {
+            let FlakeRef::Indirect { id, r#ref, rev } = &flake_ref else {
+                return Some(flake_ref);
+            };
+            let entry = self
+                .entries
+                .iter()
+                .find(|entry| entry.matches(id, r#ref, rev))?;
+            flake_ref = match entry.kind {
+                MatchKind::Exact => entry.to.clone(),
+                MatchKind::Prefix => with_overrides(
+                    entry.to.clone(),
+                    r#ref.clone().filter(|_| entry.r#ref.is_none()),
+                    rev.clone().filter(|_| entry.rev.is_none()),
+                )?,
+            };
+        }
+        None
+    }
+}
+
+impl Entry {
+    fn matches(&self, id: &str, r#ref: &Option<String>, rev: &Option<String>) -> bool {
+        if self.id != id {
+            return false;
+        }
+        match self.kind {
+            MatchKind::Exact => self.r#ref == *r#ref && self.rev == *rev,
+            MatchKind::Prefix => {
+                (self.r#ref.is_none() || self.r#ref == *r#ref)
+                    && (self.rev.is_none() || self.rev == *rev)
+            }
+        }
+    }
+}
+
+// Parses the indirect shorthand `[flake:]id[/ref][/rev]`, which isn't a URL.
+fn parse_indirect(s: &str) -> Option<FlakeRef> {
+    let mut parts = s.strip_prefix("flake:").unwrap_or(s).split('/');
+    let id = parts.next()?;
+    let mut chars = id.chars();
+    if !chars.next()?.is_ascii_alphabetic()
+        || !chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+
+    let is_rev = |s: &str| s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit());
+    let (r#ref, rev) = match (parts.next(), parts.next(), parts.next()) {
+        (None, None, None) => (None, None),
+        (Some(rev), None, None) if is_rev(rev) => (None, Some(rev)),
+        (Some(r#ref), None, None) if !r#ref.is_empty() => (Some(r#ref), None),
+        (Some(r#ref), Some(rev), None) if !r#ref.is_empty() && is_rev(rev) => {
+            (Some(r#ref), Some(rev))
+        }
+        _ => return None,
+    };
+
+    Some(FlakeRef::Indirect {
+        id: id.to_string(),
+        r#ref: r#ref.map(String::from),
+        rev: rev.map(String::from),
+    })
+}
+
+// Builds a flake reference from its attribute form, as registry entries store them.
+fn from_attrs(attrs: &Map<String, Value>) -> Option<FlakeRef> {
+    let string = |key: &str| attrs.get(key).and_then(Value::as_str).map(String::from);
+    let number = |key: &str| attrs.get(key).and_then(Value::as_u64);
+    let flag = |key: &str| attrs.get(key).and_then(Value::as_bool).unwrap_or(false);
+
+    Some(match attrs.get("type")?.as_str()? {
+        "indirect" => FlakeRef::Indirect {
+            id: string("id")?,
+            r#ref: string("ref"),
+            rev: string("rev"),
+        },
+        "github" => FlakeRef::GitHub {
+            owner: string("owner")?,
+            repo: string("repo")?,
+            dir: string("dir"),
+            host: string("host"),
+            keytype: None,
+            public_key: None,
+            public_keys: None,
+            r#ref: string("ref"),
+            rev: string("rev"),
+        },
+        "gitlab" => FlakeRef::GitLab {
+            owner: string("owner")?,
+            repo: string("repo")?,
+            dir: string("dir"),
+            host: string("host"),
+            keytype: None,
+            public_key: None,
+            public_keys: None,
+            r#ref: string("ref"),
+            rev: string("rev"),
+        },
+        "sourcehut" => FlakeRef::SourceHut {
+            owner: string("owner")?,
+            repo: string("repo")?,
+            dir: string("dir"),
+            host: string("host"),
+            keytype: None,
+            public_key: None,
+            public_keys: None,
+            r#ref: string("ref"),
+            rev: string("rev"),
+        },
+        "git" => FlakeRef::Git {
+            all_refs: flag("allRefs"),
+            dir: string("dir"),
+            export_ignore: flag("exportIgnore"),
+            keytype: None,
+            public_key: None,
+            public_keys: None,
+            r#ref: string("ref"),
+            rev: string("rev"),
+            shallow: flag("shallow"),
+            submodules: flag("submodules"),
+            url: string("url")?.parse().ok()?,
+            verify_commit: flag("verifyCommit"),
+        },
+        "path" => FlakeRef::Path {
+            dir: string("dir"),
+            last_modified: number("lastModified"),
+            nar_hash: string("narHash"),
+            path: string("path")?.into(),
+            rev: string("rev"),
+            rev_count: number("revCount"),
+        },
+        "tarball" => FlakeRef::Tarball {
+            last_modified: number("lastModified"),
+            nar_hash: string("narHash"),
+            rev: string("rev"),
+            rev_count: number("revCount"),
+            url: string("url")?.parse().ok()?,
+        },
+        "file" => FlakeRef::File {
+            last_modified: number("lastModified"),
+            nar_hash: string("narHash"),
+            rev: string("rev"),
+            rev_count: number("revCount"),
+            url: string("url")?.parse().ok()?,
+        },
+        _ => return None,
+    })
+}
+
+// Applies the `ref` and `rev` of the reference being resolved to its target. Targets
+// that can't take them (a path or tarball) make the resolution fail.
+fn with_overrides(
+    mut flake_ref: FlakeRef,
+    new_ref: Option<String>,
+    new_rev: Option<String>,
+) -> Option<FlakeRef> {
+    if new_ref.is_none() && new_rev.is_none() {
+        return Some(flake_ref);
+    }
+    match &mut flake_ref {
+        FlakeRef::Indirect { r#ref, rev, .. }
+        | FlakeRef::Git { r#ref, rev, .. }
+        | FlakeRef::GitHub { r#ref, rev, .. }
+        | FlakeRef::GitLab { r#ref, rev, .. }
+        | FlakeRef::SourceHut { r#ref, rev, .. } => {
+            if new_ref.is_some() {
+                *r#ref = new_ref;
+            }
+            if new_rev.is_some() {
+                *rev = new_rev;
+            }
+        }
+        _ => return None,
+    }
+    Some(flake_ref)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTRY: &str = r#"{
+        "version": 2,
+        "flakes": [
+            {
+                "from": { "type": "indirect", "id": "nixpkgs" },
+                "to": { "type": "github", "owner": "NixOS", "repo": "nixpkgs" }
+            },
+            {
+                "from": { "type": "indirect", "id": "stable" },
+                "to": { "type": "indirect", "id": "nixpkgs", "ref": "nixos-24.05" }
+            },
+            {
+                "from": { "type": "indirect", "id": "pinned", "ref": "v1" },
+                "to": { "type": "path", "path": "/src/pinned-v1" },
+                "exact": true
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn resolves_nixpkgs_to_github() {
+        let registry = Registry::from_json(REGISTRY).unwrap();
+
+        assert!(matches!(
+            registry.resolve("nixpkgs"),
+            Some(FlakeRef::GitHub { owner, repo, r#ref: None, rev: None, .. })
+                if owner == "NixOS" && repo == "nixpkgs"
+        ));
+        assert_eq!(
+            registry
+                .resolve("flake:nixpkgs/nixos-unstable")
+                .unwrap()
+                .to_string(),
+            "github:NixOS/nixpkgs/nixos-unstable"
+        );
+
+        // Indirect to indirect, keeping the intermediate entry's ref.
+        assert_eq!(
+            registry.resolve("stable").unwrap().to_string(),
+            "github:NixOS/nixpkgs/nixos-24.05"
+        );
+
+        // Direct references need no registry.
+        assert_eq!(
+            registry.resolve("github:owner/repo").unwrap().to_string(),
+            "github:owner/repo"
+        );
+    }
+
+    #[test]
+    fn exact_entries_need_an_exact_match() {
+        let registry = Registry::from_json(REGISTRY).unwrap();
+        assert!(matches!(
+            registry.resolve("pinned/v1"),
+            Some(FlakeRef::Path { path, .. }) if path == Path::new("/src/pinned-v1")
+        ));
+        assert!(registry.resolve("pinned").is_none());
+        assert!(registry.resolve("pinned/v2").is_none());
+    }
+
+    #[test]
+    fn unknown_name_is_none() {
+        let registry = Registry::from_json(REGISTRY).unwrap();
+        assert!(registry.resolve("home-manager").is_none());
+        assert!(Registry::default().resolve("nixpkgs").is_none());
+    }
+
+    #[test]
+    fn cycles_do_not_resolve() {
+        let registry = Registry::from_json(
+            r#"{ "version": 2, "flakes": [
+                { "from": { "type": "indirect", "id": "a" }, "to": { "type": "indirect", "id": "b" } },
+                { "from": { "type": "indirect", "id": "b" }, "to": { "type": "indirect", "id": "a" } }
+            ] }"#,
+        )
+        .unwrap();
+        assert!(registry.resolve("a").is_none());
+    }
+
+    #[test]
+    fn rejects_other_versions_and_bad_entries() {
+        assert!(matches!(
+            Registry::from_json(r#"{ "version": 1, "flakes": [] }"#),
+            Err(RegistryError::UnsupportedVersion(1))
+        ));
+        assert!(matches!(
+            Registry::from_json(
+                r#"{ "version": 2, "flakes": [
+                    { "from": { "type": "github", "owner": "a", "repo": "b" },
+                      "to": { "type": "indirect", "id": "c" } }
+                ] }"#
+            ),
+            Err(RegistryError::InvalidEntry(0, _))
+        ));
+    }
+}
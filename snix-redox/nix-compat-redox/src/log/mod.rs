@@ -1,6 +1,15 @@
 //! Contains types Nix uses for its logging, visible in the "internal-json" log
 //! messages as well as in nix-daemon communication.
 
+#[cfg(feature = "serde")]
+mod parse;
+#[cfg(feature = "serde")]
+pub use parse::{ERROR_CONTEXT_LINES, LogEvent, parse};
+#[cfg(feature = "serde")]
+mod render;
+#[cfg(feature = "serde")]
+pub use render::Renderer;
+
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
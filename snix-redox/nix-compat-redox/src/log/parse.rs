@@ -0,0 +1,338 @@
+//! Turns a raw build log into a stream of [LogEvent]s.
+//!
+//! Both the "internal-json" format (`@nix {...}` lines) and plain text logs
+//! are understood, also mixed in one log, as in `nix log` output.
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+use super::{AT_NIX_PREFIX, ActivityType, Field, LogMessage, ResultType, VerbosityLevel};
+
+/// How many lines preceding an error are kept as its context.
+pub const ERROR_CONTEXT_LINES: usize = 10;
+
+/// A noteworthy line in a build log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LogEvent {
+    /// A derivation started building (`building '/nix/store/….drv'...`).
+    Building { drv: String },
+    /// The builder entered a stdenv phase, e.g. `unpackPhase`.
+    Phase { name: String },
+    /// A `warning:` message, without the prefix.
+    Warning { message: String },
+    /// An `error:` message, without the prefix and including its indented
+    /// continuation lines, along with up to [ERROR_CONTEXT_LINES] output
+    /// lines that preceded it.
+    ///
+    /// Errors printed by tools inside the build are reported as well; when
+    /// the build itself failed, its error is the last one.
+    Error {
+        message: String,
+        context: Vec<String>,
+    },
+    /// Any other output line.
+    Line(String),
+}
+
+/// Parses the build log read from `reader`.
+///
+/// Invalid UTF-8 is replaced, and JSON log messages that carry nothing of
+/// interest (activity start/stop, progress) are skipped. Iteration stops at
+/// the end of the log or on the first read error.
+pub fn parse<R: BufRead>(reader: R) -> impl Iterator<Item = LogEvent> {
+    Parser {
+        reader,
+        recent: VecDeque::with_capacity(ERROR_CONTEXT_LINES),
+        peeked: None,
+    }
+}
+
+struct Parser<R> {
+    reader: R,
+    /// The last few [LogEvent::Line]s, for error context.
+    recent: VecDeque<String>,
+    /// A line read past the end of an error's continuation lines.
+    peeked: Option<String>,
+}
+
+impl<R: BufRead> Iterator for Parser<R> {
+    type Item = LogEvent;
+
+    fn next(&mut self) -> Option<LogEvent> {
+        loop {
+            let line = self.next_line()?;
+            let event = if line.starts_with(AT_NIX_PREFIX) {
+                match LogMessage::from_json_str(&line) {
+                    Ok(msg) => match json_event(msg) {
+                        Some(event) => event,
+                        None => continue,
+                    },
+                    Err(_) => text_event(&line),
+                }
+            } else {
+                text_event(&line)
+            };
+            return Some(self.finish(event));
+        }
+    }
+}
+
+impl<R: BufRead> Parser<R> {
+    fn next_line(&mut self) -> Option<String> {
+        if let Some(line) = self.peeked.take() {
+            return Some(line);
+        }
+
+        let mut buf = Vec::new();
+        match self.reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                Some(String::from_utf8_lossy(line).into_owned())
+            }
+        }
+    }
+
+    /// Tracks context lines, and completes errors with their continuation
+    /// lines and context.
+    fn finish(&mut self, event: LogEvent) -> LogEvent {
+        match event {
+            LogEvent::Line(line) => {
+                if self.recent.len() == ERROR_CONTEXT_LINES {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(line.clone());
+                LogEvent::Line(line)
+            }
+            LogEvent::Error { mut message, .. } => {
+                // Nix indents the rest of a multi-line error.
+                while let Some(line) = self.next_line() {
+                    if !line.starts_with(' ') {
+                        self.peeked = Some(line);
+                        break;
+                    }
+                    message.push('\n');
+                    message.push_str(line.trim_start());
+                }
+                LogEvent::Error {
+                    message,
+                    context: self.recent.drain(..).collect(),
+                }
+            }
+            event => event,
+        }
+    }
+}
+
+fn text_event(line: &str) -> LogEvent {
+    if let Some((drv, _)) = line
+        .strip_prefix("building '")
+        .and_then(|rest| rest.split_once('\''))
+    {
+        LogEvent::Building { drv: drv.into() }
+    } else if let Some(name) = line.strip_prefix("Running phase: ") {
+        LogEvent::Phase {
+            name: name.trim().into(),
+        }
+    } else if let Some(message) = line.strip_prefix("warning: ") {
+        LogEvent::Warning {
+            message: message.into(),
+        }
+    } else if let Some(message) = line.strip_prefix("error: ") {
+        LogEvent::Error {
+            message: message.into(),
+            context: vec![],
+        }
+    } else {
+        LogEvent::Line(line.into())
+    }
+}
+
+fn json_event(msg: LogMessage<'_>) -> Option<LogEvent> {
+    Some(match msg {
+        LogMessage::SetPhase { phase } => LogEvent::Phase { name: phase.into() },
+        LogMessage::Result {
+            r#type: ResultType::SetPhase,
+            fields,
+            ..
+        } => LogEvent::Phase {
+            name: first_string(&fields)?,
+        },
+        // Output of the builder, which may itself contain phase lines.
+        LogMessage::Result {
+            r#type: ResultType::BuildLogLine,
+            fields,
+            ..
+        } => text_event(&first_string(&fields)?),
+        LogMessage::Start {
+            r#type: ActivityType::Build,
+            fields: Some(fields),
+            ..
+        } => LogEvent::Building {
+            drv: first_string(&fields)?,
+        },
+        LogMessage::Msg { level, msg } => {
+            let msg = strip_ansi(&msg);
+            match level {
+                VerbosityLevel::Error => LogEvent::Error {
+                    message: msg.strip_prefix("error: ").unwrap_or(&msg).into(),
+                    context: vec![],
+                },
+                VerbosityLevel::Warn => LogEvent::Warning {
+                    message: msg.strip_prefix("warning: ").unwrap_or(&msg).into(),
+                },
+                _ => LogEvent::Line(msg),
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn first_string(fields: &[Field<'_>]) -> Option<String> {
+    match fields.first()? {
+        Field::String(s) => Some(String::from_utf8_lossy(s).into_owned()),
+        Field::Int(_) => None,
+    }
+}
+
+/// Removes the terminal color codes Nix puts into `msg` fields.
+pub(super) fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI sequences end with a byte in '@'..='~'.
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(log: &str) -> Vec<LogEvent> {
+        parse(log.as_bytes()).collect()
+    }
+
+    fn phase(name: &str) -> LogEvent {
+        LogEvent::Phase { name: name.into() }
+    }
+
+    #[test]
+    fn internal_json_actions() {
+        let log = concat!(
+            r#"@nix {"action":"start","fields":["/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv","",1,1],"id":1,"level":3,"parent":0,"text":"building '/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv'","type":105}"#,
+            "\n",
+            r#"@nix {"action":"setPhase","phase":"unpackPhase"}"#,
+            "\n",
+            r#"@nix {"action":"result","fields":["unpacking source archive hello-2.12.1.tar.gz"],"id":1,"type":101}"#,
+            "\n",
+            r#"@nix {"action":"result","fields":[1,2],"id":1,"type":105}"#,
+            "\n",
+            r#"@nix {"action":"result","fields":["buildPhase"],"id":1,"type":104}"#,
+            "\n",
+            r#"@nix {"action":"msg","level":1,"msg":"\u001b[35;1mwarning:\u001b[0m Git tree is dirty"}"#,
+            "\n",
+            r#"@nix {"action":"stop","id":1}"#,
+            "\n",
+        );
+
+        assert_eq!(
+            events(log),
+            vec![
+                LogEvent::Building {
+                    drv: "/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv".into()
+                },
+                phase("unpackPhase"),
+                LogEvent::Line("unpacking source archive hello-2.12.1.tar.gz".into()),
+                phase("buildPhase"),
+                LogEvent::Warning {
+                    message: "Git tree is dirty".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_text_phases() {
+        let log = "building '/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv'...\r\n\
+                   Running phase: unpackPhase\n\
+                   unpacking source archive /nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz\n\
+                   Running phase: configurePhase\n\
+                   warning: unknown setting 'foo'\n\
+                   @nix not json\n";
+
+        assert_eq!(
+            events(log),
+            vec![
+                LogEvent::Building {
+                    drv: "/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv".into()
+                },
+                phase("unpackPhase"),
+                LogEvent::Line(
+                    "unpacking source archive /nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz"
+                        .into()
+                ),
+                phase("configurePhase"),
+                LogEvent::Warning {
+                    message: "unknown setting 'foo'".into()
+                },
+                LogEvent::Line("@nix not json".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ends_in_error() {
+        let mut log = String::from("Running phase: buildPhase\n");
+        for i in 0..12 {
+            log.push_str(&format!("cc -c file{i}.c\n"));
+        }
+        log.push_str(
+            "error: builder for '/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv' failed with exit code 2;\n       \
+             last 1 log lines:\n       \
+             > make: *** [Makefile:42: all] Error 1\n       \
+             For full logs, run 'nix log /nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv'.\n",
+        );
+
+        let events = events(&log);
+        assert_eq!(events.len(), 1 + 12 + 1);
+        assert_eq!(events[0], phase("buildPhase"));
+        assert_eq!(
+            events.last().unwrap(),
+            &LogEvent::Error {
+                message: "builder for '/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv' failed with exit code 2;\n\
+                          last 1 log lines:\n\
+                          > make: *** [Makefile:42: all] Error 1\n\
+                          For full logs, run 'nix log /nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv'."
+                    .into(),
+                context: (2..12).map(|i| format!("cc -c file{i}.c")).collect(),
+            }
+        );
+    }
+
+    #[test]
+    fn line_after_error_is_kept() {
+        assert_eq!(
+            events("error: first\n  more\nafter\n"),
+            vec![
+                LogEvent::Error {
+                    message: "first\nmore".into(),
+                    context: vec![],
+                },
+                LogEvent::Line("after".into()),
+            ]
+        );
+    }
+}
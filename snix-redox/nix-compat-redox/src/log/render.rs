@@ -0,0 +1,151 @@
+//! Turns [LogEvent]s back into text, either ANSI-colored for a terminal or
+//! plain for files and pipes.
+
+use std::io::{self, Write};
+
+use super::parse::{LogEvent, strip_ansi};
+
+const BOLD: &str = "\u{1b}[1m";
+const RED_BOLD: &str = "\u{1b}[31;1m";
+const MAGENTA_BOLD: &str = "\u{1b}[35;1m";
+const RESET: &str = "\u{1b}[0m";
+
+/// Indentation of the continuation lines of a multi-line error, as Nix
+/// prints them (the width of `error: `).
+const ERROR_INDENT: &str = "       ";
+
+/// Renders [LogEvent]s one line (or, for errors, one block) at a time.
+///
+/// Phase headers are bold and errors red when colored, using the same
+/// codes as Nix. The plain renderer also drops any terminal codes contained
+/// in the log itself.
+///
+/// An error's context is not repeated: its lines were already rendered as
+/// [LogEvent::Line]s when they came by.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    /// A renderer emitting ANSI escape codes if `color` is set, and plain
+    /// text otherwise.
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    /// Renders a single event, without a trailing newline.
+    pub fn render(&self, event: &LogEvent) -> String {
+        match event {
+            LogEvent::Building { drv } => {
+                format!("building '{}'...", self.paint(MAGENTA_BOLD, drv))
+            }
+            LogEvent::Phase { name } => {
+                self.paint(BOLD, &format!("Running phase: {}", self.text(name)))
+            }
+            LogEvent::Warning { message } => {
+                format!(
+                    "{} {}",
+                    self.paint(MAGENTA_BOLD, "warning:"),
+                    self.text(message)
+                )
+            }
+            LogEvent::Error { message, .. } => format!(
+                "{} {}",
+                self.paint(RED_BOLD, "error:"),
+                self.text(message)
+                    .replace('\n', &format!("\n{ERROR_INDENT}"))
+            ),
+            LogEvent::Line(line) => self.text(line),
+        }
+    }
+
+    /// Renders `events` to `w`, one per line.
+    pub fn write_all<W, I>(&self, w: &mut W, events: I) -> io::Result<()>
+    where
+        W: Write,
+        I: IntoIterator<Item = LogEvent>,
+    {
+        for event in events {
+            writeln!(w, "{}", self.render(&event))?;
+        }
+        Ok(())
+    }
+
+    fn paint(&self, sgr: &str, s: &str) -> String {
+        if self.color {
+            format!("{sgr}{s}{RESET}")
+        } else {
+            s.into()
+        }
+    }
+
+    /// Text from the log itself, which may carry codes of its own.
+    fn text(&self, s: &str) -> String {
+        if self.color { s.into() } else { strip_ansi(s) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::parse;
+
+    const LOG: &str = "building '/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv'...\n\
+                       Running phase: buildPhase\n\
+                       \u{1b}[32mcc -c hello.c\u{1b}[0m\n\
+                       warning: unused variable 'x'\n\
+                       error: builder failed with exit code 2;\n       \
+                       last 1 log lines:\n";
+
+    fn render(color: bool) -> String {
+        let mut out = Vec::new();
+        Renderer::new(color)
+            .write_all(&mut out, parse(LOG.as_bytes()))
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn plain_has_no_escape_codes() {
+        let out = render(false);
+        assert!(!out.contains('\u{1b}'), "{out:?}");
+        assert_eq!(
+            out,
+            "building '/nix/store/q3wx1gab2ysnk5nyvyyg56ana2v4r2ar-hello-2.12.1.drv'...\n\
+             Running phase: buildPhase\n\
+             cc -c hello.c\n\
+             warning: unused variable 'x'\n\
+             error: builder failed with exit code 2;\n       \
+             last 1 log lines:\n"
+        );
+
+        // What was rendered parses back to the same events.
+        let reparsed: Vec<_> = parse(out.as_bytes()).collect();
+        let mut original: Vec<_> = parse(LOG.as_bytes()).collect();
+        original[2] = LogEvent::Line("cc -c hello.c".into());
+        if let LogEvent::Error { context, .. } = &mut original[4] {
+            *context = vec!["cc -c hello.c".into()];
+        }
+        assert_eq!(reparsed, original);
+    }
+
+    #[test]
+    fn colored_wraps_errors_in_red() {
+        let out = render(true);
+        assert!(
+            out.contains(
+                "\u{1b}[31;1merror:\u{1b}[0m builder failed with exit code 2;\n       last 1 log lines:\n"
+            ),
+            "{out:?}"
+        );
+        assert!(
+            out.contains("\u{1b}[1mRunning phase: buildPhase\u{1b}[0m\n"),
+            "{out:?}"
+        );
+        assert!(
+            out.contains("\u{1b}[35;1mwarning:\u{1b}[0m unused variable 'x'\n"),
+            "{out:?}"
+        );
+    }
+}
@@ -4,12 +4,15 @@
 //! a variety of things, including addressing fixed-output derivations
 //! and transferring store paths between Nix stores.
 
+use std::collections::HashSet;
 use std::io::{
     self, BufRead,
     ErrorKind::{InvalidData, UnexpectedEof},
     Read, Write,
 };
 
+use bstr::ByteSlice;
+
 #[cfg(not(debug_assertions))]
 use std::marker::PhantomData;
 
@@ -36,6 +39,9 @@ struct ArchiveReader<'a, 'r> {
     ///
     /// All of these checks vanish in release mode.
     status: ArchiveReaderStatus<'a>,
+
+    /// Whether directory entries must be in canonical (strictly increasing) order.
+    strict: bool,
 }
 
 macro_rules! try_or_poison {
@@ -50,11 +56,28 @@ macro_rules! try_or_poison {
     };
 }
 /// Start reading a NAR file from `reader`.
+///
+/// Directory entries must be sorted by name, as Nix writes them; an
+/// out-of-order entry is an [InvalidData] error.
 pub fn open<'a, 'r>(reader: &'a mut Reader<'r>) -> io::Result<Node<'a, 'r>> {
+    open_with_strict(reader, true)
+}
+
+/// Start reading a NAR file from `reader`, optionally accepting legacy
+/// archives whose directory entries are not in canonical order.
+///
+/// With `strict` unset, only a name repeating an earlier entry is rejected.
+/// Such archives do not re-serialize to the same bytes, so their NAR hash
+/// cannot be checked by round-tripping.
+pub fn open_with_strict<'a, 'r>(
+    reader: &'a mut Reader<'r>,
+    strict: bool,
+) -> io::Result<Node<'a, 'r>> {
     read::token(reader, &wire::TOK_NAR)?;
     Node::new(ArchiveReader {
         inner: reader,
         status: ArchiveReaderStatus::top(),
+        strict,
     })
 }
 
@@ -267,6 +290,8 @@ pub struct DirReader<'a, 'r> {
     /// Previous directory entry name.
     /// We have to hang onto this to enforce name monotonicity.
     prev_name: Vec<u8>,
+    /// Names read so far, to reject repeats when entries needn't be sorted.
+    seen: HashSet<Vec<u8>>,
 }
 
 pub struct Entry<'a, 'r> {
@@ -279,6 +304,7 @@ impl<'a, 'r> DirReader<'a, 'r> {
         Self {
             reader,
             prev_name: vec![],
+            seen: HashSet::new(),
         }
     }
 
@@ -325,9 +351,23 @@ impl<'a, 'r> DirReader<'a, 'r> {
         }
 
         // Enforce strict monotonicity of directory entry names.
-        if &self.prev_name[..] >= name {
+        if self.reader.strict && &self.prev_name[..] >= name {
             self.reader.status.poison();
-            return Err(InvalidData.into());
+            return Err(io::Error::new(
+                InvalidData,
+                format!(
+                    "non-canonical NAR: directory entry {:?} does not sort after {:?}",
+                    name.as_bstr(),
+                    self.prev_name.as_bstr()
+                ),
+            ));
+        }
+        if !self.reader.strict && !self.seen.insert(name.to_vec()) {
+            self.reader.status.poison();
+            return Err(io::Error::new(
+                InvalidData,
+                format!("NAR directory entry {:?} appears twice", name.as_bstr()),
+            ));
         }
 
         self.prev_name.clear();
@@ -437,6 +477,7 @@ impl<'r> ArchiveReader<'_, 'r> {
     fn child(&mut self) -> ArchiveReader<'_, 'r> {
         ArchiveReader {
             inner: self.inner,
+            strict: self.strict,
             #[cfg(not(debug_assertions))]
             status: ArchiveReaderStatus::None(PhantomData),
             #[cfg(debug_assertions)]
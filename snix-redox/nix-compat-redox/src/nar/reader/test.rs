@@ -1,6 +1,6 @@
 use std::io::Read;
 
-use crate::nar;
+use crate::nar::{self, wire};
 
 #[test]
 fn symlink() {
@@ -247,6 +247,110 @@ fn dir_read_after_none() {
     }
 }
 
+/// A NAR of a directory holding the empty regular files `names`, in the given order.
+fn dir_nar(names: &[&str]) -> Vec<u8> {
+    fn string(buf: &mut Vec<u8>, s: &[u8]) {
+        buf.extend_from_slice(&(s.len() as u64).to_le_bytes());
+        buf.extend_from_slice(s);
+        buf.resize(buf.len().next_multiple_of(8), 0);
+    }
+
+    let mut nar = wire::TOK_NAR.to_vec();
+    nar.extend_from_slice(&wire::TOK_DIR);
+    for name in names {
+        nar.extend_from_slice(&wire::TOK_ENT);
+        string(&mut nar, name.as_bytes());
+        nar.extend_from_slice(&wire::TOK_NOD);
+        nar.extend_from_slice(&wire::TOK_REG);
+        nar.extend_from_slice(&0u64.to_le_bytes());
+        nar.extend_from_slice(&wire::TOK_PAR); // node
+        nar.extend_from_slice(&wire::TOK_PAR); // entry
+    }
+    nar.extend_from_slice(&wire::TOK_PAR);
+    nar
+}
+
+#[test]
+fn dir_out_of_order_rejected() {
+    let bytes = dir_nar(&["b", "a"]);
+    let mut f = std::io::Cursor::new(&bytes);
+    let node = nar::reader::open(&mut f).unwrap();
+
+    match node {
+        nar::reader::Node::Directory(mut dir_reader) => {
+            must_read_file(
+                "b",
+                dir_reader
+                    .next()
+                    .expect("next must succeed")
+                    .expect("must be some"),
+            );
+
+            let err = dir_reader
+                .next()
+                .err()
+                .expect("out-of-order entry must be rejected");
+            assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+            assert_eq!(
+                "non-canonical NAR: directory entry \"a\" does not sort after \"b\"",
+                err.to_string()
+            );
+        }
+        _ => panic!("unexpected type"),
+    }
+}
+
+#[test]
+fn dir_out_of_order_lenient() {
+    let bytes = dir_nar(&["b", "a"]);
+    let mut f = std::io::Cursor::new(&bytes);
+    let node = nar::reader::open_with_strict(&mut f, false).unwrap();
+
+    match node {
+        nar::reader::Node::Directory(mut dir_reader) => {
+            for name in ["b", "a"] {
+                must_read_file(
+                    name,
+                    dir_reader
+                        .next()
+                        .expect("next must succeed")
+                        .expect("must be some"),
+                );
+            }
+            assert!(dir_reader.next().expect("must succeed").is_none());
+        }
+        _ => panic!("unexpected type"),
+    }
+}
+
+#[test]
+fn dir_duplicate_rejected_when_lenient() {
+    let bytes = dir_nar(&["b", "a", "b"]);
+    let mut f = std::io::Cursor::new(&bytes);
+    let node = nar::reader::open_with_strict(&mut f, false).unwrap();
+
+    match node {
+        nar::reader::Node::Directory(mut dir_reader) => {
+            for name in ["b", "a"] {
+                must_read_file(
+                    name,
+                    dir_reader
+                        .next()
+                        .expect("next must succeed")
+                        .expect("must be some"),
+                );
+            }
+            let err = dir_reader
+                .next()
+                .err()
+                .expect("duplicate entry must be rejected");
+            assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+            assert_eq!("NAR directory entry \"b\" appears twice", err.to_string());
+        }
+        _ => panic!("unexpected type"),
+    }
+}
+
 fn must_read_file(name: &'static str, entry: nar::reader::Entry<'_, '_>) {
     assert_eq!(name.as_bytes(), entry.name);
 
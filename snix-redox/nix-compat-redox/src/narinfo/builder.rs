@@ -0,0 +1,165 @@
+//! Assembling [NarInfo]s from scratch, e.g. to publish paths to a binary cache.
+
+use crate::{nixhash::CAHash, store_path::StorePathRef};
+
+use super::{Flags, NarInfo, SigningKey};
+
+/// Builds a [NarInfo] for a store path.
+///
+/// Only the store path, NAR URL, NAR hash and NAR size are required.
+///
+/// The [Display](std::fmt::Display) output of the built [NarInfo] is the
+/// canonical narinfo file, with fields in the order Nix writes them.
+#[derive(Debug)]
+pub struct NarInfoBuilder<'a> {
+    narinfo: NarInfo<'a>,
+}
+
+impl<'a> NarInfoBuilder<'a> {
+    /// `url` is relative to the narinfo file, usually
+    /// `nar/<file hash>.nar.<compression>`.
+    pub fn new(
+        store_path: StorePathRef<'a>,
+        url: &'a str,
+        nar_hash: [u8; 32],
+        nar_size: u64,
+    ) -> Self {
+        Self {
+            narinfo: NarInfo {
+                flags: Flags::empty(),
+                store_path,
+                nar_hash,
+                nar_size,
+                references: vec![],
+                signatures: vec![],
+                ca: None,
+                system: None,
+                deriver: None,
+                url,
+                compression: None,
+                file_hash: None,
+                file_size: None,
+            },
+        }
+    }
+
+    /// Compression of the NAR file, e.g. `xz` or `zstd`. `None` (the
+    /// default) is written as `Compression: none`.
+    pub fn compression(mut self, compression: Option<&'a str>) -> Self {
+        self.narinfo.compression = compression;
+        self
+    }
+
+    /// SHA-256 digest and size of the (compressed) file at the URL.
+    pub fn file(mut self, file_hash: [u8; 32], file_size: u64) -> Self {
+        self.narinfo.file_hash = Some(file_hash);
+        self.narinfo.file_size = Some(file_size);
+        self
+    }
+
+    /// References of the store path. They are sorted, as in Nix.
+    pub fn references<I>(mut self, references: I) -> Self
+    where
+        I: IntoIterator<Item = StorePathRef<'a>>,
+    {
+        self.narinfo.references = references.into_iter().collect();
+        self.narinfo.references.sort();
+        self
+    }
+
+    /// Derivation that produced the store path, without its `.drv` suffix
+    /// (see [NarInfo::deriver]).
+    pub fn deriver(mut self, deriver: StorePathRef<'a>) -> Self {
+        self.narinfo.deriver = Some(deriver);
+        self
+    }
+
+    /// Nix system triple of the deriver.
+    pub fn system(mut self, system: &'a str) -> Self {
+        self.narinfo.system = Some(system);
+        self
+    }
+
+    pub fn ca(mut self, ca: CAHash) -> Self {
+        self.narinfo.ca = Some(ca);
+        self
+    }
+
+    /// Finishes the [NarInfo], without signatures.
+    pub fn build(self) -> NarInfo<'a> {
+        self.narinfo
+    }
+
+    /// Finishes the [NarInfo] and signs it with `signer`.
+    ///
+    /// Further signatures can be added with [NarInfo::add_signature].
+    pub fn build_signed<S>(self, signer: &'a SigningKey<S>) -> NarInfo<'a>
+    where
+        S: ed25519::signature::Signer<ed25519::Signature>,
+    {
+        let mut narinfo = self.build();
+        narinfo.add_signature(signer);
+        narinfo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use pretty_assertions::assert_eq;
+
+    use crate::narinfo::{
+        DUMMY_KEYPAIR, DUMMY_VERIFYING_KEY, NarInfo, VerifyingKey, parse_keypair,
+    };
+    use crate::store_path::StorePathRef;
+
+    use super::NarInfoBuilder;
+
+    #[test]
+    fn build_sign_roundtrip() {
+        let store_path =
+            StorePathRef::from_bytes(b"syd87l2rxw8cbsxmxl853h0r6pdwhwjr-curl-7.82.0-bin").unwrap();
+        let zlib =
+            StorePathRef::from_bytes(b"j5jxw3iy7bbz4a57fh9g2xm2gxmyal8h-zlib-1.2.12").unwrap();
+        let curl =
+            StorePathRef::from_bytes(b"0jqd0rlxzra1rs38rdxl43yh6rxchgc6-curl-7.82.0").unwrap();
+        let deriver =
+            StorePathRef::from_bytes(b"5rwxzi7pal3qhpsyfc16gzkh939q1np6-curl-7.82.0").unwrap();
+        let (signing_key, _) = parse_keypair(DUMMY_KEYPAIR).expect("must succeed");
+
+        let narinfo = NarInfoBuilder::new(
+            store_path,
+            "nar/05ra3y72i3qjri7xskf9qj8kb29r6naqy1sqpbs3azi3xcigmj56.nar.xz",
+            hex!("60adfd293a4d81ad7cd7b6e2bc9e9a0e8f12d8a2c3f0a4b3e1b2c3d4e5f60718"),
+            196040,
+        )
+        .compression(Some("xz"))
+        .file(
+            hex!("a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f90"),
+            68852,
+        )
+        .references([zlib.clone(), curl.clone()])
+        .deriver(deriver.clone())
+        .system("x86_64-linux")
+        .build_signed(&signing_key);
+        let serialized = narinfo.to_string();
+
+        let parsed = NarInfo::parse(&serialized).expect("must parse");
+        assert_eq!(parsed.to_string(), serialized);
+        assert_eq!(
+            parsed.references,
+            vec![curl, zlib],
+            "references must be sorted"
+        );
+        assert_eq!(parsed.compression, Some("xz"));
+        assert_eq!(parsed.file_size, Some(68852));
+        assert_eq!(parsed.deriver, Some(deriver));
+
+        let verifying_key = VerifyingKey::parse(DUMMY_VERIFYING_KEY).unwrap();
+        assert_eq!(parsed.signatures.len(), 1);
+        assert!(
+            verifying_key.verify(&parsed.fingerprint(), &parsed.signatures[0]),
+            "signature must verify"
+        );
+    }
+}
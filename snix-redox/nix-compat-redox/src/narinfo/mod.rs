@@ -26,11 +26,13 @@ use std::{
 
 use crate::{nixbase32, nixhash::CAHash, store_path::StorePathRef};
 
+mod builder;
 mod fingerprint;
 mod signature;
 mod signing_keys;
 mod verifying_keys;
 
+pub use builder::NarInfoBuilder;
 pub use fingerprint::fingerprint;
 pub use signature::{Error as SignatureError, Signature, SignatureRef};
 pub use signing_keys::parse_keypair;
@@ -232,7 +234,7 @@ impl<'a> NarInfo<'a> {
                                 }
 
                                 StorePathRef::from_bytes(s.as_bytes())
-                                    .map_err(|err| Error::InvalidReference(i, err))
+                                    .map_err(|err| Error::InvalidReference(i, s.to_string(), err))
                             })
                             .collect::<Result<_, _>>()?
                     } else {
@@ -423,8 +425,8 @@ pub enum Error {
     #[error("invalid {0}: {1}")]
     UnableToParseSize(&'static str, String),
 
-    #[error("unable to parse #{0} reference: {1}")]
-    InvalidReference(usize, crate::store_path::Error),
+    #[error("unable to parse #{0} reference {1:?}: {2}")]
+    InvalidReference(usize, String, crate::store_path::Error),
 
     #[error("invalid Deriver store path: {0}")]
     InvalidDeriverStorePath(crate::store_path::Error),
@@ -463,7 +465,7 @@ mod test {
         store_path::StorePathRef,
     };
 
-    use super::{Flags, NarInfo};
+    use super::{Error, Flags, NarInfo};
 
     static CASES: LazyLock<&'static [&'static str]> = LazyLock::new(|| {
         let data = zstd::decode_all(io::Cursor::new(include_bytes!(
@@ -519,6 +521,47 @@ Sig: cache.nixos.org-1:o1DTsjCz0PofLJ216P2RBuSulI8BAb6zHxWE4N+tzlcELk5Uk/GO2SCxW
         );
     }
 
+    #[test]
+    fn references_valid() {
+        let parsed = NarInfo::parse(
+            r#"StorePath: /nix/store/k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+URL: nar/0i5biw0g01514llhfswxy6xfav8lxxdq1xg6ik7hgsqbpw0f06yi.nar.xz
+Compression: xz
+NarHash: sha256:0h1bm4sj1cnfkxgyhvgi8df1qavnnv94sd0v09wcrm971602shfg
+NarSize: 22552
+References: a8922c0h87iilxzzvwn2hmv8x210aqb9-glibc-2.7 k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+"#,
+        )
+        .expect("should parse");
+
+        assert_eq!(parsed.references.len(), 2);
+        assert_eq!(*parsed.references[0].name(), "glibc-2.7");
+        assert_eq!(parsed.references[1], parsed.store_path);
+    }
+
+    #[test]
+    fn references_garbage() {
+        let err = NarInfo::parse(
+            r#"StorePath: /nix/store/k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+URL: nar/0i5biw0g01514llhfswxy6xfav8lxxdq1xg6ik7hgsqbpw0f06yi.nar.xz
+Compression: xz
+NarHash: sha256:0h1bm4sj1cnfkxgyhvgi8df1qavnnv94sd0v09wcrm971602shfg
+NarSize: 22552
+References: a8922c0h87iilxzzvwn2hmv8x210aqb9-glibc-2.7 not-a-store-path
+"#,
+        )
+        .expect_err("must fail");
+
+        assert!(
+            matches!(
+                &err,
+                Error::InvalidReference(1, reference, _) if reference == "not-a-store-path"
+            ),
+            "{err:?}"
+        );
+        assert!(err.to_string().contains("\"not-a-store-path\""), "{err}");
+    }
+
     #[test]
     fn ca_nar_hash_sha1() {
         let parsed = NarInfo::parse(
@@ -542,6 +585,84 @@ CA: fixed:r:sha1:1ak1ymbmsfx7z8kh09jzkr3a4dvkrfjw
         );
     }
 
+    #[test]
+    fn ca_roundtrip() {
+        // Flat, recursive (NAR) and text content addresses all re-emit
+        // exactly as parsed.
+        for (ca_line, expected) in [
+            (
+                "fixed:sha256:0lxxfhy5fmfz0sbnqkqjdf7gx9gsxrfzz49n19y8sr93inawhshh",
+                CAHash::Flat(NixHash::Sha256(hex!(
+                    "106ac8958d23658d7c0a3691ff5deefaa5fe8e6b124f6c9706df55573c74bd53"
+                ))),
+            ),
+            (
+                "fixed:r:sha256:0lxxfhy5fmfz0sbnqkqjdf7gx9gsxrfzz49n19y8sr93inawhshh",
+                CAHash::Nar(NixHash::Sha256(hex!(
+                    "106ac8958d23658d7c0a3691ff5deefaa5fe8e6b124f6c9706df55573c74bd53"
+                ))),
+            ),
+            (
+                "text:sha256:0lxxfhy5fmfz0sbnqkqjdf7gx9gsxrfzz49n19y8sr93inawhshh",
+                CAHash::Text(hex!(
+                    "106ac8958d23658d7c0a3691ff5deefaa5fe8e6b124f6c9706df55573c74bd53"
+                )),
+            ),
+        ] {
+            let input = format!(
+                r#"StorePath: /nix/store/k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+URL: nar/0i5biw0g01514llhfswxy6xfav8lxxdq1xg6ik7hgsqbpw0f06yi.nar.xz
+Compression: xz
+FileHash: sha256:0i5biw0g01514llhfswxy6xfav8lxxdq1xg6ik7hgsqbpw0f06yi
+FileSize: 7120
+NarHash: sha256:0h1bm4sj1cnfkxgyhvgi8df1qavnnv94sd0v09wcrm971602shfg
+NarSize: 22552
+References: k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+Sig: cache.nixos.org-1:u01BybwQhyI5H1bW1EIWXssMDhDDIvXOG5uh8Qzgdyjz6U1qg6DHhMAvXZOUStIj6X5t4/ufFgR8i3fjf0bMAw==
+CA: {ca_line}
+"#
+            );
+            let parsed = NarInfo::parse(&input).expect("should parse");
+
+            let expected_nixbase32 = expected.to_nix_nixbase32_string();
+            assert_eq!(parsed.ca, Some(expected));
+            assert_eq!(expected_nixbase32, ca_line);
+            assert_eq!(parsed.to_string(), input, "should roundtrip");
+        }
+    }
+
+    #[test]
+    fn no_ca_roundtrip() {
+        let input = r#"StorePath: /nix/store/k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+URL: nar/0i5biw0g01514llhfswxy6xfav8lxxdq1xg6ik7hgsqbpw0f06yi.nar.xz
+Compression: xz
+FileHash: sha256:0i5biw0g01514llhfswxy6xfav8lxxdq1xg6ik7hgsqbpw0f06yi
+FileSize: 7120
+NarHash: sha256:0h1bm4sj1cnfkxgyhvgi8df1qavnnv94sd0v09wcrm971602shfg
+NarSize: 22552
+References: k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+Sig: cache.nixos.org-1:u01BybwQhyI5H1bW1EIWXssMDhDDIvXOG5uh8Qzgdyjz6U1qg6DHhMAvXZOUStIj6X5t4/ufFgR8i3fjf0bMAw==
+"#;
+        let parsed = NarInfo::parse(input).expect("should parse");
+
+        assert_eq!(parsed.ca, None);
+        assert_eq!(parsed.to_string(), input, "should roundtrip");
+    }
+
+    #[test]
+    fn ca_invalid() {
+        let input = r#"StorePath: /nix/store/k20pahypzvr49fy82cw5sx72hdfg3qcr-texlive-hyphenex-37354
+URL: nar/0i5biw0g01514llhfswxy6xfav8lxxdq1xg6ik7hgsqbpw0f06yi.nar.xz
+NarHash: sha256:0h1bm4sj1cnfkxgyhvgi8df1qavnnv94sd0v09wcrm971602shfg
+NarSize: 22552
+CA: fixed:r:blake3:0lxxfhy5fmfz0sbnqkqjdf7gx9gsxrfzz49n19y8sr93inawhshh
+"#;
+        assert!(matches!(
+            NarInfo::parse(input),
+            Err(super::Error::UnableToParseCA(_))
+        ));
+    }
+
     #[test]
     fn compression_default() {
         // This doesn't exist as such in cache.nixos.org.
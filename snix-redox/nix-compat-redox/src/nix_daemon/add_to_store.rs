@@ -0,0 +1,242 @@
+//! Server side of [Operation::AddToStore]: deriving the store path of an
+//! uploaded NAR from its name, content-address method and references.
+//!
+//! [Operation::AddToStore]: super::worker_protocol::Operation::AddToStore
+
+use std::io::{self, Cursor, ErrorKind};
+
+use data_encoding::HEXLOWER;
+use sha2::{Digest, Sha256, Sha512};
+
+use super::types::{AddToStoreRequest, UnkeyedValidPathInfo, ValidPathInfo};
+use crate::{
+    nar,
+    nixhash::{CAHash, HashAlgo, NixHash},
+    store_path::{StorePath, build_ca_path},
+};
+
+/// Content-address method and hash algorithm, as sent in `camStr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentAddressMethod {
+    /// `text:sha256`: the hash of a single file's contents, references allowed
+    Text,
+    /// `fixed:$algo`: the hash of a single file's contents
+    Flat(HashAlgo),
+    /// `fixed:r:$algo`: the hash of the NAR serialization
+    Nar(HashAlgo),
+}
+
+impl ContentAddressMethod {
+    /// Parses the `camStr` field, e.g. `fixed:r:sha256`.
+    pub fn parse(cam_str: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported content-address method '{cam_str}'"),
+            )
+        };
+
+        Ok(match cam_str.split_once(':').ok_or_else(invalid)? {
+            ("text", "sha256") => Self::Text,
+            ("fixed", rest) => match rest.strip_prefix("r:") {
+                Some(algo) => Self::Nar(algo.parse().map_err(|_| invalid())?),
+                None => Self::Flat(rest.parse().map_err(|_| invalid())?),
+            },
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+/// Derives the store path and path info of a NAR uploaded with `request`.
+///
+/// The NAR must be well-formed and fit the claimed method: `text` and flat
+/// `fixed` paths are a single regular file (non-executable for `text`).
+/// As in C++ Nix, only `text` and `fixed:r:sha256` paths may have
+/// references, and `request.name` must be a valid store path name.
+///
+/// `registration_time` is left at 0 for the [super::NixDaemonIO] to fill in.
+pub fn compute_path_info(
+    request: &AddToStoreRequest,
+    nar_bytes: &[u8],
+) -> io::Result<ValidPathInfo> {
+    let ca = match ContentAddressMethod::parse(&request.cam_str)? {
+        ContentAddressMethod::Text => {
+            CAHash::Text(Sha256::digest(single_file_contents(nar_bytes, false)?).into())
+        }
+        ContentAddressMethod::Flat(algo) => {
+            CAHash::Flat(hash_with(algo, &single_file_contents(nar_bytes, true)?)?)
+        }
+        ContentAddressMethod::Nar(algo) => {
+            let mut cursor = Cursor::new(nar_bytes);
+            nar::copy(&mut cursor, &mut io::sink())?;
+            ensure_consumed(&cursor)?;
+            CAHash::Nar(hash_with(algo, nar_bytes)?)
+        }
+    };
+
+    let path: StorePath<String> = build_ca_path(
+        &request.name,
+        &ca,
+        request.references.iter().map(|r| r.to_absolute_path()),
+        false,
+    )
+    .map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("{}: {e}", request.name)))?;
+
+    Ok(ValidPathInfo {
+        path,
+        info: UnkeyedValidPathInfo {
+            deriver: None,
+            nar_hash: HEXLOWER.encode(&Sha256::digest(nar_bytes)),
+            references: request.references.clone(),
+            registration_time: 0,
+            nar_size: nar_bytes.len() as u64,
+            ultimate: true,
+            signatures: vec![],
+            ca: Some(ca),
+        },
+    })
+}
+
+/// Returns the contents of a NAR whose root is a single regular file.
+fn single_file_contents(nar_bytes: &[u8], allow_executable: bool) -> io::Result<Vec<u8>> {
+    let mut cursor = Cursor::new(nar_bytes);
+    let contents = match nar::reader::open(&mut cursor)? {
+        nar::reader::Node::File {
+            executable,
+            mut reader,
+        } if allow_executable || !executable => {
+            let mut contents = Vec::with_capacity(reader.len() as usize);
+            reader.copy(&mut contents)?;
+            contents
+        }
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "content-address method requires a single regular file",
+            ));
+        }
+    };
+    ensure_consumed(&cursor)?;
+    Ok(contents)
+}
+
+/// Rejects trailing bytes after the end of the NAR.
+fn ensure_consumed(cursor: &Cursor<&[u8]>) -> io::Result<()> {
+    if cursor.position() != cursor.get_ref().len() as u64 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "trailing data after NAR",
+        ));
+    }
+    Ok(())
+}
+
+fn hash_with(algo: HashAlgo, data: &[u8]) -> io::Result<NixHash> {
+    let digest = match algo {
+        HashAlgo::Sha256 => Sha256::digest(data).to_vec(),
+        HashAlgo::Sha512 => Sha512::digest(data).to_vec(),
+        HashAlgo::Md5 | HashAlgo::Sha1 => {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!("{algo} is not supported for AddToStore"),
+            ));
+        }
+    };
+    NixHash::from_algo_and_digest(algo, &digest).map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const HELLOWORLD_NAR: &[u8] = include_bytes!("../nar/tests/helloworld.nar");
+
+    fn request(name: &str, cam_str: &str, references: Vec<StorePath<String>>) -> AddToStoreRequest {
+        AddToStoreRequest {
+            name: name.to_owned(),
+            cam_str: cam_str.to_owned(),
+            references,
+            repair: false,
+        }
+    }
+
+    #[rstest]
+    #[case::text("text:sha256", ContentAddressMethod::Text)]
+    #[case::flat("fixed:sha256", ContentAddressMethod::Flat(HashAlgo::Sha256))]
+    #[case::nar("fixed:r:sha512", ContentAddressMethod::Nar(HashAlgo::Sha512))]
+    fn parse_method(#[case] cam_str: &str, #[case] expected: ContentAddressMethod) {
+        assert_eq!(expected, ContentAddressMethod::parse(cam_str).unwrap());
+    }
+
+    #[rstest]
+    #[case::text_sha1("text:sha1")]
+    #[case::git("fixed:git:sha1")]
+    #[case::no_algo("fixed")]
+    #[case::unknown("nar:sha256")]
+    fn parse_method_invalid(#[case] cam_str: &str) {
+        assert!(ContentAddressMethod::parse(cam_str).is_err());
+    }
+
+    #[rstest]
+    #[case::text("text:sha256", "/nix/store/r4mvpxzh7rgrm4j831b2yi90zq64grqm-hello.txt")]
+    #[case::flat("fixed:sha256", "/nix/store/z304n4ccbya3lf8bq20sl2zhmzd3lx1a-hello.txt")]
+    #[case::nar("fixed:r:sha256", "/nix/store/925f1jb1ajrypjbyq7rylwryqwizvhp0-hello.txt")]
+    fn computes_store_path(#[case] cam_str: &str, #[case] expected: &str) {
+        let info = compute_path_info(&request("hello.txt", cam_str, vec![]), HELLOWORLD_NAR)
+            .expect("must compute path info");
+
+        assert_eq!(expected, info.path.to_absolute_path());
+        assert_eq!(
+            "03e7f63be30b065d78bcf615f5473545fdb4eb69aa416f43495b4e05cdfb8040",
+            info.info.nar_hash
+        );
+        assert_eq!(HELLOWORLD_NAR.len() as u64, info.info.nar_size);
+        let ca = info.info.ca.expect("must be content-addressed");
+        assert!(ca.to_nix_nixbase32_string().starts_with(cam_str));
+    }
+
+    #[test]
+    fn rejects_directory_for_text() {
+        let mut nar = Vec::new();
+        nar::writer::open(&mut nar)
+            .unwrap()
+            .directory()
+            .unwrap()
+            .close()
+            .unwrap();
+
+        let err = compute_path_info(&request("hello.txt", "text:sha256", vec![]), &nar)
+            .expect_err("a directory is not a text file");
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn rejects_references_for_flat() {
+        let reference = StorePath::<String>::from_absolute_path(
+            b"/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello-1.0",
+        )
+        .unwrap();
+
+        compute_path_info(
+            &request("hello.txt", "fixed:sha256", vec![reference]),
+            HELLOWORLD_NAR,
+        )
+        .expect_err("flat paths cannot have references");
+    }
+
+    #[test]
+    fn rejects_invalid_name() {
+        compute_path_info(&request("hello/world", "fixed:r:sha256", vec![]), HELLOWORLD_NAR)
+            .expect_err("'/' is not allowed in a store path name");
+    }
+
+    #[test]
+    fn rejects_trailing_data() {
+        let mut nar = HELLOWORLD_NAR.to_vec();
+        nar.extend_from_slice(&[0; 8]);
+
+        compute_path_info(&request("hello.txt", "fixed:r:sha256", vec![]), &nar)
+            .expect_err("trailing data must be rejected");
+    }
+}
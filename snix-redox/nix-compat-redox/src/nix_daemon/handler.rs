@@ -8,9 +8,9 @@ use tokio::{
 use tracing::{debug, warn};
 
 use super::{
-    NixDaemonIO,
+    NixDaemonIO, add_to_store,
     framing::{NixFramedReader, StderrReadFramedReader},
-    types::{AddToStoreNarRequest, QueryValidPaths},
+    types::{AddToStoreNarRequest, AddToStoreRequest, QueryValidPaths},
     worker_protocol::{ClientSettings, Operation, STDERR_LAST, Trust, server_handshake_client},
 };
 
@@ -126,6 +126,31 @@ where
                         let path: StorePath<String> = self.reader.read_value().await?;
                         Self::handle(&self.writer, io.is_valid_path(&path)).await?
                     }
+                    Operation::AddToStore => {
+                        // Older clients send a different request layout followed by an
+                        // unframed NAR, which can only be delimited by parsing it.
+                        if self.protocol_version.minor() < 25 {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::Unsupported,
+                                "AddToStore requires protocol version 1.25 or newer",
+                            ));
+                        }
+                        let request: AddToStoreRequest = self.reader.read_value().await?;
+
+                        // The NAR is Framed, see serialization.md#framed. It is read in
+                        // full before hashing, so a rejected path still leaves the
+                        // connection in sync.
+                        let mut nar = Vec::new();
+                        NixFramedReader::new(&mut self.reader)
+                            .read_to_end(&mut nar)
+                            .await?;
+
+                        Self::handle(&self.writer, async {
+                            let info = add_to_store::compute_path_info(&request, &nar)?;
+                            self.io.add_to_store(info, &nar).await
+                        })
+                        .await?
+                    }
                     // Note this operation does not currently delegate to NixDaemonIO,
                     // The general idea is that we will pass relevant ClientSettings
                     // into individual NixDaemonIO method calls if the need arises.
@@ -274,7 +299,7 @@ mod tests {
     use tokio::io::AsyncWriteExt;
 
     use crate::{
-        nix_daemon::MockNixDaemonIO,
+        nix_daemon::{MockNixDaemonIO, types::ValidPathInfo},
         wire::ProtocolVersion,
         worker_protocol::{ClientSettings, WORKER_MAGIC_1, WORKER_MAGIC_2},
     };
@@ -439,4 +464,121 @@ mod tests {
                 .kind()
         );
     }
+
+    /// Encodes an AddToStore request, followed by `nar` as a single frame.
+    async fn add_to_store_input(
+        request: &AddToStoreRequest,
+        nar: &[u8],
+        version: ProtocolVersion,
+    ) -> Vec<u8> {
+        let mut input = Into::<u64>::into(Operation::AddToStore).to_le_bytes().to_vec();
+        input.extend(serialize(request, version).await);
+        input.extend((nar.len() as u64).to_le_bytes());
+        input.extend(nar);
+        input.extend(0u64.to_le_bytes());
+        input
+    }
+
+    #[tokio::test]
+    async fn test_handle_add_to_store_ok() {
+        let version = ProtocolVersion::from_parts(1, 37);
+        let (io, mut handle) = tokio_test::io::Builder::new().build_with_handle();
+        let mut mock = MockNixDaemonIO::new();
+        let (reader, writer) = split(io);
+
+        let nar = include_bytes!("../nar/tests/helloworld.nar");
+        let request = AddToStoreRequest {
+            name: "hello.txt".to_owned(),
+            cam_str: "fixed:r:sha256".to_owned(),
+            references: vec![],
+            repair: false,
+        };
+        let expected = add_to_store::compute_path_info(&request, nar).unwrap();
+        assert_eq!(
+            "/nix/store/925f1jb1ajrypjbyq7rylwryqwizvhp0-hello.txt",
+            expected.path.to_absolute_path()
+        );
+
+        mock.expect_add_to_store()
+            .withf({
+                let expected = expected.clone();
+                move |info, uploaded| *info == expected && uploaded == &nar[..]
+            })
+            .times(1)
+            .returning(|info, _| Box::pin(async { Ok(info) }));
+
+        handle.read(&add_to_store_input(&request, nar, version).await);
+        handle.write(&respond(&Ok(expected), version).await);
+        drop(handle);
+
+        let mut daemon = NixDaemon::new(
+            Arc::new(mock),
+            version,
+            ClientSettings::default(),
+            NixReader::new(reader),
+            NixWriter::new(writer),
+        );
+        assert_eq!(
+            ErrorKind::UnexpectedEof,
+            daemon
+                .handle_client()
+                .await
+                .expect_err("Expecting eof")
+                .kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_add_to_store_mismatched_method() {
+        let version = ProtocolVersion::from_parts(1, 37);
+        let (io, mut handle) = tokio_test::io::Builder::new().build_with_handle();
+        // No expectation: the path must not be registered.
+        let mock = MockNixDaemonIO::new();
+        let (reader, writer) = split(io);
+
+        // A directory claimed as a text file.
+        let mut nar = Vec::new();
+        crate::nar::writer::open(&mut nar)
+            .unwrap()
+            .directory()
+            .unwrap()
+            .close()
+            .unwrap();
+        let request = AddToStoreRequest {
+            name: "hello.txt".to_owned(),
+            cam_str: "text:sha256".to_owned(),
+            references: vec![],
+            repair: false,
+        };
+
+        handle.read(&add_to_store_input(&request, &nar, version).await);
+        handle.write(
+            &respond::<ValidPathInfo>(
+                &Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "content-address method requires a single regular file",
+                )),
+                version,
+            )
+            .await,
+        );
+        drop(handle);
+
+        let mut daemon = NixDaemon::new(
+            Arc::new(mock),
+            version,
+            ClientSettings::default(),
+            NixReader::new(reader),
+            NixWriter::new(writer),
+        );
+        // The framed NAR was consumed, so the connection stays usable until EOF.
+        assert_eq!(
+            ErrorKind::UnexpectedEof,
+            daemon
+                .handle_client()
+                .await
+                .expect_err("Expecting eof")
+                .kind()
+        );
+    }
 }
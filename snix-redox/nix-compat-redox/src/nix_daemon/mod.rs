@@ -4,10 +4,11 @@ use std::io::Result;
 
 use tokio::io::AsyncRead;
 use tracing::warn;
-use types::{AddToStoreNarRequest, QueryValidPaths, UnkeyedValidPathInfo};
+use types::{AddToStoreNarRequest, QueryValidPaths, UnkeyedValidPathInfo, ValidPathInfo};
 
 use crate::store_path::StorePath;
 
+pub mod add_to_store;
 pub mod framing;
 pub mod handler;
 pub mod types;
@@ -75,6 +76,27 @@ pub trait NixDaemonIO: Sync {
     ) -> impl std::future::Future<Output = Result<()>> + Send
     where
         R: AsyncRead + Send + Unpin;
+
+    /// Registers a path uploaded with [worker_protocol::Operation::AddToStore].
+    ///
+    /// `info` has already been derived from `nar`, the path's NAR
+    /// serialization, by [add_to_store::compute_path_info]. Returns the
+    /// info as registered, e.g. with `registration_time` filled in.
+    ///
+    /// Stores that don't accept uploads can leave this out; the client
+    /// is then told the operation is unsupported.
+    fn add_to_store(
+        &self,
+        _info: ValidPathInfo,
+        _nar: &[u8],
+    ) -> impl std::future::Future<Output = Result<ValidPathInfo>> + Send {
+        async move {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "this store does not accept AddToStore",
+            ))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -231,4 +253,33 @@ mod tests {
             .expect("expected to get a non-empty response");
         assert_eq!(result, vec![deriver], "expected to get non empty response");
     }
+
+    #[tokio::test]
+    async fn test_add_to_store_is_unsupported_by_default() {
+        let path =
+            StorePath::<String>::from_bytes("z6r3bn5l51679pwkvh9nalp6c317z34m-hello".as_bytes())
+                .unwrap();
+        let io = MockNixDaemonIO {
+            query_path_info_result: None,
+        };
+
+        let info = super::types::ValidPathInfo {
+            path,
+            info: UnkeyedValidPathInfo {
+                deriver: None,
+                nar_hash: "".to_owned(),
+                references: vec![],
+                registration_time: 0,
+                nar_size: 1,
+                ultimate: true,
+                signatures: vec![],
+                ca: None,
+            },
+        };
+        let err = io
+            .add_to_store(info, &[])
+            .await
+            .expect_err("expected the default to refuse the upload");
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
 }
@@ -9,7 +9,8 @@ use crate::{
     },
 };
 use nix_compat_derive::{NixDeserialize, NixSerialize};
-use std::future::Future;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::{collections::BTreeMap, future::Future, time::Duration};
 
 /// Marker type that consumes/sends and ignores a u64.
 #[derive(Clone, Debug, NixDeserialize, NixSerialize)]
@@ -238,7 +239,7 @@ pub struct UnkeyedValidPathInfo {
 }
 
 /// Request tuple for [super::worker_protocol::Operation::QueryValidPaths]
-#[derive(NixDeserialize)]
+#[derive(NixDeserialize, NixSerialize)]
 pub struct QueryValidPaths {
     // Paths to query
     pub paths: Vec<StorePath<String>>,
@@ -304,3 +305,343 @@ pub struct AddToStoreNarRequest {
     // - dontCheckSigs :: [Bool64][se-Bool64]
     pub dont_check_sigs: bool,
 }
+
+/// Request type for [super::worker_protocol::Operation::AddToStore]
+///
+/// This is the layout used since protocol version 1.25. The NAR follows
+/// the request as a [Framed][se-Framed] stream.
+#[derive(NixDeserialize, NixSerialize, Debug)]
+pub struct AddToStoreRequest {
+    // - name :: [String][se-String]
+    pub name: String,
+    // - camStr :: [ContentAddressMethodWithAlgo][se-ContentAddressMethodWithAlgo]
+    pub cam_str: String,
+    // - references :: [Set][se-Set] of [StorePath][se-StorePath]
+    pub references: Vec<StorePath<String>>,
+    // - repair :: [Bool64][se-Bool64]
+    pub repair: bool,
+}
+
+/// Response type for [super::worker_protocol::Operation::AddToStore]
+#[derive(NixSerialize, Debug, Clone, PartialEq)]
+pub struct ValidPathInfo {
+    pub path: StorePath<String>,
+    pub info: UnkeyedValidPathInfo,
+}
+
+/// Status of a [BuildResult], as in C++ Nix's `BuildResult::Status`.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive, IntoPrimitive, NixDeserialize, NixSerialize,
+)]
+#[nix(try_from = "u64", into = "u64")]
+#[repr(u64)]
+pub enum BuildStatus {
+    Built = 0,
+    Substituted = 1,
+    AlreadyValid = 2,
+    PermanentFailure = 3,
+    InputRejected = 4,
+    OutputRejected = 5,
+    /// Possibly transient; retrying may succeed.
+    TransientFailure = 6,
+    /// No longer used by C++ Nix.
+    CachedFailure = 7,
+    TimedOut = 8,
+    MiscFailure = 9,
+    DependencyFailed = 10,
+    LogLimitExceeded = 11,
+    NotDeterministic = 12,
+    ResolvesToAlreadyValid = 13,
+    NoSubstituters = 14,
+}
+
+impl BuildStatus {
+    /// Whether the outputs are valid afterwards.
+    pub fn is_success(self) -> bool {
+        matches!(
+            self,
+            Self::Built | Self::Substituted | Self::AlreadyValid | Self::ResolvesToAlreadyValid
+        )
+    }
+}
+
+/// `std::optional<std::chrono::microseconds>`: a `0`/`1` tag, then the
+/// number of microseconds if the tag is `1`.
+impl NixDeserialize for Option<Duration> {
+    async fn try_deserialize<R>(reader: &mut R) -> Result<Option<Self>, R::Error>
+    where
+        R: ?Sized + NixRead + Send,
+    {
+        match reader.try_read_number().await? {
+            None => Ok(None),
+            Some(0) => Ok(Some(None)),
+            Some(1) => {
+                let micros: u64 = reader.read_value().await?;
+                Ok(Some(Some(Duration::from_micros(micros))))
+            }
+            Some(tag) => Err(R::Error::invalid_data(format!(
+                "invalid optional duration tag {tag}"
+            ))),
+        }
+    }
+}
+
+impl NixSerialize for Option<Duration> {
+    async fn serialize<W>(&self, writer: &mut W) -> Result<(), W::Error>
+    where
+        W: NixWrite,
+    {
+        match self {
+            Some(duration) => {
+                writer.write_value(&1u64).await?;
+                writer.write_value(&(duration.as_micros() as u64)).await
+            }
+            None => writer.write_value(&0u64).await,
+        }
+    }
+}
+
+/// Response type for [super::worker_protocol::Operation::BuildDerivation],
+/// and the per-path result of
+/// [super::worker_protocol::Operation::BuildPathsWithResults].
+///
+/// Fields introduced by later protocol versions are defaulted when talking
+/// to older peers.
+#[derive(NixDeserialize, NixSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildResult {
+    pub status: BuildStatus,
+    /// Empty unless the build failed.
+    pub error_msg: String,
+    #[nix(version = "29..")]
+    pub times_built: u64,
+    #[nix(version = "29..")]
+    pub is_non_deterministic: bool,
+    /// Seconds since the epoch.
+    #[nix(version = "29..")]
+    pub start_time: u64,
+    /// Seconds since the epoch.
+    #[nix(version = "29..")]
+    pub stop_time: u64,
+    #[nix(version = "37..")]
+    pub cpu_user: Option<Duration>,
+    #[nix(version = "37..")]
+    pub cpu_system: Option<Duration>,
+    /// `DrvOutput` id (`sha256:<hash>!<output>`) to the `Realisation`
+    /// of that output, as JSON.
+    #[nix(version = "28..")]
+    pub built_outputs: BTreeMap<String, String>,
+}
+
+/// Element of the [super::worker_protocol::Operation::BuildPathsWithResults]
+/// response.
+#[derive(NixDeserialize, NixSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyedBuildResult {
+    /// The `DerivedPath` that was built, e.g. `/nix/store/…-hello.drv^out`.
+    pub path: String,
+    pub result: BuildResult,
+}
+
+/// Request type for [super::worker_protocol::Operation::QueryMissing]
+#[derive(NixDeserialize, NixSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct QueryMissingRequest {
+    /// The `DerivedPath`s to realise, e.g. `/nix/store/…-hello.drv^out`
+    /// or a plain store path.
+    pub targets: Vec<String>,
+}
+
+/// Response type for [super::worker_protocol::Operation::QueryMissing]:
+/// what realising the requested paths would take.
+#[derive(NixDeserialize, NixSerialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryMissingResponse {
+    /// Derivations that would be built.
+    pub will_build: Vec<StorePath<String>>,
+    /// Paths that would be fetched from a substituter.
+    pub will_substitute: Vec<StorePath<String>>,
+    /// Paths that can neither be built nor substituted.
+    pub unknown: Vec<StorePath<String>>,
+    /// Bytes to download for the substituted paths, compressed.
+    pub download_size: u64,
+    /// Unpacked NAR size of the substituted paths.
+    pub nar_size: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use rstest::rstest;
+    use tokio::io::AsyncWriteExt;
+    use tokio_test::io::Builder;
+
+    use super::*;
+    use crate::wire::{
+        ProtocolVersion,
+        de::{NixRead, NixReader},
+        ser::{NixWrite, NixWriter},
+    };
+
+    /// BuildDerivation reply for a successful build, protocol 1.37.
+    const BUILT: [u8; 136] = hex!(
+        "0000 0000 0000 0000" // status: Built
+        "0000 0000 0000 0000" // errorMsg: ""
+        "0100 0000 0000 0000" // timesBuilt: 1
+        "0000 0000 0000 0000" // isNonDeterministic: false
+        "6400 0000 0000 0000" // startTime: 100
+        "c800 0000 0000 0000" // stopTime: 200
+        "0100 0000 0000 0000 dc05 0000 0000 0000" // cpuUser: 1500us
+        "0000 0000 0000 0000" // cpuSystem: none
+        "0100 0000 0000 0000" // builtOutputs: 1 entry
+        "1100 0000 0000 0000 7368 6132 3536 3a31 6230 6539 7221 6f75 7400 0000 0000 0000" // "sha256:1b0e9r!out"
+        "0f00 0000 0000 0000 7b22 6f75 7450 6174 6822 3a22 7822 7d00" // {"outPath":"x"}
+    );
+
+    /// BuildDerivation reply for a failed build, protocol 1.28.
+    const FAILED: [u8; 128] = hex!(
+        "0300 0000 0000 0000" // status: PermanentFailure
+        "6200 0000 0000 0000 6275 696c 6465 7220" // errorMsg
+        "666f 7220 272f 6e69 782f 7374 6f72 652f"
+        "6968 3670 6337 7831 6270 7769 7172 7363"
+        "7973 7871 6d38 6c64 366d 6737 7663 7a35"
+        "2d68 656c 6c6f 2d32 2e31 322e 312e 6472"
+        "7627 2066 6169 6c65 6420 7769 7468 2065"
+        "7869 7420 636f 6465 2031 0000 0000 0000"
+        "0000 0000 0000 0000" // builtOutputs: none
+    );
+
+    /// QueryMissing request for `hello.drv^out`.
+    const QUERY_MISSING: [u8; 80] = hex!(
+        "0100 0000 0000 0000" // targets: 1 entry
+        "4000 0000 0000 0000 2f6e 6978 2f73 746f 7265 2f69 6836 7063" // ".../hello-2.12.1.drv^out"
+        "3778 3162 7077 6971 7273 6379 7378 716d 386c 6436 6d67 3776"
+        "637a 352d 6865 6c6c 6f2d 322e 3132 2e31 2e64 7276 5e6f 7574"
+    );
+
+    /// QueryMissing reply in the protocol 1.37 layout, encoded by hand.
+    const MISSING: [u8; 184] = hex!(
+        "0100 0000 0000 0000" // willBuild: 1 entry
+        "3c00 0000 0000 0000 2f6e 6978 2f73 746f 7265 2f69 6836 7063" // ".../hello-2.12.1.drv"
+        "3778 3162 7077 6971 7273 6379 7378 716d 386c 6436 6d67 3776"
+        "637a 352d 6865 6c6c 6f2d 322e 3132 2e31 2e64 7276 0000 0000"
+        "0100 0000 0000 0000" // willSubstitute: 1 entry
+        "3900 0000 0000 0000 2f6e 6978 2f73 746f 7265 2f30 3062 6764" // ".../libidn2-2.3.7"
+        "3034 357a 3064 3469 6370 6263 3279 797a 3467 7834 3861 6b34"
+        "346c 612d 6c69 6269 646e 322d 322e 332e 3700 0000 0000 0000"
+        "0000 0000 0000 0000" // unknown: none
+        "40e2 0100 0000 0000" // downloadSize: 123456
+        "55f8 0600 0000 0000" // narSize: 456789
+    );
+
+    const HELLO_DRV: &str = "/nix/store/ih6pc7x1bpwiqrscysxqm8ld6mg7vcz5-hello-2.12.1.drv";
+
+    fn built() -> BuildResult {
+        BuildResult {
+            status: BuildStatus::Built,
+            error_msg: String::new(),
+            times_built: 1,
+            is_non_deterministic: false,
+            start_time: 100,
+            stop_time: 200,
+            cpu_user: Some(Duration::from_micros(1500)),
+            cpu_system: None,
+            built_outputs: BTreeMap::from([(
+                "sha256:1b0e9r!out".to_string(),
+                r#"{"outPath":"x"}"#.to_string(),
+            )]),
+        }
+    }
+
+    fn failed() -> BuildResult {
+        BuildResult {
+            status: BuildStatus::PermanentFailure,
+            error_msg: "builder for '/nix/store/ih6pc7x1bpwiqrscysxqm8ld6mg7vcz5-hello-2.12.1.drv' failed with exit code 1".to_string(),
+            times_built: 0,
+            is_non_deterministic: false,
+            start_time: 0,
+            stop_time: 0,
+            cpu_user: None,
+            cpu_system: None,
+            built_outputs: BTreeMap::new(),
+        }
+    }
+
+    #[rstest]
+    #[case::built(37, &BUILT, built())]
+    #[case::failed(28, &FAILED, failed())]
+    #[tokio::test]
+    async fn build_result_roundtrip(
+        #[case] minor: u8,
+        #[case] data: &[u8],
+        #[case] expected: BuildResult,
+    ) {
+        let version = ProtocolVersion::from_parts(1, minor);
+
+        let mock = Builder::new().read(data).build();
+        let mut reader = NixReader::builder().set_version(version).build(mock);
+        let actual: BuildResult = reader.read_value().await.unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(
+            actual.status.is_success(),
+            expected.status == BuildStatus::Built
+        );
+
+        let mock = Builder::new().write(data).build();
+        let mut writer = NixWriter::builder().set_version(version).build(mock);
+        writer.write_value(&expected).await.unwrap();
+        writer.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn query_missing_request() {
+        let request = QueryMissingRequest {
+            targets: vec![format!("{HELLO_DRV}^out")],
+        };
+
+        let mock = Builder::new().write(&QUERY_MISSING).build();
+        let mut writer = NixWriter::new(mock);
+        writer.write_value(&request).await.unwrap();
+        writer.flush().await.unwrap();
+
+        let mock = Builder::new().read(&QUERY_MISSING).build();
+        let mut reader = NixReader::new(mock);
+        assert_eq!(
+            reader.read_value::<QueryMissingRequest>().await.unwrap(),
+            request
+        );
+    }
+
+    #[tokio::test]
+    async fn query_missing_response_roundtrip() {
+        let version = ProtocolVersion::from_parts(1, 37);
+        let expected = QueryMissingResponse {
+            will_build: vec![StorePath::from_absolute_path(HELLO_DRV.as_bytes()).unwrap()],
+            will_substitute: vec![
+                StorePath::from_absolute_path(
+                    b"/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-libidn2-2.3.7",
+                )
+                .unwrap(),
+            ],
+            unknown: vec![],
+            download_size: 123456,
+            nar_size: 456789,
+        };
+
+        let mock = Builder::new().read(&MISSING).build();
+        let mut reader = NixReader::builder().set_version(version).build(mock);
+        let actual: QueryMissingResponse = reader.read_value().await.unwrap();
+        assert_eq!(actual, expected);
+
+        let mock = Builder::new().write(&MISSING).build();
+        let mut writer = NixWriter::builder().set_version(version).build(mock);
+        writer.write_value(&expected).await.unwrap();
+        writer.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unknown_build_status() {
+        let mock = Builder::new().read(&hex!("0f00 0000 0000 0000")).build();
+        let mut reader = NixReader::new(mock);
+        reader
+            .read_value::<BuildStatus>()
+            .await
+            .expect_err("15 is not a build status");
+    }
+}
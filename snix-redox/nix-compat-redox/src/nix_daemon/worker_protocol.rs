@@ -11,6 +11,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use crate::{log::VerbosityLevel, wire};
 
 use crate::wire::ProtocolVersion;
+use crate::wire::ser::{NixSerialize, NixWrite};
 
 pub(crate) static WORKER_MAGIC_1: u64 = 0x6e697863; // "nixc"
 pub(crate) static WORKER_MAGIC_2: u64 = 0x6478696f; // "dxio"
@@ -39,7 +40,10 @@ pub(crate) static STDERR_READ: u64 = 0x64617461; // "data"
 /// | 2.8.0 - 2.14.1  | 1.34     |
 /// | 2.15.0 - 2.19.4 | 1.35     |
 /// | 2.20.0 - 2.22.0 | 1.37     |
-static PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::from_parts(1, 37);
+pub static PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::from_parts(1, 37);
+
+/// Oldest peer protocol version we talk to (Nix 1.0).
+pub static MIN_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::from_parts(1, 10);
 
 /// Max length of a Nix setting name/value. In bytes.
 ///
@@ -179,7 +183,7 @@ where
         let client_version: ProtocolVersion = client_version
             .try_into()
             .map_err(|e| Error::new(ErrorKind::Unsupported, e))?;
-        if client_version < ProtocolVersion::from_parts(1, 10) {
+        if client_version < MIN_PROTOCOL_VERSION {
             return Err(Error::new(
                 ErrorKind::Unsupported,
                 format!("The nix client version {client_version} is too old"),
@@ -209,6 +213,102 @@ where
     }
 }
 
+/// What a daemon announced during [client_handshake_server].
+#[derive(Debug, PartialEq)]
+pub struct ServerInfo {
+    /// The protocol version to use for further comms,
+    /// min(daemon_version, our_version). Readers and writers for the
+    /// connection must be built with it, see [write_request].
+    pub version: ProtocolVersion,
+    /// Semantic version of the daemon's Nix, sent since 1.33.
+    pub nix_version: Option<String>,
+    /// Whether the daemon trusts us, sent since 1.35. Daemons may also
+    /// leave it unspecified.
+    pub trust: Option<Trust>,
+}
+
+/// Performs the initial handshake with a Nix daemon, as a client.
+///
+/// This is the counterpart of [server_handshake_client]: after exchanging
+/// magic numbers, both sides send their protocol version and from then on
+/// use the older of the two. What follows depends on that version, so
+/// daemons back to Nix 1.0 are understood.
+///
+/// The daemon then starts sending log messages, terminated by
+/// [STDERR_LAST], which the caller must read before sending an operation.
+pub async fn client_handshake_server<'a, RW: 'a>(
+    mut conn: &'a mut RW,
+) -> std::io::Result<ServerInfo>
+where
+    &'a mut RW: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    conn.write_u64_le(WORKER_MAGIC_1).await?;
+    conn.flush().await?;
+    let worker_magic_2 = conn.read_u64_le().await?;
+    if worker_magic_2 != WORKER_MAGIC_2 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Incorrect worker magic number received: {worker_magic_2}"),
+        ));
+    }
+
+    let server_version: ProtocolVersion = conn
+        .read_u64_le()
+        .await?
+        .try_into()
+        .map_err(|e| Error::new(ErrorKind::Unsupported, e))?;
+    if server_version < MIN_PROTOCOL_VERSION {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("The nix daemon version {server_version} is too old"),
+        ));
+    }
+    conn.write_u64_le(PROTOCOL_VERSION.into()).await?;
+
+    let picked_version = min(PROTOCOL_VERSION, server_version);
+    if picked_version.minor() >= 14 {
+        // Obsolete CPU affinity: none.
+        conn.write_u64_le(0).await?;
+    }
+    if picked_version.minor() >= 11 {
+        // Obsolete reserveSpace
+        conn.write_u64_le(0).await?;
+    }
+    conn.flush().await?;
+
+    let nix_version = if picked_version.minor() >= 33 {
+        Some(wire::read_string(&mut conn, 0..=MAX_SETTING_SIZE).await?)
+    } else {
+        None
+    };
+    let trust = if picked_version.minor() >= 35 {
+        read_worker_trust_level(&mut conn).await?
+    } else {
+        None
+    };
+
+    Ok(ServerInfo {
+        version: picked_version,
+        nix_version,
+        trust,
+    })
+}
+
+/// Write a worker [Operation] followed by its request.
+///
+/// Fields of `request` are encoded for the version `writer` was built
+/// with, so fields a daemon doesn't know yet are left out. For instance
+/// [QueryValidPaths](super::types::QueryValidPaths) only carries its
+/// `substitute` flag since 1.27.
+pub async fn write_request<W, T>(writer: &mut W, op: Operation, request: &T) -> Result<(), W::Error>
+where
+    W: NixWrite,
+    T: NixSerialize + Send + Sync,
+{
+    writer.write_value(&op).await?;
+    writer.write_value(request).await
+}
+
 /// Read a worker [Operation] from the wire.
 pub async fn read_op<R: AsyncReadExt + Unpin>(r: &mut R) -> std::io::Result<Operation> {
     let op_number = r.read_u64_le().await?;
@@ -221,9 +321,13 @@ pub async fn read_op<R: AsyncReadExt + Unpin>(r: &mut R) -> std::io::Result<Oper
 }
 
 /// Write a worker [Operation] to the wire.
+///
+/// Like every integer in the protocol, and like [read_op] expects, the op
+/// is a little-endian u64. (This used to write big-endian, which no daemon
+/// understands: IsValidPath went out as op 2^56.)
 pub async fn write_op<W: AsyncWriteExt + Unpin>(w: &mut W, op: Operation) -> std::io::Result<()> {
     let op: u64 = op.into();
-    w.write_u64(op).await
+    w.write_u64_le(op).await
 }
 
 #[derive(Debug, PartialEq)]
@@ -248,9 +352,32 @@ where
     }
 }
 
+/// Read the worker [Trust] level from the wire. `None` means the daemon
+/// didn't say.
+pub async fn read_worker_trust_level<R>(conn: &mut R) -> std::io::Result<Option<Trust>>
+where
+    R: AsyncReadExt + Unpin,
+{
+    match conn.read_u64_le().await? {
+        0 => Ok(None),
+        1 => Ok(Some(Trust::Trusted)),
+        2 => Ok(Some(Trust::NotTrusted)),
+        t => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid trust level {t}"),
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use hex_literal::hex;
+    use rstest::rstest;
+
     use super::*;
+    use crate::nix_daemon::types::QueryValidPaths;
+    use crate::store_path::StorePath;
+    use crate::wire::ser::NixWriter;
 
     #[tokio::test]
     async fn test_init_hanshake() {
@@ -331,4 +458,116 @@ mod tests {
 
         assert_eq!(picked_version, ProtocolVersion::from_parts(1, 24))
     }
+
+    #[tokio::test]
+    async fn client_handshake_with_current_daemon() {
+        let mut test_conn = tokio_test::io::Builder::new()
+            .write(&WORKER_MAGIC_1.to_le_bytes())
+            .read(&WORKER_MAGIC_2.to_le_bytes())
+            .read(&[37, 1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            .write(&[37, 1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            // cpu affinity
+            .write(&[0; 8])
+            // reservespace
+            .write(&[0; 8])
+            // version (size)
+            .read(&[0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            // version (data == 2.18.2 + padding)
+            .read(&[50, 46, 49, 56, 46, 50, 0, 0])
+            // Not trusted
+            .read(&[2, 0, 0, 0, 0, 0, 0, 0])
+            .build();
+
+        assert_eq!(
+            client_handshake_server(&mut test_conn).await.unwrap(),
+            ServerInfo {
+                version: PROTOCOL_VERSION,
+                nix_version: Some("2.18.2".to_string()),
+                trust: Some(Trust::NotTrusted),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn client_handshake_with_older_daemon() {
+        let mut test_conn = tokio_test::io::Builder::new()
+            .write(&WORKER_MAGIC_1.to_le_bytes())
+            .read(&WORKER_MAGIC_2.to_le_bytes())
+            // Nix 2.3
+            .read(&[21, 1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            .write(&[37, 1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            // cpu affinity
+            .write(&[0; 8])
+            // reservespace
+            .write(&[0; 8])
+            // NOTE: no version and trust from a daemon this old.
+            .build();
+
+        assert_eq!(
+            client_handshake_server(&mut test_conn).await.unwrap(),
+            ServerInfo {
+                version: ProtocolVersion::from_parts(1, 21),
+                nix_version: None,
+                trust: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn client_handshake_rejects_ancient_daemon() {
+        let mut test_conn = tokio_test::io::Builder::new()
+            .write(&WORKER_MAGIC_1.to_le_bytes())
+            .read(&WORKER_MAGIC_2.to_le_bytes())
+            .read(&[9, 1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            .build();
+
+        let err = client_handshake_server(&mut test_conn).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn write_op_is_little_endian() {
+        let mut mock = tokio_test::io::Builder::new()
+            .write(&hex!("1f00 0000 0000 0000"))
+            .build();
+        write_op(&mut mock, Operation::QueryValidPaths).await.unwrap();
+
+        let mut mock = tokio_test::io::Builder::new()
+            .read(&hex!("0100 0000 0000 0000"))
+            .build();
+        assert_eq!(read_op(&mut mock).await.unwrap(), Operation::IsValidPath);
+    }
+
+    /// QueryValidPaths for one path, without the substitute flag.
+    const QUERY_VALID_PATHS_26: [u8; 80] = hex!(
+        "1f00 0000 0000 0000" // op: QueryValidPaths
+        "0100 0000 0000 0000" // paths: 1 entry
+        "3800 0000 0000 0000" // "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello-2.12.1"
+        "2f6e 6978 2f73 746f 7265 2f30 3062 6764"
+        "3034 357a 3064 3469 6370 6263 3279 797a"
+        "3467 7834 3861 6b34 346c 612d 6865 6c6c"
+        "6f2d 322e 3132 2e31"
+    );
+
+    #[rstest]
+    #[case::without_substitute(26, QUERY_VALID_PATHS_26.to_vec())]
+    #[case::with_substitute(27, [&QUERY_VALID_PATHS_26[..], &hex!("0100 0000 0000 0000")[..]].concat())]
+    #[tokio::test]
+    async fn query_valid_paths_by_version(#[case] minor: u8, #[case] expected: Vec<u8>) {
+        let request = QueryValidPaths {
+            paths: vec![
+                StorePath::from_bytes(b"00bgd045z0d4icpbc2yyz4gx48ak44la-hello-2.12.1").unwrap(),
+            ],
+            substitute: true,
+        };
+
+        let mock = tokio_test::io::Builder::new().write(&expected).build();
+        let mut writer = NixWriter::builder()
+            .set_version(ProtocolVersion::from_parts(1, minor))
+            .build(mock);
+        write_request(&mut writer, Operation::QueryValidPaths, &request)
+            .await
+            .unwrap();
+        writer.flush().await.unwrap();
+    }
 }
@@ -75,18 +75,56 @@ pub fn decode(input: impl AsRef<[u8]>) -> Result<Vec<u8>, DecodeError> {
 }
 
 pub fn decode_fixed<const K: usize>(input: impl AsRef<[u8]>) -> Result<[u8; K], DecodeError> {
-    let input = input.as_ref();
+    let mut output = [0; K];
+    decode_into(input.as_ref(), &mut output)?;
+    Ok(output)
+}
 
-    if input.len() != encode_len(K) {
+/// Decodes input into a caller-provided buffer, avoiding an allocation.
+///
+/// `output` must be exactly `decode_len(input.len())` bytes long, and the
+/// input length must be a canonical encoding of that many bytes.
+/// On error, the contents of `output` are unspecified.
+pub fn decode_into(input: &[u8], output: &mut [u8]) -> Result<(), DecodeError> {
+    if input.len() != encode_len(output.len()) {
         return Err(DecodeError {
-            position: input.len().min(encode_len(K)),
+            position: input.len().min(encode_len(output.len())),
             kind: DecodeKind::Length,
         });
     }
 
-    let mut output = [0; K];
-    decode_inner(input, &mut output)?;
-    Ok(output)
+    output.fill(0);
+    decode_inner(input, output)
+}
+
+/// Returns whether input is valid nixbase32, without decoding it.
+///
+/// Agrees with `decode(input).is_ok()`: the length must be canonical, every
+/// character must be in the alphabet, and the leading character must not
+/// carry bits past the end of the decoded output.
+pub fn is_valid(input: &[u8]) -> bool {
+    if input.len() != encode_len(decode_len(input.len())) {
+        return false;
+    }
+
+    // OR all digits together instead of exiting early, so the loop stays
+    // branch-free and can be vectorized.
+    let mask = input
+        .iter()
+        .fold(0, |mask, &c| mask | BASE32_ORD[c as usize]);
+    if mask == 0xFF {
+        return false;
+    }
+
+    // The first character holds the most significant bits; anything shifted
+    // past the last output byte is what decode reports as Trailing.
+    match input.first() {
+        None => true,
+        Some(&c) => {
+            let j = ((input.len() - 1) * 5) % 8;
+            ((BASE32_ORD[c as usize] as u16) << j) >> 8 == 0
+        }
+    }
 }
 
 fn decode_inner(input: &[u8], output: &mut [u8]) -> Result<(), DecodeError> {
@@ -147,6 +185,9 @@ pub const fn encode_len(len: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use hex_literal::hex;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::select;
     use rstest::rstest;
 
     #[rstest]
@@ -202,6 +243,84 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_into() {
+        let mut output = [0xAA; 20];
+        super::decode_into(b"00bgd045z0d4icpbc2yyz4gx48ak44la", &mut output).unwrap();
+        assert_eq!(output, hex!("8a12321522fd91efbd60ebb2481af88580f61600"));
+
+        let mut short = [0; 19];
+        assert_eq!(
+            super::decode_into(b"00bgd045z0d4icpbc2yyz4gx48ak44la", &mut short).unwrap_err(),
+            super::DecodeError {
+                position: 31,
+                kind: super::DecodeKind::Length
+            }
+        );
+    }
+
+    #[rstest]
+    #[case::empty("", true)]
+    #[case::store_path("00bgd045z0d4icpbc2yyz4gx48ak44la", true)]
+    #[case::trailing("zz", false)]
+    #[case::length("0zz", false)]
+    #[case::e("00bgd045z0d4icpbc2yyz4gx48ak44le", false)]
+    #[case::o("o0", false)]
+    #[case::t("0t", false)]
+    #[case::u("u0", false)]
+    #[test]
+    fn is_valid(#[case] enc: &str, #[case] valid: bool) {
+        assert_eq!(valid, super::is_valid(enc.as_bytes()));
+    }
+
+    /// The alphabet plus the four letters nixbase32 leaves out.
+    const CHARS: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyzeotu";
+
+    proptest! {
+        #[test]
+        fn prop_encode_decode_into_roundtrip(input in vec(any::<u8>(), 0..64)) {
+            let encoded = super::encode(&input);
+            prop_assert!(super::is_valid(encoded.as_bytes()), "{}", encoded);
+
+            let mut output = vec![0xFF; input.len()];
+            super::decode_into(encoded.as_bytes(), &mut output).unwrap();
+            prop_assert_eq!(input, output);
+        }
+
+        /// Mostly in-alphabet inputs, so that some of the longer ones decode.
+        #[test]
+        fn prop_is_valid_agrees_with_decode(
+            input in vec(prop_oneof![63 => select(CHARS), 1 => any::<u8>()], 0..56)
+        ) {
+            prop_assert_eq!(super::is_valid(&input), super::decode(&input).is_ok());
+        }
+    }
+
+    #[test]
+    fn is_valid_agrees_with_decode_on_short_inputs() {
+        let mut inputs: Vec<Vec<u8>> = vec![vec![]];
+        for len in 1..=3 {
+            let mut next = Vec::new();
+            for prefix in inputs.iter().filter(|i| i.len() == len - 1) {
+                for &c in CHARS {
+                    let mut input = prefix.clone();
+                    input.push(c);
+                    next.push(input);
+                }
+            }
+            inputs.extend(next);
+        }
+
+        for input in inputs {
+            assert_eq!(
+                super::is_valid(&input),
+                super::decode(&input).is_ok(),
+                "{:?}",
+                String::from_utf8_lossy(&input)
+            );
+        }
+    }
+
     #[test]
     fn encode_len() {
         assert_eq!(super::encode_len(0), 0);
@@ -31,87 +31,91 @@ impl<'a> NixConfig<'a> {
         let mut out = Self::default();
 
         for line in input.lines() {
-            // strip comments at the end of the line
-            let line = if let Some((line, _comment)) = line.split_once('#') {
-                line
-            } else {
-                line
-            };
-
-            // skip comments and empty lines
-            if line.trim().is_empty() {
-                continue;
+            if let Some((tag, val)) = assignment(line)? {
+                out.set(tag, val)?;
             }
+        }
 
-            let (tag, val) = line
-                .split_once('=')
-                .ok_or_else(|| Error::InvalidLine(line.to_string()))?;
-
-            // trim whitespace
-            let tag = tag.trim();
-            let val = val.trim();
-
-            #[inline]
-            fn parse_val<'a>(this: &mut NixConfig<'a>, tag: &str, val: &'a str) -> Option<()> {
-                match tag {
-                    "allowed-users" => {
-                        this.allowed_users = Some(val.split_whitespace().collect());
-                    }
-                    "auto-optimise-store" => {
-                        this.auto_optimise_store = Some(val.parse::<bool>().ok()?);
-                    }
-                    "cores" => {
-                        this.cores = Some(val.parse().ok()?);
-                    }
-                    "max-jobs" => {
-                        this.max_jobs = Some(val.parse().ok()?);
-                    }
-                    "require-sigs" => {
-                        this.require_sigs = Some(val.parse().ok()?);
-                    }
-                    "sandbox" => this.sandbox = Some(val.parse().ok()?),
-                    "sandbox-fallback" => this.sandbox_fallback = Some(val.parse().ok()?),
-                    "substituters" => this.substituters = Some(val.split_whitespace().collect()),
-                    "system-features" => {
-                        this.system_features = Some(val.split_whitespace().collect())
-                    }
-                    "trusted-public-keys" => {
-                        this.trusted_public_keys = Some(
-                            val.split_whitespace()
-                                .map(crate::narinfo::VerifyingKey::parse)
-                                .collect::<Result<Vec<crate::narinfo::VerifyingKey>, _>>()
-                                .ok()?,
-                        )
-                    }
-                    "trusted-substituters" => {
-                        this.trusted_substituters = Some(val.split_whitespace().collect())
-                    }
-                    "trusted-users" => this.trusted_users = Some(val.split_whitespace().collect()),
-                    "extra-platforms" => {
-                        this.extra_platforms = Some(val.split_whitespace().collect())
-                    }
-                    "extra-sandbox-paths" => {
-                        this.extra_sandbox_paths = Some(val.split_whitespace().collect())
-                    }
-                    "experimental-features" => {
-                        this.experimental_features = Some(val.split_whitespace().collect())
-                    }
-                    "builders-use-substitutes" => {
-                        this.builders_use_substitutes = Some(val.parse().ok()?)
-                    }
-                    _ => return None,
-                }
-                Some(())
-            }
+        Ok(out)
+    }
 
-            parse_val(&mut out, tag, val)
-                .ok_or_else(|| Error::InvalidValue(tag.to_string(), val.to_string()))?
+    /// Applies a single `tag = val` assignment.
+    pub(super) fn set(&mut self, tag: &str, val: &'a str) -> Result<(), Error> {
+        #[inline]
+        fn parse<T: FromStr>(tag: &str, val: &str) -> Result<T, Error> {
+            val.parse()
+                .map_err(|_| Error::InvalidValue(tag.to_string(), val.to_string()))
         }
 
-        Ok(out)
+        match tag {
+            "allowed-users" => {
+                self.allowed_users = Some(val.split_whitespace().collect());
+            }
+            "auto-optimise-store" => {
+                self.auto_optimise_store = Some(parse(tag, val)?);
+            }
+            "cores" => {
+                self.cores = Some(parse(tag, val)?);
+            }
+            "max-jobs" => {
+                self.max_jobs = Some(parse(tag, val)?);
+            }
+            "require-sigs" => {
+                self.require_sigs = Some(parse(tag, val)?);
+            }
+            "sandbox" => self.sandbox = Some(parse(tag, val)?),
+            "sandbox-fallback" => self.sandbox_fallback = Some(parse(tag, val)?),
+            "substituters" => self.substituters = Some(val.split_whitespace().collect()),
+            "system-features" => self.system_features = Some(val.split_whitespace().collect()),
+            "trusted-public-keys" => {
+                self.trusted_public_keys = Some(
+                    val.split_whitespace()
+                        .map(crate::narinfo::VerifyingKey::parse)
+                        .collect::<Result<Vec<crate::narinfo::VerifyingKey>, _>>()
+                        .map_err(|_| Error::InvalidValue(tag.to_string(), val.to_string()))?,
+                )
+            }
+            "trusted-substituters" => {
+                self.trusted_substituters = Some(val.split_whitespace().collect())
+            }
+            "trusted-users" => self.trusted_users = Some(val.split_whitespace().collect()),
+            "extra-platforms" => self.extra_platforms = Some(val.split_whitespace().collect()),
+            "extra-sandbox-paths" => {
+                self.extra_sandbox_paths = Some(val.split_whitespace().collect())
+            }
+            "experimental-features" => {
+                self.experimental_features = Some(val.split_whitespace().collect())
+            }
+            "builders-use-substitutes" => self.builders_use_substitutes = Some(parse(tag, val)?),
+            _ => return Err(Error::UnrecognizedKey(tag.to_string())),
+        }
+        Ok(())
     }
 }
 
+/// Splits a config line into its trimmed key and value, dropping any comment.
+/// Returns `None` for blank and comment-only lines.
+pub(super) fn assignment(line: &str) -> Result<Option<(&str, &str)>, Error> {
+    // strip comments at the end of the line
+    let line = if let Some((line, _comment)) = line.split_once('#') {
+        line
+    } else {
+        line
+    };
+
+    // skip comments and empty lines
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let (tag, val) = line
+        .split_once('=')
+        .ok_or_else(|| Error::InvalidLine(line.to_string()))?;
+
+    // trim whitespace
+    Ok(Some((tag.trim(), val.trim())))
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Invalid line: {0}")]
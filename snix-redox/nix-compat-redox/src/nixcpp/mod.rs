@@ -7,3 +7,4 @@
 //! protocol live elsewhere.
 
 pub mod conf;
+pub mod settings;
@@ -0,0 +1,348 @@
+//! Owned, include-aware parsing of `nix.conf` files.
+//!
+//! Unlike [NixConfig::parse], this follows `include` and `!include`
+//! directives, so it can read a real `/etc/nix/nix.conf`. Lines and values
+//! are parsed as [NixConfig] does; keys it doesn't know are kept verbatim
+//! in [Settings::other].
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use super::conf::{self, NixConfig};
+use crate::narinfo::VerifyingKey;
+
+/// Value of the `max-jobs` setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaxJobs {
+    /// `auto`: one job per CPU.
+    Auto,
+    Jobs(u64),
+}
+
+/// Settings collected from a `nix.conf` and the files it includes.
+///
+/// Later assignments override earlier ones, and `extra-<key>` appends to
+/// `<key>` instead of replacing it, as in Nix.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// Keys [NixConfig] understands, with their raw (merged) values.
+    known: BTreeMap<String, String>,
+
+    /// Keys [NixConfig] doesn't understand, with their raw (merged) values.
+    pub other: BTreeMap<String, String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("{0}:{1}: invalid line: {2}")]
+    InvalidLine(PathBuf, usize, String),
+    #[error("{0}:{1}: invalid value '{3}' for key '{2}'")]
+    InvalidValue(PathBuf, usize, String, String),
+    #[error("{0}: include cycle")]
+    IncludeCycle(PathBuf),
+}
+
+impl Settings {
+    /// Reads `path` from disk, following includes relative to each file.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::load_with(path, &mut |p| std::fs::read_to_string(p))
+    }
+
+    /// Like [Settings::load], but reads files through `read`.
+    ///
+    /// A `!include` whose target `read` reports as [io::ErrorKind::NotFound]
+    /// is skipped; every other error is returned.
+    pub fn load_with(
+        path: &Path,
+        read: &mut dyn FnMut(&Path) -> io::Result<String>,
+    ) -> Result<Self, Error> {
+        let mut out = Self::default();
+        let input = read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        out.parse_file(path, &input, read, &mut vec![path.to_owned()])?;
+        Ok(out)
+    }
+
+    /// The known settings, parsed.
+    pub fn config(&self) -> NixConfig<'_> {
+        let mut config = NixConfig::default();
+        for (key, val) in &self.known {
+            // Every value was checked by `set`; only `max-jobs = auto` fails.
+            let _ = config.set(key, val);
+        }
+        config
+    }
+
+    /// Substituter URLs from `substituters` and `extra-substituters`.
+    pub fn substituters(&self) -> Vec<&str> {
+        self.config().substituters.unwrap_or_default()
+    }
+
+    /// Parsed `trusted-public-keys`.
+    pub fn trusted_public_keys(&self) -> Vec<VerifyingKey> {
+        self.config().trusted_public_keys.unwrap_or_default()
+    }
+
+    /// Enabled `experimental-features`.
+    pub fn experimental_features(&self) -> Vec<&str> {
+        self.config().experimental_features.unwrap_or_default()
+    }
+
+    /// Whether `feature` is listed in `experimental-features`.
+    pub fn has_experimental_feature(&self, feature: &str) -> bool {
+        self.experimental_features().contains(&feature)
+    }
+
+    pub fn max_jobs(&self) -> Option<MaxJobs> {
+        match self.known.get("max-jobs")?.as_str() {
+            "auto" => Some(MaxJobs::Auto),
+            _ => self.config().max_jobs.map(MaxJobs::Jobs),
+        }
+    }
+
+    fn parse_file(
+        &mut self,
+        path: &Path,
+        input: &str,
+        read: &mut dyn FnMut(&Path) -> io::Result<String>,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
+        for (idx, line) in input.lines().enumerate() {
+            let lineno = idx + 1;
+            let line = line.split_once('#').map_or(line, |(line, _comment)| line);
+            let line = line.trim();
+
+            let include = if let Some(rest) = line.strip_prefix("!include") {
+                Some((rest, true))
+            } else {
+                line.strip_prefix("include").map(|rest| (rest, false))
+            };
+            if let Some((target, optional)) = include {
+                // `includes = …` is an ordinary key, not a directive.
+                if target.starts_with(char::is_whitespace) {
+                    self.include(path, target.trim(), optional, read, stack)?;
+                    continue;
+                }
+            }
+
+            let invalid_line = || Error::InvalidLine(path.to_owned(), lineno, line.to_owned());
+            let (key, val) = match conf::assignment(line) {
+                Ok(Some(("", _))) => return Err(invalid_line()),
+                Ok(Some(assignment)) => assignment,
+                Ok(None) => continue,
+                Err(_) => return Err(invalid_line()),
+            };
+
+            self.set(key, val).map_err(|_| {
+                Error::InvalidValue(path.to_owned(), lineno, key.to_owned(), val.to_owned())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        from: &Path,
+        target: &str,
+        optional: bool,
+        read: &mut dyn FnMut(&Path) -> io::Result<String>,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
+        let target = match from.parent() {
+            Some(dir) => dir.join(target),
+            None => PathBuf::from(target),
+        };
+        if stack.contains(&target) {
+            return Err(Error::IncludeCycle(target));
+        }
+
+        let input = match read(&target) {
+            Ok(input) => input,
+            Err(e) if optional && e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::Io(target, e)),
+        };
+
+        stack.push(target.clone());
+        self.parse_file(&target, &input, read, stack)?;
+        stack.pop();
+        Ok(())
+    }
+
+    /// Applies one `key = value` assignment, checking the value as
+    /// [NixConfig] would.
+    fn set(&mut self, key: &str, val: &str) -> Result<(), conf::Error> {
+        // Some keys, like `extra-platforms`, carry the prefix in their name.
+        let (key, extra) = match key.strip_prefix("extra-") {
+            Some(base) if !is_known(key) => (base, true),
+            _ => (key, false),
+        };
+
+        let mut merged = String::new();
+        if extra && let Some(old) = self.known.get(key).or_else(|| self.other.get(key)) {
+            merged.push_str(old);
+            merged.push(' ');
+        }
+        merged.push_str(val);
+        let merged = merged.trim().to_owned();
+
+        // Nix also takes `auto`, which [NixConfig] doesn't.
+        let checked = match (key, merged.as_str()) {
+            ("max-jobs", "auto") => Ok(()),
+            _ => NixConfig::default().set(key, &merged),
+        };
+        match checked {
+            Ok(()) => self.known.insert(key.to_owned(), merged),
+            Err(conf::Error::UnrecognizedKey(_)) => self.other.insert(key.to_owned(), merged),
+            Err(e) => return Err(e),
+        };
+        Ok(())
+    }
+}
+
+/// Whether [NixConfig] has a field for `key`.
+fn is_known(key: &str) -> bool {
+    !matches!(
+        NixConfig::default().set(key, ""),
+        Err(conf::Error::UnrecognizedKey(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::nixcpp::conf::SandboxSetting;
+
+    fn load(files: &[(&str, &str)]) -> Result<Settings, Error> {
+        let files: HashMap<PathBuf, String> = files
+            .iter()
+            .map(|(p, c)| (PathBuf::from(p), c.to_string()))
+            .collect();
+        Settings::load_with(Path::new("/etc/nix/nix.conf"), &mut |p| {
+            files
+                .get(p)
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        })
+    }
+
+    #[test]
+    fn list_valued_keys() {
+        let settings = load(&[(
+            "/etc/nix/nix.conf",
+            "substituters = https://cache.nixos.org/ https://example.org\n\
+             extra-substituters =\thttps://extra.example.org # trailing comment\n\
+             trusted-public-keys = cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=\n\
+             experimental-features = nix-command flakes\n\
+             max-jobs = auto\n\
+             keep-outputs = true\n",
+        )])
+        .expect("must parse");
+
+        assert_eq!(
+            settings.substituters(),
+            [
+                "https://cache.nixos.org/",
+                "https://example.org",
+                "https://extra.example.org"
+            ]
+        );
+        assert_eq!(settings.trusted_public_keys().len(), 1);
+        assert!(settings.has_experimental_feature("flakes"));
+        assert!(!settings.has_experimental_feature("ca-derivations"));
+        assert_eq!(settings.max_jobs(), Some(MaxJobs::Auto));
+        assert_eq!(
+            settings.other.get("keep-outputs").map(String::as_str),
+            Some("true")
+        );
+    }
+
+    #[test]
+    fn include_second_file() {
+        let settings = load(&[
+            (
+                "/etc/nix/nix.conf",
+                "substituters = https://cache.nixos.org/\n\
+                 max-jobs = 2\n\
+                 include machine.conf\n\
+                 sandbox = false\n",
+            ),
+            (
+                "/etc/nix/machine.conf",
+                "max-jobs = 8\nsandbox = true\nextra-substituters = https://local\n",
+            ),
+        ])
+        .expect("must parse");
+
+        // Included settings override earlier lines and are overridden by later ones.
+        assert_eq!(settings.max_jobs(), Some(MaxJobs::Jobs(8)));
+        assert_eq!(settings.config().sandbox, Some(SandboxSetting::False));
+        assert_eq!(
+            settings.substituters(),
+            ["https://cache.nixos.org/", "https://local"]
+        );
+    }
+
+    #[test]
+    fn extra_appends_to_the_base_key() {
+        let settings = load(&[(
+            "/etc/nix/nix.conf",
+            "allowed-uris = https://a\n\
+             extra-allowed-uris = https://b\n\
+             extra-experimental-features = flakes\n\
+             extra-platforms = aarch64-linux\n",
+        )])
+        .expect("must parse");
+
+        assert_eq!(
+            settings.other.get("allowed-uris").map(String::as_str),
+            Some("https://a https://b")
+        );
+        assert!(!settings.other.contains_key("extra-allowed-uris"));
+        assert_eq!(settings.experimental_features(), ["flakes"]);
+        // `extra-platforms` is a key of its own, not an append to `platforms`.
+        assert_eq!(
+            settings.config().extra_platforms,
+            Some(vec!["aarch64-linux"])
+        );
+        assert!(!settings.other.contains_key("platforms"));
+    }
+
+    #[test]
+    fn missing_optional_include() {
+        let settings = load(&[(
+            "/etc/nix/nix.conf",
+            "!include nix.conf.local\nmax-jobs = 4\n",
+        )])
+        .expect("missing !include is not an error");
+        assert_eq!(settings.max_jobs(), Some(MaxJobs::Jobs(4)));
+
+        let err = load(&[("/etc/nix/nix.conf", "include nix.conf.local\n")])
+            .expect_err("missing include is an error");
+        assert!(matches!(err, Error::Io(p, _) if p == Path::new("/etc/nix/nix.conf.local")));
+    }
+
+    #[test]
+    fn include_cycle() {
+        let err = load(&[
+            ("/etc/nix/nix.conf", "include a.conf\n"),
+            ("/etc/nix/a.conf", "include nix.conf\n"),
+        ])
+        .expect_err("cycle must be rejected");
+        assert!(matches!(err, Error::IncludeCycle(_)));
+    }
+
+    #[test]
+    fn invalid_max_jobs() {
+        let err = load(&[("/etc/nix/nix.conf", "\nmax-jobs = lots\n")]).expect_err("must fail");
+        assert!(matches!(err, Error::InvalidValue(_, 2, k, _) if k == "max-jobs"));
+
+        let err = load(&[("/etc/nix/nix.conf", "sandbox = maybe\n")]).expect_err("must fail");
+        assert!(matches!(err, Error::InvalidValue(_, 1, k, _) if k == "sandbox"));
+    }
+}
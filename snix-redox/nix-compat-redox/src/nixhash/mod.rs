@@ -107,6 +107,25 @@ impl NixHash {
         }
     }
 
+    /// Compares two hashes without short-circuiting on the first differing
+    /// digest byte, for checks where timing shouldn't reveal how much of a
+    /// digest matched.
+    ///
+    /// Hashes of different algos are never equal; that is decided up front,
+    /// as the algo is not secret.
+    pub fn verify_eq(&self, other: &NixHash) -> bool {
+        if self.algo() != other.algo() {
+            return false;
+        }
+
+        let diff = self
+            .digest_as_bytes()
+            .iter()
+            .zip(other.digest_as_bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        std::hint::black_box(diff) == 0
+    }
+
     /// Constructs a new [NixHash] by specifying [HashAlgo] and digest.
     /// It can fail if the passed digest length doesn't match what's expected for
     /// the passed algo.
@@ -265,6 +284,29 @@ impl NixHash {
     }
 }
 
+/// Fold `input` (usually a digest) into `output_len` bytes, the way Nix
+/// shortens the SHA-256 fingerprint of a store path to the 20 bytes that
+/// are nixbase32-encoded into its name.
+///
+/// Input byte `i` is XOR'ed into output byte `i % output_len`, so an
+/// `output_len` equal to the input length returns the input unchanged, and
+/// a larger one pads it with zero bytes. An `output_len` of 0 returns an
+/// empty digest.
+///
+/// [crate::store_path::compress_hash] is the fixed-size variant.
+pub fn compress_hash(input: &[u8], output_len: usize) -> Vec<u8> {
+    let mut output = vec![0; output_len];
+    if output_len == 0 {
+        return output;
+    }
+
+    for (ii, ch) in input.iter().enumerate() {
+        output[ii % output_len] ^= ch;
+    }
+
+    output
+}
+
 /// Errors related to NixHash construction.
 #[derive(Debug, Eq, PartialEq, thiserror::Error)]
 pub enum Error {
@@ -311,7 +353,7 @@ fn decode_digest(s: &[u8], algo: HashAlgo) -> Result<NixHash, Error> {
 
 #[cfg(test)]
 mod tests {
-    use crate::nixhash::{HashAlgo, NixHash};
+    use crate::nixhash::{HashAlgo, NixHash, compress_hash};
     use hex_literal::hex;
     use rstest::rstest;
     use std::sync::LazyLock;
@@ -327,6 +369,66 @@ mod tests {
     });
     const NIXHASH_MD5: NixHash = NixHash::Md5(hex!("c4874a8897440b393d862d8fd459073f"));
 
+    #[rstest]
+    #[case::md5(NIXHASH_MD5)]
+    #[case::sha1(NIXHASH_SHA1)]
+    #[case::sha256(NIXHASH_SHA256)]
+    #[case::sha512((*NIXHASH_SHA512).clone())]
+    fn verify_eq(#[case] hash: NixHash) {
+        assert!(hash.verify_eq(&hash.clone()));
+
+        // Flip one bit at each end of the digest.
+        for idx in [0, hash.algo().digest_length() - 1] {
+            let mut digest = hash.digest_as_bytes().to_vec();
+            digest[idx] ^= 1;
+            let other = NixHash::from_algo_and_digest(hash.algo(), &digest).unwrap();
+            assert!(!hash.verify_eq(&other));
+            assert!(!other.verify_eq(&hash));
+        }
+    }
+
+    #[test]
+    fn compress_hash_like_nix() {
+        // The fingerprint digest of `builtins.toFile "foo" "bar"`, which Nix
+        // names /nix/store/vxjiwkjkn7x4079qvh1jkl5pn05j2aw0-foo.
+        let fingerprint = hex!("6055c3f2a839edfcecd3d7fc40fab1534e1e65dfe07ee2f9188e3dd5ef0fefe1");
+
+        let compressed = compress_hash(&fingerprint, 20);
+        assert_eq!(compressed, hex!("802b210bb0b7d02903dc381d40fab1534e1e65df"));
+        assert_eq!(
+            crate::nixbase32::encode(&compressed),
+            "vxjiwkjkn7x4079qvh1jkl5pn05j2aw0"
+        );
+        assert_eq!(
+            compressed,
+            crate::store_path::compress_hash::<20>(&fingerprint)
+        );
+    }
+
+    #[test]
+    fn compress_hash_identity_and_padding() {
+        let digest = NIXHASH_SHA256.digest_as_bytes();
+        assert_eq!(compress_hash(digest, 32), digest);
+
+        let padded = compress_hash(digest, 40);
+        assert_eq!(&padded[..32], digest);
+        assert_eq!(&padded[32..], [0; 8]);
+
+        assert!(compress_hash(digest, 0).is_empty());
+    }
+
+    #[test]
+    fn verify_eq_different_algos() {
+        // A sha512 whose digest starts with the sha256 digest.
+        let mut digest = NIXHASH_SHA256.digest_as_bytes().to_vec();
+        digest.resize(64, 0);
+        let sha512 = NixHash::from_algo_and_digest(HashAlgo::Sha512, &digest).unwrap();
+
+        assert!(!NIXHASH_SHA256.verify_eq(&sha512));
+        assert!(!sha512.verify_eq(&NIXHASH_SHA256));
+        assert!(!NIXHASH_SHA256.verify_eq(&NIXHASH_SHA512));
+    }
+
     /// Test parsing a hash string in various formats, and also when/how the out-of-band algo is needed.
     #[rstest]
     // regular SRI hashes. We test some funny encoding edge cases in a separate test.
@@ -108,6 +108,34 @@ where
         }
     }
 
+    /// Splits the name into package name and version, like Nix'
+    /// `DrvName`: the version starts after the first `-` that is not
+    /// followed by a letter. `hello-2.12.1` becomes `("hello", Some("2.12.1"))`,
+    /// `bash-interactive` has no version.
+    pub fn name_version(&self) -> (&str, Option<&str>) {
+        let name = self.name.as_ref();
+        let split = name
+            .char_indices()
+            .find(|&(i, c)| {
+                c == '-'
+                    && name[i + 1..]
+                        .chars()
+                        .next()
+                        .is_some_and(|next| !next.is_ascii_alphabetic())
+            })
+            .map(|(i, _)| i);
+
+        match split {
+            Some(i) => (&name[..i], Some(&name[i + 1..])),
+            None => (name, None),
+        }
+    }
+
+    /// Whether this is a store derivation, i.e. the name ends in `.drv`.
+    pub fn is_derivation(&self) -> bool {
+        self.name.as_ref().ends_with(".drv")
+    }
+
     /// Construct a [StorePath] by passing the `$digest-$name` string
     /// that comes after [STORE_DIR_WITH_SLASH].
     pub fn from_bytes<'a>(s: &'a [u8]) -> Result<Self, Error>
@@ -597,6 +625,35 @@ mod tests {
         assert_eq!(exp_path, actual_path);
     }
 
+    #[rstest]
+    #[case::simple("hello-2.12.1", "hello", Some("2.12.1"))]
+    #[case::multiple_hyphens(
+        "net-tools-1.60_p20170221182432",
+        "net-tools",
+        Some("1.60_p20170221182432")
+    )]
+    #[case::hyphen_in_version("xorg-server-21.1.8-dev", "xorg-server", Some("21.1.8-dev"))]
+    #[case::no_version("bash-interactive", "bash-interactive", None)]
+    #[case::trailing_hyphen("foo-", "foo-", None)]
+    #[case::drv("hello-2.12.1.drv", "hello", Some("2.12.1.drv"))]
+    fn name_version(#[case] name: &str, #[case] pname: &str, #[case] version: Option<&str>) {
+        let s = format!("00bgd045z0d4icpbc2yyz4gx48ak44la-{name}");
+        let store_path = StorePathRef::from_bytes(s.as_bytes()).expect("must parse");
+
+        assert_eq!((pname, version), store_path.name_version());
+    }
+
+    #[test]
+    fn is_derivation() {
+        let drv = StorePathRef::from_bytes(b"00bgd045z0d4icpbc2yyz4gx48ak44la-hello-2.12.1.drv")
+            .expect("must parse");
+        let out = StorePathRef::from_bytes(b"00bgd045z0d4icpbc2yyz4gx48ak44la-hello-2.12.1")
+            .expect("must parse");
+
+        assert!(drv.is_derivation());
+        assert!(!out.is_derivation());
+    }
+
     #[test]
     fn from_absolute_path_errors() {
         assert_eq!(
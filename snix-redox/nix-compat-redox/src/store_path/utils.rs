@@ -28,15 +28,12 @@ pub enum BuildStorePathError {
 /// input. It consumes 1 byte at a time, and XOR's it with the current
 /// value in the output buffer.
 ///
-/// This mimics equivalent functionality in C++ Nix.
+/// This mimics equivalent functionality in C++ Nix. See
+/// [crate::nixhash::compress_hash] for an output length chosen at runtime.
 pub fn compress_hash<const OUTPUT_SIZE: usize>(input: &[u8]) -> [u8; OUTPUT_SIZE] {
-    let mut output = [0; OUTPUT_SIZE];
-
-    for (ii, ch) in input.iter().enumerate() {
-        output[ii % OUTPUT_SIZE] ^= ch;
-    }
-
-    output
+    crate::nixhash::compress_hash(input, OUTPUT_SIZE)
+        .try_into()
+        .expect("output has OUTPUT_SIZE bytes")
 }
 
 /// This builds a store path, by calculating the text_hash_string of either a
@@ -73,7 +70,7 @@ where
     I: IntoIterator<Item = S>,
 {
     // self references are only allowed for CAHash::Nar(NixHash::Sha256(_)).
-    if self_reference && matches!(ca_hash, CAHash::Nar(NixHash::Sha256(_))) {
+    if self_reference && !matches!(ca_hash, CAHash::Nar(NixHash::Sha256(_))) {
         return Err(BuildStorePathError::InvalidReference());
     }
 
@@ -292,4 +289,88 @@ mod test {
             "/nix/store/s89y431zzhmdn3k8r96rvakryddkpv2v-baz"
         );
     }
+
+    #[test]
+    fn build_flat_path() {
+        // $ nix-prefetch-url mirror://gnu/hello/hello-2.12.1.tar.gz
+        // path is '/nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz'
+        // 086vqwk2wl8zfs47sq2xpjc9k066ilmb8z6dn0q6ymwjzlm196cd
+        let store_path: StorePathRef = build_ca_path(
+            "hello-2.12.1.tar.gz",
+            &CAHash::Flat(NixHash::Sha256(hex!(
+                "8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20"
+            ))),
+            Vec::<String>::new(),
+            false,
+        )
+        .expect("must succeed");
+
+        assert_eq!(
+            store_path.to_absolute_path().as_str(),
+            "/nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz"
+        );
+    }
+
+    #[test]
+    fn build_nar_path() {
+        // The `out` of a fixed-output derivation with
+        // outputHashMode = "recursive", as in Nix' derivation test data.
+        let store_path: StorePathRef = build_ca_path(
+            "bar",
+            &CAHash::Nar(NixHash::Sha256(hex!(
+                "08813cbee9903c62be4c5027726a418a300da4500b2d369d3af9286f4815ceba"
+            ))),
+            Vec::<String>::new(),
+            false,
+        )
+        .expect("must succeed");
+
+        assert_eq!(
+            store_path.to_absolute_path().as_str(),
+            "/nix/store/4q0pg5zpfmznxscq3avycvf9xdvx50n3-bar"
+        );
+    }
+
+    #[test]
+    fn build_flat_path_rejects_references() {
+        assert_eq!(
+            build_ca_path::<_, &str, _>(
+                "bar",
+                &CAHash::Flat(NixHash::Sha256([0; 32])),
+                ["/nix/store/dxwkwjzdaq7ka55pkk252gh32bgpmql4-foo"],
+                false,
+            )
+            .expect_err("must fail"),
+            BuildStorePathError::InvalidReference()
+        );
+    }
+
+    #[test]
+    fn self_reference_only_for_sha256_nar() {
+        let digest = hex!("08813cbee9903c62be4c5027726a418a300da4500b2d369d3af9286f4815ceba");
+
+        let nar: StorePathRef = build_ca_path(
+            "bar",
+            &CAHash::Nar(NixHash::Sha256(digest)),
+            Vec::<String>::new(),
+            true,
+        )
+        .expect("self references are allowed for sha256 NARs");
+        assert_ne!(
+            nar.to_absolute_path().as_str(),
+            "/nix/store/4q0pg5zpfmznxscq3avycvf9xdvx50n3-bar",
+            "the self reference is part of the fingerprint"
+        );
+
+        assert_eq!(
+            build_ca_path::<_, &str, _>(
+                "bar",
+                &CAHash::Flat(NixHash::Sha256(digest)),
+                Vec::<String>::new(),
+                true,
+            )
+            .expect_err("must fail"),
+            BuildStorePathError::InvalidReference()
+        );
+    }
 }
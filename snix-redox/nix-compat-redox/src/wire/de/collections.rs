@@ -1,6 +1,24 @@
 use std::{collections::BTreeMap, future::Future};
 
-use super::{NixDeserialize, NixRead};
+use super::{Error, NixDeserialize, NixRead};
+
+/// Most elements preallocated for a list, however long it claims to be.
+/// Longer lists still read fine, they just grow as elements arrive.
+const MAX_PREALLOC_LEN: usize = 1024;
+
+/// Read the length prefix of a list or map, checking it against
+/// [NixRead::max_list_len].
+async fn try_read_len<R>(reader: &mut R) -> Result<Option<usize>, R::Error>
+where
+    R: ?Sized + NixRead + Send,
+{
+    match reader.try_read_value::<usize>().await? {
+        Some(len) if len > reader.max_list_len() => {
+            Err(R::Error::invalid_data("list length out of range"))
+        }
+        len => Ok(len),
+    }
+}
 
 #[allow(clippy::manual_async_fn)]
 impl<T> NixDeserialize for Vec<T>
@@ -14,8 +32,8 @@ where
         R: ?Sized + NixRead + Send,
     {
         async move {
-            if let Some(len) = reader.try_read_value::<usize>().await? {
-                let mut ret = Vec::with_capacity(len);
+            if let Some(len) = try_read_len(reader).await? {
+                let mut ret = Vec::with_capacity(len.min(MAX_PREALLOC_LEN));
                 for _ in 0..len {
                     ret.push(reader.read_value().await?);
                 }
@@ -40,7 +58,7 @@ where
         R: ?Sized + NixRead + Send,
     {
         async move {
-            if let Some(len) = reader.try_read_value::<usize>().await? {
+            if let Some(len) = try_read_len(reader).await? {
                 let mut ret = BTreeMap::new();
                 for _ in 0..len {
                     let key = reader.read_value().await?;
@@ -58,7 +76,7 @@ where
 #[cfg(test)]
 mod test {
     use std::collections::BTreeMap;
-    use std::fmt;
+    use std::{fmt, io};
 
     use hex_literal::hex;
     use rstest::rstest;
@@ -102,4 +120,41 @@ mod test {
         let actual: E = reader.read_value().await.unwrap();
         assert_eq!(actual, expected);
     }
+
+    #[tokio::test]
+    async fn test_read_vec_longer_than_limit() {
+        // Claims 2^40 strings: must fail on the length, not allocate for it.
+        let mock = Builder::new().read(&hex!("0000 0000 0001 0000")).build();
+        let mut reader = NixReader::builder().set_max_list_len(1000).build(mock);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            reader.read_value::<Vec<String>>().await.unwrap_err().kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_vec_within_limit() {
+        let mock = Builder::new()
+            .read(&hex!(
+                "0200 0000 0000 0000 0200 0000 0000 0000 6974 0000 0000 0000 0000 0000 0000 0000"
+            ))
+            .build();
+        let mut reader = NixReader::builder().set_max_list_len(2).build(mock);
+        let actual: Vec<String> = reader.read_value().await.unwrap();
+        assert_eq!(actual, vec!["it".to_string(), String::new()]);
+    }
+
+    #[tokio::test]
+    async fn test_read_map_longer_than_limit() {
+        let mock = Builder::new().read(&hex!("0300 0000 0000 0000")).build();
+        let mut reader = NixReader::builder().set_max_list_len(2).build(mock);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            reader
+                .read_value::<BTreeMap<String, String>>()
+                .await
+                .unwrap_err()
+                .kind()
+        );
+    }
 }
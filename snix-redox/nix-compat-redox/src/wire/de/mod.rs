@@ -14,7 +14,7 @@ mod int;
 pub mod mock;
 mod reader;
 
-pub use reader::{NixReader, NixReaderBuilder};
+pub use reader::{DEFAULT_MAX_LIST_LEN, NixReader, NixReaderBuilder};
 
 /// Like serde the `Error` trait allows `NixRead` implementations to add
 /// custom error handling for `NixDeserialize`.
@@ -68,6 +68,14 @@ pub trait NixRead: Send {
     /// of the protocol and so this can be used for implementing that.
     fn version(&self) -> ProtocolVersion;
 
+    /// The largest number of elements a list or map read from the protocol
+    /// may announce. Longer lengths are rejected as invalid data, instead of
+    /// trusting a (possibly bogus) length prefix with an allocation.
+    /// The default implementation has no limit.
+    fn max_list_len(&self) -> usize {
+        usize::MAX
+    }
+
     /// Read a single u64 from the protocol.
     /// This returns an Option to support graceful shutdown.
     fn try_read_number(
@@ -153,6 +161,10 @@ impl<T: ?Sized + NixRead> NixRead for &mut T {
         (**self).version()
     }
 
+    fn max_list_len(&self) -> usize {
+        (**self).max_list_len()
+    }
+
     fn try_read_number(
         &mut self,
     ) -> impl Future<Output = Result<Option<u64>, Self::Error>> + Send + '_ {
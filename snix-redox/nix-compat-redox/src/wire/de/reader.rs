@@ -12,10 +12,14 @@ use crate::wire::{EMPTY_BYTES, ProtocolVersion};
 
 use super::{Error, NixRead};
 
+/// Default for [NixReaderBuilder::set_max_list_len].
+pub const DEFAULT_MAX_LIST_LEN: usize = 1 << 20;
+
 pub struct NixReaderBuilder {
     buf: Option<BytesMut>,
     reserved_buf_size: usize,
     max_buf_size: usize,
+    max_list_len: usize,
     version: ProtocolVersion,
 }
 
@@ -25,6 +29,7 @@ impl Default for NixReaderBuilder {
             buf: Default::default(),
             reserved_buf_size: 8192,
             max_buf_size: 8192,
+            max_list_len: DEFAULT_MAX_LIST_LEN,
             version: Default::default(),
         }
     }
@@ -41,11 +46,19 @@ impl NixReaderBuilder {
         self
     }
 
+    /// Longest bytes (and strings) read without an explicit limit.
     pub fn set_max_buf_size(mut self, size: usize) -> Self {
         self.max_buf_size = size;
         self
     }
 
+    /// Most elements a list or map read from the peer may have
+    /// (see [NixRead::max_list_len]).
+    pub fn set_max_list_len(mut self, len: usize) -> Self {
+        self.max_list_len = len;
+        self
+    }
+
     pub fn set_version(mut self, version: ProtocolVersion) -> Self {
         self.version = version;
         self
@@ -58,6 +71,7 @@ impl NixReaderBuilder {
             inner: reader,
             reserved_buf_size: self.reserved_buf_size,
             max_buf_size: self.max_buf_size,
+            max_list_len: self.max_list_len,
             version: self.version,
         }
     }
@@ -70,6 +84,7 @@ pin_project! {
         buf: BytesMut,
         reserved_buf_size: usize,
         max_buf_size: usize,
+        max_list_len: usize,
         version: ProtocolVersion,
     }
 }
@@ -153,6 +168,10 @@ where
         self.version
     }
 
+    fn max_list_len(&self) -> usize {
+        self.max_list_len
+    }
+
     async fn try_read_number(&mut self) -> Result<Option<u64>, Self::Error> {
         let mut buf = [0u8; 8];
         let read = self.read_buf(&mut &mut buf[..]).await?;
@@ -457,6 +476,24 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn test_read_string_longer_than_max_buf_size() {
+        let mock = Builder::new()
+            .read(&hex!("0500 0000 0000 0000 7768 6572 6500 0000"))
+            .build();
+        let mut reader = NixReader::builder().set_max_buf_size(4).build(mock);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            reader.read_value::<String>().await.unwrap_err().kind()
+        );
+
+        let mock = Builder::new()
+            .read(&hex!("0400 0000 0000 0000 7765 7265 0000 0000"))
+            .build();
+        let mut reader = NixReader::builder().set_max_buf_size(4).build(mock);
+        assert_eq!("were", reader.read_value::<String>().await.unwrap());
+    }
+
     #[tokio::test]
     async fn test_read_bytes_length_overflow() {
         let mock = Builder::new().read(&hex!("F9FF FFFF FFFF FFFF")).build();
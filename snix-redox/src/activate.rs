@@ -14,7 +14,7 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
 
-use crate::system::{FileInfo, Manifest, Package};
+use crate::system::{EtcSource, FileInfo, Manifest, Package};
 
 /// System profile bin directory (where managed package binaries live).
 const SYSTEM_PROFILE_BIN: &str = "/nix/system/profile/bin";
@@ -389,6 +389,10 @@ pub struct ActivationResult {
 ///
 /// If `dry_run` is true, computes and displays the plan without modifying anything.
 ///
+/// Managed config files that were edited by hand (their content matches
+/// neither the old nor the new manifest) are left alone with a warning,
+/// unless `force` is true.
+///
 /// **Idempotent**: activation ALWAYS rebuilds the profile and updates GC roots,
 /// even if the plan shows no package changes. This handles out-of-band
 /// modifications (e.g., a symlink was manually deleted) and ensures the system
@@ -398,6 +402,7 @@ pub fn activate(
     old: &Manifest,
     new: &Manifest,
     dry_run: bool,
+    force: bool,
 ) -> Result<ActivationResult, Box<dyn std::error::Error>> {
     let activation_plan = plan(old, new);
 
@@ -414,7 +419,12 @@ pub fn activate(
     let mut warnings = Vec::new();
 
     // ── Step 1: Pre-activation hooks ──
-    // (Reserved for future use — custom scripts before activation)
+    // A failing pre-activation hook aborts before anything is modified.
+    let hook_dir = hook_base_dir(new);
+    for script in &new.services.pre_activation_scripts {
+        run_hook(script, &hook_dir)
+            .map_err(|e| format!("pre-activation hook failed, activation aborted: {e}"))?;
+    }
 
     // ── Step 2: Rebuild system profile (always, for idempotency) ──
     // Even if the plan says packages didn't change, the on-disk profile
@@ -446,11 +456,16 @@ pub fn activate(
     };
 
     // ── Step 3: Update config files ──
+    let root_tree = (!new.root_tree.is_empty()).then(|| Path::new(&new.root_tree));
     let config_files_updated = update_config_files(
         &activation_plan.config_files_added,
         &activation_plan.config_files_removed,
         &activation_plan.config_files_changed,
         &new.files,
+        &new.etc,
+        root_tree,
+        Path::new("/"),
+        force,
         &mut warnings,
     );
 
@@ -460,21 +475,35 @@ pub fn activate(
     }
 
     // ── Step 5: Post-activation hooks ──
-    // (Reserved for future use)
-
-    // ── Determine if reboot is recommended ──
-    let reboot_recommended = !activation_plan.services_added.is_empty()
-        || !activation_plan.services_removed.is_empty()
-        || has_boot_config_changed(old, new);
+    // The system is already switched; a failing hook is reported, not rolled back.
+    for script in &new.services.post_activation_scripts {
+        if let Err(e) = run_hook(script, &hook_dir) {
+            warnings.push(format!("post-activation hook failed: {e}"));
+        }
+    }
 
     Ok(ActivationResult {
         binaries_linked,
         config_files_updated,
         warnings,
-        reboot_recommended,
+        reboot_recommended: needs_reboot(&activation_plan, old, new),
     })
 }
 
+/// Whether the change only fully applies after a reboot: services are
+/// started by init, and drivers and boot settings live in the initfs.
+fn needs_reboot(plan: &ActivationPlan, old: &Manifest, new: &Manifest) -> bool {
+    !plan.services_added.is_empty()
+        || !plan.services_removed.is_empty()
+        || has_boot_config_changed(old, new)
+}
+
+/// Whether switching from `old` to `new` recommends a reboot, i.e. what
+/// [ActivationResult::reboot_recommended] will say, without activating.
+pub fn reboot_recommended(old: &Manifest, new: &Manifest) -> bool {
+    needs_reboot(&plan(old, new), old, new)
+}
+
 /// Check if boot-critical configuration changed (kernel, bootloader, drivers).
 fn has_boot_config_changed(old: &Manifest, new: &Manifest) -> bool {
     old.drivers.initfs != new.drivers.initfs
@@ -502,58 +531,90 @@ fn has_boot_config_changed(old: &Manifest, new: &Manifest) -> bool {
 fn atomic_profile_swap(packages: &[Package]) -> Result<u32, Box<dyn std::error::Error>> {
     let staging_bin = PathBuf::from(STAGING_DIR).join("bin");
     let profile_bin = PathBuf::from(SYSTEM_PROFILE_BIN);
-    let profile_bin_new = profile_bin.with_file_name("bin.new");
-    let profile_bin_old = profile_bin.with_file_name("bin.old");
 
     // Clean up any leftover staging from a previous failed activation
     cleanup_path(&staging_bin);
-    cleanup_path(&profile_bin_new);
-    cleanup_path(&profile_bin_old);
 
     // Step 1: Build the new profile in staging
     std::fs::create_dir_all(&staging_bin)?;
-    let count = populate_profile_dir(&staging_bin, packages)?;
+    let count = match populate_profile_dir(&staging_bin, packages) {
+        Ok(count) => count,
+        Err(e) => {
+            cleanup_path(&PathBuf::from(STAGING_DIR));
+            return Err(e);
+        }
+    };
+
+    // Steps 2–5: swap it into place
+    let result = swap_dir_into_place(&staging_bin, &profile_bin);
+    cleanup_path(&PathBuf::from(STAGING_DIR));
+    result.map(|()| count)
+}
 
-    // Step 2: Move staging → bin.new
-    std::fs::rename(&staging_bin, &profile_bin_new)?;
+/// Replace `target` with the fully-built `staged` directory using renames.
+///
+/// `staged` is renamed to `{target}.new`, the current `target` (if any) to
+/// `{target}.old`, and then `{target}.new` to `target`. If the final rename
+/// fails, the old directory is moved back, so `target` is never left
+/// missing or half-populated. Shared with `install` for profile commits.
+pub(crate) fn swap_dir_into_place(
+    staged: &Path,
+    target: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| format!("invalid swap target: {}", target.display()))?
+        .to_string_lossy()
+        .to_string();
+    let target_new = target.with_file_name(format!("{file_name}.new"));
+    let target_old = target.with_file_name(format!("{file_name}.old"));
+
+    // Clean up leftovers from a previous failed swap
+    cleanup_path(&target_new);
+    cleanup_path(&target_old);
 
     // Ensure parent exists
-    if let Some(parent) = profile_bin.parent() {
+    if let Some(parent) = target.parent() {
         std::fs::create_dir_all(parent)?;
     }
 
-    // Step 3: Move current bin → bin.old (if it exists)
-    let had_old = if profile_bin.exists() || profile_bin.symlink_metadata().is_ok() {
+    // Move staged → target.new
+    std::fs::rename(staged, &target_new)?;
+
+    // Move current target → target.old (if it exists)
+    let had_old = if target.exists() || target.symlink_metadata().is_ok() {
         // Make writable first (Nix store outputs have mode 555)
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
-            let parent = profile_bin.parent().unwrap();
-            let _ = std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o755));
+            if let Some(parent) = target.parent() {
+                let _ = std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o755));
+            }
+        }
+        if let Err(e) = std::fs::rename(target, &target_old) {
+            cleanup_path(&target_new);
+            return Err(e.into());
         }
-        std::fs::rename(&profile_bin, &profile_bin_old)?;
         true
     } else {
         false
     };
 
-    // Step 4: Move bin.new → bin (the atomic swap)
-    match std::fs::rename(&profile_bin_new, &profile_bin) {
+    // Move target.new → target (the atomic swap)
+    match std::fs::rename(&target_new, target) {
         Ok(()) => {
-            // Success! Clean up old profile
+            // Success! Clean up old directory
             if had_old {
-                cleanup_path(&profile_bin_old);
+                cleanup_path(&target_old);
             }
-            cleanup_path(&PathBuf::from(STAGING_DIR));
-            Ok(count)
+            Ok(())
         }
         Err(e) => {
-            // Step 4 failed — roll back step 3
+            // Swap failed — roll back the previous rename
             if had_old {
-                let _ = std::fs::rename(&profile_bin_old, &profile_bin);
+                let _ = std::fs::rename(&target_old, target);
             }
-            cleanup_path(&profile_bin_new);
-            cleanup_path(&PathBuf::from(STAGING_DIR));
+            cleanup_path(&target_new);
             Err(format!("atomic swap failed: {e}").into())
         }
     }
@@ -633,37 +694,62 @@ fn fallback_profile_rebuild(packages: &[Package]) -> Result<u32, Box<dyn std::er
 /// Config files tracked in the manifest are "managed" — we own them and can
 /// overwrite. Files NOT in the manifest are left alone (user modifications).
 ///
-/// For changed files, we write the new content from the manifest's rootTree.
-/// Since we're running on a live system, the actual file content comes from
-/// comparing the on-disk file hash against the manifest hashes.
+/// The manifest only records hashes, so the content of added and changed
+/// files is copied from the new generation's rootTree (`root_tree`) into
+/// `target_root`, or taken from `etc` for files declared with
+/// `environment.etc`. Files that can't be resolved are reported in
+/// `warnings` and left as they are, and so are changed files whose content
+/// matches neither manifest (local edits), unless `force` is set. Returns
+/// the number of files written or removed.
+#[allow(clippy::too_many_arguments)]
 fn update_config_files(
     added: &[String],
     removed: &[String],
     changed: &[ConfigChange],
     new_files: &BTreeMap<String, FileInfo>,
+    etc: &BTreeMap<String, EtcSource>,
+    root_tree: Option<&Path>,
+    target_root: &Path,
+    force: bool,
     warnings: &mut Vec<String>,
 ) -> u32 {
     let mut updated = 0u32;
 
+    let install = |path: &str| match (new_files.get(path), etc.get(path), root_tree) {
+        (Some(info), Some(source), _) => install_etc_file(source, target_root, path, info),
+        (Some(info), None, Some(tree)) => install_config_file(tree, target_root, path, info),
+        _ => Err("no rootTree to copy it from".to_string()),
+    };
+
     // Handle added config files
-    // Note: we can't create files from thin air — the manifest only has hashes.
-    // Added files are only relevant if they exist in the rootTree (which was
-    // already written to disk by the build process). For live activation,
-    // new config files come from the new rootTree's store path.
     for path in added {
-        // The file should already exist if the rootTree was properly deployed.
-        // Just verify it's there.
-        let full_path = PathBuf::from("/").join(path);
-        if !full_path.exists() {
-            warnings.push(format!(
-                "new config file /{path} not found on disk (expected from rootTree)"
-            ));
+        let full_path = target_root.join(path);
+        let deployed = if etc.contains_key(path) {
+            // Declared files replace whatever was there before.
+            hash_file_if_exists(&full_path).as_deref()
+                == new_files.get(path).map(|info| info.blake3.as_str())
+        } else {
+            // E.g. the rootTree was written by the installer.
+            full_path.exists()
+        };
+        if deployed {
+            continue;
+        }
+        let installed = install(path.as_str());
+        match installed {
+            Ok(()) => {
+                updated += 1;
+                eprintln!("  added /{path}");
+            }
+            Err(e) => warnings.push(format!(
+                "new config file /{path} not found on disk (expected from rootTree): {e}"
+            )),
         }
     }
 
     // Handle removed config files
     for path in removed {
-        let full_path = PathBuf::from("/").join(path);
+        let full_path = target_root.join(path);
         if full_path.exists() {
             match std::fs::remove_file(&full_path) {
                 Ok(()) => {
@@ -678,29 +764,32 @@ fn update_config_files(
     }
 
     // Handle changed config files
-    // For live systems, changed config files need their content from the new
-    // rootTree. Since we track the hash but not the content in the manifest,
-    // we check if the on-disk file already has the new hash (rootTree deployed
-    // it) or if it needs updating from the new store path.
     for change in changed {
-        let full_path = PathBuf::from("/").join(&change.path);
-        if full_path.exists() {
-            // Check if file already has the new content (rootTree was deployed)
-            match hash_file_if_exists(&full_path) {
-                Some(hash) if hash == change.new_hash => {
-                    // Already up to date (rootTree deployed this file)
-                }
-                _ => {
-                    // File exists but has different content.
-                    // We can't update it without the new content bytes.
-                    // Flag it for the user.
-                    warnings.push(format!(
-                        "config file /{} needs update (hash mismatch) — redeploy rootTree or reboot",
-                        change.path
-                    ));
-                }
+        let full_path = target_root.join(&change.path);
+        let on_disk = hash_file_if_exists(&full_path);
+        if on_disk.as_deref() == Some(change.new_hash.as_str()) {
+            // Already up to date (rootTree deployed this file)
+            continue;
+        }
+        let edited = on_disk
+            .is_some_and(|hash| !change.old_hash.is_empty() && hash != change.old_hash);
+        if edited && !force {
+            warnings.push(format!(
+                "/{} was modified locally; keeping your version (use --force to replace it)",
+                change.path
+            ));
+            continue;
+        }
+        let installed = install(change.path.as_str());
+        match installed {
+            Ok(()) => {
+                updated += 1;
+                eprintln!("  updated /{}", change.path);
             }
-            updated += 1;
+            Err(e) => warnings.push(format!(
+                "config file /{} needs update ({e}) — redeploy rootTree or reboot",
+                change.path
+            )),
         }
     }
 
@@ -711,6 +800,87 @@ fn update_config_files(
     updated
 }
 
+/// Copy `path` from `root_tree` to `target_root`, atomically.
+///
+/// The rootTree copy must match the manifest hash.
+fn install_config_file(
+    root_tree: &Path,
+    target_root: &Path,
+    path: &str,
+    info: &FileInfo,
+) -> Result<(), String> {
+    let source = root_tree.join(path);
+    if !source.is_file() {
+        return Err(format!("{} is not in the rootTree", source.display()));
+    }
+    let contents =
+        std::fs::read(&source).map_err(|e| format!("reading {}: {e}", source.display()))?;
+    if blake3::hash(&contents).to_hex().as_str() != info.blake3 {
+        return Err(format!("{} does not match the manifest hash", source.display()));
+    }
+
+    write_config_file(target_root, path, info, &contents)
+}
+
+/// Write an `environment.etc` file from its inline text or store path.
+fn install_etc_file(
+    source: &EtcSource,
+    target_root: &Path,
+    path: &str,
+    info: &FileInfo,
+) -> Result<(), String> {
+    let contents = match source {
+        EtcSource::Text(text) => text.clone().into_bytes(),
+        EtcSource::Source(store_path) => {
+            std::fs::read(store_path).map_err(|e| format!("reading {store_path}: {e}"))?
+        }
+    };
+    if blake3::hash(&contents).to_hex().as_str() != info.blake3 {
+        return Err(format!("/{path} does not match the manifest hash"));
+    }
+
+    write_config_file(target_root, path, info, &contents)
+}
+
+/// Write `contents` to `path` under `target_root`, atomically.
+///
+/// The file is written to a temporary file next to the target, given the
+/// manifest mode, and renamed over the target, so readers never see a
+/// partially written file.
+fn write_config_file(
+    target_root: &Path,
+    path: &str,
+    info: &FileInfo,
+    contents: &[u8],
+) -> Result<(), String> {
+    let dest = target_root.join(path);
+    let file_name = dest
+        .file_name()
+        .ok_or_else(|| format!("invalid config file path: {path}"))?
+        .to_string_lossy()
+        .into_owned();
+    let tmp = dest.with_file_name(format!(".{file_name}.snix-new"));
+
+    let write = || -> std::io::Result<()> {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&tmp, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Ok(mode) = u32::from_str_radix(&info.mode, 8) {
+                std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+            }
+        }
+        std::fs::rename(&tmp, &dest)
+    };
+    write().map_err(|e| {
+        cleanup_path(&tmp);
+        format!("writing {}: {e}", dest.display())
+    })
+}
+
 /// Hash a file if it exists, returning None on any error.
 fn hash_file_if_exists(path: &Path) -> Option<String> {
     use std::io::Read;
@@ -727,12 +897,46 @@ fn hash_file_if_exists(path: &Path) -> Option<String> {
     Some(hasher.finalize().to_hex().to_string())
 }
 
+// ═══════════════════════════════════════════════════════════════════════════
+// Activation Hooks
+// ═══════════════════════════════════════════════════════════════════════════
+
+/// Directory that relative hook script names resolve against: the new
+/// manifest's system profile, or the default profile location.
+fn hook_base_dir(manifest: &Manifest) -> PathBuf {
+    if manifest.system_profile.is_empty() {
+        Path::new(SYSTEM_PROFILE_BIN)
+            .parent()
+            .unwrap_or(Path::new("/"))
+            .to_path_buf()
+    } else {
+        PathBuf::from(&manifest.system_profile)
+    }
+}
+
+/// Run one hook script and wait for it.
+///
+/// `script` is resolved against `base` unless it is absolute. Returns an
+/// error if the script can't be started or exits unsuccessfully.
+fn run_hook(script: &str, base: &Path) -> Result<(), String> {
+    let path = base.join(script);
+    let status = std::process::Command::new(&path)
+        .status()
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{}: {status}", path.display()))
+    }
+}
+
 // ═══════════════════════════════════════════════════════════════════════════
 // Helpers
 // ═══════════════════════════════════════════════════════════════════════════
 
 /// Remove a path (file or directory) silently.
-fn cleanup_path(path: &Path) {
+pub(crate) fn cleanup_path(path: &Path) {
     if path.is_dir() {
         let _ = std::fs::remove_dir_all(path);
     } else if path.exists() || path.symlink_metadata().is_ok() {
@@ -840,6 +1044,8 @@ mod tests {
             services: Services {
                 init_scripts: vec!["10_net".to_string(), "15_dhcp".to_string()],
                 startup_script: "/startup.sh".to_string(),
+                pre_activation_scripts: vec![],
+                post_activation_scripts: vec![],
             },
             files: BTreeMap::from([
                 (
@@ -859,7 +1065,9 @@ mod tests {
                     },
                 ),
             ]),
+            etc: BTreeMap::new(),
             system_profile: String::new(),
+            root_tree: String::new(),
         }
     }
 
@@ -1119,6 +1327,16 @@ mod tests {
         assert!(has_boot_config_changed(&old, &new));
     }
 
+    #[test]
+    fn reboot_recommended_for_driver_change() {
+        let old = sample_manifest();
+        let mut new = sample_manifest();
+        assert!(!reboot_recommended(&old, &new));
+
+        new.drivers.initfs.push("ahcid".to_string());
+        assert!(reboot_recommended(&old, &new));
+    }
+
     #[test]
     fn plan_boot_config_no_change() {
         let old = sample_manifest();
@@ -1291,6 +1509,205 @@ mod tests {
         assert_eq!(changed[0].path, "etc/passwd");
     }
 
+    /// Stage `files` (path, content) under `root`, returning their manifest entries.
+    fn stage(root: &Path, files: &[(&str, &str)], mode: &str) -> BTreeMap<String, FileInfo> {
+        let mut infos = BTreeMap::new();
+        for (path, content) in files {
+            let full = root.join(path);
+            std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+            std::fs::write(&full, content).unwrap();
+            infos.insert(
+                path.to_string(),
+                FileInfo {
+                    blake3: hash_file_if_exists(&full).unwrap(),
+                    size: content.len() as u64,
+                    mode: mode.to_string(),
+                },
+            );
+        }
+        infos
+    }
+
+    #[test]
+    fn update_config_files_copies_from_root_tree() {
+        let tree = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let old = stage(target.path(), &[("etc/hostname", "oldhost")], "644");
+        let new = stage(
+            tree.path(),
+            &[("etc/hostname", "newhost"), ("etc/motd", "welcome")],
+            "600",
+        );
+
+        let changed = vec![ConfigChange {
+            path: "etc/hostname".to_string(),
+            old_hash: old["etc/hostname"].blake3.clone(),
+            new_hash: new["etc/hostname"].blake3.clone(),
+        }];
+        let mut warnings = Vec::new();
+        let updated = update_config_files(
+            &["etc/motd".to_string()],
+            &[],
+            &changed,
+            &new,
+            &BTreeMap::new(),
+            Some(tree.path()),
+            target.path(),
+            false,
+            &mut warnings,
+        );
+
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(updated, 2);
+        let hostname = target.path().join("etc/hostname");
+        assert_eq!(std::fs::read_to_string(&hostname).unwrap(), "newhost");
+        assert_eq!(std::fs::read_to_string(target.path().join("etc/motd")).unwrap(), "welcome");
+        assert!(!target.path().join("etc/.hostname.snix-new").exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&hostname).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn update_config_files_warns_when_missing_from_root_tree() {
+        let tree = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        stage(target.path(), &[("etc/hostname", "oldhost")], "644");
+        // The manifest wants new content, but the rootTree doesn't have it.
+        let scratch = tempfile::tempdir().unwrap();
+        let new = stage(scratch.path(), &[("etc/hostname", "newhost")], "644");
+
+        let changed = vec![ConfigChange {
+            path: "etc/hostname".to_string(),
+            old_hash: String::new(),
+            new_hash: new["etc/hostname"].blake3.clone(),
+        }];
+        let mut warnings = Vec::new();
+        let updated = update_config_files(
+            &[],
+            &[],
+            &changed,
+            &new,
+            &BTreeMap::new(),
+            Some(tree.path()),
+            target.path(),
+            false,
+            &mut warnings,
+        );
+
+        assert_eq!(updated, 0);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("/etc/hostname needs update"), "{}", warnings[0]);
+        assert_eq!(
+            std::fs::read_to_string(target.path().join("etc/hostname")).unwrap(),
+            "oldhost"
+        );
+    }
+
+    #[test]
+    fn update_config_files_rejects_root_tree_hash_mismatch() {
+        let tree = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let mut new = stage(tree.path(), &[("etc/hostname", "newhost")], "644");
+        new.get_mut("etc/hostname").unwrap().blake3 = "0".repeat(64);
+
+        let mut warnings = Vec::new();
+        let updated = update_config_files(
+            &["etc/hostname".to_string()],
+            &[],
+            &[],
+            &new,
+            &BTreeMap::new(),
+            Some(tree.path()),
+            target.path(),
+            false,
+            &mut warnings,
+        );
+
+        assert_eq!(updated, 0);
+        assert!(warnings[0].contains("does not match the manifest hash"), "{}", warnings[0]);
+        assert!(!target.path().join("etc/hostname").exists());
+    }
+
+    #[test]
+    fn update_config_files_writes_declared_etc_text() {
+        let target = tempfile::tempdir().unwrap();
+        // A stale copy is already on disk; the declared text replaces it.
+        stage(target.path(), &[("etc/motd", "old")], "644");
+        let scratch = tempfile::tempdir().unwrap();
+        let new = stage(scratch.path(), &[("etc/motd", "welcome\n")], "644");
+        let etc = BTreeMap::from([(
+            "etc/motd".to_string(),
+            EtcSource::Text("welcome\n".to_string()),
+        )]);
+
+        let mut warnings = Vec::new();
+        let updated = update_config_files(
+            &["etc/motd".to_string()],
+            &[],
+            &[],
+            &new,
+            &etc,
+            None,
+            target.path(),
+            false,
+            &mut warnings,
+        );
+
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(updated, 1);
+        assert_eq!(
+            std::fs::read_to_string(target.path().join("etc/motd")).unwrap(),
+            "welcome\n"
+        );
+    }
+
+    #[test]
+    fn update_config_files_keeps_local_edits() {
+        let tree = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let scratch = tempfile::tempdir().unwrap();
+        let old = stage(scratch.path(), &[("etc/hostname", "oldhost")], "644");
+        let new = stage(tree.path(), &[("etc/hostname", "newhost")], "644");
+        // Matches neither manifest: someone edited it by hand.
+        stage(target.path(), &[("etc/hostname", "myhost")], "644");
+
+        let changed = vec![ConfigChange {
+            path: "etc/hostname".to_string(),
+            old_hash: old["etc/hostname"].blake3.clone(),
+            new_hash: new["etc/hostname"].blake3.clone(),
+        }];
+        let run = |force, warnings: &mut Vec<String>| {
+            update_config_files(
+                &[],
+                &[],
+                &changed,
+                &new,
+                &BTreeMap::new(),
+                Some(tree.path()),
+                target.path(),
+                force,
+                warnings,
+            )
+        };
+        let hostname = target.path().join("etc/hostname");
+
+        let mut warnings = Vec::new();
+        assert_eq!(run(false, &mut warnings), 0);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("/etc/hostname was modified locally"), "{}", warnings[0]);
+        assert_eq!(std::fs::read_to_string(&hostname).unwrap(), "myhost");
+
+        let mut warnings = Vec::new();
+        assert_eq!(run(true, &mut warnings), 1);
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(std::fs::read_to_string(&hostname).unwrap(), "newhost");
+    }
+
     // ── User diff tests ──
 
     #[test]
@@ -1395,6 +1812,125 @@ mod tests {
         assert!(!sub.exists());
     }
 
+    #[test]
+    fn swap_dir_into_place_replaces_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("bin");
+        let staged = dir.path().join("staging");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("old-tool"), "old").unwrap();
+        std::fs::create_dir_all(&staged).unwrap();
+        std::fs::write(staged.join("new-tool"), "new").unwrap();
+
+        swap_dir_into_place(&staged, &target).unwrap();
+
+        assert!(target.join("new-tool").exists());
+        assert!(!target.join("old-tool").exists());
+        assert!(!staged.exists());
+        assert!(!dir.path().join("bin.new").exists());
+        assert!(!dir.path().join("bin.old").exists());
+    }
+
+    #[test]
+    fn swap_dir_into_place_without_existing_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("profile/bin");
+        let staged = dir.path().join("staging");
+        std::fs::create_dir_all(&staged).unwrap();
+
+        swap_dir_into_place(&staged, &target).unwrap();
+        assert!(target.is_dir());
+    }
+
+    #[test]
+    fn swap_dir_into_place_missing_staging_keeps_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("bin");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("tool"), "data").unwrap();
+
+        assert!(swap_dir_into_place(&dir.path().join("missing"), &target).is_err());
+        assert!(target.join("tool").exists());
+    }
+
+    // ── Hook tests ──
+
+    /// Write an executable shell script into `dir`.
+    fn write_hook(dir: &Path, name: &str, body: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn run_hook_success_touches_sentinel() {
+        let dir = tempfile::tempdir().unwrap();
+        let sentinel = dir.path().join("ran");
+        write_hook(dir.path(), "hook", &format!("touch {}", sentinel.display()));
+
+        run_hook("hook", dir.path()).unwrap();
+        assert!(sentinel.exists());
+    }
+
+    #[test]
+    fn run_hook_nonzero_exit_is_error() {
+        let dir = tempfile::tempdir().unwrap();
+        write_hook(dir.path(), "fail", "exit 3");
+
+        let err = run_hook("fail", dir.path()).unwrap_err();
+        assert!(err.contains("fail"), "{err}");
+    }
+
+    #[test]
+    fn run_hook_missing_script_is_error() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(run_hook("does-not-exist", dir.path()).is_err());
+    }
+
+    #[test]
+    fn run_hook_absolute_path_ignores_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let sentinel = dir.path().join("ran");
+        write_hook(dir.path(), "hook", &format!("touch {}", sentinel.display()));
+        let absolute = dir.path().join("hook").to_string_lossy().to_string();
+
+        run_hook(&absolute, Path::new("/nonexistent")).unwrap();
+        assert!(sentinel.exists());
+    }
+
+    #[test]
+    fn failing_pre_hook_aborts_before_profile_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let sentinel = dir.path().join("second-ran");
+        write_hook(dir.path(), "fail", "exit 1");
+        write_hook(dir.path(), "second", &format!("touch {}", sentinel.display()));
+
+        let old = sample_manifest();
+        let mut new = sample_manifest();
+        new.system_profile = dir.path().to_string_lossy().to_string();
+        new.services.pre_activation_scripts = vec!["fail".to_string(), "second".to_string()];
+
+        let err = activate(&old, &new, false, false).unwrap_err().to_string();
+        assert!(err.contains("pre-activation hook failed"), "{err}");
+        // Later hooks don't run once one has failed.
+        assert!(!sentinel.exists());
+    }
+
+    #[test]
+    fn hook_base_dir_defaults_to_system_profile() {
+        let manifest = sample_manifest();
+        assert_eq!(hook_base_dir(&manifest), PathBuf::from("/nix/system/profile"));
+    }
+
+    #[test]
+    fn services_hooks_default_when_absent() {
+        let json = r#"{"initScripts": [], "startupScript": "/startup.sh"}"#;
+        let services: Services = serde_json::from_str(json).unwrap();
+        assert!(services.pre_activation_scripts.is_empty());
+        assert!(services.post_activation_scripts.is_empty());
+    }
+
     #[test]
     fn activation_result_defaults() {
         let result = ActivationResult {
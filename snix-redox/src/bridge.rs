@@ -157,6 +157,8 @@ pub fn rebuild_via_bridge(
                 &tmp_path,
                 Some("rebuild via bridge"),
                 false,
+                false,
+                false,
                 gen_dir,
                 Some(mpath),
             );
@@ -2,31 +2,59 @@
 //!
 //! Protocol:
 //!   1. GET /{hash}.narinfo → NarInfo metadata (store path, NAR hash, URL, compression)
-//!   2. GET /nar/{hash}.nar.{compression} → compressed NAR file
+//!   2. GET /nar/{hash}.nar.{compression} → compressed NAR file, written to
+//!      a `.part` file and resumed with `Range` requests if the transfer dies
 //!   3. Decompress → NAR reader → extract to /nix/store/
 //!
-//! Supports single-path and recursive (full closure) fetching.
+//! Supports single-path and recursive (full closure) fetching, from one
+//! cache or from several `Substituters` tried in `nix-cache-info` priority order.
+//! Download progress is reported through an optional [`ProgressFn`].
+//! Verified NARs are kept in a local [`NarCache`] keyed by NAR hash, so
+//! fetching the same content again (e.g. after a rollback) reads it from disk.
+//! Caches whose `nix-cache-info` advertises a `StoreDir` other than
+//! `/nix/store` are refused, since none of their paths would be usable.
+//! `snix store ping-cache` reports what a cache's `nix-cache-info` says and
+//! how long it takes to answer.
 //! Uses nix-compat for NarInfo parsing and NAR reading (sync).
 //! Uses ureq for HTTP (sync, no tokio).
 
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use nix_compat::narinfo::NarInfo;
 use nix_compat::nixbase32;
-use nix_compat::store_path::StorePath;
+use nix_compat::nixhash::NixHash;
+use nix_compat::store_path::{StorePath, STORE_DIR};
 use sha2::{Digest, Sha256};
 
-use crate::nar;
-use crate::pathinfo::PathInfoDb;
+use crate::nar::{self, Compression};
+use crate::nix_http::{self, NixHttpClient, RetryPolicy};
+use crate::pathinfo::{LockFile, PathInfoDb};
 use crate::store;
+use crate::store_root;
+
+/// Download progress callback: bytes downloaded so far, bytes to download
+/// in total (the sum of the narinfo `FileSize`s) and the store path being
+/// downloaded. Byte counts never decrease.
+pub type ProgressFn<'a> = dyn FnMut(u64, u64, &str) + 'a;
 
 /// Fetch and display narinfo for a store path.
+///
+/// With `check_store_dir`, the cache must be one for `/nix/store` (see
+/// [check_store_dir]).
 pub fn path_info(
     store_path_str: &str,
     cache_url: &str,
+    check_store_dir: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let sp = StorePath::<String>::from_absolute_path(store_path_str.as_bytes())?;
+    if check_store_dir {
+        self::check_store_dir(cache_url)?;
+    }
     let narinfo = fetch_narinfo(&sp, cache_url)?;
 
     println!("StorePath: {}", sp.to_absolute_path());
@@ -48,6 +76,9 @@ pub fn path_info(
     if let Some(fs) = narinfo.file_size {
         println!("FileSize:  {fs}");
     }
+    if let Some(ca) = &narinfo.ca {
+        println!("CA:        {}", ca.to_nix_nixbase32_string());
+    }
     println!("References:");
     for r in &narinfo.references {
         println!("  {}", r.to_absolute_path());
@@ -65,61 +96,204 @@ pub fn path_info(
 /// Fetch a single store path from a binary cache and install it.
 ///
 /// Downloads the NAR, decompresses it, extracts to /nix/store/,
-/// verifies the hash, and optionally registers the path.
+/// verifies the hash, and optionally registers the path. With
+/// `check_store_dir`, the cache must be one for `/nix/store` (see
+/// [check_store_dir]).
 pub fn fetch(
     store_path_str: &str,
     cache_url: &str,
+    check_store_dir: bool,
+    progress: Option<&mut ProgressFn<'_>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    fetch_inner(store_path_str, cache_url, None)
+    fetch_inner(store_path_str, cache_url, check_store_dir, None, progress)
 }
 
 /// Recursively fetch a store path and all its transitive dependencies.
 ///
 /// Uses BFS to discover and download the full closure. Each fetched
 /// path is registered in the PathInfo database so closures and GC work.
+/// Every path is looked up across all substituters independently, so a
+/// closure may be assembled from several caches.
+///
+/// The whole closure is queried before the first download, so `progress`
+/// is told the total up front.
 pub fn fetch_recursive(
     store_path_str: &str,
-    cache_url: &str,
+    substituters: &Substituters,
+    progress: Option<&mut ProgressFn<'_>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let db = PathInfoDb::open()?;
 
-    let mut queue: VecDeque<String> = VecDeque::new();
-    let mut visited: BTreeSet<String> = BTreeSet::new();
     let mut fetched_count: u32 = 0;
     let mut skipped_count: u32 = 0;
     let mut total_nar_size: u64 = 0;
 
-    queue.push_back(store_path_str.to_string());
+    let mut entries = Vec::new();
+    walk_closure(store_path_str, substituters, &db, &on_disk, |entry| {
+        entries.push(entry);
+        Ok(())
+    })?;
+    let download_size = entries
+        .iter()
+        .map(|entry| match entry {
+            ClosureEntry::Remote { path, narinfo, .. } if !on_disk(path) => download_size(narinfo),
+            _ => 0,
+        })
+        .sum();
+    let mut progress = Progress::new(progress, download_size);
+
+    for entry in entries {
+        let (path, cache_url, narinfo) = match entry {
+            ClosureEntry::Local(path) => {
+                skipped_count += 1;
+                eprintln!("✓ already present: {path}");
+                continue;
+            }
+            ClosureEntry::Remote { path, cache_url, narinfo } => (path, cache_url, narinfo),
+        };
 
-    while let Some(path) = queue.pop_front() {
-        if visited.contains(&path) {
-            continue;
+        // Download and extract if not already on disk
+        if !on_disk(&path) {
+            let sp = StorePath::<String>::from_absolute_path(path.as_bytes())?;
+            store::ensure_store_dir()?;
+            substituters.install(&sp, cache_url, &narinfo, Some(&db), &mut progress)?;
+        } else {
+            // Present on disk but not registered — register it
+            store::register_narinfo(&db, &path, &narinfo, Vec::new())?;
+            eprintln!("✓ registered: {path}");
+            skipped_count += 1;
         }
-        visited.insert(path.clone());
 
-        // Check if already present locally
-        let already_present = std::path::Path::new(&path).exists();
-        let already_registered = db.is_registered(&path);
+        total_nar_size += narinfo.nar_size;
+        fetched_count += 1;
+    }
 
-        if already_present && already_registered {
-            skipped_count += 1;
-            eprintln!("✓ already present: {path}");
+    eprintln!();
+    eprintln!(
+        "Done: {} fetched, {} already present, {} total NAR size",
+        fetched_count,
+        skipped_count,
+        human_size(total_nar_size),
+    );
 
+    Ok(())
+}
+
+/// Report what `fetch_recursive` would download, without fetching any NAR.
+///
+/// Only narinfo is queried. Paths already on disk, registered or not, are
+/// counted separately and left out of the totals.
+pub fn fetch_recursive_dry_run(
+    store_path_str: &str,
+    substituters: &Substituters,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let size = closure_size(store_path_str, substituters, &db, &on_disk)?;
+
+    println!("{} paths to fetch, {} already present", size.paths, size.present);
+    println!("Download size: {}", human_size(size.download_size));
+    println!("Unpacked size: {}", human_size(size.nar_size));
+
+    Ok(())
+}
+
+/// Totals for the part of a closure that is missing locally.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ClosureSize {
+    /// Paths that would be fetched
+    paths: u32,
+    /// Paths already on disk; unregistered ones only need registering
+    present: u32,
+    /// Sum of `FileSize` (compressed), falling back to `NarSize` when absent
+    download_size: u64,
+    /// Sum of `NarSize`
+    nar_size: u64,
+}
+
+fn closure_size(
+    store_path_str: &str,
+    substituters: &Substituters,
+    db: &PathInfoDb,
+    on_disk: &dyn Fn(&str) -> bool,
+) -> Result<ClosureSize, Box<dyn std::error::Error>> {
+    let mut size = ClosureSize::default();
+
+    walk_closure(store_path_str, substituters, db, on_disk, |entry| {
+        match entry {
+            ClosureEntry::Local(_) => size.present += 1,
+            ClosureEntry::Remote { path, .. } if on_disk(&path) => size.present += 1,
+            ClosureEntry::Remote { narinfo, .. } => {
+                size.paths += 1;
+                size.download_size += download_size(&narinfo);
+                size.nar_size += narinfo.nar_size;
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(size)
+}
+
+/// Bytes to download for `narinfo`: its `FileSize`, falling back to
+/// `NarSize` when absent.
+fn download_size(narinfo: &NarInfo<'_>) -> u64 {
+    narinfo.file_size.unwrap_or(narinfo.nar_size)
+}
+
+/// Whether a store path exists in the local filesystem.
+fn on_disk(path: &str) -> bool {
+    store_root::real_path(path).exists()
+}
+
+/// A path met while walking a closure.
+enum ClosureEntry<'a> {
+    /// Present on disk and registered; its references come from the db.
+    Local(String),
+    /// Missing or unregistered, with narinfo from the cache that has it.
+    Remote {
+        path: String,
+        cache_url: &'a str,
+        narinfo: Box<NarInfo<'static>>,
+    },
+}
+
+/// BFS over the closure of `root`, calling `visit` once per path.
+///
+/// References of local paths are read from `db`, the rest from narinfo,
+/// so nothing but narinfo is downloaded here.
+fn walk_closure<'a>(
+    root: &str,
+    substituters: &'a Substituters,
+    db: &PathInfoDb,
+    on_disk: &dyn Fn(&str) -> bool,
+    mut visit: impl FnMut(ClosureEntry<'a>) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut queue: VecDeque<String> = VecDeque::new();
+    let mut visited: BTreeSet<String> = BTreeSet::new();
+
+    queue.push_back(root.to_string());
+
+    while let Some(path) = queue.pop_front() {
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+
+        if on_disk(&path) && db.is_registered(&path) {
             // Still need to follow references for completeness
             if let Some(info) = db.get(&path)? {
-                for r in &info.references {
-                    if !visited.contains(r) {
-                        queue.push_back(r.clone());
+                for r in info.references {
+                    if !visited.contains(&r) {
+                        queue.push_back(r);
                     }
                 }
             }
+            visit(ClosureEntry::Local(path))?;
             continue;
         }
 
-        // Fetch narinfo first (we need references even if present on disk)
         let sp = StorePath::<String>::from_absolute_path(path.as_bytes())?;
-        let narinfo = match fetch_narinfo(&sp, cache_url) {
-            Ok(ni) => ni,
+        let (cache_url, narinfo) = match substituters.fetch_narinfo(&sp) {
+            Ok(found) => found,
             Err(e) => {
                 return Err(
                     format!("failed to fetch narinfo for {path}: {e}").into()
@@ -127,51 +301,16 @@ pub fn fetch_recursive(
             }
         };
 
-        // Extract references and enqueue them
-        let references: Vec<String> = narinfo
-            .references
-            .iter()
-            .map(|r| r.to_absolute_path())
-            .collect();
-
-        for r in &references {
-            if !visited.contains(r) {
-                queue.push_back(r.clone());
+        for r in &narinfo.references {
+            let r = r.to_absolute_path();
+            if !visited.contains(&r) {
+                queue.push_back(r);
             }
         }
 
-        // Download and extract if not already on disk
-        if !already_present {
-            fetch_inner(&path, cache_url, Some(&db))?;
-        } else if !already_registered {
-            // Present on disk but not registered — register it
-            let nar_hash_hex = data_encoding::HEXLOWER.encode(&narinfo.nar_hash);
-            let signatures: Vec<String> =
-                narinfo.signatures.iter().map(|s| s.to_string()).collect();
-            store::register_path(
-                &db,
-                &path,
-                &nar_hash_hex,
-                narinfo.nar_size,
-                references.clone(),
-                signatures,
-            )?;
-            eprintln!("✓ registered: {path}");
-            skipped_count += 1;
-        }
-
-        total_nar_size += narinfo.nar_size;
-        fetched_count += 1;
+        visit(ClosureEntry::Remote { path, cache_url, narinfo: Box::new(narinfo) })?;
     }
 
-    eprintln!();
-    eprintln!(
-        "Done: {} fetched, {} already present, {} total NAR size",
-        fetched_count,
-        skipped_count,
-        human_size(total_nar_size),
-    );
-
     Ok(())
 }
 
@@ -182,17 +321,23 @@ pub fn fetch_recursive(
 fn fetch_inner(
     store_path_str: &str,
     cache_url: &str,
+    check_store_dir: bool,
     db: Option<&PathInfoDb>,
+    progress: Option<&mut ProgressFn<'_>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let sp = StorePath::<String>::from_absolute_path(store_path_str.as_bytes())?;
     let dest = sp.to_absolute_path();
 
     // Check if already present
-    if std::path::Path::new(&dest).exists() {
+    if store_root::real_path(&dest).exists() {
         eprintln!("already exists: {dest}");
         return Ok(());
     }
 
+    if check_store_dir {
+        self::check_store_dir(cache_url)?;
+    }
+
     // Ensure /nix/store exists
     store::ensure_store_dir()?;
 
@@ -200,44 +345,103 @@ fn fetch_inner(
     eprintln!("fetching narinfo for {}...", sp.to_absolute_path());
     let narinfo = fetch_narinfo(&sp, cache_url)?;
 
-    // Fetch the NAR
+    let mut progress = Progress::new(progress, download_size(&narinfo));
+    install_nar(&sp, &narinfo, cache_url, db, &mut progress)
+}
+
+/// Download, decompress, verify and extract the NAR described by `narinfo`.
+///
+/// If `db` is `Some`, the path is registered after successful extraction.
+fn install_nar(
+    sp: &StorePath<String>,
+    narinfo: &NarInfo<'_>,
+    cache_url: &str,
+    db: Option<&PathInfoDb>,
+    progress: &mut Progress<'_, '_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dest = sp.to_absolute_path();
+    let target = store_root::real_path(&dest);
+
     let nar_url = format!("{}/{}", cache_url.trim_end_matches('/'), narinfo.url);
-    eprintln!("downloading {}...", narinfo.url);
+    let nar_cache = NarCache::open().ok();
+    let part = part_path(narinfo.url)?;
+    // Another snix fetching the same NAR would write to the same .part
+    // file. Wait for it instead, and keep what it installed.
+    let _lock = LockFile::acquire(part.with_extension("lock"), PART_LOCK_TIMEOUT)?;
+    if target.exists() {
+        eprintln!("already exists: {dest}");
+        return Ok(());
+    }
+    unpack_nar(&dest, &target, &nar_url, &part, narinfo, nar_cache.as_ref(), progress)?;
 
-    let resp = ureq::get(&nar_url).call()?;
-    let reader = resp.into_body().into_reader();
-
-    // Decompress based on compression type (pure Rust decompressors)
-    let decompressed: Box<dyn Read> = match narinfo.compression {
-        None | Some("none") => Box::new(reader),
-        Some("xz") => {
-            let mut input = io::BufReader::new(reader);
-            let mut output = Vec::new();
-            lzma_rs::xz_decompress(&mut input, &mut output)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("xz: {e}")))?;
-            Box::new(io::Cursor::new(output))
-        }
-        Some("zstd") | Some("zst") => Box::new(
-            ruzstd::decoding::StreamingDecoder::new(reader)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("zstd: {e}")))?,
-        ),
-        Some("bzip2") | Some("bz2") => Box::new(bzip2_rs::DecoderReader::new(reader)),
-        Some(other) => return Err(format!("unsupported compression: {other}").into()),
-    };
+    // Register in PathInfo database if provided
+    if let Some(db) = db {
+        store::register_narinfo(db, &dest, narinfo, Vec::new())?;
+    }
+
+    eprintln!("✓ verified and installed: {dest}");
+    Ok(())
+}
+
+/// Extract the NAR of `dest` to `target`, verifying its NAR hash.
+///
+/// The NAR comes from `nar_cache` when an earlier fetch left it there,
+/// otherwise it is downloaded from `nar_url` into `part` and added to the
+/// cache. A cached NAR that fails to extract or verify was damaged after it
+/// was cached: it is dropped from the cache and downloaded instead.
+fn unpack_nar(
+    dest: &str,
+    target: &Path,
+    nar_url: &str,
+    part: &Path,
+    narinfo: &NarInfo<'_>,
+    nar_cache: Option<&NarCache>,
+    progress: &mut Progress<'_, '_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut nar = open_nar(dest, nar_url, part, narinfo, nar_cache, progress)?;
+    let mut extracted = extract_verified(&mut nar, target, narinfo);
+    if let (Err(e), true, Some(cache)) = (&extracted, nar.cached, nar_cache) {
+        eprintln!("warning: discarding the cached NAR of {dest}: {e}");
+        cache.remove(&narinfo.nar_hash);
+        nar = open_nar(dest, nar_url, part, narinfo, nar_cache, progress)?;
+        extracted = extract_verified(&mut nar, target, narinfo);
+    }
+    // A download that doesn't extract to the NAR hash isn't resumed from.
+    let _ = fs::remove_file(part);
+    extracted?;
 
-    // Verify NAR hash while extracting
-    let mut hashing_reader = HashingReader::new(SendReader(decompressed));
+    if let Some(entry) = nar.entry {
+        if let Err(e) = entry.commit() {
+            eprintln!("warning: could not keep the NAR of {dest} in the local cache: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Extract `nar` to `target`, hashing it on the way. Nothing is left at
+/// `target` unless the hash is `narinfo`'s NAR hash.
+fn extract_verified(
+    nar: &mut OpenNar<'_>,
+    target: &Path,
+    narinfo: &NarInfo<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut hashing_reader = HashingReader::new(SendReader(CachingReader {
+        inner: &mut nar.reader,
+        entry: nar.entry.as_mut(),
+    }));
     let mut buf_reader = BufReader::new(&mut hashing_reader);
 
-    // Extract NAR to store path
-    eprintln!("extracting to {dest}...");
-    nar::extract(&mut buf_reader, &dest)?;
+    eprintln!("extracting to {}...", target.display());
+    if let Err(e) = nar::extract(&mut buf_reader, &target.to_string_lossy()) {
+        let _ = fs::remove_dir_all(target);
+        let _ = fs::remove_file(target);
+        return Err(e.into());
+    }
 
-    // Verify hash
     let actual_hash = hashing_reader.finalize();
-    if actual_hash != narinfo.nar_hash {
-        // Clean up on hash mismatch
-        let _ = std::fs::remove_dir_all(&dest);
+    if !NixHash::Sha256(actual_hash).verify_eq(&NixHash::Sha256(narinfo.nar_hash)) {
+        let _ = fs::remove_dir_all(target);
+        let _ = fs::remove_file(target);
         return Err(format!(
             "NAR hash mismatch!\n  expected: {}\n  got:      {}",
             data_encoding::HEXLOWER.encode(&narinfo.nar_hash),
@@ -245,26 +449,226 @@ fn fetch_inner(
         )
         .into());
     }
+    Ok(())
+}
 
-    // Register in PathInfo database if provided
-    if let Some(db) = db {
-        let nar_hash_hex = data_encoding::HEXLOWER.encode(&narinfo.nar_hash);
-        let references: Vec<String> = narinfo
-            .references
-            .iter()
-            .map(|r| r.to_absolute_path())
-            .collect();
-        let signatures: Vec<String> =
-            narinfo.signatures.iter().map(|s| s.to_string()).collect();
+/// An uncompressed NAR, as [open_nar] found it.
+struct OpenNar<'c> {
+    reader: Box<dyn Read + Send>,
+    /// Where a downloaded NAR is copied while it is read.
+    entry: Option<NarCacheEntry<'c>>,
+    /// Whether the NAR is a hit in the local cache.
+    cached: bool,
+}
 
-        store::register_path(db, &dest, &nar_hash_hex, narinfo.nar_size, references, signatures)?;
+/// The uncompressed NAR of `dest`: from `nar_cache` if it holds the NAR
+/// hash, otherwise downloaded from `nar_url` into `part`. A downloaded NAR
+/// comes with the [NarCacheEntry] to copy it into while it is read.
+fn open_nar<'c>(
+    dest: &str,
+    nar_url: &str,
+    part: &Path,
+    narinfo: &NarInfo<'_>,
+    nar_cache: Option<&'c NarCache>,
+    progress: &mut Progress<'_, '_>,
+) -> Result<OpenNar<'c>, Box<dyn std::error::Error>> {
+    progress.begin(dest, download_size(narinfo));
+    if let Some(file) = nar_cache.and_then(|cache| cache.get(&narinfo.nar_hash)) {
+        eprintln!("using cached NAR for {dest}");
+        progress.finish();
+        return Ok(OpenNar {
+            reader: Box::new(BufReader::new(file)),
+            entry: None,
+            cached: true,
+        });
+    }
+
+    eprintln!("downloading {}...", narinfo.url);
+    download_nar(nar_url, part, narinfo, &RetryPolicy::default(), progress)?;
+    progress.finish();
+
+    let reader = BufReader::new(File::open(part)?);
+    let decompressed = nar::decompress_nar(reader, Compression::parse(narinfo.compression)?)?;
+    let entry = nar_cache.and_then(|cache| cache.start(&narinfo.nar_hash).ok());
+    Ok(OpenNar {
+        reader: decompressed,
+        entry,
+        cached: false,
+    })
+}
+
+/// How long a fetch waits for another process downloading the same NAR.
+const PART_LOCK_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Where the download of the NAR at `nar_url` (relative, as in the narinfo)
+/// is staged: `/nix/var/snix/downloads/{file name}.part`, next to the
+/// `{file name}.lock` held while it is in use.
+fn part_path(nar_url: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = store_root::var_dir().join("downloads");
+    fs::create_dir_all(&dir)?;
+    let name = nar_url.rsplit('/').next().unwrap_or(nar_url);
+    Ok(dir.join(format!("{name}.part")))
+}
+
+/// Download `url` into `part`, resuming from the bytes already there.
+///
+/// Failed attempts are retried as `retry` describes, and only here: each
+/// attempt is one request, so that a transfer that dies mid-stream can be
+/// resumed with `Range: bytes=<n>-`. 4xx replies are final.
+/// If the server ignores the range and answers `200`, the file is
+/// rewritten from scratch; a `206` starting anywhere but at `<n>` has the
+/// next attempt start over, and so does a `416` unless the size of the
+/// file is unknown, in which case it was already complete. The assembled
+/// file is then checked against the narinfo: `NarHash` for uncompressed
+/// NARs, `FileHash` (when given) otherwise; compressed NARs are checked
+/// against `NarHash` on extraction.
+/// The file is hashed while it is written, and only read back if the
+/// download was resumed. A file that fails the check is deleted so the
+/// next fetch starts over.
+fn download_nar(
+    url: &str,
+    part: &Path,
+    narinfo: &NarInfo<'_>,
+    retry: &RetryPolicy,
+    progress: &mut Progress<'_, '_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = NixHttpClient::new().with_retry_policy(RetryPolicy::NONE);
+    let uncompressed = matches!(narinfo.compression, None | Some("none"));
+    let expected_size = if uncompressed {
+        Some(narinfo.nar_size)
+    } else {
+        narinfo.file_size
+    };
+
+    let mut streamed = None;
+    let mut last_err: Option<Box<dyn std::error::Error>> = None;
+    for attempt in 0..retry.max_attempts.max(1) {
+        if attempt > 0 {
+            std::thread::sleep(retry.delay(attempt - 1));
+        }
+        let have = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+        match expected_size {
+            Some(size) if have == size => {
+                last_err = None;
+                break;
+            }
+            Some(size) if have > size => fs::remove_file(part)?,
+            _ => {}
+        }
+
+        match download_once(&client, url, part, expected_size, progress) {
+            Ok(hash) => {
+                streamed = hash;
+                last_err = None;
+                break;
+            }
+            Err(e) if e.downcast_ref().is_some_and(|e| !nix_http::is_transient(e)) => {
+                return Err(format!("failed to download {url}: {e}").into());
+            }
+            Err(e) => {
+                eprintln!("download interrupted: {e}");
+                last_err = Some(e);
+            }
+        }
+    }
+    if let Some(e) = last_err {
+        return Err(format!("failed to download {url}: {e}").into());
+    }
+
+    let expected_hash = if uncompressed {
+        Some(narinfo.nar_hash)
+    } else {
+        narinfo.file_hash
+    };
+    if let Some(expected) = expected_hash {
+        let actual = match streamed {
+            Some(hash) => hash,
+            None => {
+                let mut hashing_reader = HashingReader::new(File::open(part)?);
+                io::copy(&mut hashing_reader, &mut io::sink())?;
+                NixHash::Sha256(hashing_reader.finalize())
+            }
+        };
+        if !actual.verify_eq(&NixHash::Sha256(expected)) {
+            let _ = fs::remove_file(part);
+            return Err(format!(
+                "downloaded file hash mismatch for {url}\n  expected: {}\n  got:      {}",
+                data_encoding::HEXLOWER.encode(&expected),
+                data_encoding::HEXLOWER.encode(actual.digest_as_bytes()),
+            )
+            .into());
+        }
     }
 
-    eprintln!("✓ verified and installed: {dest}");
     Ok(())
 }
 
+/// One GET of `url`, appending to `part` if the server honours the range.
+/// `expected_size` is the size of the whole file, if known.
+///
+/// Returns the SHA-256 of `part` if this request wrote all of it.
+fn download_once(
+    client: &NixHttpClient,
+    url: &str,
+    part: &Path,
+    expected_size: Option<u64>,
+    progress: &mut Progress<'_, '_>,
+) -> Result<Option<NixHash>, Box<dyn std::error::Error>> {
+    let have = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+
+    let mut headers = Vec::new();
+    if have > 0 {
+        headers.push(("Range", format!("bytes={have}-")));
+    }
+
+    let resp = match client.get(url, &headers) {
+        Ok(resp) => resp,
+        Err(ureq::Error::StatusCode(416)) if expected_size.is_none() => {
+            // Nothing is left past our last byte: we have all of it. The
+            // NAR hash is checked on extraction.
+            return Ok(None);
+        }
+        Err(ureq::Error::StatusCode(416)) => {
+            // Our partial file doesn't fit what the server has: start over.
+            fs::remove_file(part)?;
+            return Err("range not satisfiable, restarting download".into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let (file, offset) = if have > 0 && resp.status() == 206 {
+        if content_range_start(&resp) != Some(have) {
+            // Appending would splice in bytes from the wrong place.
+            fs::remove_file(part)?;
+            return Err(
+                format!("partial reply doesn't start at byte {have}, restarting download").into(),
+            );
+        }
+        (OpenOptions::new().append(true).open(part)?, have)
+    } else {
+        (File::create(part)?, 0)
+    };
+
+    let mut body = ProgressReader {
+        inner: resp.into_body().into_reader(),
+        progress,
+        read: offset,
+    };
+    let mut file = nar::HashingWriter::new(file);
+    io::copy(&mut body, &mut file)?;
+    Ok((offset == 0).then(|| file.finalize()))
+}
+
+/// First byte of a `Content-Range: bytes <first>-<last>/<size>` reply.
+fn content_range_start(resp: &ureq::http::Response<ureq::Body>) -> Option<u64> {
+    let range = resp.headers().get("content-range")?.to_str().ok()?;
+    range.strip_prefix("bytes ")?.split('-').next()?.parse().ok()
+}
+
 /// Fetch narinfo from binary cache.
+///
+/// Revalidates a previously downloaded copy with a conditional GET, so
+/// repeated queries for the same path only transfer headers.
 fn fetch_narinfo(
     sp: &StorePath<String>,
     cache_url: &str,
@@ -272,8 +676,7 @@ fn fetch_narinfo(
     let hash = nixbase32::encode(sp.digest());
     let url = format!("{}/{}.narinfo", cache_url.trim_end_matches('/'), hash);
 
-    let resp = ureq::get(&url).call()?;
-    let body = resp.into_body().read_to_string()?;
+    let body = NixHttpClient::new().get_string(&url)?;
 
     // NarInfo::parse borrows from the input string, so we need to leak it
     // to get a 'static lifetime. This is fine for a CLI tool.
@@ -283,6 +686,471 @@ fn fetch_narinfo(
     Ok(narinfo)
 }
 
+// ===== Local NAR Cache =====
+
+/// Size the local NAR cache is trimmed to after each insertion.
+const NAR_CACHE_MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Uncompressed NARs of earlier fetches, keyed by NAR hash, so switching
+/// back and forth between generations doesn't download the same content
+/// again: `/nix/var/snix/nar-cache/{nixbase32 NAR hash}.nar`.
+///
+/// Entries are only added once their hash was verified. A hit bumps the
+/// entry's mtime, and insertions evict the least recently used entries
+/// until the cache fits `max_size`.
+pub struct NarCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl NarCache {
+    /// The cache under the [store_root], capped at [NAR_CACHE_MAX_SIZE].
+    pub fn open() -> io::Result<Self> {
+        Self::open_at(store_root::var_dir().join("nar-cache"), NAR_CACHE_MAX_SIZE)
+    }
+
+    /// A cache in `dir` holding at most `max_size` bytes.
+    pub fn open_at(dir: PathBuf, max_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, max_size })
+    }
+
+    fn entry_path(&self, nar_hash: &[u8; 32]) -> PathBuf {
+        self.dir.join(format!("{}.nar", nixbase32::encode(nar_hash)))
+    }
+
+    /// The cached NAR with `nar_hash`, marked as just used.
+    pub fn get(&self, nar_hash: &[u8; 32]) -> Option<File> {
+        let file = File::open(self.entry_path(nar_hash)).ok()?;
+        let _ = file.set_modified(std::time::SystemTime::now());
+        Some(file)
+    }
+
+    /// Drop the NAR with `nar_hash`, e.g. because it no longer matches it.
+    pub fn remove(&self, nar_hash: &[u8; 32]) {
+        let _ = fs::remove_file(self.entry_path(nar_hash));
+    }
+
+    /// Start caching the NAR with `nar_hash`; see [NarCacheEntry].
+    fn start(&self, nar_hash: &[u8; 32]) -> io::Result<NarCacheEntry<'_>> {
+        let path = self.entry_path(nar_hash);
+        let tmp = path.with_extension(format!("nar.tmp-{}", std::process::id()));
+        Ok(NarCacheEntry {
+            cache: self,
+            file: Some(File::create(&tmp)?),
+            tmp,
+            path,
+        })
+    }
+
+    /// Remove the least recently used entries until the cache fits
+    /// `max_size`. Returns how many were removed.
+    pub fn evict(&self) -> io::Result<usize> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("nar") {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            entries.push((meta.modified()?, meta.len(), path));
+        }
+        entries.sort();
+
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        let mut removed = 0;
+        for (_, size, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            fs::remove_file(&path)?;
+            total -= size;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+/// A NAR on its way into the [NarCache], written to a temporary file as it
+/// is read for extraction. Only [NarCacheEntry::commit] moves it into
+/// place; dropping it, e.g. after a hash mismatch, throws the copy away.
+struct NarCacheEntry<'c> {
+    cache: &'c NarCache,
+    /// `None` once a write failed; the NAR is then not cached.
+    file: Option<File>,
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+impl NarCacheEntry<'_> {
+    fn write(&mut self, buf: &[u8]) {
+        if let Some(file) = self.file.as_mut() {
+            if io::Write::write_all(file, buf).is_err() {
+                self.file = None;
+            }
+        }
+    }
+
+    /// Add the NAR to the cache and trim the cache to its size.
+    fn commit(mut self) -> io::Result<()> {
+        if self.file.take().is_none() {
+            return Err(io::Error::other("writing the cached copy failed"));
+        }
+        fs::rename(&self.tmp, &self.path)?;
+        self.cache.evict()?;
+        Ok(())
+    }
+}
+
+impl Drop for NarCacheEntry<'_> {
+    fn drop(&mut self) {
+        // Gone already if the entry was committed.
+        let _ = fs::remove_file(&self.tmp);
+    }
+}
+
+/// Reader wrapper copying what is read into a [NarCacheEntry].
+struct CachingReader<'e, 'c, R> {
+    inner: R,
+    entry: Option<&'e mut NarCacheEntry<'c>>,
+}
+
+impl<R: Read> Read for CachingReader<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(entry) = self.entry.as_mut() {
+            entry.write(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+// ===== Substituters =====
+
+/// Priority assumed for caches whose `nix-cache-info` has none (Nix's default).
+const DEFAULT_PRIORITY: u32 = 50;
+
+/// A binary cache and its advertised priority (lower is preferred).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substituter {
+    pub url: String,
+    pub priority: u32,
+}
+
+/// Binary caches tried in priority order, like Nix's `substituters` setting.
+///
+/// A path is fetched from the first cache that has its narinfo; caches that
+/// answer 404 or can't be reached are skipped. If that cache's NAR can't be
+/// downloaded or doesn't verify, the next cache with the path is tried.
+#[derive(Debug, Clone)]
+pub struct Substituters {
+    caches: Vec<Substituter>,
+}
+
+impl Substituters {
+    /// Probe each cache's `/nix-cache-info` and order them by `Priority`.
+    ///
+    /// Each entry may itself be a comma-separated list of URLs. Caches that
+    /// are unreachable are skipped with a warning; caches without a
+    /// `nix-cache-info` (or without a `Priority` in it) get priority 50.
+    /// Ties keep the order they were given in.
+    ///
+    /// With `check_store_dir`, a cache for another store directory is an
+    /// error rather than a cache to skip: it is reachable, just wrong.
+    pub fn probe(
+        cache_urls: &[String],
+        check_store_dir: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut caches = Vec::new();
+
+        for url in split_cache_urls(cache_urls) {
+            match fetch_cache_info(&url) {
+                Ok(info) => {
+                    if check_store_dir {
+                        info.ensure_store_dir(&url)?;
+                    }
+                    caches.push(Substituter { url, priority: info.priority });
+                }
+                Err(e) => eprintln!("warning: skipping unreachable cache {url}: {e}"),
+            }
+        }
+
+        Ok(Self::from_ordered(caches))
+    }
+
+    /// Build from already-known caches, sorting them by priority.
+    pub fn from_ordered(mut caches: Vec<Substituter>) -> Self {
+        caches.sort_by_key(|c| c.priority);
+        Self { caches }
+    }
+
+    /// Fetch narinfo from the first cache that has it.
+    ///
+    /// Returns the URL of the cache that served it, so the NAR is fetched
+    /// from the same place.
+    pub fn fetch_narinfo(
+        &self,
+        sp: &StorePath<String>,
+    ) -> Result<(&str, NarInfo<'static>), Box<dyn std::error::Error>> {
+        let mut failures = Vec::new();
+
+        for cache in &self.caches {
+            match fetch_narinfo(sp, &cache.url) {
+                Ok(narinfo) => return Ok((&cache.url, narinfo)),
+                Err(e) => failures.push(format!("  {}: {e}", cache.url)),
+            }
+        }
+
+        if failures.is_empty() {
+            return Err("no reachable substituters".into());
+        }
+        Err(format!(
+            "{} not found in any substituter:\n{}",
+            sp.to_absolute_path(),
+            failures.join("\n")
+        )
+        .into())
+    }
+
+    /// Fetch a single store path from the first cache that has it.
+    ///
+    /// If `db` is `Some`, the path is registered after successful extraction.
+    pub fn fetch_from_any(
+        &self,
+        store_path_str: &str,
+        db: Option<&PathInfoDb>,
+        progress: Option<&mut ProgressFn<'_>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let sp = StorePath::<String>::from_absolute_path(store_path_str.as_bytes())?;
+        let dest = sp.to_absolute_path();
+
+        if store_root::real_path(&dest).exists() {
+            eprintln!("already exists: {dest}");
+            return Ok(());
+        }
+
+        store::ensure_store_dir()?;
+
+        eprintln!("fetching narinfo for {dest}...");
+        let (cache_url, narinfo) = self.fetch_narinfo(&sp)?;
+        if self.caches.len() > 1 {
+            eprintln!("substituting from {cache_url}");
+        }
+
+        let mut progress = Progress::new(progress, download_size(&narinfo));
+        self.install(&sp, cache_url, &narinfo, db, &mut progress)
+    }
+
+    /// Install `sp` from `cache_url`, which served `narinfo`, falling back
+    /// to the caches after it when the NAR can't be downloaded or verified.
+    fn install(
+        &self,
+        sp: &StorePath<String>,
+        cache_url: &str,
+        narinfo: &NarInfo<'_>,
+        db: Option<&PathInfoDb>,
+        progress: &mut Progress<'_, '_>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let e = match install_nar(sp, narinfo, cache_url, db, progress) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let dest = sp.to_absolute_path();
+        let mut failures = vec![format!("  {cache_url}: {e}")];
+        let mut failed = cache_url;
+
+        let later = self.caches.iter().skip_while(|c| c.url != cache_url).skip(1);
+        for cache in later {
+            let Ok(narinfo) = fetch_narinfo(sp, &cache.url) else {
+                continue;
+            };
+            eprintln!("warning: could not fetch {dest} from {failed}, trying {}", cache.url);
+            match install_nar(sp, &narinfo, &cache.url, db, progress) {
+                Ok(()) => return Ok(()),
+                Err(e) => failures.push(format!("  {}: {e}", cache.url)),
+            }
+            failed = &cache.url;
+        }
+
+        Err(format!("could not fetch the NAR of {dest}:\n{}", failures.join("\n")).into())
+    }
+}
+
+/// Flatten `--cache-url` values, splitting comma-separated lists.
+fn split_cache_urls(cache_urls: &[String]) -> Vec<String> {
+    cache_urls
+        .iter()
+        .flat_map(|s| s.split(','))
+        .map(|s| s.trim().trim_end_matches('/'))
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// What a cache's `nix-cache-info` advertises.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheInfo {
+    pub priority: u32,
+    /// `StoreDir`, if the cache names one.
+    pub store_dir: Option<String>,
+    /// `WantMassQuery`, if the cache names it.
+    pub want_mass_query: Option<bool>,
+}
+
+impl CacheInfo {
+    fn parse(body: &str) -> Self {
+        let field = |name: &str| {
+            body.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .map(str::trim)
+        };
+        Self {
+            priority: parse_cache_priority(body).unwrap_or(DEFAULT_PRIORITY),
+            store_dir: field("StoreDir").map(String::from),
+            want_mass_query: field("WantMassQuery").map(|v| v == "1"),
+        }
+    }
+
+    /// Refuse a cache built for another store directory: its paths, and
+    /// the references baked into their contents, point somewhere else.
+    fn ensure_store_dir(&self, cache_url: &str) -> Result<(), String> {
+        match &self.store_dir {
+            Some(dir) if dir.trim_end_matches('/') != STORE_DIR => Err(format!(
+                "binary cache {cache_url} is for store directory {dir}, not {STORE_DIR}; \
+                 its paths can't be used here (pass --no-check-store-dir to ignore this)"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Fetch a cache's `nix-cache-info`, once per URL per process.
+///
+/// An HTTP error status still proves the cache is reachable, so it falls
+/// back to the defaults (no `StoreDir`, priority 50); only transport
+/// failures that outlast the client's retries are errors, and those are
+/// not remembered.
+fn fetch_cache_info(cache_url: &str) -> Result<CacheInfo, ureq::Error> {
+    static SEEN: OnceLock<Mutex<HashMap<String, CacheInfo>>> = OnceLock::new();
+    let seen = SEEN.get_or_init(Default::default);
+    if let Some(info) = seen.lock().unwrap().get(cache_url) {
+        return Ok(info.clone());
+    }
+
+    let url = format!("{cache_url}/nix-cache-info");
+    let info = match NixHttpClient::new().get(&url, &[]) {
+        Ok(resp) => CacheInfo::parse(&resp.into_body().read_to_string()?),
+        Err(ureq::Error::StatusCode(_)) => CacheInfo::parse(""),
+        Err(e) => return Err(e),
+    };
+    seen.lock().unwrap().insert(cache_url.to_string(), info.clone());
+    Ok(info)
+}
+
+/// Check that the cache at `cache_url` serves paths for `/nix/store`, as
+/// Nix does before substituting from it. Caches without a `nix-cache-info`
+/// or without a `StoreDir` in it are assumed to.
+pub fn check_store_dir(cache_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let cache_url = cache_url.trim_end_matches('/');
+    fetch_cache_info(cache_url)?.ensure_store_dir(cache_url)?;
+    Ok(())
+}
+
+/// What [ping_cache] found out about a binary cache.
+#[derive(Debug, Clone)]
+pub struct CachePing {
+    pub info: CacheInfo,
+    /// Round trip of the `nix-cache-info` request.
+    pub latency: Duration,
+    /// Status of a HEAD on the probed path's narinfo, if one was given.
+    pub probe_status: Option<u16>,
+}
+
+/// Fetch the `nix-cache-info` of `cache_url`, timing the request, and
+/// HEAD the narinfo of `probe_path` if given.
+///
+/// Unlike substitution, which falls back to the defaults, a cache that
+/// doesn't serve `nix-cache-info` is an error naming the HTTP status.
+pub fn ping_cache(
+    cache_url: &str,
+    probe_path: Option<&str>,
+) -> Result<CachePing, Box<dyn std::error::Error>> {
+    let cache_url = cache_url.trim_end_matches('/');
+    let probe = probe_path
+        .map(|path| StorePath::<String>::from_absolute_path(path.as_bytes()))
+        .transpose()?;
+
+    let url = format!("{cache_url}/nix-cache-info");
+    let start = Instant::now();
+    let body = match ureq::get(&url).call() {
+        Ok(resp) => resp.into_body().read_to_string()?,
+        Err(ureq::Error::StatusCode(code)) => {
+            return Err(format!("{url}: server answered HTTP {code}").into())
+        }
+        Err(e) => return Err(format!("{url}: {e}").into()),
+    };
+    let latency = start.elapsed();
+
+    let probe_status = match probe {
+        Some(sp) => {
+            let url = format!("{cache_url}/{}.narinfo", nixbase32::encode(sp.digest()));
+            match ureq::head(&url).call() {
+                Ok(resp) => Some(resp.status().as_u16()),
+                Err(ureq::Error::StatusCode(code)) => Some(code),
+                Err(e) => return Err(format!("{url}: {e}").into()),
+            }
+        }
+        None => None,
+    };
+
+    Ok(CachePing {
+        info: CacheInfo::parse(&body),
+        latency,
+        probe_status,
+    })
+}
+
+/// `snix store ping-cache`: print what [ping_cache] found.
+pub fn run_ping_cache(
+    cache_url: &str,
+    probe_path: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ping = ping_cache(cache_url, probe_path)?;
+    let yes_no = |v: bool| if v { "yes" } else { "no" };
+
+    println!("Cache:         {}", cache_url.trim_end_matches('/'));
+    println!(
+        "StoreDir:      {}",
+        ping.info.store_dir.as_deref().unwrap_or("(not set)")
+    );
+    println!("Priority:      {}", ping.info.priority);
+    println!(
+        "WantMassQuery: {}",
+        ping.info.want_mass_query.map_or("(not set)", yes_no)
+    );
+    println!("Latency:       {} ms", ping.latency.as_millis());
+    if let (Some(path), Some(status)) = (probe_path, ping.probe_status) {
+        match status {
+            200 => println!("Probe:         {path} is in the cache"),
+            404 => println!("Probe:         {path} is not in the cache"),
+            code => println!("Probe:         {path}: HTTP {code}"),
+        }
+    }
+    if let Err(e) = ping.info.ensure_store_dir(cache_url) {
+        eprintln!("warning: {e}");
+    }
+
+    Ok(())
+}
+
+/// Extract `Priority: N` from a `nix-cache-info` body.
+fn parse_cache_priority(body: &str) -> Option<u32> {
+    body.lines()
+        .find_map(|line| line.strip_prefix("Priority:"))
+        .and_then(|v| v.trim().parse().ok())
+}
+
 // ===== Helpers =====
 
 /// Wrapper to make a reader also Send (ureq readers are Send).
@@ -325,6 +1193,92 @@ impl<R: Read> Read for HashingReader<R> {
     }
 }
 
+/// Running download totals across the NARs of one fetch.
+struct Progress<'a, 'b> {
+    report: Option<&'a mut ProgressFn<'b>>,
+    total: u64,
+    /// Bytes reported so far.
+    done: u64,
+    /// Bytes of the NARs already downloaded.
+    finished: u64,
+    /// Store path and expected size of the NAR being downloaded.
+    current: String,
+    current_size: u64,
+}
+
+impl<'a, 'b> Progress<'a, 'b> {
+    fn new(report: Option<&'a mut ProgressFn<'b>>, total: u64) -> Self {
+        Self { report, total, done: 0, finished: 0, current: String::new(), current_size: 0 }
+    }
+
+    /// Start downloading `path`, expected to be `size` bytes.
+    fn begin(&mut self, path: &str, size: u64) {
+        self.current = path.to_string();
+        self.current_size = size;
+        self.update(0);
+    }
+
+    /// `bytes` of the current NAR are on disk. A download that restarts
+    /// from scratch doesn't move the count back.
+    fn update(&mut self, bytes: u64) {
+        self.done = self.done.max(self.finished + bytes.min(self.current_size));
+        if let Some(report) = self.report.as_mut() {
+            report(self.done, self.total, &self.current);
+        }
+    }
+
+    /// The current NAR is complete.
+    fn finish(&mut self) {
+        self.finished += self.current_size;
+        self.update(0);
+    }
+}
+
+/// Reader wrapper reporting how far into the NAR the download is.
+struct ProgressReader<'p, 'a, 'b, R> {
+    inner: R,
+    progress: &'p mut Progress<'a, 'b>,
+    /// Bytes of the NAR on disk, including those of earlier attempts.
+    read: u64,
+}
+
+impl<R: Read> Read for ProgressReader<'_, '_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.read += n as u64;
+            self.progress.update(self.read);
+        }
+        Ok(n)
+    }
+}
+
+/// A [ProgressFn] drawing one line on stderr, e.g.
+/// `[ 42%] 1.2 MB / 2.9 MB hello-2.12.1`, for terminals.
+///
+/// The line is only redrawn when the percentage or the path changes, and
+/// ends with a carriage return, so the next message overwrites it.
+pub fn stderr_progress() -> impl FnMut(u64, u64, &str) {
+    let mut last: Option<(u64, String)> = None;
+    move |done, total, path| {
+        let percent = (done.min(total) * 100).checked_div(total).unwrap_or(100);
+        if last.as_ref().is_some_and(|(p, l)| *p == percent && l == path) {
+            return;
+        }
+        // The name is enough, and keeps the line shorter than the
+        // `extracting to /nix/store/…` that follows it.
+        let name = StorePath::<String>::from_absolute_path(path.as_bytes())
+            .map(|sp| sp.name().clone())
+            .unwrap_or_else(|_| path.to_string());
+        eprint!(
+            "\x1b[K[{percent:>3}%] {} / {} {name}\r",
+            human_size(done),
+            human_size(total)
+        );
+        last = Some((percent, path.to_string()));
+    }
+}
+
 /// Format bytes as human-readable size.
 fn human_size(bytes: u64) -> String {
     const KB: u64 = 1024;
@@ -490,6 +1444,564 @@ mod tests {
         assert_eq!(decompressed, b"hello");
     }
 
+    // ===== Substituter Tests =====
+
+    use crate::test_http;
+    use std::net::TcpListener;
+
+    const SUB_PATH: &str = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello-1.0";
+
+    fn sample_narinfo() -> String {
+        format!(
+            "StorePath: {SUB_PATH}\n\
+             URL: nar/hello.nar\n\
+             Compression: none\n\
+             NarHash: sha256:0c5b8vw40dy178xlpddw65q9gf1h2186jcc3p4swinwggbllv8mk\n\
+             NarSize: 120\n\
+             References: \n"
+        )
+    }
+
+    #[test]
+    fn parse_cache_priority_values() {
+        let info = "StoreDir: /nix/store\nWantMassQuery: 1\nPriority: 40\n";
+        assert_eq!(parse_cache_priority(info), Some(40));
+        assert_eq!(parse_cache_priority("StoreDir: /nix/store\n"), None);
+        assert_eq!(parse_cache_priority("Priority: high\n"), None);
+    }
+
+    #[test]
+    fn parse_cache_info_store_dir() {
+        let info = CacheInfo::parse("StoreDir: /nix/store\nPriority: 40\n");
+        assert_eq!(info.store_dir.as_deref(), Some("/nix/store"));
+        assert!(info.ensure_store_dir("https://a.example").is_ok());
+
+        // No nix-cache-info, or no StoreDir in it: assumed to match.
+        assert_eq!(
+            CacheInfo::parse(""),
+            CacheInfo { priority: 50, store_dir: None, want_mass_query: None }
+        );
+        assert!(CacheInfo::parse("").ensure_store_dir("https://a.example").is_ok());
+    }
+
+    #[test]
+    fn fetch_refuses_cache_for_other_store_dir() {
+        let cache = test_http::serve_files(&[
+            ("/nix-cache-info", "StoreDir: /gnu/store\nPriority: 40\n"),
+            ("/00bgd045z0d4icpbc2yyz4gx48ak44la.narinfo", &sample_narinfo()),
+        ]);
+
+        let err = fetch(SUB_PATH, &cache, true, None).unwrap_err().to_string();
+        assert!(err.contains("/gnu/store, not /nix/store"), "{err}");
+        assert!(err.contains("--no-check-store-dir"), "{err}");
+        assert!(path_info(SUB_PATH, &cache, true).is_err());
+        assert!(path_info(SUB_PATH, &cache, false).is_ok());
+
+        let err = Substituters::probe(&[cache.clone()], true).unwrap_err().to_string();
+        assert!(err.contains(&cache), "{err}");
+        let subs = Substituters::probe(&[cache], false).unwrap();
+        assert_eq!(subs.caches[0].priority, 40);
+    }
+
+    #[test]
+    fn cache_info_is_fetched_once_per_cache() {
+        let (cache, requests) = test_http::serve_sequence(vec![test_http::ok(
+            "StoreDir: /nix/store\nPriority: 30\n",
+        )]);
+
+        check_store_dir(&cache).unwrap();
+        // The server is gone; only a remembered answer gets through.
+        check_store_dir(&cache).unwrap();
+        let subs = Substituters::probe(&[cache], true).unwrap();
+        assert_eq!(subs.caches[0].priority, 30);
+        assert_eq!(requests.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn ping_cache_reports_info_and_probe() {
+        let cache = test_http::serve_files(&[
+            (
+                "/nix-cache-info",
+                "StoreDir: /nix/store\nWantMassQuery: 1\nPriority: 40\n",
+            ),
+            ("/00bgd045z0d4icpbc2yyz4gx48ak44la.narinfo", &sample_narinfo()),
+        ]);
+
+        let ping = ping_cache(&format!("{cache}/"), Some(SUB_PATH)).unwrap();
+        assert_eq!(
+            ping.info,
+            CacheInfo {
+                priority: 40,
+                store_dir: Some("/nix/store".to_string()),
+                want_mass_query: Some(true),
+            }
+        );
+        assert_eq!(ping.probe_status, Some(200));
+
+        let other = "/nix/store/3d8fkhz1wq2vk4a6ywmc1x6jb7p2xr9l-missing-1.0";
+        assert_eq!(ping_cache(&cache, Some(other)).unwrap().probe_status, Some(404));
+        assert_eq!(ping_cache(&cache, None).unwrap().probe_status, None);
+    }
+
+    #[test]
+    fn ping_cache_reports_status_of_missing_cache_info() {
+        let cache = test_http::serve_files(&[]);
+        let err = ping_cache(&cache, None).unwrap_err().to_string();
+        assert!(err.contains("nix-cache-info"), "{err}");
+        assert!(err.contains("HTTP 404"), "{err}");
+    }
+
+    #[test]
+    fn split_cache_urls_commas_and_flags() {
+        let urls = vec![
+            "https://a.example/, https://b.example".to_string(),
+            "https://c.example".to_string(),
+        ];
+        assert_eq!(
+            split_cache_urls(&urls),
+            vec!["https://a.example", "https://b.example", "https://c.example"]
+        );
+    }
+
+    #[test]
+    fn substituters_sorted_by_priority() {
+        let subs = Substituters::from_ordered(vec![
+            Substituter { url: "low".to_string(), priority: 50 },
+            Substituter { url: "high".to_string(), priority: 10 },
+            Substituter { url: "also-low".to_string(), priority: 50 },
+        ]);
+        let urls: Vec<&str> = subs.caches.iter().map(|c| c.url.as_str()).collect();
+        assert_eq!(urls, vec!["high", "low", "also-low"]);
+    }
+
+    #[test]
+    fn substituters_fall_back_on_404() {
+        let hash_path = "/00bgd045z0d4icpbc2yyz4gx48ak44la.narinfo";
+        let preferred = test_http::serve_files(&[(
+            "/nix-cache-info",
+            "StoreDir: /nix/store\nPriority: 10\n",
+        )]);
+        let fallback = test_http::serve_files(&[
+            ("/nix-cache-info", "StoreDir: /nix/store\nPriority: 40\n"),
+            (hash_path, &sample_narinfo()),
+        ]);
+
+        // Given in the "wrong" order: probing must sort by priority.
+        let subs = Substituters::probe(&[format!("{fallback},{preferred}")], true).unwrap();
+        assert_eq!(subs.caches[0].url, preferred);
+        assert_eq!(subs.caches[0].priority, 10);
+
+        let sp = StorePath::<String>::from_absolute_path(SUB_PATH.as_bytes()).unwrap();
+        let (served_by, narinfo) = subs.fetch_narinfo(&sp).unwrap();
+        assert_eq!(served_by, fallback);
+        assert_eq!(narinfo.nar_size, 120);
+    }
+
+    #[test]
+    fn substituters_skip_unreachable_cache() {
+        // Bind and immediately drop a listener to get a closed port.
+        let dead = {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", l.local_addr().unwrap())
+        };
+        let live = test_http::serve_files(&[]);
+
+        let subs = Substituters::probe(&[dead, live.clone()], true).unwrap();
+        assert_eq!(subs.caches.len(), 1);
+        assert_eq!(subs.caches[0].url, live);
+        assert_eq!(subs.caches[0].priority, DEFAULT_PRIORITY);
+    }
+
+    #[test]
+    fn substituters_all_missing_is_error() {
+        let only = test_http::serve_files(&[]);
+        let subs = Substituters::probe(&[only], true).unwrap();
+
+        let sp = StorePath::<String>::from_absolute_path(SUB_PATH.as_bytes()).unwrap();
+        let err = subs.fetch_narinfo(&sp).unwrap_err().to_string();
+        assert!(err.contains("not found in any substituter"), "{err}");
+    }
+
+    #[test]
+    fn dry_run_sums_missing_closure() {
+        // a -> b -> c, where b is already registered (with its reference to c).
+        let a = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-a-1.0";
+        let b = "/nix/store/11bgd045z0d4icpbc2yyz4gx48ak44la-b-1.0";
+        let c = "/nix/store/22bgd045z0d4icpbc2yyz4gx48ak44la-c-1.0";
+        let narinfo = |path: &str, refs: &str, file_size: u64, nar_size: u64| {
+            format!(
+                "StorePath: {path}\n\
+                 URL: nar/x.nar.xz\n\
+                 Compression: xz\n\
+                 FileSize: {file_size}\n\
+                 NarHash: sha256:0c5b8vw40dy178xlpddw65q9gf1h2186jcc3p4swinwggbllv8mk\n\
+                 NarSize: {nar_size}\n\
+                 References: {refs}\n"
+            )
+        };
+        let cache = test_http::serve_files(&[
+            (
+                "/00bgd045z0d4icpbc2yyz4gx48ak44la.narinfo",
+                &narinfo(a, "11bgd045z0d4icpbc2yyz4gx48ak44la-b-1.0", 100, 400),
+            ),
+            (
+                "/11bgd045z0d4icpbc2yyz4gx48ak44la.narinfo",
+                &narinfo(b, "22bgd045z0d4icpbc2yyz4gx48ak44la-c-1.0", 1000, 4000),
+            ),
+            ("/22bgd045z0d4icpbc2yyz4gx48ak44la.narinfo", &narinfo(c, "", 10, 40)),
+        ]);
+        let subs = Substituters::from_ordered(vec![Substituter { url: cache, priority: 40 }]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+        store::register_path(&db, b, &"0".repeat(64), 4000, vec![c.to_string()], vec![], None)
+            .unwrap();
+
+        // c is on disk but unregistered: registering it downloads nothing.
+        let size = closure_size(a, &subs, &db, &|path| path != a).unwrap();
+        assert_eq!(
+            size,
+            ClosureSize { paths: 1, present: 2, download_size: 100, nar_size: 400 }
+        );
+
+        // A registered path missing from disk is fetched again.
+        let size = closure_size(a, &subs, &db, &|_| false).unwrap();
+        assert_eq!(
+            size,
+            ClosureSize { paths: 3, present: 0, download_size: 1110, nar_size: 4440 }
+        );
+    }
+
+    // ===== Resumable Download Tests =====
+
+    fn uncompressed_narinfo(nar: &[u8]) -> String {
+        format!(
+            "StorePath: {SUB_PATH}\n\
+             URL: nar/hello.nar\n\
+             Compression: none\n\
+             NarHash: sha256:{}\n\
+             NarSize: {}\n\
+             References: \n",
+            nixbase32::encode(&Sha256::digest(nar)),
+            nar.len()
+        )
+    }
+
+    /// Three attempts, without noticeable delays.
+    const QUICK: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(5),
+    };
+
+    fn download(
+        url: &str,
+        part: &Path,
+        narinfo: &NarInfo<'_>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("{url}/nar/hello.nar");
+        download_nar(&url, part, narinfo, &QUICK, &mut Progress::new(None, 0))
+    }
+
+    #[test]
+    fn download_retries_once_per_attempt() {
+        let nar = b"served after the network came up".to_vec();
+        let (url, heads) = test_http::serve_sequence(vec![
+            test_http::status("503 Service Unavailable"),
+            test_http::status("503 Service Unavailable"),
+            test_http::status("503 Service Unavailable"),
+            test_http::reply("200 OK", &format!("Content-Length: {}\r\n", nar.len()), &nar),
+        ]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.part");
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+
+        // Three attempts are three requests: the client doesn't retry too.
+        let err = download(&url, &part, &narinfo).unwrap_err();
+        assert!(err.to_string().contains("503"), "{err}");
+        assert_eq!(heads.try_iter().count(), 3);
+
+        let (url, heads) = test_http::serve_sequence(vec![
+            test_http::status("404 Not Found"),
+            test_http::reply("200 OK", &format!("Content-Length: {}\r\n", nar.len()), &nar),
+        ]);
+        assert!(download(&url, &part, &narinfo).is_err());
+        assert_eq!(heads.try_iter().count(), 1, "404 must not be retried");
+    }
+
+    #[test]
+    fn download_resumes_with_range() {
+        let nar: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let (first, rest) = nar.split_at(1000);
+
+        // The first response promises the whole NAR but dies after 1000 bytes.
+        let (url, heads) = test_http::serve_sequence(vec![
+            test_http::reply("200 OK", &format!("Content-Length: {}\r\n", nar.len()), first),
+            test_http::reply(
+                "206 Partial Content",
+                &format!(
+                    "Content-Length: {}\r\nContent-Range: bytes 1000-{}/{}\r\n",
+                    rest.len(),
+                    nar.len() - 1,
+                    nar.len()
+                ),
+                rest,
+            ),
+        ]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.part");
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+
+        download(&url, &part, &narinfo).unwrap();
+
+        let assembled = fs::read(&part).unwrap();
+        assert_eq!(Sha256::digest(&assembled).as_slice(), narinfo.nar_hash);
+        assert_eq!(assembled, nar);
+
+        assert!(!heads.recv().unwrap().contains("range:"));
+        assert!(heads.recv().unwrap().contains("range: bytes=1000-\r\n"));
+    }
+
+    #[test]
+    fn download_restarts_when_range_ignored() {
+        let nar = b"a complete NAR served from the start".to_vec();
+        let (url, heads) = test_http::serve_sequence(vec![test_http::reply(
+            "200 OK",
+            &format!("Content-Length: {}\r\n", nar.len()),
+            &nar,
+        )]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.part");
+        fs::write(&part, b"stale").unwrap();
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+
+        download(&url, &part, &narinfo).unwrap();
+
+        assert_eq!(fs::read(&part).unwrap(), nar);
+        assert!(heads.recv().unwrap().contains("range: bytes=5-"));
+    }
+
+    #[test]
+    fn download_restarts_when_range_misplaced() {
+        let nar = b"a complete NAR served from the start".to_vec();
+        let (url, heads) = test_http::serve_sequence(vec![
+            // Asked for byte 5 onwards, but answers with the start of the NAR.
+            test_http::reply(
+                "206 Partial Content",
+                &format!(
+                    "Content-Length: {}\r\nContent-Range: bytes 0-{}/{}\r\n",
+                    nar.len(),
+                    nar.len() - 1,
+                    nar.len()
+                ),
+                &nar,
+            ),
+            test_http::reply("200 OK", &format!("Content-Length: {}\r\n", nar.len()), &nar),
+        ]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.part");
+        fs::write(&part, b"stale").unwrap();
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+
+        download(&url, &part, &narinfo).unwrap();
+
+        assert_eq!(fs::read(&part).unwrap(), nar);
+        assert!(heads.recv().unwrap().contains("range: bytes=5-"));
+        assert!(!heads.recv().unwrap().contains("range:"));
+    }
+
+    #[test]
+    fn download_keeps_complete_part_of_unknown_size() {
+        let (url, heads) =
+            test_http::serve_sequence(vec![test_http::status("416 Range Not Satisfiable")]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.xz.part");
+        fs::write(&part, b"all of a compressed NAR").unwrap();
+        // Compressed, and no FileSize to tell whether the file is complete.
+        let narinfo_str =
+            uncompressed_narinfo(b"the NAR").replace("Compression: none", "Compression: xz");
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+
+        download(&url, &part, &narinfo).unwrap();
+
+        assert_eq!(fs::read(&part).unwrap(), b"all of a compressed NAR");
+        assert!(heads.recv().unwrap().contains("range: bytes=23-"));
+    }
+
+    #[test]
+    fn download_hash_mismatch_removes_part() {
+        let nar = b"the NAR the narinfo describes".to_vec();
+        let other = b"a different NAR, same length!".to_vec();
+        assert_eq!(nar.len(), other.len());
+        let (url, _heads) = test_http::serve_sequence(vec![test_http::reply(
+            "200 OK",
+            &format!("Content-Length: {}\r\n", other.len()),
+            &other,
+        )]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.part");
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+
+        let err = download(&url, &part, &narinfo).unwrap_err();
+        assert!(err.to_string().contains("hash mismatch"), "{err}");
+        assert!(!part.exists());
+    }
+
+    #[test]
+    fn download_reports_progress() {
+        let nar: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let (first, rest) = nar.split_at(30_000);
+        let (url, _heads) = test_http::serve_sequence(vec![
+            test_http::reply("200 OK", &format!("Content-Length: {}\r\n", nar.len()), first),
+            test_http::reply(
+                "206 Partial Content",
+                &format!(
+                    "Content-Length: {}\r\nContent-Range: bytes 30000-{}/{}\r\n",
+                    rest.len(),
+                    nar.len() - 1,
+                    nar.len()
+                ),
+                rest,
+            ),
+        ]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.part");
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+
+        let mut calls = Vec::new();
+        let mut record = |done: u64, total: u64, path: &str| calls.push((done, total, path.to_string()));
+        let report: &mut ProgressFn = &mut record;
+        let mut progress = Progress::new(Some(report), download_size(&narinfo));
+        progress.begin(SUB_PATH, download_size(&narinfo));
+        let nar_url = format!("{url}/nar/hello.nar");
+        download_nar(&nar_url, &part, &narinfo, &QUICK, &mut progress).unwrap();
+        progress.finish();
+        drop(progress);
+
+        let nar_size = nar.len() as u64;
+        assert!(calls.len() > 2, "{} calls", calls.len());
+        assert!(calls.windows(2).all(|w| w[0].0 <= w[1].0), "byte counts must not decrease");
+        assert!(calls.iter().all(|(_, total, path)| *total == nar_size && path == SUB_PATH));
+        assert_eq!(calls.last().unwrap().0, nar_size);
+    }
+
+    // ===== NAR Cache Tests =====
+
+    #[test]
+    fn cached_nar_is_not_downloaded_again() {
+        let nar = b"the NAR of a path shared by two generations".to_vec();
+        let (url, heads) = test_http::serve_sequence(vec![test_http::reply(
+            "200 OK",
+            &format!("Content-Length: {}\r\n", nar.len()),
+            &nar,
+        )]);
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+        let nar_url = format!("{url}/nar/hello.nar");
+
+        let tmp = tempfile::tempdir().unwrap();
+        let part = tmp.path().join("hello.nar.part");
+        let cache = NarCache::open_at(tmp.path().join("nar-cache"), 1 << 20).unwrap();
+        let cache = &cache;
+
+        let read = |progress: &mut Progress<'_, '_>| {
+            let OpenNar { reader, mut entry, .. } =
+                open_nar(SUB_PATH, &nar_url, &part, &narinfo, Some(cache), progress).unwrap();
+            let mut out = Vec::new();
+            CachingReader { inner: reader, entry: entry.as_mut() }
+                .read_to_end(&mut out)
+                .unwrap();
+            (out, entry)
+        };
+
+        let (first, entry) = read(&mut Progress::new(None, 0));
+        assert_eq!(first, nar);
+        entry.expect("a download is cached").commit().unwrap();
+        assert!(heads.recv().unwrap().starts_with("get /nar/hello.nar"));
+
+        // The mock only answers once, so another download would fail.
+        let (second, entry) = read(&mut Progress::new(None, 0));
+        assert_eq!(second, nar);
+        assert!(entry.is_none());
+        assert!(heads.try_recv().is_err());
+    }
+
+    #[test]
+    fn corrupt_cached_nar_is_downloaded_again() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("hello");
+        fs::write(&file, "#!/bin/sh\necho hello\n").unwrap();
+        let mut nar = Vec::new();
+        nar::dump(&file, &mut nar).unwrap();
+
+        let (url, heads) = test_http::serve_sequence(vec![test_http::reply(
+            "200 OK",
+            &format!("Content-Length: {}\r\n", nar.len()),
+            &nar,
+        )]);
+        let narinfo_str = uncompressed_narinfo(&nar);
+        let narinfo = NarInfo::parse(&narinfo_str).unwrap();
+        let nar_url = format!("{url}/nar/hello.nar");
+        let part = tmp.path().join("hello.nar.part");
+        let target = tmp.path().join("out");
+
+        // The cached copy was damaged after it was verified.
+        let cache = NarCache::open_at(tmp.path().join("nar-cache"), 1 << 20).unwrap();
+        let mut damaged = nar.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        fs::write(cache.entry_path(&narinfo.nar_hash), &damaged).unwrap();
+
+        let mut progress = Progress::new(None, 0);
+        unpack_nar(SUB_PATH, &target, &nar_url, &part, &narinfo, Some(&cache), &mut progress)
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(&target).unwrap(), "#!/bin/sh\necho hello\n");
+        assert!(heads.recv().unwrap().starts_with("get /nar/hello.nar"));
+        // The download replaced the damaged entry.
+        assert_eq!(fs::read(cache.entry_path(&narinfo.nar_hash)).unwrap(), nar);
+        assert!(!part.exists());
+    }
+
+    #[test]
+    fn nar_cache_evicts_least_recently_used() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = NarCache::open_at(tmp.path().to_path_buf(), 250).unwrap();
+        let (old, used, new) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+
+        // Three 100-byte entries, inserted in order a minute apart.
+        let start = std::time::SystemTime::now() - Duration::from_secs(3600);
+        for (i, hash) in [old, used, new].iter().enumerate() {
+            let mut entry = cache.start(hash).unwrap();
+            entry.write(&[0u8; 100]);
+            fs::rename(&entry.tmp, &entry.path).unwrap();
+            File::open(&entry.path)
+                .unwrap()
+                .set_modified(start + Duration::from_secs(60 * i as u64))
+                .unwrap();
+        }
+        // Reading the oldest entry makes it the most recently used.
+        assert!(cache.get(&old).is_some());
+
+        assert_eq!(cache.evict().unwrap(), 1);
+        assert!(cache.get(&used).is_none(), "least recently used entry is evicted");
+        assert!(cache.get(&old).is_some());
+        assert!(cache.get(&new).is_some());
+        assert_eq!(cache.evict().unwrap(), 0);
+    }
+
     #[test]
     fn human_size_formatting() {
         assert_eq!(human_size(0), "0 B");
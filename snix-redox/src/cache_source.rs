@@ -9,7 +9,8 @@
 //! CacheSource::Remote("http://10.0.2.2")   — fetches files via HTTP GET
 //! ```
 
-use std::io::{self, BufReader, Read};
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
 
 use nix_compat::narinfo::NarInfo;
@@ -17,6 +18,7 @@ use nix_compat::nixbase32;
 use nix_compat::store_path::StorePath;
 
 use crate::local_cache::{self, PackageIndex};
+use crate::nar::{self, Compression};
 
 /// Default local cache path on Redox.
 pub const DEFAULT_CACHE_PATH: &str = "/nix/cache";
@@ -114,8 +116,14 @@ impl CacheSource {
         &self,
         sp: &StorePath<String>,
     ) -> Result<NarInfo<'static>, Box<dyn std::error::Error>> {
-        let hash = nixbase32::encode(sp.digest());
+        self.fetch_narinfo_by_hash(&nixbase32::encode(sp.digest()))
+    }
 
+    /// Fetch narinfo by the nixbase32 hash part of a store path.
+    pub fn fetch_narinfo_by_hash(
+        &self,
+        hash: &str,
+    ) -> Result<NarInfo<'static>, Box<dyn std::error::Error>> {
         let body = match self {
             CacheSource::Local(path) => {
                 let narinfo_path = path.join(format!("{hash}.narinfo"));
@@ -178,74 +186,33 @@ impl CacheSource {
         narinfo: &NarInfo<'_>,
     ) -> Result<Box<dyn Read + Send>, Box<dyn std::error::Error>> {
         let reader = self.open_nar(narinfo)?;
-
-        match narinfo.compression {
-            None | Some("none") => Ok(reader),
-            Some("zstd") | Some("zst") => Ok(Box::new(
-                ruzstd::decoding::StreamingDecoder::new(reader)
-                    .map_err(|e| format!("zstd decompression error: {e}"))?,
-            )),
-            Some("xz") => {
-                let mut input = BufReader::new(reader);
-                let mut output = Vec::new();
-                lzma_rs::xz_decompress(&mut input, &mut output)
-                    .map_err(|e| format!("xz decompression error: {e}"))?;
-                Ok(Box::new(io::Cursor::new(output)))
-            }
-            Some("bzip2") | Some("bz2") => Ok(Box::new(bzip2_rs::DecoderReader::new(reader))),
-            Some(other) => Err(format!("unsupported compression: {other}").into()),
-        }
+        Ok(nar::decompress_nar(reader, Compression::parse(narinfo.compression)?)?)
     }
 
     // ── Search ─────────────────────────────────────────────────────────
 
     /// Search for packages matching an optional pattern.
     ///
-    /// Fetches the package index and filters by substring match on name/pname.
+    /// Fetches the package index and ranks matches on name/pname (and
+    /// description) as described in [`local_cache::search`].
     pub fn search(
         &self,
         pattern: Option<&str>,
+        regex: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let index = self.read_index()?;
-
-        let matches: Vec<_> = index
-            .packages
-            .iter()
-            .filter(|(name, entry)| match pattern {
-                Some(pat) => {
-                    let pat_lower = pat.to_lowercase();
-                    name.to_lowercase().contains(&pat_lower)
-                        || entry.pname.to_lowercase().contains(&pat_lower)
-                }
-                None => true,
-            })
-            .collect();
+        let compiled = local_cache::SearchPattern::parse(pattern, regex)?;
+        let matches = local_cache::rank_packages(&index, compiled.as_ref());
 
         if matches.is_empty() {
-            if let Some(pat) = pattern {
-                eprintln!("No packages matching '{pat}'");
-            } else {
-                eprintln!("No packages in cache at {}", self.display_name());
+            match pattern.filter(|p| !p.is_empty()) {
+                Some(pat) => eprintln!("No packages matching '{pat}'"),
+                None => eprintln!("No packages in cache at {}", self.display_name()),
             }
             return Ok(());
         }
 
-        println!("{} packages available:", matches.len());
-        println!();
-        for (name, entry) in &matches {
-            let size_str = match entry.file_size {
-                Some(s) => format_size(s),
-                None => "?".to_string(),
-            };
-            let installed = Path::new(&entry.store_path).exists();
-            let status = if installed { " [installed]" } else { "" };
-            println!(
-                "  {:<16} {:<12} {:>8}{}",
-                name, entry.version, size_str, status
-            );
-        }
-        println!();
-
+        local_cache::print_search_results(&matches);
         Ok(())
     }
 
@@ -298,6 +265,31 @@ impl CacheSource {
 
         Ok(())
     }
+
+    /// Print a cached package's runtime dependency tree, following
+    /// narinfo references through the cache.
+    pub fn show_package_tree(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let index = self.read_index()?;
+        let entry = index
+            .packages
+            .get(name)
+            .ok_or_else(|| format!("package '{name}' not found in {}", self.display_name()))?;
+
+        // Shared dependencies are looked up once, however often they appear.
+        let mut known: BTreeMap<String, Option<Vec<String>>> = BTreeMap::new();
+        let tree = local_cache::render_dependency_tree(&entry.store_path, |path| {
+            known
+                .entry(path.to_string())
+                .or_insert_with(|| {
+                    let sp = StorePath::<String>::from_absolute_path(path.as_bytes()).ok()?;
+                    let narinfo = self.fetch_narinfo(&sp).ok()?;
+                    Some(narinfo.references.iter().map(|r| r.to_absolute_path()).collect())
+                })
+                .clone()
+        });
+        print!("{tree}");
+        Ok(())
+    }
 }
 
 // ── Helpers ────────────────────────────────────────────────────────────────
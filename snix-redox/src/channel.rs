@@ -7,6 +7,7 @@
 //! Channel layout (remote):
 //!   https://example.com/redox/latest/
 //!     manifest.json          — system manifest
+//!     manifest.json.sig      — detached ed25519 signature of manifest.json
 //!     packages.json          — binary cache index (optional)
 //!
 //! Local state:
@@ -14,11 +15,18 @@
 //!     {name}/
 //!       manifest.json        — cached manifest
 //!       url                  — channel URL
+//!       public-key           — trusted key, `name:base64` (optional)
 //!       last-fetched         — timestamp of last fetch
+//!
+//! When a channel has a `public-key`, `update` refuses a manifest whose
+//! `manifest.json.sig` (a Nix-style `name:base64` signature over the raw
+//! manifest bytes) is missing or does not verify against that key.
 
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use nix_compat::narinfo::{SignatureRef, VerifyingKey};
+
 const CHANNELS_DIR: &str = "/nix/var/snix/channels";
 
 /// A registered channel.
@@ -31,17 +39,66 @@ pub struct Channel {
     pub manifest_path: PathBuf,
 }
 
+/// A fetched manifest failed signature verification against the channel key.
+#[derive(Debug)]
+pub struct VerificationError(String);
+
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for VerificationError {}
+
+/// How far a fetched manifest can be trusted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ManifestTrust {
+    /// Signed by the channel's trusted key (named here)
+    Verified(String),
+    /// The channel has no trusted key, so nothing was checked
+    Unsigned,
+}
+
 /// Add or update a channel registration.
 ///
 /// The URL points to a manifest.json (system configuration).
-/// Optionally, the channel can have a binary cache URL for fetching packages.
-pub fn add(name: &str, url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let channel_dir = Path::new(CHANNELS_DIR).join(name);
+/// Optionally, the channel can have a binary cache URL for fetching packages,
+/// and a trusted public key (`name:base64`, as produced by
+/// `nix-store --generate-binary-cache-key`) that manifests must be signed with.
+/// Re-adding a channel without a key keeps the key it already has.
+pub fn add(
+    name: &str,
+    url: &str,
+    public_key: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    add_at(Path::new(CHANNELS_DIR), name, url, public_key)
+}
+
+/// [add] with the channels kept in `channels_dir`.
+fn add_at(
+    channels_dir: &Path,
+    name: &str,
+    url: &str,
+    public_key: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(key) = public_key {
+        VerifyingKey::parse(key).map_err(|e| format!("invalid public key '{key}': {e}"))?;
+    }
+
+    let channel_dir = channels_dir.join(name);
     fs::create_dir_all(&channel_dir)?;
 
     fs::write(channel_dir.join("url"), url)?;
+    let key_file = channel_dir.join("public-key");
+    if let Some(key) = public_key {
+        fs::write(&key_file, key)?;
+    }
 
     println!("Channel '{name}' registered: {url}");
+    if !key_file.exists() {
+        eprintln!("warning: no --key given; manifests from '{name}' will not be verified");
+    }
     println!("Run `snix channel update {name}` to fetch the manifest.");
     Ok(())
 }
@@ -121,8 +178,10 @@ pub fn list() -> Result<(), Box<dyn std::error::Error>> {
         let cache_url = fs::read_to_string(entry.path().join("cache-url"))
             .ok()
             .map(|s| s.trim().to_string());
+        let key_name = read_public_key(&entry.path())
+            .and_then(|k| k.split_once(':').map(|(n, _)| n.to_string()));
 
-        channels.push((name, url, cache_url, last_fetched, has_manifest));
+        channels.push((name, url, cache_url, key_name, last_fetched, has_manifest));
     }
 
     if channels.is_empty() {
@@ -134,13 +193,17 @@ pub fn list() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Registered channels:");
     println!();
-    for (name, url, cache_url, fetched, has_manifest) in &channels {
+    for (name, url, cache_url, key_name, fetched, has_manifest) in &channels {
         let status = if *has_manifest { "✓" } else { "○" };
         println!("  {status} {name}");
         println!("    URL:     {url}");
         if let Some(cu) = cache_url {
             println!("    Cache:   {cu}");
         }
+        match key_name {
+            Some(k) => println!("    Key:     {k}"),
+            None => println!("    Key:     none (unverified)"),
+        }
         if let Some(ts) = fetched {
             println!("    Fetched: {ts}");
         } else {
@@ -154,7 +217,12 @@ pub fn list() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 /// Fetch/update a channel's manifest from its URL.
-pub fn update(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+///
+/// If the channel has a trusted key, the manifest's detached signature is
+/// fetched and checked before anything is written. A failed check returns a
+/// [`VerificationError`] unless `insecure` is set, which downgrades it to a
+/// warning.
+pub fn update(name: &str, insecure: bool) -> Result<(), Box<dyn std::error::Error>> {
     let channel_dir = Path::new(CHANNELS_DIR).join(name);
     if !channel_dir.exists() {
         return Err(format!("channel '{name}' not found. Add it with: snix channel add {name} <url>").into());
@@ -188,6 +256,21 @@ pub fn update(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let _: serde_json::Value = serde_json::from_str(&body)
         .map_err(|e| format!("invalid JSON from {manifest_url}: {e}"))?;
 
+    // Check the signature before the manifest replaces the cached copy
+    let public_key = read_public_key(&channel_dir);
+    let signature = match public_key {
+        Some(_) => fetch_signature(&format!("{manifest_url}.sig")),
+        None => None,
+    };
+    match verify_manifest(&body, signature.as_deref(), public_key.as_deref()) {
+        Ok(ManifestTrust::Verified(key)) => eprintln!("Manifest signature verified ({key})"),
+        Ok(ManifestTrust::Unsigned) => eprintln!(
+            "warning: channel '{name}' has no trusted key; manifest signature not checked"
+        ),
+        Err(e) if insecure => eprintln!("warning: {e} (continuing: --insecure)"),
+        Err(e) => return Err(e.into()),
+    }
+
     // Save manifest
     fs::write(channel_dir.join("manifest.json"), &body)?;
 
@@ -232,6 +315,63 @@ pub fn update(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Check a manifest body against a channel's trusted key.
+///
+/// `signature` is the contents of `manifest.json.sig`: one or more
+/// `name:base64` signatures, one per line. Any line signed by `public_key`
+/// is enough. Without a key there is nothing to check against, so the
+/// manifest is reported as [`ManifestTrust::Unsigned`].
+pub fn verify_manifest(
+    body: &str,
+    signature: Option<&str>,
+    public_key: Option<&str>,
+) -> Result<ManifestTrust, VerificationError> {
+    let Some(public_key) = public_key else {
+        return Ok(ManifestTrust::Unsigned);
+    };
+    let key = VerifyingKey::parse(public_key)
+        .map_err(|e| VerificationError(format!("invalid channel key '{public_key}': {e}")))?;
+
+    let signature = signature.ok_or_else(|| {
+        VerificationError(format!(
+            "manifest is not signed, but channel trusts key '{}'",
+            key.name()
+        ))
+    })?;
+
+    let verified = signature
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| SignatureRef::parse(line).ok())
+        .any(|sig| key.verify(body, &sig));
+
+    if verified {
+        Ok(ManifestTrust::Verified(key.name().to_string()))
+    } else {
+        Err(VerificationError(format!(
+            "manifest signature does not verify against key '{}'",
+            key.name()
+        )))
+    }
+}
+
+/// Read a channel's trusted public key, if one is configured.
+fn read_public_key(channel_dir: &Path) -> Option<String> {
+    fs::read_to_string(channel_dir.join("public-key"))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Fetch a detached signature, treating any failure as "not signed".
+fn fetch_signature(url: &str) -> Option<String> {
+    match ureq::get(url).call() {
+        Ok(resp) if resp.status() == 200 => resp.into_body().read_to_string().ok(),
+        _ => None,
+    }
+}
+
 /// Get the packages.json path for a named channel, if it exists.
 pub fn get_packages_index_path(name: &str) -> Option<PathBuf> {
     let path = Path::new(CHANNELS_DIR).join(name).join("packages.json");
@@ -239,7 +379,7 @@ pub fn get_packages_index_path(name: &str) -> Option<PathBuf> {
 }
 
 /// Update all registered channels.
-pub fn update_all() -> Result<(), Box<dyn std::error::Error>> {
+pub fn update_all(insecure: bool) -> Result<(), Box<dyn std::error::Error>> {
     let dir = Path::new(CHANNELS_DIR);
     if !dir.exists() {
         println!("No channels to update.");
@@ -263,7 +403,7 @@ pub fn update_all() -> Result<(), Box<dyn std::error::Error>> {
     let mut errors = 0;
 
     for name in &names {
-        if let Err(e) = update(name) {
+        if let Err(e) = update(name, insecure) {
             eprintln!("error updating '{name}': {e}");
             errors += 1;
         }
@@ -321,4 +461,56 @@ mod tests {
         let url = fs::read_to_string(channel_dir.join("url")).unwrap();
         assert_eq!(url.trim(), "https://example.com/redox/");
     }
+
+    #[test]
+    fn re_adding_without_key_keeps_it() {
+        let tmp = tempfile::tempdir().unwrap();
+        let key_file = tmp.path().join("stable/public-key");
+
+        add_at(tmp.path(), "stable", "https://example.com/a/", Some(TEST_KEY)).unwrap();
+        add_at(tmp.path(), "stable", "https://example.com/b/", None).unwrap();
+
+        assert_eq!(fs::read_to_string(&key_file).unwrap(), TEST_KEY);
+        let url = fs::read_to_string(tmp.path().join("stable/url")).unwrap();
+        assert_eq!(url, "https://example.com/b/");
+
+        add_at(tmp.path(), "other", "https://example.com/c/", None).unwrap();
+        assert!(!tmp.path().join("other/public-key").exists());
+    }
+
+    // Signed with the ed25519 key whose seed is the bytes 0..32.
+    const TEST_KEY: &str = "test-channel-1:A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=";
+    const TEST_MANIFEST: &str = "{\"manifestVersion\":1,\"system\":{\"hostname\":\"redox\"}}\n";
+    const TEST_SIG: &str = "test-channel-1:02wvwqm9EUCCVIJrH2PWWyk9axash+v1f0Z0el2qzpcQHlJYh1Lc5bC1hK8dGxgqvBeWwjSk1SwuPyem5czSBg==\n";
+
+    #[test]
+    fn verify_signed_manifest() {
+        let trust = verify_manifest(TEST_MANIFEST, Some(TEST_SIG), Some(TEST_KEY)).unwrap();
+        assert_eq!(trust, ManifestTrust::Verified("test-channel-1".to_string()));
+    }
+
+    #[test]
+    fn verify_tampered_manifest_fails() {
+        let tampered = TEST_MANIFEST.replace("redox", "evil");
+        let err = verify_manifest(&tampered, Some(TEST_SIG), Some(TEST_KEY)).unwrap_err();
+        assert!(err.to_string().contains("does not verify"), "{err}");
+    }
+
+    #[test]
+    fn verify_missing_signature_fails() {
+        let err = verify_manifest(TEST_MANIFEST, None, Some(TEST_KEY)).unwrap_err();
+        assert!(err.to_string().contains("not signed"), "{err}");
+    }
+
+    #[test]
+    fn verify_without_key_is_unsigned() {
+        assert_eq!(
+            verify_manifest(TEST_MANIFEST, None, None).unwrap(),
+            ManifestTrust::Unsigned
+        );
+        assert_eq!(
+            verify_manifest(TEST_MANIFEST, Some(TEST_SIG), None).unwrap(),
+            ManifestTrust::Unsigned
+        );
+    }
 }
@@ -0,0 +1,270 @@
+//! Shell completion scripts for the snix CLI.
+//!
+//! The scripts are generated from the clap command tree, so new
+//! subcommands and flags are picked up without touching this module.
+//! Completion is static: subcommands and flags only, no package or GC
+//! root names.
+//!
+//! Usage:
+//!   snix completions bash > /usr/share/bash-completion/completions/snix
+//!   snix completions zsh > ~/.zfunc/_snix
+//!   snix completions ion > ~/.config/ion/snix.ion
+
+use std::fmt::Write;
+
+use clap::{Command, ValueEnum};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    /// Redox's shell. It has no programmable completion hook, so the
+    /// script defines a function listing the candidates instead.
+    Ion,
+}
+
+/// The words that may follow one (sub)command.
+struct Node {
+    /// Command names from the root, e.g. `["snix", "store", "gc"]`.
+    path: Vec<String>,
+    /// Subcommands, then flags, each with its one-line description.
+    words: Vec<(String, String)>,
+}
+
+/// Generate the completion script for `shell`.
+pub fn generate(shell: Shell, cmd: &Command) -> String {
+    // Building adds the implicit --help/--version flags and help subcommand.
+    let mut cmd = cmd.clone();
+    cmd.build();
+
+    let mut nodes = Vec::new();
+    collect(&cmd, vec![cmd.get_name().to_string()], &mut nodes);
+
+    match shell {
+        Shell::Bash => bash(cmd.get_name(), &nodes),
+        Shell::Zsh => zsh(cmd.get_name(), &nodes),
+        Shell::Ion => ion(cmd.get_name(), &nodes),
+    }
+}
+
+fn collect(cmd: &Command, path: Vec<String>, nodes: &mut Vec<Node>) {
+    let subcommands: Vec<&Command> = cmd.get_subcommands().filter(|s| !s.is_hide_set()).collect();
+
+    let mut words = Vec::new();
+    for sub in &subcommands {
+        let about = sub.get_about().map(|a| a.to_string()).unwrap_or_default();
+        words.push((sub.get_name().to_string(), first_line(&about)));
+    }
+    for arg in cmd.get_arguments().filter(|a| !a.is_hide_set()) {
+        let help = arg
+            .get_help()
+            .map(|h| first_line(&h.to_string()))
+            .unwrap_or_default();
+        if let Some(long) = arg.get_long() {
+            words.push((format!("--{long}"), help.clone()));
+        }
+        if let Some(short) = arg.get_short() {
+            words.push((format!("-{short}"), help));
+        }
+    }
+    nodes.push(Node {
+        path: path.clone(),
+        words,
+    });
+
+    // `help <command>` mirrors the whole tree; offering `help` is enough.
+    for sub in subcommands.into_iter().filter(|s| s.get_name() != "help") {
+        let mut sub_path = path.clone();
+        sub_path.push(sub.get_name().to_string());
+        collect(sub, sub_path, nodes);
+    }
+}
+
+fn first_line(text: &str) -> String {
+    text.lines().next().unwrap_or_default().trim().to_string()
+}
+
+/// `name` as part of a shell function name.
+fn ident(name: &str) -> String {
+    name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+// ===== Bash =====
+
+fn bash(name: &str, nodes: &[Node]) -> String {
+    let func = format!("_{}", ident(name));
+    let subpaths: Vec<String> = nodes[1..].iter().map(|n| n.path.join("__")).collect();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "# bash completion for {name}");
+    let _ = writeln!(out, "{func}() {{");
+    let _ = writeln!(out, "    local cur cmd i opts");
+    let _ = writeln!(out, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(out, "    cmd={name}");
+    let _ = writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do");
+    let _ = writeln!(out, "        case \"${{cmd}}__${{COMP_WORDS[i]}}\" in");
+    let _ = writeln!(out, "            {})", subpaths.join("|"));
+    let _ = writeln!(out, "                cmd=\"${{cmd}}__${{COMP_WORDS[i]}}\"");
+    let _ = writeln!(out, "                ;;");
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "    done");
+    let _ = writeln!(out, "    case \"$cmd\" in");
+    for node in nodes {
+        let words: Vec<&str> = node.words.iter().map(|(w, _)| w.as_str()).collect();
+        let _ = writeln!(out, "        {})", node.path.join("__"));
+        let _ = writeln!(out, "            opts=\"{}\"", words.join(" "));
+        let _ = writeln!(out, "            ;;");
+    }
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out, "complete -F {func} {name}");
+    out
+}
+
+// ===== Zsh =====
+
+fn zsh(name: &str, nodes: &[Node]) -> String {
+    let func = format!("_{}", ident(name));
+    let subpaths: Vec<String> = nodes[1..].iter().map(|n| n.path.join("__")).collect();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "#compdef {name}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "{func}() {{");
+    let _ = writeln!(out, "    local cmd={name} i");
+    let _ = writeln!(out, "    local -a opts");
+    let _ = writeln!(out, "    for ((i = 2; i < CURRENT; i++)); do");
+    let _ = writeln!(out, "        case \"${{cmd}}__${{words[i]}}\" in");
+    let _ = writeln!(out, "            ({})", subpaths.join("|"));
+    let _ = writeln!(out, "                cmd=\"${{cmd}}__${{words[i]}}\"");
+    let _ = writeln!(out, "                ;;");
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "    done");
+    let _ = writeln!(out, "    case $cmd in");
+    for node in nodes {
+        let _ = writeln!(out, "        ({})", node.path.join("__"));
+        let _ = writeln!(out, "            opts=(");
+        for (word, desc) in &node.words {
+            let entry = if desc.is_empty() {
+                word.clone()
+            } else {
+                format!("{word}:{desc}")
+            };
+            let _ = writeln!(out, "                '{}'", entry.replace('\'', "'\\''"));
+        }
+        let _ = writeln!(out, "            )");
+        let _ = writeln!(out, "            ;;");
+    }
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "    _describe '{name}' opts");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "{func} \"$@\"");
+    out
+}
+
+// ===== Ion =====
+
+fn ion(name: &str, nodes: &[Node]) -> String {
+    let func = format!("{}_complete", ident(name));
+
+    let mut out = String::new();
+    let _ = writeln!(out, "# {name} completion for ion");
+    let _ = writeln!(out, "#");
+    let _ = writeln!(
+        out,
+        "# Ion has no programmable completion hook, so this defines a function"
+    );
+    let _ = writeln!(
+        out,
+        "# printing what may follow a partial {name} command line:"
+    );
+    let _ = writeln!(out, "#   {func} \"store\"");
+    let _ = writeln!(out, "fn {func} line");
+    let _ = writeln!(out, "    let cmd = {name}");
+    let _ = writeln!(out, "    for word in @split($line)");
+    let _ = writeln!(out, "        match \"$cmd/$word\"");
+    for node in &nodes[1..] {
+        let _ = writeln!(out, "            case \"{}\"", node.path.join("/"));
+        let _ = writeln!(out, "                let cmd = \"$cmd/$word\"");
+    }
+    let _ = writeln!(out, "        end");
+    let _ = writeln!(out, "    end");
+    let _ = writeln!(out, "    match $cmd");
+    for node in nodes {
+        let words: Vec<&str> = node.words.iter().map(|(w, _)| w.as_str()).collect();
+        let _ = writeln!(out, "        case \"{}\"", node.path.join("/"));
+        let _ = writeln!(out, "            echo {}", words.join(" "));
+    }
+    let _ = writeln!(out, "    end");
+    let _ = writeln!(out, "end");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn cli() -> Command {
+        Command::new("snix")
+            .subcommand(
+                Command::new("eval")
+                    .about("Evaluate a Nix expression and print the result")
+                    .arg(
+                        Arg::new("expr")
+                            .long("expr")
+                            .short('e')
+                            .help("Nix expression"),
+                    ),
+            )
+            .subcommand(
+                Command::new("store")
+                    .about("Store management")
+                    .subcommand(Command::new("gc").about("Delete unreachable paths")),
+            )
+            .subcommand(Command::new("completions").hide(true))
+    }
+
+    #[test]
+    fn every_shell_mentions_subcommands() {
+        for shell in Shell::value_variants() {
+            let script = generate(*shell, &cli());
+            assert!(!script.is_empty(), "{shell:?}");
+            for word in ["eval", "store", "gc", "--expr", "--help"] {
+                assert!(
+                    script.contains(word),
+                    "{shell:?} script lacks {word}:\n{script}"
+                );
+            }
+            // Comments may say "completion"; no candidate may name the hidden command.
+            let offered = script
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .any(|line| line.contains("completions"));
+            assert!(!offered, "{shell:?} offers a hidden command");
+        }
+    }
+
+    #[test]
+    fn bash_offers_nested_subcommands() {
+        let script = generate(Shell::Bash, &cli());
+        assert!(
+            script.contains("snix__eval|snix__store|snix__store__gc)"),
+            "{script}"
+        );
+        assert!(script.contains("opts=\"eval store help --"), "{script}");
+        assert!(script.contains("opts=\"gc help --"), "{script}");
+        assert!(script.contains("opts=\"--expr -e --"), "{script}");
+        assert!(script.ends_with("complete -F _snix snix\n"));
+    }
+
+    #[test]
+    fn zsh_quotes_descriptions() {
+        let cmd = Command::new("snix").subcommand(Command::new("gc").about("Don't keep: anything"));
+        let script = generate(Shell::Zsh, &cmd);
+        assert!(script.starts_with("#compdef snix\n"));
+        assert!(script.contains("'gc:Don'\\''t keep: anything'"), "{script}");
+    }
+}
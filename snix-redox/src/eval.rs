@@ -1,51 +1,193 @@
 //! Nix expression evaluation using snix-eval's bytecode VM.
 
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
-use snix_eval::Evaluation;
+use nix_compat::nixhash::CAHashMode;
+use snix_eval::{Evaluation, Value};
 
 use crate::derivation_builtins::{derivation_builtins, SnixRedoxState};
 use crate::fetchers::fetcher_builtins;
 use crate::known_paths::KnownPaths;
 
+/// Arguments for auto-calling an expression that evaluates to a function,
+/// like `nix eval --arg`/`--argstr`. Values are Nix expressions.
+#[derive(Debug, Default)]
+pub struct AutoArgs {
+    args: BTreeMap<String, String>,
+}
+
+impl AutoArgs {
+    /// Build from flattened `NAME VALUE` pairs of `--arg` and `--argstr`.
+    /// `--argstr` values are taken literally, as strings.
+    pub fn from_cli(arg: &[String], argstr: &[String]) -> Self {
+        let mut args = BTreeMap::new();
+        for pair in arg.chunks_exact(2) {
+            args.insert(pair[0].clone(), pair[1].clone());
+        }
+        for pair in argstr.chunks_exact(2) {
+            args.insert(pair[0].clone(), string_literal(&pair[1]));
+        }
+        Self { args }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Wrap `source` so that, if it is a function with formals, it is called
+    /// with these arguments, as Nix does. `{ a, ... }:` receives all of
+    /// them, `{ a }:` only those it declares, and a required formal that
+    /// isn't given is an error. Other values, `a: ...` included, are left
+    /// as they are.
+    ///
+    /// `source` and the values are passed to [`AUTO_CALL`] rather than
+    /// placed inside it, so they see none of its bindings.
+    fn apply_to(&self, source: &str) -> String {
+        let mut expr = String::from(AUTO_CALL);
+        expr.push_str(&format!("(\n{source}\n)\n"));
+        expr.push_str("{\n");
+        for (name, value) in &self.args {
+            expr.push_str(&format!("  {} = (\n{value}\n);\n", string_literal(name)));
+        }
+        expr.push_str("}\n");
+        expr
+    }
+}
+
+/// Function of the expression and the argument set used by
+/// [`AutoArgs::apply_to`].
+///
+/// Only `toXML` tells `{ ... }:` from `a:` (both have no `functionArgs`),
+/// so the function's XML is searched for the pattern it was written with.
+const AUTO_CALL: &str = r#"(f: args:
+  let
+    xml = builtins.toXML f;
+    has = s: builtins.replaceStrings [ s ] [ "" ] xml != xml;
+    formals = builtins.functionArgs f;
+    missing = builtins.filter (n: !formals.${n} && !(args ? ${n})) (builtins.attrNames formals);
+  in
+  if !builtins.isFunction f || !has "<attrspat" then f
+  else if missing != [] then
+    throw "cannot evaluate a function that has an argument without a value ('${builtins.head missing}')"
+  else if has "<attrspat ellipsis=\"1\"" then f args
+  else f (builtins.intersectAttrs formals args))
+"#;
+
 /// Evaluate a Nix expression from --expr or --file
 pub fn run(
     expr: Option<String>,
     file: Option<String>,
+    args: &AutoArgs,
     raw: bool,
+    budget: &EvalBudget,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    print!("{}", run_output(expr, file, args, raw, budget)?);
+    Ok(())
+}
+
+/// What [run] prints.
+fn run_output(
+    expr: Option<String>,
+    file: Option<String>,
+    args: &AutoArgs,
+    raw: bool,
+    budget: &EvalBudget,
+) -> Result<String, Box<dyn std::error::Error>> {
     let source = match (expr, file) {
-        (Some(e), _) => e,
-        (_, Some(f)) => std::fs::read_to_string(&f)?,
+        (Some(e), _) if args.is_empty() => e,
+        (Some(e), _) => args.apply_to(&e),
+        // Like nix-instantiate, a file that holds a function is always called.
+        (_, Some(f)) => args.apply_to(&std::fs::read_to_string(&f)?),
         _ => return Err("provide --expr or --file".into()),
     };
 
-    let result = evaluate(&source)?;
+    let result = with_budget(budget, move || evaluate(&source).map_err(|e| e.to_string()))?;
     if raw {
         // Strip surrounding quotes from string values (e.g. "hello" → hello)
         let s = result.to_string();
         if s.starts_with('"') && s.ends_with('"') && s.len() >= 2 {
             // Unescape the inner string (handle \" → " and \\ → \ etc.)
             let inner = &s[1..s.len() - 1];
-            let unescaped = inner
+            Ok(inner
                 .replace("\\\"", "\"")
                 .replace("\\\\", "\\")
                 .replace("\\n", "\n")
-                .replace("\\t", "\t");
-            print!("{unescaped}");
+                .replace("\\t", "\t"))
         } else {
-            print!("{s}");
+            Ok(s)
         }
     } else {
-        println!("{result}");
+        Ok(format!("{result}\n"))
+    }
+}
+
+/// Limits on a single evaluation, so that a looping or runaway expression
+/// fails instead of hanging the caller.
+///
+/// snix-eval has no way to interrupt the VM, so the limit is wall-clock
+/// time: the evaluation runs on a worker thread and is given up on when
+/// the timeout passes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvalBudget {
+    /// Give up after this long. `None` evaluates without a limit.
+    pub timeout: Option<Duration>,
+}
+
+impl EvalBudget {
+    /// A budget of `secs` seconds, or no limit for `None`.
+    pub fn from_secs(secs: Option<u64>) -> Self {
+        Self { timeout: secs.map(Duration::from_secs) }
     }
-    Ok(())
 }
 
-/// Show a .drv file in human-readable JSON
-pub fn show_derivation(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Stack for the evaluation thread, as deep as the main thread's usually is.
+const EVAL_THREAD_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Run the evaluation `f` within `budget`.
+///
+/// Without a timeout `f` runs on the calling thread. Otherwise it runs on
+/// a worker thread; when the timeout passes the worker is left behind
+/// (it can't be stopped) and an "evaluation budget exceeded" error is
+/// returned, so callers should exit soon after.
+pub fn with_budget<T, F>(budget: &EvalBudget, f: F) -> Result<T, Box<dyn std::error::Error>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    let Some(timeout) = budget.timeout else {
+        return Ok(f()?);
+    };
+
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("snix-eval".into())
+        .stack_size(EVAL_THREAD_STACK_SIZE)
+        .spawn(move || {
+            // The receiver is gone if the budget ran out first.
+            let _ = tx.send(f());
+        })?;
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => Ok(result?),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(format!(
+            "evaluation budget exceeded: no result after {}s",
+            timeout.as_secs_f64()
+        )
+        .into()),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err("evaluation thread panicked".into()),
+    }
+}
+
+/// Show a .drv file as `nix show-derivation` JSON, or with `summary` in a
+/// human-readable form
+pub fn show_derivation(path: &str, summary: bool) -> Result<(), Box<dyn std::error::Error>> {
     let bytes = std::fs::read(path)?;
 
     // Trim trailing whitespace (Nix derivations shouldn't have it)
@@ -53,18 +195,15 @@ pub fn show_derivation(path: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     match nix_compat::derivation::Derivation::from_aterm_bytes(trimmed) {
         Ok(drv) => {
-            let json = serde_json::json!({
-                "args": drv.arguments,
-                "builder": drv.builder,
-                "env": drv.environment.into_iter()
-                    .map(|(k, v)| (k, v.to_string()))
-                    .collect::<std::collections::BTreeMap<String, String>>(),
-                "inputDrvs": drv.input_derivations,
-                "inputSrcs": drv.input_sources,
-                "outputs": drv.outputs,
-                "system": drv.system,
-            });
-            println!("{}", serde_json::to_string_pretty(&json)?);
+            if let Some(expected) = drv_path_mismatch(path, &drv) {
+                eprintln!("warning: {path} does not match its contents, expected {expected}");
+            }
+            if summary {
+                print!("{}", format_derivation(path, &drv));
+            } else {
+                let json = derivation_json(path, &drv);
+                println!("{}", serde_json::to_string_pretty(&json)?);
+            }
         }
         Err(e) => return Err(format!("parse error: {e:#?}").into()),
     }
@@ -72,23 +211,152 @@ pub fn show_derivation(path: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// The drv path `drv` should have, if `path` is named like a store path
+/// but isn't it.
+///
+/// A `.drv` is named after the hash of its own ATerm and references, so a
+/// mismatch means the file was edited after Nix wrote it, or renamed.
+fn drv_path_mismatch(path: &str, drv: &nix_compat::derivation::Derivation) -> Option<String> {
+    let file_name = Path::new(path).file_name()?.to_str()?;
+    let store_path = nix_compat::store_path::StorePathRef::from_bytes(file_name.as_bytes()).ok()?;
+    let name = store_path.name().strip_suffix(".drv")?;
+
+    let expected = drv.calculate_derivation_path(name).ok()?.to_absolute_path();
+    (expected != store_path.to_absolute_path()).then_some(expected)
+}
+
+/// Render a derivation in the `nix show-derivation` JSON schema:
+/// an object keyed by the drv path, with fixed-output hashes in hex.
+///
+/// Environment values are emitted verbatim as strings, so structured
+/// attributes stay a JSON-encoded string under `__json`, as in Nix.
+fn derivation_json(
+    drv_path: &str,
+    drv: &nix_compat::derivation::Derivation,
+) -> serde_json::Value {
+    let outputs: serde_json::Map<String, serde_json::Value> = drv
+        .outputs
+        .iter()
+        .map(|(name, output)| {
+            let mut obj = serde_json::Map::new();
+            if let Some(path) = &output.path {
+                obj.insert("path".into(), path.to_absolute_path().into());
+            }
+            if let Some(ca) = &output.ca_hash {
+                let hash = ca.hash();
+                let algo = match ca.mode() {
+                    CAHashMode::Nar => format!("r:{}", hash.algo()),
+                    CAHashMode::Flat => hash.algo().to_string(),
+                    CAHashMode::Text => format!("text:{}", hash.algo()),
+                };
+                obj.insert("hashAlgo".into(), algo.into());
+                obj.insert(
+                    "hash".into(),
+                    data_encoding::HEXLOWER
+                        .encode(hash.digest_as_bytes())
+                        .into(),
+                );
+            }
+            (name.clone(), obj.into())
+        })
+        .collect();
+
+    let input_drvs: serde_json::Map<String, serde_json::Value> = drv
+        .input_derivations
+        .iter()
+        .map(|(path, outputs)| (path.to_absolute_path(), serde_json::json!(outputs)))
+        .collect();
+
+    let env: BTreeMap<&str, String> = drv
+        .environment
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.to_string()))
+        .collect();
+
+    let input_srcs: Vec<String> = drv
+        .input_sources
+        .iter()
+        .map(|p| p.to_absolute_path())
+        .collect();
+
+    serde_json::json!({
+        drv_path: {
+            "args": drv.arguments,
+            "builder": drv.builder,
+            "env": env,
+            "inputDrvs": input_drvs,
+            "inputSrcs": input_srcs,
+            "outputs": outputs,
+            "system": drv.system,
+        }
+    })
+}
+
+/// Render a derivation as an indented, human-readable summary.
+fn format_derivation(drv_path: &str, drv: &nix_compat::derivation::Derivation) -> String {
+    let mut out = format!("{drv_path}\n");
+    out.push_str(&format!("  system:  {}\n", drv.system));
+    out.push_str(&format!("  builder: {}\n", drv.builder));
+    if !drv.arguments.is_empty() {
+        out.push_str(&format!("  args:    {}\n", drv.arguments.join(" ")));
+    }
+
+    out.push_str("  outputs:\n");
+    for (name, output) in &drv.outputs {
+        out.push_str(&format!("    {name}: {}", output.path_str()));
+        if let Some(ca) = &output.ca_hash {
+            out.push_str(&format!(" ({})", ca.to_nix_nixbase32_string()));
+        }
+        out.push('\n');
+    }
+
+    if !drv.input_derivations.is_empty() {
+        out.push_str("  input derivations:\n");
+        for (path, outputs) in &drv.input_derivations {
+            let outputs: Vec<&str> = outputs.iter().map(String::as_str).collect();
+            out.push_str(&format!(
+                "    {} [{}]\n",
+                path.to_absolute_path(),
+                outputs.join(", ")
+            ));
+        }
+    }
+
+    if !drv.input_sources.is_empty() {
+        out.push_str("  input sources:\n");
+        for path in &drv.input_sources {
+            out.push_str(&format!("    {}\n", path.to_absolute_path()));
+        }
+    }
+
+    out.push_str("  env:\n");
+    for (key, value) in &drv.environment {
+        out.push_str(&format!("    {key} = {value}\n"));
+    }
+
+    out
+}
+
 /// Interactive REPL
+///
+/// Bindings made with `name = expr` and attrsets brought in with `:load`
+/// stay in scope for later prompts. See [`ReplSession`].
 pub fn repl() -> Result<(), Box<dyn std::error::Error>> {
     println!("snix repl (Redox OS)");
-    println!("Type Nix expressions. Ctrl-D to exit.\n");
+    println!("Type Nix expressions, `:?` for help. Ctrl-D to exit.\n");
 
     let stdin = io::stdin();
     let mut stdout = io::stdout();
+    let mut session = ReplSession::default();
 
-    for line in stdin.lock().lines() {
-        let line = line?;
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
+    print!("nix> ");
+    stdout.flush()?;
 
-        match evaluate(line) {
-            Ok(val) => println!("{val}"),
+    for line in stdin.lock().lines() {
+        match session.handle_line(&line?) {
+            Ok(ReplOutcome::Quit) => break,
+            Ok(ReplOutcome::Output(text)) => println!("{text}"),
+            Ok(ReplOutcome::Nothing) => {}
             Err(e) => eprintln!("error: {e}"),
         }
 
@@ -99,6 +367,218 @@ pub fn repl() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+const REPL_HELP: &str = "\
+The following commands are available:
+
+  <expr>        Evaluate and print expression
+  <x> = <expr>  Bind expression to variable
+  :l <path>     Load Nix expression and add it to scope
+  :r            Reload all files
+  :t <expr>     Describe the type of the result of evaluating <expr>
+  :doc <expr>   Show documentation of a builtin function
+  :?            Show this help
+  :q            Exit";
+
+/// What the REPL should do after handling one line of input.
+#[derive(Debug, PartialEq, Eq)]
+enum ReplOutcome {
+    /// Print this text
+    Output(String),
+    /// Nothing to print (empty input or a new binding)
+    Nothing,
+    /// Leave the REPL
+    Quit,
+}
+
+/// State carried between REPL prompts.
+///
+/// Each input is evaluated inside an accumulated scope expression:
+///
+/// ```text
+/// with (import /loaded/a.nix); with (import /loaded/b.nix);
+/// let x = (...); y = (...); in (INPUT)
+/// ```
+///
+/// Bindings form one recursive `let`, so rebinding a name in terms of its
+/// old value (`x = x + 1`) is infinite recursion and is rejected.
+#[derive(Default)]
+struct ReplSession {
+    /// `name = expr` bindings, by name
+    bindings: BTreeMap<String, String>,
+    /// Canonical paths of files brought into scope with `:load`
+    loaded: Vec<PathBuf>,
+}
+
+impl ReplSession {
+    /// Handle one line of input: a command, a binding, or an expression.
+    fn handle_line(&mut self, line: &str) -> Result<ReplOutcome, Box<dyn std::error::Error>> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(ReplOutcome::Nothing);
+        }
+
+        if let Some(command) = line.strip_prefix(':') {
+            let (command, arg) = match command.split_once(char::is_whitespace) {
+                Some((c, a)) => (c, a.trim()),
+                None => (command, ""),
+            };
+            return match command {
+                "?" | "help" => Ok(ReplOutcome::Output(REPL_HELP.to_string())),
+                "q" | "quit" => Ok(ReplOutcome::Quit),
+                "l" | "load" => self.load(arg),
+                "r" | "reload" => self.reload(),
+                "t" | "type" => self.type_of(arg),
+                "doc" => self.doc(arg),
+                _ => Err(format!("unknown command ':{command}', try ':?'").into()),
+            };
+        }
+
+        if let Some((name, expr)) = split_binding(line) {
+            // Check the binding against a candidate scope so a failing
+            // expression never replaces a working one.
+            let mut candidate = self.bindings.clone();
+            candidate.insert(name.to_string(), expr.to_string());
+            evaluate(&scoped_expr(&self.loaded, &candidate, name))?;
+            self.bindings = candidate;
+            return Ok(ReplOutcome::Nothing);
+        }
+
+        let value = evaluate(&scoped_expr(&self.loaded, &self.bindings, line))?;
+        Ok(ReplOutcome::Output(value))
+    }
+
+    /// `:type EXPR` — the Nix type name of EXPR's value (`int`, `set`, `lambda`, ...).
+    fn type_of(&self, expr: &str) -> Result<ReplOutcome, Box<dyn std::error::Error>> {
+        if expr.is_empty() {
+            return Err(":type needs an expression".into());
+        }
+        let type_name = evaluate(&scoped_expr(
+            &self.loaded,
+            &self.bindings,
+            &format!("builtins.typeOf (\n{expr}\n)"),
+        ))?;
+        Ok(ReplOutcome::Output(type_name.trim_matches('"').to_string()))
+    }
+
+    /// `:doc EXPR` — the documentation string of the builtin EXPR evaluates to.
+    fn doc(&self, expr: &str) -> Result<ReplOutcome, Box<dyn std::error::Error>> {
+        if expr.is_empty() {
+            return Err(":doc needs an expression".into());
+        }
+        let (value, _state) = evaluate_value(&scoped_expr(&self.loaded, &self.bindings, expr))?;
+        match value {
+            Value::Builtin(builtin) => match builtin.documentation() {
+                Some(doc) => Ok(ReplOutcome::Output(format!(
+                    "builtins.{}\n\n{}",
+                    builtin.name(),
+                    doc.trim()
+                ))),
+                None => Ok(ReplOutcome::Output(format!(
+                    "builtins.{} has no documentation",
+                    builtin.name()
+                ))),
+            },
+            other => Err(format!("value is a {}, not a builtin", other.type_of()).into()),
+        }
+    }
+
+    /// `:load FILE` — bring the attrset FILE evaluates to into scope.
+    fn load(&mut self, path: &str) -> Result<ReplOutcome, Box<dyn std::error::Error>> {
+        if path.is_empty() {
+            return Err(":load needs a file path".into());
+        }
+        let path = Path::new(path)
+            .canonicalize()
+            .map_err(|e| format!("{path}: {e}"))?;
+
+        let count = count_attrs(&path)?;
+        if !self.loaded.contains(&path) {
+            self.loaded.push(path);
+        }
+        Ok(ReplOutcome::Output(format!("Added {count} variables.")))
+    }
+
+    /// `:reload` — re-read every loaded file, keeping only those that still evaluate.
+    fn reload(&mut self) -> Result<ReplOutcome, Box<dyn std::error::Error>> {
+        let mut report = Vec::new();
+        let mut kept = Vec::new();
+        for path in &self.loaded {
+            match count_attrs(path) {
+                Ok(count) => {
+                    report.push(format!("{}: {count} variables", path.display()));
+                    kept.push(path.clone());
+                }
+                Err(e) => report.push(format!("{}: dropped: {e}", path.display())),
+            }
+        }
+        self.loaded = kept;
+
+        if report.is_empty() {
+            return Ok(ReplOutcome::Output("No files loaded.".to_string()));
+        }
+        Ok(ReplOutcome::Output(report.join("\n")))
+    }
+}
+
+/// Split `name = expr` into its parts, if `line` is a binding.
+fn split_binding(line: &str) -> Option<(&str, &str)> {
+    let (name, rest) = line.split_once('=')?;
+    let name = name.trim();
+    let expr = rest.trim();
+
+    let mut chars = name.chars();
+    let valid_name = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'));
+
+    // `==` is comparison, not assignment
+    if !valid_name || rest.starts_with('=') || expr.is_empty() {
+        return None;
+    }
+    Some((name, expr))
+}
+
+/// Wrap `body` in the REPL scope: loaded files, then bindings.
+fn scoped_expr(loaded: &[PathBuf], bindings: &BTreeMap<String, String>, body: &str) -> String {
+    let mut expr = String::new();
+    for path in loaded {
+        expr.push_str(&format!("with ({});\n", import_expr(path)));
+    }
+    if !bindings.is_empty() {
+        expr.push_str("let\n");
+        for (name, value) in bindings {
+            expr.push_str(&format!("  {name} = (\n{value}\n);\n"));
+        }
+        expr.push_str("in\n");
+    }
+    // Newlines keep a trailing `# comment` in the input from eating the paren.
+    expr.push_str(&format!("(\n{body}\n)"));
+    expr
+}
+
+/// A Nix expression importing `path`, safe for any characters in the path.
+fn import_expr(path: &Path) -> String {
+    format!("import (/. + {})", string_literal(&path.to_string_lossy()))
+}
+
+/// A Nix string literal for `s`, escaping quotes, backslashes and `${`.
+fn string_literal(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{escaped}\"")
+}
+
+/// Number of attributes in the attrset `path` evaluates to.
+fn count_attrs(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    evaluate(&format!(
+        "builtins.length (builtins.attrNames ({}))",
+        import_expr(path)
+    ))
+}
+
 /// Core evaluation function
 fn evaluate(expr: &str) -> Result<String, Box<dyn std::error::Error>> {
     let (result, _state) = evaluate_with_state(expr)?;
@@ -113,6 +593,15 @@ fn evaluate(expr: &str) -> Result<String, Box<dyn std::error::Error>> {
 pub fn evaluate_with_state(
     expr: &str,
 ) -> Result<(String, Rc<SnixRedoxState>), Box<dyn std::error::Error>> {
+    let (value, state) = evaluate_value(expr)?;
+    Ok((format!("{value}"), state))
+}
+
+/// Evaluate a Nix expression to its [`Value`], for callers that inspect
+/// the value itself rather than its printed form.
+fn evaluate_value(
+    expr: &str,
+) -> Result<(Value, Rc<SnixRedoxState>), Box<dyn std::error::Error>> {
     let state = Rc::new(SnixRedoxState {
         known_paths: RefCell::new(KnownPaths::default()),
     });
@@ -134,7 +623,7 @@ pub fn evaluate_with_state(
     }
 
     match result.value {
-        Some(v) => Ok((format!("{v}"), state)),
+        Some(v) => Ok((v, state)),
         None => Err("no value produced".into()),
     }
 }
@@ -143,6 +632,26 @@ pub fn evaluate_with_state(
 mod tests {
     use super::*;
 
+    // ===== Budget =====
+
+    #[test]
+    fn test_budget_aborts_infinite_recursion() {
+        let budget = EvalBudget { timeout: Some(Duration::from_millis(200)) };
+        let start = std::time::Instant::now();
+        let err = with_budget(&budget, || {
+            evaluate("let loop = x: loop x; in loop 1").map_err(|e| e.to_string())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("evaluation budget exceeded"), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Within the budget the result comes through, errors included.
+        let ok = with_budget(&budget, || evaluate("1 + 1").map_err(|e| e.to_string()));
+        assert_eq!(ok.unwrap(), "2");
+        let failed = with_budget(&budget, || evaluate("throw \"no\"").map_err(|e| e.to_string()));
+        assert!(failed.unwrap_err().to_string().contains("no"));
+    }
+
     // ===== Arithmetic =====
 
     #[test]
@@ -322,12 +831,198 @@ mod tests {
 
     #[test]
     fn test_run_no_args_error() {
-        let result = run(None, None, false);
+        let result = run(None, None, &AutoArgs::default(), false, &EvalBudget::default());
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(err.to_string().contains("provide --expr or --file"));
     }
 
+    // ===== Auto-call Arguments =====
+
+    const GREET: &str = r#"{ name, count, greeting ? "hello" }:
+        "${greeting} ${name} x${toString count}""#;
+
+    #[test]
+    fn test_auto_args_mixed() {
+        let args = AutoArgs::from_cli(
+            &["count".to_string(), "1 + 2".to_string()],
+            &["name".to_string(), "\"snix\"".to_string(), "unused".to_string(), "x".to_string()],
+        );
+        let result = evaluate(&args.apply_to(GREET)).unwrap();
+        assert_eq!(result, r#""hello \"snix\" x3""#);
+    }
+
+    #[test]
+    fn test_auto_args_missing_required() {
+        let args = AutoArgs::from_cli(&[], &["name".to_string(), "snix".to_string()]);
+        let err = evaluate(&args.apply_to(GREET)).unwrap_err().to_string();
+        assert!(err.contains("argument without a value ('count')"), "{err}");
+    }
+
+    #[test]
+    fn test_auto_args_non_function_untouched() {
+        let args = AutoArgs::from_cli(&["x".to_string(), "1".to_string()], &[]);
+        assert_eq!(evaluate(&args.apply_to("{ a = 1; }.a")).unwrap(), "1");
+        assert_eq!(evaluate(&args.apply_to("builtins.toString")).unwrap(), "<PRIMOP>");
+
+        // Without formals a function isn't called, so it is still one here.
+        let plain = format!("({}) {{ x = 41; }}", args.apply_to("a: a.x + 1"));
+        assert_eq!(evaluate(&plain).unwrap(), "42");
+    }
+
+    #[test]
+    fn test_auto_args_ellipsis_gets_all() {
+        let args = AutoArgs::from_cli(
+            &["x".to_string(), "1".to_string(), "y".to_string(), "2".to_string()],
+            &[],
+        );
+        let names = "{ x, ... }@all: builtins.attrNames all";
+        assert_eq!(evaluate(&args.apply_to(names)).unwrap(), r#"[ "x" "y" ]"#);
+        assert_eq!(evaluate(&args.apply_to("{ ... }@all: all.y")).unwrap(), "2");
+        assert_eq!(evaluate(&args.apply_to("{ x }@all: all ? y")).unwrap(), "false");
+    }
+
+    #[test]
+    fn test_run_file_with_args() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("default.nix");
+        std::fs::write(&file, GREET).unwrap();
+
+        let args = AutoArgs::from_cli(
+            &["count".to_string(), "3".to_string()],
+            &["name".to_string(), "hello".to_string()],
+        );
+        let path = Some(file.display().to_string());
+        let output = run_output(None, path, &args, false, &EvalBudget::default());
+        assert_eq!(output.unwrap(), "\"hello hello x3\"\n");
+    }
+
+    #[test]
+    fn test_run_file_without_args_calls_function() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("default.nix");
+        std::fs::write(&file, "{ pkgs, lib ? null }: pkgs").unwrap();
+
+        let path = Some(file.display().to_string());
+        let err = run(None, path, &AutoArgs::default(), false, &EvalBudget::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("argument without a value ('pkgs')"), "{err}");
+    }
+
+    #[test]
+    fn test_auto_args_do_not_leak_wrapper_bindings() {
+        // Each would name one of the wrapper's bindings if spliced into it.
+        let none = AutoArgs::default();
+        for name in ["f", "args", "xml", "has", "formals", "missing"] {
+            assert!(evaluate(&none.apply_to(name)).is_err(), "{name} is bound");
+        }
+        assert_eq!(evaluate(&none.apply_to("{ args ? 1 }: args")).unwrap(), "1");
+
+        let args = AutoArgs::from_cli(&["x".to_string(), "formals".to_string()], &[]);
+        assert!(evaluate(&args.apply_to("{ x }: x")).is_err(), "argument sees formals");
+    }
+
+    // ===== REPL =====
+
+    fn out(text: &str) -> ReplOutcome {
+        ReplOutcome::Output(text.to_string())
+    }
+
+    #[test]
+    fn test_repl_bindings_persist() {
+        let mut session = ReplSession::default();
+
+        assert_eq!(session.handle_line("x = 20").unwrap(), ReplOutcome::Nothing);
+        assert_eq!(session.handle_line("y = x * 2 + 2").unwrap(), ReplOutcome::Nothing);
+        assert_eq!(session.handle_line("y").unwrap(), out("42"));
+        assert_eq!(session.handle_line("x == 20").unwrap(), out("true"));
+        assert_eq!(session.handle_line("  ").unwrap(), ReplOutcome::Nothing);
+    }
+
+    #[test]
+    fn test_repl_failed_binding_keeps_scope() {
+        let mut session = ReplSession::default();
+        session.handle_line("x = 1").unwrap();
+
+        assert!(session.handle_line("x = throw \"boom\"").is_err());
+        assert!(session.handle_line("z = undefinedName").is_err());
+
+        assert_eq!(session.handle_line("x").unwrap(), out("1"));
+        assert!(session.handle_line("z").is_err(), "z must not be bound");
+    }
+
+    #[test]
+    fn test_repl_load_and_reload() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("lib.nix");
+        std::fs::write(&file, "{ a = 40; b = 2; }").unwrap();
+
+        let mut session = ReplSession::default();
+        let load = format!(":load {}", file.display());
+        assert_eq!(session.handle_line(&load).unwrap(), out("Added 2 variables."));
+        assert_eq!(session.handle_line("a + b").unwrap(), out("42"));
+
+        // Bindings shadow loaded attributes
+        session.handle_line("b = 0").unwrap();
+        assert_eq!(session.handle_line("a + b").unwrap(), out("40"));
+
+        std::fs::write(&file, "{ a = 1; c = 3; }").unwrap();
+        let reloaded = session.handle_line(":r").unwrap();
+        assert!(matches!(reloaded, ReplOutcome::Output(ref r) if r.ends_with(": 2 variables")));
+        assert_eq!(session.handle_line("c").unwrap(), out("3"));
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let mut session = ReplSession::default();
+
+        let help = session.handle_line(":?").unwrap();
+        assert!(matches!(help, ReplOutcome::Output(ref h) if h.contains(":l <path>")));
+        assert_eq!(session.handle_line(":q").unwrap(), ReplOutcome::Quit);
+        assert!(session.handle_line(":frobnicate").is_err());
+        assert!(session.handle_line(":load /nonexistent/file.nix").is_err());
+        assert_eq!(session.handle_line(":reload").unwrap(), out("No files loaded."));
+    }
+
+    #[test]
+    fn test_repl_type() {
+        let mut session = ReplSession::default();
+
+        assert_eq!(session.handle_line(":type 1").unwrap(), out("int"));
+        assert_eq!(session.handle_line(":t (x: x)").unwrap(), out("lambda"));
+        assert_eq!(session.handle_line(":t { }").unwrap(), out("set"));
+
+        session.handle_line("s = \"hi\"").unwrap();
+        assert_eq!(session.handle_line(":t s").unwrap(), out("string"));
+
+        let err = session.handle_line(":t throw \"boom\"").unwrap_err();
+        assert!(err.to_string().contains("boom"), "{err}");
+        assert!(session.handle_line(":type").is_err());
+    }
+
+    #[test]
+    fn test_repl_doc() {
+        let mut session = ReplSession::default();
+
+        let doc = session.handle_line(":doc builtins.derivationStrict").unwrap();
+        assert!(
+            matches!(doc, ReplOutcome::Output(ref d) if d.starts_with("builtins.derivationStrict\n\n")),
+            "{doc:?}"
+        );
+        assert!(session.handle_line(":doc 1").is_err());
+    }
+
+    #[test]
+    fn test_repl_split_binding() {
+        assert_eq!(split_binding("x = 1"), Some(("x", "1")));
+        assert_eq!(split_binding("my-pkg' = { a = 1; }"), Some(("my-pkg'", "{ a = 1; }")));
+        assert_eq!(split_binding("x == 1"), None);
+        assert_eq!(split_binding("{ a = 1; }"), None);
+        assert_eq!(split_binding("let a = 1; in a"), None);
+        assert_eq!(split_binding("x ="), None);
+    }
+
     // ===== Derivation Parsing =====
 
     #[test]
@@ -346,6 +1041,136 @@ mod tests {
         assert!(!drv.outputs.is_empty(), "derivation should have outputs");
     }
 
+    #[test]
+    fn test_derivation_aterm_roundtrip() {
+        let fixtures: [&[u8]; 3] = [
+            include_bytes!("../testdata/4wvvbi4jwn0prsdxb7vs673qa5h9gr7x-foo.drv"),
+            include_bytes!("../testdata/ch49594n9avinrf8ip0aslidkc4lxkqv-foo.drv"),
+            include_bytes!("../testdata/ss2p4wmxijn652haqyd7dckxwl4c7hxx-bar.drv"),
+        ];
+
+        for bytes in fixtures {
+            let trimmed = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+            let drv = nix_compat::derivation::Derivation::from_aterm_bytes(trimmed).unwrap();
+            assert_eq!(
+                drv.to_aterm_bytes(),
+                trimmed,
+                "re-serialized ATerm must match the original bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn test_derivation_aterm_path() {
+        // The drv path is the hash of the ATerm bytes, so a byte-exact writer
+        // reproduces the file name Nix chose.
+        let cases: [(&str, &[u8]); 2] = [
+            (
+                "/nix/store/4wvvbi4jwn0prsdxb7vs673qa5h9gr7x-foo.drv",
+                include_bytes!("../testdata/4wvvbi4jwn0prsdxb7vs673qa5h9gr7x-foo.drv"),
+            ),
+            (
+                "/nix/store/ch49594n9avinrf8ip0aslidkc4lxkqv-foo.drv",
+                include_bytes!("../testdata/ch49594n9avinrf8ip0aslidkc4lxkqv-foo.drv"),
+            ),
+        ];
+
+        for (expected, bytes) in cases {
+            let trimmed = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+            let drv = nix_compat::derivation::Derivation::from_aterm_bytes(trimmed).unwrap();
+            let drv_path = drv.calculate_derivation_path("foo").unwrap();
+            assert_eq!(drv_path.to_absolute_path(), expected);
+        }
+    }
+
+    #[test]
+    fn test_drv_path_mismatch() {
+        let bytes = include_bytes!("../testdata/4wvvbi4jwn0prsdxb7vs673qa5h9gr7x-foo.drv");
+        let trimmed = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+        let drv = nix_compat::derivation::Derivation::from_aterm_bytes(trimmed).unwrap();
+
+        assert_eq!(
+            drv_path_mismatch("testdata/4wvvbi4jwn0prsdxb7vs673qa5h9gr7x-foo.drv", &drv),
+            None
+        );
+        assert_eq!(
+            drv_path_mismatch("/tmp/00000000000000000000000000000000-foo.drv", &drv).as_deref(),
+            Some("/nix/store/4wvvbi4jwn0prsdxb7vs673qa5h9gr7x-foo.drv")
+        );
+        // Not named like a store path: nothing to check against.
+        assert_eq!(drv_path_mismatch("foo.drv", &drv), None);
+    }
+
+    // ===== show-derivation =====
+
+    const TWO_OUTPUT_DRV: &str = concat!(
+        r#"Derive([("dev","/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-foo-dev","",""),"#,
+        r#"("out","/nix/store/4q0pg5zpfmznxscq3avycvf9xdvx50n3-foo","","")],"#,
+        r#"[("/nix/store/0hm2f1psjpcwg8fijsmr4wwxrx59s092-bar.drv",["out"])],"#,
+        r#"["/nix/store/5vyvcwah9l9kf07d52rcgdk70g2f4y13-builder.sh"],"#,
+        r#""x86_64-linux","/bin/sh",["-e","/nix/store/5vyvcwah9l9kf07d52rcgdk70g2f4y13-builder.sh"],"#,
+        r#"[("__json","{\"name\":\"foo\",\"outputs\":[\"out\",\"dev\"]}"),"#,
+        r#"("dev","/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-foo-dev"),"#,
+        r#"("out","/nix/store/4q0pg5zpfmznxscq3avycvf9xdvx50n3-foo")])"#,
+    );
+
+    #[test]
+    fn test_show_derivation_json_two_outputs() {
+        let drv =
+            nix_compat::derivation::Derivation::from_aterm_bytes(TWO_OUTPUT_DRV.as_bytes()).unwrap();
+        let json = derivation_json("/nix/store/ch49594n9avinrf8ip0aslidkc4lxkqv-foo.drv", &drv);
+
+        let expected = serde_json::json!({
+            "/nix/store/ch49594n9avinrf8ip0aslidkc4lxkqv-foo.drv": {
+                "args": ["-e", "/nix/store/5vyvcwah9l9kf07d52rcgdk70g2f4y13-builder.sh"],
+                "builder": "/bin/sh",
+                "env": {
+                    "__json": r#"{"name":"foo","outputs":["out","dev"]}"#,
+                    "dev": "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-foo-dev",
+                    "out": "/nix/store/4q0pg5zpfmznxscq3avycvf9xdvx50n3-foo",
+                },
+                "inputDrvs": {
+                    "/nix/store/0hm2f1psjpcwg8fijsmr4wwxrx59s092-bar.drv": ["out"],
+                },
+                "inputSrcs": ["/nix/store/5vyvcwah9l9kf07d52rcgdk70g2f4y13-builder.sh"],
+                "outputs": {
+                    "dev": { "path": "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-foo-dev" },
+                    "out": { "path": "/nix/store/4q0pg5zpfmznxscq3avycvf9xdvx50n3-foo" },
+                },
+                "system": "x86_64-linux",
+            }
+        });
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn test_show_derivation_json_fixed_output_hash_is_hex() {
+        let bytes: &[u8] = include_bytes!("../testdata/ss2p4wmxijn652haqyd7dckxwl4c7hxx-bar.drv");
+        let trimmed = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+        let drv = nix_compat::derivation::Derivation::from_aterm_bytes(trimmed).unwrap();
+        let json = derivation_json("bar.drv", &drv);
+
+        assert_eq!(
+            json["bar.drv"]["outputs"]["out"],
+            serde_json::json!({
+                "hash": "08813cbee9903c62be4c5027726a418a300da4500b2d369d3af9286f4815ceba",
+                "hashAlgo": "r:sha256",
+                "path": "/nix/store/mp57d33657rf34lzvlbpfa1gjfv5gmpg-bar",
+            })
+        );
+    }
+
+    #[test]
+    fn test_show_derivation_human() {
+        let drv =
+            nix_compat::derivation::Derivation::from_aterm_bytes(TWO_OUTPUT_DRV.as_bytes()).unwrap();
+        let text = format_derivation("foo.drv", &drv);
+
+        assert!(text.starts_with("foo.drv\n  system:  x86_64-linux\n"), "{text}");
+        assert!(text.contains("    dev: /nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-foo-dev\n"));
+        assert!(text.contains("    /nix/store/0hm2f1psjpcwg8fijsmr4wwxrx59s092-bar.drv [out]\n"));
+    }
+
     // ===== Derivation Builtins =====
     //
     // Expected paths verified against Nix (upstream snix test vectors).
@@ -7,24 +7,40 @@
 //!   /nix/var/snix/profiles/default/
 //!     bin/           — symlinks to package binaries
 //!     manifest.json  — installed package metadata
+//!   /nix/var/snix/profiles/default-generations/
+//!     N/             — snapshot of bin/ and manifest.json after change N
+//!     current        — generation the profile currently points at
+//!
+//! Installs are transactional: links are staged next to the profile and
+//! only swapped in once every fetched path is present (see `InstallTransaction`).
+//! Once linked, the package's binaries are scanned for store paths, and any
+//! that aren't in the store are reported as a broken closure.
 //!
 //! Commands:
 //!   snix install <name>   — fetch from cache, extract, link into profile
+//!                           (also a /nix/store path or a .narinfo URL)
 //!   snix remove <name>    — unlink from profile, remove GC root
 //!   snix profile list     — show installed packages
+//!   snix profile history  — list generations and what each one changed
+//!   snix profile rollback — switch back to the previous generation
 
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
 
-use nix_compat::store_path::StorePath;
+use nix_compat::narinfo::NarInfo;
+use nix_compat::nixbase32;
+use nix_compat::store_path::{StorePath, STORE_DIR};
 use sha2::{Digest, Sha256};
 
+use crate::activate;
 use crate::cache_source::CacheSource;
+use crate::local_build;
 use crate::local_cache;
 use crate::nar;
 use crate::pathinfo::PathInfoDb;
 use crate::store;
+use crate::store_root;
 
 // ─── Profiled Scheme Integration ───────────────────────────────────────────
 
@@ -178,7 +194,11 @@ pub struct ProfileManifest {
 
 impl ProfileManifest {
     fn load() -> Self {
-        match std::fs::read_to_string(PROFILE_MANIFEST) {
+        Self::load_from(Path::new(PROFILE_MANIFEST))
+    }
+
+    fn load_from(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
             Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
             Err(_) => Self {
                 version: 1,
@@ -189,18 +209,122 @@ impl ProfileManifest {
 
     fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
         ensure_dir(PROFILE_DIR)?;
+        self.save_to(Path::new(PROFILE_MANIFEST))
+    }
+
+    fn save_to(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
         let json = serde_json::to_string_pretty(self)?;
-        std::fs::write(PROFILE_MANIFEST, json)?;
+        std::fs::write(path, json)?;
         Ok(())
     }
 }
 
-/// Install a package by name from a binary cache (local or remote).
+// ─── Install Targets ───────────────────────────────────────────────────────
+
+/// What `snix install` was asked to install.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallTarget {
+    /// A package name from the cache's packages.json.
+    Name(String),
+    /// A full store path, fetched without consulting packages.json.
+    StorePath(String),
+    /// A narinfo URL; the directory it is in is used as the cache.
+    NarinfoUrl(String),
+}
+
+impl InstallTarget {
+    /// Tell the argument forms apart: `http(s)://….narinfo` is a narinfo
+    /// URL, anything under `/nix/store/` a store path, the rest a name.
+    pub fn parse(arg: &str) -> Self {
+        let is_url = arg.starts_with("http://") || arg.starts_with("https://");
+        if is_url && arg.ends_with(".narinfo") {
+            InstallTarget::NarinfoUrl(arg.to_string())
+        } else if arg.starts_with(&format!("{STORE_DIR}/")) {
+            InstallTarget::StorePath(arg.trim_end_matches('/').to_string())
+        } else {
+            InstallTarget::Name(arg.to_string())
+        }
+    }
+}
+
+/// The package an [`InstallTarget`] refers to, and the cache holding it.
+#[derive(Debug)]
+struct ResolvedPackage {
+    name: String,
+    pname: String,
+    version: String,
+    store_path: String,
+    source: CacheSource,
+}
+
+/// Resolve `target` to a package: names via the package index of `source`,
+/// store paths directly, and narinfo URLs via the cache they live in.
+fn resolve_target(
+    target: &InstallTarget,
+    source: &CacheSource,
+) -> Result<ResolvedPackage, Box<dyn std::error::Error>> {
+    match target {
+        InstallTarget::Name(name) => {
+            let index = source.read_index()?;
+            let entry = index
+                .packages
+                .get(name)
+                .ok_or_else(|| format!("package '{name}' not found in {}. Run `snix search` to list available packages.", source.display_name()))?;
+            Ok(ResolvedPackage {
+                name: name.clone(),
+                pname: entry.pname.clone(),
+                version: entry.version.clone(),
+                store_path: entry.store_path.clone(),
+                source: source.clone(),
+            })
+        }
+        InstallTarget::StorePath(path) => package_for_store_path(path, source.clone()),
+        InstallTarget::NarinfoUrl(url) => {
+            let (cache_url, file) = url
+                .rsplit_once('/')
+                .ok_or_else(|| format!("invalid narinfo URL: {url}"))?;
+            let hash = file.trim_end_matches(".narinfo");
+            let cache = CacheSource::Remote(cache_url.to_string());
+
+            let narinfo = cache.fetch_narinfo_by_hash(hash)?;
+            if nixbase32::encode(narinfo.store_path.digest()) != hash {
+                return Err(format!(
+                    "{url} describes {}, not a path with hash {hash}",
+                    narinfo.store_path.to_absolute_path()
+                )
+                .into());
+            }
+            package_for_store_path(&narinfo.store_path.to_absolute_path(), cache)
+        }
+    }
+}
+
+/// A package for a bare store path, named after the path's name component.
+fn package_for_store_path(
+    path: &str,
+    source: CacheSource,
+) -> Result<ResolvedPackage, Box<dyn std::error::Error>> {
+    let sp = StorePath::<String>::from_absolute_path(path.as_bytes())
+        .map_err(|e| format!("invalid store path {path}: {e}"))?;
+    let (pname, version) = sp.name_version();
+    Ok(ResolvedPackage {
+        name: pname.to_string(),
+        pname: pname.to_string(),
+        version: version.unwrap_or_default().to_string(),
+        store_path: sp.to_absolute_path(),
+        source,
+    })
+}
+
+/// Install a package from a binary cache (local or remote).
+///
+/// `target` is a package name, a store path or a narinfo URL (see
+/// [`InstallTarget::parse`]).
 pub fn install(
-    name: &str,
+    target: &str,
     source: &CacheSource,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    install_with_options(name, source, false)
+    install_with_options(target, source, false)
 }
 
 /// Install a package with optional lazy mode.
@@ -211,16 +335,13 @@ pub fn install(
 /// When `lazy` is true but `stored` is not running, falls back to eager
 /// extraction (lazy requires stored for on-demand access).
 pub fn install_with_options(
-    name: &str,
+    target: &str,
     source: &CacheSource,
     lazy: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // 1. Look up package in index
-    let index = source.read_index()?;
-    let entry = index
-        .packages
-        .get(name)
-        .ok_or_else(|| format!("package '{name}' not found in {}. Run `snix search` to list available packages.", source.display_name()))?;
+    // 1. Resolve the package: index lookup, or straight from the store path
+    let entry = resolve_target(&InstallTarget::parse(target), source)?;
+    let (name, source) = (entry.name.as_str(), &entry.source);
 
     // 2. Check if already installed in profile
     let mut manifest = ProfileManifest::load();
@@ -230,14 +351,75 @@ pub fn install_with_options(
         return Ok(());
     }
 
-    // 3. Fetch from cache — eager or lazy
+    // 3. Stage the install — the live profile is only touched by `commit`
+    let mut txn = InstallTransaction::begin(Path::new(PROFILE_DIR))?;
+    let binaries = match stage_install(&mut txn, name, &entry.store_path, &entry.version, source, lazy) {
+        Ok(binaries) => binaries,
+        Err(e) => {
+            eprintln!("install of {name} failed, rolling back...");
+            txn.rollback(PathInfoDb::open().ok().as_ref());
+            return Err(e);
+        }
+    };
+
+    if binaries.is_empty() {
+        eprintln!("  note: no binaries found in {}/bin/", entry.store_path);
+    } else {
+        eprintln!("  linked {} binaries:", binaries.len());
+        for bin in &binaries {
+            eprintln!("    {bin}");
+        }
+    }
+    let bin_dir = store_root::real_path(&entry.store_path).join("bin");
+    check_references(name, &entry.store_path, &bin_dir, PathInfoDb::open().ok().as_ref());
+
+    // 4. Commit: update profile manifest (always, regardless of
+    //    profiled/symlink mode) and swap the staged profile into place
+    manifest.packages.insert(
+        name.to_string(),
+        InstalledPackage {
+            name: name.to_string(),
+            pname: entry.pname.clone(),
+            version: entry.version.clone(),
+            store_path: entry.store_path.clone(),
+            binaries: binaries.clone(),
+        },
+    );
+    txn.commit(&manifest)?;
+
+    eprintln!();
+    eprintln!("✓ installed {name} {}", entry.version);
+    if !binaries.is_empty() {
+        if profiled_is_running() {
+            eprintln!("  binaries available via profile: scheme");
+        } else {
+            eprintln!("  binaries available in {PROFILE_BIN}/");
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetch a package and stage its profile links inside `txn`.
+///
+/// Returns the binaries the package provides.
+fn stage_install(
+    txn: &mut InstallTransaction,
+    name: &str,
+    store_path: &str,
+    version: &str,
+    source: &CacheSource,
+    lazy: bool,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    // Fetch from cache — eager or lazy
     let stored_running = stored_is_running();
     if lazy && stored_running {
         // Lazy install: register in PathInfoDb without extracting.
         // The stored daemon will extract on first access via the store: scheme.
-        if !Path::new(&entry.store_path).exists() {
-            eprintln!("lazy-installing {name} {} (stored will extract on demand)...", entry.version);
-            register_without_extract(&entry.store_path, source)?;
+        if !store_root::real_path(store_path).exists() {
+            eprintln!("lazy-installing {name} {version} (stored will extract on demand)...");
+            txn.record_fetched(store_path);
+            register_without_extract(store_path, source)?;
         } else {
             eprintln!("'{name}' already in store...");
         }
@@ -246,83 +428,51 @@ pub fn install_with_options(
             eprintln!("note: --lazy requires the stored daemon; falling back to eager install");
         }
         // Eager install: download, decompress, extract to /nix/store/
-        if !Path::new(&entry.store_path).exists() {
-            eprintln!("installing {name} {}...", entry.version);
-            fetch_and_extract(&entry.store_path, source)?;
+        if !store_root::real_path(store_path).exists() {
+            eprintln!("installing {name} {version}...");
+            txn.fetch(store_path, source)?;
         } else {
             eprintln!("'{name}' already in store, linking into profile...");
         }
     }
 
-    // 4. Notify stored daemon about the new manifest (if running).
-    //    This lets stored serve directory listings and file content
-    //    for packages installed after the daemon started.
+    // Notify stored daemon about the new manifest (if running).
+    // This lets stored serve directory listings and file content
+    // for packages installed after the daemon started.
     if stored_running {
         let files = crate::pathinfo::PathInfoDb::open()
             .ok()
-            .and_then(|db| db.get(&entry.store_path).ok().flatten())
+            .and_then(|db| db.get(store_path).ok().flatten())
             .map(|info| info.files)
             .unwrap_or_default();
-        stored_notify(&entry.store_path, &files);
+        stored_notify(store_path, &files);
     }
 
-    // 5. Add GC root to protect from garbage collection
-    let root_name = format!("profile-{name}");
-    store::add_root(&root_name, &entry.store_path)?;
+    // Add GC root to protect from garbage collection
+    txn.add_root(&format!("profile-{name}"), store_path)?;
 
-    // 5. Link into profile — prefer profiled daemon, fall back to symlinks
+    // Link into profile — prefer profiled daemon, fall back to symlinks
     let binaries = if profiled_is_running() {
         // Use the profiled scheme daemon (no symlinks needed).
-        match profiled_add(name, &entry.store_path) {
+        match profiled_add(name, store_path) {
             Ok(()) => {
                 eprintln!("  registered via profiled daemon");
+                txn.record_profiled(name);
                 // Discover binaries for manifest metadata (informational only).
-                list_binaries(&PathBuf::from(&entry.store_path).join("bin"))
+                list_binaries(&PathBuf::from(store_path).join("bin"))
                     .unwrap_or_default()
             }
             Err(e) => {
                 eprintln!("  warning: profiled command failed ({e}), falling back to symlinks");
-                link_package_binaries(&entry.store_path)?
+                link_package_binaries(&txn.staging_bin(), store_path)?
             }
         }
     } else {
         // Fall back to traditional symlink-based profile.
-        link_package_binaries(&entry.store_path)?
+        link_package_binaries(&txn.staging_bin(), store_path)?
     };
 
-    if binaries.is_empty() {
-        eprintln!("  note: no binaries found in {}/bin/", entry.store_path);
-    } else {
-        eprintln!("  linked {} binaries:", binaries.len());
-        for bin in &binaries {
-            eprintln!("    {bin}");
-        }
-    }
-
-    // 6. Update profile manifest (always, regardless of profiled/symlink mode)
-    manifest.packages.insert(
-        name.to_string(),
-        InstalledPackage {
-            name: name.to_string(),
-            pname: entry.pname.clone(),
-            version: entry.version.clone(),
-            store_path: entry.store_path.clone(),
-            binaries: binaries.clone(),
-        },
-    );
-    manifest.save()?;
-
-    eprintln!();
-    eprintln!("✓ installed {name} {}", entry.version);
-    if !binaries.is_empty() {
-        if profiled_is_running() {
-            eprintln!("  binaries available via profile: scheme");
-        } else {
-            eprintln!("  binaries available in {PROFILE_BIN}/");
-        }
-    }
-
-    Ok(())
+    Ok(binaries)
 }
 
 /// Remove a package from the profile.
@@ -333,6 +483,7 @@ pub fn remove(name: &str) -> Result<(), Box<dyn std::error::Error>> {
         .packages
         .remove(name)
         .ok_or_else(|| format!("'{name}' is not installed. Run `snix profile list` to see installed packages."))?;
+    ensure_baseline_generation(Path::new(PROFILE_DIR));
 
     // Remove from profile — prefer profiled daemon, fall back to symlinks
     if profiled_is_running() {
@@ -367,6 +518,7 @@ pub fn remove(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let _ = store::remove_root(&root_name); // Best-effort
 
     manifest.save()?;
+    record_generation(Path::new(PROFILE_DIR));
 
     eprintln!("✓ removed {name}");
     eprintln!("  store path still exists: {}", pkg.store_path);
@@ -417,9 +569,76 @@ pub fn list_profile() -> Result<(), Box<dyn std::error::Error>> {
 pub fn show(
     name: &str,
     source: &CacheSource,
+    tree: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Delegate to the CacheSource's show_package which handles both variants
-    source.show_package(name)
+    // Delegate to the CacheSource, which handles both variants
+    if tree {
+        source.show_package_tree(name)
+    } else {
+        source.show_package(name)
+    }
+}
+
+/// List profile generations and what each one changed.
+pub fn history() -> Result<(), Box<dyn std::error::Error>> {
+    let profile_dir = Path::new(PROFILE_DIR);
+    let generations = list_generations(profile_dir)?;
+    if generations.is_empty() {
+        println!("No profile generations yet.");
+        println!("Generations are recorded by `snix install` and `snix remove`.");
+        return Ok(());
+    }
+
+    let current = current_generation(profile_dir);
+    let empty = ProfileManifest::default();
+    let mut previous = &empty;
+    for generation in &generations {
+        let marker = if Some(generation.number) == current { " (current)" } else { "" };
+        println!("Generation {} ({}){marker}:", generation.number, generation.created);
+
+        let changes = manifest_diff(previous, &generation.manifest);
+        if changes.is_empty() {
+            println!("  (no package changes)");
+        }
+        for change in changes {
+            println!("  {change}");
+        }
+        println!();
+        previous = &generation.manifest;
+    }
+
+    Ok(())
+}
+
+/// Switch the profile back to the generation before the current one.
+pub fn rollback() -> Result<(), Box<dyn std::error::Error>> {
+    let before = ProfileManifest::load();
+    let (from, to) = rollback_profile(Path::new(PROFILE_DIR))?;
+    let after = ProfileManifest::load();
+
+    // Keep GC roots and the profiled daemon in step with the restored manifest
+    let using_profiled = profiled_is_running();
+    for name in before.packages.keys().filter(|n| !after.packages.contains_key(*n)) {
+        let _ = store::remove_root(&format!("profile-{name}"));
+        if using_profiled {
+            let _ = profiled_remove(name);
+        }
+    }
+    for (name, pkg) in &after.packages {
+        if before.packages.get(name).map(|p| &p.store_path) != Some(&pkg.store_path) {
+            store::add_root(&format!("profile-{name}"), &pkg.store_path)?;
+            if using_profiled {
+                let _ = profiled_remove(name);
+                profiled_add(name, &pkg.store_path)?;
+            }
+        }
+    }
+
+    for change in manifest_diff(&before, &after) {
+        eprintln!("  {change}");
+    }
+    eprintln!("✓ rolled back profile from generation {from} to {to}");
+    Ok(())
 }
 
 /// Install a package and all its transitive dependencies from a binary cache.
@@ -427,25 +646,99 @@ pub fn show(
 /// Uses BFS to discover dependencies from narinfo References fields.
 /// Already-present local store paths are skipped.
 pub fn install_recursive(
-    name: &str,
+    target: &str,
     source: &CacheSource,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // 1. Look up package in index
-    let index = source.read_index()?;
-    let entry = index
-        .packages
-        .get(name)
-        .ok_or_else(|| format!("package '{name}' not found in {}.", source.display_name()))?;
+    // 1. Resolve the package: index lookup, or straight from the store path
+    let entry = resolve_target(&InstallTarget::parse(target), source)?;
+    let (name, source) = (entry.name.as_str(), &entry.source);
+
+    // 2. Resolve the whole closure up front: a dependency missing from the
+    //    cache fails here, before anything is downloaded.
+    let db = PathInfoDb::open()?;
+    let closure = resolve_closure(&entry.store_path, source, &db)?;
 
-    // 2. BFS dependency resolution
+    // 3. Fetch and link inside a transaction
+    let mut txn = InstallTransaction::begin(Path::new(PROFILE_DIR))?;
+    let mut manifest = ProfileManifest::load();
+    let already_installed = manifest.packages.contains_key(name);
+
+    let staged = fetch_closure(&mut txn, &closure, source, &db).and_then(|fetched| {
+        if already_installed {
+            return Ok((fetched, None));
+        }
+        let binaries = link_package_binaries(&txn.staging_bin(), &entry.store_path)?;
+        txn.add_root(&format!("profile-{name}"), &entry.store_path)?;
+        Ok((fetched, Some(binaries)))
+    });
+
+    let (fetched, binaries) = match staged {
+        Ok(staged) => staged,
+        Err(e) => {
+            eprintln!("install of {name} failed, rolling back...");
+            txn.rollback(Some(&db));
+            return Err(e);
+        }
+    };
+
+    eprintln!();
+    eprintln!("Done: {fetched} fetched, {} already present", closure.already_present);
+
+    // 4. Commit the profile (same as regular install)
+    if let Some(binaries) = binaries {
+        manifest.packages.insert(
+            name.to_string(),
+            InstalledPackage {
+                name: name.to_string(),
+                pname: entry.pname.clone(),
+                version: entry.version.clone(),
+                store_path: entry.store_path.clone(),
+                binaries,
+            },
+        );
+    }
+    txn.commit(&manifest)?;
+
+    if !already_installed {
+        let bin_dir = store_root::real_path(&entry.store_path).join("bin");
+        check_references(name, &entry.store_path, &bin_dir, Some(&db));
+        eprintln!("✓ installed {name} {} (with dependencies)", entry.version);
+    }
+
+    Ok(())
+}
+
+/// A closure member that is not yet both on disk and registered.
+struct PendingPath {
+    store_path: String,
+    narinfo: NarInfo<'static>,
+    on_disk: bool,
+}
+
+/// The result of resolving an install closure against the local store.
+struct ResolvedClosure {
+    /// Paths that still need fetching or registering, in BFS order.
+    pending: Vec<PendingPath>,
+    /// Paths already present and registered.
+    already_present: u32,
+}
+
+/// Walk the closure of `root`, fetching narinfo for every path the store lacks.
+///
+/// Uses BFS over narinfo References (or PathInfo references for paths
+/// already registered). Fails if any member is missing from the cache, so
+/// an install never starts on a closure it cannot complete.
+fn resolve_closure(
+    root: &str,
+    source: &CacheSource,
+    db: &PathInfoDb,
+) -> Result<ResolvedClosure, Box<dyn std::error::Error>> {
     let mut queue: VecDeque<String> = VecDeque::new();
     let mut visited: BTreeSet<String> = BTreeSet::new();
-    let mut fetched: u32 = 0;
-    let mut skipped: u32 = 0;
-
-    queue.push_back(entry.store_path.clone());
+    let mut pending = Vec::new();
+    let mut already_present: u32 = 0;
 
-    let db = PathInfoDb::open()?;
+    queue.push_back(root.to_string());
 
     while let Some(path) = queue.pop_front() {
         if visited.contains(&path) {
@@ -453,11 +746,10 @@ pub fn install_recursive(
         }
         visited.insert(path.clone());
 
-        let already_present = Path::new(&path).exists();
-        let already_registered = db.is_registered(&path);
+        let on_disk = store_root::real_path(&path).exists();
 
-        if already_present && already_registered {
-            skipped += 1;
+        if on_disk && db.is_registered(&path) {
+            already_present += 1;
             eprintln!("✓ already present: {path}");
 
             // Follow references for completeness
@@ -473,71 +765,373 @@ pub fn install_recursive(
 
         // Fetch narinfo to discover references
         let sp = StorePath::<String>::from_absolute_path(path.as_bytes())?;
-        let narinfo = source.fetch_narinfo(&sp)?;
+        let narinfo = source
+            .fetch_narinfo(&sp)
+            .map_err(|e| format!("closure of {root} is incomplete: {path}: {e}"))?;
 
         // Enqueue dependencies
-        let references: Vec<String> = narinfo
-            .references
-            .iter()
-            .map(|r| r.to_absolute_path())
-            .collect();
-        for r in &references {
-            if !visited.contains(r) {
-                queue.push_back(r.clone());
+        for r in &narinfo.references {
+            let r = r.to_absolute_path();
+            if !visited.contains(&r) {
+                queue.push_back(r);
             }
         }
 
-        // Download and extract if not present
-        if !already_present {
-            fetch_and_extract(&path, source)?;
-        } else if !already_registered {
+        pending.push(PendingPath {
+            store_path: path,
+            narinfo,
+            on_disk,
+        });
+    }
+
+    Ok(ResolvedClosure {
+        pending,
+        already_present,
+    })
+}
+
+/// Download or register every pending closure member. Returns the count handled.
+fn fetch_closure(
+    txn: &mut InstallTransaction,
+    closure: &ResolvedClosure,
+    source: &CacheSource,
+    db: &PathInfoDb,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    let mut fetched: u32 = 0;
+
+    for pending in &closure.pending {
+        if !pending.on_disk {
+            txn.fetch(&pending.store_path, source)?;
+        } else {
             // Present on disk but not registered
-            let nar_hash_hex = data_encoding::HEXLOWER.encode(&narinfo.nar_hash);
-            let signatures: Vec<String> =
-                narinfo.signatures.iter().map(|s| s.to_string()).collect();
-            store::register_path(
-                &db,
-                &path,
-                &nar_hash_hex,
-                narinfo.nar_size,
-                references.clone(),
-                signatures,
-            )?;
-            eprintln!("✓ registered: {path}");
+            store::register_narinfo(db, &pending.store_path, &pending.narinfo, Vec::new())?;
+            eprintln!("✓ registered: {}", pending.store_path);
         }
 
         fetched += 1;
     }
 
-    eprintln!();
-    eprintln!("Done: {fetched} fetched, {skipped} already present");
+    Ok(fetched)
+}
 
-    // 3. Link into profile (same as regular install)
-    let mut manifest = ProfileManifest::load();
-    if !manifest.packages.contains_key(name) {
-        let binaries = link_package_binaries(&entry.store_path)?;
+// ─── Install Transactions ──────────────────────────────────────────────────
 
-        manifest.packages.insert(
-            name.to_string(),
-            InstalledPackage {
-                name: name.to_string(),
-                pname: entry.pname.clone(),
-                version: entry.version.clone(),
-                store_path: entry.store_path.clone(),
-                binaries: binaries.clone(),
-            },
-        );
-        manifest.save()?;
+/// An install in progress.
+///
+/// Profile symlinks are built in a staging copy of the profile next to
+/// it, and every store path fetched along the way is recorded. Nothing in
+/// the live profile changes until `commit`, which writes the new manifest
+/// into the staged profile and renames the whole directory into place
+/// (`activate::swap_dir_into_place`), so `bin/` and `manifest.json` change
+/// with the same rename.
+/// `rollback` removes the staging directory and everything this install
+/// added, leaving the previous profile untouched.
+struct InstallTransaction {
+    profile_dir: PathBuf,
+    staging_dir: PathBuf,
+    /// Store paths fetched (or lazily registered) by this install
+    fetched: Vec<String>,
+    /// GC roots added by this install
+    roots: Vec<String>,
+    /// Packages registered with the profiled daemon by this install
+    profiled: Vec<String>,
+}
 
-        let root_name = format!("profile-{name}");
-        store::add_root(&root_name, &entry.store_path)?;
+impl InstallTransaction {
+    /// Start a transaction, seeding the staged `bin/` with the current links.
+    fn begin(profile_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let profile_name = profile_dir
+            .file_name()
+            .ok_or_else(|| format!("invalid profile directory: {}", profile_dir.display()))?
+            .to_string_lossy()
+            .to_string();
+        let staging_dir = profile_dir.with_file_name(format!(".{profile_name}-staging"));
+
+        // A commit interrupted between its renames leaves only `{profile}.old`
+        let old = profile_dir.with_file_name(format!("{profile_name}.old"));
+        if !profile_dir.exists() && old.is_dir() {
+            std::fs::rename(&old, profile_dir)?;
+        }
 
-        eprintln!("✓ installed {name} {} (with dependencies)", entry.version);
+        // Leftovers from an interrupted install were never committed
+        activate::cleanup_path(&staging_dir);
+        let staging_bin = staging_dir.join("bin");
+        std::fs::create_dir_all(&staging_bin)?;
+
+        copy_links(&profile_dir.join("bin"), &staging_bin)?;
+
+        Ok(Self {
+            profile_dir: profile_dir.to_path_buf(),
+            staging_dir,
+            fetched: Vec::new(),
+            roots: Vec::new(),
+            profiled: Vec::new(),
+        })
+    }
+
+    /// The staged `bin/` directory that new links go into.
+    fn staging_bin(&self) -> PathBuf {
+        self.staging_dir.join("bin")
+    }
+
+    /// Record a store path added by this install, to be removed on rollback.
+    fn record_fetched(&mut self, store_path: &str) {
+        self.fetched.push(store_path.to_string());
+    }
+
+    /// Record a package registered with the profiled daemon.
+    fn record_profiled(&mut self, name: &str) {
+        self.profiled.push(name.to_string());
+    }
+
+    /// Fetch a store path into the store unless it is already present.
+    fn fetch(
+        &mut self,
+        store_path: &str,
+        source: &CacheSource,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if store_root::real_path(store_path).exists() {
+            return Ok(());
+        }
+        // Record first so a partially extracted path is cleaned up too
+        self.record_fetched(store_path);
+        fetch_and_extract(store_path, source)
     }
 
+    /// Add a GC root, to be removed on rollback.
+    fn add_root(&mut self, name: &str, store_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        store::add_root(name, store_path)?;
+        self.roots.push(name.to_string());
+        Ok(())
+    }
+
+    /// Make the staged profile live. Rolls back if the swap fails.
+    fn commit(self, manifest: &ProfileManifest) -> Result<(), Box<dyn std::error::Error>> {
+        // The profile being replaced becomes generation 1 if none exist yet
+        ensure_baseline_generation(&self.profile_dir);
+
+        let result = manifest
+            .save_to(&self.staging_dir.join("manifest.json"))
+            .and_then(|()| activate::swap_dir_into_place(&self.staging_dir, &self.profile_dir));
+        if let Err(e) = result {
+            self.rollback(PathInfoDb::open().ok().as_ref());
+            return Err(format!("could not commit profile: {e}").into());
+        }
+        record_generation(&self.profile_dir);
+        Ok(())
+    }
+
+    /// Discard the staged profile and undo everything this install added.
+    fn rollback(self, db: Option<&PathInfoDb>) {
+        activate::cleanup_path(&self.staging_dir);
+        for name in &self.profiled {
+            let _ = profiled_remove(name);
+        }
+        for name in &self.roots {
+            let _ = store::remove_root(name);
+        }
+        for path in &self.fetched {
+            activate::cleanup_path(&store_root::real_path(path));
+            if let Some(db) = db {
+                let _ = db.delete(path);
+            }
+            eprintln!("  rolled back {path}");
+        }
+    }
+}
+
+// ─── Generations ───────────────────────────────────────────────────────────
+
+/// A numbered snapshot of the profile's `bin/` links and manifest.
+#[derive(Debug)]
+struct Generation {
+    number: u32,
+    created: String,
+    manifest: ProfileManifest,
+}
+
+/// Metadata stored next to each generation snapshot.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct GenerationInfo {
+    created: String,
+}
+
+/// `/nix/var/snix/profiles/default` → `/nix/var/snix/profiles/default-generations`
+fn generations_dir(profile_dir: &Path) -> PathBuf {
+    let profile_name = profile_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    profile_dir.with_file_name(format!("{profile_name}-generations"))
+}
+
+/// All generations of a profile, oldest first.
+fn list_generations(profile_dir: &Path) -> Result<Vec<Generation>, Box<dyn std::error::Error>> {
+    let dir = generations_dir(profile_dir);
+    let mut generations = Vec::new();
+    if !dir.is_dir() {
+        return Ok(generations);
+    }
+
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        // Skip `current` and half-written `N.tmp` snapshots
+        let Ok(number) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        let created = std::fs::read_to_string(entry.path().join("generation.json"))
+            .ok()
+            .and_then(|json| serde_json::from_str::<GenerationInfo>(&json).ok())
+            .map(|info| info.created)
+            .unwrap_or_else(|| "unknown".to_string());
+        generations.push(Generation {
+            number,
+            created,
+            manifest: ProfileManifest::load_from(&entry.path().join("manifest.json")),
+        });
+    }
+
+    generations.sort_by_key(|g| g.number);
+    Ok(generations)
+}
+
+/// The generation the profile points at (the newest one if unrecorded).
+fn current_generation(profile_dir: &Path) -> Option<u32> {
+    let dir = generations_dir(profile_dir);
+    std::fs::read_to_string(dir.join("current"))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .or_else(|| {
+            list_generations(profile_dir)
+                .ok()?
+                .last()
+                .map(|g| g.number)
+        })
+}
+
+fn set_current_generation(profile_dir: &Path, number: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = generations_dir(profile_dir);
+    let tmp = dir.join("current.tmp");
+    std::fs::write(&tmp, format!("{number}\n"))?;
+    std::fs::rename(&tmp, dir.join("current"))?;
     Ok(())
 }
 
+/// Snapshot the profile as a new generation and make it current.
+fn snapshot_generation(profile_dir: &Path) -> Result<u32, Box<dyn std::error::Error>> {
+    let dir = generations_dir(profile_dir);
+    std::fs::create_dir_all(&dir)?;
+    let number = list_generations(profile_dir)?
+        .last()
+        .map_or(1, |g| g.number + 1);
+
+    // Built under a temporary name so a crash never leaves a partial generation
+    let staged = dir.join(format!("{number}.tmp"));
+    activate::cleanup_path(&staged);
+    copy_links(&profile_dir.join("bin"), &staged.join("bin"))?;
+    let manifest = profile_dir.join("manifest.json");
+    if manifest.exists() {
+        std::fs::copy(&manifest, staged.join("manifest.json"))?;
+    }
+    let info = GenerationInfo {
+        created: crate::pathinfo::current_timestamp(),
+    };
+    std::fs::write(staged.join("generation.json"), serde_json::to_string_pretty(&info)?)?;
+    std::fs::rename(&staged, dir.join(number.to_string()))?;
+
+    set_current_generation(profile_dir, number)?;
+    Ok(number)
+}
+
+/// Record the profile as it stands before the first tracked change.
+///
+/// Best-effort: a failure is reported but never blocks the install or remove.
+fn ensure_baseline_generation(profile_dir: &Path) {
+    if !profile_dir.join("manifest.json").exists() {
+        return;
+    }
+    let has_generations = list_generations(profile_dir).is_ok_and(|g| !g.is_empty());
+    if !has_generations {
+        if let Err(e) = snapshot_generation(profile_dir) {
+            eprintln!("  warning: could not record profile generation: {e}");
+        }
+    }
+}
+
+/// Record the profile after a change. Best-effort, like `ensure_baseline_generation`.
+fn record_generation(profile_dir: &Path) {
+    if let Err(e) = snapshot_generation(profile_dir) {
+        eprintln!("  warning: could not record profile generation: {e}");
+    }
+}
+
+/// Point the profile at the generation before the current one.
+///
+/// The snapshot's links and manifest are staged and swapped in as one
+/// directory, as in `InstallTransaction::commit`. Returns `(from, to)`.
+fn rollback_profile(profile_dir: &Path) -> Result<(u32, u32), Box<dyn std::error::Error>> {
+    let generations = list_generations(profile_dir)?;
+    let current = current_generation(profile_dir)
+        .ok_or("no profile generations recorded yet; nothing to roll back")?;
+    let target = generations
+        .iter()
+        .rev()
+        .find(|g| g.number < current)
+        .ok_or_else(|| format!("generation {current} is the oldest; nothing to roll back to"))?;
+
+    // Links into garbage-collected paths would leave the profile broken
+    for pkg in target.manifest.packages.values() {
+        if !store_root::real_path(&pkg.store_path).exists() {
+            return Err(format!(
+                "cannot roll back to generation {}: {} has been garbage-collected",
+                target.number, pkg.store_path
+            )
+            .into());
+        }
+    }
+
+    let snapshot = generations_dir(profile_dir).join(target.number.to_string());
+    let profile_name = profile_dir
+        .file_name()
+        .ok_or_else(|| format!("invalid profile directory: {}", profile_dir.display()))?
+        .to_string_lossy()
+        .to_string();
+    let staging_dir = profile_dir.with_file_name(format!(".{profile_name}-staging"));
+    activate::cleanup_path(&staging_dir);
+    copy_links(&snapshot.join("bin"), &staging_dir.join("bin"))?;
+    let result = target
+        .manifest
+        .save_to(&staging_dir.join("manifest.json"))
+        .and_then(|()| activate::swap_dir_into_place(&staging_dir, profile_dir));
+    if let Err(e) = result {
+        activate::cleanup_path(&staging_dir);
+        return Err(e);
+    }
+
+    set_current_generation(profile_dir, target.number)?;
+    Ok((current, target.number))
+}
+
+/// Describe how `new` differs from `old`, one line per package.
+fn manifest_diff(old: &ProfileManifest, new: &ProfileManifest) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (name, pkg) in &new.packages {
+        match old.packages.get(name) {
+            None => changes.push(format!("+ {name} {}", pkg.version)),
+            Some(prev) if prev.store_path != pkg.store_path => {
+                changes.push(format!("~ {name} {} → {}", prev.version, pkg.version))
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, pkg) in &old.packages {
+        if !new.packages.contains_key(name) {
+            changes.push(format!("- {name} {}", pkg.version));
+        }
+    }
+    changes
+}
+
 // ─── Fetch & Extract ───────────────────────────────────────────────────────
 
 /// Register a store path in PathInfoDb WITHOUT extracting the NAR.
@@ -556,15 +1150,7 @@ fn register_without_extract(
 
     // Register in PathInfoDb (no extraction — stored daemon handles that)
     let db = PathInfoDb::open()?;
-    let nar_hash_hex = data_encoding::HEXLOWER.encode(&narinfo.nar_hash);
-    let references: Vec<String> = narinfo
-        .references
-        .iter()
-        .map(|r| r.to_absolute_path())
-        .collect();
-    let signatures: Vec<String> = narinfo.signatures.iter().map(|s| s.to_string()).collect();
-
-    store::register_path(&db, store_path_str, &nar_hash_hex, narinfo.nar_size, references, signatures)?;
+    store::register_narinfo(&db, store_path_str, &narinfo, Vec::new())?;
 
     eprintln!("✓ registered (lazy): {store_path_str}");
     Ok(())
@@ -577,8 +1163,9 @@ fn fetch_and_extract(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let sp = StorePath::<String>::from_absolute_path(store_path_str.as_bytes())?;
     let dest = sp.to_absolute_path();
+    let target = store_root::real_path(&dest);
 
-    if Path::new(&dest).exists() {
+    if target.exists() {
         eprintln!("already exists: {dest}");
         return Ok(());
     }
@@ -596,13 +1183,13 @@ fn fetch_and_extract(
     let mut hashing = HashingReader::new(decompressed);
     let mut buf_reader = BufReader::new(&mut hashing);
 
-    eprintln!("extracting to {dest}...");
-    let manifest = nar::extract_with_manifest(&mut buf_reader, &dest)?;
+    eprintln!("extracting to {}...", target.display());
+    let manifest = nar::extract_with_manifest(&mut buf_reader, &target.to_string_lossy())?;
 
     // Verify hash
     let actual_hash = hashing.finalize();
     if actual_hash != narinfo.nar_hash {
-        let _ = std::fs::remove_dir_all(&dest);
+        let _ = std::fs::remove_dir_all(&target);
         return Err(format!(
             "NAR hash mismatch!\n  expected: {}\n  got:      {}",
             data_encoding::HEXLOWER.encode(&narinfo.nar_hash),
@@ -613,18 +1200,7 @@ fn fetch_and_extract(
 
     // Register in PathInfoDb
     let db = PathInfoDb::open()?;
-    let nar_hash_hex = data_encoding::HEXLOWER.encode(&narinfo.nar_hash);
-    let references: Vec<String> = narinfo
-        .references
-        .iter()
-        .map(|r| r.to_absolute_path())
-        .collect();
-    let signatures: Vec<String> = narinfo.signatures.iter().map(|s| s.to_string()).collect();
-
-    store::register_path_with_files(
-        &db, &dest, &nar_hash_hex, narinfo.nar_size,
-        references, signatures, manifest,
-    )?;
+    store::register_narinfo(&db, &dest, &narinfo, manifest)?;
 
     eprintln!("✓ verified and installed: {dest}");
     Ok(())
@@ -661,9 +1237,12 @@ impl<R: Read> Read for HashingReader<R> {
 
 // ─── Helpers ───────────────────────────────────────────────────────────────
 
-/// Discover binaries in a store path and create profile symlinks.
-fn link_package_binaries(store_path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    ensure_dir(PROFILE_BIN)?;
+/// Discover binaries in a store path and create symlinks to them in `profile_bin`.
+fn link_package_binaries(
+    profile_bin: &Path,
+    store_path: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(profile_bin)?;
 
     let bin_dir = PathBuf::from(store_path).join("bin");
     if !bin_dir.is_dir() {
@@ -678,7 +1257,7 @@ fn link_package_binaries(store_path: &str) -> Result<Vec<String>, Box<dyn std::e
         let name = file_name.to_string_lossy().to_string();
 
         let target = entry.path();
-        let link = PathBuf::from(PROFILE_BIN).join(&name);
+        let link = profile_bin.join(&name);
 
         // Remove existing symlink if present (might be from different version)
         if link.is_symlink() {
@@ -698,6 +1277,61 @@ fn link_package_binaries(store_path: &str) -> Result<Vec<String>, Box<dyn std::e
     Ok(binaries)
 }
 
+/// Warn about store paths that the binaries in `bin_dir` (of `store_path`)
+/// mention but that are neither registered nor on disk.
+///
+/// Those are usually libraries or interpreters missing from an incomplete
+/// closure: the binary would fail when run. Returns the missing paths.
+fn check_references(
+    name: &str,
+    store_path: &str,
+    bin_dir: &Path,
+    db: Option<&PathInfoDb>,
+) -> Vec<String> {
+    let Ok(referenced) = local_build::scan_store_paths(bin_dir) else {
+        return Vec::new();
+    };
+    let missing: Vec<String> = referenced
+        .into_iter()
+        .filter(|path| path != store_path)
+        .filter(|path| {
+            let registered = db.is_some_and(|db| matches!(db.get(path), Ok(Some(_))));
+            !registered && !store_root::real_path(path).exists()
+        })
+        .collect();
+
+    if !missing.is_empty() {
+        eprintln!("  warning: binaries of {name} reference store paths that are not in the store:");
+        for path in &missing {
+            eprintln!("    {path}");
+        }
+        eprintln!("  they will fail to run; `snix install --recursive` also fetches dependencies");
+    }
+    missing
+}
+
+/// Recreate the symlinks in `from` inside `to` (created if missing).
+fn copy_links(from: &Path, to: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(to)?;
+    if !from.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let Ok(target) = std::fs::read_link(entry.path()) else {
+            continue;
+        };
+        let link = to.join(entry.file_name());
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&target, &link)?;
+
+        #[cfg(not(unix))]
+        std::fs::copy(&target, &link)?;
+    }
+    Ok(())
+}
+
 fn list_binaries(bin_dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     let mut bins = Vec::new();
     if bin_dir.is_dir() {
@@ -731,6 +1365,7 @@ fn format_size(bytes: u64) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_http;
 
     #[test]
     fn profile_manifest_roundtrip() {
@@ -875,4 +1510,370 @@ mod tests {
         // On non-Redox, profiled_list returns None.
         assert!(profiled_list().is_none());
     }
+
+    // ── Transactional Install Tests ────────────────────────────────────
+
+    const P_APP: &str = "/nix/store/1b9jydsiygi6jhlz2dxbrxi6b4m1rn4r-app-1.0";
+    const P_LIB: &str = "/nix/store/2c8kzfrjzhi7jkmz3fxcsyj7c5n2sp5s-lib-1.0";
+
+    fn write_narinfo(cache: &Path, store_path: &str, references: &[&str]) {
+        let base = store_path.strip_prefix("/nix/store/").unwrap();
+        let hash = &base[..32];
+        let refs: Vec<&str> = references
+            .iter()
+            .map(|r| r.strip_prefix("/nix/store/").unwrap())
+            .collect();
+        let narinfo = format!(
+            "StorePath: {store_path}\nURL: nar/{hash}.nar\nCompression: none\n\
+             NarHash: sha256:{}\nNarSize: 120\nReferences: {}\n",
+            "0".repeat(52),
+            refs.join(" "),
+        );
+        std::fs::write(cache.join(format!("{hash}.narinfo")), narinfo).unwrap();
+    }
+
+    #[test]
+    fn resolve_closure_fails_on_missing_dependency() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        std::fs::create_dir_all(&cache).unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+
+        // The app references a library whose narinfo is missing from the cache.
+        write_narinfo(&cache, P_APP, &[P_LIB]);
+
+        let err = resolve_closure(P_APP, &CacheSource::Local(cache), &db)
+            .err()
+            .expect("closure with a missing dependency must not resolve")
+            .to_string();
+        assert!(err.contains("incomplete"), "{err}");
+        assert!(err.contains(P_LIB), "{err}");
+    }
+
+    #[test]
+    fn check_references_warns_about_unregistered_paths() {
+        use crate::pathinfo::PathInfo;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+        db.register(&PathInfo {
+            store_path: P_LIB.to_string(),
+            nar_hash: "0".repeat(64),
+            nar_size: 0,
+            references: vec![],
+            deriver: None,
+            output: None,
+            registration_time: "2026-01-01T00:00:00Z".to_string(),
+            signatures: vec![],
+            files: vec![],
+        })
+        .unwrap();
+
+        // A binary with its own path, a registered library and an
+        // unregistered one in its RPATH.
+        let missing = "/nix/store/3d8fkhz1wq2vk4a6ywmc1x6jb7p2xr9l-libfoo-1.0";
+        let bin = tmp.path().join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(
+            bin.join("app"),
+            format!("\x7fELF\0{P_APP}/share\0{P_LIB}/lib:{missing}/lib\0"),
+        )
+        .unwrap();
+
+        assert_eq!(check_references("app", P_APP, &bin, Some(&db)), vec![missing]);
+
+        // Once the library is there, nothing is reported.
+        std::fs::write(bin.join("app"), format!("\x7fELF\0{P_LIB}/lib\0")).unwrap();
+        assert!(check_references("app", P_APP, &bin, Some(&db)).is_empty());
+    }
+
+    #[test]
+    fn resolve_closure_complete() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().join("cache");
+        std::fs::create_dir_all(&cache).unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+
+        write_narinfo(&cache, P_APP, &[P_LIB]);
+        write_narinfo(&cache, P_LIB, &[]);
+
+        let closure = resolve_closure(P_APP, &CacheSource::Local(cache), &db).unwrap();
+        let paths: Vec<&str> = closure
+            .pending
+            .iter()
+            .map(|p| p.store_path.as_str())
+            .collect();
+        assert_eq!(paths, vec![P_APP, P_LIB]);
+        assert_eq!(closure.already_present, 0);
+    }
+
+    // ── Install Target Tests ───────────────────────────────────────────
+
+    #[test]
+    fn install_target_forms() {
+        assert_eq!(InstallTarget::parse("ripgrep"), InstallTarget::Name("ripgrep".into()));
+        assert_eq!(
+            InstallTarget::parse(&format!("{P_APP}/")),
+            InstallTarget::StorePath(P_APP.into())
+        );
+        let url = "http://10.0.2.2:8080/1b9jydsiygi6jhlz2dxbrxi6b4m1rn4r.narinfo";
+        assert_eq!(InstallTarget::parse(url), InstallTarget::NarinfoUrl(url.into()));
+        // A cache URL alone is not something to install.
+        assert_eq!(
+            InstallTarget::parse("http://10.0.2.2:8080"),
+            InstallTarget::Name("http://10.0.2.2:8080".into())
+        );
+    }
+
+    #[test]
+    fn resolve_name_uses_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(
+            tmp.path().join("packages.json"),
+            format!(
+                r#"{{"version":1,"packages":{{"myapp":{{"storePath":"{P_APP}","pname":"app","version":"1.0"}}}}}}"#
+            ),
+        )
+        .unwrap();
+        let source = CacheSource::Local(tmp.path().to_path_buf());
+
+        let pkg = resolve_target(&InstallTarget::parse("myapp"), &source).unwrap();
+        assert_eq!(pkg.name, "myapp");
+        assert_eq!(pkg.pname, "app");
+        assert_eq!(pkg.store_path, P_APP);
+
+        let err = resolve_target(&InstallTarget::parse("missing"), &source).unwrap_err();
+        assert!(err.to_string().contains("not found"), "{err}");
+    }
+
+    #[test]
+    fn resolve_store_path_skips_index() {
+        // No packages.json: a store path must not need one.
+        let tmp = tempfile::tempdir().unwrap();
+        write_narinfo(tmp.path(), P_APP, &[]);
+        let source = CacheSource::Local(tmp.path().to_path_buf());
+
+        let pkg = resolve_target(&InstallTarget::parse(P_APP), &source).unwrap();
+        assert_eq!((pkg.name.as_str(), pkg.version.as_str()), ("app", "1.0"));
+        assert_eq!(pkg.store_path, P_APP);
+        assert!(pkg.source.is_local());
+
+        let bad = "/nix/store/not-a-valid-hash-app-1.0";
+        assert!(resolve_target(&InstallTarget::parse(bad), &source).is_err());
+    }
+
+    #[test]
+    fn resolve_narinfo_url_uses_its_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        write_narinfo(tmp.path(), P_APP, &[P_LIB]);
+        let hash = &P_APP["/nix/store/".len()..][..32];
+        let narinfo = std::fs::read_to_string(tmp.path().join(format!("{hash}.narinfo"))).unwrap();
+
+        let base = test_http::serve_files(&[
+            (&format!("/sub/{hash}.narinfo"), &narinfo),
+            // Served under another path hash than it describes.
+            ("/sub/00bgd045z0d4icpbc2yyz4gx48ak44la.narinfo", &narinfo),
+        ]);
+        // The cache passed on the command line is not consulted.
+        let source = CacheSource::Local(tmp.path().join("unused"));
+
+        let url = format!("{base}/sub/{hash}.narinfo");
+        let pkg = resolve_target(&InstallTarget::parse(&url), &source).unwrap();
+        assert_eq!(pkg.name, "app");
+        assert_eq!(pkg.store_path, P_APP);
+        assert!(matches!(&pkg.source, CacheSource::Remote(u) if *u == format!("{base}/sub")));
+
+        let url = format!("{base}/sub/00bgd045z0d4icpbc2yyz4gx48ak44la.narinfo");
+        assert!(resolve_target(&InstallTarget::parse(&url), &source).is_err());
+    }
+
+    fn profile_with_link(tmp: &Path) -> PathBuf {
+        let profile = tmp.join("profiles/default");
+        std::fs::create_dir_all(profile.join("bin")).unwrap();
+        std::os::unix::fs::symlink("/nix/store/old/bin/old", profile.join("bin/old")).unwrap();
+        std::fs::write(profile.join("manifest.json"), "{\"version\":1,\"packages\":{}}").unwrap();
+        profile
+    }
+
+    #[test]
+    fn transaction_rollback_leaves_profile_untouched() {
+        let tmp = tempfile::tempdir().unwrap();
+        let profile = profile_with_link(tmp.path());
+        let manifest_before = std::fs::read_to_string(profile.join("manifest.json")).unwrap();
+
+        // A path this install "fetched" before a dependency failed.
+        let partial = tmp.path().join("partial-output");
+        std::fs::create_dir_all(partial.join("bin")).unwrap();
+
+        let mut txn = InstallTransaction::begin(&profile).unwrap();
+        txn.record_fetched(&partial.to_string_lossy());
+        std::os::unix::fs::symlink("/nix/store/new/bin/new", txn.staging_bin().join("new")).unwrap();
+        let staging = txn.staging_dir.clone();
+        txn.rollback(None);
+
+        assert!(!staging.exists());
+        assert!(!partial.exists());
+        assert!(profile.join("bin/old").symlink_metadata().is_ok());
+        assert!(profile.join("bin/new").symlink_metadata().is_err());
+        assert_eq!(
+            std::fs::read_to_string(profile.join("manifest.json")).unwrap(),
+            manifest_before
+        );
+    }
+
+    #[test]
+    fn transaction_commit_swaps_profile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let profile = profile_with_link(tmp.path());
+
+        let txn = InstallTransaction::begin(&profile).unwrap();
+        std::os::unix::fs::symlink("/nix/store/new/bin/new", txn.staging_bin().join("new")).unwrap();
+        let staging = txn.staging_dir.clone();
+
+        let mut manifest = ProfileManifest {
+            version: 1,
+            packages: BTreeMap::new(),
+        };
+        manifest.packages.insert(
+            "new".to_string(),
+            InstalledPackage {
+                name: "new".to_string(),
+                pname: "new".to_string(),
+                version: "1.0".to_string(),
+                store_path: "/nix/store/new".to_string(),
+                binaries: vec!["new".to_string()],
+            },
+        );
+        txn.commit(&manifest).unwrap();
+
+        assert!(!staging.exists());
+        assert!(!profile.with_file_name("default.new").exists());
+        assert!(!profile.with_file_name("default.old").exists());
+        // Existing links are carried over into the new profile.
+        assert!(profile.join("bin/old").symlink_metadata().is_ok());
+        assert_eq!(
+            std::fs::read_link(profile.join("bin/new")).unwrap(),
+            PathBuf::from("/nix/store/new/bin/new")
+        );
+        let saved: ProfileManifest =
+            serde_json::from_str(&std::fs::read_to_string(profile.join("manifest.json")).unwrap())
+                .unwrap();
+        assert!(saved.packages.contains_key("new"));
+    }
+
+    #[test]
+    fn transaction_recovers_interrupted_commit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let profile = profile_with_link(tmp.path());
+
+        // The old profile was moved aside, the new one never moved in.
+        let old = profile.with_file_name("default.old");
+        std::fs::rename(&profile, &old).unwrap();
+
+        let txn = InstallTransaction::begin(&profile).unwrap();
+        assert!(!old.exists());
+        assert!(profile.join("bin/old").symlink_metadata().is_ok());
+        assert!(txn.staging_bin().join("old").symlink_metadata().is_ok());
+    }
+
+    fn fake_package(tmp: &Path, name: &str, version: &str) -> InstalledPackage {
+        let store_path = tmp.join(format!("store/{name}-{version}"));
+        std::fs::create_dir_all(store_path.join("bin")).unwrap();
+        std::fs::write(store_path.join("bin").join(name), "#!/bin/sh\n").unwrap();
+        InstalledPackage {
+            name: name.to_string(),
+            pname: name.to_string(),
+            version: version.to_string(),
+            store_path: store_path.to_string_lossy().to_string(),
+            binaries: vec![name.to_string()],
+        }
+    }
+
+    /// Install through the same transaction `install` uses, minus the fetch.
+    fn install_fake(profile: &Path, manifest: &mut ProfileManifest, pkg: InstalledPackage) {
+        let txn = InstallTransaction::begin(profile).unwrap();
+        link_package_binaries(&txn.staging_bin(), &pkg.store_path).unwrap();
+        manifest.packages.insert(pkg.name.clone(), pkg);
+        txn.commit(manifest).unwrap();
+    }
+
+    #[test]
+    fn rollback_restores_previous_generation() {
+        let tmp = tempfile::tempdir().unwrap();
+        let profile = tmp.path().join("profiles/default");
+        let mut manifest = ProfileManifest { version: 1, packages: BTreeMap::new() };
+
+        install_fake(&profile, &mut manifest, fake_package(tmp.path(), "hello", "2.12"));
+        install_fake(&profile, &mut manifest, fake_package(tmp.path(), "ripgrep", "14.1"));
+
+        let generations = list_generations(&profile).unwrap();
+        assert_eq!(generations.iter().map(|g| g.number).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(current_generation(&profile), Some(2));
+        assert!(profile.join("bin/ripgrep").symlink_metadata().is_ok());
+
+        assert_eq!(rollback_profile(&profile).unwrap(), (2, 1));
+
+        assert!(profile.join("bin/hello").symlink_metadata().is_ok());
+        assert!(profile.join("bin/ripgrep").symlink_metadata().is_err());
+        let restored = ProfileManifest::load_from(&profile.join("manifest.json"));
+        assert_eq!(restored.packages.keys().collect::<Vec<_>>(), vec!["hello"]);
+        assert_eq!(current_generation(&profile), Some(1));
+        assert!(!tmp.path().join("profiles/.default-staging").exists());
+
+        // Generation 1 is the oldest
+        assert!(rollback_profile(&profile).is_err());
+    }
+
+    #[test]
+    fn first_change_snapshots_existing_profile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let profile = profile_with_link(tmp.path());
+        let mut manifest = ProfileManifest::load_from(&profile.join("manifest.json"));
+
+        install_fake(&profile, &mut manifest, fake_package(tmp.path(), "hello", "2.12"));
+
+        let generations = list_generations(&profile).unwrap();
+        assert_eq!(generations.len(), 2);
+        assert!(generations[0].manifest.packages.is_empty());
+        assert!(generations[1].manifest.packages.contains_key("hello"));
+
+        rollback_profile(&profile).unwrap();
+        assert!(profile.join("bin/old").symlink_metadata().is_ok());
+        assert!(profile.join("bin/hello").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn rollback_refuses_collected_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let profile = tmp.path().join("profiles/default");
+        let mut manifest = ProfileManifest { version: 1, packages: BTreeMap::new() };
+
+        let hello = fake_package(tmp.path(), "hello", "2.12");
+        let hello_path = hello.store_path.clone();
+        install_fake(&profile, &mut manifest, hello);
+        install_fake(&profile, &mut manifest, fake_package(tmp.path(), "ripgrep", "14.1"));
+        std::fs::remove_dir_all(&hello_path).unwrap();
+
+        let err = rollback_profile(&profile).unwrap_err().to_string();
+        assert!(err.contains("garbage-collected"), "{err}");
+        assert_eq!(current_generation(&profile), Some(2));
+        assert!(profile.join("bin/ripgrep").symlink_metadata().is_ok());
+    }
+
+    #[test]
+    fn manifest_diff_lists_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut old = ProfileManifest::default();
+        let mut new = ProfileManifest::default();
+        old.packages.insert("hello".to_string(), fake_package(tmp.path(), "hello", "2.12"));
+        old.packages.insert("fd".to_string(), fake_package(tmp.path(), "fd", "9.0"));
+        new.packages.insert("fd".to_string(), fake_package(tmp.path(), "fd", "10.1"));
+        new.packages.insert("ripgrep".to_string(), fake_package(tmp.path(), "ripgrep", "14.1"));
+
+        assert_eq!(
+            manifest_diff(&old, &new),
+            vec!["~ fd 9.0 → 10.1", "+ ripgrep 14.1", "- hello 2.12"]
+        );
+        assert!(manifest_diff(&new, &new).is_empty());
+    }
 }
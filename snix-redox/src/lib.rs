@@ -10,6 +10,7 @@ pub mod build_proxy;
 pub mod cache;
 pub mod cache_source;
 pub mod channel;
+pub mod completions;
 pub mod derivation_builtins;
 pub mod eval;
 pub mod fetchers;
@@ -20,12 +21,17 @@ pub mod known_paths;
 pub mod local_build;
 pub mod local_cache;
 pub mod nar;
+pub mod nix_daemon;
+pub mod nix_http;
 pub mod pathinfo;
 pub mod profiled;
 pub mod rebuild;
 pub mod sandbox;
 pub mod snix_io;
 pub mod store;
+pub mod store_root;
 pub mod stored;
 pub mod system;
+#[cfg(test)]
+mod test_http;
 pub mod vendor;
@@ -25,15 +25,12 @@
 
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::io::{self, BufReader};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-#[cfg(unix)]
-use std::os::unix::fs::PermissionsExt;
-
 use nix_compat::nixbase32;
-use nix_compat::store_path::{StorePath, STORE_DIR};
+use nix_compat::store_path::{StorePath, STORE_DIR, STORE_DIR_WITH_SLASH};
 use sha2::{Digest, Sha256};
 
 use crate::known_paths::KnownPaths;
@@ -436,6 +433,7 @@ fn build_derivation_inner(
             nar_size: size,
             references: references.iter().cloned().collect(),
             deriver: Some(drv_path.to_absolute_path()),
+            output: Some(name.clone()),
             registration_time: pathinfo::current_timestamp(),
             signatures: vec![],
             files: vec![],
@@ -504,6 +502,7 @@ fn build_result_from_existing(
             nar_size: size,
             references: references.iter().cloned().collect(),
             deriver: Some(drv_path.to_absolute_path()),
+            output: Some(name.clone()),
             registration_time: pathinfo::current_timestamp(),
             signatures: vec![],
             files: vec![],
@@ -644,8 +643,7 @@ fn topological_sort(
 /// result. This matches `nix hash path --type sha256` output.
 pub fn nar_hash_path(path: &Path) -> io::Result<(String, u64)> {
     let mut buf: Vec<u8> = Vec::new();
-    let nar = nix_compat::nar::writer::open(&mut buf)?;
-    write_path_to_nar(nar, path)?;
+    crate::nar::dump(path, &mut buf)?;
 
     let hash = Sha256::digest(&buf);
     let nar_hash = format!("sha256:{:x}", hash);
@@ -654,59 +652,6 @@ pub fn nar_hash_path(path: &Path) -> io::Result<(String, u64)> {
     Ok((nar_hash, nar_size))
 }
 
-/// Recursively serialize a filesystem path into NAR format.
-fn write_path_to_nar<W: io::Write>(
-    node: nix_compat::nar::writer::Node<'_, W>,
-    path: &Path,
-) -> io::Result<()> {
-    let meta = fs::symlink_metadata(path)?;
-
-    if meta.file_type().is_symlink() {
-        let target = fs::read_link(path)?;
-        #[cfg(unix)]
-        {
-            use std::os::unix::ffi::OsStrExt;
-            node.symlink(target.as_os_str().as_bytes())?;
-        }
-        #[cfg(not(unix))]
-        {
-            node.symlink(target.to_string_lossy().as_bytes())?;
-        }
-    } else if meta.is_file() {
-        #[cfg(unix)]
-        let executable = meta.permissions().mode() & 0o111 != 0;
-        #[cfg(not(unix))]
-        let executable = false;
-
-        let size = meta.len();
-        let file = fs::File::open(path)?;
-        let mut reader = BufReader::new(file);
-        node.file(executable, size, &mut reader)?;
-    } else if meta.is_dir() {
-        let mut entries: Vec<fs::DirEntry> = fs::read_dir(path)?
-            .collect::<Result<Vec<_>, _>>()?;
-        entries.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
-
-        let mut dir = node.directory()?;
-        for entry in &entries {
-            let name = entry.file_name();
-            #[cfg(unix)]
-            let name_bytes = {
-                use std::os::unix::ffi::OsStrExt;
-                name.as_bytes().to_vec()
-            };
-            #[cfg(not(unix))]
-            let name_bytes = name.to_string_lossy().as_bytes().to_vec();
-
-            let child = dir.entry(&name_bytes)?;
-            write_path_to_nar(child, &entry.path())?;
-        }
-        dir.close()?;
-    }
-
-    Ok(())
-}
-
 // ── Reference Scanning ─────────────────────────────────────────────────────
 
 /// Collect all store paths that could potentially be referenced by a
@@ -756,6 +701,9 @@ fn collect_potential_references(
     candidates
 }
 
+/// Length of the nixbase32 hash part of a store path.
+const HASH_LEN: usize = 32;
+
 /// Scan all files under `path` for store path references.
 ///
 /// Searches for the 32-character nixbase32 hash component of each
@@ -778,37 +726,75 @@ fn scan_path(
     candidates: &HashMap<String, String>,
     found: &mut BTreeSet<String>,
 ) -> io::Result<()> {
+    for_each_content(path, &mut |bytes| scan_bytes(bytes, candidates, found))
+}
+
+/// Find every `/nix/store/<hash>-<name>` string under `path`.
+///
+/// Unlike [scan_references], which only looks for hashes it is given,
+/// this also finds paths the local store knows nothing about.
+pub fn scan_store_paths(path: &Path) -> io::Result<BTreeSet<String>> {
+    let mut found = BTreeSet::new();
+    for_each_content(path, &mut |bytes| find_store_paths(bytes, &mut found))?;
+    Ok(found)
+}
+
+/// Call `f` with the contents of every file under `path`, and with the
+/// target of every symlink.
+fn for_each_content(path: &Path, f: &mut dyn FnMut(&[u8])) -> io::Result<()> {
     let meta = fs::symlink_metadata(path)?;
 
     if meta.is_file() {
         let content = fs::read(path)?;
-        for (hash, store_path) in candidates {
-            if !found.contains(store_path)
-                && content
-                    .windows(hash.len())
-                    .any(|w| w == hash.as_bytes())
-            {
-                found.insert(store_path.clone());
-            }
-        }
+        f(&content);
     } else if meta.is_dir() {
         for entry in fs::read_dir(path)? {
             let entry = entry?;
-            scan_path(&entry.path(), candidates, found)?;
+            for_each_content(&entry.path(), f)?;
         }
     } else if meta.file_type().is_symlink() {
         let target = fs::read_link(path)?;
-        let target_str = target.to_string_lossy();
-        for (hash, store_path) in candidates {
-            if !found.contains(store_path) && target_str.contains(hash.as_str()) {
-                found.insert(store_path.clone());
-            }
-        }
+        f(target.to_string_lossy().as_bytes());
     }
 
     Ok(())
 }
 
+/// Collect the store paths spelled out in `bytes`, cut off at the first
+/// character that can't be part of a store path name (e.g. `/bin/hello`).
+fn find_store_paths(bytes: &[u8], found: &mut BTreeSet<String>) {
+    let prefix = STORE_DIR_WITH_SLASH.as_bytes();
+    let is_name_char =
+        |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.' | b'_' | b'?' | b'=');
+
+    let mut rest = bytes;
+    while let Some(at) = rest.windows(prefix.len()).position(|w| w == prefix) {
+        let after = &rest[at + prefix.len()..];
+        let len = after.iter().take_while(|&&b| is_name_char(b)).count();
+        let candidate = &rest[at..at + prefix.len() + len];
+        if let Ok(sp) = StorePath::<String>::from_absolute_path(candidate) {
+            found.insert(sp.to_absolute_path());
+        }
+        rest = after;
+    }
+}
+
+/// Look up every hash-sized window of `bytes` in `candidates`.
+///
+/// One pass per file regardless of the number of candidates, which
+/// matters for `snix store repair-db`: there every path in the store is
+/// a candidate.
+fn scan_bytes(bytes: &[u8], candidates: &HashMap<String, String>, found: &mut BTreeSet<String>) {
+    for window in bytes.windows(HASH_LEN) {
+        let Ok(hash) = std::str::from_utf8(window) else {
+            continue;
+        };
+        if let Some(store_path) = candidates.get(hash) {
+            found.insert(store_path.clone());
+        }
+    }
+}
+
 // ── Builtin Fetcher Execution ───────────────────────────────────────────────
 
 /// Build a derivation using a builtin fetcher (e.g., `builtin:fetchurl`).
@@ -887,6 +873,7 @@ fn build_builtin_fetcher(
         nar_size,
         references: references.iter().cloned().collect(),
         deriver: Some(drv_path.to_absolute_path()),
+        output: Some("out".to_string()),
         registration_time: pathinfo::current_timestamp(),
         signatures: vec![],
         files: vec![],
@@ -1215,6 +1202,25 @@ mod tests {
         assert!(refs.contains(&store_path));
     }
 
+    #[test]
+    fn scan_store_paths_finds_unknown_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("pkg");
+        fs::create_dir_all(dir.join("bin")).unwrap();
+
+        let lib = "/nix/store/1b9jydsiygi6jhlz2dxbrxi6b4m1rn4r-libfoo-1.0";
+        let dep = "/nix/store/2c8kzfrjzhi7jkmz3fxcsyj7c5n2sp5s-dep-2.0";
+        fs::write(
+            dir.join("bin/hello"),
+            format!("\x7fELF\0{lib}/lib:/nix/store/too-short-1.0\0{lib}/lib/libfoo.so\0"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(format!("{dep}/bin/dep"), dir.join("bin/dep")).unwrap();
+
+        let found = scan_store_paths(&dir).unwrap();
+        assert_eq!(found, BTreeSet::from([lib.to_string(), dep.to_string()]));
+    }
+
     #[test]
     fn scan_empty_candidates() {
         let tmp = tempfile::tempdir().unwrap();
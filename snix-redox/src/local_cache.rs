@@ -9,18 +9,19 @@
 //!   /nix/cache/{hash}.narinfo    — per-path metadata
 //!   /nix/cache/nar/*.nar.zst     — compressed NAR files
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::{self, BufReader, Read};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use nix_compat::narinfo::NarInfo;
 use nix_compat::nixbase32;
 use nix_compat::store_path::StorePath;
 use sha2::{Digest, Sha256};
 
-use crate::nar;
+use crate::nar::{self, Compression};
 use crate::pathinfo::PathInfoDb;
 use crate::store;
+use crate::store_root;
 
 /// Default local cache path on Redox.
 pub const DEFAULT_CACHE_PATH: &str = "/nix/cache";
@@ -35,6 +36,9 @@ pub struct PackageEntry {
     pub nar_hash: Option<String>,
     pub nar_size: Option<u64>,
     pub file_size: Option<u64>,
+    /// One-line package description (`meta.description`), if exported.
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 /// Package index (packages.json).
@@ -53,49 +57,235 @@ pub fn read_index(cache_path: &str) -> Result<PackageIndex, Box<dyn std::error::
     Ok(index)
 }
 
-/// Search for packages matching a pattern.
-pub fn search(cache_path: &str, pattern: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+/// Search for packages matching a pattern and print them, best match first.
+///
+/// `pattern` is a case-insensitive substring, or a regular expression
+/// when `regex` is set. An empty or missing pattern lists everything.
+pub fn search(
+    cache_path: &str,
+    pattern: Option<&str>,
+    regex: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     let index = read_index(cache_path)?;
+    let compiled = SearchPattern::parse(pattern, regex)?;
+    let matches = rank_packages(&index, compiled.as_ref());
+
+    if matches.is_empty() {
+        match pattern.filter(|p| !p.is_empty()) {
+            Some(pat) => eprintln!("No packages matching '{pat}'"),
+            None => eprintln!("No packages in cache"),
+        }
+        return Ok(());
+    }
+
+    print_search_results(&matches);
+    Ok(())
+}
+
+// ─── Search ────────────────────────────────────────────────────────────────
+
+/// A compiled `snix search` pattern.
+#[derive(Debug)]
+pub enum SearchPattern {
+    /// Lowercased substring, matched case-insensitively.
+    Substring(String),
+    /// Case-insensitive regular expression.
+    Regex(regex::Regex),
+}
+
+impl SearchPattern {
+    /// Compile a search pattern. `None` or an empty pattern matches everything.
+    pub fn parse(
+        pattern: Option<&str>,
+        regex: bool,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let Some(pat) = pattern.filter(|p| !p.is_empty()) else {
+            return Ok(None);
+        };
+        if !regex {
+            return Ok(Some(Self::Substring(pat.to_lowercase())));
+        }
+        let re = regex::RegexBuilder::new(pat)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("invalid search regex '{pat}': {e}"))?;
+        Ok(Some(Self::Regex(re)))
+    }
+
+    /// How well a package matches, or `None` if it doesn't.
+    fn rank(&self, name: &str, entry: &PackageEntry) -> Option<MatchRank> {
+        let description = entry.description.as_deref().unwrap_or("");
+        match self {
+            Self::Substring(pat) => {
+                let names = [name.to_lowercase(), entry.pname.to_lowercase()];
+                if names.iter().any(|n| n == pat) {
+                    Some(MatchRank::Exact)
+                } else if names.iter().any(|n| n.starts_with(pat.as_str())) {
+                    Some(MatchRank::Prefix)
+                } else if names.iter().any(|n| n.contains(pat.as_str())) {
+                    Some(MatchRank::Substring)
+                } else if description.to_lowercase().contains(pat.as_str()) {
+                    Some(MatchRank::Description)
+                } else {
+                    None
+                }
+            }
+            Self::Regex(re) => {
+                if re.is_match(name) || re.is_match(&entry.pname) {
+                    Some(MatchRank::Substring)
+                } else if re.is_match(description) {
+                    Some(MatchRank::Description)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// How a package matched a search, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchRank {
+    /// The name is the pattern.
+    Exact,
+    /// The name starts with the pattern.
+    Prefix,
+    /// The pattern occurs in (or, for a regex, matches) the name.
+    Substring,
+    /// Only the description matches.
+    Description,
+}
 
-    let matches: Vec<_> = index
+/// Packages matching `pattern`, ordered by rank and then by name.
+pub fn rank_packages<'a>(
+    index: &'a PackageIndex,
+    pattern: Option<&SearchPattern>,
+) -> Vec<(&'a str, &'a PackageEntry)> {
+    let mut ranked: Vec<_> = index
         .packages
         .iter()
-        .filter(|(name, entry)| match pattern {
-            Some(pat) => {
-                let pat_lower = pat.to_lowercase();
-                name.to_lowercase().contains(&pat_lower)
-                    || entry.pname.to_lowercase().contains(&pat_lower)
-            }
-            None => true,
+        .filter_map(|(name, entry)| {
+            let rank = match pattern {
+                Some(pat) => pat.rank(name, entry)?,
+                None => MatchRank::Exact,
+            };
+            Some((rank, name.as_str(), entry))
         })
         .collect();
+    // BTreeMap iteration is already sorted by name; keep it within a rank.
+    ranked.sort_by_key(|(rank, _, _)| *rank);
+    ranked
+        .into_iter()
+        .map(|(_, name, entry)| (name, entry))
+        .collect()
+}
 
-    if matches.is_empty() {
-        if let Some(pat) = pattern {
-            eprintln!("No packages matching '{pat}'");
-        } else {
-            eprintln!("No packages in cache");
-        }
-        return Ok(());
-    }
+/// Print search results as `name version size [installed]  description`,
+/// cutting descriptions off at the terminal width (`$COLUMNS`, default 80).
+pub fn print_search_results(matches: &[(&str, &PackageEntry)]) {
+    let width = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse::<usize>().ok())
+        .unwrap_or(80);
 
     println!("{} packages available:", matches.len());
     println!();
-    for (name, entry) in &matches {
+    for (name, entry) in matches {
         let size_str = match entry.file_size {
             Some(s) => format_size(s),
             None => "?".to_string(),
         };
-        let installed = Path::new(&entry.store_path).exists();
+        let installed = store_root::real_path(&entry.store_path).exists();
         let status = if installed { " [installed]" } else { "" };
-        println!(
+        let line = format!(
             "  {:<16} {:<12} {:>8}{}",
             name, entry.version, size_str, status
         );
+        match entry.description.as_deref() {
+            Some(desc) if !desc.is_empty() => {
+                let room = width.saturating_sub(line.chars().count() + 2);
+                println!("{line}  {}", truncate(desc, room));
+            }
+            _ => println!("{line}"),
+        }
     }
     println!();
+}
 
-    Ok(())
+/// First line of `s`, cut to at most `max` characters (ending in `…` if cut).
+fn truncate(s: &str, max: usize) -> String {
+    let line = s.lines().next().unwrap_or("");
+    if max == 0 {
+        return String::new();
+    }
+    if line.chars().count() <= max {
+        return line.to_string();
+    }
+    let mut out: String = line.chars().take(max.saturating_sub(1)).collect();
+    out.push('…');
+    out
+}
+
+/// Render the runtime dependency tree of `root`, like `cargo tree`.
+///
+/// `references` returns a path's references (from its narinfo), or `None`
+/// if they can't be looked up; such paths are marked `(unresolved)`.
+/// A path already shown elsewhere is marked `(*)` and not expanded again,
+/// and a reference back to an ancestor is marked `(cycle)`. Self-references
+/// are left out.
+pub fn render_dependency_tree(
+    root: &str,
+    mut references: impl FnMut(&str) -> Option<Vec<String>>,
+) -> String {
+    fn walk(
+        path: &str,
+        prefix: &str,
+        ancestors: &mut Vec<String>,
+        seen: &mut BTreeSet<String>,
+        references: &mut dyn FnMut(&str) -> Option<Vec<String>>,
+        out: &mut String,
+    ) {
+        let Some(mut refs) = references(path) else {
+            return;
+        };
+        refs.retain(|r| r != path);
+        refs.sort();
+        ancestors.push(path.to_string());
+        for (i, child) in refs.iter().enumerate() {
+            let last = i + 1 == refs.len();
+            let branch = if last { "└── " } else { "├── " };
+            let label = tree_label(child);
+            if ancestors.contains(child) {
+                out.push_str(&format!("{prefix}{branch}{label} (cycle)\n"));
+            } else if !seen.insert(child.clone()) {
+                out.push_str(&format!("{prefix}{branch}{label} (*)\n"));
+            } else if references(child).is_none() {
+                out.push_str(&format!("{prefix}{branch}{label} (unresolved)\n"));
+            } else {
+                out.push_str(&format!("{prefix}{branch}{label}\n"));
+                let indent = if last { "    " } else { "│   " };
+                walk(child, &format!("{prefix}{indent}"), ancestors, seen, references, out);
+            }
+        }
+        ancestors.pop();
+    }
+
+    let mut out = format!("{}\n", tree_label(root));
+    if references(root).is_none() {
+        out.truncate(out.len() - 1);
+        out.push_str(" (unresolved)\n");
+        return out;
+    }
+    let mut seen = BTreeSet::from([root.to_string()]);
+    walk(root, "", &mut Vec::new(), &mut seen, &mut references, &mut out);
+    out
+}
+
+/// A store path's name (`ripgrep-14.1.0`), or the path itself if it isn't one.
+fn tree_label(path: &str) -> String {
+    StorePath::<String>::from_absolute_path(path.as_bytes())
+        .map(|sp| sp.name().to_string())
+        .unwrap_or_else(|_| path.to_string())
 }
 
 /// Fetch a store path from a local binary cache.
@@ -107,9 +297,10 @@ pub fn fetch_local(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let sp = StorePath::<String>::from_absolute_path(store_path.as_bytes())?;
     let dest = sp.to_absolute_path();
+    let target = store_root::real_path(&dest);
 
     // Already present?
-    if Path::new(&dest).exists() {
+    if target.exists() {
         eprintln!("already exists: {dest}");
         return Ok(());
     }
@@ -133,35 +324,19 @@ pub fn fetch_local(
         .map_err(|e| format!("NAR file not found: {}: {e}", nar_path.display()))?;
     let reader = BufReader::new(file);
 
-    // Decompress (all decompressors produce Send types)
-    let decompressed: Box<dyn Read + Send> = match narinfo.compression {
-        None | Some("none") => Box::new(reader),
-        Some("zstd") | Some("zst") => Box::new(
-            ruzstd::decoding::StreamingDecoder::new(reader)
-                .map_err(|e| format!("zstd decompression error: {e}"))?,
-        ),
-        Some("xz") => {
-            let mut input = BufReader::new(reader);
-            let mut output = Vec::new();
-            lzma_rs::xz_decompress(&mut input, &mut output)
-                .map_err(|e| format!("xz decompression error: {e}"))?;
-            Box::new(io::Cursor::new(output))
-        }
-        Some("bzip2") | Some("bz2") => Box::new(bzip2_rs::DecoderReader::new(reader)),
-        Some(other) => return Err(format!("unsupported compression: {other}").into()),
-    };
+    let decompressed = nar::decompress_nar(reader, Compression::parse(narinfo.compression)?)?;
 
     // Hash while extracting
     let mut hashing = HashingReader::new(decompressed);
     let mut buf_reader = BufReader::new(&mut hashing);
 
-    eprintln!("extracting to {dest}...");
-    let manifest = nar::extract_with_manifest(&mut buf_reader, &dest)?;
+    eprintln!("extracting to {}...", target.display());
+    let manifest = nar::extract_with_manifest(&mut buf_reader, &target.to_string_lossy())?;
 
     // Verify NAR hash
     let actual_hash = hashing.finalize();
     if actual_hash != narinfo.nar_hash {
-        let _ = std::fs::remove_dir_all(&dest);
+        let _ = std::fs::remove_dir_all(&target);
         return Err(format!(
             "NAR hash mismatch!\n  expected: {}\n  got:      {}",
             data_encoding::HEXLOWER.encode(&narinfo.nar_hash),
@@ -172,18 +347,7 @@ pub fn fetch_local(
 
     // Register in PathInfo database
     let db = PathInfoDb::open()?;
-    let nar_hash_hex = data_encoding::HEXLOWER.encode(&narinfo.nar_hash);
-    let references: Vec<String> = narinfo
-        .references
-        .iter()
-        .map(|r| r.to_absolute_path())
-        .collect();
-    let signatures: Vec<String> = narinfo.signatures.iter().map(|s| s.to_string()).collect();
-
-    store::register_path_with_files(
-        &db, &dest, &nar_hash_hex, narinfo.nar_size,
-        references, signatures, manifest,
-    )?;
+    store::register_narinfo(&db, &dest, &narinfo, manifest)?;
 
     eprintln!("✓ verified and installed: {dest}");
     Ok(())
@@ -263,6 +427,78 @@ mod tests {
         assert_eq!(rg.file_size, Some(2000000));
     }
 
+    fn search_index() -> PackageIndex {
+        let entry = |pname: &str, description: Option<&str>| PackageEntry {
+            store_path: format!("/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-{pname}-1.0"),
+            pname: pname.to_string(),
+            version: "1.0".to_string(),
+            nar_hash: None,
+            nar_size: None,
+            file_size: None,
+            description: description.map(str::to_string),
+        };
+        let packages = [
+            entry("grep", Some("GNU grep")),
+            entry("ripgrep", Some("Recursively search directories")),
+            entry("grep-utils", None),
+            entry("fd", Some("Simple alternative to find, like grep for names")),
+            entry("bat", Some("A cat clone with wings")),
+        ];
+        PackageIndex {
+            version: 1,
+            packages: packages
+                .into_iter()
+                .map(|e| (e.pname.clone(), e))
+                .collect(),
+        }
+    }
+
+    fn names(matches: &[(&str, &PackageEntry)]) -> Vec<String> {
+        matches.iter().map(|(n, _)| n.to_string()).collect()
+    }
+
+    #[test]
+    fn search_ranks_exact_prefix_substring_description() {
+        let index = search_index();
+        let pattern = SearchPattern::parse(Some("GREP"), false).unwrap();
+
+        let matches = rank_packages(&index, pattern.as_ref());
+        assert_eq!(names(&matches), ["grep", "grep-utils", "ripgrep", "fd"]);
+    }
+
+    #[test]
+    fn search_regex_matches_description() {
+        let index = search_index();
+        let pattern = SearchPattern::parse(Some("^a cat\\b"), true).unwrap();
+
+        let matches = rank_packages(&index, pattern.as_ref());
+        assert_eq!(names(&matches), ["bat"]);
+    }
+
+    #[test]
+    fn search_invalid_regex_errors() {
+        let err = SearchPattern::parse(Some("grep("), true).unwrap_err();
+        assert!(err.to_string().contains("invalid search regex 'grep('"), "{err}");
+    }
+
+    #[test]
+    fn search_empty_pattern_lists_everything() {
+        let index = search_index();
+        let pattern = SearchPattern::parse(Some(""), true).unwrap();
+        assert!(pattern.is_none());
+
+        let matches = rank_packages(&index, pattern.as_ref());
+        assert_eq!(names(&matches), ["bat", "fd", "grep", "grep-utils", "ripgrep"]);
+    }
+
+    #[test]
+    fn truncate_descriptions() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("a much longer description", 10), "a much lo…");
+        assert_eq!(truncate("first line\nsecond", 80), "first line");
+        assert_eq!(truncate("anything", 0), "");
+    }
+
     #[test]
     fn format_sizes() {
         assert_eq!(format_size(0), "0 B");
@@ -292,4 +528,41 @@ mod tests {
         assert_eq!(pkg.nar_size, None);
         assert_eq!(pkg.file_size, None);
     }
+
+    const P_APP: &str = "/nix/store/1b9jydsiygi6jhlz2dxbrxi6b4m1rn4r-app-1.0";
+    const P_LIBFOO: &str = "/nix/store/2c8kzfrjzhi7jkmz3fxcsyj7c5n2sp5s-libfoo-2.1";
+    const P_LIBC: &str = "/nix/store/3d7lxgskakh8klnz4gydrzk8d6p3rq6r-libc-0.2";
+    const P_LOST: &str = "/nix/store/4f6mybrlblj9lmpz5hzfs0l9f7q4sp7s-lost-1.0";
+
+    #[test]
+    fn dependency_tree_two_levels() {
+        let refs = BTreeMap::from([
+            (P_APP, vec![P_APP, P_LIBFOO, P_LIBC, P_LOST]),
+            (P_LIBFOO, vec![P_LIBC]),
+            (P_LIBC, vec![]),
+        ]);
+        let tree = render_dependency_tree(P_APP, |path| {
+            refs.get(path)
+                .map(|r| r.iter().map(|s| s.to_string()).collect())
+        });
+
+        assert_eq!(
+            tree,
+            "app-1.0\n\
+             ├── libfoo-2.1\n\
+             │   └── libc-0.2\n\
+             ├── libc-0.2 (*)\n\
+             └── lost-1.0 (unresolved)\n"
+        );
+    }
+
+    #[test]
+    fn dependency_tree_marks_cycles() {
+        let refs = BTreeMap::from([(P_APP, vec![P_LIBFOO]), (P_LIBFOO, vec![P_APP])]);
+        let tree = render_dependency_tree(P_APP, |path| {
+            refs.get(path)
+                .map(|r| r.iter().map(|s| s.to_string()).collect())
+        });
+        assert_eq!(tree, "app-1.0\n└── libfoo-2.1\n    └── app-1.0 (cycle)\n");
+    }
 }
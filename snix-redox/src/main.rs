@@ -7,6 +7,8 @@
 //!   /nix/store/              — store paths (the data)
 //!   /nix/var/snix/pathinfo/  — per-path metadata (JSON)
 //!   /nix/var/snix/gcroots/   — GC root symlinks
+//!
+//! `--store-root DIR` moves all of these under DIR (see `store_root`).
 
 mod activate;
 mod bridge;
@@ -15,6 +17,7 @@ mod build_proxy;
 mod cache;
 mod cache_source;
 mod channel;
+mod completions;
 mod derivation_builtins;
 mod eval;
 mod fetchers;
@@ -30,16 +33,25 @@ mod install;
 mod known_paths;
 mod local_cache;
 mod nar;
+mod nix_daemon;
+mod nix_http;
 mod pathinfo;
 mod rebuild;
 mod store;
+mod store_root;
 mod system;
+#[cfg(test)]
+mod test_http;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "snix", version, about = "Nix for Redox OS")]
 struct Cli {
+    /// Keep the store and snix's metadata under DIR/nix instead of /nix
+    #[arg(long, global = true, value_name = "DIR", env = store_root::STORE_ROOT_ENV)]
+    store_root: Option<std::path::PathBuf>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -52,13 +64,25 @@ enum Command {
         #[arg(short, long)]
         expr: Option<String>,
 
-        /// File to evaluate
+        /// File to evaluate (called with --arg/--argstr if it is a `{ ... }:` function)
         #[arg(short, long)]
         file: Option<String>,
 
         /// Print raw string value (strip quotes, no escaping)
         #[arg(long)]
         raw: bool,
+
+        /// Pass a Nix expression as argument NAME if the result is a `{ ... }:` function
+        #[arg(long, num_args = 2, value_names = ["NAME", "EXPR"])]
+        arg: Vec<String>,
+
+        /// Pass a string as argument NAME if the result is a `{ ... }:` function
+        #[arg(long, num_args = 2, value_names = ["NAME", "VALUE"])]
+        argstr: Vec<String>,
+
+        /// Abort evaluation after SECS seconds
+        #[arg(long, value_name = "SECS")]
+        eval_timeout: Option<u64>,
     },
 
     /// Build a derivation (evaluate + execute builder)
@@ -101,10 +125,14 @@ enum Command {
         no_sandbox: bool,
     },
 
-    /// Show a derivation in human-readable form
+    /// Show a derivation as `nix show-derivation` JSON
     ShowDerivation {
         /// Path to .drv file
         path: String,
+
+        /// Print an indented human-readable summary instead
+        #[arg(long)]
+        summary: bool,
     },
 
     /// Fetch a store path from a binary cache
@@ -112,13 +140,22 @@ enum Command {
         /// Store path to fetch (e.g. /nix/store/abc...-hello-2.12.1)
         store_path: String,
 
-        /// Binary cache URL
-        #[arg(short, long, default_value = "https://cache.nixos.org")]
-        cache_url: String,
+        /// Binary cache URL (repeatable or comma-separated; tried in
+        /// nix-cache-info priority order)
+        #[arg(short, long, default_value = "https://cache.nixos.org", value_delimiter = ',')]
+        cache_url: Vec<String>,
 
         /// Recursively fetch all dependencies (full closure)
         #[arg(short, long)]
         recursive: bool,
+
+        /// With --recursive: only report how much would be downloaded
+        #[arg(long, requires = "recursive")]
+        dry_run: bool,
+
+        /// Use caches whose nix-cache-info names another StoreDir
+        #[arg(long)]
+        no_check_store_dir: bool,
     },
 
     /// Show info about a store path from a binary cache
@@ -129,6 +166,29 @@ enum Command {
         /// Binary cache URL
         #[arg(short, long, default_value = "https://cache.nixos.org")]
         cache_url: String,
+
+        /// Query a cache whose nix-cache-info names another StoreDir
+        #[arg(long)]
+        no_check_store_dir: bool,
+    },
+
+    /// Show why a store path depends on another (shortest reference chain)
+    WhyDepends {
+        /// Root store path
+        path: String,
+
+        /// Dependency to explain
+        dependency: String,
+    },
+
+    /// Check that every path in a closure is signed by a trusted key
+    VerifyClosure {
+        /// Root store path
+        path: String,
+
+        /// Trusted public key (`name:base64`), may be repeated
+        #[arg(long = "trusted-key", value_name = "KEY", required = true)]
+        trusted_keys: Vec<String>,
     },
 
     /// Local store operations
@@ -139,7 +199,7 @@ enum Command {
 
     /// Install a package from a binary cache (local or remote)
     Install {
-        /// Package name (as listed in `snix search`)
+        /// Package name (as listed in `snix search`), store path, or narinfo URL
         name: String,
 
         /// Remote binary cache URL (e.g., http://10.0.2.2:8080)
@@ -167,9 +227,13 @@ enum Command {
 
     /// Search available packages in a binary cache (local or remote)
     Search {
-        /// Optional search pattern (substring match)
+        /// Optional search pattern (case-insensitive substring match)
         pattern: Option<String>,
 
+        /// Treat the pattern as a regular expression over name and description
+        #[arg(long)]
+        regex: bool,
+
         /// Remote binary cache URL (e.g., http://10.0.2.2:8080)
         #[arg(long)]
         cache_url: Option<String>,
@@ -184,6 +248,10 @@ enum Command {
         /// Package name
         name: String,
 
+        /// Show the runtime dependency tree instead
+        #[arg(long)]
+        tree: bool,
+
         /// Remote binary cache URL (e.g., http://10.0.2.2:8080)
         #[arg(long)]
         cache_url: Option<String>,
@@ -247,6 +315,23 @@ enum Command {
         #[arg(long, default_value = "/nix/store")]
         store_dir: String,
     },
+
+    /// Run a read-only Nix daemon on a Unix socket
+    ///
+    /// Answers IsValidPath, QueryPathInfo and QueryValidPaths from the
+    /// local PathInfoDb so other programs can query the store over IPC.
+    Daemon {
+        /// Socket path to listen on
+        #[arg(long, default_value = nix_daemon::DEFAULT_SOCKET)]
+        socket: String,
+    },
+
+    /// Print a shell completion script
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate the script for
+        shell: completions::Shell,
+    },
 }
 
 #[derive(Subcommand)]
@@ -257,16 +342,40 @@ enum StoreCommand {
     /// List all registered store paths with sizes
     List,
 
+    /// Show how much space each GC root keeps alive (shared paths split between roots)
+    Du,
+
     /// Show metadata for a registered store path
     Info {
         /// Store path to look up
         path: String,
     },
 
+    /// List the files of a registered store path (like `nix store ls`)
+    Ls {
+        /// Store path to list
+        path: String,
+
+        /// File or directory inside the store path (e.g. /bin)
+        subpath: Option<String>,
+
+        /// List everything below a directory, not just its entries
+        #[arg(short = 'R', long)]
+        recursive: bool,
+
+        /// Show file type, permissions and size
+        #[arg(short, long)]
+        long: bool,
+    },
+
     /// Show the transitive closure (all dependencies) of a store path
     Closure {
         /// Root store path
         path: String,
+
+        /// Print the reference graph as Graphviz DOT
+        #[arg(long)]
+        dot: bool,
     },
 
     /// Run garbage collection (delete unreferenced paths)
@@ -274,6 +383,79 @@ enum StoreCommand {
         /// Show what would be deleted without actually deleting
         #[arg(long)]
         dry_run: bool,
+
+        /// Delete even if live paths still reference dead ones
+        #[arg(long)]
+        force: bool,
+
+        /// Stop once this many bytes have been freed
+        #[arg(long, value_name = "BYTES")]
+        max_freed: Option<u64>,
+
+        /// Only collect paths registered more than this many days ago
+        #[arg(long, value_name = "DAYS")]
+        max_age: Option<u64>,
+
+        /// Print the paths that would be collected, one per line, and exit
+        #[arg(long, conflicts_with_all = ["dry_run", "force", "max_freed", "max_age"])]
+        print_dead: bool,
+
+        /// Print the paths reachable from GC roots, one per line, and exit
+        #[arg(
+            long,
+            conflicts_with_all = ["dry_run", "force", "max_freed", "max_age", "print_dead"]
+        )]
+        print_live: bool,
+    },
+
+    /// Check that a binary cache answers and show its nix-cache-info (like `nix store ping`)
+    PingCache {
+        /// Binary cache URL
+        url: String,
+
+        /// Also check whether the cache has this store path
+        #[arg(long)]
+        path: Option<String>,
+    },
+
+    /// Deduplicate identical files across store paths with hardlinks
+    Optimise,
+
+    /// Rebuild the path info database by rehashing and rescanning /nix/store
+    RepairDb,
+
+    /// Fold the path info database's per-path files into its index
+    Compact,
+
+    /// Sign store paths with a binary cache secret key (like `nix store sign`)
+    Sign {
+        /// Store paths to sign
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// File holding the secret key (`name:base64`)
+        #[arg(long, value_name = "KEYFILE")]
+        key: String,
+
+        /// Sign the closures of the paths as well
+        #[arg(short, long)]
+        recursive: bool,
+    },
+
+    /// Write store paths and their closures to stdout (nix-store --export format)
+    Export {
+        /// Store paths to export
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Import paths from a nix-store --export stream on stdin
+    Import,
+
+    /// Add a local file or directory to the store (like nix-store --add)
+    Add {
+        /// File or directory to add; its name becomes the store path's name
+        path: String,
     },
 
     /// Add a GC root (protect a path from garbage collection)
@@ -281,8 +463,12 @@ enum StoreCommand {
         /// Symbolic name for the root (e.g. "my-app", "system")
         name: String,
 
-        /// Store path to protect
+        /// Store path to protect, or with --indirect a symlink to one
         path: String,
+
+        /// Protect whatever the symlink PATH points to at GC time (e.g. ./result)
+        #[arg(long)]
+        indirect: bool,
     },
 
     /// Remove a GC root
@@ -302,7 +488,7 @@ enum ProfileCommand {
 
     /// Install a package into the user profile
     Install {
-        /// Package name (as listed in `snix search`)
+        /// Package name (as listed in `snix search`), store path, or narinfo URL
         name: String,
 
         /// Remote binary cache URL (e.g., http://10.0.2.2:8080)
@@ -324,11 +510,21 @@ enum ProfileCommand {
         name: String,
     },
 
+    /// List profile generations and the packages each one changed
+    History,
+
+    /// Switch the profile back to the previous generation
+    Rollback,
+
     /// Show detailed info about a package
     Show {
         /// Package name
         name: String,
 
+        /// Show the runtime dependency tree instead
+        #[arg(long)]
+        tree: bool,
+
         /// Remote binary cache URL (e.g., http://10.0.2.2:8080)
         #[arg(long)]
         cache_url: Option<String>,
@@ -357,11 +553,34 @@ enum SystemCommand {
         /// Path to manifest file (default: /etc/redox-system/manifest.json)
         #[arg(short, long)]
         manifest: Option<String>,
+
+        /// Number of files to hash in parallel (default: one per CPU)
+        #[arg(short, long)]
+        jobs: Option<usize>,
     },
 
-    /// Compare current system manifest with another
+    /// Compare current system manifest with another, or two generations
     Diff {
         /// Path to the other manifest.json to compare against
+        #[arg(required_unless_present = "from", conflicts_with = "from")]
+        path: Option<String>,
+
+        /// Generation to compare from (a number, or "current")
+        #[arg(long)]
+        from: Option<String>,
+
+        /// Generation to compare to (a number, or "current")
+        #[arg(long, conflicts_with = "path", default_value = "current")]
+        to: String,
+
+        /// Path to generations directory (with --from)
+        #[arg(short, long, conflicts_with = "path")]
+        gen_dir: Option<String>,
+    },
+
+    /// Check a manifest's cross-field invariants before building an image
+    Validate {
+        /// Path to the manifest.json to validate
         path: String,
     },
 
@@ -372,6 +591,13 @@ enum SystemCommand {
         dir: Option<String>,
     },
 
+    /// Show a timeline of generation switches, newest first
+    History {
+        /// Path to generations directory (default: /etc/redox-system/generations)
+        #[arg(short, long)]
+        dir: Option<String>,
+    },
+
     /// Switch to a new system manifest, saving current as a generation
     Switch {
         /// Path to the new manifest.json to activate (or omit if using --channel)
@@ -389,6 +615,26 @@ enum SystemCommand {
         #[arg(long)]
         dry_run: bool,
 
+        /// Overwrite managed config files even if they were edited by hand
+        #[arg(long)]
+        force: bool,
+
+        /// Save the new generation but only activate it on the next boot
+        #[arg(long)]
+        boot: bool,
+
+        /// Path to generations directory
+        #[arg(short, long)]
+        gen_dir: Option<String>,
+
+        /// Path to current manifest file
+        #[arg(short, long)]
+        manifest: Option<String>,
+    },
+
+    /// Activate the generation staged with `switch --boot` (run by init)
+    #[command(hide = true)]
+    ActivateBoot {
         /// Path to generations directory
         #[arg(short, long)]
         gen_dir: Option<String>,
@@ -407,6 +653,10 @@ enum SystemCommand {
         #[arg(long)]
         dry_run: bool,
 
+        /// Overwrite managed config files even if they were edited by hand
+        #[arg(long)]
+        force: bool,
+
         /// Path to current manifest file
         #[arg(short, long)]
         manifest: Option<String>,
@@ -425,6 +675,14 @@ enum SystemCommand {
         #[arg(short = 'y', long)]
         yes: bool,
 
+        /// Continue even if the manifest signature does not verify
+        #[arg(long)]
+        insecure: bool,
+
+        /// Fetch from a channel cache whose nix-cache-info names another StoreDir
+        #[arg(long)]
+        no_check_store_dir: bool,
+
         /// Path to current manifest file
         #[arg(short, long)]
         manifest: Option<String>,
@@ -440,6 +698,10 @@ enum SystemCommand {
         #[arg(short, long)]
         generation: Option<u32>,
 
+        /// Overwrite managed config files even if they were edited by hand
+        #[arg(long)]
+        force: bool,
+
         /// Path to generations directory
         #[arg(short, long)]
         dir: Option<String>,
@@ -459,6 +721,18 @@ enum SystemCommand {
         #[arg(long)]
         dry_run: bool,
 
+        /// Save the new generation but only activate it on the next boot
+        #[arg(long, conflicts_with = "bridge")]
+        boot: bool,
+
+        /// Evaluate configuration.nix even if a cached result is up to date
+        #[arg(long)]
+        no_eval_cache: bool,
+
+        /// Abort evaluating configuration.nix after SECS seconds
+        #[arg(long, value_name = "SECS", conflicts_with = "bridge")]
+        eval_timeout: Option<u64>,
+
         /// Initialize a default configuration.nix
         #[arg(long)]
         init: bool,
@@ -494,6 +768,32 @@ enum SystemCommand {
         #[arg(short, long)]
         config: Option<String>,
     },
+
+    /// List configurable options and their current values (like nixos-option)
+    Options {
+        /// Only show options whose name starts with this prefix
+        #[arg(long, value_name = "PREFIX")]
+        filter: Option<String>,
+
+        /// Path to current manifest file
+        #[arg(short, long)]
+        manifest: Option<String>,
+    },
+
+    /// Write a configuration.nix reproducing the current system
+    GenerateConfig {
+        /// Where to write it (default: print to stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Replace an existing output file
+        #[arg(long)]
+        force: bool,
+
+        /// Path to current manifest file
+        #[arg(short, long)]
+        manifest: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -505,6 +805,10 @@ enum ChannelCommand {
 
         /// Channel URL (points to a directory with manifest.json)
         url: String,
+
+        /// Trusted public key (name:base64) that signs the channel's manifests
+        #[arg(long)]
+        key: Option<String>,
     },
 
     /// Remove a channel
@@ -520,14 +824,29 @@ enum ChannelCommand {
     Update {
         /// Channel name (or omit to update all)
         name: Option<String>,
+
+        /// Keep a manifest even if its signature does not verify
+        #[arg(long)]
+        insecure: bool,
     },
 }
 
 fn main() {
     let cli = Cli::parse();
+    if let Some(root) = &cli.store_root {
+        // Child processes pick the root up through the --store-root env fallback.
+        std::env::set_var(store_root::STORE_ROOT_ENV, root);
+    }
+    store_root::init(cli.store_root.clone());
 
     let result = match cli.command {
-        Command::Eval { expr, file, raw } => eval::run(expr, file, raw),
+        Command::Eval { expr, file, raw, arg, argstr, eval_timeout } => eval::run(
+            expr,
+            file,
+            &eval::AutoArgs::from_cli(&arg, &argstr),
+            raw,
+            &eval::EvalBudget::from_secs(eval_timeout),
+        ),
         Command::Build {
             installable,
             expr,
@@ -560,29 +879,69 @@ fn main() {
                 local_build::run_with_options(expr, file, no_sandbox)
             }
         }
-        Command::ShowDerivation { path } => eval::show_derivation(&path),
+        Command::ShowDerivation { path, summary } => eval::show_derivation(&path, summary),
         Command::Fetch {
             store_path,
             cache_url,
             recursive,
+            dry_run,
+            no_check_store_dir,
         } => {
-            if recursive {
-                cache::fetch_recursive(&store_path, &cache_url)
-            } else {
-                cache::fetch(&store_path, &cache_url)
+            // Only draw progress on a terminal; logs get the plain messages.
+            let mut draw = cache::stderr_progress();
+            let progress: Option<&mut cache::ProgressFn> =
+                if std::io::IsTerminal::is_terminal(&std::io::stderr()) {
+                    Some(&mut draw)
+                } else {
+                    None
+                };
+            match cache::Substituters::probe(&cache_url, !no_check_store_dir) {
+                Err(e) => Err(e),
+                Ok(subs) if dry_run => cache::fetch_recursive_dry_run(&store_path, &subs),
+                Ok(subs) if recursive => cache::fetch_recursive(&store_path, &subs, progress),
+                Ok(subs) => subs.fetch_from_any(&store_path, None, progress),
             }
         }
         Command::PathInfo {
             store_path,
             cache_url,
-        } => cache::path_info(&store_path, &cache_url),
+            no_check_store_dir,
+        } => cache::path_info(&store_path, &cache_url, !no_check_store_dir),
+        Command::WhyDepends { path, dependency } => store::show_why_depends(&path, &dependency),
+        Command::VerifyClosure { path, trusted_keys } => {
+            store::run_verify_closure(&path, &trusted_keys)
+        }
         Command::Store { command } => match command {
             StoreCommand::Verify => store::verify(),
             StoreCommand::List => store::list_registered(),
+            StoreCommand::Du => store::run_du(),
             StoreCommand::Info { path } => store::show_info(&path),
-            StoreCommand::Closure { path } => store::show_closure(&path),
-            StoreCommand::Gc { dry_run } => store::run_gc(dry_run),
-            StoreCommand::AddRoot { name, path } => store::add_root(&name, &path),
+            StoreCommand::Ls { path, subpath, recursive, long } => {
+                store::run_ls(&path, subpath.as_deref(), recursive, long)
+            }
+            StoreCommand::Closure { path, dot } => store::show_closure(&path, dot),
+            StoreCommand::Gc { print_dead: true, .. } => store::run_gc_print(false),
+            StoreCommand::Gc { print_live: true, .. } => store::run_gc_print(true),
+            StoreCommand::Gc { dry_run, force, max_freed, max_age, .. } => {
+                store::run_gc(dry_run, force, max_freed, max_age)
+            }
+            StoreCommand::PingCache { url, path } => cache::run_ping_cache(&url, path.as_deref()),
+            StoreCommand::Optimise => store::run_optimise(),
+            StoreCommand::RepairDb => store::run_repair_db(),
+            StoreCommand::Compact => store::run_compact(),
+            StoreCommand::Sign { paths, key, recursive } => {
+                store::run_sign(&paths, &key, recursive)
+            }
+            StoreCommand::Export { paths } => store::run_export(&paths),
+            StoreCommand::Import => store::run_import(),
+            StoreCommand::Add { path } => store::run_add(&path),
+            StoreCommand::AddRoot { name, path, indirect } => {
+                if indirect {
+                    store::add_indirect_root(&name, &path)
+                } else {
+                    store::add_root(&name, &path)
+                }
+            }
             StoreCommand::RemoveRoot { name } => store::remove_root(&name),
             StoreCommand::Roots => store::list_roots(),
         },
@@ -606,6 +965,7 @@ fn main() {
         Command::Remove { name } => install::remove(&name),
         Command::Search {
             pattern,
+            regex,
             cache_url,
             cache_path,
         } => {
@@ -613,10 +973,11 @@ fn main() {
                 cache_url.as_deref(),
                 Some(&cache_path),
             );
-            source.search(pattern.as_deref())
+            source.search(pattern.as_deref(), regex)
         }
         Command::Show {
             name,
+            tree,
             cache_url,
             cache_path,
         } => {
@@ -624,7 +985,7 @@ fn main() {
                 cache_url.as_deref(),
                 Some(&cache_path),
             );
-            install::show(&name, &source)
+            install::show(&name, &source, tree)
         }
         Command::Profile { command } => match command {
             ProfileCommand::List => install::list_profile(),
@@ -645,8 +1006,11 @@ fn main() {
                 }
             }
             ProfileCommand::Remove { name } => install::remove(&name),
+            ProfileCommand::History => install::history(),
+            ProfileCommand::Rollback => install::rollback(),
             ProfileCommand::Show {
                 name,
+                tree,
                 cache_url,
                 cache_path,
             } => {
@@ -654,37 +1018,48 @@ fn main() {
                     cache_url.as_deref(),
                     Some(&cache_path),
                 );
-                install::show(&name, &source)
+                install::show(&name, &source, tree)
             }
         },
         Command::Repl => eval::repl(),
         Command::Vendor { command } => vendor::run(&command),
         Command::Channel { command } => match command {
-            ChannelCommand::Add { name, url } => channel::add(&name, &url),
+            ChannelCommand::Add { name, url, key } => channel::add(&name, &url, key.as_deref()),
             ChannelCommand::Remove { name } => channel::remove(&name),
             ChannelCommand::List => channel::list(),
-            ChannelCommand::Update { name } => match name {
-                Some(n) => channel::update(&n),
-                None => channel::update_all(),
+            ChannelCommand::Update { name, insecure } => match name {
+                Some(n) => channel::update(&n, insecure),
+                None => channel::update_all(insecure),
             },
         },
         Command::System { command } => match command {
             SystemCommand::Info { manifest } => system::info(manifest.as_deref()),
-            SystemCommand::Verify { verbose, manifest } => {
-                system::verify(manifest.as_deref(), verbose)
-            }
-            SystemCommand::Diff { path } => system::diff(&path),
+            SystemCommand::Verify {
+                verbose,
+                manifest,
+                jobs,
+            } => system::verify(manifest.as_deref(), verbose, jobs),
+            SystemCommand::Diff { path, from, to, gen_dir } => match (path, from) {
+                (_, Some(from)) => system::diff_generations(&from, &to, gen_dir.as_deref()),
+                (Some(path), None) => system::diff(&path),
+                (None, None) => unreachable!("clap requires a path or --from"),
+            },
+            SystemCommand::Validate { path } => system::validate(&path),
             SystemCommand::Generations { dir } => system::generations(dir.as_deref()),
+            SystemCommand::History { dir } => system::history(dir.as_deref()),
             SystemCommand::Activate {
                 path,
                 dry_run,
+                force,
                 manifest,
-            } => system::activate_cmd(&path, dry_run, manifest.as_deref()),
+            } => system::activate_cmd(&path, dry_run, force, manifest.as_deref()),
             SystemCommand::Switch {
                 path,
                 channel: channel_name,
                 description,
                 dry_run,
+                force,
+                boot,
                 gen_dir,
                 manifest,
             } => {
@@ -702,33 +1077,46 @@ fn main() {
                         &p,
                         description.as_deref(),
                         dry_run,
+                        force,
+                        boot,
                         gen_dir.as_deref(),
                         manifest.as_deref(),
                     ),
                     Err(e) => Err(e),
                 }
             }
+            SystemCommand::ActivateBoot { gen_dir, manifest } => {
+                system::activate_boot(gen_dir.as_deref(), manifest.as_deref())
+            }
             SystemCommand::Upgrade {
                 channel: channel_name,
                 dry_run,
                 yes,
+                insecure,
+                no_check_store_dir,
                 manifest,
                 gen_dir,
             } => system::upgrade(
                 channel_name.as_deref(),
                 dry_run,
                 yes,
+                insecure,
+                !no_check_store_dir,
                 manifest.as_deref(),
                 gen_dir.as_deref(),
             ),
             SystemCommand::Rollback {
                 generation,
+                force,
                 dir,
                 manifest,
-            } => system::rollback(generation, dir.as_deref(), manifest.as_deref()),
+            } => system::rollback(generation, force, dir.as_deref(), manifest.as_deref()),
             SystemCommand::Rebuild {
                 config,
                 dry_run,
+                boot,
+                no_eval_cache,
+                eval_timeout,
                 init,
                 manifest,
                 gen_dir,
@@ -749,18 +1137,29 @@ fn main() {
                         gen_dir.as_deref(),
                     )
                 } else {
-                    rebuild::rebuild(
-                        config.as_deref(),
+                    rebuild::rebuild(&rebuild::RebuildOptions {
+                        config_path: config.as_deref(),
                         dry_run,
-                        manifest.as_deref(),
-                        gen_dir.as_deref(),
-                        cache_index.as_deref(),
-                    )
+                        boot,
+                        eval_cache: !no_eval_cache,
+                        manifest_path: manifest.as_deref(),
+                        gen_dir: gen_dir.as_deref(),
+                        cache_index_path: cache_index.as_deref(),
+                        eval_budget: eval::EvalBudget::from_secs(eval_timeout),
+                    })
                 }
             }
             SystemCommand::ShowConfig { config } => {
                 rebuild::show_config(config.as_deref())
             }
+            SystemCommand::GenerateConfig {
+                output,
+                force,
+                manifest,
+            } => system::generate_config(output.as_deref(), force, manifest.as_deref()),
+            SystemCommand::Options { filter, manifest } => {
+                system::options(filter.as_deref(), manifest.as_deref())
+            }
         },
         Command::Stored {
             cache_path,
@@ -776,6 +1175,11 @@ fn main() {
             profiles_dir,
             store_dir,
         }),
+        Command::Daemon { socket } => nix_daemon::serve(std::path::Path::new(&socket)),
+        Command::Completions { shell } => {
+            print!("{}", completions::generate(shell, &Cli::command()));
+            Ok(())
+        }
     };
 
     if let Err(e) = result {
@@ -2,13 +2,20 @@
 //!
 //! NAR is a deterministic archive format used by Nix. It represents
 //! a directory tree with files, symlinks, and directories.
+//!
+//! Binary caches serve NARs compressed (`.nar.xz`, `.nar.zst`, ...).
+//! `decompress_nar` undoes that so the reader always sees plain NAR bytes.
+//! `HashingWriter` hashes a NAR on its way to disk, so it needn't be read
+//! back to verify it.
 
 use std::fs;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 use nix_compat::nar::reader;
+use nix_compat::nixhash::NixHash;
+use sha2::{Digest, Sha256};
 
 /// A file tree manifest entry collected during NAR extraction.
 ///
@@ -26,6 +33,166 @@ pub struct ManifestEntry {
     pub executable: bool,
 }
 
+// ===== Compression =====
+
+/// NAR compression, as named by the narinfo `Compression:` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Parse a narinfo `Compression:` value. A missing field means `none`.
+    pub fn parse(field: Option<&str>) -> io::Result<Self> {
+        match field {
+            None | Some("none") => Ok(Self::None),
+            Some("xz") => Ok(Self::Xz),
+            Some("zstd") | Some("zst") => Ok(Self::Zstd),
+            Some("bzip2") | Some("bz2") => Ok(Self::Bzip2),
+            Some(other) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported compression: {other}"),
+            )),
+        }
+    }
+
+    /// Compression implied by a NAR file name (`.nar.zst`, `.nar.xz`, ...).
+    pub fn from_file_name(name: &str) -> Self {
+        match name.rsplit_once('.').map(|(_, ext)| ext) {
+            Some("xz") => Self::Xz,
+            Some("zst") => Self::Zstd,
+            Some("bz2") => Self::Bzip2,
+            _ => Self::None,
+        }
+    }
+}
+
+/// Wrap `reader` so it yields the plain NAR.
+///
+/// zstd and bzip2 stream. lzma-rs has no streaming xz decoder, so xz
+/// input is decompressed into memory up front.
+pub fn decompress_nar<'a, R: Read + Send + 'a>(
+    reader: R,
+    compression: Compression,
+) -> io::Result<Box<dyn Read + Send + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Xz => {
+            let mut output = Vec::new();
+            lzma_rs::xz_decompress(&mut BufReader::new(reader), &mut output)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("xz: {e}")))?;
+            Box::new(io::Cursor::new(output))
+        }
+        Compression::Zstd => Box::new(
+            ruzstd::decoding::StreamingDecoder::new(reader)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("zstd: {e}")))?,
+        ),
+        Compression::Bzip2 => Box::new(bzip2_rs::DecoderReader::new(reader)),
+    })
+}
+
+// ===== Hashing =====
+
+/// Writer that passes bytes through to `inner` while hashing them with
+/// SHA-256 (what narinfo `NarHash` and `FileHash` use).
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    sha256: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            sha256: Sha256::new(),
+        }
+    }
+
+    /// SHA-256 of everything written.
+    pub fn finalize(self) -> NixHash {
+        NixHash::Sha256(self.sha256.finalize().into())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only hash what the inner writer accepted.
+        let n = self.inner.write(buf)?;
+        self.sha256.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// ===== Serialization =====
+
+/// Serialize the filesystem tree at `path` as a NAR into `w`.
+pub fn dump<W: Write>(path: &Path, mut w: W) -> io::Result<()> {
+    let node = nix_compat::nar::writer::open(&mut w)?;
+    dump_node(node, path)
+}
+
+/// Recursively serialize a filesystem path into NAR format.
+fn dump_node<W: Write>(
+    node: nix_compat::nar::writer::Node<'_, W>,
+    path: &Path,
+) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            node.symlink(target.as_os_str().as_bytes())?;
+        }
+        #[cfg(not(unix))]
+        {
+            node.symlink(target.to_string_lossy().as_bytes())?;
+        }
+    } else if meta.is_file() {
+        #[cfg(unix)]
+        let executable = meta.permissions().mode() & 0o111 != 0;
+        #[cfg(not(unix))]
+        let executable = false;
+
+        let size = meta.len();
+        let file = fs::File::open(path)?;
+        let mut reader = BufReader::new(file);
+        node.file(executable, size, &mut reader)?;
+    } else if meta.is_dir() {
+        let mut entries: Vec<fs::DirEntry> = fs::read_dir(path)?
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+        let mut dir = node.directory()?;
+        for entry in &entries {
+            let name = entry.file_name();
+            #[cfg(unix)]
+            let name_bytes = {
+                use std::os::unix::ffi::OsStrExt;
+                name.as_bytes().to_vec()
+            };
+            #[cfg(not(unix))]
+            let name_bytes = name.to_string_lossy().as_bytes().to_vec();
+
+            let child = dir.entry(&name_bytes)?;
+            dump_node(child, &entry.path())?;
+        }
+        dir.close()?;
+    }
+
+    Ok(())
+}
+
+// ===== Extraction =====
+
 /// Extract a NAR from a reader to a destination path.
 /// The reader must implement BufRead + Send (nix-compat requirement).
 pub fn extract(r: &mut (dyn BufRead + Send), dest: &str) -> io::Result<()> {
@@ -156,10 +323,13 @@ fn extract_node(node: reader::Node<'_, '_>, path: &Path) -> io::Result<()> {
     Ok(())
 }
 
-/// List the contents of a NAR without extracting
+/// List the contents of a NAR without extracting.
+///
+/// Nothing is re-serialized, so legacy archives with unsorted directory
+/// entries are listed too; only a repeated entry name is an error.
 #[allow(dead_code)] // Public API not yet wired to CLI
 pub fn list(r: &mut (dyn BufRead + Send)) -> io::Result<()> {
-    let node = reader::open(r)?;
+    let node = reader::open_with_strict(r, false)?;
     list_node(node, "")
 }
 
@@ -200,6 +370,60 @@ mod tests {
     use super::*;
     use std::io::Cursor;
 
+    const HELLOWORLD_NAR: &[u8] = include_bytes!("../testdata/nar/helloworld.nar");
+
+    fn roundtrip(compression: Compression) {
+        let mut compressed = Vec::new();
+        match compression {
+            Compression::Xz => {
+                lzma_rs::xz_compress(&mut BufReader::new(HELLOWORLD_NAR), &mut compressed)
+                    .unwrap()
+            }
+            Compression::Zstd => ruzstd::encoding::compress(
+                HELLOWORLD_NAR,
+                &mut compressed,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            ),
+            other => panic!("no encoder for {other:?}"),
+        }
+        assert_ne!(compressed, HELLOWORLD_NAR);
+
+        let mut decompressed = Vec::new();
+        decompress_nar(&compressed[..], compression)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(Sha256::digest(&decompressed), Sha256::digest(HELLOWORLD_NAR));
+
+        // The decompressed stream is a valid NAR
+        let tempdir = tempfile::tempdir().unwrap();
+        let dest = tempdir.path().join("hello");
+        let mut reader = BufReader::new(decompress_nar(&compressed[..], compression).unwrap());
+        extract(&mut reader, dest.to_str().unwrap()).unwrap();
+        assert!(dest.is_file());
+    }
+
+    #[test]
+    fn zstd_roundtrip() {
+        roundtrip(Compression::Zstd);
+    }
+
+    #[test]
+    fn xz_roundtrip() {
+        roundtrip(Compression::Xz);
+    }
+
+    #[test]
+    fn compression_names() {
+        assert_eq!(Compression::parse(None).unwrap(), Compression::None);
+        assert_eq!(Compression::parse(Some("zst")).unwrap(), Compression::Zstd);
+        assert_eq!(Compression::parse(Some("bzip2")).unwrap(), Compression::Bzip2);
+        assert!(Compression::parse(Some("lz4")).is_err());
+
+        assert_eq!(Compression::from_file_name("abc.nar.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_file_name("abc.nar"), Compression::None);
+    }
+
     /// Test extracting a single file NAR
     #[test]
     fn extract_helloworld() {
@@ -302,6 +526,44 @@ mod tests {
         // the code path exists by checking valid extraction works
     }
 
+    /// A NAR of a directory holding empty files `names`, in the given order.
+    fn unsorted_dir_nar(names: &[&str]) -> Vec<u8> {
+        fn push(nar: &mut Vec<u8>, s: &str) {
+            nar.extend_from_slice(&(s.len() as u64).to_le_bytes());
+            nar.extend_from_slice(s.as_bytes());
+            nar.resize(nar.len().next_multiple_of(8), 0);
+        }
+
+        let mut nar = Vec::new();
+        for token in ["nix-archive-1", "(", "type", "directory"] {
+            push(&mut nar, token);
+        }
+        for name in names {
+            for token in ["entry", "(", "name", name, "node", "("] {
+                push(&mut nar, token);
+            }
+            for token in ["type", "regular", "contents", "", ")", ")"] {
+                push(&mut nar, token);
+            }
+        }
+        push(&mut nar, ")");
+        nar
+    }
+
+    #[test]
+    fn list_accepts_unsorted_entries() {
+        let nar_data = unsorted_dir_nar(&["b", "a"]);
+        list(&mut Cursor::new(&nar_data[..])).unwrap();
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let dest = tempdir.path().join("out");
+        let err = extract(&mut Cursor::new(&nar_data[..]), dest.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let nar_data = unsorted_dir_nar(&["b", "a", "b"]);
+        assert!(list(&mut Cursor::new(&nar_data[..])).is_err());
+    }
+
     /// Test listing a single file NAR and verify its properties
     #[test]
     fn list_helloworld() {
@@ -406,7 +668,6 @@ mod tests {
         assert!(dest_path.join("keep").is_dir());
 
         // NAR is deterministic - same input bytes always produce same output
-        use sha2::{Sha256, Digest};
         let hash = Sha256::digest(nar_data);
         let hash_hex = format!("{:x}", hash);
 
@@ -414,4 +675,25 @@ mod tests {
         assert!(!hash_hex.is_empty());
         assert_eq!(hash_hex.len(), 64); // SHA256 hex is 64 chars
     }
+
+    #[test]
+    fn hashing_writer_matches_independent_hash() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let src = tempdir.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin/hello"), "#!/bin/sh\necho hello\n").unwrap();
+        fs::write(src.join("README"), "hello\n".repeat(10_000)).unwrap();
+
+        let mut writer = HashingWriter::new(Vec::new());
+        dump(&src, &mut writer).unwrap();
+        let nar = writer.inner.clone();
+        let sha256 = writer.finalize();
+
+        assert_eq!(sha256, NixHash::Sha256(Sha256::digest(&nar).into()));
+
+        // Same bytes as dumping straight into a buffer.
+        let mut plain = Vec::new();
+        dump(&src, &mut plain).unwrap();
+        assert_eq!(nar, plain);
+    }
 }
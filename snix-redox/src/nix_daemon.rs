@@ -0,0 +1,578 @@
+//! Minimal read-only Nix daemon, so other programs can query the store over IPC.
+//!
+//! Speaks the worker protocol (the one `nix-daemon` uses) on a Unix socket,
+//! which Redox provides through its `uds_stream:` scheme. nix-compat's
+//! `nix_daemon` module is async (tokio) and stripped from our fork, so this
+//! is a small blocking implementation of the subset snix needs:
+//!
+//!   IsValidPath, QueryPathInfo, QueryValidPaths — answered from PathInfoDb
+//!   SetOptions                                  — accepted and ignored
+//!   everything else                             — rejected, connection closed
+//!
+//! Write ops cannot be skipped without understanding their payload, so a
+//! rejected op always ends the session after the error is sent.
+//!
+//! Usage:
+//!   snix daemon [--socket /nix/var/nix/daemon-socket/socket]
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::Arc;
+
+use nix_compat::nixhash::{HashAlgo, NixHash};
+use nix_compat::store_path::StorePath;
+
+use crate::pathinfo::{self, PathInfo, PathInfoDb};
+
+/// Default socket path, as used by C++ Nix.
+pub const DEFAULT_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+
+const WORKER_MAGIC_1: u64 = 0x6e697863; // "nixc"
+const WORKER_MAGIC_2: u64 = 0x6478696f; // "dxio"
+const STDERR_LAST: u64 = 0x616c7473; // "alts"
+const STDERR_ERROR: u64 = 0x63787470; // "cxtp"
+
+/// Protocol 1.37 (Nix 2.20), encoded as `major << 8 | minor`.
+const PROTOCOL_VERSION: u64 = (1 << 8) | 37;
+
+/// Oldest client protocol we talk to (Nix 1.0).
+const MIN_CLIENT_MINOR: u64 = 10;
+
+/// Upper bound for a single string on the wire (store paths, settings).
+const MAX_STRING_LEN: usize = 64 * 1024;
+
+/// Upper bound for the number of elements in a list on the wire.
+const MAX_LIST_LEN: u64 = 1 << 20;
+
+/// The worker ops the daemon handles, numbered as in nix-compat's
+/// `worker_protocol::Operation`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkerOp {
+    IsValidPath,
+    SetOptions,
+    QueryPathInfo,
+    QueryValidPaths,
+}
+
+impl WorkerOp {
+    /// Every op, in opcode order.
+    pub const ALL: [WorkerOp; 4] = [
+        WorkerOp::IsValidPath,
+        WorkerOp::SetOptions,
+        WorkerOp::QueryPathInfo,
+        WorkerOp::QueryValidPaths,
+    ];
+
+    /// The opcode sent on the wire.
+    pub fn to_u64(self) -> u64 {
+        match self {
+            WorkerOp::IsValidPath => 1,
+            WorkerOp::SetOptions => 19,
+            WorkerOp::QueryPathInfo => 26,
+            WorkerOp::QueryValidPaths => 31,
+        }
+    }
+
+    /// The op for a wire opcode, or an error naming an opcode we don't handle.
+    pub fn from_u64(op: u64) -> Result<Self, UnsupportedOp> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.to_u64() == op)
+            .ok_or(UnsupportedOp(op))
+    }
+}
+
+/// A worker opcode the daemon doesn't handle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnsupportedOp(pub u64);
+
+impl std::fmt::Display for UnsupportedOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "operation {} is not supported by the read-only snix daemon", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedOp {}
+
+/// Store backend for the daemon.
+///
+/// Implemented by `PathInfoDb`; tests substitute an in-memory map.
+pub trait DaemonStore: Send + Sync {
+    /// Look up a registered store path by its absolute path.
+    fn query_path_info(&self, store_path: &str) -> Result<Option<PathInfo>, Box<dyn std::error::Error>>;
+}
+
+impl DaemonStore for PathInfoDb {
+    fn query_path_info(&self, store_path: &str) -> Result<Option<PathInfo>, Box<dyn std::error::Error>> {
+        Ok(self.get(store_path)?)
+    }
+}
+
+/// `snix daemon` — serve the local PathInfoDb on `socket` until killed.
+pub fn serve(socket: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let store: Arc<dyn DaemonStore> = Arc::new(PathInfoDb::open()?);
+
+    if let Some(parent) = socket.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // A socket left behind by a previous daemon would make bind fail
+    if socket.symlink_metadata().is_ok() {
+        std::fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)
+        .map_err(|e| format!("cannot bind {}: {e}", socket.display()))?;
+    eprintln!("snix daemon: listening on {} (read-only)", socket.display());
+
+    serve_listener(listener, store)
+}
+
+/// Accept connections forever, one thread per client.
+pub fn serve_listener(
+    listener: UnixListener,
+    store: Arc<dyn DaemonStore>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("snix daemon: accept failed: {e}");
+                continue;
+            }
+        };
+        let store = Arc::clone(&store);
+        std::thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, store.as_ref()) {
+                eprintln!("snix daemon: connection closed: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Handle one client: handshake, then ops until the client hangs up.
+pub fn serve_connection<S: Read + Write>(mut conn: S, store: &dyn DaemonStore) -> io::Result<()> {
+    let minor = handshake(&mut conn)?;
+
+    loop {
+        let op = match read_u64(&mut conn) {
+            Ok(op) => op,
+            // Hanging up between ops is how clients end a session
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        match WorkerOp::from_u64(op) {
+            Ok(WorkerOp::IsValidPath) => {
+                let path = read_store_path(&mut conn)?;
+                match path.map(|p| store.query_path_info(&p)) {
+                    Ok(Ok(info)) => {
+                        write_u64(&mut conn, STDERR_LAST)?;
+                        write_u64(&mut conn, info.is_some() as u64)?;
+                    }
+                    Ok(Err(e)) => write_error(&mut conn, minor, &e.to_string())?,
+                    Err(msg) => write_error(&mut conn, minor, &msg)?,
+                }
+            }
+            Ok(WorkerOp::SetOptions) => {
+                read_client_settings(&mut conn, minor)?;
+                write_u64(&mut conn, STDERR_LAST)?;
+            }
+            Ok(WorkerOp::QueryPathInfo) => {
+                let path = read_store_path(&mut conn)?;
+                match path.map(|p| store.query_path_info(&p)) {
+                    Ok(Ok(Some(info))) => {
+                        write_u64(&mut conn, STDERR_LAST)?;
+                        if minor >= 17 {
+                            write_u64(&mut conn, 1)?;
+                        }
+                        write_path_info(&mut conn, minor, &info)?;
+                    }
+                    // Clients before 1.17 expect an error for unknown paths
+                    Ok(Ok(None)) if minor < 17 => {
+                        write_error(&mut conn, minor, "path is not valid")?
+                    }
+                    Ok(Ok(None)) => {
+                        write_u64(&mut conn, STDERR_LAST)?;
+                        write_u64(&mut conn, 0)?;
+                    }
+                    Ok(Err(e)) => write_error(&mut conn, minor, &e.to_string())?,
+                    Err(msg) => write_error(&mut conn, minor, &msg)?,
+                }
+            }
+            Ok(WorkerOp::QueryValidPaths) => {
+                let paths = read_strings(&mut conn)?;
+                if minor >= 27 {
+                    // substitute: we never substitute, so this is ignored
+                    read_u64(&mut conn)?;
+                }
+                let mut valid = Vec::new();
+                for path in paths {
+                    if matches!(store.query_path_info(&path), Ok(Some(_))) {
+                        valid.push(path);
+                    }
+                }
+                write_u64(&mut conn, STDERR_LAST)?;
+                write_strings(&mut conn, &valid)?;
+            }
+            Err(unsupported) => {
+                // The request payload is unknown to us, so the stream can't be
+                // resynchronised: report the error and end the session.
+                let msg = unsupported.to_string();
+                write_error(&mut conn, minor, &msg)?;
+                conn.flush()?;
+                return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+            }
+        }
+        conn.flush()?;
+    }
+}
+
+// ─── Handshake ─────────────────────────────────────────────────────────────
+
+/// Server side of the worker handshake. Returns the negotiated minor version.
+fn handshake<S: Read + Write>(conn: &mut S) -> io::Result<u64> {
+    let magic = read_u64(conn)?;
+    if magic != WORKER_MAGIC_1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("incorrect worker magic number: {magic:#x}"),
+        ));
+    }
+    write_u64(conn, WORKER_MAGIC_2)?;
+    write_u64(conn, PROTOCOL_VERSION)?;
+    conn.flush()?;
+
+    let client_version = read_u64(conn)?;
+    let client_major = (client_version >> 8) & 0xff;
+    let client_minor = client_version & 0xff;
+    if client_major != 1 || client_minor < MIN_CLIENT_MINOR {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported client protocol {client_major}.{client_minor}"),
+        ));
+    }
+    let minor = client_minor.min(PROTOCOL_VERSION & 0xff);
+
+    if minor >= 14 {
+        // Obsolete CPU affinity
+        if read_u64(conn)? != 0 {
+            read_u64(conn)?;
+        }
+    }
+    if minor >= 11 {
+        // Obsolete reserveSpace
+        read_u64(conn)?;
+    }
+    if minor >= 33 {
+        write_bytes(conn, concat!("snix ", env!("CARGO_PKG_VERSION")).as_bytes())?;
+    }
+    if minor >= 35 {
+        // Read-only access doesn't need trust: report "not trusted"
+        write_u64(conn, 2)?;
+    }
+
+    // No startup logs; tell the client we're ready for ops
+    write_u64(conn, STDERR_LAST)?;
+    conn.flush()?;
+    Ok(minor)
+}
+
+/// Consume a SetOptions payload. Settings don't affect read-only ops.
+fn read_client_settings<R: Read>(conn: &mut R, minor: u64) -> io::Result<()> {
+    // keepFailed .. useSubstitutes: twelve fixed fields
+    for _ in 0..12 {
+        read_u64(conn)?;
+    }
+    if minor >= 12 {
+        let overrides = read_u64(conn)?;
+        if overrides > MAX_LIST_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many settings overrides"));
+        }
+        for _ in 0..overrides {
+            read_bytes(conn)?;
+            read_bytes(conn)?;
+        }
+    }
+    Ok(())
+}
+
+// ─── Responses ─────────────────────────────────────────────────────────────
+
+/// Serialize the UnkeyedValidPathInfo part of a QueryPathInfo reply.
+fn write_path_info<W: Write>(conn: &mut W, minor: u64, info: &PathInfo) -> io::Result<()> {
+    write_bytes(conn, info.deriver.as_deref().unwrap_or("").as_bytes())?;
+    write_bytes(conn, wire_nar_hash(&info.nar_hash).as_bytes())?;
+    write_strings(conn, &info.references)?;
+    write_u64(conn, pathinfo::parse_timestamp(&info.registration_time).unwrap_or(0))?;
+    write_u64(conn, info.nar_size)?;
+    if minor >= 16 {
+        // ultimate: PathInfoDb doesn't record which paths were built locally
+        write_u64(conn, 0)?;
+        write_strings(conn, &info.signatures)?;
+        // ca: PathInfoDb doesn't record content addresses
+        write_bytes(conn, b"")?;
+    }
+    Ok(())
+}
+
+/// Send an error in the format the negotiated protocol expects.
+fn write_error<W: Write>(conn: &mut W, minor: u64, msg: &str) -> io::Result<()> {
+    write_u64(conn, STDERR_ERROR)?;
+    if minor >= 26 {
+        write_bytes(conn, b"Error")?;
+        write_u64(conn, 0)?; // level: error
+        write_bytes(conn, b"Error")?;
+        write_bytes(conn, msg.as_bytes())?;
+        write_u64(conn, 0)?; // havePos
+        write_u64(conn, 0)?; // traces
+    } else {
+        write_bytes(conn, msg.as_bytes())?;
+        write_u64(conn, 1)?; // exit status
+    }
+    Ok(())
+}
+
+/// The wire carries the NAR hash as bare lowercase hex.
+///
+/// PathInfoDb stores whatever the cache or builder produced
+/// (`sha256:<hex>`, `sha256:<nixbase32>` or SRI), so normalize it.
+fn wire_nar_hash(nar_hash: &str) -> String {
+    match NixHash::from_str(nar_hash, Some(HashAlgo::Sha256)) {
+        Ok(hash) => data_encoding::HEXLOWER.encode(hash.digest_as_bytes()),
+        Err(_) => nar_hash.strip_prefix("sha256:").unwrap_or(nar_hash).to_string(),
+    }
+}
+
+// ─── Wire primitives ───────────────────────────────────────────────────────
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+/// Length-prefixed bytes, zero-padded to a multiple of 8.
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u64(r)?;
+    if len > MAX_STRING_LEN as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("string of {len} bytes exceeds the {MAX_STRING_LEN} byte limit"),
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf)?;
+
+    let mut padding = [0u8; 8];
+    let pad = (8 - len as usize % 8) % 8;
+    r.read_exact(&mut padding[..pad])?;
+    if padding.iter().any(|&b| b != 0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "non-zero string padding"));
+    }
+    Ok(buf)
+}
+
+pub(crate) fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_u64(w, bytes.len() as u64)?;
+    w.write_all(bytes)?;
+    let pad = (8 - bytes.len() % 8) % 8;
+    w.write_all(&[0u8; 8][..pad])
+}
+
+pub(crate) fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    String::from_utf8(read_bytes(r)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) fn read_strings<R: Read>(r: &mut R) -> io::Result<Vec<String>> {
+    let count = read_u64(r)?;
+    if count > MAX_LIST_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("list of {count} elements is too long"),
+        ));
+    }
+    (0..count).map(|_| read_string(r)).collect()
+}
+
+pub(crate) fn write_strings<W: Write>(w: &mut W, strings: &[String]) -> io::Result<()> {
+    write_u64(w, strings.len() as u64)?;
+    for s in strings {
+        write_bytes(w, s.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Read a store path argument.
+///
+/// The outer error is an I/O failure; the inner one is an invalid path,
+/// which is reported to the client without ending the session.
+fn read_store_path<R: Read>(r: &mut R) -> io::Result<Result<String, String>> {
+    let path = read_string(r)?;
+    Ok(match StorePath::<String>::from_absolute_path(path.as_bytes()) {
+        Ok(_) => Ok(path),
+        Err(e) => Err(format!("{path}: invalid store path: {e}")),
+    })
+}
+
+// ─── Tests ─────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::os::unix::net::UnixStream;
+
+    const P_HELLO: &str = "/nix/store/5g5nzcsmcmk0mnqz6i0gr1m0g8r5rq8r-hello-1.0";
+    const P_GLIBC: &str = "/nix/store/6h4pzdrnfnl1npqz7j1hs2n1h9s6rp9s-glibc-2.35";
+    const NAR_HASH_HEX: &str = "03e7f63be30b065d78bcf615f5473545fdb4eb69aa416f43495b4e05cdfb8040";
+
+    struct MemoryStore(BTreeMap<String, PathInfo>);
+
+    impl DaemonStore for MemoryStore {
+        fn query_path_info(&self, store_path: &str) -> Result<Option<PathInfo>, Box<dyn std::error::Error>> {
+            Ok(self.0.get(store_path).cloned())
+        }
+    }
+
+    fn store() -> Arc<dyn DaemonStore> {
+        let info = PathInfo {
+            store_path: P_HELLO.to_string(),
+            nar_hash: format!("sha256:{NAR_HASH_HEX}"),
+            nar_size: 128,
+            references: vec![P_GLIBC.to_string()],
+            deriver: None,
+            output: None,
+            registration_time: "2026-02-20T12:00:00Z".to_string(),
+            signatures: vec!["cache.example.org-1:c2ln".to_string()],
+            files: vec![],
+        };
+        Arc::new(MemoryStore(BTreeMap::from([(P_HELLO.to_string(), info)])))
+    }
+
+    /// Run a server on one end of a socket pair; return the client end.
+    fn connect(store: Arc<dyn DaemonStore>) -> (UnixStream, std::thread::JoinHandle<io::Result<()>>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let handle = std::thread::spawn(move || serve_connection(server, store.as_ref()));
+        (client, handle)
+    }
+
+    /// Client side of the handshake at protocol 1.37.
+    fn client_handshake(conn: &mut UnixStream) {
+        write_u64(conn, WORKER_MAGIC_1).unwrap();
+        assert_eq!(read_u64(conn).unwrap(), WORKER_MAGIC_2);
+        assert_eq!(read_u64(conn).unwrap(), PROTOCOL_VERSION);
+        write_u64(conn, (1 << 8) | 37).unwrap();
+        write_u64(conn, 0).unwrap(); // cpu affinity
+        write_u64(conn, 0).unwrap(); // reserveSpace
+        let version = read_string(conn).unwrap();
+        assert!(version.starts_with("snix "), "{version}");
+        assert_eq!(read_u64(conn).unwrap(), 2); // not trusted
+        assert_eq!(read_u64(conn).unwrap(), STDERR_LAST);
+    }
+
+    #[test]
+    fn handshake_then_query_path_info() {
+        let (mut conn, server) = connect(store());
+        client_handshake(&mut conn);
+
+        write_u64(&mut conn, WorkerOp::QueryPathInfo.to_u64()).unwrap();
+        write_bytes(&mut conn, P_HELLO.as_bytes()).unwrap();
+
+        assert_eq!(read_u64(&mut conn).unwrap(), STDERR_LAST);
+        assert_eq!(read_u64(&mut conn).unwrap(), 1); // valid
+        assert_eq!(read_string(&mut conn).unwrap(), ""); // deriver
+        assert_eq!(read_string(&mut conn).unwrap(), NAR_HASH_HEX);
+        assert_eq!(read_strings(&mut conn).unwrap(), vec![P_GLIBC.to_string()]);
+        assert_eq!(read_u64(&mut conn).unwrap(), 1771588800);
+        assert_eq!(read_u64(&mut conn).unwrap(), 128);
+        assert_eq!(read_u64(&mut conn).unwrap(), 0); // ultimate
+        assert_eq!(read_strings(&mut conn).unwrap(), vec!["cache.example.org-1:c2ln".to_string()]);
+        assert_eq!(read_string(&mut conn).unwrap(), ""); // ca
+
+        drop(conn);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn unknown_path_and_valid_paths() {
+        let (mut conn, server) = connect(store());
+        client_handshake(&mut conn);
+
+        let missing = "/nix/store/2c8kzfrjzhi7jkmz3fxcsyj7c5n2sp5s-b-2.0";
+        write_u64(&mut conn, WorkerOp::QueryPathInfo.to_u64()).unwrap();
+        write_bytes(&mut conn, missing.as_bytes()).unwrap();
+        assert_eq!(read_u64(&mut conn).unwrap(), STDERR_LAST);
+        assert_eq!(read_u64(&mut conn).unwrap(), 0);
+
+        write_u64(&mut conn, WorkerOp::IsValidPath.to_u64()).unwrap();
+        write_bytes(&mut conn, P_HELLO.as_bytes()).unwrap();
+        assert_eq!(read_u64(&mut conn).unwrap(), STDERR_LAST);
+        assert_eq!(read_u64(&mut conn).unwrap(), 1);
+
+        write_u64(&mut conn, WorkerOp::QueryValidPaths.to_u64()).unwrap();
+        write_strings(&mut conn, &[P_HELLO.to_string(), missing.to_string()]).unwrap();
+        write_u64(&mut conn, 0).unwrap(); // substitute
+        assert_eq!(read_u64(&mut conn).unwrap(), STDERR_LAST);
+        assert_eq!(read_strings(&mut conn).unwrap(), vec![P_HELLO.to_string()]);
+
+        drop(conn);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn write_ops_are_rejected() {
+        let (mut conn, server) = connect(store());
+        client_handshake(&mut conn);
+
+        write_u64(&mut conn, 7).unwrap(); // AddToStore
+        assert_eq!(read_u64(&mut conn).unwrap(), STDERR_ERROR);
+        assert_eq!(read_string(&mut conn).unwrap(), "Error");
+        assert_eq!(read_u64(&mut conn).unwrap(), 0);
+        assert_eq!(read_string(&mut conn).unwrap(), "Error");
+        let msg = read_string(&mut conn).unwrap();
+        assert!(msg.contains("read-only"), "{msg}");
+
+        let err = server.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn worker_ops_round_trip() {
+        for op in WorkerOp::ALL {
+            assert_eq!(WorkerOp::from_u64(op.to_u64()), Ok(op));
+        }
+        let err = WorkerOp::from_u64(7).unwrap_err();
+        assert_eq!(err, UnsupportedOp(7));
+        assert_eq!(
+            err.to_string(),
+            "operation 7 is not supported by the read-only snix daemon"
+        );
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let (mut conn, server) = connect(store());
+        write_u64(&mut conn, 0xdead).unwrap();
+        assert!(server.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn wire_nar_hash_normalizes() {
+        assert_eq!(wire_nar_hash(&format!("sha256:{NAR_HASH_HEX}")), NAR_HASH_HEX);
+        assert_eq!(wire_nar_hash("abc123def456"), "abc123def456");
+    }
+
+    #[test]
+    fn bytes_roundtrip_with_padding() {
+        let mut buf = Vec::new();
+        write_bytes(&mut buf, b"hello").unwrap();
+        assert_eq!(buf.len(), 16);
+        assert_eq!(read_bytes(&mut buf.as_slice()).unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,513 @@
+//! HTTP client for binary cache metadata with conditional GET support.
+//!
+//! `snix path-info` and `snix fetch` query the same narinfo files over
+//! and over. `NixHttpClient` keeps the last response body next to its
+//! `ETag`/`Last-Modified` validators and revalidates with
+//! `If-None-Match`/`If-Modified-Since`. A `304 Not Modified` reply is
+//! served from the local copy, so only headers cross the (slow) VM network.
+//!
+//! Cache layout:
+//!   /nix/var/snix/http-cache/
+//!     {sha256(url)}.body             — last response body
+//!     {sha256(url)}.validators.json  — ETag / Last-Modified sidecar
+//!
+//! `NixHttpClient::present_paths` answers "which of these paths does the
+//! cache have?" with `HEAD {hash}.narinfo` requests. Binary caches have no
+//! standard listing endpoint, so this is the portable way to ask.
+//!
+//! Every request is a GET or HEAD, so failures that may go away on their
+//! own (5xx replies, refused or dropped connections, timeouts) are retried
+//! as described by the client's [`RetryPolicy`]. This matters during VM
+//! boot, when the network may still be coming up. 4xx replies are final.
+//! [`NixHttpClient::get`] gives uncached GETs the same retries. NAR
+//! downloads turn them off ([`RetryPolicy::NONE`]) and retry in the
+//! download loop instead, which can resume where a transfer broke off.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use nix_compat::nixbase32;
+use nix_compat::store_path::StorePath;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::store_root;
+
+/// How many `HEAD` requests `present_paths` keeps in flight.
+const HEAD_CONCURRENCY: usize = 4;
+
+/// Cache validators returned by the server for a response.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Validators {
+    /// Value of the `ETag` response header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+
+    /// Value of the `Last-Modified` response header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// How often, and how patiently, a failed request is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first. `1` disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each further one.
+    pub base_delay: Duration,
+    /// Upper bound on a single delay.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// A single attempt, for callers that retry on their own.
+    pub const NONE: Self = Self {
+        max_attempts: 1,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+
+    /// Delay before retry number `retry` (`0` is the first retry).
+    ///
+    /// The exponential delay is randomized down to half its value, so
+    /// clients that failed together don't retry in lockstep.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        delay / 2 + (delay / 2).mul_f64(jitter())
+    }
+
+    /// Run `call` until it succeeds, fails for good, or attempts run out.
+    fn run<T>(&self, mut call: impl FnMut() -> Result<T, ureq::Error>) -> Result<T, ureq::Error> {
+        let mut attempt = 1;
+        loop {
+            match call() {
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    std::thread::sleep(self.delay(attempt - 1));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Four attempts, waiting at most 1.75s in total.
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Whether a request failing with `e` may succeed when sent again.
+pub fn is_transient(e: &ureq::Error) -> bool {
+    matches!(
+        e,
+        ureq::Error::StatusCode(500..=599)
+            | ureq::Error::Io(_)
+            | ureq::Error::Timeout(_)
+            | ureq::Error::HostNotFound
+            | ureq::Error::ConnectionFailed
+    )
+}
+
+/// A number in `[0, 1)`, different on every call.
+fn jitter() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    // Each RandomState is freshly keyed, which is random enough for spacing retries.
+    let bits = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// ureq wrapper that revalidates cached responses instead of re-downloading them.
+pub struct NixHttpClient {
+    cache_dir: PathBuf,
+    retry: RetryPolicy,
+}
+
+impl NixHttpClient {
+    /// Create a client using the default cache directory.
+    pub fn new() -> Self {
+        Self::with_cache_dir(store_root::var_dir().join("http-cache"))
+    }
+
+    /// Create a client with a custom cache directory (for testing).
+    pub fn with_cache_dir(cache_dir: PathBuf) -> Self {
+        Self {
+            cache_dir,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Replace the [`RetryPolicy`] used for every request.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// GET `url` with the extra request `headers`, retrying transient failures.
+    pub fn get(
+        &self,
+        url: &str,
+        headers: &[(&str, String)],
+    ) -> Result<ureq::http::Response<ureq::Body>, ureq::Error> {
+        self.retry.run(|| {
+            let mut request = ureq::get(url);
+            for (name, value) in headers {
+                request = request.header(*name, value);
+            }
+            request.call()
+        })
+    }
+
+    /// GET `url` as a string, reusing the cached body on `304 Not Modified`.
+    pub fn get_string(&self, url: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let cached = self.load(url);
+
+        let mut headers = Vec::new();
+        if let Some((_, validators)) = &cached {
+            if let Some(etag) = &validators.etag {
+                headers.push(("If-None-Match", etag.clone()));
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                headers.push(("If-Modified-Since", last_modified.clone()));
+            }
+        }
+
+        let resp = match self.get(url, &headers) {
+            Ok(resp) if resp.status() == 304 => return Self::not_modified(url, cached),
+            Ok(resp) => resp,
+            Err(ureq::Error::StatusCode(304)) => return Self::not_modified(url, cached),
+            Err(e) => return Err(e.into()),
+        };
+
+        let validators = Validators {
+            etag: header(&resp, "etag"),
+            last_modified: header(&resp, "last-modified"),
+        };
+        let body = resp.into_body().read_to_string()?;
+
+        // Caching is an optimization: a read-only or missing /nix/var must
+        // not turn a successful download into an error.
+        if !validators.is_empty() {
+            let _ = self.store(url, &body, &validators);
+        }
+
+        Ok(body)
+    }
+
+    /// Return the subset of `store_paths` that the cache at `cache_url` has.
+    ///
+    /// Sends a `HEAD {hash}.narinfo` per path, at most `HEAD_CONCURRENCY`
+    /// at a time. `404` and `403` (S3 buckets answer missing keys with
+    /// 403) mean absent; any other failure is returned as an error.
+    pub fn present_paths(
+        &self,
+        cache_url: &str,
+        store_paths: &[&str],
+    ) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+        let base = cache_url.trim_end_matches('/');
+        let urls = store_paths
+            .iter()
+            .map(|path| {
+                let sp = StorePath::<String>::from_absolute_path(path.as_bytes())
+                    .map_err(|e| format!("invalid store path {path}: {e}"))?;
+                Ok(format!("{base}/{}.narinfo", nixbase32::encode(sp.digest())))
+            })
+            .collect::<Result<Vec<String>, String>>()?;
+
+        let next = AtomicUsize::new(0);
+        let present = Mutex::new(BTreeSet::new());
+        let failure: Mutex<Option<String>> = Mutex::new(None);
+
+        std::thread::scope(|scope| {
+            for _ in 0..HEAD_CONCURRENCY.min(urls.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= urls.len() || failure.lock().unwrap().is_some() {
+                        break;
+                    }
+                    match self.retry.run(|| ureq::head(&urls[i]).call()) {
+                        Ok(_) => {
+                            present.lock().unwrap().insert(store_paths[i].to_string());
+                        }
+                        Err(ureq::Error::StatusCode(403 | 404)) => {}
+                        Err(e) => {
+                            failure
+                                .lock()
+                                .unwrap()
+                                .get_or_insert(format!("{}: {e}", urls[i]));
+                        }
+                    }
+                });
+            }
+        });
+
+        if let Some(e) = failure.into_inner().unwrap() {
+            return Err(e.into());
+        }
+        Ok(present.into_inner().unwrap())
+    }
+
+    /// Resolve a `304` reply from the cached body.
+    fn not_modified(
+        url: &str,
+        cached: Option<(String, Validators)>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        match cached {
+            Some((body, _)) => Ok(body),
+            None => Err(format!("{url}: 304 Not Modified without a cached copy").into()),
+        }
+    }
+
+    /// Load the cached body and validators for `url`, if both are present.
+    fn load(&self, url: &str) -> Option<(String, Validators)> {
+        let (body_file, validators_file) = self.cache_files(url);
+        let validators: Validators =
+            serde_json::from_str(&fs::read_to_string(validators_file).ok()?).ok()?;
+        let body = fs::read_to_string(body_file).ok()?;
+        Some((body, validators))
+    }
+
+    /// Persist the body and its validators sidecar for `url`.
+    fn store(&self, url: &str, body: &str, validators: &Validators) -> std::io::Result<()> {
+        fs::create_dir_all(&self.cache_dir)?;
+        let (body_file, validators_file) = self.cache_files(url);
+        let json = serde_json::to_string(validators)?;
+        // Body first: a sidecar without a body is ignored by `load`.
+        fs::write(body_file, body)?;
+        fs::write(validators_file, json)?;
+        Ok(())
+    }
+
+    fn cache_files(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = data_encoding::HEXLOWER.encode(&Sha256::digest(url.as_bytes()));
+        (
+            self.cache_dir.join(format!("{key}.body")),
+            self.cache_dir.join(format!("{key}.validators.json")),
+        )
+    }
+}
+
+impl Default for NixHttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn header(resp: &ureq::http::Response<ureq::Body>, name: &str) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+// ===== Tests =====
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_http;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    const NARINFO: &str = "StorePath: /nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello-1.0\n";
+
+    /// Serve `replies` in order. Returns the URL of a narinfo on the server.
+    fn mock_server(replies: Vec<Vec<u8>>) -> (String, mpsc::Receiver<String>) {
+        let (base, rx) = test_http::serve_sequence(replies);
+        (format!("{base}/00bgd045z0d4icpbc2yyz4gx48ak44la.narinfo"), rx)
+    }
+
+    fn ok_reply(body: &str, etag: &str) -> Vec<u8> {
+        let headers = format!("ETag: {etag}\r\nContent-Length: {}\r\n", body.len());
+        test_http::reply("200 OK", &headers, body.as_bytes())
+    }
+
+    #[test]
+    fn present_paths_returns_cached_subset() {
+        let paths: Vec<String> = (0..10u8)
+            .map(|i| format!("/nix/store/{}-pkg-{i}", nixbase32::encode(&[i; 20])))
+            .collect();
+        let served: Vec<String> = (0..10u8)
+            .filter(|i| i % 3 == 0)
+            .map(|i| format!("/{}.narinfo", nixbase32::encode(&[i; 20])))
+            .collect();
+        let files: Vec<(&str, &str)> = served.iter().map(|p| (p.as_str(), "")).collect();
+        let cache = test_http::serve_files(&files);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let client = NixHttpClient::with_cache_dir(tmp.path().to_path_buf());
+        let query: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let present = client.present_paths(&format!("{cache}/"), &query).unwrap();
+
+        let expected: BTreeSet<String> = [0, 3, 6, 9].iter().map(|&i| paths[i].clone()).collect();
+        assert_eq!(present, expected);
+    }
+
+    #[test]
+    fn present_paths_rejects_invalid_store_path() {
+        let client = NixHttpClient::with_cache_dir(PathBuf::from("/nonexistent"));
+        let err = client
+            .present_paths("http://example.invalid", &["/not/a/store/path"])
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid store path"), "{err}");
+    }
+
+    #[test]
+    fn present_paths_unreachable_cache_is_error() {
+        // Bind and immediately drop a listener to get a closed port.
+        let dead = {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", l.local_addr().unwrap())
+        };
+        let client = quick_retries(PathBuf::from("/nonexistent"));
+        let path = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello-1.0";
+        assert!(client.present_paths(&dead, &[path]).is_err());
+    }
+
+    #[test]
+    fn present_paths_retries_server_errors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let client = quick_retries(tmp.path().to_path_buf());
+        let (cache, heads) = test_http::serve_sequence(vec![
+            test_http::status("503 Service Unavailable"),
+            test_http::status("200 OK"),
+        ]);
+        let path = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello-1.0";
+
+        let present = client.present_paths(&cache, &[path]).unwrap();
+        assert!(present.contains(path));
+        assert!(heads.iter().take(2).all(|h| h.starts_with("head ")));
+    }
+
+    #[test]
+    fn not_modified_reuses_cached_body() {
+        let tmp = tempfile::tempdir().unwrap();
+        let client = NixHttpClient::with_cache_dir(tmp.path().join("http-cache"));
+        let (url, requests) = mock_server(vec![
+            ok_reply(NARINFO, "\"v1\""),
+            test_http::status("304 Not Modified"),
+        ]);
+
+        assert_eq!(client.get_string(&url).unwrap(), NARINFO);
+        let first = requests.recv().unwrap();
+        assert!(!first.contains("if-none-match"));
+
+        assert_eq!(client.get_string(&url).unwrap(), NARINFO);
+        let second = requests.recv().unwrap();
+        assert!(second.contains("if-none-match: \"v1\""), "{second}");
+    }
+
+    #[test]
+    fn modified_response_replaces_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let client = NixHttpClient::with_cache_dir(tmp.path().to_path_buf());
+        let (url, _requests) = mock_server(vec![
+            ok_reply(NARINFO, "\"v1\""),
+            ok_reply("StorePath: changed\n", "\"v2\""),
+        ]);
+
+        client.get_string(&url).unwrap();
+        assert_eq!(client.get_string(&url).unwrap(), "StorePath: changed\n");
+
+        let (_, validators) = client.load(&url).unwrap();
+        assert_eq!(validators.etag.as_deref(), Some("\"v2\""));
+    }
+
+    /// A client for `cache_dir` that retries without noticeable delays.
+    fn quick_retries(cache_dir: PathBuf) -> NixHttpClient {
+        NixHttpClient::with_cache_dir(cache_dir).with_retry_policy(RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        })
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let tmp = tempfile::tempdir().unwrap();
+        let client = quick_retries(tmp.path().to_path_buf());
+        let (url, requests) = mock_server(vec![
+            test_http::status("503 Service Unavailable"),
+            test_http::status("502 Bad Gateway"),
+            ok_reply(NARINFO, "\"v1\""),
+        ]);
+
+        assert_eq!(client.get_string(&url).unwrap(), NARINFO);
+        assert_eq!(requests.iter().take(3).count(), 3);
+        assert!(requests.try_recv().is_err(), "no fourth attempt");
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let tmp = tempfile::tempdir().unwrap();
+        let client = quick_retries(tmp.path().to_path_buf());
+        let (url, requests) = mock_server(vec![
+            test_http::status("404 Not Found"),
+            ok_reply(NARINFO, "\"v1\""),
+        ]);
+
+        assert!(client.get_string(&url).is_err());
+        requests.recv().unwrap();
+        assert!(requests.try_recv().is_err(), "404 must not be retried");
+    }
+
+    #[test]
+    fn retry_delay_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        for (retry, full) in [
+            (0, 100),
+            (1, 200),
+            (2, 400),
+            (3, 800),
+            (4, 1000),
+            (40, 1000),
+        ] {
+            let delay = policy.delay(retry);
+            let full = Duration::from_millis(full);
+            assert!(
+                delay >= full / 2 && delay <= full,
+                "retry {retry}: {delay:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn not_modified_without_cache_is_error() {
+        assert!(NixHttpClient::not_modified("http://example.invalid/x.narinfo", None).is_err());
+    }
+
+    #[test]
+    fn no_validators_not_cached() {
+        let tmp = tempfile::tempdir().unwrap();
+        let client = NixHttpClient::with_cache_dir(tmp.path().to_path_buf());
+        let body = "no etag here";
+        let (url, _requests) = mock_server(vec![test_http::ok(body)]);
+
+        assert_eq!(client.get_string(&url).unwrap(), body);
+        assert!(client.load(&url).is_none());
+    }
+}
@@ -3,21 +3,64 @@
 //! Each registered store path gets a JSON file at:
 //!   `/nix/var/snix/pathinfo/{nixbase32-hash}.json`
 //!
+//! Once compacted (`PathInfoDb::compact`), entries live in a single
+//! newline-delimited index, `/nix/var/snix/pathinfo/pathinfo.ndjson`,
+//! loaded into memory on open. Registrations append a line; deletions
+//! append a `{"deleted": path}` line, and the file is rewritten once
+//! superseded lines pile up. Loose per-path files are still read, so
+//! databases populated at image build time keep working.
+//!
 //! No SQLite, no daemon — just filesystem operations.
 //! Designed for <10k paths where simplicity beats performance.
+//!
+//! Recursive disk sizes are memoized in `/nix/var/snix/disk-sizes.json`
+//! (see `DiskSizeCache`), so `snix store list` doesn't re-walk every path.
+//!
+//! Writes (`register`, `delete`, `compact`) hold `/nix/var/snix/db.lock`,
+//! so concurrent snix processes don't interleave them. Reads take no lock.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use nix_compat::narinfo::{NarInfo, NarInfoBuilder};
 use nix_compat::nixbase32;
-use nix_compat::store_path::StorePath;
+use nix_compat::nixhash::{HashAlgo, NixHash};
+use nix_compat::store_path::{StorePath, StorePathRef};
 use serde::{Deserialize, Serialize};
 
-/// Default base directory for snix metadata
+/// Default base directory for snix metadata (see [crate::store_root] for
+/// moving it).
 pub const SNIX_VAR_DIR: &str = "/nix/var/snix";
 
+/// Batches at least this large are read by `get_many` from several threads.
+const GET_MANY_PARALLEL_MIN: usize = 32;
+
+/// Reader threads used by `get_many`.
+const GET_MANY_THREADS: usize = 4;
+
+/// Name of the index file inside the pathinfo directory.
+pub const INDEX_FILE: &str = "pathinfo.ndjson";
+
+/// Superseded index lines tolerated, beyond one per live entry, before
+/// the index is rewritten.
+const INDEX_REWRITE_SLACK: usize = 256;
+
+/// Name of the lock file writers hold, next to the pathinfo directory.
+pub const DB_LOCK_FILE: &str = "db.lock";
+
+/// How long a writer waits for another process to release the lock.
+const DB_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause between attempts to take the lock.
+const DB_LOCK_RETRY: Duration = Duration::from_millis(20);
+
+/// Whether the platform has advisory file locks. Redox has no `flock`.
+const FILE_LOCKS: bool = !cfg!(target_os = "redox");
+
 /// Per-path metadata stored as JSON
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
@@ -38,6 +81,10 @@ pub struct PathInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deriver: Option<String>,
 
+    /// Which output of the deriver this path is, e.g. `out` (if known)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+
     /// ISO 8601 timestamp when this path was registered locally
     pub registration_time: String,
 
@@ -52,24 +99,81 @@ pub struct PathInfo {
     pub files: Vec<crate::nar::ManifestEntry>,
 }
 
+impl PathInfo {
+    /// The narinfo a binary cache would serve for this path, without a
+    /// URL or signatures. Its [NarInfo::fingerprint] is what gets signed.
+    pub fn to_narinfo(&self) -> Result<NarInfo<'_>, PathInfoError> {
+        fn parse_path(path: &str) -> Result<StorePathRef<'_>, PathInfoError> {
+            StorePathRef::from_absolute_path(path.as_bytes())
+                .map_err(|e| PathInfoError::InvalidPath(format!("{path}: {e}")))
+        }
+        let store_path = parse_path(&self.store_path)?;
+        let nar_hash = match NixHash::from_str(&self.nar_hash, Some(HashAlgo::Sha256)) {
+            Ok(NixHash::Sha256(digest)) => digest,
+            _ => {
+                return Err(PathInfoError::Corrupt(format!(
+                    "{}: invalid NAR hash '{}'",
+                    self.store_path, self.nar_hash
+                )))
+            }
+        };
+        let references = self
+            .references
+            .iter()
+            .map(|r| parse_path(r))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(NarInfoBuilder::new(store_path, "", nar_hash, self.nar_size)
+            .references(references)
+            .build())
+    }
+
+    /// The narinfo fingerprint (`1;path;narHash;narSize;refs`) that
+    /// binary cache signatures are made over.
+    pub fn fingerprint(&self) -> Result<String, PathInfoError> {
+        Ok(self.to_narinfo()?.fingerprint())
+    }
+}
+
 /// Filesystem-backed path info database.
 ///
 /// Each store path's metadata is stored in its own JSON file,
-/// keyed by the nixbase32 hash from the store path.
+/// keyed by the nixbase32 hash from the store path, or, once the
+/// database is compacted, in the [INDEX_FILE]. Index entries take
+/// precedence over loose files for the same path.
+///
+/// The index is read on open, and again before a write if another
+/// process changed it meanwhile. Reads between writes don't see other
+/// processes' index entries, only the loose files they write.
 pub struct PathInfoDb {
     pathinfo_dir: PathBuf,
+    /// The loaded index, if the database has one.
+    index: RwLock<Option<Index>>,
+    /// How long writes wait for the [DB_LOCK_FILE].
+    lock_timeout: Duration,
+    /// Number of PathInfo files read (lets tests assert single-pass access).
+    #[cfg(test)]
+    reads: std::sync::atomic::AtomicUsize,
 }
 
 impl PathInfoDb {
     /// Open the database, creating the directory if needed.
     pub fn open() -> io::Result<Self> {
-        Self::open_at(Path::new(SNIX_VAR_DIR).join("pathinfo"))
+        Self::open_at(crate::store_root::var_dir().join("pathinfo"))
     }
 
     /// Open the database at a custom path (for testing).
     pub fn open_at(pathinfo_dir: PathBuf) -> io::Result<Self> {
         fs::create_dir_all(&pathinfo_dir)?;
-        Ok(Self { pathinfo_dir })
+        let index = Index::load(pathinfo_dir.join(INDEX_FILE))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self {
+            pathinfo_dir,
+            index: RwLock::new(index),
+            lock_timeout: DB_LOCK_TIMEOUT,
+            #[cfg(test)]
+            reads: Default::default(),
+        })
     }
 
     /// Return the directory where PathInfo JSON files are stored.
@@ -80,25 +184,99 @@ impl PathInfoDb {
     /// Look up metadata for a store path. Returns `None` if not registered.
     pub fn get(&self, store_path: &str) -> Result<Option<PathInfo>, PathInfoError> {
         let file = self.info_file(store_path)?;
+        if let Some(index) = self.index.read().unwrap().as_ref() {
+            if let Some(info) = index.entries.get(store_path) {
+                return Ok(Some(info.clone()));
+            }
+        }
         if !file.exists() {
             return Ok(None);
         }
-        let content = fs::read_to_string(&file)
-            .map_err(|e| PathInfoError::Io(format!("reading {}: {e}", file.display())))?;
+        let content = self.read_info_file(&file)?;
         let info: PathInfo = serde_json::from_str(&content)
             .map_err(|e| PathInfoError::Corrupt(format!("{}: {e}", file.display())))?;
         Ok(Some(info))
     }
 
-    /// Register a store path (write its JSON file).
-    /// Overwrites if already registered.
+    /// Look up several store paths at once. Unregistered paths are left
+    /// out of the returned map.
+    ///
+    /// Large batches are split across a few threads, since each lookup is
+    /// a separate file open and parse.
+    pub fn get_many(
+        &self,
+        store_paths: &[&str],
+    ) -> Result<BTreeMap<String, PathInfo>, PathInfoError> {
+        let mut out = BTreeMap::new();
+        if store_paths.len() < GET_MANY_PARALLEL_MIN {
+            for path in store_paths {
+                if let Some(info) = self.get(path)? {
+                    out.insert(path.to_string(), info);
+                }
+            }
+            return Ok(out);
+        }
+
+        let chunk_len = store_paths.len().div_ceil(GET_MANY_THREADS);
+        let results: Vec<Result<Vec<(String, PathInfo)>, PathInfoError>> =
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = store_paths
+                    .chunks(chunk_len)
+                    .map(|chunk| {
+                        scope.spawn(move || -> Result<_, PathInfoError> {
+                            let mut found = Vec::new();
+                            for path in chunk {
+                                if let Some(info) = self.get(path)? {
+                                    found.push((path.to_string(), info));
+                                }
+                            }
+                            Ok(found)
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("pathinfo reader thread panicked"))
+                    .collect()
+            });
+
+        for found in results {
+            out.extend(found?);
+        }
+        Ok(out)
+    }
+
+    /// Register a store path (write its JSON file, or append it to the
+    /// index). Overwrites if already registered.
     pub fn register(&self, info: &PathInfo) -> Result<(), PathInfoError> {
         let file = self.info_file(&info.store_path)?;
-        let json = serde_json::to_string_pretty(info)
-            .map_err(|e| PathInfoError::Io(format!("serializing: {e}")))?;
-        fs::write(&file, json)
-            .map_err(|e| PathInfoError::Io(format!("writing {}: {e}", file.display())))?;
-        Ok(())
+        self.locked(|index| {
+            if let Some(index) = index.as_mut() {
+                return index.insert(info.clone());
+            }
+            let json = serde_json::to_string_pretty(info)
+                .map_err(|e| PathInfoError::Io(format!("serializing: {e}")))?;
+            fs::write(&file, json)
+                .map_err(|e| PathInfoError::Io(format!("writing {}: {e}", file.display())))
+        })
+    }
+
+    /// Add `signature` (`keyname:base64`) to a registered path, replacing
+    /// any earlier signature by the same key. Returns `false` if the path
+    /// already carried exactly this signature.
+    pub fn add_signature(&self, store_path: &str, signature: &str) -> Result<bool, PathInfoError> {
+        let mut info = self
+            .get(store_path)?
+            .ok_or_else(|| PathInfoError::InvalidPath(format!("not registered: {store_path}")))?;
+        if info.signatures.iter().any(|s| s == signature) {
+            return Ok(false);
+        }
+        let key_name = signature.split_once(':').map_or(signature, |(name, _)| name);
+        info.signatures
+            .retain(|s| s.split_once(':').map(|(name, _)| name) != Some(key_name));
+        info.signatures.push(signature.to_string());
+        self.register(&info)?;
+        Ok(true)
     }
 
     /// Save a file manifest for a store path.
@@ -147,24 +325,107 @@ impl PathInfoDb {
 
     /// Check whether a store path is registered.
     pub fn is_registered(&self, store_path: &str) -> bool {
-        self.info_file(store_path)
-            .map(|f| f.exists())
-            .unwrap_or(false)
+        let Ok(file) = self.info_file(store_path) else {
+            return false;
+        };
+        let indexed = self
+            .index
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|index| index.entries.contains_key(store_path));
+        indexed || file.exists()
     }
 
     /// Delete the metadata for a store path.
     pub fn delete(&self, store_path: &str) -> Result<(), PathInfoError> {
         let file = self.info_file(store_path)?;
-        if file.exists() {
-            fs::remove_file(&file)
-                .map_err(|e| PathInfoError::Io(format!("deleting {}: {e}", file.display())))?;
-        }
-        Ok(())
+        self.locked(|index| {
+            if let Some(index) = index.as_mut() {
+                index.remove(store_path)?;
+            }
+            if file.exists() {
+                fs::remove_file(&file).map_err(|e| {
+                    PathInfoError::Io(format!("deleting {}: {e}", file.display()))
+                })?;
+            }
+            Ok(())
+        })
     }
 
     /// List all registered store paths (scans the directory).
     pub fn list_paths(&self) -> Result<Vec<String>, PathInfoError> {
-        let mut paths = Vec::new();
+        Ok(self
+            .list_infos()?
+            .into_iter()
+            .map(|info| info.store_path)
+            .collect())
+    }
+
+    /// Load every registered PathInfo in a single directory scan,
+    /// sorted by store path. Each JSON file is read exactly once.
+    pub fn list_infos(&self) -> Result<Vec<PathInfo>, PathInfoError> {
+        let mut infos: BTreeMap<String, PathInfo> = self
+            .loose_infos()?
+            .into_iter()
+            .map(|(_, info)| (info.store_path.clone(), info))
+            .collect();
+        if let Some(index) = self.index.read().unwrap().as_ref() {
+            infos.extend(index.entries.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        Ok(infos.into_values().collect())
+    }
+
+    /// Fold all per-path JSON files into the index, creating it if needed,
+    /// and remove them. Returns how many files were folded in.
+    ///
+    /// Index entries win over loose files for the same path. Files that
+    /// don't parse are left in place.
+    pub fn compact(&self) -> Result<usize, PathInfoError> {
+        self.locked(|guard| {
+            let loose = self.loose_infos()?;
+
+            let index =
+                guard.get_or_insert_with(|| Index::new(self.pathinfo_dir.join(INDEX_FILE)));
+            for (_, info) in &loose {
+                index
+                    .entries
+                    .entry(info.store_path.clone())
+                    .or_insert_with(|| info.clone());
+            }
+            index.rewrite()?;
+
+            for (file, _) in &loose {
+                fs::remove_file(file).map_err(|e| {
+                    PathInfoError::Io(format!("deleting {}: {e}", file.display()))
+                })?;
+            }
+            Ok(loose.len())
+        })
+    }
+
+    /// Run a write to the database holding the [DB_LOCK_FILE], with the
+    /// index brought up to date with other processes' writes first.
+    fn locked<T>(
+        &self,
+        write: impl FnOnce(&mut Option<Index>) -> Result<T, PathInfoError>,
+    ) -> Result<T, PathInfoError> {
+        let _lock = LockFile::acquire(self.lock_file(), self.lock_timeout)?;
+        let mut index = self.index.write().unwrap();
+        Index::refresh(&mut index, &self.pathinfo_dir.join(INDEX_FILE))?;
+        write(&mut index)
+    }
+
+    fn lock_file(&self) -> PathBuf {
+        self.pathinfo_dir
+            .parent()
+            .unwrap_or(Path::new(SNIX_VAR_DIR))
+            .join(DB_LOCK_FILE)
+    }
+
+    /// Parse every per-path JSON file, skipping ones that don't parse.
+    fn loose_infos(&self) -> Result<Vec<(PathBuf, PathInfo)>, PathInfoError> {
+        let mut infos = Vec::new();
         for entry in fs::read_dir(&self.pathinfo_dir)
             .map_err(|e| PathInfoError::Io(format!("reading dir: {e}")))?
         {
@@ -175,14 +436,28 @@ impl PathInfoDb {
             if !name_str.ends_with(".json") {
                 continue;
             }
-            let content = fs::read_to_string(entry.path())
-                .map_err(|e| PathInfoError::Io(format!("reading {}: {e}", name_str)))?;
+            let content = self.read_info_file(&entry.path())?;
             if let Ok(info) = serde_json::from_str::<PathInfo>(&content) {
-                paths.push(info.store_path);
+                infos.push((entry.path(), info));
             }
         }
-        paths.sort();
-        Ok(paths)
+        Ok(infos)
+    }
+
+    /// Open the on-disk size cache kept next to the pathinfo directory.
+    pub fn disk_sizes(&self) -> DiskSizeCache {
+        let var_dir = self.pathinfo_dir.parent()
+            .unwrap_or(Path::new("/nix/var/snix"));
+        DiskSizeCache::open_at(var_dir.join("disk-sizes.json"))
+    }
+
+    /// Recursive disk usage of a store path, memoized in the size cache.
+    pub fn disk_size(&self, store_path: &str) -> u64 {
+        let mut sizes = self.disk_sizes();
+        let size = sizes.size_of(store_path);
+        // The cache is an optimization — never fail a lookup over it.
+        let _ = sizes.save();
+        size
     }
 
     /// Return the set of all registered store paths (for GC).
@@ -190,6 +465,20 @@ impl PathInfoDb {
         Ok(self.list_paths()?.into_iter().collect())
     }
 
+    /// Read a PathInfo JSON file.
+    fn read_info_file(&self, file: &Path) -> Result<String, PathInfoError> {
+        #[cfg(test)]
+        self.reads.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        fs::read_to_string(file)
+            .map_err(|e| PathInfoError::Io(format!("reading {}: {e}", file.display())))
+    }
+
+    /// Number of PathInfo files read so far.
+    #[cfg(test)]
+    pub(crate) fn reads(&self) -> usize {
+        self.reads.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
     /// Compute the JSON file path for a given store path.
     fn info_file(&self, store_path: &str) -> Result<PathBuf, PathInfoError> {
         let hash = store_path_hash(store_path)?;
@@ -197,6 +486,389 @@ impl PathInfoDb {
     }
 }
 
+// ===== Index =====
+
+/// One line of the [INDEX_FILE].
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IndexRecord {
+    Deleted { deleted: String },
+    Info(PathInfo),
+}
+
+/// The in-memory copy of the [INDEX_FILE].
+struct Index {
+    file: PathBuf,
+    entries: BTreeMap<String, PathInfo>,
+    /// Lines in the file, superseded ones included.
+    lines: usize,
+    /// The last line was cut short (a crash mid-append), so the next
+    /// write rewrites the file instead of appending to it.
+    torn: bool,
+    /// The file as we last read or wrote it. Anything else means another
+    /// process wrote to it since.
+    stamp: Option<FileStamp>,
+}
+
+/// What tells versions of the index file apart: appends change its size
+/// and mtime, a rewrite replaces the inode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    size: u64,
+    mtime: Option<SystemTime>,
+    ino: u64,
+}
+
+impl FileStamp {
+    fn of(meta: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            size: meta.len(),
+            mtime: meta.modified().ok(),
+            ino: meta.ino(),
+        }
+    }
+
+    fn read(file: &Path) -> Result<Option<Self>, PathInfoError> {
+        match fs::metadata(file) {
+            Ok(meta) => Ok(Some(Self::of(&meta))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(PathInfoError::Io(format!("reading {}: {e}", file.display()))),
+        }
+    }
+}
+
+impl Index {
+    fn new(file: PathBuf) -> Self {
+        Self {
+            file,
+            entries: BTreeMap::new(),
+            lines: 0,
+            torn: false,
+            stamp: None,
+        }
+    }
+
+    /// Reload `file` into `slot` if another process created or changed it
+    /// since this one last touched it.
+    fn refresh(slot: &mut Option<Self>, file: &Path) -> Result<(), PathInfoError> {
+        let Some(stamp) = FileStamp::read(file)? else {
+            return Ok(());
+        };
+        if !matches!(slot, Some(index) if index.stamp == Some(stamp)) {
+            *slot = Self::load(file.to_path_buf())?;
+        }
+        Ok(())
+    }
+
+    /// Replay `file`, or `None` if the database has no index.
+    fn load(file: PathBuf) -> Result<Option<Self>, PathInfoError> {
+        // Taken first: a write after it only makes the next refresh reload.
+        let stamp = FileStamp::read(&file)?;
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(PathInfoError::Io(format!("reading {}: {e}", file.display())));
+            }
+        };
+
+        let mut index = Self::new(file);
+        index.stamp = stamp;
+        let complete = content.ends_with('\n');
+        let mut lines = content.lines().enumerate().peekable();
+        while let Some((lineno, line)) = lines.next() {
+            index.lines += 1;
+            match serde_json::from_str::<IndexRecord>(line) {
+                Ok(IndexRecord::Info(info)) => {
+                    index.entries.insert(info.store_path.clone(), info);
+                }
+                Ok(IndexRecord::Deleted { deleted }) => {
+                    index.entries.remove(&deleted);
+                }
+                Err(_) if lines.peek().is_none() && !complete => index.torn = true,
+                Err(e) => {
+                    return Err(PathInfoError::Corrupt(format!(
+                        "{}:{}: {e}",
+                        index.file.display(),
+                        lineno + 1
+                    )));
+                }
+            }
+        }
+        Ok(Some(index))
+    }
+
+    fn insert(&mut self, info: PathInfo) -> Result<(), PathInfoError> {
+        let line = serde_json::to_string(&info)
+            .map_err(|e| PathInfoError::Io(format!("serializing: {e}")))?;
+        self.entries.insert(info.store_path.clone(), info);
+        self.append(&line)
+    }
+
+    fn remove(&mut self, store_path: &str) -> Result<(), PathInfoError> {
+        if self.entries.remove(store_path).is_none() {
+            return Ok(());
+        }
+        let line = serde_json::json!({ "deleted": store_path }).to_string();
+        self.append(&line)
+    }
+
+    /// Append `line`, or rewrite the whole file once it is mostly
+    /// superseded lines.
+    fn append(&mut self, line: &str) -> Result<(), PathInfoError> {
+        self.lines += 1;
+        if self.torn || self.lines > 2 * self.entries.len() + INDEX_REWRITE_SLACK {
+            return self.rewrite();
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)
+            .map_err(|e| PathInfoError::Io(format!("opening {}: {e}", self.file.display())))?;
+        file.write_all(format!("{line}\n").as_bytes())
+            .and_then(|()| file.metadata())
+            .map(|meta| self.stamp = Some(FileStamp::of(&meta)))
+            .map_err(|e| PathInfoError::Io(format!("writing {}: {e}", self.file.display())))
+    }
+
+    /// Write one line per live entry to a temporary file and move it over
+    /// the index.
+    fn rewrite(&mut self) -> Result<(), PathInfoError> {
+        let mut content = String::new();
+        for info in self.entries.values() {
+            let line = serde_json::to_string(info)
+                .map_err(|e| PathInfoError::Io(format!("serializing: {e}")))?;
+            content.push_str(&line);
+            content.push('\n');
+        }
+
+        let tmp = self.file.with_extension("ndjson.tmp");
+        fs::write(&tmp, content)
+            .and_then(|()| fs::rename(&tmp, &self.file))
+            .map_err(|e| PathInfoError::Io(format!("writing {}: {e}", self.file.display())))?;
+        self.stamp = FileStamp::read(&self.file)?;
+        self.lines = self.entries.len();
+        self.torn = false;
+        Ok(())
+    }
+}
+
+// ===== Lock =====
+
+/// An exclusive `flock` on a lock file, such as the [DB_LOCK_FILE]. The
+/// file records the owner's pid for error messages and stays in place;
+/// the lock goes away with the process that holds it, however it ends.
+///
+/// Without file locks ([FILE_LOCKS]) the lock is the file itself: it is
+/// created exclusively and removed on drop, and one whose pid is no longer
+/// running is taken over.
+pub(crate) struct LockFile {
+    file: PathBuf,
+    /// The locked file, or `None` if the lock is the file's existence.
+    locked: Option<fs::File>,
+}
+
+impl LockFile {
+    /// Take the lock at `file`, retrying for up to `timeout` while another
+    /// process holds it.
+    pub(crate) fn acquire(file: PathBuf, timeout: Duration) -> Result<Self, PathInfoError> {
+        let deadline = Instant::now() + timeout;
+        if !FILE_LOCKS {
+            return Self::acquire_exclusive(file, deadline);
+        }
+
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&file)
+            .map_err(|e| PathInfoError::Io(format!("opening {}: {e}", file.display())))?;
+        loop {
+            match f.try_lock() {
+                Ok(()) => {
+                    // The pid is only for the error message.
+                    let _ = f.set_len(0).and_then(|()| write!(f, "{}", std::process::id()));
+                    return Ok(Self { file, locked: Some(f) });
+                }
+                Err(fs::TryLockError::WouldBlock) => {}
+                Err(fs::TryLockError::Error(e)) => {
+                    return Err(PathInfoError::Io(format!("locking {}: {e}", file.display())));
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return Err(Self::held(&file));
+            }
+            std::thread::sleep(DB_LOCK_RETRY);
+        }
+    }
+
+    /// [`Self::acquire`] where the lock is an exclusively created file.
+    fn acquire_exclusive(file: PathBuf, deadline: Instant) -> Result<Self, PathInfoError> {
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&file) {
+                Ok(mut f) => {
+                    // A lock without a pid still counts, it is just never stale.
+                    let _ = write!(f, "{}", std::process::id());
+                    return Ok(Self { file, locked: None });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(PathInfoError::Io(format!("creating {}: {e}", file.display())));
+                }
+            }
+
+            if let Some(pid) = Self::owner(&file).filter(|&pid| !pid_alive(pid)) {
+                Self::remove_stale(&file, pid);
+                continue;
+            }
+            if Instant::now() >= deadline {
+                return Err(Self::held(&file));
+            }
+            std::thread::sleep(DB_LOCK_RETRY);
+        }
+    }
+
+    /// Remove the lock `file` left by `pid`, which is no longer running.
+    ///
+    /// Others may find it stale at the same time, and one of them may
+    /// already have replaced it with a lock of its own. So the file is only
+    /// removed while holding `<file>.takeover`, created exclusively, and
+    /// only if it still names `pid`. A takeover file left by a process that
+    /// died holding it blocks takeovers, never hands out the lock twice.
+    fn remove_stale(file: &Path, pid: u32) {
+        let guard = file.with_extension("takeover");
+        if fs::OpenOptions::new().write(true).create_new(true).open(&guard).is_err() {
+            return;
+        }
+        if Self::owner(file) == Some(pid) {
+            eprintln!("warning: removing {} left by pid {pid}", file.display());
+            let _ = fs::remove_file(file);
+        }
+        let _ = fs::remove_file(&guard);
+    }
+
+    /// The pid recorded in the lock `file`.
+    fn owner(file: &Path) -> Option<u32> {
+        fs::read_to_string(file).ok()?.trim().parse().ok().filter(|&pid| pid > 0)
+    }
+
+    fn held(file: &Path) -> PathInfoError {
+        let owner = Self::owner(file)
+            .map(|pid| format!("pid {pid}"))
+            .unwrap_or_else(|| "unknown pid".to_string());
+        // A flock can't outlive its owner, but a lock file without a pid can.
+        let hint = if FILE_LOCKS { "" } else { "; remove it if no snix is running" };
+        PathInfoError::Locked(format!("{} is held by {owner}{hint}", file.display()))
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // A flock is released when the file is closed. Removing the file
+        // would let the next writer lock a new one while a waiter locks
+        // the old one.
+        if self.locked.is_none() {
+            let _ = fs::remove_file(&self.file);
+        }
+    }
+}
+
+/// Whether a process with `pid` is running.
+fn pid_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists and may be signalled.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// ===== Disk Size Cache =====
+
+/// A memoized disk size, valid while the path's mtime is unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct DiskSizeEntry {
+    /// mtime of the store path (nanoseconds since the epoch) when measured
+    mtime: u64,
+    /// Recursive size in bytes
+    size: u64,
+}
+
+/// Recursive disk sizes of store paths, keyed by path.
+///
+/// An entry is reused only while the path's top-level mtime matches the
+/// one recorded with it. Registered store paths are immutable, so this is
+/// almost always a hit; a repaired or re-extracted path gets a new mtime
+/// and is measured again.
+pub struct DiskSizeCache {
+    file: PathBuf,
+    entries: BTreeMap<String, DiskSizeEntry>,
+    dirty: bool,
+}
+
+impl DiskSizeCache {
+    /// Load the cache from `file`. A missing or corrupt file starts empty.
+    pub fn open_at(file: PathBuf) -> Self {
+        let entries = fs::read_to_string(&file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            file,
+            entries,
+            dirty: false,
+        }
+    }
+
+    /// Disk size of `path`, measuring it only if the cached entry is stale.
+    pub fn size_of(&mut self, path: &str) -> u64 {
+        let on_disk = crate::store_root::real_path(path);
+        let Some(mtime) = mtime_nanos(&on_disk) else {
+            // Gone from disk — forget it rather than serve a stale size.
+            if self.entries.remove(path).is_some() {
+                self.dirty = true;
+            }
+            return 0;
+        };
+
+        if let Some(entry) = self.entries.get(path) {
+            if entry.mtime == mtime {
+                return entry.size;
+            }
+        }
+
+        let size = crate::store::path_size(&on_disk).unwrap_or(0);
+        self.entries
+            .insert(path.to_string(), DiskSizeEntry { mtime, size });
+        self.dirty = true;
+        size
+    }
+
+    /// Write the cache back if anything changed.
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string(&self.entries)?;
+        fs::write(&self.file, json)
+    }
+}
+
+/// mtime of a path (not following symlinks) in nanoseconds since the epoch.
+fn mtime_nanos(path: &Path) -> Option<u64> {
+    let modified = fs::symlink_metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
 /// Extract the nixbase32 hash component from a full store path.
 ///
 /// `/nix/store/abc123...-hello-1.0` → `"abc123..."`
@@ -223,6 +895,29 @@ pub fn current_timestamp() -> String {
     }
 }
 
+/// `2026-02-20T12:00:00Z` → seconds since the epoch.
+pub fn parse_timestamp(ts: &str) -> Option<u64> {
+    let (date, time) = ts.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|p| p.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Inverse of days_to_date (days from civil date)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12; // March = 0
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146097 + day_of_era - 719468).ok()?;
+
+    Some(days * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
 /// Howard Hinnant's civil days algorithm.
 fn days_to_date(days: u64) -> (u64, u64, u64) {
     let z = days + 719468;
@@ -248,6 +943,8 @@ pub enum PathInfoError {
     Io(String),
     /// Corrupt JSON file
     Corrupt(String),
+    /// Another process holds the database lock
+    Locked(String),
 }
 
 impl std::fmt::Display for PathInfoError {
@@ -256,6 +953,7 @@ impl std::fmt::Display for PathInfoError {
             Self::InvalidPath(s) => write!(f, "invalid store path: {s}"),
             Self::Io(s) => write!(f, "I/O error: {s}"),
             Self::Corrupt(s) => write!(f, "corrupt pathinfo: {s}"),
+            Self::Locked(s) => write!(f, "store is locked by another process: {s}"),
         }
     }
 }
@@ -286,6 +984,7 @@ mod tests {
                 P_GLIBC.to_string(),
             ],
             deriver: Some("/nix/store/5g5nzcsmcmk0mnqz6i0gr1m0g8r5rq8r-hello-1.0.drv".to_string()),
+            output: Some("out".to_string()),
             registration_time: "2026-02-20T12:00:00Z".to_string(),
             signatures: vec!["cache.nixos.org-1:abc...".to_string()],
             files: vec![],
@@ -443,6 +1142,7 @@ mod tests {
                 nar_size: 100,
                 references: vec![],
                 deriver: None,
+                output: None,
                 registration_time: "2026-01-01T00:00:00Z".to_string(),
                 signatures: vec![],
                 files: vec![],
@@ -471,6 +1171,7 @@ mod tests {
                 nar_size: 100,
                 references: vec![],
                 deriver: None,
+                output: None,
                 registration_time: "2026-01-01T00:00:00Z".to_string(),
                 signatures: vec![],
                 files: vec![],
@@ -515,6 +1216,7 @@ mod tests {
                 nar_size: 0,
                 references: vec![],
                 deriver: None,
+                output: None,
                 registration_time: "t".to_string(),
                 signatures: vec![],
                 files: vec![],
@@ -551,4 +1253,375 @@ mod tests {
         assert!(ts.contains('T'));
         assert!(ts.ends_with('Z'));
     }
+
+    #[test]
+    fn parse_timestamp_known_dates() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2026-02-20T12:00:00Z"), Some(1771588800));
+        assert_eq!(parse_timestamp("2000-03-01T00:00:01Z"), Some(951868801));
+        assert_eq!(parse_timestamp("not a date"), None);
+    }
+
+    // ===== Disk Size Cache Tests =====
+
+    #[test]
+    fn db_list_infos_reads_each_file_once() {
+        let tmp = TempDir::new().unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+
+        for path in [P_A, P_B, P_C] {
+            db.register(&PathInfo {
+                store_path: path.to_string(),
+                nar_hash: "h".to_string(),
+                nar_size: 1,
+                references: vec![],
+                deriver: None,
+                output: None,
+                registration_time: "t".to_string(),
+                signatures: vec![],
+                files: vec![],
+            }).unwrap();
+        }
+
+        let infos = db.list_infos().unwrap();
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[0].store_path, P_A);
+        assert_eq!(db.reads(), 3);
+    }
+
+    fn register_sample(db: &PathInfoDb, path: &str) {
+        db.register(&PathInfo {
+            store_path: path.to_string(),
+            nar_hash: "h".to_string(),
+            nar_size: 1,
+            references: vec![],
+            deriver: None,
+            output: None,
+            registration_time: "t".to_string(),
+            signatures: vec![],
+            files: vec![],
+        }).unwrap();
+    }
+
+    #[test]
+    fn db_get_many_skips_unregistered() {
+        let tmp = TempDir::new().unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+        register_sample(&db, P_A);
+        register_sample(&db, P_C);
+
+        let infos = db.get_many(&[P_A, P_B, P_C, P_HELLO, P_A]).unwrap();
+        assert_eq!(infos.keys().map(String::as_str).collect::<Vec<_>>(), vec![P_A, P_C]);
+        assert_eq!(infos[P_C].store_path, P_C);
+    }
+
+    #[test]
+    fn db_get_many_parallel() {
+        let tmp = TempDir::new().unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+
+        let paths: Vec<String> = (0..100u8)
+            .map(|i| format!("/nix/store/{}-pkg-{i}", nixbase32::encode(&[i; 20])))
+            .collect();
+        for path in paths.iter().step_by(2) {
+            register_sample(&db, path);
+        }
+
+        let query: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let infos = db.get_many(&query).unwrap();
+        assert_eq!(infos.len(), 50);
+        assert!(paths.iter().step_by(2).all(|p| infos.contains_key(p)));
+    }
+
+    #[test]
+    fn db_get_many_invalid_path_errors() {
+        let tmp = TempDir::new().unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+        assert!(db.get_many(&[P_A, "/not/a/store/path"]).is_err());
+    }
+
+    // ===== Index Tests =====
+
+    fn index_lines(db: &PathInfoDb) -> Vec<String> {
+        fs::read_to_string(db.dir().join(INDEX_FILE))
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn db_compact_folds_loose_files_into_index() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("pathinfo");
+        let legacy = PathInfoDb::open_at(dir.clone()).unwrap();
+        legacy.register(&sample_info()).unwrap();
+        for path in [P_A, P_B, P_C] {
+            register_sample(&legacy, path);
+        }
+        let before = legacy.list_infos().unwrap();
+
+        assert_eq!(legacy.compact().unwrap(), 4);
+        let names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec![INDEX_FILE.to_string()]);
+        assert_eq!(legacy.list_infos().unwrap(), before);
+
+        let db = PathInfoDb::open_at(dir).unwrap();
+        assert_eq!(db.list_infos().unwrap(), before);
+        assert_eq!(db.get(P_HELLO).unwrap(), Some(sample_info()));
+        assert!(db.is_registered(P_C));
+        assert_eq!(db.reads(), 0, "everything is served from the index");
+    }
+
+    #[test]
+    fn db_index_replays_appends_and_deletes() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("pathinfo");
+        let db = PathInfoDb::open_at(dir.clone()).unwrap();
+        register_sample(&db, P_A);
+        register_sample(&db, P_B);
+        db.compact().unwrap();
+
+        let mut hello = sample_info();
+        db.register(&hello).unwrap();
+        hello.nar_size = 1;
+        db.register(&hello).unwrap();
+        db.delete(P_A).unwrap();
+        assert_eq!(index_lines(&db).len(), 2 + 3);
+        assert!(index_lines(&db).last().unwrap().contains("\"deleted\""));
+
+        // A loose file written by someone else is still picked up.
+        let legacy = PathInfo { store_path: P_C.to_string(), ..sample_info() };
+        fs::write(
+            dir.join(format!("{}.json", store_path_hash(P_C).unwrap())),
+            serde_json::to_string(&legacy).unwrap(),
+        )
+        .unwrap();
+
+        let db = PathInfoDb::open_at(dir).unwrap();
+        assert_eq!(db.list_paths().unwrap(), vec![P_B, P_C, P_HELLO]);
+        assert_eq!(db.get(P_HELLO).unwrap().unwrap().nar_size, 1);
+        assert_eq!(db.get(P_A).unwrap(), None);
+        assert_eq!(db.get(P_C).unwrap(), Some(legacy));
+    }
+
+    #[test]
+    fn db_index_rewritten_when_mostly_superseded() {
+        let tmp = TempDir::new().unwrap();
+        let db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+        db.compact().unwrap();
+
+        for _ in 0..INDEX_REWRITE_SLACK + 10 {
+            register_sample(&db, P_A);
+        }
+        assert!(index_lines(&db).len() <= 10, "{} lines", index_lines(&db).len());
+        assert!(db.get(P_A).unwrap().is_some());
+    }
+
+    #[test]
+    fn db_index_ignores_torn_last_line() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("pathinfo");
+        let db = PathInfoDb::open_at(dir.clone()).unwrap();
+        register_sample(&db, P_A);
+        db.compact().unwrap();
+
+        let mut file = fs::OpenOptions::new().append(true).open(dir.join(INDEX_FILE)).unwrap();
+        file.write_all(b"{\"storePath\":\"/nix/st").unwrap();
+
+        let db = PathInfoDb::open_at(dir.clone()).unwrap();
+        assert_eq!(db.list_paths().unwrap(), vec![P_A]);
+        register_sample(&db, P_B);
+        assert_eq!(index_lines(&db).len(), 2, "the torn line is dropped");
+
+        fs::write(dir.join(INDEX_FILE), "garbage\n").unwrap();
+        assert!(PathInfoDb::open_at(dir).is_err());
+    }
+
+    #[test]
+    fn db_index_reloaded_after_same_size_rewrite() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("pathinfo");
+        let db = PathInfoDb::open_at(dir.clone()).unwrap();
+        register_sample(&db, P_A);
+        db.compact().unwrap();
+        let other = PathInfoDb::open_at(dir.clone()).unwrap();
+
+        // Another process rewrites the index to one of the same length.
+        let index = dir.join(INDEX_FILE);
+        let content = fs::read_to_string(&index).unwrap();
+        let rewritten = content.replace("\"narSize\":1", "\"narSize\":7");
+        assert_ne!(rewritten, content);
+        fs::write(dir.join("rewrite.tmp"), &rewritten).unwrap();
+        fs::rename(dir.join("rewrite.tmp"), &index).unwrap();
+
+        register_sample(&other, P_B);
+        assert_eq!(other.get(P_A).unwrap().unwrap().nar_size, 7);
+    }
+
+    // ===== Lock Tests =====
+
+    #[test]
+    fn db_concurrent_registers_both_survive() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("pathinfo");
+        PathInfoDb::open_at(dir.clone()).unwrap().compact().unwrap();
+
+        // Separate handles, as two snix processes would have.
+        std::thread::scope(|scope| {
+            for path in [P_A, P_B] {
+                let db = PathInfoDb::open_at(dir.clone()).unwrap();
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        register_sample(&db, path);
+                    }
+                });
+            }
+        });
+
+        let db = PathInfoDb::open_at(dir).unwrap();
+        assert_eq!(db.list_paths().unwrap(), vec![P_A, P_B]);
+        let lock = LockFile::acquire(tmp.path().join(DB_LOCK_FILE), Duration::ZERO);
+        assert!(lock.is_ok(), "the lock is released");
+    }
+
+    #[test]
+    fn db_write_waits_for_lock_then_times_out() {
+        let tmp = TempDir::new().unwrap();
+        let mut db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+        db.lock_timeout = Duration::from_millis(50);
+        let mut holder = fs::File::create(tmp.path().join(DB_LOCK_FILE)).unwrap();
+        holder.lock().unwrap();
+        write!(holder, "4242").unwrap();
+
+        let err = db.register(&sample_info()).unwrap_err();
+        assert!(matches!(err, PathInfoError::Locked(_)));
+        assert!(
+            err.to_string().starts_with("store is locked by another process:"),
+            "{err}"
+        );
+        assert!(err.to_string().contains("pid 4242"), "{err}");
+        assert!(matches!(db.delete(P_HELLO), Err(PathInfoError::Locked(_))));
+
+        // Reads don't need the lock.
+        assert_eq!(db.get(P_HELLO).unwrap(), None);
+
+        drop(holder);
+        db.register(&sample_info()).unwrap();
+        assert!(db.is_registered(P_HELLO));
+    }
+
+    /// The pid of a process that has exited.
+    fn dead_pid() -> u32 {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn db_lock_left_by_killed_process_is_not_held() {
+        let tmp = TempDir::new().unwrap();
+        let mut db = PathInfoDb::open_at(tmp.path().join("pathinfo")).unwrap();
+        db.lock_timeout = Duration::from_millis(50);
+        fs::write(tmp.path().join(DB_LOCK_FILE), dead_pid().to_string()).unwrap();
+
+        db.register(&sample_info()).unwrap();
+        assert!(db.is_registered(P_HELLO));
+    }
+
+    #[test]
+    fn exclusive_lock_takes_over_from_dead_pid() {
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join(DB_LOCK_FILE);
+        let soon = || Instant::now() + Duration::from_millis(50);
+
+        fs::write(&file, dead_pid().to_string()).unwrap();
+        let lock = LockFile::acquire_exclusive(file.clone(), soon()).unwrap();
+        assert_eq!(LockFile::owner(&file), Some(std::process::id()));
+
+        // A live owner, this process, keeps it.
+        let err = LockFile::acquire_exclusive(file.clone(), soon()).err().unwrap();
+        assert!(err.to_string().contains(&format!("pid {}", std::process::id())), "{err}");
+
+        drop(lock);
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn stale_takeover_leaves_a_replaced_lock() {
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join(DB_LOCK_FILE);
+        let dead = dead_pid();
+
+        // Another process took over first and now holds the lock.
+        fs::write(&file, std::process::id().to_string()).unwrap();
+        LockFile::remove_stale(&file, dead);
+        assert_eq!(LockFile::owner(&file), Some(std::process::id()));
+
+        // Or is taking it over right now.
+        fs::write(&file, dead.to_string()).unwrap();
+        fs::write(file.with_extension("takeover"), "").unwrap();
+        LockFile::remove_stale(&file, dead);
+        assert!(file.exists());
+
+        fs::remove_file(file.with_extension("takeover")).unwrap();
+        LockFile::remove_stale(&file, dead);
+        assert!(!file.exists());
+        assert!(!file.with_extension("takeover").exists());
+    }
+
+    #[test]
+    fn disk_size_cache_hit() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("pkg");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), "12345").unwrap();
+        let path = dir.to_string_lossy().to_string();
+
+        let mut sizes = DiskSizeCache::open_at(tmp.path().join("disk-sizes.json"));
+        assert_eq!(sizes.size_of(&path), 5);
+        sizes.save().unwrap();
+
+        // Change the contents without touching the directory mtime:
+        // the reopened cache still serves the memoized size.
+        fs::write(dir.join("a"), "1234567890").unwrap();
+        let mut sizes = DiskSizeCache::open_at(tmp.path().join("disk-sizes.json"));
+        assert_eq!(sizes.size_of(&path), 5);
+    }
+
+    #[test]
+    fn disk_size_cache_invalidated_by_mtime() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("pkg");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), "12345").unwrap();
+        let path = dir.to_string_lossy().to_string();
+
+        let mut sizes = DiskSizeCache::open_at(tmp.path().join("disk-sizes.json"));
+        assert_eq!(sizes.size_of(&path), 5);
+        sizes.save().unwrap();
+
+        // Adding an entry changes the directory; pin the new mtime so the
+        // test doesn't depend on filesystem timestamp granularity.
+        fs::write(dir.join("b"), "678").unwrap();
+        fs::File::open(&dir)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_000_000))
+            .unwrap();
+
+        let mut sizes = DiskSizeCache::open_at(tmp.path().join("disk-sizes.json"));
+        assert_eq!(sizes.size_of(&path), 8);
+    }
+
+    #[test]
+    fn disk_size_cache_missing_path() {
+        let tmp = TempDir::new().unwrap();
+        let mut sizes = DiskSizeCache::open_at(tmp.path().join("disk-sizes.json"));
+        assert_eq!(sizes.size_of("/nix/store/cn8r5krrhrr7rrqz3q7nr8r7n5s2sp5s-nope-1.0"), 0);
+        assert!(!sizes.dirty);
+    }
 }
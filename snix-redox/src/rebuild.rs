@@ -14,7 +14,7 @@
 //!   4. Package names resolved to store paths from /nix/cache/packages.json
 //!   5. `system::switch()` activates the new manifest
 //!
-//! Configuration.nix is a simple Nix attrset — no functions or imports needed:
+//! Configuration.nix is a simple Nix attrset — no functions needed:
 //! ```nix
 //! {
 //!   hostname = "my-redox";
@@ -22,16 +22,32 @@
 //!   networking.mode = "dhcp";
 //! }
 //! ```
+//!
+//! Extra files in /etc are declared with `environment.etc`, as on NixOS:
+//! `environment.etc."motd".text = "...";` or `.source = <store path>;`.
+//!
+//! Larger configs can be split up with `imports = [ ./networking.nix ];`.
+//! Imported attrsets are deep-merged into the top level (see `CONFIG_PRELUDE`).
+//!
+//! `rebuild` caches evaluations in /nix/var/snix/eval-cache and skips the
+//! evaluator while neither the config nor its imports changed
+//! (`--no-eval-cache` turns this off).
 
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use serde::{Deserialize, Serialize};
+use snix_eval::{EvalIO, FileType, StdIO};
 
+use crate::eval::{self, EvalBudget};
 use crate::system::{
-    self, BootConfig, Configuration, GraphicsConfig as SysGraphicsConfig, Group, HardwareConfig,
-    LoggingConfig as SysLoggingConfig, Manifest, NetworkingConfig, Package, PowerConfig as SysPowerConfig,
+    self, BootConfig, Configuration, EtcSource, FileInfo, GraphicsConfig as SysGraphicsConfig,
+    Group, HardwareConfig, LoggingConfig as SysLoggingConfig, Manifest, NetworkingConfig, Package, PowerConfig as SysPowerConfig,
     SecurityConfig as SysSecurityConfig, Services, SystemInfo, User,
 };
 
@@ -48,6 +64,51 @@ const BOOT_ESSENTIAL: &[&str] = &[
     "uutils",
 ];
 
+/// Nix function that loads a configuration file and resolves `imports`.
+///
+/// Each module is an attrset (or a path to one) with an optional `imports`
+/// list. Modules are merged depth-first: nested attrsets merge recursively,
+/// lists concatenate, and a scalar set to two different values is an
+/// evaluation error naming the dotted key.
+const CONFIG_PRELUDE: &str = r#"
+let
+  merge = prefix: a: b:
+    builtins.foldl' (acc: name:
+      let
+        key = if prefix == "" then name else "${prefix}.${name}";
+        old = acc.${name};
+        new = b.${name};
+      in
+        if !(acc ? ${name}) then acc // { ${name} = new; }
+        else if builtins.isAttrs old && builtins.isAttrs new then acc // { ${name} = merge key old new; }
+        else if builtins.isList old && builtins.isList new then acc // { ${name} = old ++ new; }
+        else if old == new then acc
+        else throw "conflicting definitions for '${key}' in configuration modules"
+    ) a (builtins.attrNames b);
+
+  load = module:
+    let
+      m = if builtins.isAttrs module then module else import module;
+    in
+      builtins.foldl' (acc: imported: merge "" acc (load imported))
+        (builtins.removeAttrs m [ "imports" ])
+        (m.imports or [ ]);
+in
+  load
+"#;
+
+/// Build the Nix expression that evaluates a config file (with its
+/// imports) to a JSON string.
+fn config_expr(path: &str) -> String {
+    format!("builtins.toJSON (({CONFIG_PRELUDE}) {path})")
+}
+
+/// Allowed values for enum-like string options (kept in sync with the
+/// `t.enum` types in nix/redox-system/modules).
+const NETWORK_MODES: &[&str] = &["auto", "dhcp", "static", "none"];
+const POWER_ACTIONS: &[&str] = &["shutdown", "reboot", "suspend", "none"];
+const LOG_LEVELS: &[&str] = &["debug", "info", "warn", "error", "off"];
+
 // ===== Configuration Schema =====
 // All fields are Option<T> — only present fields override the current manifest.
 
@@ -64,6 +125,7 @@ pub struct RebuildConfig {
     pub power: Option<PowerConfig>,
     pub users: Option<BTreeMap<String, UserConfig>>,
     pub programs: Option<ProgramsConfig>,
+    pub environment: Option<EnvironmentConfig>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -116,26 +178,67 @@ pub struct ProgramsConfig {
     pub editor: Option<String>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EnvironmentConfig {
+    /// Extra files to place in /etc, keyed by path relative to /etc
+    /// (replaces the previously declared set).
+    pub etc: Option<BTreeMap<String, EtcFileConfig>>,
+}
+
+/// One `environment.etc.<path>` entry: either `text` or `source`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EtcFileConfig {
+    /// Inline file content.
+    pub text: Option<String>,
+    /// Store path of a file to copy.
+    pub source: Option<String>,
+    /// Octal permissions, "644" by default.
+    pub mode: Option<String>,
+}
+
 // ===== Public API =====
 
+/// What [rebuild] builds from and how. Paths left as `None` use the
+/// system defaults.
+#[derive(Debug, Default)]
+pub struct RebuildOptions<'a> {
+    pub config_path: Option<&'a str>,
+    /// Show the changes without applying them.
+    pub dry_run: bool,
+    /// Only take effect on the next boot (see [system::switch]).
+    pub boot: bool,
+    /// Reuse an earlier evaluation of unchanged config files.
+    pub eval_cache: bool,
+    pub manifest_path: Option<&'a str>,
+    pub gen_dir: Option<&'a str>,
+    pub cache_index_path: Option<&'a str>,
+    pub eval_budget: EvalBudget,
+}
+
 /// Rebuild the system from configuration.nix.
 ///
 /// Evaluates the Nix config, merges with the current manifest, resolves
 /// packages, and switches to the new configuration.
-pub fn rebuild(
-    config_path: Option<&str>,
-    dry_run: bool,
-    manifest_path: Option<&str>,
-    gen_dir: Option<&str>,
-    cache_index_path: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
+pub fn rebuild(opts: &RebuildOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let RebuildOptions {
+        config_path,
+        dry_run,
+        boot,
+        eval_cache,
+        manifest_path,
+        gen_dir,
+        cache_index_path,
+        ref eval_budget,
+    } = *opts;
     let cfg_path = config_path.unwrap_or(DEFAULT_CONFIG_PATH);
     let mpath = manifest_path.unwrap_or(DEFAULT_MANIFEST_PATH);
     let cache_path = cache_index_path.unwrap_or(DEFAULT_CACHE_INDEX);
 
     // Step 1: Evaluate configuration.nix
     println!("Evaluating {cfg_path}...");
-    let config = evaluate_config(cfg_path)?;
+    let cache_dir = eval_cache.then_some(Path::new(DEFAULT_EVAL_CACHE_DIR));
+    let config = evaluate_config_cached(cfg_path, cache_dir, eval_budget)?;
+    validate_config(&config)?;
 
     // Step 2: Load current manifest
     let current = system::load_manifest_from(mpath)?;
@@ -165,6 +268,8 @@ pub fn rebuild(
         &tmp_path,
         Some("rebuild from configuration.nix"),
         false,
+        false,
+        boot,
         gen_dir,
         manifest_path,
     );
@@ -175,7 +280,11 @@ pub fn rebuild(
     result?;
 
     println!();
-    println!("✓ System rebuilt from {cfg_path}");
+    if boot {
+        println!("✓ System rebuilt from {cfg_path}; reboot to activate it");
+    } else {
+        println!("✓ System rebuilt from {cfg_path}");
+    }
 
     Ok(())
 }
@@ -277,6 +386,17 @@ pub fn show_config(config_path: Option<&str>) -> Result<(), Box<dyn std::error::
         }
     }
 
+    if let Some(etc) = config.environment.as_ref().and_then(|env| env.etc.as_ref()) {
+        println!("  environment.etc:");
+        for (target, file) in etc {
+            match (&file.text, &file.source) {
+                (_, Some(src)) => println!("    /etc/{target} ← {src}"),
+                (Some(text), None) => println!("    /etc/{target} ({} bytes)", text.len()),
+                (None, None) => println!("    /etc/{target}"),
+            }
+        }
+    }
+
     Ok(())
 }
 
@@ -292,6 +412,24 @@ pub fn evaluate_config_pub(path: &str) -> Result<RebuildConfig, Box<dyn std::err
 }
 
 fn evaluate_config(path: &str) -> Result<RebuildConfig, Box<dyn std::error::Error>> {
+    evaluate_config_cached(path, None, &EvalBudget::default())
+}
+
+/// [evaluate_config], reusing the result of an earlier evaluation from
+/// `cache_dir` when none of the files it read have changed.
+///
+/// Entries are named after the config file's path and content. Each one
+/// records a fingerprint of everything the evaluation looked at: the
+/// files it opened (the config and its imports), the paths it checked
+/// with `pathExists`, the directories it listed and the paths it
+/// imported. An entry is only used if they all still match.
+///
+/// Evaluation gives up once `budget` is exhausted.
+fn evaluate_config_cached(
+    path: &str,
+    cache_dir: Option<&Path>,
+    budget: &EvalBudget,
+) -> Result<RebuildConfig, Box<dyn std::error::Error>> {
     // If the file is already JSON, parse directly (useful for testing)
     if path.ends_with(".json") {
         let content = fs::read_to_string(path)?;
@@ -307,10 +445,40 @@ fn evaluate_config(path: &str) -> Result<RebuildConfig, Box<dyn std::error::Erro
         .into());
     }
 
-    // Build the Nix expression that evaluates config → JSON
-    let expr = format!("builtins.toJSON (import {})", path);
+    let entry = match cache_dir {
+        Some(dir) => Some(dir.join(format!("{}.json", eval_cache_key(path)?))),
+        None => None,
+    };
+    if let Some(cached) = entry.as_deref().and_then(read_eval_cache) {
+        return parse_config_json(&cached.config);
+    }
+
+    let owned = path.to_string();
+    let (json_str, read) =
+        eval::with_budget(budget, move || eval_config_json(&owned).map_err(|e| e.to_string()))?;
+
+    if let Some(entry) = &entry {
+        if let Err(e) = write_eval_cache(entry, &json_str, &read) {
+            eprintln!("warning: could not cache evaluation of {path}: {e}");
+        }
+    }
+
+    parse_config_json(&json_str)
+}
 
-    let eval = snix_eval::Evaluation::builder_impure().build();
+/// Evaluate a configuration.nix to its JSON form, returning what the
+/// evaluation looked at along with it.
+fn eval_config_json(
+    path: &str,
+) -> Result<(String, Inputs), Box<dyn std::error::Error>> {
+    // Build the Nix expression that evaluates config (+ imports) → JSON
+    let expr = config_expr(path);
+
+    let read = Rc::new(RefCell::new(BTreeSet::new()));
+    let io = RecordingIO { read: Rc::clone(&read) };
+    let eval = snix_eval::Evaluation::builder_pure()
+        .enable_impure(Some(Box::new(io) as Box<dyn EvalIO>))
+        .build();
     let result = eval.evaluate(&expr, None);
 
     if !result.errors.is_empty() {
@@ -342,7 +510,189 @@ fn evaluate_config(path: &str) -> Result<RebuildConfig, Box<dyn std::error::Erro
         repr
     };
 
-    parse_config_json(&json_str)
+    let read = read.borrow().clone();
+    Ok((json_str, read))
+}
+
+// ===== Evaluation Cache =====
+
+/// Where `rebuild` caches evaluated configurations.
+const DEFAULT_EVAL_CACHE_DIR: &str = "/nix/var/snix/eval-cache";
+
+/// How an evaluation depended on a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Input {
+    /// Opened it: the file's contents.
+    Contents,
+    /// Checked whether it exists.
+    Exists,
+    /// Listed it: the directory's entry names and types.
+    Listing,
+    /// Imported it: the whole tree under it.
+    Tree,
+}
+
+/// The paths an evaluation depended on, and how.
+type Inputs = BTreeSet<(Input, PathBuf)>;
+
+/// A cached evaluation of a configuration.nix.
+#[derive(Debug, Deserialize, Serialize)]
+struct EvalCacheEntry {
+    /// Everything the evaluation looked at, with its fingerprint.
+    inputs: Vec<(Input, PathBuf, String)>,
+    /// The evaluated configuration, as JSON.
+    config: String,
+}
+
+/// [StdIO] that records every path an evaluation depends on: the
+/// configuration, everything it imports or reads, and the paths it only
+/// checks for or lists.
+struct RecordingIO {
+    read: Rc<RefCell<Inputs>>,
+}
+
+impl RecordingIO {
+    fn record(&self, input: Input, path: &Path) {
+        self.read.borrow_mut().insert((input, path.to_path_buf()));
+    }
+}
+
+impl EvalIO for RecordingIO {
+    fn path_exists(&self, path: &Path) -> io::Result<bool> {
+        self.record(Input::Exists, path);
+        StdIO.path_exists(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn io::Read>> {
+        self.record(Input::Contents, path);
+        StdIO.open(path)
+    }
+
+    fn file_type(&self, path: &Path) -> io::Result<FileType> {
+        StdIO.file_type(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(bytes::Bytes, FileType)>> {
+        self.record(Input::Listing, path);
+        StdIO.read_dir(path)
+    }
+
+    fn import_path(&self, path: &Path) -> io::Result<PathBuf> {
+        self.record(Input::Tree, path);
+        StdIO.import_path(path)
+    }
+
+    fn get_env(&self, key: &OsStr) -> Option<OsString> {
+        StdIO.get_env(key)
+    }
+}
+
+/// Cache entry name for the config file at `path`. The prelude is part of
+/// the key so that a snix with different merge rules doesn't reuse it.
+fn eval_cache_key(path: &str) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(CONFIG_PRELUDE.as_bytes());
+    hasher.update(path.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(&fs::read(path)?);
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// What `path` currently looks like, as far as an evaluation that used
+/// it as `input` can tell.
+fn fingerprint(input: Input, path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    match input {
+        Input::Contents => {
+            hasher.update(&fs::read(path)?);
+        }
+        Input::Exists => return Ok(StdIO.path_exists(path)?.to_string()),
+        Input::Listing => {
+            for (name, file_type) in StdIO.read_dir(path)? {
+                hasher.update(&name);
+                hasher.update(&[0]);
+                hasher.update(format!("{file_type:?}").as_bytes());
+                hasher.update(&[0]);
+            }
+        }
+        Input::Tree => {
+            let mut nar = Vec::new();
+            crate::nar::dump(path, &mut nar)?;
+            hasher.update(&nar);
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// The entry at `entry`, if every input it recorded is unchanged.
+fn read_eval_cache(entry: &Path) -> Option<EvalCacheEntry> {
+    let cached: EvalCacheEntry = serde_json::from_str(&fs::read_to_string(entry).ok()?).ok()?;
+    let unchanged = cached.inputs.iter().all(|(input, path, seen)| {
+        fingerprint(*input, path).is_ok_and(|now| &now == seen)
+    });
+    unchanged.then_some(cached)
+}
+
+fn write_eval_cache(
+    entry: &Path,
+    config: &str,
+    read: &Inputs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut inputs = Vec::new();
+    for (input, path) in read {
+        inputs.push((*input, path.clone(), fingerprint(*input, path)?));
+    }
+    let cached = EvalCacheEntry {
+        inputs,
+        config: config.to_string(),
+    };
+
+    if let Some(dir) = entry.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write-then-rename, so a concurrent rebuild never reads half an entry.
+    let tmp = entry.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&tmp, serde_json::to_string(&cached)?)?;
+    fs::rename(&tmp, entry)?;
+    Ok(())
+}
+
+/// Check enum-like string options against their allowed values.
+///
+/// `merge_config` copies these strings straight into the manifest, so a
+/// typo like `mode = "dhpc"` would otherwise produce a broken system.
+/// All invalid options are reported together.
+pub(crate) fn validate_config(config: &RebuildConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let mut errors = Vec::new();
+
+    let mut check = |option: &str, value: Option<&String>, allowed: &[&str]| {
+        if let Some(value) = value {
+            if !allowed.contains(&value.as_str()) {
+                errors.push(format!(
+                    "  {option} = \"{value}\" (expected one of: {})",
+                    allowed.join(", ")
+                ));
+            }
+        }
+    };
+
+    if let Some(ref net) = config.networking {
+        check("networking.mode", net.mode.as_ref(), NETWORK_MODES);
+    }
+    if let Some(ref power) = config.power {
+        check("power.powerAction", power.power_action.as_ref(), POWER_ACTIONS);
+    }
+    if let Some(ref log) = config.logging {
+        check("logging.level", log.level.as_ref(), LOG_LEVELS);
+        check("logging.kernelLevel", log.kernel_level.as_ref(), LOG_LEVELS);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid configuration:\n{}", errors.join("\n")).into())
+    }
 }
 
 /// Parse a JSON string into a RebuildConfig.
@@ -423,6 +773,9 @@ pub(crate) fn merge_config(
     resolved_packages: &[Package],
 ) -> Result<Manifest, Box<dyn std::error::Error>> {
     let mut m = current.clone();
+    // The merged manifest is in no rootTree; the current one's holds the
+    // old config files.
+    m.root_tree = String::new();
 
     // System metadata
     if let Some(ref h) = config.hostname {
@@ -554,9 +907,88 @@ pub(crate) fn merge_config(
         m.packages = merged_pkgs;
     }
 
+    // Extra /etc files — if specified, replaces the previously declared set
+    if let Some(etc) = config.environment.as_ref().and_then(|env| env.etc.as_ref()) {
+        merge_etc_files(&mut m, etc)?;
+    }
+
     Ok(m)
 }
 
+/// Replace the manifest's `environment.etc` files with `etc`.
+///
+/// Previously declared files are dropped first, so ones no longer in the
+/// config are removed on activation. A target may not shadow a system file
+/// from the rootTree. All invalid entries are reported together.
+fn merge_etc_files(
+    m: &mut Manifest,
+    etc: &BTreeMap<String, EtcFileConfig>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for path in std::mem::take(&mut m.etc).into_keys() {
+        m.files.remove(&path);
+    }
+
+    let mut errors = Vec::new();
+    for (target, file) in etc {
+        let key = format!("etc/{target}");
+        if m.files.contains_key(&key) {
+            errors.push(format!(
+                "  environment.etc.\"{target}\": /{key} is a system file tracked by the manifest"
+            ));
+            continue;
+        }
+        match etc_file(target, file) {
+            Ok((info, source)) => {
+                m.files.insert(key.clone(), info);
+                m.etc.insert(key, source);
+            }
+            Err(e) => errors.push(format!("  environment.etc.\"{target}\": {e}")),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid environment.etc:\n{}", errors.join("\n")).into())
+    }
+}
+
+/// Build the manifest entry for one `environment.etc` file, hashing its
+/// content (read from the store for `source`).
+fn etc_file(target: &str, file: &EtcFileConfig) -> Result<(FileInfo, EtcSource), String> {
+    let valid_target = !target.is_empty()
+        && target
+            .split('/')
+            .all(|c| !c.is_empty() && c != "." && c != "..");
+    if !valid_target {
+        return Err("path must be relative to /etc".to_string());
+    }
+
+    let mode = file.mode.clone().unwrap_or_else(|| "644".to_string());
+    if !matches!(u32::from_str_radix(&mode, 8), Ok(m) if m <= 0o7777) {
+        return Err(format!("invalid mode \"{mode}\""));
+    }
+
+    let (contents, source) = match (&file.text, &file.source) {
+        (Some(text), None) => (text.clone().into_bytes(), EtcSource::Text(text.clone())),
+        (None, Some(src)) => {
+            if !src.starts_with("/nix/store/") {
+                return Err(format!("source {src} is not a store path"));
+            }
+            let contents = fs::read(src).map_err(|e| format!("reading {src}: {e}"))?;
+            (contents, EtcSource::Source(src.clone()))
+        }
+        _ => return Err("set exactly one of text or source".to_string()),
+    };
+
+    let info = FileInfo {
+        blake3: blake3::hash(&contents).to_hex().to_string(),
+        size: contents.len() as u64,
+        mode,
+    };
+    Ok((info, source))
+}
+
 /// Check if a package name is boot-essential (always preserved in /bin/).
 fn is_boot_essential(name: &str) -> bool {
     BOOT_ESSENTIAL.iter().any(|&b| b == name)
@@ -668,6 +1100,22 @@ fn print_changes(current: &Manifest, merged: &Manifest, config: &RebuildConfig)
         }
     }
 
+    // Extra /etc files
+    let etc_paths: std::collections::BTreeSet<_> =
+        current.etc.keys().chain(merged.etc.keys()).collect();
+    for path in etc_paths {
+        let old = current.etc.get(path).and(current.files.get(path));
+        let new = merged.etc.get(path).and(merged.files.get(path));
+        match (old, new) {
+            (None, Some(_)) => changes.push(format!("  /{path}: added")),
+            (Some(_), None) => changes.push(format!("  /{path}: removed")),
+            (Some(o), Some(n)) if o.blake3 != n.blake3 || o.mode != n.mode => {
+                changes.push(format!("  /{path}: changed"))
+            }
+            _ => {}
+        }
+    }
+
     if changes.is_empty() {
         println!("No configuration changes detected.");
     } else {
@@ -713,9 +1161,15 @@ pub fn init_config(path: Option<&str>) -> Result<(), Box<dyn std::error::Error>>
 #   logging.{level, kernelLevel, logToFile},
 #   power.{acpiEnabled, powerAction, rebootOnPanic},
 #   users.{name = { uid, gid, home, shell }},
-#   programs.{editor}
+#   programs.{editor},
+#   environment.etc.{path = { text | source, mode }}
+#
+# Split large configs into modules with `imports`; their attrsets are
+# merged into this one (lists concatenate, conflicting values are errors).
 
 {
+  # imports = [ ./networking.nix ./users.nix ];
+
   # hostname = "redox";
   # timezone = "UTC";
 
@@ -739,6 +1193,11 @@ pub fn init_config(path: Option<&str>) -> Result<(), Box<dyn std::error::Error>>
   #     shell = "/bin/ion";
   #   };
   # };
+
+  # environment.etc = {
+  #   "motd".text = "Welcome to Redox\n";
+  #   "ssh/sshd_config" = { source = "/nix/store/...-sshd_config"; mode = "600"; };
+  # };
 }
 "#,
     )?;
@@ -752,6 +1211,134 @@ pub fn init_config(path: Option<&str>) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
+// ===== Config Generation =====
+
+/// Render a configuration.nix reproducing `manifest`.
+///
+/// Every option `merge_config` applies is written out, so rebuilding from
+/// the result yields the same hostname, timezone, options, users and
+/// `environment.etc` files. Boot-essential packages are left out of
+/// `packages`, since rebuild keeps them anyway.
+pub fn generate_config_nix(manifest: &Manifest) -> String {
+    let cfg = &manifest.configuration;
+    let mut out = String::new();
+
+    out.push_str("# /etc/redox-system/configuration.nix\n#\n");
+    out.push_str(&format!(
+        "# Generated by `snix system generate-config` from generation {}.\n",
+        manifest.generation.id
+    ));
+    out.push_str("# Run `snix system rebuild` after editing it to apply changes.\n\n");
+    out.push_str("{\n");
+
+    out.push_str(&format!("  hostname = {};\n", nix_string(&manifest.system.hostname)));
+    out.push_str(&format!("  timezone = {};\n", nix_string(&manifest.system.timezone)));
+
+    out.push_str("\n  networking = {\n");
+    out.push_str(&format!("    enable = {};\n", cfg.networking.enabled));
+    out.push_str(&format!("    mode = {};\n", nix_string(&cfg.networking.mode)));
+    out.push_str(&format!("    dns = {};\n", nix_list(&cfg.networking.dns)));
+    out.push_str("  };\n");
+
+    out.push_str("\n  graphics = {\n");
+    out.push_str(&format!("    enable = {};\n", cfg.graphics.enabled));
+    out.push_str(&format!("    resolution = {};\n", nix_string(&cfg.graphics.resolution)));
+    out.push_str("  };\n");
+
+    out.push_str("\n  security = {\n");
+    out.push_str(&format!(
+        "    protectKernelSchemes = {};\n",
+        cfg.security.protect_kernel_schemes
+    ));
+    out.push_str(&format!("    requirePasswords = {};\n", cfg.security.require_passwords));
+    out.push_str(&format!("    allowRemoteRoot = {};\n", cfg.security.allow_remote_root));
+    out.push_str("  };\n");
+
+    out.push_str("\n  logging = {\n");
+    out.push_str(&format!("    level = {};\n", nix_string(&cfg.logging.log_level)));
+    out.push_str(&format!(
+        "    kernelLevel = {};\n",
+        nix_string(&cfg.logging.kernel_log_level)
+    ));
+    out.push_str(&format!("    logToFile = {};\n", cfg.logging.log_to_file));
+    out.push_str("  };\n");
+
+    out.push_str("\n  power = {\n");
+    out.push_str(&format!("    acpiEnabled = {};\n", cfg.power.acpi_enabled));
+    out.push_str(&format!("    powerAction = {};\n", nix_string(&cfg.power.power_action)));
+    out.push_str(&format!("    rebootOnPanic = {};\n", cfg.power.reboot_on_panic));
+    out.push_str("  };\n");
+
+    out.push_str("\n  packages = [\n");
+    for pkg in manifest.packages.iter().filter(|p| !is_boot_essential(&p.name)) {
+        out.push_str(&format!("    {}\n", nix_string(&pkg.name)));
+    }
+    out.push_str("  ];\n");
+
+    out.push_str("\n  users = {\n");
+    for (name, user) in &manifest.users {
+        out.push_str(&format!(
+            "    {} = {{ uid = {}; gid = {}; home = {}; shell = {}; }};\n",
+            nix_string(name),
+            user.uid,
+            user.gid,
+            nix_string(&user.home),
+            nix_string(&user.shell)
+        ));
+    }
+    out.push_str("  };\n");
+
+    if !manifest.etc.is_empty() {
+        out.push_str("\n  environment.etc = {\n");
+        for (key, source) in &manifest.etc {
+            let target = key.strip_prefix("etc/").unwrap_or(key);
+            let content = match source {
+                EtcSource::Text(text) => format!("text = {};", nix_string(text)),
+                EtcSource::Source(src) => format!("source = {};", nix_string(src)),
+            };
+            let mode = match manifest.files.get(key) {
+                Some(info) => format!(" mode = {};", nix_string(&info.mode)),
+                None => String::new(),
+            };
+            out.push_str(&format!("    {} = {{ {content}{mode} }};\n", nix_string(target)));
+        }
+        out.push_str("  };\n");
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Quote `s` as a Nix string literal.
+fn nix_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // `${` would start an interpolation.
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A Nix list of string literals, on one line.
+fn nix_list(items: &[String]) -> String {
+    if items.is_empty() {
+        return "[ ]".to_string();
+    }
+    let items: Vec<String> = items.iter().map(|i| nix_string(i)).collect();
+    format!("[ {} ]", items.join(" "))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -857,9 +1444,13 @@ mod tests {
             services: Services {
                 init_scripts: vec!["10_net".to_string()],
                 startup_script: "/startup.sh".to_string(),
+                pre_activation_scripts: vec![],
+                post_activation_scripts: vec![],
             },
             files: BTreeMap::new(),
+            etc: BTreeMap::new(),
             system_profile: String::new(),
+            root_tree: String::new(),
         }
     }
 
@@ -945,6 +1536,15 @@ mod tests {
         assert_eq!(merged.packages.len(), 4); // unchanged
     }
 
+    #[test]
+    fn test_merge_drops_the_current_root_tree() {
+        let mut current = sample_manifest();
+        current.root_tree = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-old-root".to_string();
+
+        let merged = merge_config(&current, &RebuildConfig::default(), &[]).unwrap();
+        assert_eq!(merged.root_tree, "");
+    }
+
     #[test]
     fn test_merge_networking() {
         let current = sample_manifest();
@@ -1092,6 +1692,7 @@ mod tests {
             packages: None,
             users: None,
             programs: None,
+            environment: None,
         };
 
         let merged = merge_config(&current, &config, &[]).unwrap();
@@ -1113,6 +1714,166 @@ mod tests {
         assert!(merged.configuration.power.reboot_on_panic);
     }
 
+    // ===== Environment /etc =====
+
+    #[test]
+    fn test_merge_etc_inline_text() {
+        let config = parse_config_json(
+            r#"{ "environment": { "etc": {
+                "motd": { "text": "Welcome to Redox\n" },
+                "ssh/sshd_config": { "text": "PermitRootLogin no\n", "mode": "600" }
+            } } }"#,
+        )
+        .unwrap();
+
+        let merged = merge_config(&sample_manifest(), &config, &[]).unwrap();
+
+        // The hash is what activation and `snix system verify` compute from disk.
+        let dir = tempfile::tempdir().unwrap();
+        let on_disk = dir.path().join("motd");
+        fs::write(&on_disk, "Welcome to Redox\n").unwrap();
+        let motd = &merged.files["etc/motd"];
+        assert_eq!(motd.blake3, system::hash_file(&on_disk).unwrap());
+        assert_eq!(motd.size, 17);
+        assert_eq!(motd.mode, "644");
+        assert_eq!(
+            merged.etc["etc/motd"],
+            EtcSource::Text("Welcome to Redox\n".to_string())
+        );
+
+        assert_eq!(merged.files["etc/ssh/sshd_config"].mode, "600");
+        assert_eq!(merged.etc.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_etc_replaces_previous_declarations() {
+        let first = parse_config_json(
+            r#"{ "environment": { "etc": { "motd": { "text": "a" }, "issue": { "text": "b" } } } }"#,
+        )
+        .unwrap();
+        let second =
+            parse_config_json(r#"{ "environment": { "etc": { "motd": { "text": "c" } } } }"#)
+                .unwrap();
+
+        let current = merge_config(&sample_manifest(), &first, &[]).unwrap();
+        let merged = merge_config(&current, &second, &[]).unwrap();
+
+        assert!(!merged.files.contains_key("etc/issue"));
+        assert_eq!(merged.etc.keys().collect::<Vec<_>>(), vec!["etc/motd"]);
+        assert_ne!(merged.files["etc/motd"].blake3, current.files["etc/motd"].blake3);
+
+        // Without environment.etc, declared files are kept.
+        let kept = merge_config(&merged, &RebuildConfig::default(), &[]).unwrap();
+        assert_eq!(kept.etc, merged.etc);
+    }
+
+    #[test]
+    fn test_merge_etc_rejects_system_file() {
+        let mut current = sample_manifest();
+        current.files.insert(
+            "etc/passwd".to_string(),
+            FileInfo {
+                blake3: "aaa".to_string(),
+                size: 10,
+                mode: "644".to_string(),
+            },
+        );
+        let config =
+            parse_config_json(r#"{ "environment": { "etc": { "passwd": { "text": "root" } } } }"#)
+                .unwrap();
+
+        let err = merge_config(&current, &config, &[]).unwrap_err().to_string();
+        assert!(err.contains("/etc/passwd is a system file"), "{err}");
+    }
+
+    #[test]
+    fn test_merge_etc_rejects_invalid_entries() {
+        let config = parse_config_json(
+            r#"{ "environment": { "etc": {
+                "../shadow": { "text": "x" },
+                "both": { "text": "x", "source": "/nix/store/abc-file" },
+                "neither": { },
+                "mode": { "text": "x", "mode": "rwx" },
+                "outside": { "source": "/home/user/file" }
+            } } }"#,
+        )
+        .unwrap();
+
+        let err = merge_config(&sample_manifest(), &config, &[]).unwrap_err().to_string();
+        assert!(err.contains("\"../shadow\": path must be relative to /etc"), "{err}");
+        assert!(err.contains("\"both\": set exactly one of text or source"), "{err}");
+        assert!(err.contains("\"neither\": set exactly one of text or source"), "{err}");
+        assert!(err.contains("\"mode\": invalid mode \"rwx\""), "{err}");
+        assert!(err.contains("\"outside\": source /home/user/file is not a store path"), "{err}");
+    }
+
+    // ===== Validation =====
+
+    #[test]
+    fn test_validate_rejects_network_mode() {
+        let config = parse_config_json(r#"{ "networking": { "mode": "dhpc" } }"#).unwrap();
+        let err = validate_config(&config).unwrap_err().to_string();
+        assert!(err.contains("networking.mode = \"dhpc\""), "{err}");
+        assert!(err.contains("auto, dhcp, static, none"), "{err}");
+    }
+
+    #[test]
+    fn test_validate_rejects_power_action() {
+        let config = parse_config_json(r#"{ "power": { "powerAction": "hibernate" } }"#).unwrap();
+        let err = validate_config(&config).unwrap_err().to_string();
+        assert!(err.contains("power.powerAction = \"hibernate\""), "{err}");
+        assert!(err.contains("shutdown, reboot, suspend, none"), "{err}");
+    }
+
+    #[test]
+    fn test_validate_rejects_log_level() {
+        let config = parse_config_json(r#"{ "logging": { "level": "verbose" } }"#).unwrap();
+        let err = validate_config(&config).unwrap_err().to_string();
+        assert!(err.contains("logging.level = \"verbose\""), "{err}");
+    }
+
+    #[test]
+    fn test_validate_rejects_kernel_log_level() {
+        let config = parse_config_json(r#"{ "logging": { "kernelLevel": "loud" } }"#).unwrap();
+        let err = validate_config(&config).unwrap_err().to_string();
+        assert!(err.contains("logging.kernelLevel = \"loud\""), "{err}");
+    }
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        let config = parse_config_json(
+            r#"{ "networking": { "mode": "x" }, "logging": { "level": "y" } }"#,
+        )
+        .unwrap();
+        let err = validate_config(&config).unwrap_err().to_string();
+        assert!(err.contains("networking.mode"), "{err}");
+        assert!(err.contains("logging.level"), "{err}");
+    }
+
+    #[test]
+    fn test_validate_accepts_valid_config_unchanged() {
+        let config = parse_config_json(
+            r#"{
+                "networking": { "mode": "static" },
+                "power": { "powerAction": "suspend" },
+                "logging": { "level": "debug", "kernelLevel": "off" }
+            }"#,
+        )
+        .unwrap();
+        validate_config(&config).unwrap();
+
+        let merged = merge_config(&sample_manifest(), &config, &[]).unwrap();
+        assert_eq!(merged.configuration.networking.mode, "static");
+        assert_eq!(merged.configuration.power.power_action, "suspend");
+        assert_eq!(merged.configuration.logging.log_level, "debug");
+        assert_eq!(merged.configuration.logging.kernel_log_level, "off");
+    }
+
+    #[test]
+    fn test_validate_empty_config() {
+        validate_config(&RebuildConfig::default()).unwrap();
+    }
+
     #[test]
     fn test_merge_security_partial() {
         let current = sample_manifest();
@@ -1224,12 +1985,154 @@ mod tests {
     #[test]
     fn test_evaluate_config_expr() {
         // Verify the Nix expression we'd build
-        let path = "/etc/redox-system/configuration.nix";
-        let expr = format!("builtins.toJSON (import {})", path);
+        let expr = config_expr("/etc/redox-system/configuration.nix");
+        assert!(expr.starts_with("builtins.toJSON (("));
+        assert!(expr.ends_with(") /etc/redox-system/configuration.nix)"));
+    }
+
+    // ===== Imports =====
+
+    #[test]
+    fn test_evaluate_config_imports_disjoint() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("networking.nix"),
+            r#"{ networking.mode = "dhcp"; packages = [ "helix" ]; }"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("users.nix"),
+            r#"{ users.alice = { uid = 1001; gid = 1001; home = "/home/alice"; shell = "/bin/ion"; }; }"#,
+        )
+        .unwrap();
+        let main = dir.path().join("configuration.nix");
+        fs::write(
+            &main,
+            r#"{
+              imports = [ ./networking.nix ./users.nix ];
+              hostname = "modular";
+              packages = [ "ripgrep" ];
+              networking.dns = [ "1.1.1.1" ];
+            }"#,
+        )
+        .unwrap();
+
+        let config = evaluate_config(main.to_str().unwrap()).unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("modular"));
         assert_eq!(
-            expr,
-            "builtins.toJSON (import /etc/redox-system/configuration.nix)"
+            config.packages,
+            Some(vec!["ripgrep".to_string(), "helix".to_string()])
         );
+        let net = config.networking.unwrap();
+        assert_eq!(net.mode.as_deref(), Some("dhcp"));
+        assert_eq!(net.dns, Some(vec!["1.1.1.1".to_string()]));
+        assert_eq!(config.users.unwrap()["alice"].uid, 1001);
+    }
+
+    #[test]
+    fn test_evaluate_config_nested_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("leaf.nix"), r#"{ timezone = "UTC"; }"#).unwrap();
+        fs::write(
+            dir.path().join("middle.nix"),
+            r#"{ imports = [ ./leaf.nix ]; programs.editor = "hx"; }"#,
+        )
+        .unwrap();
+        let main = dir.path().join("configuration.nix");
+        fs::write(&main, r#"{ imports = [ ./middle.nix ]; hostname = "deep"; }"#).unwrap();
+
+        let config = evaluate_config(main.to_str().unwrap()).unwrap();
+        assert_eq!(config.timezone.as_deref(), Some("UTC"));
+        assert_eq!(config.programs.unwrap().editor.as_deref(), Some("hx"));
+    }
+
+    #[test]
+    fn test_evaluate_config_imports_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.nix"), r#"{ hostname = "one"; }"#).unwrap();
+        fs::write(dir.path().join("b.nix"), r#"{ hostname = "two"; }"#).unwrap();
+        let main = dir.path().join("configuration.nix");
+        fs::write(&main, r#"{ imports = [ ./a.nix ./b.nix ]; }"#).unwrap();
+
+        let err = evaluate_config(main.to_str().unwrap()).unwrap_err().to_string();
+        assert!(err.contains("hostname"), "{err}");
+    }
+
+    // ===== Evaluation Cache =====
+
+    /// A configuration.nix importing host.nix, which sets `hostname`.
+    fn write_modular_config(dir: &Path, hostname: &str) -> String {
+        fs::write(dir.join("host.nix"), format!(r#"{{ hostname = "{hostname}"; }}"#)).unwrap();
+        let main = dir.join("configuration.nix");
+        fs::write(&main, r#"{ imports = [ ./host.nix ]; timezone = "UTC"; }"#).unwrap();
+        main.to_str().unwrap().to_string()
+    }
+
+    fn only_cache_entry(cache: &Path) -> PathBuf {
+        let entries: Vec<PathBuf> =
+            fs::read_dir(cache).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(entries.len(), 1, "{entries:?}");
+        entries[0].clone()
+    }
+
+    #[test]
+    fn test_eval_cache_populated_then_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("eval-cache");
+        let main = write_modular_config(dir.path(), "first");
+
+        let config = evaluate_config_cached(&main, Some(&cache), &EvalBudget::default()).unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("first"));
+
+        let entry = only_cache_entry(&cache);
+        let mut cached: EvalCacheEntry =
+            serde_json::from_str(&fs::read_to_string(&entry).unwrap()).unwrap();
+        assert_eq!(cached.inputs.len(), 2, "config and import: {:?}", cached.inputs);
+
+        // Doctor the entry: getting it back means nothing was evaluated.
+        cached.config = cached.config.replace("first", "from-cache");
+        fs::write(&entry, serde_json::to_string(&cached).unwrap()).unwrap();
+
+        let config = evaluate_config_cached(&main, Some(&cache), &EvalBudget::default()).unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("from-cache"));
+        assert_eq!(config.timezone.as_deref(), Some("UTC"));
+    }
+
+    #[test]
+    fn test_eval_cache_invalidated_by_changed_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("eval-cache");
+        let main = write_modular_config(dir.path(), "first");
+        evaluate_config_cached(&main, Some(&cache), &EvalBudget::default()).unwrap();
+
+        write_modular_config(dir.path(), "second");
+        let config = evaluate_config_cached(&main, Some(&cache), &EvalBudget::default()).unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("second"));
+
+        // Same entry name (configuration.nix itself didn't change), new inputs.
+        let entry = only_cache_entry(&cache);
+        assert!(fs::read_to_string(entry).unwrap().contains("second"));
+    }
+
+    #[test]
+    fn test_eval_cache_invalidated_by_path_appearing() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("eval-cache");
+        let main = dir.path().join("configuration.nix");
+        fs::write(
+            &main,
+            r#"{ hostname = if builtins.pathExists ./extra.nix
+                 then (import ./extra.nix).hostname else "base"; }"#,
+        )
+        .unwrap();
+        let main = main.to_str().unwrap();
+        let config = evaluate_config_cached(main, Some(&cache), &EvalBudget::default()).unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("base"));
+
+        // Only the pathExists check saw extra.nix, and it now answers differently.
+        fs::write(dir.path().join("extra.nix"), r#"{ hostname = "extra"; }"#).unwrap();
+        let config = evaluate_config_cached(main, Some(&cache), &EvalBudget::default()).unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("extra"));
     }
 
     // ===== JSON Config Fallback =====
@@ -1268,4 +2171,95 @@ mod tests {
         let result = init_config(Some(path.to_str().unwrap()));
         assert!(result.is_err());
     }
+
+    // ===== Config Generation =====
+
+    #[test]
+    fn test_generate_config_roundtrip() {
+        // A system changed imperatively since it was installed.
+        let mut current = sample_manifest();
+        current.system.hostname = "workstation".to_string();
+        current.system.timezone = "Europe/Berlin".to_string();
+        current.configuration.networking.mode = "dhcp".to_string();
+        current.configuration.networking.dns = vec!["9.9.9.9".into(), "1.1.1.1".into()];
+        current.configuration.graphics.enabled = true;
+        current.configuration.security.require_passwords = true;
+        current.configuration.logging.log_level = "debug".to_string();
+        current.configuration.logging.log_to_file = false;
+        current.configuration.power.power_action = "reboot".to_string();
+        current.packages.push(Package {
+            name: "fd".to_string(),
+            version: "9.0.0".to_string(),
+            store_path: "/nix/store/mno-fd-9.0.0".to_string(),
+        });
+        current.users.insert(
+            "admin".to_string(),
+            User {
+                uid: 1001,
+                gid: 1001,
+                home: "/home/admin".to_string(),
+                shell: "/bin/ion".to_string(),
+            },
+        );
+        let etc = BTreeMap::from([(
+            "motd".to_string(),
+            EtcFileConfig {
+                text: Some("Welcome to Redox".to_string()),
+                mode: Some("600".to_string()),
+                ..Default::default()
+            },
+        )]);
+        merge_etc_files(&mut current, &etc).unwrap();
+
+        let nix = generate_config_nix(&current);
+        assert!(!nix.contains("\"ion\""), "boot-essential packages are implied:\n{nix}");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("configuration.nix");
+        fs::write(&path, &nix).unwrap();
+        let config = evaluate_config(path.to_str().unwrap()).unwrap();
+        validate_config(&config).unwrap();
+
+        let index: BTreeMap<_, _> = current
+            .packages
+            .iter()
+            .map(|p| {
+                let entry = serde_json::json!({ "storePath": p.store_path, "version": p.version });
+                (p.name.clone(), entry)
+            })
+            .collect();
+        let resolved = resolve_packages_from_json(
+            config.packages.as_deref().unwrap(),
+            &serde_json::to_string(&index).unwrap(),
+        )
+        .unwrap();
+
+        // Rebuilding the freshly installed system gets back to `current`.
+        let merged = merge_config(&sample_manifest(), &config, &resolved).unwrap();
+
+        assert_eq!(merged.system.hostname, "workstation");
+        assert_eq!(merged.system.timezone, "Europe/Berlin");
+        assert_eq!(
+            serde_json::to_value(&merged.configuration).unwrap(),
+            serde_json::to_value(&current.configuration).unwrap()
+        );
+        let packages = |m: &Manifest| -> Vec<(String, String)> {
+            m.packages.iter().map(|p| (p.name.clone(), p.store_path.clone())).collect()
+        };
+        assert_eq!(packages(&merged), packages(&current));
+        assert_eq!(merged.users, current.users);
+        assert_eq!(merged.etc, current.etc);
+        assert_eq!(merged.files["etc/motd"].blake3, current.files["etc/motd"].blake3);
+        assert_eq!(merged.files["etc/motd"].mode, "600");
+    }
+
+    #[test]
+    fn test_nix_string_escapes() {
+        assert_eq!(nix_string("plain"), r#""plain""#);
+        assert_eq!(nix_string("say \"hi\"\n"), r#""say \"hi\"\n""#);
+        assert_eq!(nix_string(r"C:\dir"), r#""C:\\dir""#);
+        assert_eq!(nix_string("${HOME} costs $5"), r#""\${HOME} costs $5""#);
+        assert_eq!(nix_list(&[]), "[ ]");
+        assert_eq!(nix_list(&["a".into(), "b".into()]), r#"[ "a" "b" ]"#);
+    }
 }
@@ -200,6 +200,7 @@ impl SnixRedoxIO {
                     nar_size,
                     references: vec![],
                     deriver: None,
+                    output: None,
                     registration_time: pathinfo::current_timestamp(),
                     signatures: vec![],
                     files: vec![],
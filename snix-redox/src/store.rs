@@ -6,23 +6,40 @@
 //!   - Closure computation (transitive dependency graphs)
 //!   - GC roots (symlinks protecting paths from collection)
 //!   - Garbage collection (mark-and-sweep)
+//!   - Disk usage attribution per GC root
+//!   - Optimisation (hardlinking identical files)
+//!   - Export/import in the `nix-store --export` format
+//!   - Adding local files and directories, like `nix-store --add`
+//!   - Rebuilding lost path metadata from the store contents
+//!   - Signing paths with a binary cache key
 //!
 //! Layout:
 //! ```text
 //! /nix/store/              — store paths (the data)
 //! /nix/var/snix/
 //!   pathinfo/{hash}.json   — per-path metadata
-//!   gcroots/               — symlinks to live roots
+//!   gcroots/               — symlinks to live roots, or to symlinks
+//!                            pointing at them (indirect roots)
+//!   links/                 — canonical copies for `snix store optimise`
 //! ```
 
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead, Read, Write};
 use std::path::{Path, PathBuf};
 
-use nix_compat::store_path::{StorePath, STORE_DIR};
+use nix_compat::narinfo::{self, NarInfo};
+use nix_compat::nixbase32;
+use nix_compat::nixhash::{CAHash, NixHash};
+use nix_compat::store_path::{build_ca_path, StorePath, STORE_DIR};
+use sha2::{Digest, Sha256};
 
-use crate::pathinfo::{self, PathInfo, PathInfoDb, PathInfoError, SNIX_VAR_DIR};
+use crate::local_build;
+use crate::nar;
+use crate::nix_daemon::{read_string, read_strings, read_u64, write_bytes, write_strings, write_u64};
+use crate::pathinfo::{self, PathInfo, PathInfoDb, PathInfoError};
+use crate::store_root;
 
 // ===== Closure Computation =====
 
@@ -37,40 +54,177 @@ pub struct Closure {
 
 /// Compute the transitive closure of a store path via BFS over references.
 ///
+/// Each BFS level is fetched with a single `PathInfoDb::get_many` call.
 /// Returns an error if the path (or any of its references) is not registered.
 pub fn compute_closure(
     db: &PathInfoDb,
     root: &str,
 ) -> Result<Closure, Box<dyn std::error::Error>> {
     let mut visited = BTreeSet::new();
-    let mut queue = VecDeque::new();
+    let mut frontier = BTreeSet::from([root.to_string()]);
     let mut total_nar_size: u64 = 0;
 
+    while !frontier.is_empty() {
+        let batch: Vec<&str> = frontier.iter().map(String::as_str).collect();
+        let infos = db.get_many(&batch)?;
+
+        let mut next = BTreeSet::new();
+        for path in &frontier {
+            let info = infos
+                .get(path)
+                .ok_or_else(|| format!("path not registered: {path}"))?;
+            total_nar_size += info.nar_size;
+            next.extend(info.references.iter().cloned());
+        }
+
+        visited.extend(std::mem::take(&mut frontier));
+        frontier = next.into_iter().filter(|r| !visited.contains(r)).collect();
+    }
+
+    Ok(Closure {
+        paths: visited,
+        total_nar_size,
+    })
+}
+
+/// Find the shortest reference chain from `root` to `target`.
+///
+/// BFS over references, so the first chain found is a shortest one; ties
+/// are broken by the order references are listed in each PathInfo.
+/// Returns `None` if `target` isn't in the closure of `root`, and an
+/// error if a path that has to be traversed is not registered.
+pub fn why_depends(
+    db: &PathInfoDb,
+    root: &str,
+    target: &str,
+) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
+    // Each visited path maps to the path it was first reached from.
+    let mut parent: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut queue = VecDeque::new();
+
+    parent.insert(root.to_string(), None);
     queue.push_back(root.to_string());
 
     while let Some(path) = queue.pop_front() {
-        if visited.contains(&path) {
-            continue;
+        if path == target {
+            let mut chain = vec![path];
+            while let Some(Some(prev)) = parent.get(chain.last().unwrap()) {
+                chain.push(prev.clone());
+            }
+            chain.reverse();
+            return Ok(Some(chain));
         }
 
         let info = db
             .get(&path)?
             .ok_or_else(|| format!("path not registered: {path}"))?;
 
-        total_nar_size += info.nar_size;
-        visited.insert(path);
-
         for r in &info.references {
-            if !visited.contains(r) {
+            if !parent.contains_key(r) {
+                parent.insert(r.clone(), Some(path.clone()));
                 queue.push_back(r.clone());
             }
         }
     }
 
-    Ok(Closure {
-        paths: visited,
-        total_nar_size,
-    })
+    Ok(None)
+}
+
+/// Render the reference graph of `root`'s closure as Graphviz DOT.
+///
+/// One node per path in the closure, labeled with its package name and
+/// version (derivations drawn as boxes), and one edge per reference. Self-references become
+/// self-loops. Nodes and edges are emitted in sorted order.
+pub fn closure_dot(
+    db: &PathInfoDb,
+    root: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let closure = compute_closure(db, root)?;
+
+    let mut out = String::from("digraph closure {\n");
+    for path in &closure.paths {
+        out.push_str(&format!("  \"{path}\" [{}];\n", dot_node_attrs(path)));
+    }
+    for path in &closure.paths {
+        let info = db
+            .get(path)?
+            .ok_or_else(|| format!("path not registered: {path}"))?;
+        let refs: BTreeSet<&String> = info.references.iter().collect();
+        for r in refs {
+            out.push_str(&format!("  \"{path}\" -> \"{r}\";\n"));
+        }
+    }
+    out.push_str("}\n");
+
+    Ok(out)
+}
+
+/// Find reference cycles among all registered paths.
+///
+/// DFS over references with an explicit recursion stack; every reference
+/// back to a path still on the stack closes a cycle, reported as the
+/// stack from that path onwards (each path references the next, the
+/// last references the first). Self-references are length-1 cycles.
+/// References to unregistered paths are ignored.
+pub fn find_cycles(db: &PathInfoDb) -> Result<Vec<Vec<String>>, Box<dyn std::error::Error>> {
+    let graph: BTreeMap<String, BTreeSet<String>> = db
+        .list_infos()?
+        .into_iter()
+        .map(|info| (info.store_path, info.references.into_iter().collect()))
+        .collect();
+
+    // Paths absent from `done` and `on_stack` are unvisited.
+    let mut done: BTreeSet<&str> = BTreeSet::new();
+    let mut on_stack: BTreeSet<&str> = BTreeSet::new();
+    let mut cycles = Vec::new();
+
+    for start in graph.keys() {
+        if done.contains(start.as_str()) {
+            continue;
+        }
+
+        // (path, references of it not yet followed)
+        let mut stack = vec![(start.as_str(), graph[start].iter())];
+        on_stack.insert(start);
+
+        while let Some((path, refs)) = stack.last_mut() {
+            let path = *path;
+            let Some(r) = refs.next() else {
+                on_stack.remove(path);
+                done.insert(path);
+                stack.pop();
+                continue;
+            };
+
+            if on_stack.contains(r.as_str()) {
+                let from = stack.iter().position(|(p, _)| *p == r.as_str()).unwrap();
+                cycles.push(stack[from..].iter().map(|(p, _)| p.to_string()).collect());
+            } else if !done.contains(r.as_str()) {
+                if let Some(next) = graph.get(r) {
+                    on_stack.insert(r);
+                    stack.push((r.as_str(), next.iter()));
+                }
+            }
+        }
+    }
+
+    Ok(cycles)
+}
+
+/// `/nix/store/<hash>-hello-1.0` → `label="hello\n1.0"`.
+fn dot_node_attrs(path: &str) -> String {
+    let Ok(sp) = StorePath::<String>::from_absolute_path(path.as_bytes()) else {
+        return format!("label=\"{path}\"");
+    };
+    let label = match sp.name_version() {
+        (name, Some(version)) => format!("{name}\\n{version}"),
+        (name, None) => name.to_string(),
+    };
+    if sp.is_derivation() {
+        format!("label=\"{label}\", shape=box")
+    } else {
+        format!("label=\"{label}\"")
+    }
 }
 
 // ===== GC Roots =====
@@ -87,12 +241,15 @@ pub struct GcRoot {
     pub name: String,
     /// Target store path the symlink points to
     pub target: String,
+    /// For indirect roots, the symlink outside the store (e.g. a `result`
+    /// link) that `target` was read from.
+    pub indirect: Option<String>,
 }
 
 impl GcRoots {
     /// Open (and create) the default GC roots directory.
     pub fn open() -> io::Result<Self> {
-        Self::open_at(Path::new(SNIX_VAR_DIR).join("gcroots"))
+        Self::open_at(store_root::var_dir().join("gcroots"))
     }
 
     /// Open at a custom path (for testing).
@@ -120,6 +277,31 @@ impl GcRoots {
         Ok(())
     }
 
+    /// Add an indirect GC root, like `nix build`'s `result` links.
+    ///
+    /// `name → link` is recorded instead of a store path, and `link` is
+    /// followed whenever roots are listed, so the root protects whatever
+    /// `link` points to at GC time. Once `link` is deleted the root
+    /// protects nothing.
+    pub fn add_indirect_root(
+        &self,
+        name: &str,
+        link: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let link = std::path::absolute(link)?;
+        let target = read_link_absolute(&link)
+            .map_err(|e| format!("{} is not a symlink: {e}", link.display()))?;
+        StorePath::<String>::from_absolute_path(target.to_string_lossy().as_bytes())
+            .map_err(|e| format!("{} does not point to a store path: {e}", link.display()))?;
+
+        let root = self.roots_dir.join(name);
+        if root.symlink_metadata().is_ok() {
+            fs::remove_file(&root)?;
+        }
+        std::os::unix::fs::symlink(&link, &root)?;
+        Ok(())
+    }
+
     /// Remove a GC root by name.
     pub fn remove_root(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
         let link = self.roots_dir.join(name);
@@ -132,15 +314,29 @@ impl GcRoots {
     }
 
     /// List all GC roots (name → target).
+    ///
+    /// Indirect roots are resolved to where their link points now; those
+    /// whose link is gone are left out. Relative links, at either level,
+    /// are followed from the directory they are in.
     pub fn list_roots(&self) -> Result<Vec<GcRoot>, Box<dyn std::error::Error>> {
         let mut roots = Vec::new();
         for entry in fs::read_dir(&self.roots_dir)? {
             let entry = entry?;
             let name = entry.file_name().to_string_lossy().to_string();
-            if let Ok(target) = fs::read_link(entry.path()) {
+            let Ok(target) = read_link_absolute(&entry.path()) else {
+                continue;
+            };
+            if target.starts_with(STORE_DIR) {
                 roots.push(GcRoot {
                     name,
                     target: target.to_string_lossy().to_string(),
+                    indirect: None,
+                });
+            } else if let Ok(store_path) = read_link_absolute(&target) {
+                roots.push(GcRoot {
+                    name,
+                    target: store_path.to_string_lossy().to_string(),
+                    indirect: Some(target.to_string_lossy().to_string()),
                 });
             }
         }
@@ -184,6 +380,100 @@ impl GcRoots {
     }
 }
 
+// ===== Disk Usage =====
+
+/// How much of the store a single GC root accounts for.
+#[derive(Debug)]
+pub struct RootUsage {
+    pub name: String,
+    pub target: String,
+    /// Number of paths in the root's closure.
+    pub closure_paths: usize,
+    /// NAR size of the whole closure, shared paths included.
+    pub closure_size: u64,
+    /// NAR size of paths no other root keeps alive — what removing this
+    /// root alone would free.
+    pub exclusive_size: u64,
+    /// Exclusive size plus an equal share of each path shared with other
+    /// roots. Summed over all roots this is the size of the live set.
+    pub attributed_size: u64,
+}
+
+/// Attribute the live set to GC roots, sorted by exclusive size (largest
+/// first).
+///
+/// A path kept alive by `n` roots counts `1/n` of its size towards each
+/// of them, so shared dependencies aren't counted more than once. Bytes
+/// that don't divide evenly go to the first roots listed.
+/// Dangling roots and roots whose closure is incomplete are skipped with a
+/// warning, as in [`GcRoots::compute_live_set`].
+pub fn disk_usage(
+    db: &PathInfoDb,
+    gc_roots: &GcRoots,
+) -> Result<Vec<RootUsage>, Box<dyn std::error::Error>> {
+    let mut closures = Vec::new();
+    for root in gc_roots.list_roots()? {
+        if !db.is_registered(&root.target) {
+            eprintln!(
+                "warning: GC root '{}' points to unregistered path: {}",
+                root.name, root.target
+            );
+            continue;
+        }
+        match compute_closure(db, &root.target) {
+            Ok(closure) => closures.push((root, closure)),
+            Err(e) => eprintln!(
+                "warning: cannot compute closure for root '{}': {e}",
+                root.name
+            ),
+        }
+    }
+
+    // How many roots keep each path alive.
+    let mut keepers: BTreeMap<&str, u64> = BTreeMap::new();
+    for (_, closure) in &closures {
+        for path in &closure.paths {
+            *keepers.entry(path.as_str()).or_default() += 1;
+        }
+    }
+
+    let live: Vec<&str> = keepers.keys().copied().collect();
+    let infos = db.get_many(&live)?;
+    let size_of = |path: &str| infos.get(path).map_or(0, |i| i.nar_size);
+
+    // How many shares of each path have been handed out so far.
+    let mut shares: BTreeMap<&str, u64> = BTreeMap::new();
+    let mut usage = Vec::with_capacity(closures.len());
+    for (root, closure) in &closures {
+        let mut exclusive_size = 0;
+        let mut attributed_size = 0;
+        for path in &closure.paths {
+            let (n, size) = (keepers[path.as_str()], size_of(path));
+            if n == 1 {
+                exclusive_size += size;
+            }
+            let handed = shares.entry(path.as_str()).or_default();
+            attributed_size += size / n + u64::from(*handed < size % n);
+            *handed += 1;
+        }
+        usage.push(RootUsage {
+            name: root.name.clone(),
+            target: root.target.clone(),
+            closure_paths: closure.paths.len(),
+            closure_size: closure.total_nar_size,
+            exclusive_size,
+            attributed_size,
+        });
+    }
+
+    usage.sort_by(|a, b| {
+        b.exclusive_size
+            .cmp(&a.exclusive_size)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(usage)
+}
+
 // ===== Garbage Collection =====
 
 /// Statistics from a GC run.
@@ -195,6 +485,38 @@ pub struct GcStats {
     pub bytes_freed: u64,
     /// Number of store paths kept (live).
     pub paths_kept: u32,
+    /// Number of dead paths left in place because of a [`GcLimits`] limit.
+    pub paths_remaining: u32,
+}
+
+/// Limits on a GC run. The default collects every dead path.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GcLimits {
+    /// Stop deleting once at least this many bytes are freed.
+    pub max_freed: Option<u64>,
+    /// Only collect paths registered before this time (seconds since the
+    /// epoch). Paths without a parseable registration time are kept.
+    pub registered_before: Option<u64>,
+}
+
+/// Registered store paths split by reachability from the GC roots.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GcPartition {
+    /// Paths in the closure of some GC root.
+    pub live: BTreeSet<String>,
+    /// Every other registered path.
+    pub dead: BTreeSet<String>,
+}
+
+/// Split the registered paths into the live and the dead set.
+pub fn partition_store(
+    db: &PathInfoDb,
+    gc_roots: &GcRoots,
+) -> Result<GcPartition, Box<dyn std::error::Error>> {
+    let all_paths = db.all_paths_set()?;
+    let live = gc_roots.compute_live_set(db)?;
+    let dead = all_paths.difference(&live).cloned().collect();
+    Ok(GcPartition { live, dead })
 }
 
 /// Run garbage collection.
@@ -203,18 +525,23 @@ pub struct GcStats {
 /// 1. Enumerate all registered store paths.
 /// 2. Compute the live set from GC roots.
 /// 3. Dead set = all − live.
-/// 4. Delete each dead path (store directory + pathinfo).
+/// 4. Check that nothing live (or rooted) references the dead set.
+/// 5. Delete dead paths oldest first (store directory + pathinfo),
+///    within `limits`.
+///
+/// Step 4 is a safety invariant: a live path referencing a dead one means
+/// the live set is wrong (e.g. a root's closure could not be computed),
+/// and sweeping would break the live path. GC refuses unless `force`.
 ///
 /// With `dry_run = true`, reports what *would* be deleted without removing anything.
 pub fn garbage_collect(
     db: &PathInfoDb,
     gc_roots: &GcRoots,
     dry_run: bool,
+    force: bool,
+    limits: &GcLimits,
 ) -> Result<GcStats, Box<dyn std::error::Error>> {
-    let all_paths = db.all_paths_set()?;
-    let live_set = gc_roots.compute_live_set(db)?;
-
-    let dead_set: BTreeSet<_> = all_paths.difference(&live_set).cloned().collect();
+    let GcPartition { live: live_set, dead: dead_set } = partition_store(db, gc_roots)?;
 
     let mut stats = GcStats {
         paths_kept: live_set.len() as u32,
@@ -225,21 +552,47 @@ pub fn garbage_collect(
         return Ok(stats);
     }
 
-    for path in &dead_set {
-        // Compute disk size before deletion
-        let size = path_size(Path::new(path)).unwrap_or(0);
+    let broken = find_references_into_dead(db, gc_roots, &live_set, &dead_set)?;
+    if !broken.is_empty() {
+        let edges: Vec<String> = broken
+            .iter()
+            .map(|(from, to)| format!("  {from} → {to}"))
+            .collect();
+        if !force {
+            return Err(format!(
+                "refusing to collect: {} reference(s) from live paths into the dead set:\n{}\n\
+                 Re-run with --force to delete anyway.",
+                broken.len(),
+                edges.join("\n")
+            )
+            .into());
+        }
+        eprintln!("warning: --force: deleting paths that are still referenced:");
+        for edge in &edges {
+            eprintln!("{edge}");
+        }
+    }
+
+    let dead_paths: Vec<&str> = dead_set.iter().map(String::as_str).collect();
+    let dead_infos = db.get_many(&dead_paths)?;
+    let plan = plan_collection(&dead_infos, limits, |path| {
+        path_size(&store_root::real_path(path)).unwrap_or(0)
+    });
+    stats.paths_remaining = (dead_set.len() - plan.len()) as u32;
 
+    for (path, size) in &plan {
+        let size = *size;
         if dry_run {
             let human = human_size(size);
             eprintln!("would delete: {path} ({human})");
         } else {
             // Remove from filesystem first
-            let p = Path::new(path);
+            let p = store_root::real_path(path);
             if p.exists() {
                 if p.is_dir() {
-                    fs::remove_dir_all(p)?;
+                    fs::remove_dir_all(&p)?;
                 } else {
-                    fs::remove_file(p)?;
+                    fs::remove_file(&p)?;
                 }
             }
             // Then remove metadata
@@ -253,181 +606,1177 @@ pub fn garbage_collect(
     Ok(stats)
 }
 
-// ===== Existing Store Functions (updated) =====
+/// Order dead paths for deletion and apply `limits`.
+///
+/// Paths go oldest first, but never before a dead path that references
+/// them, so stopping at `max_freed` can't leave a dangling reference. For
+/// the same reason, everything a kept dead path references is kept too.
+/// Paths that reference each other in a cycle are deleted together, as
+/// one unit as old as its oldest member.
+/// Returns `(path, size)` pairs, with sizes from `size_of`.
+fn plan_collection(
+    dead: &BTreeMap<String, PathInfo>,
+    limits: &GcLimits,
+    mut size_of: impl FnMut(&str) -> u64,
+) -> Vec<(String, u64)> {
+    let registered = |path: &str| pathinfo::parse_timestamp(&dead[path].registration_time);
+
+    let mut candidates: BTreeSet<&str> = dead
+        .keys()
+        .map(String::as_str)
+        .filter(|&path| match limits.registered_before {
+            Some(cutoff) => registered(path).is_some_and(|t| t < cutoff),
+            None => true,
+        })
+        .collect();
+
+    let mut kept: Vec<&str> = dead
+        .keys()
+        .map(String::as_str)
+        .filter(|path| !candidates.contains(path))
+        .collect();
+    while let Some(path) = kept.pop() {
+        for r in &dead[path].references {
+            if candidates.remove(r.as_str()) {
+                kept.push(r.as_str());
+            }
+        }
+    }
 
-/// Ensure the /nix/store directory exists.
-pub fn ensure_store_dir() -> io::Result<()> {
-    let store = Path::new(STORE_DIR);
-    if !store.exists() {
-        fs::create_dir_all(store)?;
-        eprintln!("created {STORE_DIR}");
+    let component = strongly_connected(dead, &candidates);
+    let mut members: Vec<Vec<&str>> = Vec::new();
+    for (&path, &c) in &component {
+        if c >= members.len() {
+            members.resize(c + 1, Vec::new());
+        }
+        members[c].push(path);
     }
-    Ok(())
-}
+    let references = |c: usize| {
+        members[c]
+            .iter()
+            .flat_map(|&path| &dead[path].references)
+            .filter_map(|r| component.get(r.as_str()).copied())
+            .filter(move |&rc| rc != c)
+    };
 
-/// Register a newly-fetched store path in the database.
-pub fn register_path(
-    db: &PathInfoDb,
-    store_path: &str,
-    nar_hash: &str,
-    nar_size: u64,
-    references: Vec<String>,
-    signatures: Vec<String>,
-) -> Result<(), PathInfoError> {
-    register_path_with_files(db, store_path, nar_hash, nar_size, references, signatures, Vec::new())
-}
+    // Number of references into each component from the other,
+    // not-yet-deleted ones.
+    let mut referrers = vec![0usize; members.len()];
+    for c in 0..members.len() {
+        for rc in references(c) {
+            referrers[rc] += 1;
+        }
+    }
 
-/// Register a store path with a file manifest.
-pub fn register_path_with_files(
-    db: &PathInfoDb,
-    store_path: &str,
-    nar_hash: &str,
-    nar_size: u64,
-    references: Vec<String>,
-    signatures: Vec<String>,
-    files: Vec<crate::nar::ManifestEntry>,
-) -> Result<(), PathInfoError> {
-    let info = PathInfo {
-        store_path: store_path.to_string(),
-        nar_hash: nar_hash.to_string(),
-        nar_size,
-        references,
-        deriver: None,
-        registration_time: pathinfo::current_timestamp(),
-        signatures,
-        files,
+    let oldest = |c: usize| {
+        let time = members[c].iter().map(|p| registered(p).unwrap_or(0)).min();
+        (time.unwrap_or(0), members[c][0], c)
     };
-    db.register(&info)
-}
-
-/// Verify the local store — check that all store paths are parseable.
-pub fn verify() -> Result<(), Box<dyn std::error::Error>> {
-    let store = Path::new(STORE_DIR);
+    let mut ready: BTreeSet<(u64, &str, usize)> =
+        (0..members.len()).filter(|&c| referrers[c] == 0).map(oldest).collect();
+
+    let mut plan = Vec::new();
+    let mut freed = 0u64;
+    while let Some((_, _, c)) = ready.pop_first() {
+        if limits.max_freed.is_some_and(|max| freed >= max) {
+            break;
+        }
+        for &path in &members[c] {
+            let size = size_of(path);
+            freed += size;
+            plan.push((path.to_string(), size));
+        }
 
-    if !store.exists() {
-        eprintln!("no store at {STORE_DIR}");
-        return Ok(());
+        for rc in references(c) {
+            referrers[rc] -= 1;
+            if referrers[rc] == 0 {
+                ready.insert(oldest(rc));
+            }
+        }
     }
 
-    let mut count = 0;
-    let mut errors = 0;
+    plan
+}
 
-    for entry in fs::read_dir(store)? {
-        let entry = entry?;
-        let name = entry.file_name();
-        let name_str = name.to_string_lossy();
+/// Group `nodes` into strongly connected components of the reference
+/// graph restricted to them (Tarjan's algorithm, without recursion, as
+/// closures can be deep). Returns each node's component number.
+fn strongly_connected<'a>(
+    infos: &'a BTreeMap<String, PathInfo>,
+    nodes: &BTreeSet<&'a str>,
+) -> BTreeMap<&'a str, usize> {
+    // Visit order and lowest visit order reachable, per visited node.
+    let mut order: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut component: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut components = 0;
+
+    for &start in nodes {
+        if order.contains_key(start) {
+            continue;
+        }
+        // (node, index of the next reference to follow)
+        let mut work = vec![(start, 0)];
+        while let Some(&(node, next)) = work.last() {
+            if next == 0 {
+                let n = order.len();
+                order.insert(node, (n, n));
+                stack.push(node);
+            }
+            if let Some(r) = infos[node].references.get(next) {
+                work.last_mut().unwrap().1 += 1;
+                let r = r.as_str();
+                if !nodes.contains(r) {
+                    continue;
+                }
+                match order.get(r) {
+                    None => work.push((r, 0)),
+                    Some(&(visited, _)) if !component.contains_key(r) => {
+                        let low = &mut order.get_mut(node).unwrap().1;
+                        *low = (*low).min(visited);
+                    }
+                    Some(_) => {}
+                }
+                continue;
+            }
 
-        let full_path = format!("{STORE_DIR}/{name_str}");
-        match StorePath::<String>::from_absolute_path(full_path.as_bytes()) {
-            Ok(_sp) => {
-                count += 1;
+            work.pop();
+            let (visited, low) = order[node];
+            if let Some(&(parent, _)) = work.last() {
+                let parent_low = &mut order.get_mut(parent).unwrap().1;
+                *parent_low = (*parent_low).min(low);
             }
-            Err(e) => {
-                errors += 1;
-                eprintln!("  invalid store path: {name_str}: {e}");
+            if low == visited {
+                while let Some(member) = stack.pop() {
+                    component.insert(member, components);
+                    if member == node {
+                        break;
+                    }
+                }
+                components += 1;
             }
         }
     }
 
-    println!("store: {count} paths, {errors} errors");
-    Ok(())
+    component
 }
 
-// ===== CLI Handlers =====
-
-/// `snix store list` — list all registered store paths with sizes.
-pub fn list_registered() -> Result<(), Box<dyn std::error::Error>> {
-    let db = PathInfoDb::open()?;
-    let paths = db.list_paths()?;
-
-    if paths.is_empty() {
-        println!("No registered store paths.");
-        println!("Hint: use 'snix fetch' to download packages from a binary cache.");
-        return Ok(());
+/// Find references that would dangle after deleting `dead`.
+///
+/// Returns `(from, to)` edges where `from` is a live path (or a GC root)
+/// and `to` is in the dead set.
+fn find_references_into_dead(
+    db: &PathInfoDb,
+    gc_roots: &GcRoots,
+    live: &BTreeSet<String>,
+    dead: &BTreeSet<String>,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let mut edges = Vec::new();
+
+    // A root whose closure couldn't be computed leaves its target dead.
+    for root in gc_roots.list_roots()? {
+        if dead.contains(&root.target) {
+            edges.push((format!("gc root '{}'", root.name), root.target));
+        }
     }
 
-    let mut total_nar: u64 = 0;
-    let mut total_disk: u64 = 0;
+    for path in live {
+        if let Some(info) = db.get(path)? {
+            for r in &info.references {
+                if dead.contains(r) {
+                    edges.push((path.clone(), r.clone()));
+                }
+            }
+        }
+    }
 
-    for path in &paths {
-        let nar_size = db
-            .get(path)?
-            .map(|i| i.nar_size)
-            .unwrap_or(0);
-        let disk_size = path_size(Path::new(path)).unwrap_or(0);
+    Ok(edges)
+}
 
-        total_nar += nar_size;
-        total_disk += disk_size;
+// ===== Export / Import =====
 
-        let refs = db
-            .get(path)?
-            .map(|i| i.references.len())
-            .unwrap_or(0);
+/// Marker between a path's NAR and its metadata in an export stream ("NIXE").
+const EXPORT_MAGIC: u64 = 0x4558_494e;
 
-        println!(
-            "{path}  (NAR {}, disk {}, {} refs)",
-            human_size(nar_size),
-            human_size(disk_size),
-            refs,
-        );
+/// Write `paths` to `w` in the `nix-store --export` format.
+///
+/// Each path is framed as `1`, its NAR, [`EXPORT_MAGIC`], the path, its
+/// references, its deriver (empty if unknown) and `0` (no legacy
+/// signature); a final `0` ends the stream. Integers are little-endian
+/// u64s and strings are length-prefixed and padded to 8 bytes, as on the
+/// daemon wire. Contents are read from `store_dir`.
+pub fn export_paths<W: Write>(
+    db: &PathInfoDb,
+    store_dir: &Path,
+    paths: &[String],
+    w: &mut W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for path in paths {
+        let info = db.get(path)?.ok_or_else(|| format!("{path} is not registered"))?;
+
+        write_u64(w, 1)?;
+        nar::dump(&store_dir.join(store_path_name(path)?), &mut *w)?;
+        write_u64(w, EXPORT_MAGIC)?;
+        write_bytes(w, path.as_bytes())?;
+        write_strings(w, &info.references)?;
+        write_bytes(w, info.deriver.as_deref().unwrap_or("").as_bytes())?;
+        write_u64(w, 0)?;
     }
-
-    println!();
-    println!(
-        "{} paths, NAR total {}, disk total {}",
-        paths.len(),
-        human_size(total_nar),
-        human_size(total_disk),
-    );
-
+    write_u64(w, 0)?;
+    w.flush()?;
     Ok(())
 }
 
-/// `snix store info PATH` — show metadata for a single path.
-pub fn show_info(store_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let db = PathInfoDb::open()?;
-    let info = db
-        .get(store_path)?
-        .ok_or_else(|| format!("path not registered: {store_path}"))?;
+/// Read a `nix-store --export` stream, extracting each path into
+/// `store_dir` and registering it in `db`.
+///
+/// Paths that are already registered are skipped, as in Nix. Returns the
+/// paths in stream order, skipped ones included.
+pub fn import_paths<R: BufRead + Send>(
+    db: &PathInfoDb,
+    store_dir: &Path,
+    r: &mut R,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut paths = Vec::new();
+    loop {
+        match read_u64(r)? {
+            0 => break,
+            1 => {}
+            n => return Err(format!("invalid export stream: unexpected marker {n}").into()),
+        }
 
-    println!("StorePath:    {}", info.store_path);
-    println!("NarHash:      {}", info.nar_hash);
-    println!("NarSize:      {} ({})", info.nar_size, human_size(info.nar_size));
-    println!("Registered:   {}", info.registration_time);
-    if let Some(ref drv) = info.deriver {
-        println!("Deriver:      {drv}");
-    }
-    if !info.signatures.is_empty() {
-        println!("Signatures:");
-        for sig in &info.signatures {
-            println!("  {sig}");
+        // The path name only follows the NAR, so extract under a temporary name.
+        let tmp = store_dir.join(format!(".snix-import-{}", std::process::id()));
+        remove_store_path(&tmp)?;
+        let result = import_one(db, store_dir, &tmp, r);
+        if result.is_err() {
+            let _ = remove_store_path(&tmp);
         }
+        paths.push(result?);
     }
-    println!("References:   {}", info.references.len());
-    for r in &info.references {
-        let marker = if r == &info.store_path { " (self)" } else { "" };
-        println!("  {r}{marker}");
+    Ok(paths)
+}
+
+fn import_one<R: BufRead + Send>(
+    db: &PathInfoDb,
+    store_dir: &Path,
+    tmp: &Path,
+    r: &mut R,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let tmp_str = tmp.to_str().ok_or("non-UTF-8 store directory")?;
+    let mut nar_reader = ConsumedHasher::new(&mut *r);
+    let files = nar::extract_with_manifest(&mut nar_reader, tmp_str)?;
+    let (nar_hash, nar_size) = nar_reader.finish();
+
+    if read_u64(r)? != EXPORT_MAGIC {
+        return Err("invalid export stream: missing NIXE magic after NAR".into());
+    }
+    let path = read_string(r)?;
+    StorePath::<String>::from_absolute_path(path.as_bytes())
+        .map_err(|e| format!("invalid export stream: {path}: {e}"))?;
+    let references = read_strings(r)?;
+    let deriver = read_string(r)?;
+    if read_u64(r)? != 0 {
+        // Legacy (pre-narinfo) signature; nothing checks these any more.
+        read_string(r)?;
     }
 
-    // Disk usage
-    let disk = path_size(Path::new(&info.store_path)).unwrap_or(0);
-    println!("Disk:         {}", human_size(disk));
+    if db.is_registered(&path) {
+        remove_store_path(tmp)?;
+        return Ok(path);
+    }
 
-    Ok(())
+    let dest = store_dir.join(store_path_name(&path)?);
+    remove_store_path(&dest)?;
+    fs::rename(tmp, &dest)?;
+
+    db.register(&PathInfo {
+        store_path: path.clone(),
+        nar_hash,
+        nar_size,
+        references,
+        deriver: (!deriver.is_empty()).then_some(deriver),
+        output: None,
+        registration_time: pathinfo::current_timestamp(),
+        signatures: vec![],
+        files,
+    })?;
+    Ok(path)
 }
 
-/// `snix store closure PATH` — show the transitive closure.
-pub fn show_closure(store_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let db = PathInfoDb::open()?;
-    let closure = compute_closure(&db, store_path)?;
+/// `/nix/store/abc…-hello` → `abc…-hello`.
+fn store_path_name(path: &str) -> Result<&str, Box<dyn std::error::Error>> {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("invalid store path: {path}").into())
+}
 
-    for path in &closure.paths {
-        let size = db.get(path)?.map(|i| i.nar_size).unwrap_or(0);
-        println!("{path}  ({})", human_size(size));
+/// Remove a file, symlink or directory tree if it exists.
+fn remove_store_path(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
     }
+}
 
-    println!();
-    println!(
+/// `BufRead` adapter that hashes the bytes its consumer actually takes.
+///
+/// The NAR reader stops at the end of the archive, so this sees exactly
+/// the NAR; whatever the inner reader buffered past it stays there for
+/// the export trailer.
+struct ConsumedHasher<R> {
+    inner: R,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<R: BufRead> ConsumedHasher<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, hasher: Sha256::new(), len: 0 }
+    }
+
+    /// Hex SHA-256 and length of everything consumed.
+    fn finish(self) -> (String, u64) {
+        (data_encoding::HEXLOWER.encode(&self.hasher.finalize()), self.len)
+    }
+}
+
+impl<R: BufRead> Read for ConsumedHasher<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for ConsumedHasher<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt > 0 {
+            // Already buffered by the caller's fill_buf, so this does no I/O.
+            if let Ok(buf) = self.inner.fill_buf() {
+                self.hasher.update(&buf[..amt]);
+            }
+            self.len += amt as u64;
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// The closures of `roots`, each path after everything it references.
+fn closure_in_dependency_order(
+    db: &PathInfoDb,
+    roots: &[String],
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    fn visit(
+        db: &PathInfoDb,
+        path: &str,
+        seen: &mut BTreeSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !seen.insert(path.to_string()) {
+            return Ok(());
+        }
+        let info = db.get(path)?.ok_or_else(|| format!("{path} is not registered"))?;
+        for r in info.references.iter().filter(|r| *r != path) {
+            visit(db, r, seen, order)?;
+        }
+        order.push(path.to_string());
+        Ok(())
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut order = Vec::new();
+    for root in roots {
+        visit(db, root, &mut seen, &mut order)?;
+    }
+    Ok(order)
+}
+
+// ===== Add =====
+
+/// Add the file, directory or symlink at `src` to `store_dir` as `name`,
+/// like `nix-store --add`.
+///
+/// The store path is content-addressed from the NAR of `src`, so adding
+/// the same content again returns the same, already registered path. The
+/// copy is made by extracting that NAR, which leaves files read-only and
+/// has no references.
+pub fn add_path(
+    db: &PathInfoDb,
+    store_dir: &Path,
+    src: &Path,
+    name: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut nar = Vec::new();
+    nar::dump(src, &mut nar)?;
+    let nar_sha256: [u8; 32] = Sha256::digest(&nar).into();
+
+    let ca_hash = CAHash::Nar(NixHash::Sha256(nar_sha256));
+    let store_path: StorePath<String> = build_ca_path(name, &ca_hash, Vec::<&str>::new(), false)
+        .map_err(|e| format!("{}: {e}", src.display()))?;
+    let path = store_path.to_absolute_path();
+    if db.is_registered(&path) {
+        return Ok(path);
+    }
+
+    let tmp = store_dir.join(format!(".snix-add-{}", std::process::id()));
+    remove_store_path(&tmp)?;
+    let tmp_str = tmp.to_str().ok_or("non-UTF-8 store directory")?;
+    let files = match nar::extract_with_manifest(&mut &nar[..], tmp_str) {
+        Ok(files) => files,
+        Err(e) => {
+            let _ = remove_store_path(&tmp);
+            return Err(e.into());
+        }
+    };
+
+    let dest = store_dir.join(store_path.to_string());
+    remove_store_path(&dest)?;
+    fs::rename(&tmp, &dest)?;
+
+    register_path_with_files(
+        db,
+        &path,
+        &data_encoding::HEXLOWER.encode(&nar_sha256),
+        nar.len() as u64,
+        vec![],
+        vec![],
+        files,
+    )?;
+    Ok(path)
+}
+
+// ===== Repair =====
+
+/// Rebuild the PathInfo of every path in `store_dir` from what is on disk,
+/// for when the database was lost or corrupted but the store survived.
+///
+/// Each path is serialized to a NAR again for its hash and size, and its
+/// contents are scanned for the hash parts of the other paths in the
+/// store, the same way references are found after a local build. Derivers
+/// and signatures can't be recovered and are left empty. Existing entries
+/// are overwritten. Returns the repaired paths, sorted.
+pub fn repair_db(
+    db: &PathInfoDb,
+    store_dir: &Path,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut candidates = HashMap::new();
+    for entry in fs::read_dir(store_dir)? {
+        let name = entry?.file_name();
+        let path = format!("{STORE_DIR}/{}", name.to_string_lossy());
+        // Skips in-progress `.snix-add-*` copies and other non-store entries.
+        if let Ok(sp) = StorePath::<String>::from_absolute_path(path.as_bytes()) {
+            candidates.insert(nixbase32::encode(sp.digest()), path);
+        }
+    }
+
+    let mut repaired: Vec<String> = candidates.values().cloned().collect();
+    repaired.sort();
+    for path in &repaired {
+        let on_disk = store_dir.join(store_path_name(path)?);
+        let (nar_hash, nar_size) = local_build::nar_hash_path(&on_disk)
+            .map_err(|e| format!("{}: {e}", on_disk.display()))?;
+        let references = local_build::scan_references(&on_disk, &candidates)
+            .map_err(|e| format!("{}: {e}", on_disk.display()))?;
+        register_path(
+            db,
+            path,
+            &nar_hash,
+            nar_size,
+            references.into_iter().collect(),
+            vec![],
+            None,
+        )?;
+    }
+    Ok(repaired)
+}
+
+// ===== Signing =====
+
+/// Sign registered `paths` with `secret_key` (`name:base64`, as produced
+/// by `nix-store --generate-binary-cache-key`), the way a binary cache
+/// signs its narinfo files. Each signature replaces an earlier one by the
+/// same key. Returns how many paths got a new signature.
+pub fn sign_paths(
+    db: &PathInfoDb,
+    paths: &[String],
+    secret_key: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let (key, _) =
+        narinfo::parse_keypair(secret_key).map_err(|e| format!("invalid secret key: {e}"))?;
+
+    let mut signed = 0;
+    for path in paths {
+        let info = db
+            .get(path)?
+            .ok_or_else(|| format!("path not registered: {path}"))?;
+        let mut narinfo = info.to_narinfo()?;
+        narinfo.add_signature(&key);
+        let signature = narinfo.signatures[0].to_string();
+        if db.add_signature(path, &signature)? {
+            signed += 1;
+        }
+    }
+    Ok(signed)
+}
+
+/// Why a path failed [verify_closure].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureProblem {
+    /// The path has no signatures at all.
+    Unsigned,
+    /// None of the path's signatures verifies against a trusted key.
+    Unverifiable,
+}
+
+impl fmt::Display for SignatureProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureProblem::Unsigned => f.write_str("unsigned"),
+            SignatureProblem::Unverifiable => f.write_str("no signature by a trusted key"),
+        }
+    }
+}
+
+/// Check that every path in the closure of `root` carries at least one
+/// signature that verifies against one of `trusted_keys` (`name:base64`
+/// public keys). Returns the paths that do not, sorted.
+pub fn verify_closure(
+    db: &PathInfoDb,
+    root: &str,
+    trusted_keys: &[String],
+) -> Result<Vec<(String, SignatureProblem)>, Box<dyn std::error::Error>> {
+    let keys = trusted_keys
+        .iter()
+        .map(|key| {
+            narinfo::VerifyingKey::parse(key)
+                .map_err(|e| format!("invalid public key '{key}': {e}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut failures = Vec::new();
+    for path in compute_closure(db, root)?.paths {
+        let info = db
+            .get(&path)?
+            .ok_or_else(|| format!("path not registered: {path}"))?;
+        if info.signatures.is_empty() {
+            failures.push((path, SignatureProblem::Unsigned));
+            continue;
+        }
+
+        // A path whose NAR hash can't be fingerprinted can't be verified.
+        let verified = info.fingerprint().is_ok_and(|fingerprint| {
+            info.signatures
+                .iter()
+                .filter_map(|sig| narinfo::SignatureRef::parse(sig).ok())
+                .any(|sig| keys.iter().any(|key| key.verify(&fingerprint, &sig)))
+        });
+        if !verified {
+            failures.push((path, SignatureProblem::Unverifiable));
+        }
+    }
+    Ok(failures)
+}
+
+// ===== Existing Store Functions (updated) =====
+
+/// Ensure the /nix/store directory (under the [store_root]) exists.
+pub fn ensure_store_dir() -> io::Result<()> {
+    let store = store_root::store_dir();
+    if !store.exists() {
+        fs::create_dir_all(&store)?;
+        eprintln!("created {}", store.display());
+    }
+    Ok(())
+}
+
+/// The derivation output a store path was built as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deriver {
+    /// The `.drv` store path.
+    pub drv_path: String,
+    /// The output name, e.g. `out`, if known.
+    pub output: Option<String>,
+}
+
+/// Register a newly-fetched store path in the database, with the
+/// derivation that built it if that is known.
+pub fn register_path(
+    db: &PathInfoDb,
+    store_path: &str,
+    nar_hash: &str,
+    nar_size: u64,
+    references: Vec<String>,
+    signatures: Vec<String>,
+    deriver: Option<Deriver>,
+) -> Result<(), PathInfoError> {
+    let (deriver, output) = match deriver {
+        Some(d) => (Some(d.drv_path), d.output),
+        None => (None, None),
+    };
+    db.register(&PathInfo {
+        store_path: store_path.to_string(),
+        nar_hash: nar_hash.to_string(),
+        nar_size,
+        references,
+        deriver,
+        output,
+        registration_time: pathinfo::current_timestamp(),
+        signatures,
+        files: Vec::new(),
+    })
+}
+
+/// Register a store path with a file manifest. Such paths (`snix store
+/// add`) are not built by any derivation.
+pub fn register_path_with_files(
+    db: &PathInfoDb,
+    store_path: &str,
+    nar_hash: &str,
+    nar_size: u64,
+    references: Vec<String>,
+    signatures: Vec<String>,
+    files: Vec<crate::nar::ManifestEntry>,
+) -> Result<(), PathInfoError> {
+    let info = PathInfo {
+        store_path: store_path.to_string(),
+        nar_hash: nar_hash.to_string(),
+        nar_size,
+        references,
+        deriver: None,
+        output: None,
+        registration_time: pathinfo::current_timestamp(),
+        signatures,
+        files,
+    };
+    db.register(&info)
+}
+
+/// Register a store path fetched from a binary cache, with the hash,
+/// references, signatures and deriver from its `narinfo`.
+///
+/// A narinfo doesn't say which output the path is; that is read from the
+/// deriver if the `.drv` itself is in the store.
+pub fn register_narinfo(
+    db: &PathInfoDb,
+    store_path: &str,
+    narinfo: &NarInfo<'_>,
+    files: Vec<crate::nar::ManifestEntry>,
+) -> Result<(), PathInfoError> {
+    // The narinfo parser strips `.drv` from the deriver's name.
+    let deriver = narinfo
+        .deriver
+        .as_ref()
+        .map(|drv| format!("{}.drv", drv.to_absolute_path()));
+    let output = deriver.as_deref().and_then(|drv| output_name(drv, store_path));
+    let info = PathInfo {
+        store_path: store_path.to_string(),
+        nar_hash: data_encoding::HEXLOWER.encode(&narinfo.nar_hash),
+        nar_size: narinfo.nar_size,
+        references: narinfo.references.iter().map(|r| r.to_absolute_path()).collect(),
+        deriver,
+        output,
+        registration_time: pathinfo::current_timestamp(),
+        signatures: narinfo.signatures.iter().map(|s| s.to_string()).collect(),
+        files,
+    };
+    db.register(&info)
+}
+
+/// The name of the output of the derivation `drv_path` that is
+/// `store_path`, if the `.drv` is in the local store.
+fn output_name(drv_path: &str, store_path: &str) -> Option<String> {
+    let aterm = fs::read(store_root::real_path(drv_path)).ok()?;
+    let drv = nix_compat::derivation::Derivation::from_aterm_bytes(&aterm).ok()?;
+    drv.outputs
+        .iter()
+        .find(|(_, out)| {
+            out.path.as_ref().map(|p| p.to_absolute_path()).as_deref() == Some(store_path)
+        })
+        .map(|(name, _)| name.clone())
+}
+
+/// Verify the local store — check that all store paths are parseable, and
+/// flag reference cycles between distinct registered paths.
+pub fn verify() -> Result<(), Box<dyn std::error::Error>> {
+    let store = store_root::store_dir();
+
+    if !store.exists() {
+        eprintln!("no store at {}", store.display());
+        return Ok(());
+    }
+
+    let mut count = 0;
+    let mut errors = 0;
+
+    for entry in fs::read_dir(&store)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name_str = name.to_string_lossy();
+
+        let full_path = format!("{STORE_DIR}/{name_str}");
+        match StorePath::<String>::from_absolute_path(full_path.as_bytes()) {
+            Ok(_sp) => {
+                count += 1;
+            }
+            Err(e) => {
+                errors += 1;
+                eprintln!("  invalid store path: {name_str}: {e}");
+            }
+        }
+    }
+
+    // Self-references are normal; only cycles through other paths are suspect.
+    let db = PathInfoDb::open()?;
+    let cycles: Vec<_> = find_cycles(&db)?
+        .into_iter()
+        .filter(|c| c.len() > 1)
+        .collect();
+    for cycle in &cycles {
+        eprintln!("  reference cycle: {} -> {}", cycle.join(" -> "), cycle[0]);
+    }
+
+    println!(
+        "store: {count} paths, {errors} errors, {} reference cycles",
+        cycles.len()
+    );
+    Ok(())
+}
+
+// ===== Optimise =====
+
+/// Statistics from a `snix store optimise` run.
+#[derive(Debug, Default)]
+pub struct OptimiseStats {
+    /// Files replaced by a hardlink to their canonical copy
+    pub files_linked: u64,
+    /// Disk space reclaimed by those replacements
+    pub bytes_saved: u64,
+    /// Files left alone because they changed or vanished mid-run
+    pub files_skipped: u64,
+    /// Canonical copies removed because no store file uses them any more
+    pub links_pruned: u64,
+}
+
+/// Deduplicate identical regular files under `paths` with hardlinks.
+///
+/// Each file is keyed by its BLAKE3 hash and permission bits, so an
+/// executable and a non-executable copy of the same bytes never share an
+/// inode. The first file seen for a key is hardlinked into `links_dir` as
+/// the canonical copy; later ones are replaced by a link to it. Files
+/// already sharing the canonical inode are skipped, so re-runs are cheap.
+///
+/// Store files keep their (read-only) mode since they end up hardlinked
+/// to a copy with the same permission bits. Parent directories are made
+/// writable only for the rename and restored afterwards.
+pub fn optimise(
+    paths: &[PathBuf],
+    links_dir: &Path,
+) -> Result<OptimiseStats, Box<dyn std::error::Error>> {
+    fs::create_dir_all(links_dir)?;
+    let mut stats = OptimiseStats::default();
+
+    for path in paths {
+        if path.symlink_metadata().is_ok() {
+            optimise_tree(path, links_dir, &mut stats)?;
+        }
+    }
+
+    // Canonical copies with a single link belong to collected paths
+    for entry in fs::read_dir(links_dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file() && std::os::unix::fs::MetadataExt::nlink(&meta) == 1 {
+            fs::remove_file(entry.path())?;
+            stats.links_pruned += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+fn optimise_tree(
+    path: &Path,
+    links_dir: &Path,
+    stats: &mut OptimiseStats,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let meta = path.symlink_metadata()?;
+    if meta.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            optimise_tree(&entry.path(), links_dir, stats)?;
+        }
+    } else if meta.is_file() {
+        if let Err(e) = optimise_file(path, &meta, links_dir, stats) {
+            eprintln!("warning: skipping {}: {e}", path.display());
+            stats.files_skipped += 1;
+        }
+    }
+    Ok(())
+}
+
+fn optimise_file(
+    path: &Path,
+    meta: &fs::Metadata,
+    links_dir: &Path,
+    stats: &mut OptimiseStats,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let hash = crate::system::hash_file(path)?;
+
+    // The hash only describes the file if nothing touched it meanwhile
+    let after = path.symlink_metadata()?;
+    if after.ino() != meta.ino() || after.len() != meta.len() || after.mtime() != meta.mtime() {
+        return Err("file changed while hashing".into());
+    }
+
+    let link = links_dir.join(format!("{hash}-{:o}", meta.mode() & 0o7777));
+    let canonical = match link.symlink_metadata() {
+        Ok(canonical) => canonical,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            // First copy of this content becomes the canonical one
+            fs::hard_link(path, &link)?;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if canonical.dev() == meta.dev() && canonical.ino() == meta.ino() {
+        return Ok(());
+    }
+    if canonical.len() != meta.len() {
+        return Err(format!("{} does not match its content hash", link.display()).into());
+    }
+
+    replace_with_link(path, &link)?;
+    stats.files_linked += 1;
+    // The last link to an inode frees its data
+    if meta.nlink() == 1 {
+        stats.bytes_saved += meta.len();
+    }
+    Ok(())
+}
+
+/// Atomically replace `path` with a hardlink to `link`.
+fn replace_with_link(path: &Path, link: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let parent = path
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no parent"))?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = parent.join(format!(".{file_name}.snix-link"));
+
+    let original = fs::metadata(parent)?.permissions();
+    let writable = fs::Permissions::from_mode(original.mode() | 0o200);
+    fs::set_permissions(parent, writable)?;
+
+    let result = fs::hard_link(link, &temp).and_then(|()| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    fs::set_permissions(parent, original)?;
+    result
+}
+
+// ===== Listing Contents =====
+
+/// What kind of file an [LsEntry] is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LsKind {
+    Directory,
+    Regular { executable: bool },
+    Symlink { target: String },
+}
+
+/// One file inside a store path, as `snix store ls` shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsEntry {
+    /// Path relative to the listed directory, or the file's own name when
+    /// a single file was listed.
+    pub name: String,
+    pub kind: LsKind,
+    /// Size in bytes; 0 for directories and symlinks, as in a NAR.
+    pub size: u64,
+}
+
+/// List `subpath` inside the store path extracted at `top`.
+///
+/// A directory lists its entries (all of them below it with `recursive`),
+/// sorted by name; anything else lists just itself. Symlinks are not
+/// followed. `subpath` may start with `/` but must not leave `top`.
+pub fn list_contents(
+    top: &Path,
+    subpath: &str,
+    recursive: bool,
+) -> Result<Vec<LsEntry>, Box<dyn std::error::Error>> {
+    let rel = Path::new(subpath.trim_start_matches('/'));
+    if rel
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir))
+    {
+        return Err(format!("invalid subpath: {subpath}").into());
+    }
+
+    let path = top.join(rel);
+    let meta = fs::symlink_metadata(&path).map_err(|e| format!("{subpath}: {e}"))?;
+    let mut entries = Vec::new();
+    if meta.is_dir() {
+        list_dir(&path, "", recursive, &mut entries)?;
+    } else {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        entries.push(ls_entry(&path, &meta, name)?);
+    }
+    Ok(entries)
+}
+
+fn list_dir(dir: &Path, prefix: &str, recursive: bool, out: &mut Vec<LsEntry>) -> io::Result<()> {
+    let mut children: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|e| e.file_name());
+    for child in children {
+        let path = child.path();
+        let meta = path.symlink_metadata()?;
+        let name = format!("{prefix}{}", child.file_name().to_string_lossy());
+        out.push(ls_entry(&path, &meta, name.clone())?);
+        if recursive && meta.is_dir() {
+            list_dir(&path, &format!("{name}/"), true, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn ls_entry(path: &Path, meta: &fs::Metadata, name: String) -> io::Result<LsEntry> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (kind, size) = if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)?.to_string_lossy().into_owned();
+        (LsKind::Symlink { target }, 0)
+    } else if meta.is_dir() {
+        (LsKind::Directory, 0)
+    } else {
+        let executable = meta.permissions().mode() & 0o111 != 0;
+        (LsKind::Regular { executable }, meta.len())
+    };
+    Ok(LsEntry { name, kind, size })
+}
+
+/// One line of `snix store ls` output.
+///
+/// Short lines mark directories with `/` and executables with `*`; long
+/// lines give the mode a NAR can express and the size instead. Symlinks
+/// show their target either way.
+pub fn format_ls_entry(entry: &LsEntry, long: bool) -> String {
+    let (mode, mark) = match &entry.kind {
+        LsKind::Directory => ("dr-xr-xr-x", "/"),
+        LsKind::Regular { executable: true } => ("-r-xr-xr-x", "*"),
+        LsKind::Regular { executable: false } => ("-r--r--r--", ""),
+        LsKind::Symlink { .. } => ("lrwxrwxrwx", ""),
+    };
+    let target = match &entry.kind {
+        LsKind::Symlink { target } => format!(" -> {target}"),
+        _ => String::new(),
+    };
+    if long {
+        format!("{mode} {:>12} {}{target}", entry.size, entry.name)
+    } else {
+        format!("{}{mark}{target}", entry.name)
+    }
+}
+
+// ===== CLI Handlers =====
+
+/// `snix store du` — show which GC roots account for store space.
+pub fn run_du() -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let gc_roots = GcRoots::open()?;
+    let usage = disk_usage(&db, &gc_roots)?;
+
+    if usage.is_empty() {
+        println!("No GC roots.");
+        println!("Hint: add one with 'snix store add-root NAME STORE_PATH'");
+        return Ok(());
+    }
+
+    println!(
+        "{:>10}  {:>10}  {:>10}  {:>5}  ROOT",
+        "EXCLUSIVE", "ATTRIBUTED", "CLOSURE", "PATHS"
+    );
+    let mut live_size = 0;
+    for root in &usage {
+        live_size += root.attributed_size;
+        println!(
+            "{:>10}  {:>10}  {:>10}  {:>5}  {} → {}",
+            human_size(root.exclusive_size),
+            human_size(root.attributed_size),
+            human_size(root.closure_size),
+            root.closure_paths,
+            root.name,
+            root.target,
+        );
+    }
+
+    println!();
+    println!(
+        "{} roots, live set NAR total {} (exclusive: freed by removing only that root)",
+        usage.len(),
+        human_size(live_size),
+    );
+    Ok(())
+}
+
+/// `snix store list` — list all registered store paths with sizes.
+pub fn list_registered() -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let listing = collect_listing(&db)?;
+
+    if listing.is_empty() {
+        println!("No registered store paths.");
+        println!("Hint: use 'snix fetch' to download packages from a binary cache.");
+        return Ok(());
+    }
+
+    let mut total_nar: u64 = 0;
+    let mut total_disk: u64 = 0;
+
+    for (info, disk_size) in &listing {
+        total_nar += info.nar_size;
+        total_disk += disk_size;
+
+        println!(
+            "{}  (NAR {}, disk {}, {} refs)",
+            info.store_path,
+            human_size(info.nar_size),
+            human_size(*disk_size),
+            info.references.len(),
+        );
+    }
+
+    println!();
+    println!(
+        "{} paths, NAR total {}, disk total {}",
+        listing.len(),
+        human_size(total_nar),
+        human_size(total_disk),
+    );
+
+    Ok(())
+}
+
+/// Gather every registered path with its disk size in a single pass.
+///
+/// Each PathInfo is read once, and disk sizes come from the memoized
+/// size cache instead of walking every store path on each call.
+fn collect_listing(db: &PathInfoDb) -> Result<Vec<(PathInfo, u64)>, PathInfoError> {
+    let mut sizes = db.disk_sizes();
+    let listing = db
+        .list_infos()?
+        .into_iter()
+        .map(|info| {
+            let disk_size = sizes.size_of(&info.store_path);
+            (info, disk_size)
+        })
+        .collect();
+    // The cache is an optimization — a read-only /nix/var is fine.
+    let _ = sizes.save();
+    Ok(listing)
+}
+
+/// `snix store info PATH` — show metadata for a single path.
+pub fn show_info(store_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let info = db
+        .get(store_path)?
+        .ok_or_else(|| format!("path not registered: {store_path}"))?;
+
+    print!("{}", format_info(&db, &info));
+    Ok(())
+}
+
+/// The `snix store info` report for `info`.
+pub fn format_info(db: &PathInfoDb, info: &PathInfo) -> String {
+    use std::fmt::Write as _;
+
+    let mut out = String::new();
+    let _ = writeln!(out, "StorePath:    {}", info.store_path);
+    let _ = writeln!(out, "NarHash:      {}", info.nar_hash);
+    let _ = writeln!(out, "NarSize:      {} ({})", info.nar_size, human_size(info.nar_size));
+    let _ = writeln!(out, "Registered:   {}", info.registration_time);
+    if let Some(ref drv) = info.deriver {
+        let _ = writeln!(out, "Deriver:      {drv}");
+    }
+    if let Some(ref output) = info.output {
+        let _ = writeln!(out, "Output:       {output}");
+    }
+    if !info.signatures.is_empty() {
+        let _ = writeln!(out, "Signatures:");
+        for sig in &info.signatures {
+            let _ = writeln!(out, "  {sig}");
+        }
+    }
+    let _ = writeln!(out, "References:   {}", info.references.len());
+    for r in &info.references {
+        let marker = if r == &info.store_path { " (self)" } else { "" };
+        let _ = writeln!(out, "  {r}{marker}");
+    }
+
+    // Disk usage
+    let disk = db.disk_size(&info.store_path);
+    let _ = writeln!(out, "Disk:         {}", human_size(disk));
+    out
+}
+
+/// `snix store ls PATH [SUBPATH]` — list the files of a registered path.
+pub fn run_ls(
+    store_path: &str,
+    subpath: Option<&str>,
+    recursive: bool,
+    long: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    if db.get(store_path)?.is_none() {
+        return Err(format!("path not registered: {store_path}").into());
+    }
+
+    let top = store_root::real_path(store_path);
+    let entries = list_contents(&top, subpath.unwrap_or(""), recursive)
+        .map_err(|e| format!("{store_path}: {e}"))?;
+    for entry in &entries {
+        println!("{}", format_ls_entry(entry, long));
+    }
+    Ok(())
+}
+
+/// `snix store closure PATH [--dot]` — show the transitive closure.
+pub fn show_closure(store_path: &str, dot: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    if dot {
+        print!("{}", closure_dot(&db, store_path)?);
+        return Ok(());
+    }
+
+    let closure = compute_closure(&db, store_path)?;
+
+    for path in &closure.paths {
+        let size = db.get(path)?.map(|i| i.nar_size).unwrap_or(0);
+        println!("{path}  ({})", human_size(size));
+    }
+
+    println!();
+    println!(
         "Closure: {} paths, {} total NAR size",
         closure.paths.len(),
         human_size(closure.total_nar_size),
@@ -436,8 +1785,58 @@ pub fn show_closure(store_path: &str) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
-/// `snix store gc [--dry-run]` — run garbage collection.
-pub fn run_gc(dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// `snix why-depends PATH DEP` — show why DEP is in the closure of PATH.
+pub fn show_why_depends(root: &str, target: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+
+    let Some(chain) = why_depends(&db, root, target)? else {
+        println!("{root} does not depend on {target}");
+        return Ok(());
+    };
+
+    for (depth, path) in chain.iter().enumerate() {
+        if depth == 0 {
+            println!("{path}");
+        } else {
+            println!("{}└── {path}", "    ".repeat(depth - 1));
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the dead set, or with `live` the live set, one path per line in
+/// sorted order, like `nix-store --gc --print-dead`/`--print-live`.
+pub fn write_gc_set(
+    out: &mut impl Write,
+    db: &PathInfoDb,
+    gc_roots: &GcRoots,
+    live: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let partition = partition_store(db, gc_roots)?;
+    let paths = if live { &partition.live } else { &partition.dead };
+    for path in paths {
+        writeln!(out, "{path}")?;
+    }
+    Ok(())
+}
+
+/// `snix store gc --print-dead` / `--print-live` — list what GC would
+/// delete or keep, without deleting anything.
+pub fn run_gc_print(live: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let gc_roots = GcRoots::open()?;
+    write_gc_set(&mut io::stdout().lock(), &db, &gc_roots, live)
+}
+
+/// `snix store gc [--dry-run] [--force] [--max-freed BYTES] [--max-age DAYS]`
+/// — run garbage collection.
+pub fn run_gc(
+    dry_run: bool,
+    force: bool,
+    max_freed: Option<u64>,
+    max_age_days: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let db = PathInfoDb::open()?;
     let gc_roots = GcRoots::open()?;
 
@@ -448,7 +1847,16 @@ pub fn run_gc(dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
         eprintln!();
     }
 
-    let stats = garbage_collect(&db, &gc_roots, dry_run)?;
+    let registered_before = max_age_days.map(|days| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        now.saturating_sub(days.saturating_mul(86400))
+    });
+    let limits = GcLimits { max_freed, registered_before };
+
+    let stats = garbage_collect(&db, &gc_roots, dry_run, force, &limits)?;
 
     if dry_run {
         println!();
@@ -469,6 +1877,167 @@ pub fn run_gc(dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
         println!("Nothing to collect. {} paths in use.", stats.paths_kept);
     }
 
+    if let Some(max) = max_freed {
+        println!(
+            "{} of {} requested.",
+            human_size(stats.bytes_freed),
+            human_size(max),
+        );
+    }
+    if stats.paths_remaining > 0 {
+        let limits: Vec<&str> = [
+            max_freed.map(|_| "--max-freed"),
+            max_age_days.map(|_| "--max-age"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        println!(
+            "{} dead paths left in place by {}.",
+            stats.paths_remaining,
+            limits.join(" and "),
+        );
+    }
+
+    Ok(())
+}
+
+/// `snix store export PATH...` — write the closures of `paths` to stdout.
+pub fn run_export(paths: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let order = closure_in_dependency_order(&db, paths)?;
+
+    let stdout = io::stdout();
+    if io::IsTerminal::is_terminal(&stdout) {
+        return Err("refusing to write an export stream to a terminal; redirect stdout".into());
+    }
+    export_paths(&db, &store_root::store_dir(), &order, &mut io::BufWriter::new(stdout.lock()))?;
+    eprintln!("exported {} paths", order.len());
+    Ok(())
+}
+
+/// `snix store import` — import a `nix-store --export` stream from stdin.
+pub fn run_import() -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    ensure_store_dir()?;
+
+    let mut reader = io::BufReader::new(io::stdin());
+    for path in import_paths(&db, &store_root::store_dir(), &mut reader)? {
+        println!("{path}");
+    }
+    Ok(())
+}
+
+/// `snix store add PATH` — add a local file or directory to the store.
+pub fn run_add(src: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let src = Path::new(src);
+    let name = fs::canonicalize(src)?
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| format!("{}: cannot derive a store path name", src.display()))?;
+
+    let db = PathInfoDb::open()?;
+    ensure_store_dir()?;
+    println!("{}", add_path(&db, &store_root::store_dir(), src, &name)?);
+    Ok(())
+}
+
+/// `snix store repair-db` — re-register every path in the store from disk.
+pub fn run_repair_db() -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let store = store_root::store_dir();
+    if !store.exists() {
+        eprintln!("no store at {}", store.display());
+        return Ok(());
+    }
+
+    eprintln!("rebuilding path info from {}...", store.display());
+    let repaired = repair_db(&db, &store)?;
+    println!(
+        "Repaired {} store paths (derivers and signatures are not recoverable).",
+        repaired.len()
+    );
+    Ok(())
+}
+
+/// `snix store compact` — fold the per-path JSON files of the path info
+/// database into its index, so opening it reads one file.
+pub fn run_compact() -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let folded = db.compact()?;
+    println!("Compacted {folded} path info files into the index.");
+    Ok(())
+}
+
+/// `snix store sign --key KEYFILE PATH...` — sign `paths`, or with
+/// `recursive` their closures, with the secret key in `key_file`.
+pub fn run_sign(
+    paths: &[String],
+    key_file: &str,
+    recursive: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let secret_key = fs::read_to_string(key_file).map_err(|e| format!("{key_file}: {e}"))?;
+    let db = PathInfoDb::open()?;
+    let paths = if recursive {
+        closure_in_dependency_order(&db, paths)?
+    } else {
+        paths.to_vec()
+    };
+
+    let signed = sign_paths(&db, &paths, secret_key.trim())?;
+    println!("Signed {signed} of {} paths.", paths.len());
+    Ok(())
+}
+
+/// `snix verify-closure PATH --trusted-key KEY...` — check that the whole
+/// closure of `path` is signed by one of `trusted_keys`.
+pub fn run_verify_closure(
+    path: &str,
+    trusted_keys: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let failures = verify_closure(&db, path, trusted_keys)?;
+    if failures.is_empty() {
+        println!("All paths in the closure of {path} are signed by a trusted key.");
+        return Ok(());
+    }
+
+    for (failed, problem) in &failures {
+        println!("{failed}: {problem}");
+    }
+    Err(format!("{} paths in the closure of {path} failed verification", failures.len()).into())
+}
+
+/// `snix store optimise` — hardlink identical files across store paths.
+pub fn run_optimise() -> Result<(), Box<dyn std::error::Error>> {
+    let db = PathInfoDb::open()?;
+    let paths: Vec<PathBuf> = db
+        .list_paths()?
+        .iter()
+        .map(|path| store_root::real_path(path))
+        .collect();
+    let links_dir = store_root::var_dir().join("links");
+
+    eprintln!("optimising {} store paths...", paths.len());
+    let stats = optimise(&paths, &links_dir)?;
+
+    if stats.files_skipped > 0 {
+        eprintln!("{} files skipped (changed during the run or unreadable).", stats.files_skipped);
+    }
+    if stats.links_pruned > 0 {
+        eprintln!("{} unused links removed from {}.", stats.links_pruned, links_dir.display());
+    }
+    if stats.files_linked > 0 {
+        println!(
+            "Saved {} by hardlinking {} files.",
+            human_size(stats.bytes_saved),
+            stats.files_linked,
+        );
+    } else {
+        println!("No duplicate files found.");
+    }
+
     Ok(())
 }
 
@@ -480,6 +2049,14 @@ pub fn add_root(name: &str, store_path: &str) -> Result<(), Box<dyn std::error::
     Ok(())
 }
 
+/// `snix store add-root --indirect NAME LINK`
+pub fn add_indirect_root(name: &str, link: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let gc_roots = GcRoots::open()?;
+    gc_roots.add_indirect_root(name, Path::new(link))?;
+    println!("Added indirect GC root: {name} → {link}");
+    Ok(())
+}
+
 /// `snix store remove-root NAME`
 pub fn remove_root(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let gc_roots = GcRoots::open()?;
@@ -500,9 +2077,12 @@ pub fn list_roots() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     for root in &roots {
-        let exists = Path::new(&root.target).exists();
+        let exists = store_root::real_path(&root.target).exists();
         let marker = if exists { "" } else { " (missing!)" };
-        println!("{} → {}{marker}", root.name, root.target);
+        match &root.indirect {
+            Some(link) => println!("{} → {link} → {}{marker}", root.name, root.target),
+            None => println!("{} → {}{marker}", root.name, root.target),
+        }
     }
 
     println!();
@@ -512,6 +2092,28 @@ pub fn list_roots() -> Result<(), Box<dyn std::error::Error>> {
 
 // ===== Helpers =====
 
+/// The target of the symlink at `link`. A relative target is resolved
+/// against the (canonical) directory holding the link, with `..` and `.`
+/// applied to the path rather than looked up, since it may name a store
+/// path that only exists under the store root.
+fn read_link_absolute(link: &Path) -> io::Result<PathBuf> {
+    let target = fs::read_link(link)?;
+    let Some(dir) = link.parent().filter(|_| target.is_relative()) else {
+        return Ok(target);
+    };
+    let mut resolved = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    for component in target.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            std::path::Component::CurDir => {}
+            other => resolved.push(other),
+        }
+    }
+    Ok(resolved)
+}
+
 /// Get the recursive size of a store path on disk.
 pub fn path_size(path: &Path) -> io::Result<u64> {
     if path.is_file() {
@@ -548,19 +2150,19 @@ fn human_size(bytes: u64) -> String {
 /// Check if a store path exists locally.
 #[allow(dead_code)]
 pub fn path_exists(store_path: &str) -> bool {
-    Path::new(store_path).exists()
+    store_root::real_path(store_path).exists()
 }
 
 /// List all store paths on the filesystem.
 #[allow(dead_code)]
 pub fn list_paths() -> io::Result<Vec<PathBuf>> {
-    let store = Path::new(STORE_DIR);
+    let store = store_root::store_dir();
     if !store.exists() {
         return Ok(Vec::new());
     }
 
     let mut paths = Vec::new();
-    for entry in fs::read_dir(store)? {
+    for entry in fs::read_dir(&store)? {
         let entry = entry?;
         paths.push(entry.path());
     }
@@ -598,17 +2200,21 @@ mod tests {
     }
 
     fn register(db: &PathInfoDb, path: &str, refs: Vec<&str>, size: u64) {
-        let info = PathInfo {
+        db.register(&info_at(path, refs, size, "2026-01-01T00:00:00Z")).unwrap();
+    }
+
+    fn info_at(path: &str, refs: Vec<&str>, size: u64, registered: &str) -> PathInfo {
+        PathInfo {
             store_path: path.to_string(),
             nar_hash: "deadbeef".to_string(),
             nar_size: size,
             references: refs.into_iter().map(String::from).collect(),
             deriver: None,
-            registration_time: "2026-01-01T00:00:00Z".to_string(),
+            output: None,
+            registration_time: registered.to_string(),
             signatures: vec![],
             files: vec![],
-        };
-        db.register(&info).unwrap();
+        }
     }
 
     // ===== Closure Tests =====
@@ -685,6 +2291,138 @@ mod tests {
         assert!(err.contains("not registered"));
     }
 
+    // ===== Listing Tests =====
+
+    #[test]
+    fn listing_reads_each_pathinfo_once() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        for i in 0..100u8 {
+            let hash = nix_compat::nixbase32::encode(&[i; 20]);
+            register(&db, &format!("/nix/store/{hash}-pkg-{i}"), vec![], u64::from(i));
+        }
+
+        let listing = collect_listing(&db).unwrap();
+        assert_eq!(listing.len(), 100);
+        assert_eq!(db.reads(), 100);
+        assert!(listing.windows(2).all(|w| w[0].0.store_path < w[1].0.store_path));
+        assert_eq!(listing.iter().map(|(info, _)| info.nar_size).sum::<u64>(), 4950);
+    }
+
+    // ===== Why-Depends Tests =====
+
+    #[test]
+    fn why_depends_direct() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_B, vec![], 100);
+        register(&db, P_A, vec![P_B], 100);
+
+        let chain = why_depends(&db, P_A, P_B).unwrap().unwrap();
+        assert_eq!(chain, vec![P_A, P_B]);
+    }
+
+    #[test]
+    fn why_depends_two_hops_shortest() {
+        // a → {b, c}, b → d, c → e, e → d: the chain via b is shorter
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_D, vec![], 10);
+        register(&db, P_HELLO, vec![P_D], 10);
+        register(&db, P_C, vec![P_HELLO], 10);
+        register(&db, P_B, vec![P_D], 10);
+        register(&db, P_A, vec![P_C, P_B], 10);
+
+        let chain = why_depends(&db, P_A, P_D).unwrap().unwrap();
+        assert_eq!(chain, vec![P_A, P_B, P_D]);
+    }
+
+    #[test]
+    fn why_depends_unreachable() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_B, vec![], 100);
+        register(&db, P_A, vec![P_B], 100);
+        register(&db, P_C, vec![], 100);
+
+        assert!(why_depends(&db, P_A, P_C).unwrap().is_none());
+    }
+
+    #[test]
+    fn why_depends_self() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_A, vec![P_A], 100);
+
+        assert_eq!(why_depends(&db, P_A, P_A).unwrap().unwrap(), vec![P_A]);
+    }
+
+    #[test]
+    fn closure_dot_diamond() {
+        // a → {b, c}, b → d, c → d, d → d
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_D, vec![P_D], 10);
+        register(&db, P_B, vec![P_D], 10);
+        register(&db, P_C, vec![P_D], 10);
+        register(&db, P_A, vec![P_B, P_C], 10);
+
+        let dot = closure_dot(&db, P_A).unwrap();
+        assert!(dot.starts_with("digraph closure {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches("[label=").count(), 4);
+        assert_eq!(dot.matches(" -> ").count(), 5);
+        assert!(dot.contains(&format!("\"{P_D}\" [label=\"d\\n1.0\"];")));
+        assert!(dot.contains(&format!("\"{P_D}\" -> \"{P_D}\";")));
+        assert!(dot.contains(&format!("\"{P_A}\" -> \"{P_C}\";")));
+    }
+
+    // ===== Cycle Tests =====
+
+    #[test]
+    fn cycles_none_in_chain() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_C, vec![], 100);
+        register(&db, P_B, vec![P_C], 200);
+        register(&db, P_A, vec![P_B, P_C, P_GONE], 300);
+
+        assert!(find_cycles(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cycles_two_paths() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_A, vec![P_B], 100);
+        register(&db, P_B, vec![P_A], 100);
+        register(&db, P_C, vec![P_A], 100);
+
+        assert_eq!(
+            find_cycles(&db).unwrap(),
+            vec![vec![P_A.to_string(), P_B.to_string()]]
+        );
+    }
+
+    #[test]
+    fn cycles_self_reference() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+
+        register(&db, P_A, vec![P_A, P_B], 100);
+        register(&db, P_B, vec![], 100);
+
+        assert_eq!(find_cycles(&db).unwrap(), vec![vec![P_A.to_string()]]);
+    }
+
     // ===== GC Root Tests =====
 
     #[test]
@@ -789,6 +2527,162 @@ mod tests {
         assert_eq!(live.len(), 3); // a, b, shared
     }
 
+    #[test]
+    fn live_set_follows_indirect_root() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_B, vec![], 100);
+        register(&db, P_A, vec![P_B], 200);
+        register(&db, P_C, vec![], 50);
+
+        let result = tmp.path().join("result");
+        std::os::unix::fs::symlink(P_A, &result).unwrap();
+        roots.add_indirect_root("project", &result).unwrap();
+
+        let listed = roots.list_roots().unwrap();
+        assert_eq!(listed[0].target, P_A);
+        assert_eq!(listed[0].indirect.as_deref(), result.to_str());
+        let live = roots.compute_live_set(&db).unwrap();
+        assert_eq!(live, BTreeSet::from([P_A.to_string(), P_B.to_string()]));
+
+        // A rebuild repoints `result`; the next GC keeps the new target.
+        fs::remove_file(&result).unwrap();
+        std::os::unix::fs::symlink(P_C, &result).unwrap();
+        let live = roots.compute_live_set(&db).unwrap();
+        assert_eq!(live, BTreeSet::from([P_C.to_string()]));
+
+        // Deleting `result` releases the root.
+        fs::remove_file(&result).unwrap();
+        assert!(roots.list_roots().unwrap().is_empty());
+        assert!(roots.compute_live_set(&db).unwrap().is_empty());
+    }
+
+    /// `path` relative to the directory `from`, through `/`.
+    fn relative_to(from: &Path, path: &str) -> PathBuf {
+        let up = fs::canonicalize(from).unwrap().components().count() - 1;
+        PathBuf::from("../".repeat(up)).join(path.trim_start_matches('/'))
+    }
+
+    #[test]
+    fn relative_root_links_resolve_from_their_directory() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+        register(&db, P_A, vec![], 100);
+        register(&db, P_C, vec![], 50);
+
+        let dir = tmp.path().join("gcroots");
+        std::os::unix::fs::symlink(relative_to(&dir, P_C), dir.join("direct")).unwrap();
+
+        // `result -> ../../nix/store/…`, rooted through a relative link too.
+        let project = tmp.path().join("project");
+        fs::create_dir(&project).unwrap();
+        let result = project.join("result");
+        std::os::unix::fs::symlink(relative_to(&project, P_A), &result).unwrap();
+        roots.add_indirect_root("project", &result).unwrap();
+        fs::remove_file(dir.join("project")).unwrap();
+        std::os::unix::fs::symlink("../project/result", dir.join("project")).unwrap();
+
+        let listed = roots.list_roots().unwrap();
+        assert_eq!(listed.len(), 2, "{listed:?}");
+        assert_eq!((listed[0].name.as_str(), listed[0].target.as_str()), ("direct", P_C));
+        assert_eq!((listed[1].name.as_str(), listed[1].target.as_str()), ("project", P_A));
+        let expected = fs::canonicalize(&project).unwrap().join("result");
+        assert_eq!(listed[1].indirect.as_deref(), expected.to_str());
+        let live = roots.compute_live_set(&db).unwrap();
+        assert_eq!(live, BTreeSet::from([P_A.to_string(), P_C.to_string()]));
+    }
+
+    #[test]
+    fn indirect_root_must_point_into_store() {
+        let tmp = TempDir::new().unwrap();
+        let roots = make_roots(&tmp);
+
+        let plain = tmp.path().join("plain");
+        fs::write(&plain, "not a link").unwrap();
+        assert!(roots.add_indirect_root("plain", &plain).is_err());
+
+        let elsewhere = tmp.path().join("elsewhere");
+        std::os::unix::fs::symlink("/tmp", &elsewhere).unwrap();
+        assert!(roots.add_indirect_root("elsewhere", &elsewhere).is_err());
+        assert!(roots.list_roots().unwrap().is_empty());
+    }
+
+    // ===== Disk Usage Tests =====
+
+    #[test]
+    fn disk_usage_shared_dependency() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_SHARED, vec![], 600);
+        register(&db, P_A, vec![P_SHARED], 100);
+        register(&db, P_C, vec![], 50);
+        register(&db, P_B, vec![P_SHARED, P_C], 200);
+
+        roots.add_root("app-a", P_A).unwrap();
+        roots.add_root("app-b", P_B).unwrap();
+
+        let usage = disk_usage(&db, &roots).unwrap();
+        assert_eq!(usage.len(), 2);
+
+        // Sorted by exclusive size: b keeps b + c, a only itself.
+        assert_eq!(usage[0].name, "app-b");
+        assert_eq!(usage[0].exclusive_size, 250);
+        assert_eq!(usage[0].closure_size, 850);
+        assert_eq!(usage[0].attributed_size, 250 + 300);
+        assert_eq!(usage[1].name, "app-a");
+        assert_eq!(usage[1].exclusive_size, 100);
+        assert_eq!(usage[1].closure_paths, 2);
+        assert_eq!(usage[1].attributed_size, 100 + 300);
+
+        // The shared path is counted once across roots.
+        let exclusive: u64 = usage.iter().map(|u| u.exclusive_size).sum();
+        assert_eq!(exclusive, 350);
+        let attributed: u64 = usage.iter().map(|u| u.attributed_size).sum();
+        assert_eq!(attributed, 950);
+    }
+
+    #[test]
+    fn disk_usage_attributes_every_byte() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        // 100 bytes don't split evenly three ways.
+        register(&db, P_SHARED, vec![], 100);
+        register(&db, P_A, vec![P_SHARED], 10);
+        register(&db, P_B, vec![P_SHARED], 10);
+        register(&db, P_C, vec![P_SHARED], 10);
+        for (name, path) in [("a", P_A), ("b", P_B), ("c", P_C)] {
+            roots.add_root(name, path).unwrap();
+        }
+
+        let usage = disk_usage(&db, &roots).unwrap();
+        let mut attributed: Vec<u64> = usage.iter().map(|u| u.attributed_size).collect();
+        attributed.sort();
+        assert_eq!(attributed, vec![10 + 33, 10 + 33, 10 + 34]);
+        assert_eq!(attributed.iter().sum::<u64>(), 130);
+    }
+
+    #[test]
+    fn disk_usage_skips_dangling_root() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_A, vec![], 100);
+        roots.add_root("app", P_A).unwrap();
+        roots.add_root("gone", P_GONE).unwrap();
+
+        let usage = disk_usage(&db, &roots).unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].exclusive_size, 100);
+    }
+
     // ===== Garbage Collection Tests =====
 
     #[test]
@@ -800,7 +2694,7 @@ mod tests {
         register(&db, P_A, vec![], 100);
         roots.add_root("keep", P_A).unwrap();
 
-        let stats = garbage_collect(&db, &roots, false).unwrap();
+        let stats = garbage_collect(&db, &roots, false, false, &GcLimits::default()).unwrap();
         assert_eq!(stats.paths_deleted, 0);
         assert_eq!(stats.paths_kept, 1);
     }
@@ -816,7 +2710,7 @@ mod tests {
         roots.add_root("keep", P_KEEP).unwrap();
 
         // Dry run first
-        let dry = garbage_collect(&db, &roots, true).unwrap();
+        let dry = garbage_collect(&db, &roots, true, false, &GcLimits::default()).unwrap();
         assert_eq!(dry.paths_deleted, 1);
         assert_eq!(dry.paths_kept, 1);
 
@@ -824,7 +2718,7 @@ mod tests {
         assert!(db.is_registered(P_DEAD));
 
         // Real GC
-        let stats = garbage_collect(&db, &roots, false).unwrap();
+        let stats = garbage_collect(&db, &roots, false, false, &GcLimits::default()).unwrap();
         assert_eq!(stats.paths_deleted, 1);
         assert_eq!(stats.paths_kept, 1);
 
@@ -834,6 +2728,30 @@ mod tests {
         assert!(db.is_registered(P_KEEP));
     }
 
+    #[test]
+    fn gc_print_dead_and_live_sets() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_B, vec![], 100);
+        register(&db, P_A, vec![P_B], 100);
+        register(&db, P_DEAD, vec![P_B], 100);
+        register(&db, P_ORPHAN, vec![], 100);
+        roots.add_root("app", P_A).unwrap();
+
+        let print = |live| {
+            let mut out = Vec::new();
+            write_gc_set(&mut out, &db, &roots, live).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(print(false), format!("{P_DEAD}\n{P_ORPHAN}\n"));
+        assert_eq!(print(true), format!("{P_A}\n{P_B}\n"));
+
+        // Nothing was deleted.
+        assert_eq!(db.all_paths_set().unwrap().len(), 4);
+    }
+
     #[test]
     fn gc_preserves_transitive_deps() {
         let tmp = TempDir::new().unwrap();
@@ -846,7 +2764,7 @@ mod tests {
 
         roots.add_root("app", P_A).unwrap();
 
-        let stats = garbage_collect(&db, &roots, false).unwrap();
+        let stats = garbage_collect(&db, &roots, false, false, &GcLimits::default()).unwrap();
         assert_eq!(stats.paths_deleted, 1); // only orphan
         assert_eq!(stats.paths_kept, 2);    // a + b
 
@@ -864,11 +2782,548 @@ mod tests {
         register(&db, P_A, vec![], 100);
         register(&db, P_B, vec![], 200);
 
-        let stats = garbage_collect(&db, &roots, false).unwrap();
+        let stats = garbage_collect(&db, &roots, false, false, &GcLimits::default()).unwrap();
         assert_eq!(stats.paths_deleted, 2);
         assert_eq!(stats.paths_kept, 0);
     }
 
+    #[test]
+    fn gc_refuses_when_root_closure_broken() {
+        // keep → a → b, but b's reference to gone is unregistered: the
+        // root's closure can't be computed, so a and b look dead even
+        // though keep still points at a.
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_B, vec![P_GONE], 100);
+        register(&db, P_A, vec![P_B], 100);
+        roots.add_root("keep", P_A).unwrap();
+
+        let err = garbage_collect(&db, &roots, false, false, &GcLimits::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("refusing to collect"), "{err}");
+        assert!(err.contains(&format!("gc root 'keep' → {P_A}")), "{err}");
+        assert!(db.is_registered(P_A));
+        assert!(db.is_registered(P_B));
+
+        // --force deletes anyway
+        let stats = garbage_collect(&db, &roots, false, true, &GcLimits::default()).unwrap();
+        assert_eq!(stats.paths_deleted, 2);
+        assert!(!db.is_registered(P_A));
+    }
+
+    #[test]
+    fn gc_dry_run_also_refuses() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_A, vec![P_GONE], 100);
+        roots.add_root("keep", P_A).unwrap();
+
+        assert!(garbage_collect(&db, &roots, true, false, &GcLimits::default()).is_err());
+    }
+
+    fn plan_of(infos: Vec<PathInfo>, limits: GcLimits) -> Vec<(String, u64)> {
+        let dead: BTreeMap<String, PathInfo> =
+            infos.into_iter().map(|i| (i.store_path.clone(), i)).collect();
+        let sizes: BTreeMap<String, u64> =
+            dead.values().map(|i| (i.store_path.clone(), i.nar_size)).collect();
+        plan_collection(&dead, &limits, |p| sizes[p])
+    }
+
+    #[test]
+    fn gc_max_freed_stops_early() {
+        let plan = plan_of(
+            vec![
+                info_at(P_C, vec![], 300, "2026-03-01T00:00:00Z"),
+                info_at(P_A, vec![], 100, "2026-01-01T00:00:00Z"),
+                info_at(P_B, vec![], 200, "2026-02-01T00:00:00Z"),
+            ],
+            GcLimits { max_freed: Some(250), ..Default::default() },
+        );
+
+        // Oldest first, stopping as soon as 250 bytes are freed.
+        assert_eq!(plan, vec![(P_A.to_string(), 100), (P_B.to_string(), 200)]);
+    }
+
+    #[test]
+    fn gc_plan_deletes_referrers_first() {
+        // a is oldest, but the newer b still references it.
+        let plan = plan_of(
+            vec![
+                info_at(P_A, vec![P_A], 100, "2026-01-01T00:00:00Z"),
+                info_at(P_B, vec![P_A], 100, "2026-02-01T00:00:00Z"),
+                info_at(P_C, vec![], 100, "2026-03-01T00:00:00Z"),
+            ],
+            GcLimits { max_freed: Some(1), ..Default::default() },
+        );
+        assert_eq!(plan, vec![(P_B.to_string(), 100)]);
+
+        let all = plan_of(
+            vec![
+                info_at(P_A, vec![P_A], 100, "2026-01-01T00:00:00Z"),
+                info_at(P_B, vec![P_A], 100, "2026-02-01T00:00:00Z"),
+            ],
+            GcLimits::default(),
+        );
+        assert_eq!(all, vec![(P_B.to_string(), 100), (P_A.to_string(), 100)]);
+    }
+
+    #[test]
+    fn gc_plan_collects_dead_cycles() {
+        // a and b reference each other; the newer c references the cycle.
+        let infos = || {
+            vec![
+                info_at(P_A, vec![P_B], 100, "2026-01-01T00:00:00Z"),
+                info_at(P_B, vec![P_A, P_B], 100, "2026-02-01T00:00:00Z"),
+                info_at(P_C, vec![P_A], 100, "2026-03-01T00:00:00Z"),
+                info_at(P_D, vec![], 100, "2026-04-01T00:00:00Z"),
+            ]
+        };
+        let all = plan_of(infos(), GcLimits::default());
+        let paths: Vec<&str> = all.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(paths, [P_C, P_A, P_B, P_D]);
+
+        // A limit never splits the cycle.
+        let plan = plan_of(infos(), GcLimits { max_freed: Some(150), ..Default::default() });
+        let paths: Vec<&str> = plan.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(paths, [P_C, P_A, P_B]);
+        let plan = plan_of(infos(), GcLimits { max_freed: Some(1), ..Default::default() });
+        assert_eq!(plan, vec![(P_C.to_string(), 100)]);
+    }
+
+    #[test]
+    fn gc_deletes_dead_cycles() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_A, vec![P_B], 100);
+        register(&db, P_B, vec![P_C], 100);
+        register(&db, P_C, vec![P_A], 100);
+        register(&db, P_KEEP, vec![], 100);
+        roots.add_root("keep", P_KEEP).unwrap();
+
+        let stats = garbage_collect(&db, &roots, false, false, &GcLimits::default()).unwrap();
+        assert_eq!((stats.paths_deleted, stats.paths_remaining), (3, 0));
+        assert!(!db.is_registered(P_A) && !db.is_registered(P_B) && !db.is_registered(P_C));
+        assert!(db.is_registered(P_KEEP));
+    }
+
+    #[test]
+    fn gc_max_age_keeps_recent_paths() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        db.register(&info_at(P_A, vec![], 100, "2020-01-01T00:00:00Z")).unwrap();
+        db.register(&info_at(P_B, vec![P_C], 100, "2026-06-01T00:00:00Z")).unwrap();
+        // Old, but referenced by the recent b.
+        db.register(&info_at(P_C, vec![], 100, "2020-01-01T00:00:00Z")).unwrap();
+        db.register(&info_at(P_D, vec![], 100, "not a timestamp")).unwrap();
+
+        let limits = GcLimits {
+            registered_before: pathinfo::parse_timestamp("2025-01-01T00:00:00Z"),
+            ..Default::default()
+        };
+        let stats = garbage_collect(&db, &roots, false, false, &limits).unwrap();
+
+        assert_eq!(stats.paths_deleted, 1);
+        assert_eq!(stats.paths_remaining, 3);
+        assert!(!db.is_registered(P_A));
+        assert!(db.is_registered(P_B));
+        assert!(db.is_registered(P_C));
+        assert!(db.is_registered(P_D));
+    }
+
+    // ===== Export / Import Tests =====
+
+    #[test]
+    fn export_import_roundtrip() {
+        let tmp = TempDir::new().unwrap();
+        let src_store = tmp.path().join("src-store");
+        let dst_store = tmp.path().join("dst-store");
+        fs::create_dir_all(&src_store).unwrap();
+        fs::create_dir_all(&dst_store).unwrap();
+        let src_db = PathInfoDb::open_at(tmp.path().join("src-db")).unwrap();
+        let dst_db = PathInfoDb::open_at(tmp.path().join("dst-db")).unwrap();
+
+        // a: a single file; b: a directory referencing a
+        let a_dir = src_store.join(store_path_name(P_A).unwrap());
+        fs::write(&a_dir, "lib contents\n").unwrap();
+        let b_dir = src_store.join(store_path_name(P_B).unwrap());
+        fs::create_dir_all(b_dir.join("bin")).unwrap();
+        fs::write(b_dir.join("bin/tool"), P_A).unwrap();
+        std::os::unix::fs::symlink("bin/tool", b_dir.join("tool")).unwrap();
+
+        src_db.register(&info_at(P_A, vec![], 0, "2026-01-01T00:00:00Z")).unwrap();
+        let mut b = info_at(P_B, vec![P_A, P_B], 0, "2026-01-01T00:00:00Z");
+        b.deriver = Some("/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-b-1.0.drv".to_string());
+        src_db.register(&b).unwrap();
+
+        let order = closure_in_dependency_order(&src_db, &[P_B.to_string()]).unwrap();
+        assert_eq!(order, vec![P_A.to_string(), P_B.to_string()]);
+
+        let mut stream = Vec::new();
+        export_paths(&src_db, &src_store, &order, &mut stream).unwrap();
+        assert_eq!(&stream[..8], &1u64.to_le_bytes());
+        assert_eq!(&stream[stream.len() - 8..], &0u64.to_le_bytes());
+        assert!(stream.windows(8).any(|w| w == b"NIXE\0\0\0\0"));
+
+        let imported = import_paths(&dst_db, &dst_store, &mut &stream[..]).unwrap();
+        assert_eq!(imported, order);
+
+        let b = dst_db.get(P_B).unwrap().unwrap();
+        assert_eq!(b.references, vec![P_A.to_string(), P_B.to_string()]);
+        assert_eq!(b.deriver.as_deref(), Some("/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-b-1.0.drv"));
+        assert!(dst_db.get(P_A).unwrap().unwrap().references.is_empty());
+
+        let mut nar = Vec::new();
+        nar::dump(&b_dir, &mut nar).unwrap();
+        assert_eq!(b.nar_hash, data_encoding::HEXLOWER.encode(&Sha256::digest(&nar)));
+        assert_eq!(b.nar_size, nar.len() as u64);
+
+        let dst_b = dst_store.join(store_path_name(P_B).unwrap());
+        assert_eq!(fs::read_to_string(dst_b.join("bin/tool")).unwrap(), P_A);
+        assert_eq!(fs::read_link(dst_b.join("tool")).unwrap(), Path::new("bin/tool"));
+        assert_eq!(
+            fs::read_to_string(dst_store.join(store_path_name(P_A).unwrap())).unwrap(),
+            "lib contents\n"
+        );
+        assert!(!dst_store.join(format!(".snix-import-{}", std::process::id())).exists());
+
+        // Importing again skips the registered paths.
+        let again = import_paths(&dst_db, &dst_store, &mut &stream[..]).unwrap();
+        assert_eq!(again, order);
+    }
+
+    #[test]
+    fn import_rejects_bad_magic() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let mut stream = Vec::new();
+        write_u64(&mut stream, 7).unwrap();
+
+        let err = import_paths(&db, tmp.path(), &mut &stream[..]).unwrap_err();
+        assert!(err.to_string().contains("unexpected marker 7"), "{err}");
+    }
+
+    #[test]
+    fn references_into_dead_reports_live_edges() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_B, vec![], 100);
+        register(&db, P_A, vec![P_B], 100);
+        register(&db, P_ORPHAN, vec![], 100);
+
+        // An inconsistent partition: a is live but its reference b is dead.
+        let live = BTreeSet::from([P_A.to_string()]);
+        let dead = BTreeSet::from([P_B.to_string(), P_ORPHAN.to_string()]);
+
+        let edges = find_references_into_dead(&db, &roots, &live, &dead).unwrap();
+        assert_eq!(edges, vec![(P_A.to_string(), P_B.to_string())]);
+    }
+
+    #[test]
+    fn references_into_dead_consistent_is_empty() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let roots = make_roots(&tmp);
+
+        register(&db, P_B, vec![], 100);
+        register(&db, P_A, vec![P_B], 100);
+        register(&db, P_ORPHAN, vec![], 100);
+        roots.add_root("app", P_A).unwrap();
+
+        let live = roots.compute_live_set(&db).unwrap();
+        let dead = BTreeSet::from([P_ORPHAN.to_string()]);
+        assert!(find_references_into_dead(&db, &roots, &live, &dead).unwrap().is_empty());
+    }
+
+    // ===== Add Tests =====
+
+    #[test]
+    fn add_path_is_content_addressed_and_idempotent() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let store = tmp.path().join("store");
+        fs::create_dir_all(&store).unwrap();
+
+        let src = tmp.path().join("tree");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("greeting"), "hello\n").unwrap();
+        fs::write(src.join("bin/tool"), "#!/bin/sh\necho hi\n").unwrap();
+        fs::set_permissions(src.join("bin/tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("greeting", src.join("link")).unwrap();
+
+        let path = add_path(&db, &store, &src, "tree").unwrap();
+        assert_eq!(path, "/nix/store/rc9yd16wf19nf0snkdd59afmam51s2wa-tree");
+
+        let info = db.get(&path).unwrap().unwrap();
+        assert!(info.references.is_empty());
+        let mut nar = Vec::new();
+        nar::dump(&src, &mut nar).unwrap();
+        assert_eq!(info.nar_hash, data_encoding::HEXLOWER.encode(&Sha256::digest(&nar)));
+        assert_eq!(info.nar_size, nar.len() as u64);
+
+        let dest = store.join(store_path_name(&path).unwrap());
+        assert_eq!(fs::read_to_string(dest.join("greeting")).unwrap(), "hello\n");
+        assert_eq!(fs::read_link(dest.join("link")).unwrap(), Path::new("greeting"));
+        let mode = |p: &str| fs::metadata(dest.join(p)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("greeting"), 0o444);
+        assert_eq!(mode("bin/tool"), 0o555);
+
+        assert_eq!(add_path(&db, &store, &src, "tree").unwrap(), path);
+        assert!(!store.join(format!(".snix-add-{}", std::process::id())).exists());
+    }
+
+    // ===== Repair Tests =====
+
+    #[test]
+    fn repair_db_rediscovers_references() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        let store = tmp.path().join("store");
+        let hello = store.join(store_path_name(P_HELLO).unwrap());
+        let lib = store.join(store_path_name(P_SHARED).unwrap());
+        fs::create_dir_all(hello.join("bin")).unwrap();
+        fs::create_dir_all(&lib).unwrap();
+        let script = format!("#!/bin/sh\nexec {P_SHARED}/run\n");
+        fs::write(hello.join("bin/hello"), script).unwrap();
+        fs::write(lib.join("run"), "no references here\n").unwrap();
+        fs::create_dir_all(store.join(".snix-add-1")).unwrap();
+
+        // A stale entry is replaced, not merged with.
+        register(&db, P_HELLO, vec![P_DEAD], 1);
+
+        let repaired = repair_db(&db, &store).unwrap();
+        assert_eq!(repaired, vec![P_HELLO.to_string(), P_SHARED.to_string()]);
+
+        let info = db.get(P_HELLO).unwrap().unwrap();
+        assert_eq!(info.references, vec![P_SHARED.to_string()]);
+        assert!(info.deriver.is_none());
+        assert!(info.signatures.is_empty());
+        let mut nar = Vec::new();
+        nar::dump(&hello, &mut nar).unwrap();
+        assert_eq!(info.nar_size, nar.len() as u64);
+        assert_eq!(
+            info.nar_hash,
+            format!("sha256:{}", data_encoding::HEXLOWER.encode(&Sha256::digest(&nar)))
+        );
+
+        assert!(db.get(P_SHARED).unwrap().unwrap().references.is_empty());
+    }
+
+    // ===== Signing Tests =====
+
+    const TEST_SECRET_KEY: &str = "cache.example.com-1:cCta2MEsRNuYCgWYyeRXLyfoFpKhQJKn8gLMeXWAb7vIpRKKo/3JoxJ24OYa3DxT2JVV38KjK/1ywHWuMe2JEw==";
+    const TEST_PUBLIC_KEY: &str = "cache.example.com-1:yKUSiqP9yaMSduDmGtw8U9iVVd/Coyv9csB1rjHtiRM=";
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn sign_paths_stores_verifiable_signatures() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        for (path, refs) in [(P_A, vec![P_B]), (P_B, vec![])] {
+            let mut info = info_at(path, refs, 100, "2026-01-01T00:00:00Z");
+            info.nar_hash = EMPTY_SHA256.to_string();
+            info.signatures = vec!["cache.example.com-1:stale".to_string()];
+            db.register(&info).unwrap();
+        }
+
+        let paths = closure_in_dependency_order(&db, &[P_A.to_string()]).unwrap();
+        assert_eq!(sign_paths(&db, &paths, TEST_SECRET_KEY).unwrap(), 2);
+
+        let key = narinfo::VerifyingKey::parse(TEST_PUBLIC_KEY).unwrap();
+        for path in [P_A, P_B] {
+            let info = db.get(path).unwrap().unwrap();
+            assert_eq!(info.signatures.len(), 1, "stale signature replaced");
+            let sig = narinfo::SignatureRef::parse(&info.signatures[0]).unwrap();
+            assert!(key.verify(&info.fingerprint().unwrap(), &sig), "{path}");
+        }
+
+        // Signing again changes nothing.
+        assert_eq!(sign_paths(&db, &[P_A.to_string()], TEST_SECRET_KEY).unwrap(), 0);
+        assert!(sign_paths(&db, &[P_C.to_string()], TEST_SECRET_KEY).is_err());
+    }
+
+    #[test]
+    fn verify_closure_reports_unsigned_paths() {
+        let tmp = TempDir::new().unwrap();
+        let db = make_db(&tmp);
+        for (path, refs) in [(P_A, vec![P_B, P_C]), (P_B, vec![]), (P_C, vec![])] {
+            let mut info = info_at(path, refs, 100, "2026-01-01T00:00:00Z");
+            info.nar_hash = EMPTY_SHA256.to_string();
+            db.register(&info).unwrap();
+        }
+        sign_paths(&db, &[P_A.to_string()], TEST_SECRET_KEY).unwrap();
+        db.add_signature(P_C, "other.example.com-1:c2lnbmF0dXJl").unwrap();
+
+        let trusted = [TEST_PUBLIC_KEY.to_string()];
+        assert_eq!(
+            verify_closure(&db, P_A, &trusted).unwrap(),
+            vec![
+                (P_B.to_string(), SignatureProblem::Unsigned),
+                (P_C.to_string(), SignatureProblem::Unverifiable),
+            ]
+        );
+        assert!(verify_closure(&db, P_B, &["not a key".to_string()]).is_err());
+
+        sign_paths(&db, &[P_B.to_string(), P_C.to_string()], TEST_SECRET_KEY).unwrap();
+        assert!(verify_closure(&db, P_A, &trusted).unwrap().is_empty());
+    }
+
+    // ===== Optimise Tests =====
+
+    /// Create a read-only store path directory holding `files` (name, contents, mode).
+    fn make_store_dir(root: &Path, name: &str, files: &[(&str, &str, u32)]) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents, mode) in files {
+            let path = dir.join(file);
+            fs::write(&path, contents).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(*mode)).unwrap();
+        }
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        dir
+    }
+
+    fn inode(path: &Path) -> u64 {
+        std::os::unix::fs::MetadataExt::ino(&fs::metadata(path).unwrap())
+    }
+
+    #[test]
+    fn optimise_links_identical_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let links = tmp.path().join("links");
+        let content = "shared license text\n".repeat(100);
+        let a = make_store_dir(tmp.path(), "a", &[("LICENSE", &content, 0o444)]);
+        let b = make_store_dir(tmp.path(), "b", &[("LICENSE", &content, 0o444)]);
+
+        let stats = optimise(&[a.clone(), b.clone()], &links).unwrap();
+
+        assert_eq!(stats.files_linked, 1);
+        assert_eq!(stats.bytes_saved, content.len() as u64);
+        assert_eq!(inode(&a.join("LICENSE")), inode(&b.join("LICENSE")));
+        assert_eq!(fs::read_to_string(b.join("LICENSE")).unwrap(), content);
+        // Read-only semantics are preserved
+        assert_eq!(fs::metadata(&b).unwrap().permissions().mode() & 0o777, 0o555);
+        assert_eq!(fs::metadata(b.join("LICENSE")).unwrap().permissions().mode() & 0o777, 0o444);
+        assert!(!b.join(".LICENSE.snix-link").exists());
+
+        // Already-linked files are skipped on a second run
+        let again = optimise(&[a, b], &links).unwrap();
+        assert_eq!(again.files_linked, 0);
+        assert_eq!(again.bytes_saved, 0);
+    }
+
+    #[test]
+    fn optimise_keeps_executable_bits_apart() {
+        let tmp = TempDir::new().unwrap();
+        let links = tmp.path().join("links");
+        let a = make_store_dir(tmp.path(), "a", &[("run", "#!/bin/sh\n", 0o555)]);
+        let b = make_store_dir(tmp.path(), "b", &[("run", "#!/bin/sh\n", 0o444)]);
+
+        let stats = optimise(&[a.clone(), b.clone()], &links).unwrap();
+
+        assert_eq!(stats.files_linked, 0);
+        assert_ne!(inode(&a.join("run")), inode(&b.join("run")));
+    }
+
+    #[test]
+    fn optimise_prunes_unused_links() {
+        let tmp = TempDir::new().unwrap();
+        let links = tmp.path().join("links");
+        let a = make_store_dir(tmp.path(), "a", &[("data", "payload", 0o444)]);
+        optimise(std::slice::from_ref(&a), &links).unwrap();
+        assert_eq!(fs::read_dir(&links).unwrap().count(), 1);
+
+        // Simulate GC of the only path using the canonical copy
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&a, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&a).unwrap();
+
+        let stats = optimise(&[a], &links).unwrap();
+        assert_eq!(stats.links_pruned, 1);
+        assert_eq!(fs::read_dir(&links).unwrap().count(), 0);
+    }
+
+    // ===== Listing Tests =====
+
+    /// A store path with `bin/hello` (executable), `share/doc/README` and
+    /// `lib -> bin`.
+    fn ls_fixture(tmp: &TempDir) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let top = tmp.path().join("1b9jydsiygi6jhlz2dxbrxi6b4m1rn4r-a-1.0");
+        fs::create_dir_all(top.join("bin")).unwrap();
+        fs::create_dir_all(top.join("share/doc")).unwrap();
+        fs::write(top.join("bin/hello"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(top.join("bin/hello"), fs::Permissions::from_mode(0o555)).unwrap();
+        fs::write(top.join("share/doc/README"), "hello").unwrap();
+        std::os::unix::fs::symlink("bin", top.join("lib")).unwrap();
+        top
+    }
+
+    fn ls(top: &Path, subpath: &str, recursive: bool, long: bool) -> Vec<String> {
+        list_contents(top, subpath, recursive)
+            .unwrap()
+            .iter()
+            .map(|e| format_ls_entry(e, long))
+            .collect()
+    }
+
+    #[test]
+    fn ls_directory() {
+        let tmp = TempDir::new().unwrap();
+        let top = ls_fixture(&tmp);
+
+        assert_eq!(ls(&top, "", false, false), vec!["bin/", "lib -> bin", "share/"]);
+        assert_eq!(
+            ls(&top, "/", true, false),
+            vec!["bin/", "bin/hello*", "lib -> bin", "share/", "share/doc/", "share/doc/README"]
+        );
+        assert_eq!(
+            ls(&top, "", false, true),
+            vec![
+                "dr-xr-xr-x            0 bin",
+                "lrwxrwxrwx            0 lib -> bin",
+                "dr-xr-xr-x            0 share",
+            ]
+        );
+    }
+
+    #[test]
+    fn ls_nested_subpath() {
+        let tmp = TempDir::new().unwrap();
+        let top = ls_fixture(&tmp);
+
+        assert_eq!(ls(&top, "/share/doc", false, false), vec!["README"]);
+        assert_eq!(ls(&top, "share", true, false), vec!["doc/", "doc/README"]);
+
+        // A file, or a symlink, lists just itself.
+        assert_eq!(
+            ls(&top, "/bin/hello", false, true),
+            vec!["-r-xr-xr-x           10 hello"]
+        );
+        assert_eq!(ls(&top, "/lib", true, false), vec!["lib -> bin"]);
+
+        let err = list_contents(&top, "/bin/missing", false).unwrap_err().to_string();
+        assert!(err.starts_with("/bin/missing: "), "{err}");
+        assert!(list_contents(&top, "../..", false).is_err());
+    }
+
     // ===== Helper Tests =====
 
     #[test]
@@ -0,0 +1,252 @@
+//! Relocating the store under another directory.
+//!
+//! `snix --store-root DIR` (or `SNIX_STORE_ROOT=DIR`) keeps store paths,
+//! path info, GC roots and downloads under `DIR/nix/...` instead of
+//! `/nix/...`. It is meant for tests on the host and for preparing the
+//! store of another system image.
+//!
+//! Store paths keep their names: a path is still registered, referenced
+//! and printed as `/nix/store/...`, only its files live under the root.
+//! Local builds and evaluation still read and write `/nix/store` itself.
+//!
+//! `main` hands the root to [`init`] once at startup. It also exports the
+//! environment variable, so helper processes spawned by snix see the same
+//! root; code in this crate never reads the variable itself.
+
+#[cfg(test)]
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use nix_compat::store_path::{STORE_DIR, STORE_DIR_WITH_SLASH};
+
+use crate::pathinfo::SNIX_VAR_DIR;
+
+/// Environment variable naming the store root.
+pub const STORE_ROOT_ENV: &str = "SNIX_STORE_ROOT";
+
+/// The root given to [`init`].
+static ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    /// A root for the current test only, see [`tests::with_root`].
+    static TEST_ROOT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Set the store root for the rest of the process. An empty path means
+/// none. Later calls are ignored.
+pub fn init(root: Option<PathBuf>) {
+    let _ = ROOT.set(root.filter(|root| !root.as_os_str().is_empty()));
+}
+
+/// The store root, if one is set.
+pub fn root() -> Option<PathBuf> {
+    #[cfg(test)]
+    if let Some(root) = TEST_ROOT.with(|r| r.borrow().clone()) {
+        return Some(root);
+    }
+    ROOT.get().cloned().flatten()
+}
+
+/// Where the files of `/nix/store` live.
+pub fn store_dir() -> PathBuf {
+    under_root(STORE_DIR)
+}
+
+/// Where snix keeps its metadata (`/nix/var/snix` without a root).
+pub fn var_dir() -> PathBuf {
+    under_root(SNIX_VAR_DIR)
+}
+
+/// Where the files of `store_path` live. Paths outside `/nix/store` are
+/// returned unchanged.
+pub fn real_path(store_path: &str) -> PathBuf {
+    if store_path.starts_with(STORE_DIR_WITH_SLASH) {
+        under_root(store_path)
+    } else {
+        PathBuf::from(store_path)
+    }
+}
+
+fn under_root(path: &str) -> PathBuf {
+    match root() {
+        Some(root) => root.join(path.trim_start_matches('/')),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    use nix_compat::nixbase32;
+    use sha2::{Digest, Sha256};
+
+    use crate::cache::{Substituter, Substituters};
+    use crate::pathinfo::{self, PathInfoDb};
+    use crate::store::{self, GcLimits, GcRoots};
+    use crate::{local_cache, nar, test_http};
+
+    const HASH: &str = "3d8fkhz1wq2vk4a6ywmc1x6jb7p2xr9l";
+    const PATH: &str = "/nix/store/3d8fkhz1wq2vk4a6ywmc1x6jb7p2xr9l-hello-1.0";
+    const DRV: &str = "0m4h6zc4ciyppmk3wvclsb4aslg8nvg5-hello-1.0.drv";
+
+    /// Unsets the root again, even if the test fails.
+    struct RootGuard;
+
+    impl Drop for RootGuard {
+        fn drop(&mut self) {
+            TEST_ROOT.with(|r| r.take());
+        }
+    }
+
+    /// Use `root` on this thread until the guard is dropped. Tests run on
+    /// threads of their own, so no other test sees it.
+    fn with_root(root: &Path) -> RootGuard {
+        TEST_ROOT.with(|r| *r.borrow_mut() = Some(root.to_path_buf()));
+        RootGuard
+    }
+
+    #[test]
+    fn fetch_list_gc_under_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        let _guard = with_root(&root);
+
+        // A local binary cache holding one path.
+        let src = tmp.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("greeting"), "hello\n").unwrap();
+        let mut nar_bytes = Vec::new();
+        nar::dump(&src, &mut nar_bytes).unwrap();
+        let cache = tmp.path().join("cache");
+        fs::create_dir_all(cache.join("nar")).unwrap();
+        fs::write(cache.join("nar/hello.nar"), &nar_bytes).unwrap();
+        let narinfo = format!(
+            "StorePath: {PATH}\n\
+             URL: nar/hello.nar\n\
+             Compression: none\n\
+             NarHash: sha256:{}\n\
+             NarSize: {}\n\
+             References: \n\
+             Deriver: {DRV}\n",
+            nixbase32::encode(&Sha256::digest(&nar_bytes)),
+            nar_bytes.len()
+        );
+        fs::write(cache.join(format!("{HASH}.narinfo")), narinfo).unwrap();
+
+        // The deriver is in the store, so the output name can be read from it.
+        let store = root.join("nix/store");
+        fs::create_dir_all(&store).unwrap();
+        fs::write(
+            store.join(DRV),
+            format!(
+                "Derive([(\"out\",\"{PATH}\",\"\",\"\")],[],[],\"x86_64-linux\",\
+                 \"/bin/sh\",[],[(\"out\",\"{PATH}\")])"
+            ),
+        )
+        .unwrap();
+
+        // Fetch: the files land under the root, the name stays the same.
+        local_cache::fetch_local(PATH, cache.to_str().unwrap()).unwrap();
+        // Only the output was registered; the rest of the test ignores the .drv.
+        fs::remove_file(store.join(DRV)).unwrap();
+        let on_disk = root.join(PATH.trim_start_matches('/'));
+        assert_eq!(real_path(PATH), on_disk);
+        assert_eq!(
+            fs::read_to_string(on_disk.join("greeting")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(real_path("/etc/passwd"), Path::new("/etc/passwd"));
+
+        // List
+        let db = PathInfoDb::open().unwrap();
+        assert!(root.join("nix/var/snix/pathinfo").is_dir());
+        assert_eq!(db.list_paths().unwrap(), vec![PATH.to_string()]);
+        assert_eq!(store::list_paths().unwrap(), vec![on_disk.clone()]);
+        assert!(db.disk_size(PATH) > 0);
+
+        // Info: the deriver from the narinfo was recorded.
+        let info = db.get(PATH).unwrap().unwrap();
+        assert_eq!(info.deriver, Some(format!("/nix/store/{DRV}")));
+        assert_eq!(info.output.as_deref(), Some("out"));
+        let report = store::format_info(&db, &info);
+        assert!(report.contains(&format!("Deriver:      /nix/store/{DRV}\n")), "{report}");
+        assert!(report.contains("Output:       out\n"), "{report}");
+
+        // GC: nothing roots the path, so it goes.
+        let roots = GcRoots::open().unwrap();
+        assert!(root.join("nix/var/snix/gcroots").is_dir());
+        let stats =
+            store::garbage_collect(&db, &roots, false, false, &GcLimits::default()).unwrap();
+        assert_eq!(stats.paths_deleted, 1);
+        assert!(!on_disk.exists());
+        assert!(db.list_paths().unwrap().is_empty());
+    }
+
+    #[test]
+    fn fetch_falls_back_to_the_next_cache_for_the_nar() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        let _guard = with_root(&root);
+
+        let src = tmp.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("greeting"), "hello\n").unwrap();
+        let mut nar_bytes = Vec::new();
+        nar::dump(&src, &mut nar_bytes).unwrap();
+        let nar = String::from_utf8(nar_bytes).unwrap();
+        let narinfo = format!(
+            "StorePath: {PATH}\n\
+             URL: nar/hello.nar\n\
+             Compression: none\n\
+             NarHash: sha256:{}\n\
+             NarSize: {}\n\
+             References: \n",
+            nixbase32::encode(&Sha256::digest(&nar)),
+            nar.len()
+        );
+        let narinfo_path = format!("/{HASH}.narinfo");
+
+        // The preferred cache lists the path but has lost its NAR.
+        let broken = test_http::serve_files(&[(&narinfo_path, &narinfo)]);
+        let working =
+            test_http::serve_files(&[(&narinfo_path, &narinfo), ("/nar/hello.nar", &nar)]);
+        let subs = Substituters::from_ordered(vec![
+            Substituter { url: broken, priority: 10 },
+            Substituter { url: working, priority: 20 },
+        ]);
+
+        subs.fetch_from_any(PATH, None, None).unwrap();
+        let on_disk = root.join(PATH.trim_start_matches('/'));
+        assert_eq!(fs::read_to_string(on_disk.join("greeting")).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn compact_folds_the_db_under_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        let _guard = with_root(&root);
+
+        let db = PathInfoDb::open().unwrap();
+        let other = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-other-1.0";
+        for path in [PATH, other] {
+            store::register_path(&db, path, &"0".repeat(64), 1, vec![], vec![], None).unwrap();
+        }
+
+        store::run_compact().unwrap();
+
+        let dir = root.join("nix/var/snix/pathinfo");
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, [pathinfo::INDEX_FILE]);
+        let reopened = PathInfoDb::open().unwrap();
+        assert_eq!(reopened.list_paths().unwrap(), [other, PATH]);
+    }
+}
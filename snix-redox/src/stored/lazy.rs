@@ -16,7 +16,7 @@ use nix_compat::nixbase32;
 use nix_compat::store_path::StorePath;
 use sha2::{Digest, Sha256};
 
-use crate::nar;
+use crate::nar::{self, Compression};
 use crate::pathinfo::PathInfoDb;
 
 /// Errors from lazy extraction.
@@ -163,22 +163,9 @@ fn do_extraction(
         .map_err(|e| ExtractError::Io(format!("opening {}: {e}", nar_path.display())))?;
     let reader = BufReader::new(file);
 
-    let decompressed: Box<dyn std::io::Read + Send> =
-        match nar_path.extension().and_then(|e| e.to_str()) {
-            Some("zst") => Box::new(
-                ruzstd::decoding::StreamingDecoder::new(reader)
-                    .map_err(|e| ExtractError::Io(format!("zstd init: {e}")))?,
-            ),
-            Some("xz") => {
-                let mut input = BufReader::new(reader);
-                let mut output = Vec::new();
-                lzma_rs::xz_decompress(&mut input, &mut output)
-                    .map_err(|e| ExtractError::Io(format!("xz decompress: {e}")))?;
-                Box::new(std::io::Cursor::new(output))
-            }
-            Some("bz2") => Box::new(bzip2_rs::DecoderReader::new(reader)),
-            _ => Box::new(reader), // Assume uncompressed .nar
-        };
+    let file_name = nar_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let decompressed = nar::decompress_nar(reader, Compression::from_file_name(file_name))
+        .map_err(|e| ExtractError::Io(format!("decompressing {}: {e}", nar_path.display())))?;
 
     // Hash while reading for verification.
     let mut hashing = HashingExtractReader::new(decompressed);
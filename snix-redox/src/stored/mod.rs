@@ -524,6 +524,7 @@ mod tests {
             nar_size: 100,
             references: vec![],
             deriver: None,
+            output: None,
             registration_time: "2026-01-01T00:00:00Z".to_string(),
             signatures: vec![],
             files: vec![
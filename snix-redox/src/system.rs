@@ -11,11 +11,14 @@
 //!   - `snix system generations` — list all tracked system generations
 //!   - `snix system switch`      — save current generation and activate a new manifest
 //!   - `snix system rollback`    — revert to the previous generation
+//!   - `snix system generate-config` — write a configuration.nix for the current manifest
+//!   - `snix system options`     — list configurable options and their current values
 
 use std::collections::BTreeMap;
+use std::fmt::Write as _;
 use std::fs;
-use std::io::Read;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -42,8 +45,19 @@ pub struct Manifest {
     pub services: Services,
     #[serde(default)]
     pub files: BTreeMap<String, FileInfo>,
+    /// Files declared with `environment.etc` in configuration.nix, keyed
+    /// like `files`. They aren't in the rootTree, so activation writes
+    /// them from here.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub etc: BTreeMap<String, EtcSource>,
     #[serde(default, rename = "systemProfile")]
     pub system_profile: String,
+    /// Store path of the generation's rootTree, used to install changed
+    /// config files during activation. Not known at build time (the
+    /// manifest lives inside the rootTree); it is worked out from where a
+    /// new manifest was loaded (see `load_new_manifest`).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub root_tree: String,
 }
 
 /// System profile directory (managed by generation switching)
@@ -188,6 +202,13 @@ pub struct Group {
 pub struct Services {
     pub init_scripts: Vec<String>,
     pub startup_script: String,
+    /// Hook scripts run before activation touches the system, in order.
+    /// Relative names resolve against the system profile directory.
+    #[serde(default)]
+    pub pre_activation_scripts: Vec<String>,
+    /// Hook scripts run after activation completes, in order.
+    #[serde(default)]
+    pub post_activation_scripts: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -198,6 +219,16 @@ pub struct FileInfo {
     pub mode: String,
 }
 
+/// Where the content of an `environment.etc` file comes from.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EtcSource {
+    /// Inline content.
+    Text(String),
+    /// Store path of the file to copy.
+    Source(String),
+}
+
 // ===== Manifest Loading =====
 
 pub fn load_manifest_from(path: &str) -> Result<Manifest, Box<dyn std::error::Error>> {
@@ -299,9 +330,13 @@ pub fn info(manifest_path: Option<&str>) -> Result<(), Box<dyn std::error::Error
 }
 
 /// Verify system files against manifest hashes
+///
+/// Files are hashed on `jobs` threads (default: one per CPU). The report
+/// is the same whatever the thread count: issues are listed by path.
 pub fn verify(
     manifest_path: Option<&str>,
     verbose: bool,
+    jobs: Option<usize>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let manifest = match manifest_path {
         Some(p) => load_manifest_from(p)?,
@@ -316,45 +351,18 @@ pub fn verify(
     println!("Verifying {} tracked files...", manifest.files.len());
     println!();
 
-    let mut verified: u32 = 0;
-    let mut modified: u32 = 0;
-    let mut missing: u32 = 0;
-    let mut errors: Vec<String> = Vec::new();
+    let jobs = jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    });
+    let report = verify_files(Path::new("/"), &manifest.files, jobs);
 
-    let mut sorted_files: Vec<_> = manifest.files.iter().collect();
-    sorted_files.sort_by_key(|(path, _)| path.as_str());
-
-    for (path, expected) in &sorted_files {
-        let full_path = Path::new("/").join(path);
-
-        if !full_path.exists() {
-            missing += 1;
-            errors.push(format!("  MISSING  {path}"));
-            continue;
-        }
-
-        match hash_file(&full_path) {
-            Ok(actual_hash) => {
-                if actual_hash == expected.blake3 {
-                    verified += 1;
-                    if verbose {
-                        println!("  OK       {path}");
-                    }
-                } else {
-                    modified += 1;
-                    errors.push(format!(
-                        "  CHANGED  {path}  (expected {}…, got {}…)",
-                        &expected.blake3[..12],
-                        &actual_hash[..12]
-                    ));
-                }
-            }
-            Err(e) => {
-                errors.push(format!("  ERROR    {path}: {e}"));
-            }
+    if verbose {
+        for path in &report.ok {
+            println!("  OK       {path}");
         }
     }
 
+    let (verified, modified, missing) = (report.verified, report.modified, report.missing);
     println!("Results:");
     println!("  Verified:  {verified}");
     if modified > 0 {
@@ -364,10 +372,10 @@ pub fn verify(
         println!("  Missing:   {missing}");
     }
 
-    if !errors.is_empty() {
+    if !report.errors.is_empty() {
         println!();
         println!("Issues:");
-        for err in &errors {
+        for err in &report.errors {
             println!("{err}");
         }
         println!();
@@ -383,23 +391,162 @@ pub fn verify(
     Ok(())
 }
 
-/// Compare two manifests and show differences
+/// Tallies from checking tracked files against their manifest hashes.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct VerifyReport {
+    verified: u32,
+    modified: u32,
+    missing: u32,
+    /// Paths whose hash matched, in path order
+    ok: Vec<String>,
+    /// One line per problem, in path order
+    errors: Vec<String>,
+}
+
+/// Result of checking a single tracked file.
+enum FileCheck {
+    Ok,
+    Missing,
+    Changed(String),
+    Error(std::io::Error),
+}
+
+/// Check every file in `files` (paths relative to `root`) on up to `jobs`
+/// threads. Threads pull the next file from a shared counter, so at most
+/// `jobs` files are open at once.
+fn verify_files(root: &Path, files: &BTreeMap<String, FileInfo>, jobs: usize) -> VerifyReport {
+    let entries: Vec<(&String, &FileInfo)> = files.iter().collect();
+    let jobs = jobs.clamp(1, entries.len().max(1));
+
+    let check = |i: usize| {
+        let (path, expected) = entries[i];
+        let full_path = root.join(path);
+        if !full_path.exists() {
+            return FileCheck::Missing;
+        }
+        match hash_file(&full_path) {
+            Ok(actual) if actual == expected.blake3 => FileCheck::Ok,
+            Ok(actual) => FileCheck::Changed(actual),
+            Err(e) => FileCheck::Error(e),
+        }
+    };
+
+    let mut checks: Vec<(usize, FileCheck)> = if jobs == 1 {
+        (0..entries.len()).map(|i| (i, check(i))).collect()
+    } else {
+        let next = std::sync::atomic::AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            if i >= entries.len() {
+                                break done;
+                            }
+                            done.push((i, check(i)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("verify worker panicked"))
+                .collect()
+        })
+    };
+    checks.sort_by_key(|(i, _)| *i);
+
+    let mut report = VerifyReport::default();
+    for (i, outcome) in checks {
+        let (path, expected) = entries[i];
+        match outcome {
+            FileCheck::Ok => {
+                report.verified += 1;
+                report.ok.push(path.clone());
+            }
+            FileCheck::Missing => {
+                report.missing += 1;
+                report.errors.push(format!("  MISSING  {path}"));
+            }
+            FileCheck::Changed(actual_hash) => {
+                report.modified += 1;
+                report.errors.push(format!(
+                    "  CHANGED  {path}  (expected {}…, got {}…)",
+                    &expected.blake3[..12.min(expected.blake3.len())],
+                    &actual_hash[..12]
+                ));
+            }
+            FileCheck::Error(e) => {
+                report.errors.push(format!("  ERROR    {path}: {e}"));
+            }
+        }
+    }
+    report
+}
+
+/// Compare the current manifest with the one at `other_path`.
 pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let current = load_manifest()?;
     let other = load_manifest_from(other_path)?;
+    print!("{}", render_diff(&other, &current));
+    Ok(())
+}
+
+/// Compare two stored generations by number; either may be `current`, the
+/// running system's manifest.
+pub fn diff_generations(
+    from: &str,
+    to: &str,
+    gen_dir: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let gens = scan_generations(gen_dir.unwrap_or(GENERATIONS_DIR))?;
+    let old = resolve_generation(&gens, from)?;
+    let new = resolve_generation(&gens, to)?;
+    print!("{}", render_diff(&old, &new));
+    Ok(())
+}
 
+/// The manifest of generation `spec` (a number, or `current`).
+fn resolve_generation(
+    gens: &[Generation],
+    spec: &str,
+) -> Result<Manifest, Box<dyn std::error::Error>> {
+    if spec == "current" {
+        return load_manifest();
+    }
+    let id: u32 = spec
+        .parse()
+        .map_err(|_| format!("invalid generation '{spec}' (expected a number or 'current')"))?;
+    if let Some(gen) = gens.iter().find(|g| g.id == id) {
+        return Ok(gen.manifest.clone());
+    }
+
+    let available: Vec<String> = gens.iter().map(|g| g.id.to_string()).collect();
+    Err(if available.is_empty() {
+        format!("generation {id} not found; no generations are stored")
+    } else {
+        format!("generation {id} not found; available: {}", available.join(", "))
+    }
+    .into())
+}
+
+/// Describe what changed going from `other` to `current`.
+fn render_diff(other: &Manifest, current: &Manifest) -> String {
+    let mut out = String::new();
     let mut has_diff = false;
 
     // Generation metadata
     if current.generation.id != other.generation.id {
-        println!("Generation: {} -> {}", other.generation.id, current.generation.id);
+        let _ = writeln!(out, "Generation: {} -> {}", other.generation.id, current.generation.id);
         has_diff = true;
     }
     if current.generation.build_hash != other.generation.build_hash
         && !current.generation.build_hash.is_empty()
         && !other.generation.build_hash.is_empty()
     {
-        println!("Build hash: {}… -> {}…",
+        let _ = writeln!(out, "Build hash: {}… -> {}…",
             &other.generation.build_hash[..12.min(other.generation.build_hash.len())],
             &current.generation.build_hash[..12.min(current.generation.build_hash.len())]);
         has_diff = true;
@@ -407,15 +554,15 @@ pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     // System metadata
     if current.system.redox_system_version != other.system.redox_system_version {
-        println!("Version: {} -> {}", other.system.redox_system_version, current.system.redox_system_version);
+        let _ = writeln!(out, "Version: {} -> {}", other.system.redox_system_version, current.system.redox_system_version);
         has_diff = true;
     }
     if current.system.profile != other.system.profile {
-        println!("Profile: {} -> {}", other.system.profile, current.system.profile);
+        let _ = writeln!(out, "Profile: {} -> {}", other.system.profile, current.system.profile);
         has_diff = true;
     }
     if current.system.hostname != other.system.hostname {
-        println!("Hostname: {} -> {}", other.system.hostname, current.system.hostname);
+        let _ = writeln!(out, "Hostname: {} -> {}", other.system.hostname, current.system.hostname);
         has_diff = true;
     }
 
@@ -439,11 +586,11 @@ pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     if !pkg_changes.is_empty() {
         if has_diff {
-            println!();
+            let _ = writeln!(out);
         }
-        println!("Packages:");
+        let _ = writeln!(out, "Packages:");
         for change in &pkg_changes {
-            println!("{change}");
+            let _ = writeln!(out, "{change}");
         }
         has_diff = true;
     }
@@ -456,14 +603,14 @@ pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     if !added_drvs.is_empty() || !removed_drvs.is_empty() {
         if has_diff {
-            println!();
+            let _ = writeln!(out);
         }
-        println!("Drivers:");
+        let _ = writeln!(out, "Drivers:");
         for d in &added_drvs {
-            println!("  + {d}");
+            let _ = writeln!(out, "  + {d}");
         }
         for d in &removed_drvs {
-            println!("  - {d}");
+            let _ = writeln!(out, "  - {d}");
         }
         has_diff = true;
     }
@@ -482,11 +629,11 @@ pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     }
     if !user_changes.is_empty() {
         if has_diff {
-            println!();
+            let _ = writeln!(out);
         }
-        println!("Users:");
+        let _ = writeln!(out, "Users:");
         for change in &user_changes {
-            println!("{change}");
+            let _ = writeln!(out, "{change}");
         }
         has_diff = true;
     }
@@ -529,11 +676,11 @@ pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     if !cfg_changes.is_empty() {
         if has_diff {
-            println!();
+            let _ = writeln!(out);
         }
-        println!("Configuration:");
+        let _ = writeln!(out, "Configuration:");
         for change in &cfg_changes {
-            println!("{change}");
+            let _ = writeln!(out, "{change}");
         }
         has_diff = true;
     }
@@ -550,30 +697,211 @@ pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     if !added_files.is_empty() || !removed_files.is_empty() || !changed_files.is_empty() {
         if has_diff {
-            println!();
+            let _ = writeln!(out);
         }
-        println!("Files ({} added, {} removed, {} changed):",
+        let _ = writeln!(out, "Files ({} added, {} removed, {} changed):",
             added_files.len(), removed_files.len(), changed_files.len());
         for f in added_files.iter().take(20) {
-            println!("  + {f}");
+            let _ = writeln!(out, "  + {f}");
         }
         for f in removed_files.iter().take(20) {
-            println!("  - {f}");
+            let _ = writeln!(out, "  - {f}");
         }
         for f in changed_files.iter().take(20) {
-            println!("  ~ {f}");
+            let _ = writeln!(out, "  ~ {f}");
         }
         let total = added_files.len() + removed_files.len() + changed_files.len();
         if total > 60 {
-            println!("  ... and {} more", total - 60);
+            let _ = writeln!(out, "  ... and {} more", total - 60);
         }
         has_diff = true;
     }
 
     if !has_diff {
-        println!("No differences.");
+        let _ = writeln!(out, "No differences.");
+    }
+
+    out
+}
+
+// ===== Validate Command =====
+
+/// Check a manifest's cross-field invariants before it is used to build
+/// a disk image. Prints every problem found, not just the first.
+pub fn validate(manifest_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = load_manifest_from(manifest_path)?;
+    let problems = validate_manifest(&manifest);
+
+    if problems.is_empty() {
+        println!("{manifest_path}: OK");
+        return Ok(());
+    }
+
+    println!("{manifest_path}:");
+    for problem in &problems {
+        println!("  ✗ {problem}");
+    }
+    Err(format!("{} problem(s) found in manifest", problems.len()).into())
+}
+
+/// Collect all invariant violations in a manifest.
+fn validate_manifest(manifest: &Manifest) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for pkg in &manifest.packages {
+        if !pkg.store_path.is_empty() && !Path::new(&pkg.store_path).exists() {
+            problems.push(format!(
+                "package {}: store path {} does not exist",
+                pkg.name, pkg.store_path
+            ));
+        }
+    }
+
+    for driver in &manifest.drivers.initfs {
+        if !manifest.drivers.all.contains(driver) {
+            problems.push(format!(
+                "driver {driver} is in drivers.initfs but not in drivers.all"
+            ));
+        }
+    }
+
+    for (name, user) in &manifest.users {
+        if !manifest.groups.values().any(|g| g.gid == user.gid) {
+            problems.push(format!("user {name}: no group has gid {}", user.gid));
+        }
+    }
+
+    if manifest.services.startup_script.is_empty() {
+        problems.push("services.startupScript is empty".to_string());
+    }
+
+    problems
+}
+
+// ===== Generate Config Command =====
+
+/// Write a configuration.nix reproducing the current manifest, for moving
+/// a system set up with `snix install` to `snix system rebuild`.
+///
+/// Prints to stdout unless `output` is given; an existing `output` is only
+/// replaced with `force`.
+pub fn generate_config(
+    output: Option<&str>,
+    force: bool,
+    manifest_path: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = load_manifest_from(manifest_path.unwrap_or(MANIFEST_PATH))?;
+    let config = crate::rebuild::generate_config_nix(&manifest);
+
+    let Some(output) = output else {
+        print!("{config}");
+        return Ok(());
+    };
+    if Path::new(output).exists() && !force {
+        return Err(format!("{output} already exists (pass --force to replace it)").into());
     }
+    if let Some(parent) = Path::new(output).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(output, config)?;
 
+    println!("Wrote {output}");
+    println!("Check it with: snix system rebuild --dry-run --config {output}");
+    Ok(())
+}
+
+// ===== Options Command =====
+
+/// One configurable option of the manifest and its current value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemOption {
+    /// Dotted name, as in the manifest JSON (e.g. `networking.mode`).
+    pub name: String,
+    /// `bool`, `int`, `string` or `list of string`.
+    pub kind: &'static str,
+    pub value: serde_json::Value,
+}
+
+/// Every configurable option of `manifest`: `hostname`, `timezone` and
+/// each leaf of the `configuration` section, found by walking its JSON so
+/// new fields show up without being listed here. Sorted by name.
+pub fn system_options(manifest: &Manifest) -> Vec<SystemOption> {
+    fn walk(prefix: &str, value: serde_json::Value, out: &mut Vec<SystemOption>) {
+        let kind = match &value {
+            serde_json::Value::Object(_) => "",
+            serde_json::Value::Bool(_) => "bool",
+            serde_json::Value::Number(_) => "int",
+            serde_json::Value::String(_) => "string",
+            serde_json::Value::Array(_) => "list of string",
+            serde_json::Value::Null => "null",
+        };
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (key, field) in fields {
+                    walk(&format!("{prefix}.{key}"), field, out);
+                }
+            }
+            value => out.push(SystemOption {
+                name: prefix.to_string(),
+                kind,
+                value,
+            }),
+        }
+    }
+
+    let mut options = vec![
+        SystemOption {
+            name: "hostname".to_string(),
+            kind: "string",
+            value: manifest.system.hostname.clone().into(),
+        },
+        SystemOption {
+            name: "timezone".to_string(),
+            kind: "string",
+            value: manifest.system.timezone.clone().into(),
+        },
+    ];
+    if let Ok(serde_json::Value::Object(sections)) = serde_json::to_value(&manifest.configuration)
+    {
+        for (section, value) in sections {
+            walk(&section, value, &mut options);
+        }
+    }
+    options.sort_by(|a, b| a.name.cmp(&b.name));
+    options
+}
+
+/// Render `options` whose name starts with `filter`, one per line, like
+/// `nixos-option` does for a single option.
+pub fn format_options(options: &[SystemOption], filter: Option<&str>) -> String {
+    let shown: Vec<&SystemOption> = options
+        .iter()
+        .filter(|o| o.name.starts_with(filter.unwrap_or_default()))
+        .collect();
+    let width = shown.iter().map(|o| o.name.len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for option in shown {
+        let _ = writeln!(
+            out,
+            "{:width$}  {:<14}  {}",
+            option.name, option.kind, option.value
+        );
+    }
+    out
+}
+
+/// `snix system options` — list the manifest's options and their values.
+pub fn options(
+    filter: Option<&str>,
+    manifest_path: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = load_manifest_from(manifest_path.unwrap_or(MANIFEST_PATH))?;
+    let out = format_options(&system_options(&manifest), filter);
+    if out.is_empty() {
+        return Err(format!("no option starts with '{}'", filter.unwrap_or_default()).into());
+    }
+    print!("{out}");
     Ok(())
 }
 
@@ -591,10 +919,16 @@ pub fn diff(other_path: &str) -> Result<(), Box<dyn std::error::Error>> {
 /// If the channel has a binary cache URL, new packages are downloaded from it.
 /// Otherwise, packages must already exist in the local store (e.g., from a
 /// pre-staged binary cache in the rootTree).
+///
+/// A manifest that fails the channel's signature check aborts the upgrade
+/// (the cached manifest is not used as a fallback) unless `insecure` is set.
+/// `check_store_dir` refuses a channel cache for another store directory.
 pub fn upgrade(
     channel_name: Option<&str>,
     dry_run: bool,
     auto_yes: bool,
+    insecure: bool,
+    check_store_dir: bool,
     manifest_path: Option<&str>,
     gen_dir: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -608,7 +942,13 @@ pub fn upgrade(
     println!();
 
     // Step 1: Fetch the latest manifest from the channel URL
-    if let Err(e) = crate::channel::update(&name) {
+    if let Err(e) = crate::channel::update(&name, insecure) {
+        if e.downcast_ref::<crate::channel::VerificationError>().is_some() {
+            return Err(format!(
+                "refusing to upgrade from channel '{name}': {e}\n\
+                 Pass --insecure to upgrade anyway."
+            ).into());
+        }
         // If network fetch fails, check if we have a cached manifest
         let cached = crate::channel::get_manifest_path(&name);
         if cached.is_err() {
@@ -626,7 +966,7 @@ pub fn upgrade(
     let current = load_manifest_from(mpath)?;
 
     let new_manifest_path = crate::channel::get_manifest_path(&name)?;
-    let new_manifest = load_manifest_from(new_manifest_path.to_str().unwrap_or(""))?;
+    let new_manifest = load_new_manifest(new_manifest_path.to_str().unwrap_or(""))?;
 
     // Step 3: Compare manifests — are they different?
     let plan = crate::activate::plan(&current, &new_manifest);
@@ -666,7 +1006,7 @@ pub fn upgrade(
     }
 
     // Step 6: Fetch new packages if needed
-    let packages_fetched = fetch_upgrade_packages(&current, &new_manifest, &name)?;
+    let packages_fetched = fetch_upgrade_packages(&current, &new_manifest, &name, check_store_dir)?;
     if packages_fetched > 0 {
         println!("{packages_fetched} packages installed from cache");
         println!();
@@ -684,6 +1024,8 @@ pub fn upgrade(
         &tmp_path,
         Some(&desc),
         false,
+        false,
+        false,
         gen_dir,
         manifest_path,
     );
@@ -702,11 +1044,13 @@ pub fn upgrade(
 /// Fetch packages that are in the new manifest but not in the local store.
 ///
 /// Checks the channel's binary cache (local path or URL) for each new/changed package.
+/// With `check_store_dir`, a remote cache must be one for `/nix/store`.
 /// Returns the number of packages successfully fetched.
 fn fetch_upgrade_packages(
     current: &Manifest,
     new: &Manifest,
     channel_name: &str,
+    check_store_dir: bool,
 ) -> Result<u32, Box<dyn std::error::Error>> {
     // Build set of store paths that need to be present
     let current_paths: std::collections::BTreeSet<&str> = current
@@ -765,7 +1109,7 @@ fn fetch_upgrade_packages(
 
         // Strategy 3: Remote binary cache URL (if configured)
         if let Some(ref url) = cache_url {
-            if let Ok(()) = crate::cache::fetch(&pkg.store_path, url) {
+            if let Ok(()) = crate::cache::fetch(&pkg.store_path, url, check_store_dir, None) {
                 fetched += 1;
                 continue;
             }
@@ -784,13 +1128,14 @@ fn fetch_upgrade_packages(
 pub fn activate_cmd(
     target_path: &str,
     dry_run: bool,
+    force: bool,
     manifest_path: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mpath = manifest_path.unwrap_or(MANIFEST_PATH);
     let current = load_manifest_from(mpath)?;
-    let target = load_manifest_from(target_path)?;
+    let target = load_new_manifest(target_path)?;
 
-    let result = crate::activate::activate(&current, &target, dry_run)?;
+    let result = crate::activate::activate(&current, &target, dry_run, force)?;
 
     if !dry_run {
         // Show summary
@@ -933,14 +1278,153 @@ pub fn generations(gen_dir: Option<&str>) -> Result<(), Box<dyn std::error::Erro
     Ok(())
 }
 
+/// How a generation came to be, inferred from its description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GenerationKind {
+    Switch,
+    Rollback,
+    Upgrade,
+}
+
+impl GenerationKind {
+    /// `rollback` and `upgrade` write fixed description prefixes; anything
+    /// else (including user-supplied descriptions) is a plain switch.
+    fn from_description(description: &str) -> Self {
+        if description.starts_with("rollback") {
+            Self::Rollback
+        } else if description.starts_with("upgrade") {
+            Self::Upgrade
+        } else {
+            Self::Switch
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Switch => "switch",
+            Self::Rollback => "rollback",
+            Self::Upgrade => "upgrade",
+        }
+    }
+}
+
+/// One row of `snix system history`
+#[derive(Debug)]
+struct HistoryEntry {
+    id: u32,
+    timestamp: String,
+    description: String,
+    kind: GenerationKind,
+    packages: usize,
+    /// Package count change from the previous generation (None for the first)
+    package_delta: Option<i64>,
+}
+
+/// Build history entries, newest first.
+///
+/// Deltas are taken against the preceding generation by ID. Ordering is by
+/// timestamp (ISO 8601 sorts lexically), then ID; generations without a
+/// timestamp sort after all timestamped ones.
+fn history_entries(gens: &[Generation]) -> Vec<HistoryEntry> {
+    let mut by_id: Vec<&Generation> = gens.iter().collect();
+    by_id.sort_by_key(|g| g.id);
+
+    let mut entries: Vec<HistoryEntry> = Vec::with_capacity(by_id.len());
+    let mut prev_packages: Option<usize> = None;
+    for gen in by_id {
+        let info = &gen.manifest.generation;
+        let packages = gen.manifest.packages.len();
+        entries.push(HistoryEntry {
+            id: gen.id,
+            timestamp: info.timestamp.clone(),
+            description: info.description.clone(),
+            kind: GenerationKind::from_description(&info.description),
+            packages,
+            package_delta: prev_packages.map(|p| packages as i64 - p as i64),
+        });
+        prev_packages = Some(packages);
+    }
+
+    entries.sort_by(|a, b| {
+        (!b.timestamp.is_empty(), &b.timestamp, b.id)
+            .cmp(&(!a.timestamp.is_empty(), &a.timestamp, a.id))
+    });
+    entries
+}
+
+/// Show a timeline of generation switches, newest first
+pub fn history(gen_dir: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = gen_dir.unwrap_or(GENERATIONS_DIR);
+    let gens = scan_generations(dir)?;
+
+    if gens.is_empty() {
+        println!("No generations found.");
+        println!("Hint: generations are created when you run 'snix system switch'.");
+        return Ok(());
+    }
+
+    println!("System History");
+    println!("==============");
+    println!();
+    println!("{:20}  {:>4}  {:8}  {:>4}  {:>5}  Description",
+        "Timestamp", "Gen", "Kind", "Pkgs", "Δ");
+    println!("{}", "-".repeat(72));
+
+    for entry in history_entries(&gens) {
+        let delta = match entry.package_delta {
+            Some(d) => format!("{d:+}"),
+            None => "-".to_string(),
+        };
+        println!("{:20}  {:>4}  {:8}  {:>4}  {:>5}  {}",
+            if entry.timestamp.is_empty() { "-" } else { &entry.timestamp },
+            entry.id,
+            entry.kind.as_str(),
+            entry.packages,
+            delta,
+            entry.description,
+        );
+    }
+
+    Ok(())
+}
+
+/// The rootTree a manifest was loaded from, if `path` is (a symlink to)
+/// `<store path>/etc/redox-system/manifest.json`, with store paths in
+/// `store_dir`.
+fn root_tree_of_manifest(path: &str, store_dir: &Path) -> Option<String> {
+    let resolved = fs::canonicalize(path).ok()?;
+    let root = resolved.parent()?.parent()?.parent()?;
+    let in_store = root.parent() == Some(store_dir);
+    (resolved.ends_with("etc/redox-system/manifest.json") && in_store)
+        .then(|| root.to_string_lossy().into_owned())
+}
+
+/// Load the manifest of a generation about to be created or activated.
+///
+/// Its `rootTree` is recomputed from where it was loaded: one in the file
+/// belongs to whatever generation the manifest was derived from, and
+/// installing config files from there would install the old ones.
+fn load_new_manifest(path: &str) -> Result<Manifest, Box<dyn std::error::Error>> {
+    let mut manifest = load_manifest_from(path)?;
+    manifest.root_tree = root_tree_of_manifest(path, Path::new("/nix/store")).unwrap_or_default();
+    Ok(manifest)
+}
+
 /// Switch to a new manifest, saving the current one as a generation.
 ///
 /// If `dry_run` is true, computes and displays the activation plan without
-/// modifying anything on disk.
+/// modifying anything on disk. `force` overwrites config files that were
+/// edited by hand.
+///
+/// With `boot`, the new generation is saved and staged for the next boot
+/// (see [activate_boot]) but the running system, including the current
+/// manifest, is left alone — for changes like drivers that can't apply live.
 pub fn switch(
     new_manifest_path: &str,
     description: Option<&str>,
     dry_run: bool,
+    force: bool,
+    boot: bool,
     gen_dir: Option<&str>,
     manifest_path: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -951,7 +1435,7 @@ pub fn switch(
     let current = load_manifest_from(mpath)?;
 
     // Load new manifest
-    let mut new_manifest = load_manifest_from(new_manifest_path)?;
+    let mut new_manifest = load_new_manifest(new_manifest_path)?;
 
     // Assign next generation ID
     let next_id = next_generation_id(dir, &current);
@@ -963,9 +1447,18 @@ pub fn switch(
 
     // ── Dry-run mode: show plan and exit ──
     if dry_run {
-        println!("Dry run: switch to generation {next_id}");
+        if boot {
+            println!("Dry run: stage generation {next_id} for the next boot");
+        } else {
+            println!("Dry run: switch to generation {next_id}");
+        }
         println!();
-        crate::activate::activate(&current, &new_manifest, true)?;
+        crate::activate::activate(&current, &new_manifest, true, force)?;
+        if !boot && crate::activate::reboot_recommended(&current, &new_manifest) {
+            println!();
+            println!("⚠ Reboot recommended: service or boot configuration changes.");
+            println!("  Use --boot to apply them on the next boot only.");
+        }
         return Ok(());
     }
 
@@ -986,11 +1479,20 @@ pub fn switch(
     let new_json = serde_json::to_string_pretty(&new_manifest)?;
     fs::write(new_gen_dir.join("manifest.json"), &new_json)?;
 
+    if boot {
+        fs::write(boot_pointer(dir), format!("{next_id}\n"))?;
+        println!("Generation {next_id} will be activated on the next boot");
+        return Ok(());
+    }
+
+    // A live switch supersedes whatever was staged for boot.
+    clear_boot_generation(dir)?;
+
     // Install as current manifest
     fs::write(mpath, &new_json)?;
 
     // ── Activate: atomic profile swap + config file updates ──
-    let activation = crate::activate::activate(&current, &new_manifest, false)?;
+    let activation = crate::activate::activate(&current, &new_manifest, false, force)?;
 
     println!("Switched to generation {next_id}");
 
@@ -1028,14 +1530,72 @@ pub fn switch(
     if activation.reboot_recommended {
         println!();
         println!("⚠ Reboot recommended: service or boot configuration changed.");
+        println!("  Next time, --boot stages changes like these for the next boot instead.");
+    }
+
+    Ok(())
+}
+
+/// Activate the generation staged by `switch --boot`, if any. Init runs
+/// this early in boot, before services start, so drivers and boot
+/// settings are already those of the new generation.
+///
+/// The pointer is removed first: a generation that fails to activate
+/// is reported once instead of on every boot.
+pub fn activate_boot(
+    gen_dir: Option<&str>,
+    manifest_path: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = gen_dir.unwrap_or(GENERATIONS_DIR);
+    let mpath = manifest_path.unwrap_or(MANIFEST_PATH);
+
+    let Some(id) = boot_generation(dir) else {
+        return Ok(());
+    };
+    clear_boot_generation(dir)?;
+
+    let current = load_manifest_from(mpath)?;
+    if current.generation.id == id {
+        return Ok(());
+    }
+
+    let staged_path = Path::new(dir).join(id.to_string()).join("manifest.json");
+    let staged = load_manifest_from(&staged_path.to_string_lossy())
+        .map_err(|e| format!("generation {id} staged for boot: {e}"))?;
+
+    fs::write(mpath, serde_json::to_string_pretty(&staged)?)?;
+    let activation = crate::activate::activate(&current, &staged, false, false)?;
+    println!("Activated generation {id} (staged for boot)");
+
+    for w in &activation.warnings {
+        println!("  ⚠ {w}");
     }
 
     Ok(())
 }
 
+/// File in the generations directory holding the id of the generation
+/// staged with `switch --boot`.
+fn boot_pointer(gen_dir: &str) -> PathBuf {
+    Path::new(gen_dir).join("boot")
+}
+
+/// The generation staged for the next boot, if any.
+pub fn boot_generation(gen_dir: &str) -> Option<u32> {
+    fs::read_to_string(boot_pointer(gen_dir)).ok()?.trim().parse().ok()
+}
+
+fn clear_boot_generation(gen_dir: &str) -> io::Result<()> {
+    match fs::remove_file(boot_pointer(gen_dir)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
 /// Rollback to the previous generation (or a specific one)
 pub fn rollback(
     target_id: Option<u32>,
+    force: bool,
     gen_dir: Option<&str>,
     manifest_path: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -1116,10 +1676,11 @@ pub fn rollback(
     fs::write(new_gen_dir.join("manifest.json"), &new_json)?;
 
     // Install as current
+    clear_boot_generation(dir)?;
     fs::write(mpath, &new_json)?;
 
     // ── Activate: atomic profile swap + config file updates ──
-    let activation = crate::activate::activate(&current, &rolled_back, false)?;
+    let activation = crate::activate::activate(&current, &rolled_back, false, force)?;
 
     println!();
     println!("Rolled back to generation {} (saved as generation {next_id})", target.id);
@@ -1297,7 +1858,8 @@ fn days_to_date(days: u64) -> (u64, u64, u64) {
 
 // ===== Helpers =====
 
-fn hash_file(path: &Path) -> std::io::Result<String> {
+/// BLAKE3 hex digest of a file's contents.
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<String> {
     let mut file = fs::File::open(path)?;
     let mut hasher = blake3::Hasher::new();
     let mut buf = [0u8; 16384]; // Larger buffer — BLAKE3 thrives on bulk
@@ -1405,9 +1967,13 @@ mod tests {
             services: Services {
                 init_scripts: vec!["10_net".to_string(), "15_dhcp".to_string()],
                 startup_script: "/startup.sh".to_string(),
+                pre_activation_scripts: vec![],
+                post_activation_scripts: vec![],
             },
             files: BTreeMap::new(),
+            etc: BTreeMap::new(),
             system_profile: String::new(),
+            root_tree: String::new(),
         }
     }
 
@@ -1455,6 +2021,56 @@ mod tests {
         assert_eq!(manifest.files["etc/passwd"].blake3, "abc123");
     }
 
+    #[test]
+    fn validate_sample_manifest_ok() {
+        assert!(validate_manifest(&sample_manifest()).is_empty());
+    }
+
+    #[test]
+    fn validate_user_without_group() {
+        let mut manifest = sample_manifest();
+        manifest.users.insert(
+            "guest".to_string(),
+            User {
+                uid: 1001,
+                gid: 2000,
+                home: "/home/guest".to_string(),
+                shell: "/bin/ion".to_string(),
+            },
+        );
+
+        let problems = validate_manifest(&manifest);
+        assert_eq!(problems, vec!["user guest: no group has gid 2000"]);
+    }
+
+    #[test]
+    fn validate_initfs_driver_missing_from_all() {
+        let mut manifest = sample_manifest();
+        manifest.drivers.initfs = vec!["virtio-blkd".to_string(), "ahcid".to_string()];
+
+        let problems = validate_manifest(&manifest);
+        assert_eq!(
+            problems,
+            vec!["driver ahcid is in drivers.initfs but not in drivers.all"]
+        );
+    }
+
+    #[test]
+    fn validate_reports_all_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = sample_manifest();
+        manifest.packages[0].store_path = dir.path().to_string_lossy().into_owned();
+        manifest.packages[1].store_path = dir.path().join("gone").to_string_lossy().into_owned();
+        manifest.drivers.initfs = vec!["ahcid".to_string()];
+        manifest.groups.clear();
+        manifest.services.startup_script.clear();
+
+        let problems = validate_manifest(&manifest);
+        assert_eq!(problems.len(), 4, "{problems:?}");
+        assert!(problems[0].starts_with("package uutils: store path"));
+        assert_eq!(problems[3], "services.startupScript is empty");
+    }
+
     #[test]
     fn hash_file_works() {
         let dir = tempfile::tempdir().unwrap();
@@ -1520,7 +2136,7 @@ mod tests {
                 },
                 "power": {
                     "acpiEnabled": true,
-                    "powerAction": "hibernate",
+                    "powerAction": "suspend",
                     "rebootOnPanic": true
                 }
             },
@@ -1535,7 +2151,7 @@ mod tests {
         let manifest: Manifest = serde_json::from_str(json).unwrap();
         assert_eq!(manifest.system.hostname, "myhost");
         assert_eq!(manifest.configuration.logging.log_level, "debug");
-        assert_eq!(manifest.configuration.power.power_action, "hibernate");
+        assert_eq!(manifest.configuration.power.power_action, "suspend");
         assert!(manifest.configuration.power.reboot_on_panic);
     }
 
@@ -1608,6 +2224,64 @@ mod tests {
         assert_eq!(loaded.files.len(), 1);
     }
 
+    #[test]
+    fn verify_files_parallel_matches_sequential() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+
+        let mut files = BTreeMap::new();
+        for i in 0..50 {
+            let rel = format!("etc/file-{i:02}");
+            std::fs::write(root.join(&rel), format!("contents {i}")).unwrap();
+            let blake3 = hash_file(&root.join(&rel)).unwrap();
+            files.insert(rel, FileInfo { blake3, size: 0, mode: "644".to_string() });
+        }
+        // A few broken entries scattered through the ordering.
+        std::fs::write(root.join("etc/file-07"), "tampered").unwrap();
+        std::fs::write(root.join("etc/file-31"), "tampered").unwrap();
+        std::fs::remove_file(root.join("etc/file-18")).unwrap();
+        std::fs::remove_file(root.join("etc/file-44")).unwrap();
+
+        let sequential = verify_files(root, &files, 1);
+        assert_eq!(sequential.verified, 46);
+        assert_eq!(sequential.modified, 2);
+        assert_eq!(sequential.missing, 2);
+        assert!(sequential.errors[0].contains("etc/file-07"));
+        assert!(sequential.errors[3].contains("etc/file-44"));
+
+        for jobs in [2, 8, 64] {
+            assert_eq!(verify_files(root, &files, jobs), sequential, "jobs = {jobs}");
+        }
+    }
+
+    #[test]
+    fn verify_files_empty_inventory() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = verify_files(dir.path(), &BTreeMap::new(), 4);
+        assert_eq!(report, VerifyReport::default());
+    }
+
+    #[test]
+    fn test_options_filtered_by_prefix() {
+        let options = system_options(&sample_manifest());
+        let mode = options.iter().find(|o| o.name == "networking.mode").unwrap();
+        assert_eq!((mode.kind, &mode.value), ("string", &serde_json::json!("auto")));
+        assert!(options.iter().any(|o| o.name == "logging.maxLogSizeMB" && o.kind == "int"));
+        assert!(options.iter().any(|o| o.name == "hostname"));
+
+        let out = format_options(&options, Some("networking."));
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3, "{out}");
+        assert!(lines.iter().all(|l| l.starts_with("networking.")), "{out}");
+        let mode_line = lines.iter().find(|l| l.starts_with("networking.mode ")).unwrap();
+        assert!(mode_line.ends_with("\"auto\""), "{mode_line}");
+        assert!(mode_line.contains("string"), "{mode_line}");
+        assert!(out.contains("[\"1.1.1.1\"]"), "{out}");
+
+        assert_eq!(format_options(&options, Some("nonexistent.")), "");
+    }
+
     // ===== Generation Tests =====
 
     #[test]
@@ -1746,6 +2420,35 @@ mod tests {
         assert_eq!(gens[0].manifest.generation.description, "gen 1");
     }
 
+    #[test]
+    fn diff_between_stored_generations() {
+        let dir = tempfile::tempdir().unwrap();
+        for (id, extra) in [(3, "ripgrep"), (5, "helix")] {
+            let mut m = sample_manifest();
+            m.generation.id = id;
+            m.packages.push(Package {
+                name: extra.to_string(),
+                version: "1.0".to_string(),
+                store_path: String::new(),
+            });
+            let gen_dir = dir.path().join(id.to_string());
+            std::fs::create_dir_all(&gen_dir).unwrap();
+            std::fs::write(gen_dir.join("manifest.json"), serde_json::to_string(&m).unwrap()).unwrap();
+        }
+        let gens = scan_generations(dir.path().to_str().unwrap()).unwrap();
+
+        let old = resolve_generation(&gens, "3").unwrap();
+        let new = resolve_generation(&gens, "5").unwrap();
+        let report = render_diff(&old, &new);
+        assert!(report.starts_with("Generation: 3 -> 5\n"), "{report}");
+        assert!(report.contains("Packages:\n  + helix 1.0\n  - ripgrep 1.0\n"), "{report}");
+
+        let err = resolve_generation(&gens, "4").unwrap_err().to_string();
+        assert_eq!(err, "generation 4 not found; available: 3, 5");
+        assert!(resolve_generation(&gens, "latest").is_err());
+        assert!(render_diff(&old, &old).contains("No differences."));
+    }
+
     #[test]
     fn scan_generations_skips_non_numeric_dirs() {
         let dir = tempfile::tempdir().unwrap();
@@ -1805,6 +2508,8 @@ mod tests {
             new_manifest_file.to_str().unwrap(),
             Some("added ripgrep"),
             false,
+            false,
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         ).unwrap();
@@ -1823,6 +2528,46 @@ mod tests {
         assert!(!active.generation.timestamp.is_empty());
     }
 
+    #[test]
+    fn switch_boot_leaves_running_system_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let gen_dir = dir.path().join("generations");
+        let gen_dir_str = gen_dir.to_str().unwrap();
+        let manifest_file = dir.path().join("current.json");
+        let new_manifest_file = dir.path().join("new.json");
+
+        let current = sample_manifest();
+        std::fs::write(&manifest_file, serde_json::to_string_pretty(&current).unwrap()).unwrap();
+        let mut new_m = sample_manifest();
+        new_m.drivers.initfs.push("ahcid".to_string());
+        std::fs::write(&new_manifest_file, serde_json::to_string_pretty(&new_m).unwrap()).unwrap();
+
+        switch(
+            new_manifest_file.to_str().unwrap(),
+            Some("new disk driver"),
+            false,
+            false,
+            true,
+            Some(gen_dir_str),
+            Some(manifest_file.to_str().unwrap()),
+        ).unwrap();
+
+        let staged = load_manifest_from(gen_dir.join("2/manifest.json").to_str().unwrap()).unwrap();
+        assert_eq!(staged.generation.description, "new disk driver");
+        assert_eq!(boot_generation(gen_dir_str), Some(2));
+
+        let active = load_manifest_from(manifest_file.to_str().unwrap()).unwrap();
+        assert_eq!(active.generation.id, 1);
+        assert!(!active.drivers.initfs.contains(&"ahcid".to_string()));
+
+        // What init does on the next boot.
+        activate_boot(Some(gen_dir_str), Some(manifest_file.to_str().unwrap())).unwrap();
+        let active = load_manifest_from(manifest_file.to_str().unwrap()).unwrap();
+        assert_eq!(active.generation.id, 2);
+        assert!(active.drivers.initfs.contains(&"ahcid".to_string()));
+        assert_eq!(boot_generation(gen_dir_str), None);
+    }
+
     #[test]
     fn rollback_restores_previous() {
         let dir = tempfile::tempdir().unwrap();
@@ -1850,6 +2595,7 @@ mod tests {
         // Rollback to generation 1
         rollback(
             Some(1),
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         ).unwrap();
@@ -1873,6 +2619,7 @@ mod tests {
 
         let result = rollback(
             None,
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         );
@@ -1921,6 +2668,90 @@ mod tests {
         generations(Some(gen_dir.to_str().unwrap())).unwrap();
     }
 
+    fn history_gen(id: u32, timestamp: &str, description: &str, packages: usize) -> Generation {
+        let mut m = sample_manifest();
+        m.generation.id = id;
+        m.generation.timestamp = timestamp.to_string();
+        m.generation.description = description.to_string();
+        m.packages = (0..packages)
+            .map(|i| Package {
+                name: format!("pkg{i}"),
+                version: "1.0".to_string(),
+                store_path: String::new(),
+            })
+            .collect();
+        Generation { id, manifest: m, path: std::path::PathBuf::new() }
+    }
+
+    #[test]
+    fn history_package_deltas() {
+        let gens = vec![
+            history_gen(1, "2025-01-01T00:00:00Z", "initial build", 3),
+            history_gen(2, "2025-01-02T00:00:00Z", "added ripgrep", 5),
+            history_gen(3, "2025-01-03T00:00:00Z", "rollback to generation 1", 3),
+            history_gen(4, "2025-01-04T00:00:00Z", "upgrade from channel 'stable'", 3),
+        ];
+        let entries = history_entries(&gens);
+
+        let ids: Vec<u32> = entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![4, 3, 2, 1]);
+        let deltas: Vec<Option<i64>> = entries.iter().map(|e| e.package_delta).collect();
+        assert_eq!(deltas, vec![Some(0), Some(-2), Some(2), None]);
+        let kinds: Vec<GenerationKind> = entries.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![
+            GenerationKind::Upgrade,
+            GenerationKind::Rollback,
+            GenerationKind::Switch,
+            GenerationKind::Switch,
+        ]);
+    }
+
+    #[test]
+    fn history_orders_by_timestamp() {
+        // Timestamps win over IDs, e.g. after a clock correction
+        let gens = vec![
+            history_gen(1, "2025-03-01T00:00:00Z", "a", 1),
+            history_gen(2, "2025-02-01T00:00:00Z", "b", 2),
+            history_gen(3, "2025-04-01T00:00:00Z", "c", 4),
+        ];
+        let entries = history_entries(&gens);
+        let ids: Vec<u32> = entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![3, 1, 2]);
+
+        // Deltas still follow ID order, not display order
+        let gen1 = entries.iter().find(|e| e.id == 1).unwrap();
+        assert_eq!(gen1.package_delta, None);
+        let gen2 = entries.iter().find(|e| e.id == 2).unwrap();
+        assert_eq!(gen2.package_delta, Some(1));
+    }
+
+    #[test]
+    fn history_empty_timestamps_fall_back_to_id() {
+        let gens = vec![
+            history_gen(2, "", "b", 1),
+            history_gen(1, "", "initial build", 1),
+            history_gen(3, "2025-01-01T00:00:00Z", "c", 1),
+            history_gen(4, "", "d", 1),
+        ];
+        let entries = history_entries(&gens);
+        let ids: Vec<u32> = entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![3, 4, 2, 1]);
+    }
+
+    #[test]
+    fn history_with_stored_gens() {
+        let dir = tempfile::tempdir().unwrap();
+        let gen_dir = dir.path().join("generations");
+        std::fs::create_dir_all(gen_dir.join("1")).unwrap();
+        std::fs::write(
+            gen_dir.join("1/manifest.json"),
+            serde_json::to_string(&sample_manifest()).unwrap(),
+        ).unwrap();
+
+        history(Some(gen_dir.to_str().unwrap())).unwrap();
+        history(Some(dir.path().join("missing").to_str().unwrap())).unwrap();
+    }
+
     // ===== Comprehensive Generation Switching Tests =====
 
     #[test]
@@ -2027,6 +2858,8 @@ mod tests {
             new_manifest_file.to_str().unwrap(),
             Some("test switch"),
             false,
+            false,
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         ).unwrap();
@@ -2058,6 +2891,8 @@ mod tests {
             new_manifest_file.to_str().unwrap(),
             Some("new gen"),
             false,
+            false,
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         ).unwrap();
@@ -2096,6 +2931,8 @@ mod tests {
             new_manifest_file.to_str().unwrap(),
             Some("test"),
             false,
+            false,
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         ).unwrap();
@@ -2132,6 +2969,7 @@ mod tests {
         // Rollback to gen 1
         rollback(
             Some(1),
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         ).unwrap();
@@ -2160,6 +2998,7 @@ mod tests {
         // Try to rollback to the same generation
         let result = rollback(
             Some(2),
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         );
@@ -2287,6 +3126,8 @@ mod tests {
             new_manifest_file.to_str().unwrap(),
             Some("test gc"),
             false,
+            false,
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         );
@@ -2331,6 +3172,7 @@ mod tests {
 
         let result = rollback(
             Some(1),
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         );
@@ -2365,6 +3207,8 @@ mod tests {
             new_manifest_file.to_str().unwrap(),
             Some("dry run test"),
             true, // dry_run = true
+            false,
+            false,
             Some(gen_dir.to_str().unwrap()),
             Some(manifest_file.to_str().unwrap()),
         ).unwrap();
@@ -2510,9 +3354,80 @@ mod tests {
         let result = activate_cmd(
             target_file.to_str().unwrap(),
             true,
+            false,
             Some(current_file.to_str().unwrap()),
         );
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn root_tree_of_manifest_finds_the_store_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = fs::canonicalize(dir.path()).unwrap().join("store");
+        let root = store.join("00bgd045z0d4icpbc2yyz4gx48ak44la-root-tree");
+        fs::create_dir_all(root.join("etc/redox-system")).unwrap();
+        let manifest = root.join("etc/redox-system/manifest.json");
+        fs::write(&manifest, "{}").unwrap();
+        let link = dir.path().join("manifest.json");
+        std::os::unix::fs::symlink(&manifest, &link).unwrap();
+
+        let expected = Some(root.to_string_lossy().into_owned());
+        assert_eq!(root_tree_of_manifest(manifest.to_str().unwrap(), &store), expected);
+        // Through a symlink, like /etc/redox-system/manifest.json.
+        assert_eq!(root_tree_of_manifest(link.to_str().unwrap(), &store), expected);
+
+        // Elsewhere in a store path, or outside the store.
+        let other = root.join("manifest.json");
+        fs::write(&other, "{}").unwrap();
+        assert_eq!(root_tree_of_manifest(other.to_str().unwrap(), &store), None);
+        assert_eq!(root_tree_of_manifest(manifest.to_str().unwrap(), dir.path()), None);
+        assert_eq!(root_tree_of_manifest("/nonexistent/manifest.json", &store), None);
+    }
+
+    #[test]
+    fn new_manifests_drop_a_copied_root_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.json");
+        let mut m = sample_manifest();
+        m.root_tree = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-old-root".to_string();
+        fs::write(&path, serde_json::to_string(&m).unwrap()).unwrap();
+
+        // What switch, upgrade and activate load.
+        assert_eq!(load_new_manifest(path.to_str().unwrap()).unwrap().root_tree, "");
+        // Saved generations keep theirs.
+        assert_eq!(load_manifest_from(path.to_str().unwrap()).unwrap().root_tree, m.root_tree);
+    }
+
+    #[test]
+    fn switch_recomputes_root_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let gen_dir = dir.path().join("generations");
+        let manifest_file = dir.path().join("current.json");
+        let new_manifest_file = dir.path().join("new.json");
+
+        let mut current = sample_manifest();
+        current.root_tree = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-old-root".to_string();
+        fs::write(&manifest_file, serde_json::to_string_pretty(&current).unwrap()).unwrap();
+        // Derived from the current manifest, rootTree and all.
+        let mut new_m = current.clone();
+        new_m.system.hostname = "renamed".to_string();
+        fs::write(&new_manifest_file, serde_json::to_string_pretty(&new_m).unwrap()).unwrap();
+
+        switch(
+            new_manifest_file.to_str().unwrap(),
+            None,
+            false,
+            false,
+            false,
+            Some(gen_dir.to_str().unwrap()),
+            Some(manifest_file.to_str().unwrap()),
+        )
+        .unwrap();
+
+        let active = load_manifest_from(manifest_file.to_str().unwrap()).unwrap();
+        assert_eq!(active.system.hostname, "renamed");
+        assert_eq!(active.root_tree, "");
+        let saved = load_manifest_from(gen_dir.join("1/manifest.json").to_str().unwrap()).unwrap();
+        assert_eq!(saved.root_tree, current.root_tree);
+    }
 }
@@ -0,0 +1,81 @@
+//! Throwaway HTTP servers for tests that talk to a binary cache.
+//!
+//! Each server listens on a fresh `127.0.0.1` port and serves one request
+//! per connection (`Connection: close`), which is all ureq needs.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+
+/// Answer successive connections with `replies`, in order, reporting each
+/// request head (lowercased) on the returned channel. Returns the base URL.
+pub fn serve_sequence(replies: Vec<Vec<u8>>) -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for reply in replies {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = tx.send(read_head(&mut stream).to_lowercase());
+            let _ = stream.write_all(&reply);
+        }
+    });
+
+    (format!("http://{addr}"), rx)
+}
+
+/// Serve fixed bodies by request path (404 for anything else) until the
+/// test process exits. Returns the base URL.
+pub fn serve_files(files: &[(&str, &str)]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let files: Vec<(String, String)> =
+        files.iter().map(|(p, b)| (p.to_string(), b.to_string())).collect();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let head = read_head(&mut stream);
+            let path = head.split_whitespace().nth(1).unwrap_or("");
+            let reply = match files.iter().find(|(p, _)| p == path) {
+                Some((_, body)) => ok(body),
+                None => status("404 Not Found"),
+            };
+            let _ = stream.write_all(&reply);
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+/// A reply with `status`, the extra `headers` (each ending in `\r\n`) and `body`.
+pub fn reply(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+    let mut out = format!("HTTP/1.1 {status}\r\n{headers}Connection: close\r\n\r\n").into_bytes();
+    out.extend_from_slice(body);
+    out
+}
+
+/// A `200 OK` reply carrying `body`.
+pub fn ok(body: &str) -> Vec<u8> {
+    reply("200 OK", &format!("Content-Length: {}\r\n", body.len()), body.as_bytes())
+}
+
+/// An empty reply with `status`, e.g. `"404 Not Found"`.
+pub fn status(status: &str) -> Vec<u8> {
+    reply(status, "Content-Length: 0\r\n", b"")
+}
+
+/// Read a request head, up to and including the blank line.
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).unwrap_or(0) == 0 {
+            break;
+        }
+        head.push(byte[0]);
+    }
+    String::from_utf8_lossy(&head).into_owned()
+}
@@ -0,0 +1,228 @@
+// Declarative network config: /etc/net/config.toml
+//
+// Only the small subset of TOML this file needs is understood: one
+// `[interface.<name>]` table per interface, holding `key = value` lines
+// where a value is a "string", an integer, or an array of strings.
+//
+//   [interface.eth0]
+//   method = "static"
+//   address = "10.0.0.5"
+//   prefix = 24
+//   gateway = "10.0.0.1"
+//   dns = ["1.1.1.1", "8.8.8.8"]
+//
+//   [interface.eth1]
+//   method = "dhcp"
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Dhcp,
+    Static,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceConfig {
+    pub name: String,
+    pub method: Method,
+    pub address: Option<String>,
+    pub prefix: u8,
+    pub gateway: Option<String>,
+    pub dns: Vec<String>,
+}
+
+impl InterfaceConfig {
+    fn new(name: &str) -> Self {
+        InterfaceConfig {
+            name: name.to_string(),
+            method: Method::Dhcp,
+            address: None,
+            prefix: 24,
+            gateway: None,
+            dns: Vec::new(),
+        }
+    }
+}
+
+enum Value {
+    Str(String),
+    Int(u64),
+    List(Vec<String>),
+}
+
+// Parse the config file contents, returning interfaces in file order
+pub fn parse(input: &str) -> Result<Vec<InterfaceConfig>, String> {
+    let mut ifaces: Vec<InterfaceConfig> = Vec::new();
+
+    for (idx, raw) in input.lines().enumerate() {
+        let lineno = idx + 1;
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .map(str::trim)
+                .and_then(|h| h.strip_prefix("interface."))
+                .filter(|n| !n.is_empty())
+                .ok_or_else(|| format!("line {}: expected [interface.<name>]", lineno))?;
+            if ifaces.iter().any(|i| i.name == name) {
+                return Err(format!("line {}: duplicate interface '{}'", lineno, name));
+            }
+            ifaces.push(InterfaceConfig::new(name));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key = value", lineno))?;
+        let (key, value) = (key.trim(), parse_value(value.trim(), lineno)?);
+
+        let iface = ifaces
+            .last_mut()
+            .ok_or_else(|| format!("line {}: '{}' outside an [interface] table", lineno, key))?;
+
+        match (key, value) {
+            ("method", Value::Str(m)) => {
+                iface.method = match m.as_str() {
+                    "dhcp" => Method::Dhcp,
+                    "static" => Method::Static,
+                    _ => return Err(format!("line {}: unknown method '{}'", lineno, m)),
+                }
+            }
+            ("address", Value::Str(a)) => iface.address = Some(a),
+            ("gateway", Value::Str(g)) => iface.gateway = Some(g),
+            ("prefix", Value::Int(p)) if p <= 32 => iface.prefix = p as u8,
+            ("dns", Value::Str(d)) => iface.dns = vec![d],
+            ("dns", Value::List(d)) => iface.dns = d,
+            ("method" | "address" | "gateway" | "prefix" | "dns", _) => {
+                return Err(format!("line {}: invalid value for '{}'", lineno, key));
+            }
+            _ => return Err(format!("line {}: unknown key '{}'", lineno, key)),
+        }
+    }
+
+    for iface in &ifaces {
+        if iface.method == Method::Static && iface.address.is_none() {
+            return Err(format!(
+                "interface {}: static method requires an address",
+                iface.name
+            ));
+        }
+    }
+
+    Ok(ifaces)
+}
+
+// Drop a trailing `# comment`, ignoring '#' inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str, lineno: usize) -> Result<Value, String> {
+    if let Some(items) = value.strip_prefix('[') {
+        let items = items
+            .strip_suffix(']')
+            .ok_or_else(|| format!("line {}: unterminated array", lineno))?;
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| parse_string(item, lineno))
+            .collect::<Result<_, _>>()
+            .map(Value::List);
+    }
+    if value.starts_with('"') {
+        return parse_string(value, lineno).map(Value::Str);
+    }
+    value
+        .parse()
+        .map(Value::Int)
+        .map_err(|_| format!("line {}: invalid value '{}'", lineno, value))
+}
+
+fn parse_string(value: &str, lineno: usize) -> Result<String, String> {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .filter(|v| !v.contains('"') && !v.contains('\\'))
+        .map(str::to_string)
+        .ok_or_else(|| format!("line {}: invalid string {}", lineno, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_static_interfaces() {
+        let ifaces = parse(
+            "# Two NICs on separate networks\n\
+             [interface.eth0]\n\
+             method = \"static\"\n\
+             address = \"10.0.0.5\"\n\
+             prefix = 24\n\
+             gateway = \"10.0.0.1\"\n\
+             dns = [\"1.1.1.1\", \"8.8.8.8\"]\n\
+             \n\
+             [interface.eth1]\n\
+             method = \"static\"   # no gateway: local network only\n\
+             address = \"192.168.100.2\"\n\
+             prefix = 16\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            ifaces,
+            vec![
+                InterfaceConfig {
+                    name: "eth0".to_string(),
+                    method: Method::Static,
+                    address: Some("10.0.0.5".to_string()),
+                    prefix: 24,
+                    gateway: Some("10.0.0.1".to_string()),
+                    dns: vec!["1.1.1.1".to_string(), "8.8.8.8".to_string()],
+                },
+                InterfaceConfig {
+                    name: "eth1".to_string(),
+                    method: Method::Static,
+                    address: Some("192.168.100.2".to_string()),
+                    prefix: 16,
+                    gateway: None,
+                    dns: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dhcp_is_the_default_method() {
+        let ifaces = parse("[interface.eth0]\ndns = \"9.9.9.9\"\n").unwrap();
+        assert_eq!(ifaces[0].method, Method::Dhcp);
+        assert_eq!(ifaces[0].dns, vec!["9.9.9.9".to_string()]);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        for input in [
+            "method = \"dhcp\"\n",
+            "[interface.eth0]\nmethod = \"bootp\"\n",
+            "[interface.eth0]\nmethod = \"static\"\n",
+            "[interface.eth0]\nprefix = 33\n",
+            "[interface.eth0]\nmtu = 1500\n",
+            "[interface.eth0]\n[interface.eth0]\n",
+            "[network]\n",
+        ] {
+            assert!(parse(input).is_err(), "accepted {:?}", input);
+        }
+    }
+}
@@ -1,13 +1,19 @@
+mod config;
+
 use std::fs;
 use std::io;
+use std::net::Ipv4Addr;
 use std::path::Path;
 use std::thread;
 use std::time::Duration;
 
+// Root of smolnetd's network configuration scheme
+const NETCFG: &str = "/scheme/netcfg";
+
 // Helper function to wait for a network interface to appear
-// Polls /scheme/netcfg/ifaces/{iface}/mac for existence
-fn wait_for_interface(iface: &str, attempts: u32, interval_ms: u64) -> bool {
-    let mac_path = format!("/scheme/netcfg/ifaces/{}/mac", iface);
+// Polls {netcfg}/ifaces/{iface}/mac for existence
+fn wait_for_interface(netcfg: &str, iface: &str, attempts: u32, interval_ms: u64) -> bool {
+    let mac_path = format!("{}/ifaces/{}/mac", netcfg, iface);
     let interval = Duration::from_millis(interval_ms);
 
     for _ in 0..attempts {
@@ -36,49 +42,118 @@ fn read_config(path: &str) -> Result<String, io::Error> {
     fs::read_to_string(path).map(|s| s.trim().to_string())
 }
 
+// Helper function to check a static address and gateway before use
+// The gateway must be on the address's subnet, or the default route
+// points at a host smolnetd cannot reach.
+fn validate_static(address: &str, prefix: u8, gateway: Option<&str>) -> Result<(), String> {
+    let addr: Ipv4Addr = address
+        .parse()
+        .map_err(|_| format!("invalid address '{}'", address))?;
+    if prefix > 32 {
+        return Err(format!("invalid prefix length {}", prefix));
+    }
+
+    if let Some(gateway) = gateway {
+        let gw: Ipv4Addr = gateway
+            .parse()
+            .map_err(|_| format!("invalid gateway '{}'", gateway))?;
+        let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+        if u32::from(addr) & mask != u32::from(gw) & mask {
+            return Err(format!(
+                "gateway {} is not in {}/{}",
+                gateway, address, prefix
+            ));
+        }
+    }
+    Ok(())
+}
+
 // Helper function to apply static network configuration
-// Performs best-effort writes (continues even if one fails)
-fn apply_static_config(iface: &str, address: &str, gateway: &str, dns: &str) {
+// Validates the inputs first, then performs best-effort writes
+// (continues even if one fails)
+// Without a gateway no default route is added; smolnetd keeps a single
+// nameserver, so only the first DNS server is used.
+fn apply_static_config(
+    iface: &str,
+    address: &str,
+    prefix: u8,
+    gateway: Option<&str>,
+    dns: &[&str],
+) -> Result<(), String> {
+    validate_static(address, prefix, gateway)?;
+
     let addr_set_path = format!("/scheme/netcfg/ifaces/{}/addr/set", iface);
     let route_add_path = "/scheme/netcfg/route/add";
     let nameserver_path = "/scheme/netcfg/resolv/nameserver";
 
-    let addr_content = format!("{}/24", address);
-    let route_content = format!("default via {}", gateway);
+    let addr_content = format!("{}/{}", address, prefix);
 
     // Best-effort writes - continue even if one fails
     let _ = write_scheme(&addr_set_path, &addr_content);
-    let _ = write_scheme(route_add_path, &route_content);
-    let _ = write_scheme(nameserver_path, dns);
+    if let Some(gateway) = gateway {
+        let _ = write_scheme(route_add_path, &format!("default via {}", gateway));
+    }
+    if let Some(dns) = dns.first() {
+        let _ = write_scheme(nameserver_path, dns);
+    }
+    Ok(())
+}
+
+// Helper function to wait for a DHCP lease on an interface
+// Polls {netcfg}/ifaces/{iface}/addr/list until it holds an address
+fn wait_for_lease(
+    netcfg: &str,
+    iface: &str,
+    attempts: u32,
+    interval_ms: u64,
+    tag: &str,
+) -> Option<String> {
+    let addr_list_path = format!("{}/ifaces/{}/addr/list", netcfg, iface);
+    let interval = Duration::from_millis(interval_ms);
+
+    for attempt in 0..attempts {
+        // Try to read the DHCP-assigned address
+        if let Ok(content) = read_config(&addr_list_path) {
+            // smolnetd returns "Not configured" before DHCP completes.
+            // Only accept responses that look like an IP address (contain a dot).
+            if !content.is_empty() && content.contains('.') {
+                return Some(content);
+            }
+            if attempt == 0 || attempt % 10 == 0 {
+                eprintln!("{}: addr/list = '{}' (attempt {})", tag, content, attempt);
+            }
+        }
+        thread::sleep(interval);
+    }
+    None
+}
+
+// Helper function to find the default gateway in a route/list dump
+// Route lines look like "default via 10.0.2.2 ..."
+fn default_gateway(routes: &str) -> Option<&str> {
+    routes.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("default"), Some("via"), gw) => gw,
+            _ => None,
+        }
+    })
 }
 
 // Subcommand: auto
 // Auto-configure network with DHCP and static fallback
 fn cmd_auto() -> i32 {
     // Wait for eth0 to appear (30 attempts × 200ms = 6 seconds)
-    if !wait_for_interface("eth0", 30, 200) {
+    if !wait_for_interface(NETCFG, "eth0", 30, 200) {
         eprintln!("netcfg-auto: eth0 not found");
         return 0; // Not a fatal error
     }
 
     // Wait for DHCP (30 attempts × 500ms = 15 seconds)
     eprintln!("netcfg-auto: Waiting for DHCP...");
-    let addr_list_path = "/scheme/netcfg/ifaces/eth0/addr/list";
-
-    for attempt in 0..30 {
-        // Try to read the DHCP-assigned address
-        if let Ok(content) = read_config(addr_list_path) {
-            // smolnetd returns "Not configured" before DHCP completes.
-            // Only accept responses that look like an IP address (contain a dot).
-            if !content.is_empty() && content.contains('.') {
-                eprintln!("netcfg-auto: DHCP configured: {}", content);
-                return 0;
-            }
-            if attempt == 0 || attempt % 10 == 0 {
-                eprintln!("netcfg-auto: addr/list = '{}' (attempt {})", content, attempt);
-            }
-        }
-        thread::sleep(Duration::from_millis(500));
+    if let Some(addr) = wait_for_lease(NETCFG, "eth0", 30, 500, "netcfg-auto") {
+        eprintln!("netcfg-auto: DHCP configured: {}", addr);
+        return 0;
     }
 
     // DHCP timed out, try static fallback
@@ -106,24 +181,92 @@ fn cmd_auto() -> i32 {
         }
     };
 
-    apply_static_config("eth0", &ip, &gateway, "1.1.1.1");
+    if let Err(e) = apply_static_config("eth0", &ip, 24, Some(&gateway), &["1.1.1.1"]) {
+        eprintln!("netcfg-auto: Static fallback not applied: {}", e);
+        return 0;
+    }
     eprintln!("netcfg-auto: Static config applied ({})", ip);
 
     0
 }
 
+// A DHCP lease as smolnetd reports it
+#[derive(Debug, PartialEq)]
+struct Lease {
+    address: String,
+    gateway: String,
+    dns: String,
+}
+
+// Helper function to trigger DHCP on an interface and wait for its lease
+// Polls every 500ms until the timeout; the error says what went wrong
+fn request_lease(netcfg: &str, iface: &str, timeout_secs: u64) -> Result<Lease, String> {
+    // Wait for interface to appear (30 attempts × 200ms = 6 seconds)
+    if !wait_for_interface(netcfg, iface, 30, 200) {
+        return Err(format!("{} not found", iface));
+    }
+
+    // Older smolnetd builds run DHCP on their own and have no trigger;
+    // then this just waits for the lease like `auto` does.
+    let start_path = format!("{}/ifaces/{}/dhcp/start", netcfg, iface);
+    if Path::new(&start_path).exists() {
+        if write_scheme(&start_path, "1").is_err() {
+            return Err(format!("could not start DHCP on {}", iface));
+        }
+        eprintln!("netcfg-dhcp: DHCP started on {}", iface);
+    } else {
+        eprintln!("netcfg-dhcp: No DHCP trigger, waiting for lease...");
+    }
+
+    let attempts = (timeout_secs * 2).max(1) as u32;
+    let address = wait_for_lease(netcfg, iface, attempts, 500, "netcfg-dhcp")
+        .ok_or_else(|| format!("No lease on {} after {}s", iface, timeout_secs))?;
+
+    let routes = read_config(&format!("{}/route/list", netcfg)).unwrap_or_default();
+    let gateway = default_gateway(&routes).unwrap_or("none").to_string();
+    let dns = read_config(&format!("{}/resolv/nameserver", netcfg)).unwrap_or_default();
+    let dns = if dns.is_empty() { "none".to_string() } else { dns };
+
+    Ok(Lease { address, gateway, dns })
+}
+
+// Subcommand: dhcp
+// Trigger DHCP on an interface and wait for a lease (timeout is fatal)
+fn cmd_dhcp(iface: &str, timeout_secs: u64) -> i32 {
+    match request_lease(NETCFG, iface, timeout_secs) {
+        Ok(lease) => {
+            println!("address: {}", lease.address);
+            println!("gateway: {}", lease.gateway);
+            println!("dns: {}", lease.dns);
+            0
+        }
+        Err(e) => {
+            eprintln!("netcfg-dhcp: {}", e);
+            1
+        }
+    }
+}
+
 // Subcommand: static
 // Configure static network with explicit parameters
 fn cmd_static(iface: &str, address: &str, gateway: &str) -> i32 {
     eprintln!("netcfg-static: Configuring interface {}...", iface);
 
+    if let Err(e) = validate_static(address, 24, Some(gateway)) {
+        eprintln!("netcfg-static: {}", e);
+        return 1;
+    }
+
     // Wait for interface to appear (30 attempts × 200ms = 6 seconds)
-    if !wait_for_interface(iface, 30, 200) {
+    if !wait_for_interface(NETCFG, iface, 30, 200) {
         eprintln!("netcfg-static: {} not found", iface);
         return 1;
     }
 
-    apply_static_config(iface, address, gateway, "1.1.1.1");
+    if let Err(e) = apply_static_config(iface, address, 24, Some(gateway), &["1.1.1.1"]) {
+        eprintln!("netcfg-static: {}", e);
+        return 1;
+    }
     eprintln!("netcfg-static: Network ready ({})", address);
 
     0
@@ -158,25 +301,106 @@ fn cmd_cloud() -> i32 {
         }
     };
 
-    apply_static_config("eth0", &ip, &gateway, "1.1.1.1");
+    if let Err(e) = apply_static_config("eth0", &ip, 24, Some(&gateway), &["1.1.1.1"]) {
+        eprintln!("Error: {}", e);
+        return 1;
+    }
     eprintln!("Network configured: {}/24 via {}", ip, gateway);
 
     0
 }
 
+// Subcommand: apply
+// Configure every interface described in a declarative config file
+fn cmd_apply(config_path: Option<&str>) -> i32 {
+    let path = config_path.unwrap_or("/etc/net/config.toml");
+
+    // Without the config file, fall back to the legacy split files
+    // (only when the default path was not found, not for an explicit --config)
+    if config_path.is_none() && !Path::new(path).exists() {
+        eprintln!(
+            "netcfg-apply: {} not found, using /etc/net/cloud-hypervisor",
+            path
+        );
+        return cmd_cloud();
+    }
+
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("netcfg-apply: Failed to read {}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let ifaces = match config::parse(&content) {
+        Ok(ifaces) => ifaces,
+        Err(e) => {
+            eprintln!("netcfg-apply: {}: {}", path, e);
+            return 1;
+        }
+    };
+
+    // Configure every interface, but fail if any of them did
+    let mut exit_code = 0;
+    for iface in &ifaces {
+        let code = match iface.method {
+            config::Method::Dhcp => cmd_dhcp(&iface.name, 10),
+            config::Method::Static => {
+                if wait_for_interface(NETCFG, &iface.name, 30, 200) {
+                    let address = iface.address.as_deref().unwrap_or_default();
+                    let dns: Vec<&str> = iface.dns.iter().map(String::as_str).collect();
+                    match apply_static_config(
+                        &iface.name,
+                        address,
+                        iface.prefix,
+                        iface.gateway.as_deref(),
+                        &dns,
+                    ) {
+                        Ok(()) => {
+                            eprintln!(
+                                "netcfg-apply: {} configured ({}/{})",
+                                iface.name, address, iface.prefix
+                            );
+                            0
+                        }
+                        Err(e) => {
+                            eprintln!("netcfg-apply: {}: {}", iface.name, e);
+                            1
+                        }
+                    }
+                } else {
+                    eprintln!("netcfg-apply: {} not found", iface.name);
+                    1
+                }
+            }
+        };
+        if code != 0 {
+            exit_code = code;
+        }
+    }
+
+    exit_code
+}
+
 fn print_usage() {
     eprintln!("Usage: netcfg-setup <COMMAND> [OPTIONS]");
     eprintln!();
     eprintln!("Commands:");
     eprintln!("  auto                                      Auto-configure network (DHCP with static fallback)");
+    eprintln!("  dhcp [--interface <IF>] [--timeout <SECS>]");
+    eprintln!("                                            Run DHCP and wait for a lease (default: eth0, 10s)");
     eprintln!("  static --interface <IF> --address <ADDR> --gateway <GW>");
     eprintln!("                                            Configure static network");
     eprintln!("  cloud                                     Configure for Cloud Hypervisor");
+    eprintln!("  apply [--config <PATH>]                   Apply /etc/net/config.toml (or PATH)");
     eprintln!();
     eprintln!("Examples:");
     eprintln!("  netcfg-setup auto");
+    eprintln!("  netcfg-setup dhcp --interface eth0 --timeout 10");
     eprintln!("  netcfg-setup static --interface eth0 --address 10.0.0.5 --gateway 10.0.0.1");
     eprintln!("  netcfg-setup cloud");
+    eprintln!("  netcfg-setup apply --config /etc/net/config.toml");
 }
 
 fn main() {
@@ -190,6 +414,46 @@ fn main() {
     let exit_code = match args[1].as_str() {
         "auto" => cmd_auto(),
 
+        "dhcp" => {
+            // Parse --interface, --timeout flags
+            let mut iface = String::from("eth0");
+            let mut timeout = 10;
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--interface" => {
+                        if i + 1 < args.len() {
+                            iface = args[i + 1].clone();
+                            i += 2;
+                        } else {
+                            eprintln!("Error: --interface requires a value");
+                            print_usage();
+                            std::process::exit(1);
+                        }
+                    }
+                    "--timeout" => match args.get(i + 1).and_then(|t| t.parse().ok()) {
+                        Some(t) => {
+                            timeout = t;
+                            i += 2;
+                        }
+                        None => {
+                            eprintln!("Error: --timeout requires a number of seconds");
+                            print_usage();
+                            std::process::exit(1);
+                        }
+                    },
+                    _ => {
+                        eprintln!("Error: Unknown option '{}'", args[i]);
+                        print_usage();
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            cmd_dhcp(&iface, timeout)
+        }
+
         "static" => {
             // Parse --interface, --address, --gateway flags
             let mut iface = None;
@@ -251,6 +515,21 @@ fn main() {
 
         "cloud" => cmd_cloud(),
 
+        "apply" => match args.get(2).map(String::as_str) {
+            None => cmd_apply(None),
+            Some("--config") if args.len() == 4 => cmd_apply(Some(&args[3])),
+            Some("--config") if args.len() == 3 => {
+                eprintln!("Error: --config requires a value");
+                print_usage();
+                1
+            }
+            _ => {
+                eprintln!("Error: Unknown option '{}'", args[args.len() - 1]);
+                print_usage();
+                1
+            }
+        },
+
         "-h" | "--help" => {
             print_usage();
             0
@@ -265,3 +544,103 @@ fn main() {
 
     std::process::exit(exit_code);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_address() {
+        assert_eq!(
+            validate_static("10.0.0.300", 24, Some("10.0.0.1")),
+            Err("invalid address '10.0.0.300'".to_string())
+        );
+        assert_eq!(
+            validate_static("10.0.0.5", 24, Some("router")),
+            Err("invalid gateway 'router'".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_gateway_outside_subnet() {
+        assert_eq!(
+            validate_static("10.0.0.5", 24, Some("10.0.1.1")),
+            Err("gateway 10.0.1.1 is not in 10.0.0.5/24".to_string())
+        );
+        // A shorter prefix takes the same gateway in.
+        assert_eq!(validate_static("10.0.0.5", 16, Some("10.0.1.1")), Ok(()));
+    }
+
+    // A fake netcfg scheme tree for eth0, removed again on drop
+    struct FakeNetcfg(std::path::PathBuf);
+
+    impl FakeNetcfg {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("netcfg-setup-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("ifaces/eth0/addr")).unwrap();
+            fs::create_dir_all(root.join("route")).unwrap();
+            fs::create_dir_all(root.join("resolv")).unwrap();
+            fs::write(root.join("ifaces/eth0/mac"), "52:54:00:12:34:56").unwrap();
+            FakeNetcfg(root)
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for FakeNetcfg {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn dhcp_triggers_and_reports_lease() {
+        let netcfg = FakeNetcfg::new("lease");
+        netcfg.write("ifaces/eth0/dhcp/start", "");
+        netcfg.write("ifaces/eth0/addr/list", "10.0.2.15/24\n");
+        netcfg.write("route/list", "10.0.2.0/24 dev eth0\ndefault via 10.0.2.2 dev eth0\n");
+        netcfg.write("resolv/nameserver", "10.0.2.3\n");
+
+        let lease = request_lease(netcfg.path(), "eth0", 0).unwrap();
+        assert_eq!(
+            lease,
+            Lease {
+                address: "10.0.2.15/24".to_string(),
+                gateway: "10.0.2.2".to_string(),
+                dns: "10.0.2.3".to_string(),
+            }
+        );
+        let trigger = fs::read_to_string(netcfg.0.join("ifaces/eth0/dhcp/start")).unwrap();
+        assert_eq!(trigger, "1");
+    }
+
+    #[test]
+    fn dhcp_fails_without_lease() {
+        let netcfg = FakeNetcfg::new("timeout");
+        netcfg.write("ifaces/eth0/addr/list", "Not configured");
+
+        assert_eq!(
+            request_lease(netcfg.path(), "eth0", 0),
+            Err("No lease on eth0 after 0s".to_string())
+        );
+        // No trigger to write: older smolnetd builds start DHCP on their own.
+        assert!(!netcfg.0.join("ifaces/eth0/dhcp/start").exists());
+    }
+
+    #[test]
+    fn accepts_valid_pair() {
+        assert_eq!(validate_static("10.0.0.5", 24, Some("10.0.0.1")), Ok(()));
+        assert_eq!(validate_static("192.168.100.2", 16, None), Ok(()));
+        assert_eq!(validate_static("10.0.0.5", 0, Some("8.8.8.8")), Ok(()));
+    }
+}